@@ -0,0 +1,64 @@
+//! End to end throughput of two Interfaces in the same process, each
+//! iteration sends a batch of messages over the local loopback.
+
+use actaeon::config::Config;
+use actaeon::message::Message;
+use actaeon::node::{Address, Center};
+use actaeon::transaction::{Class, Transaction};
+use actaeon::Interface;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use sodiumoxide::crypto::box_;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Messages sent in each iteration.
+const BATCH: usize = 100;
+
+async fn interface(port: usize, other: usize) -> (Interface, Center) {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), other);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), port);
+    let interface = Interface::new(config, center.clone()).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    (interface, center)
+}
+
+fn bench_loopback(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let ((sender, scenter), (receiver, rcenter)) = runtime.block_on(async {
+        let sender = interface(42494, 42495).await;
+        let receiver = interface(42495, 42494).await;
+        (sender, receiver)
+    });
+
+    let mut group = c.benchmark_group("loopback");
+    group.throughput(Throughput::Elements(BATCH as u64));
+    group.bench_function("interface", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                for _ in 0..BATCH {
+                    let t = Transaction::new(Message::new(
+                        Class::Action,
+                        scenter.public.clone(),
+                        rcenter.public.clone(),
+                        Address::default(),
+                        vec![42; 1024],
+                    ));
+                    sender.send(t).unwrap();
+                }
+                for _ in 0..BATCH {
+                    receiver.recv().await.unwrap();
+                }
+            })
+        })
+    });
+    group.finish();
+
+    runtime.block_on(async {
+        sender.shutdown().await;
+        receiver.shutdown().await;
+    });
+}
+
+criterion_group!(benches, bench_loopback);
+criterion_main!(benches);
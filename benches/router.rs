@@ -0,0 +1,90 @@
+//! Benchmarks of the routing table with more than ten thousand Nodes
+//! and of the duplicate detection cache.
+
+use actaeon::cache::Cache;
+use actaeon::node::{Address, Center, Link, Node};
+use actaeon::router::Safe;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use sodiumoxide::crypto::box_;
+use std::time::Duration;
+
+/// Number of Nodes added to the benchmarked tables.
+const NODES: usize = 10_000;
+
+fn node() -> Node {
+    Node::new(
+        Address::random(),
+        Some(Link::new(String::from("127.0.0.1"), 4242)),
+    )
+}
+
+fn table() -> Safe {
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 4242);
+    let table = Safe::new(20, center);
+    for _ in 0..NODES {
+        table.add(node());
+    }
+    table
+}
+
+fn bench_table(c: &mut Criterion) {
+    let table = table();
+    let nodes: Vec<Node> = (0..NODES).map(|_| node()).collect();
+    let targets: Vec<Address> = (0..NODES).map(|_| Address::random()).collect();
+
+    c.bench_function("table_add", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            table.add(black_box(nodes[i % NODES].clone()));
+            i += 1;
+        })
+    });
+    c.bench_function("table_get", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            let found = table.get_copy(black_box(&targets[i % NODES]), 20);
+            i += 1;
+            found
+        })
+    });
+    c.bench_function("table_find", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            let found = table.find(black_box(&nodes[i % NODES].address));
+            i += 1;
+            found
+        })
+    });
+}
+
+fn bench_cache(c: &mut Criterion) {
+    let uuids: Vec<[u8; 16]> = (0..NODES).map(|_| rand::random()).collect();
+
+    c.bench_function("cache_insert", |b| {
+        b.iter_batched(
+            || Cache::new(NODES / 2, Duration::from_secs(60)),
+            |cache| {
+                for uuid in &uuids {
+                    cache.insert(black_box(uuid));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("cache_duplicate", |b| {
+        let cache = Cache::new(NODES, Duration::from_secs(60));
+        for uuid in &uuids {
+            cache.insert(uuid);
+        }
+        let mut i = 0;
+        b.iter(|| {
+            let fresh = cache.insert(black_box(&uuids[i % NODES]));
+            i += 1;
+            fresh
+        })
+    });
+}
+
+criterion_group!(benches, bench_table, bench_cache);
+criterion_main!(benches);
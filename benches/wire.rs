@@ -0,0 +1,60 @@
+//! Serialization benchmarks for Wires with large bodies, covering the
+//! path of a Transaction from its creation to the network and back.
+//!
+//! The parse group compares copying the received bytes with sharing
+//! them. Changes to the serialization can be compared against a
+//! baseline saved before them:
+//!
+//! ``` text
+//! cargo bench --bench wire -- --save-baseline before
+//! cargo bench --bench wire -- --baseline before
+//! ```
+
+use actaeon::message::Message;
+use actaeon::node::Address;
+use actaeon::transaction::{Class, Transaction, Wire};
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+/// Size of the benchmarked bodies.
+const BODY: usize = 60 * 1024;
+
+fn transaction() -> Transaction {
+    let message = Message::new(
+        Class::Action,
+        Address::random(),
+        Address::random(),
+        Address::random(),
+        vec![42; BODY],
+    );
+    Transaction::new(message)
+}
+
+fn bench_wire(c: &mut Criterion) {
+    let t = transaction();
+    let wire = t.to_wire();
+    let raw = wire.as_bytes();
+
+    c.bench_function("to_wire", |b| b.iter(|| black_box(&t).to_wire()));
+    c.bench_function("as_bytes", |b| b.iter(|| black_box(&wire).as_bytes()));
+
+    let mut group = c.benchmark_group("parse");
+    group.bench_function("copied", |b| {
+        b.iter(|| Wire::from_bytes(black_box(&raw)).unwrap())
+    });
+    group.bench_function("shared", |b| {
+        let raw = Bytes::from(raw.clone());
+        b.iter(|| Wire::parse(black_box(raw.clone())).unwrap())
+    });
+    group.finish();
+
+    c.bench_function("convert", |b| {
+        b.iter(|| black_box(wire.clone()).convert().unwrap())
+    });
+    c.bench_function("from_wire", |b| {
+        b.iter(|| Transaction::from_wire(black_box(&wire)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_wire);
+criterion_main!(benches);
@@ -0,0 +1,15 @@
+//! Parses arbitrary bytes as Nodes and Links, for example from
+//! bootstrap responses or discovery announcements.
+
+#![no_main]
+
+use actaeon::discovery::Discovery;
+use actaeon::node::{Link, Node};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Node::from_bytes(data.to_vec());
+    let _ = Node::from_bulk(data.to_vec());
+    let _ = Link::from_bytes(data.to_vec());
+    let _ = Discovery::decode(data);
+});
@@ -0,0 +1,19 @@
+//! Parses arbitrary bytes as they would arrive from the network and
+//! runs them through the rest of the receiving path.
+
+#![no_main]
+
+use actaeon::transaction::{Fragments, Wire};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(mut wire) = Wire::from_bytes(data) {
+        assert_eq!(wire.as_bytes(), data);
+        let _ = wire.verify();
+        if wire.decompress().is_ok() {
+            if let Ok(Some(wire)) = Fragments::new().insert(wire) {
+                let _ = wire.convert();
+            }
+        }
+    }
+});
@@ -0,0 +1,252 @@
+//! # Backend
+//!
+//! Records, routing Table snapshots, the messages queued while offline
+//! and the ones waiting to be delivered can be persisted through any
+//! key/value store implementing the Storage trait, selected through
+//! the InterfaceBuilder. Each component only uses its own namespace,
+//! so a single Storage can be shared by all of them. Two
+//! implementations are included: MemoryStorage, which is mostly
+//! useful for tests, and FileStorage, which stores every value in a
+//! plain file.
+//!
+//! Without a Storage the paths of the Config are used, which keep the
+//! Records in a Journal and the Table in a snapshot file.
+
+use crate::error::Error;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Namespace of the Records, keyed by their Address.
+pub const RECORDS: &str = "records";
+
+/// Namespace of the routing Table snapshot and the banned Addresses.
+pub const TABLE: &str = "table";
+
+/// Namespace of the messages queued while the node is offline, keyed
+/// by their position in the queue.
+pub const OUTBOX: &str = "outbox";
+
+/// Namespace of the at-least-once messages waiting to be
+/// acknowledged, keyed by their target and Receipt.
+pub const DELIVERY: &str = "delivery";
+
+/// Keys of a namespace with their values.
+pub type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Values of all namespaces of a MemoryStorage.
+type Namespaces = HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>;
+
+/// Key/value store used for persisting the state of a node. Keys are
+/// namespaced by the component using them. Implementations have to
+/// be safe to use from multiple threads, errors get logged by the
+/// callers and don't stop the system.
+pub trait Storage: Send + Sync {
+    /// Returns the value of the key, None if it doesn't exist.
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Stores the value under the key, replacing the previous one.
+    fn put(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<(), Error>;
+
+    /// Removes the key, keys that don't exist are ignored.
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<(), Error>;
+
+    /// Returns all keys of the namespace with their values, sorted by
+    /// key.
+    fn iterate(&self, namespace: &str) -> Result<Entries, Error>;
+}
+
+/// Keeps all values in memory, they are lost once the last clone is
+/// dropped. Clones share the same values.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage(Arc<Mutex<Namespaces>>);
+
+/// Stores every value in its own file, named after the hex encoded
+/// key, inside a directory per namespace. Values are written to a
+/// temporary file first, so a crash never leaves a partial one
+/// behind.
+#[derive(Debug, Clone)]
+pub struct FileStorage {
+    /// Directory containing the namespaces, it gets created on
+    /// demand.
+    root: PathBuf,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Namespaces>, Error> {
+        self.0
+            .lock()
+            .map_err(|_| Error::System(String::from("storage lock is poisoned")))
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .lock()?
+            .get(namespace)
+            .and_then(|values| values.get(key))
+            .cloned())
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+        self.lock()?
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_vec(), value);
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<(), Error> {
+        if let Some(values) = self.lock()?.get_mut(namespace) {
+            values.remove(key);
+        }
+        Ok(())
+    }
+
+    fn iterate(&self, namespace: &str) -> Result<Entries, Error> {
+        Ok(self
+            .lock()?
+            .get(namespace)
+            .map(|values| values.clone().into_iter().collect())
+            .unwrap_or_default())
+    }
+}
+
+impl FileStorage {
+    /// Creates a FileStorage in the directory, nothing gets written
+    /// until the first value is stored.
+    pub fn new(root: &str) -> Self {
+        Self {
+            root: PathBuf::from(root),
+        }
+    }
+
+    /// Directory of the namespace, fails for names that aren't
+    /// usable as directory names.
+    fn directory(&self, namespace: &str) -> Result<PathBuf, Error> {
+        let valid = !namespace.is_empty()
+            && namespace
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            return Err(Error::Invalid(String::from("namespace is not valid")));
+        }
+        Ok(self.root.join(namespace))
+    }
+
+    fn path(&self, namespace: &str, key: &[u8]) -> Result<PathBuf, Error> {
+        Ok(self.directory(namespace)?.join(encode(key)))
+    }
+}
+
+impl Storage for FileStorage {
+    fn get(&self, namespace: &str, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        match fs::read(self.path(namespace, key)?) {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn put(&self, namespace: &str, key: &[u8], value: Vec<u8>) -> Result<(), Error> {
+        fs::create_dir_all(self.directory(namespace)?)?;
+        let path = self.path(namespace, key)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, value)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn delete(&self, namespace: &str, key: &[u8]) -> Result<(), Error> {
+        match fs::remove_file(self.path(namespace, key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn iterate(&self, namespace: &str) -> Result<Entries, Error> {
+        let entries = match fs::read_dir(self.directory(namespace)?) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut values = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            // Temporary files of interrupted writes are skipped.
+            let key = match entry.file_name().to_str().and_then(decode) {
+                Some(key) => key,
+                None => continue,
+            };
+            values.push((key, fs::read(entry.path())?));
+        }
+        values.sort();
+        Ok(values)
+    }
+}
+
+/// Hex encodes a key for the file name.
+fn encode(key: &[u8]) -> String {
+    key.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes a file name, None if it isn't a hex encoded key.
+fn decode(name: &str) -> Option<Vec<u8>> {
+    if name.is_empty() || !name.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..name.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(name.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(storage: &dyn Storage) {
+        assert_eq!(storage.get("one", &[1]).unwrap(), None);
+        storage.put("one", &[2], vec![20]).unwrap();
+        storage.put("one", &[1], vec![10]).unwrap();
+        storage.put("two", &[1], vec![30]).unwrap();
+        storage.put("one", &[1], vec![11]).unwrap();
+        assert_eq!(storage.get("one", &[1]).unwrap(), Some(vec![11]));
+        assert_eq!(
+            storage.iterate("one").unwrap(),
+            vec![(vec![1], vec![11]), (vec![2], vec![20])]
+        );
+        storage.delete("one", &[1]).unwrap();
+        storage.delete("one", &[3]).unwrap();
+        assert_eq!(storage.iterate("one").unwrap(), vec![(vec![2], vec![20])]);
+        assert_eq!(storage.iterate("two").unwrap(), vec![(vec![1], vec![30])]);
+        assert!(storage.iterate("three").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_storage_memory() {
+        let storage = MemoryStorage::new();
+        check(&storage);
+        assert_eq!(storage.clone().get("two", &[1]).unwrap(), Some(vec![30]));
+    }
+
+    #[test]
+    fn test_storage_file() {
+        let mut root = std::env::temp_dir();
+        root.push(format!("actaeon-{}", rand::random::<u64>()));
+        let root = root.to_string_lossy().to_string();
+        let storage = FileStorage::new(&root);
+        check(&storage);
+        assert!(storage.put("../escape", &[1], vec![1]).is_err());
+        let storage = FileStorage::new(&root);
+        assert_eq!(storage.get("two", &[1]).unwrap(), Some(vec![30]));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -23,6 +23,10 @@ pub struct Bucket {
     nodes: Vec<Node>,
     /// Maximum length of the nodes array.
     limit: usize,
+    /// Recently seen nodes that didn't fit into the full bucket, from
+    /// old to new. They replace evicted nodes and are limited to the
+    /// same length as the nodes.
+    candidates: Vec<Node>,
 }
 
 impl Bucket {
@@ -36,6 +40,7 @@ impl Bucket {
         Bucket {
             nodes: Vec::new(),
             limit,
+            candidates: Vec::new(),
         }
     }
 
@@ -55,11 +60,11 @@ impl Bucket {
     /// function will also fail.
     pub fn try_add(&mut self, node: Node) -> Result<(), Error> {
         if self.len() == self.limit {
-            return Err(Error::Full);
+            Err(Error::Full)
         } else {
             self.nodes.push(node);
             self.dedup();
-            return Ok(());
+            Ok(())
         }
     }
 
@@ -67,12 +72,12 @@ impl Bucket {
     /// is not. It roughly follows the Kademlia update rules:
     ///
     /// - If there is still space in the bucket, the node is simply
-    /// appended.
+    ///   appended.
     ///
     /// - If there is no space, the oldest node gets replaced, but
-    /// only if it is currently not reachable. This part requires the
-    /// nodes in the table to get checked by a dedicated process. No
-    /// status checks are happening in the table.
+    ///   only if it is currently not reachable. This part requires the
+    ///   nodes in the table to get checked by a dedicated process. No
+    ///   status checks are happening in the table.
     ///
     /// This function will not split buckets or create new, should the
     /// bucket be full the node is simply disregarded.
@@ -88,6 +93,8 @@ impl Bucket {
                 // dedicated process.
                 if !first.is_reachable() {
                     *first = node;
+                } else {
+                    self.remember(node);
                 }
                 self.sort();
                 self.dedup();
@@ -97,25 +104,32 @@ impl Bucket {
 
     /// Takes ownership of the Bucket and returns two new once, with
     /// the nodes distributed between the two based on their distance
-    /// in comparison to the upper limit. The center has to be
-    /// provided to calculate the distance, the "ul" parameter is the
-    /// upper limit of the bucket. When spliting the root bucket the
-    /// upper limit would be 255 and the two new buckets would have
-    /// upper limits of 127 and 255. This function will do no
-    /// validation of size and will return even if one of the buckets
-    /// is empty.
-    pub fn split(self, center: &Center, ul: u8) -> (Self, Self) {
+    /// to the center. The "depth" parameter is the number of leading
+    /// bits all nodes in the bucket share with the center, nodes
+    /// sharing more of them are near. When spliting the root bucket
+    /// the depth would be 0, the near bucket then gets all nodes
+    /// whose distance starts with a zero bit. This function will do
+    /// no validation of size and will return even if one of the
+    /// buckets is empty. Candidates get distributed the same way.
+    pub fn split(self, center: &Center, depth: usize) -> (Self, Self) {
         let mut near = Bucket::new(self.limit);
         let mut far = Bucket::new(self.limit);
+        let is_near = |node: &Node| node.address.leading_zeros(&center.public) as usize > depth;
 
         for i in self.nodes {
-            let index = (i.address.clone() ^ center.public.clone())[0];
-            if index < (ul / 2) {
+            if is_near(&i) {
                 near.add(i);
             } else {
                 far.add(i);
             }
         }
+        for i in self.candidates {
+            if is_near(&i) {
+                near.remember(i);
+            } else {
+                far.remember(i);
+            }
+        }
 
         (near, far)
     }
@@ -152,7 +166,7 @@ impl Bucket {
         }
         targets.sort();
         targets.truncate(limit);
-        return targets;
+        targets
     }
 
     pub fn remove(&mut self, target: &Address) -> Result<(), Error> {
@@ -162,7 +176,47 @@ impl Bucket {
                 self.nodes.remove(i);
                 Ok(())
             }
-            None => Err(Error::Unknown),
+            None => Err(Error::Routing(String::from("node is not in the bucket"))),
+        }
+    }
+
+    /// Moves the most recently seen candidate into the bucket, as
+    /// long as there is space for it. Returns its Address if one was
+    /// added.
+    pub fn promote(&mut self) -> Option<Address> {
+        if self.len() >= self.limit {
+            return None;
+        }
+        // Candidates might have been added to the bucket in the
+        // meantime.
+        let node = loop {
+            let node = self.candidates.pop()?;
+            if self.find(&node.address).is_none() {
+                break node;
+            }
+        };
+        let address = node.address.clone();
+        self.nodes.push(node);
+        self.sort();
+        Some(address)
+    }
+
+    /// All stored candidates, from old to new.
+    pub fn candidates(&self) -> &[Node] {
+        &self.candidates
+    }
+
+    /// Stores the node as the newest candidate, should the limit be
+    /// reached the oldest one gets dropped. Nodes already in the
+    /// bucket are ignored.
+    pub fn remember(&mut self, node: Node) {
+        if self.find(&node.address).is_some() {
+            return;
+        }
+        self.candidates.retain(|c| c.address != node.address);
+        self.candidates.push(node);
+        if self.candidates.len() > self.limit {
+            self.candidates.remove(0);
         }
     }
 
@@ -186,6 +240,11 @@ impl Bucket {
         self.nodes.len()
     }
 
+    /// Returns true if the bucket holds no nodes.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
     /// Uses the Ord and Partial Ord implementation Address to sort
     /// the nodes based on that. This does not represent the distance
     /// sorting for Kademlia but is just a shortcut for easier
@@ -207,7 +266,7 @@ mod tests {
         let mut bucket = gen_bucket(20);
         let node = gen_node("test");
         let ret = bucket.try_add(node);
-        assert_eq!(ret.is_err(), false);
+        assert!(ret.is_ok());
     }
 
     #[test]
@@ -217,7 +276,7 @@ mod tests {
         leaf.try_add(node).unwrap();
         let node = gen_node("test2");
         let ret = leaf.try_add(node);
-        assert_eq!(ret.is_err(), true);
+        assert!(ret.is_err());
     }
 
     #[test]
@@ -227,7 +286,7 @@ mod tests {
         bucket.try_add(node).unwrap();
         let node = gen_node("test2");
         let ret = bucket.try_add(node);
-        assert_eq!(ret.is_err(), true);
+        assert!(ret.is_err());
     }
 
     #[test]
@@ -258,7 +317,7 @@ mod tests {
         root.add(gen_node("second"));
         root.add(gen_node("another"));
         let center = gen_center();
-        let (near, far) = root.split(&center, 255);
+        let (near, far) = root.split(&center, 0);
         assert_eq!(near.len(), 2);
         assert_eq!(far.len(), 1);
     }
@@ -288,7 +347,43 @@ mod tests {
         let mut root = Bucket::new(20);
         let target = gen_node("first").address;
 
-        assert_eq!(root.remove(&target).is_err(), true);
+        assert!(root.remove(&target).is_err());
+    }
+
+    #[test]
+    fn test_bucket_candidates() {
+        let mut bucket = gen_bucket(1);
+        let mut node = gen_node("first");
+        node.link = Some(crate::node::Link::new(String::from("127.0.0.1"), 4242));
+        node.update(true);
+        bucket.add(node.clone());
+        bucket.add(gen_node("second"));
+        bucket.add(gen_node("second"));
+        assert_eq!((bucket.len(), bucket.candidates().len()), (1, 1));
+        assert_eq!(bucket.promote(), None);
+
+        bucket.remove(&node.address).unwrap();
+        assert_eq!(bucket.promote(), Some(gen_node("second").address));
+        assert_eq!((bucket.len(), bucket.candidates().len()), (1, 0));
+    }
+
+    #[test]
+    fn test_bucket_split_candidates() {
+        let mut root = Bucket::new(1);
+        for name in ["first", "second", "another"] {
+            root.remember(gen_node(name));
+        }
+        root.remember(gen_node("first"));
+        assert_eq!(root.candidates().len(), 1);
+        root.add(gen_node("second"));
+        assert_eq!(root.promote(), None);
+
+        let mut root = Bucket::new(20);
+        for name in ["first", "second", "another"] {
+            root.remember(gen_node(name));
+        }
+        let (near, far) = root.split(&gen_center(), 0);
+        assert_eq!((near.candidates().len(), far.candidates().len()), (2, 1));
     }
 
     fn gen_bucket(l: usize) -> Bucket {
@@ -303,6 +398,10 @@ mod tests {
         let mut b = [0; 32];
         b[0] = 42;
         let s = SecretKey::from_slice(&b).unwrap();
-        Center::new(s, String::from(""), 8080)
+        // The tests depend on the Address of this key.
+        Center {
+            public: Address::new(s.public_key()),
+            ..Center::new(s, String::from(""), 8080)
+        }
     }
 }
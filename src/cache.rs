@@ -0,0 +1,234 @@
+//! # Cache
+//!
+//! Deduplication of incoming and outgoing Wires. Since each message
+//! might get received multiple times, the UUIDs of all recent
+//! Transactions are stored and Wires with a known UUID get dropped.
+//! Entries expire once they haven't been seen for the TTL and the
+//! least recently seen ones get evicted should the cache be full.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Thread safe LRU cache of Transaction UUIDs. Clones share the same
+/// entries, limits and counters, so a single Cache can be used by all
+/// Handler threads.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    entries: Arc<Mutex<Entries>>,
+    /// Number of UUIDs that were already in the cache.
+    hits: Arc<AtomicU64>,
+    /// Number of UUIDs that were not yet in the cache.
+    misses: Arc<AtomicU64>,
+}
+
+/// The entries are stored twice: the map allows lookups by UUID, the
+/// order contains the same entries sorted by when they were last
+/// seen. Every time an entry is seen it gets a new sequence number,
+/// which moves it to the end of the order.
+#[derive(Debug, Default)]
+struct Entries {
+    /// Sequence number and time each UUID was last seen.
+    map: HashMap<[u8; 16], (u64, Instant)>,
+    /// UUIDs by sequence number, the first one is the least recently
+    /// seen.
+    order: BTreeMap<u64, [u8; 16]>,
+    /// Next sequence number, it only ever increases.
+    next: u64,
+    /// Maximum number of UUIDs, once it is reached the least recently
+    /// seen one gets evicted.
+    limit: usize,
+    /// Duration after which an entry that hasn't been seen expires.
+    ttl: Duration,
+}
+
+impl Cache {
+    /// Creates a new empty Cache, the limit is the maximum number of
+    /// UUIDs and the TTL the time after which they expire.
+    pub fn new(limit: usize, ttl: Duration) -> Self {
+        let entries = Entries {
+            limit,
+            ttl,
+            ..Entries::default()
+        };
+        Self {
+            entries: Arc::new(Mutex::new(entries)),
+            hits: Arc::new(AtomicU64::new(0)),
+            misses: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Adds the UUID to the cache and returns true if it wasn't
+    /// already in it. Known UUIDs count as seen again, which resets
+    /// their TTL. Checking and adding happen at once, so the same
+    /// UUID arriving on two connections is only accepted once.
+    pub fn insert(&self, uuid: &[u8; 16]) -> bool {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(now);
+        let known = entries.touch(uuid, now);
+        if known {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            entries.shrink();
+        }
+        !known
+    }
+
+    /// Changes the limit and the TTL of all clones, entries above the
+    /// new limit get evicted right away.
+    pub fn resize(&self, limit: usize, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.limit = limit;
+        entries.ttl = ttl;
+        entries.expire(Instant::now());
+        entries.shrink();
+    }
+
+    /// Maximum number of UUIDs and their TTL.
+    pub fn limits(&self) -> (usize, Duration) {
+        let entries = self.entries.lock().unwrap();
+        (entries.limit, entries.ttl)
+    }
+
+    /// Checks if the UUID is in the cache without changing it.
+    pub fn contains(&self, uuid: &[u8; 16]) -> bool {
+        let entries = self.entries.lock().unwrap();
+        match entries.map.get(uuid) {
+            Some((_, seen)) => seen.elapsed() < entries.ttl,
+            None => false,
+        }
+    }
+
+    /// Number of UUIDs currently in the cache, including expired ones
+    /// that haven't been removed yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of inserted UUIDs that were already in the cache.
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Number of inserted UUIDs that were not yet in the cache.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl Entries {
+    /// Marks the UUID as seen right now, returns true if it was
+    /// already known.
+    fn touch(&mut self, uuid: &[u8; 16], now: Instant) -> bool {
+        let sequence = self.next;
+        self.next += 1;
+        self.order.insert(sequence, *uuid);
+        match self.map.insert(*uuid, (sequence, now)) {
+            Some((previous, _)) => {
+                self.order.remove(&previous);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all entries that haven't been seen for the TTL. Since
+    /// the order is sorted by time they are all at the start.
+    fn expire(&mut self, now: Instant) {
+        while let Some((_, uuid)) = self.order.first_key_value() {
+            match self.map.get(uuid) {
+                Some((_, seen)) if now.duration_since(*seen) < self.ttl => break,
+                _ => self.evict(),
+            }
+        }
+    }
+
+    /// Evicts the least recently seen entries until the limit is no
+    /// longer exceeded.
+    fn shrink(&mut self) {
+        while self.map.len() > self.limit {
+            self.evict();
+        }
+    }
+
+    /// Removes the least recently seen entry.
+    fn evict(&mut self) {
+        if let Some((_, uuid)) = self.order.pop_first() {
+            self.map.remove(&uuid);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_insert() {
+        let cache = Cache::new(10, Duration::from_secs(60));
+        assert!(cache.insert(&[1; 16]));
+        assert!(!cache.insert(&[1; 16]));
+        assert!(cache.insert(&[2; 16]));
+        assert!(cache.contains(&[1; 16]));
+        assert!(!cache.contains(&[3; 16]));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 2);
+        let clone = cache.clone();
+        assert!(!clone.insert(&[2; 16]));
+        assert_eq!(cache.hits(), 2);
+    }
+
+    #[test]
+    fn test_cache_evict() {
+        let cache = Cache::new(3, Duration::from_secs(60));
+        for i in 0..3 {
+            cache.insert(&[i; 16]);
+        }
+        // Seeing the first UUID again makes the second one the least
+        // recently seen.
+        assert!(!cache.insert(&[0; 16]));
+        assert!(cache.insert(&[3; 16]));
+        assert_eq!(cache.len(), 3);
+        assert!(cache.contains(&[0; 16]));
+        assert!(!cache.contains(&[1; 16]));
+        assert!(cache.contains(&[3; 16]));
+        // The newest entries are never the ones evicted.
+        for i in 4..100 {
+            assert!(cache.insert(&[i; 16]));
+            assert!(!cache.insert(&[i; 16]));
+        }
+    }
+
+    #[test]
+    fn test_cache_expire() {
+        let cache = Cache::new(10, Duration::from_millis(20));
+        cache.insert(&[1; 16]);
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(!cache.contains(&[1; 16]));
+        assert!(cache.insert(&[2; 16]));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.insert(&[1; 16]));
+    }
+
+    #[test]
+    fn test_cache_resize() {
+        let cache = Cache::new(10, Duration::from_secs(60));
+        let clone = cache.clone();
+        for i in 0..10 {
+            cache.insert(&[i; 16]);
+        }
+        clone.resize(4, Duration::from_secs(30));
+        assert_eq!(cache.limits(), (4, Duration::from_secs(30)));
+        assert_eq!(cache.len(), 4);
+        assert!(!cache.contains(&[5; 16]));
+        assert!(cache.contains(&[6; 16]));
+    }
+}
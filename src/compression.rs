@@ -0,0 +1,69 @@
+//! # Compression
+//!
+//! Raw DEFLATE compression of Wire bodies through flate2. Text heavy
+//! payloads like JSON usually shrink to a fraction of their size,
+//! random data grows slightly, in which case the body is simply sent
+//! uncompressed.
+
+use crate::error::Error;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Compresses the data, the result can be larger than the input.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut encoder = DeflateEncoder::new(Vec::with_capacity(data.len() / 2), Compression::fast());
+    // Writing into a Vec can't fail.
+    let _ = encoder.write_all(data);
+    encoder.finish().unwrap_or_default()
+}
+
+/// Reverses compress. Fails on invalid data or if the result would
+/// be larger than the limit, without ever inflating more than that.
+pub fn decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::with_capacity(data.len() * 2);
+    DeflateDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut output)
+        .map_err(|_| Error::Invalid(String::from("compressed data is not valid")))?;
+    if output.len() > limit {
+        return Err(Error::Invalid(String::from(
+            "decompressed data is too large",
+        )));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compression_text() {
+        let data = r#"{"name": "actaeon", "type": "topic"}"#.repeat(100);
+        let compressed = compress(data.as_bytes());
+        assert!(compressed.len() < data.len() / 10);
+        let decompressed = decompress(&compressed, data.len()).unwrap();
+        assert_eq!(decompressed, data.as_bytes());
+    }
+
+    #[test]
+    fn test_compression_random() {
+        for length in [0, 1, 4, 127, 128, 129, 5000] {
+            let data: Vec<u8> = (0..length).map(|_| rand::random()).collect();
+            let compressed = compress(&data);
+            assert!(compressed.len() <= data.len() + data.len() / 100 + 16);
+            assert_eq!(decompress(&compressed, length).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_compression_invalid() {
+        let data = vec![42; 1000];
+        let compressed = compress(&data);
+        assert!(decompress(&compressed, 999).is_err());
+        assert!(decompress(&compressed[..compressed.len() - 1], 1000).is_err());
+        assert!(decompress(&[0xff, 1, 0], 1000).is_err());
+    }
+}
@@ -11,37 +11,270 @@
 //! node, which currently need to be manually set by the user. In the
 //! future this should get replaced by some sort of setup script or
 //! automatically handled in the signaling config.
+//!
+//! The system config file has a section for each subsystem: network,
+//! routing, cache, connections, security, topics, storage, outbox,
+//! discovery and status. All of them are optional, missing settings
+//! keep their default values. Older files listing all settings in the
+//! network section are still accepted.
 
 use crate::error::Error;
+use crate::node::{Address, Host, Link, Node};
+use crate::router;
+use crate::selection::{self, Closest, ClosestReachable, LowestLatency};
+use crate::trace;
+use crate::transaction;
+use crate::transport::{self, Tcp, Timeouts, Udp, WebSocket};
 use serde::Deserialize;
+use std::fmt;
 use std::fs;
 use std::fs::File;
 use std::io::BufRead;
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
 
-/// Config values for the config of networking parameters if the
-/// config is loaded from the default toml file. The values will
-/// usually come from the config file. Others might get populated by
-/// the code in order to prevent the user from chaning parameters.
-#[derive(Deserialize)]
+/// The network section of the config file. Besides the connection to
+/// the network it still accepts all the settings of the other
+/// sections, which is how older config files list them. Should a
+/// setting be in both the dedicated section wins.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 struct Network {
-    /// serde deserialization value for the config file.
-    bucket: usize,
-    /// Number of message to be sent
-    replication: usize,
-    /// serde deserialization value for the config file.
-    signaling: String,
+    /// Address or hostname of the signaling server.
+    signaling: Option<String>,
+    /// Port of the signaling server.
+    port: Option<usize>,
+    /// Either "tcp", "udp" or "websocket".
+    transport: Option<String>,
+    /// Register with the signaling server as the relay.
+    relay: Option<bool>,
+    /// Capacity of the internal Channels.
+    capacity: Option<usize>,
+    /// Known nodes the node bootstraps through.
+    #[serde(default)]
+    bootstrap: Vec<Peer>,
+    /// Further Links the node listens on.
+    #[serde(default)]
+    listen: Vec<Listen>,
+    bucket: Option<usize>,
+    replication: Option<usize>,
+    refresh: Option<u64>,
+    ping: Option<u64>,
+    attempts: Option<usize>,
+    difficulty: Option<u32>,
+    cache: Option<usize>,
+    ttl: Option<u64>,
+    connections: Option<usize>,
+    pinned: Option<usize>,
+    timeout: Option<u64>,
+    connect_timeout: Option<u64>,
+    read_timeout: Option<u64>,
+    write_timeout: Option<u64>,
+    fanout: Option<usize>,
+    message_size: Option<usize>,
+    compression: Option<usize>,
+    coalesce: Option<usize>,
+    flush: Option<u64>,
+    encryption: Option<bool>,
+    signatures: Option<bool>,
+    presence: Option<u64>,
+    republish: Option<u64>,
+    transfer: Option<u64>,
+    replicas: Option<usize>,
+    expiry: Option<u64>,
+    branching: Option<usize>,
+    database: Option<String>,
+    records: Option<String>,
+    discovery: Option<usize>,
+}
+
+/// The routing section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Routing {
+    /// Size of each bucket of the routing table.
+    bucket: Option<usize>,
+    /// Number of nodes each message is sent to.
+    replication: Option<usize>,
+    /// Bucket refresh interval in seconds.
+    refresh: Option<u64>,
+    /// Liveness check interval in seconds.
+    ping: Option<u64>,
+    /// Failed attempts in a row before a node is evicted.
+    attempts: Option<usize>,
+    /// Leading zero bits of every Address hash.
+    difficulty: Option<u32>,
+    /// Maximum depth of the routing tree in bits.
+    depth: Option<usize>,
+    /// Maximum number of nodes in bootstrap answers.
+    sample: Option<usize>,
+    /// Either "closest", "closest-reachable" or "lowest-latency".
+    selection: Option<String>,
+}
+
+/// The cache section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Caching {
+    /// Maximum number of entries in the Transaction cache.
+    size: Option<usize>,
+    /// Transaction cache TTL in seconds.
+    ttl: Option<u64>,
+}
+
+/// The connections section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Connections {
+    /// Maximum number of open connections.
+    limit: Option<usize>,
+    /// Number of closest nodes whose connections are pinned.
+    pinned: Option<usize>,
+    /// Idle timeout in seconds.
+    timeout: Option<u64>,
+    /// Timeout of establishing connections in seconds.
+    connect_timeout: Option<u64>,
+    /// Timeout of blocking reads in seconds.
+    read_timeout: Option<u64>,
+    /// Timeout of blocking writes in seconds.
+    write_timeout: Option<u64>,
+    /// Number of threads dialing peers.
+    fanout: Option<usize>,
+    /// Largest Wire accepted from peers in bytes.
+    message_size: Option<usize>,
+    /// Bodies larger than this many bytes get compressed.
+    compression: Option<usize>,
+    /// Size threshold of coalesced Wires in bytes.
+    coalesce: Option<usize>,
+    /// Flush delay of coalesced Wires in milliseconds.
+    flush: Option<u64>,
+    /// Seconds undeliverable Transactions are retried.
+    retry: Option<u64>,
+}
+
+/// The security section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Security {
+    /// End-to-end encryption of Action messages.
+    encryption: Option<bool>,
+    /// Signed Wires.
+    signatures: Option<bool>,
+}
+
+/// The topics section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Topics {
+    /// Presence announcement interval in seconds.
+    presence: Option<u64>,
+    /// Record republish interval in seconds.
+    republish: Option<u64>,
+    /// Record transfer interval in seconds.
+    transfer: Option<u64>,
+    /// Number of nodes hosting each Record.
+    replicas: Option<usize>,
+    /// Record inactivity period in seconds.
+    expiry: Option<u64>,
+    /// Subscribers a Record sends propagated messages to directly.
+    branching: Option<usize>,
+    /// Total size of the messages kept for offline subscribers.
+    inbox: Option<usize>,
+}
+
+/// The storage section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Storage {
+    /// Path of the routing table snapshot.
+    database: Option<String>,
+    /// Path of the Record journal.
+    records: Option<String>,
+}
+
+/// The outbox section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Queueing {
+    /// Maximum number of messages queued while offline.
+    messages: Option<usize>,
+    /// Maximum total size of the queued messages in bytes.
+    size: Option<usize>,
+}
+
+/// The values section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Values {
+    /// Maximum number of values stored for other nodes.
+    entries: Option<usize>,
+    /// Maximum total size of the stored values in bytes.
+    size: Option<usize>,
+}
+
+/// The discovery section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Discovering {
+    /// UDP multicast port.
+    port: Option<usize>,
+}
+
+/// The status section of the config file.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Reporting {
+    /// TCP port of the status endpoint.
+    port: Option<usize>,
+    /// IP address the status endpoint binds to.
+    address: Option<String>,
+}
+
+/// A known node in the config file, the Address is hex encoded.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Peer {
+    address: Address,
+    ip: String,
+    port: usize,
+}
+
+/// A further Link the node listens on in the config file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Listen {
+    ip: String,
     port: usize,
-    /// serde deserialization value for the config file.
-    cache: usize,
 }
 
-/// The current config only contains details about the network. In the
-/// default toml file this is still stored as a section, therefor
-/// requiring a dedicated struct.
+/// The system config file has one section for each subsystem, all of
+/// them are optional. Settings missing in all sections keep the value
+/// of Config::default.
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct LoadConfig {
-    /// Only section in the system config toml file.
+    #[serde(default)]
     network: Network,
+    #[serde(default)]
+    routing: Routing,
+    #[serde(default)]
+    cache: Caching,
+    #[serde(default)]
+    connections: Connections,
+    #[serde(default)]
+    security: Security,
+    #[serde(default)]
+    topics: Topics,
+    #[serde(default)]
+    storage: Storage,
+    #[serde(default)]
+    outbox: Queueing,
+    #[serde(default)]
+    values: Values,
+    #[serde(default)]
+    discovery: Discovering,
+    #[serde(default)]
+    status: Reporting,
 }
 
 /// The main system config for the entire system. It is created here
@@ -52,7 +285,7 @@ struct LoadConfig {
 /// converted, so the function might fail. Depending on how much is
 /// eventually handled by the application instead of the implementer
 /// it might also contain details about logging.
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq)]
 pub struct Config {
     /// Defines the size value of the kademlia based routing system,
     /// comparable to the variable "k". It defines the size of each
@@ -69,6 +302,247 @@ pub struct Config {
     /// Maximum number of arguments in the Transaction cache in the
     /// Actaeon Process.
     pub cache: usize,
+    /// Seconds after which a Transaction that hasn't been seen again
+    /// is removed from the cache. Should the same message arrive
+    /// after that it gets processed again.
+    pub ttl: u64,
+    /// Bodies of Action messages are encrypted end-to-end by default.
+    /// Disabling it only works if all nodes in the network do the
+    /// same, since encrypted and plain messages can't be told apart.
+    pub encryption: bool,
+    /// All outgoing Wires are signed and unsigned incoming ones get
+    /// dropped. Disabling it allows communication with nodes that
+    /// don't sign their Wires.
+    pub signatures: bool,
+    /// Seconds after which a connection without any incoming data is
+    /// considered dead and gets closed. Idle connections send
+    /// keep-alive frames three times in that interval.
+    pub timeout: u64,
+    /// Seconds a new connection may take to get established before it
+    /// fails with a Timeout.
+    pub connect_timeout: u64,
+    /// Seconds blocking reads wait for the peer, mostly during the
+    /// handshake and the bootstrap. Connections handled by the I/O
+    /// threads use the idle timeout instead.
+    pub read_timeout: u64,
+    /// Seconds blocking writes wait for the peer to accept the data.
+    pub write_timeout: u64,
+    /// Seconds after which a bucket of the routing table without any
+    /// activity gets refreshed through a lookup of a random Address
+    /// in it.
+    pub refresh: u64,
+    /// Seconds between republishing all Records hosted on this node
+    /// to the closest nodes found for them.
+    pub republish: u64,
+    /// Seconds between maintenance rounds of the Records hosted on
+    /// this node. Records this node is no longer a replica of get
+    /// transferred to the closest node and removed locally once it
+    /// accepted them, the subscribers of all others get synchronized
+    /// with the other replicas.
+    pub transfer: u64,
+    /// Number of nodes closest to a Record that host it. Subscribers
+    /// only reach the closest one, the others catch up through
+    /// periodic digest exchanges.
+    pub replicas: usize,
+    /// Seconds without any subscription, announcement or message
+    /// after which a Record gets garbage collected. Records are
+    /// always removed once their last subscriber left, they are kept
+    /// regardless of activity if it isn't set.
+    pub expiry: Option<u64>,
+    /// Number of subscribers a Record sends messages propagated from
+    /// a path to directly. Larger subtrees get split into that many
+    /// partitions, one subscriber of each passes the message on to
+    /// the rest of its partition the same way.
+    pub branching: usize,
+    /// Maximum total size in bytes of the messages Records hosted on
+    /// this node keep for subscribers that can't be reached, see the
+    /// inbox module. Subscribers have to ask for it with a Retention,
+    /// zero disables it.
+    pub inbox: usize,
+    /// Seconds between presence announcements of subscribers. Records
+    /// forget subscribers that haven't announced themselves for three
+    /// intervals.
+    pub presence: u64,
+    /// Seconds between liveness checks of all nodes in the routing
+    /// table. Nodes that don't answer the Ping in time get removed.
+    pub ping: u64,
+    /// Nodes that failed this many connection attempts or Pings in a
+    /// row get evicted from the routing table.
+    pub attempts: usize,
+    /// Number of leading zero bits the hash of every Address needs
+    /// to have, Nodes with less get rejected by the Listener and the
+    /// routing table. This makes it expensive to generate Addresses
+    /// close to a target. The own Center has to satisfy it as well,
+    /// it is disabled with zero.
+    pub difficulty: u32,
+    /// Maximum number of leading bits of the distance to the own
+    /// Address the routing table splits its buckets on, which limits
+    /// it to depth + 1 buckets. By default it can split on all 256
+    /// bits, so nodes close to this one are tracked more precisely in
+    /// large networks. With 8 buckets only cover the first byte of
+    /// the distance, like in earlier versions.
+    pub depth: usize,
+    /// Maximum number of nodes other nodes get when they bootstrap
+    /// through this one, the Center comes on top. They are sampled
+    /// from all buckets of the routing table, nodes that failed to
+    /// answer the last time are left out.
+    pub sample: usize,
+    /// Maximum number of messages waiting in each of the Channels
+    /// between the Interface, Topics, the Switch and the Listener.
+    /// Once a Channel is full messages get rejected with Busy or
+    /// dropped instead of using more and more memory.
+    pub capacity: usize,
+    /// Maximum number of connections kept open. Once it is reached
+    /// the connection idle for the longest time gets closed to make
+    /// room for a new one, should all of them be pinned messages get
+    /// sent through short lived connections instead.
+    pub connections: usize,
+    /// Number of nodes closest to this one whose connections are
+    /// pinned, so they never get closed to make room for others.
+    /// Peers of Sessions and relayed nodes are always pinned.
+    pub pinned: usize,
+    /// Number of threads dialing peers without a connection. Wires
+    /// for several of them get sent at the same time, so a slow or
+    /// unreachable peer doesn't delay the others.
+    pub fanout: usize,
+    /// Largest Wire in bytes accepted from peers, reassembled
+    /// fragments included. Connections of peers sending larger ones
+    /// get closed, since they violate the protocol.
+    pub message_size: usize,
+    /// Nodes that aren't publicly reachable, for example because
+    /// they are behind a NAT, register with the signaling server as
+    /// their relay. Other nodes then reach them through it.
+    pub relay: bool,
+    /// Wire bodies larger than this many bytes get compressed, as
+    /// long as the peer supports it. Compression is disabled if it
+    /// isn't set.
+    pub compression: Option<usize>,
+    /// Small outgoing Wires get packed into a single write until this
+    /// many bytes are waiting, which saves syscalls and TCP segments
+    /// when many small messages are sent. Coalescing is disabled if it
+    /// isn't set. It doesn't change the protocol, so peers don't need
+    /// to support it.
+    pub coalesce: Option<usize>,
+    /// Milliseconds a coalesced Wire waits at most before it gets
+    /// written, even if the threshold wasn't reached.
+    pub flush: u64,
+    /// Seconds a Transaction no node could be found for is retried
+    /// with an increasing backoff, for example while the routing
+    /// table is still empty at startup. Should it still be
+    /// undeliverable after that a DeliveryFailed Event gets emitted,
+    /// zero disables retrying.
+    pub retry: u64,
+    /// Path of the routing table snapshot file. If it is set the
+    /// table is loaded on startup and stored periodically and on
+    /// shutdown.
+    pub database: Option<String>,
+    /// Path of the Record journal file. If it is set all Records
+    /// hosted on this node are loaded on startup and every change is
+    /// written to it.
+    pub records: Option<String>,
+    /// Maximum number of messages of the user queued while the
+    /// routing table is empty, they get sent once the node knows
+    /// other nodes again. With a Storage they also survive restarts.
+    /// Messages are sent right away if it isn't set.
+    pub outbox: Option<usize>,
+    /// Maximum total size of the queued messages in bytes, messages
+    /// that would exceed it get dropped.
+    pub outbox_size: usize,
+    /// Maximum number of values this node stores, see the storage
+    /// module. Once it is reached new values get rejected until
+    /// others expire.
+    pub values: usize,
+    /// Maximum total size of the stored values in bytes.
+    pub values_size: usize,
+    /// Port used for discovering other nodes on the local network
+    /// through UDP multicast. Discovery is disabled if it isn't set.
+    pub discovery: Option<usize>,
+    /// Port of the status endpoint, which serves the health of the
+    /// node as JSON over HTTP. It is disabled if it isn't set.
+    pub status: Option<usize>,
+    /// IP address the status endpoint binds to, by default only local
+    /// clients can reach it.
+    pub status_address: String,
+    /// Known nodes the Listener connects to when it starts, in
+    /// addition to the bootstrap through the signaling server. Their
+    /// routing tables get added just the same, so small fixed
+    /// clusters don't need a signaling server at all.
+    pub bootstrap: Vec<Node>,
+    /// Further Links the node listens on besides the one of the
+    /// Center, for example a LAN address next to the public one. They
+    /// are advertised in the handshake, peers in the same network as
+    /// one of them use it instead of the one of the Center.
+    pub listen: Vec<Link>,
+    /// Transport used for all connections, TCP by default.
+    pub transport: Transport,
+    /// Decides which of the closest nodes a Wire gets sent to, the
+    /// closest one by default.
+    pub selection: Selection,
+}
+
+/// Changes to the settings of a running node, only the fields that are
+/// set get applied. All other settings require a restart, changes to
+/// them get ignored. Settings of connections only apply to the ones
+/// opened afterwards.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigDelta {
+    /// Number of nodes returned by lookups, see Config.bucket.
+    pub bucket: Option<usize>,
+    /// Number of nodes each message is sent to.
+    pub replication: Option<usize>,
+    /// Maximum number of UUIDs in the duplicate detection cache.
+    pub cache: Option<usize>,
+    /// Seconds after which cached UUIDs expire.
+    pub ttl: Option<u64>,
+    /// Seconds without incoming data after which a connection gets
+    /// closed.
+    pub timeout: Option<u64>,
+    /// Seconds between the presence announcements of subscribers.
+    pub presence: Option<u64>,
+    /// Maximum number of open connections.
+    pub connections: Option<usize>,
+    /// Number of closest nodes whose connections are pinned.
+    pub pinned: Option<usize>,
+    /// Largest Wire accepted from peers in bytes.
+    pub message_size: Option<usize>,
+}
+
+/// Reloads the config file once it changes and returns the
+/// difference to the previous version.
+#[derive(Debug)]
+pub struct Watcher {
+    path: String,
+    /// Modification time of the file when it was last read.
+    modified: Option<SystemTime>,
+    /// The last valid version of the file.
+    config: Config,
+}
+
+/// Selects the Transport used by the Listener. Custom Transports can
+/// be provided directly, they only have to implement the Transport
+/// and Stream traits.
+pub enum Transport {
+    /// Every connection is a TcpStream.
+    Tcp,
+    /// Every Wire is sent as a single datagram.
+    Udp,
+    /// Every Wire is sent as a binary WebSocket message.
+    WebSocket,
+    /// A user provided Transport, it gets bound by the Listener.
+    Custom(Box<dyn transport::Transport>),
+}
+
+/// Selects the SelectionPolicy used by the Listener, see the
+/// selection module. Custom policies can be provided directly.
+pub enum Selection {
+    /// Always the closest nodes.
+    Closest,
+    /// The closest nodes that didn't fail recently.
+    ClosestReachable,
+    /// The fastest links among twice as many of the closest nodes.
+    LowestLatency,
+    /// A user provided SelectionPolicy.
+    Custom(Box<dyn selection::SelectionPolicy>),
 }
 
 /// The center config can be loaded from a dedicated file, therefore a
@@ -78,6 +552,7 @@ pub struct Config {
 /// secret key file (currently) has to be stored in the same directory
 /// as the center file.
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct LoadCenter {
     /// IP without any protocols (https, tcp, etc). Domains (should)
     /// also work.
@@ -88,6 +563,8 @@ struct LoadCenter {
     /// Whenever possible the hostname is used instead of the routing
     /// key.
     hostname: String,
+    /// Optional, path of the key file.
+    key: Option<String>,
 }
 
 /// Internally it is more convenient to use a dedicated struct for the
@@ -105,7 +582,8 @@ pub struct Signaling {
 /// method. Like with the SystemConfig all fields are public and will
 /// be parsed into internall formats down the line.
 pub struct CenterConfig {
-    /// IP address, currently must be reachable publicly.
+    /// IPv4 / IPv6 address or hostname, currently must be reachable
+    /// publicly.
     pub ip: String,
     /// Currently hard coded to networking, for full modularity this
     /// needs to be replaced by something part of the adapter, since
@@ -118,6 +596,13 @@ pub struct CenterConfig {
     /// Where possible this is used as a user facing alternative to
     /// the routing key.
     pub hostname: String,
+    /// Path of the key file, used if no secret key is set. It gets
+    /// created with a new key on the first start, so the identity of
+    /// the node persists across restarts.
+    pub key: Option<String>,
+    /// Encrypts the key file. It is never read from the config file,
+    /// since it would be stored right next to the key.
+    pub passphrase: Option<String>,
 }
 
 impl Signaling {
@@ -126,17 +611,106 @@ impl Signaling {
         Self { server, port }
     }
 
+    /// Returns a copy of the server.
+    pub fn server(&self) -> String {
+        self.server.clone()
+    }
+
+    /// Returns the port of the server.
+    pub fn port(&self) -> usize {
+        self.port
+    }
+}
+
+impl Transport {
+    /// Creates the selected Transport, it still has to be bound.
+    pub fn build(self) -> Box<dyn transport::Transport> {
+        match self {
+            Self::Tcp => Box::new(Tcp::new()),
+            Self::Udp => Box::new(Udp::new()),
+            Self::WebSocket => Box::new(WebSocket::new()),
+            Self::Custom(transport) => transport,
+        }
+    }
+}
+
+impl fmt::Debug for Transport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp => write!(f, "Tcp"),
+            Self::Udp => write!(f, "Udp"),
+            Self::WebSocket => write!(f, "WebSocket"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PartialEq for Transport {
+    /// Custom Transports can't be compared, so they are never equal.
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::Tcp, Self::Tcp) | (Self::Udp, Self::Udp) | (Self::WebSocket, Self::WebSocket)
+        )
+    }
+}
+
+impl Selection {
+    /// Creates the selected SelectionPolicy.
+    pub fn build(self) -> Box<dyn selection::SelectionPolicy> {
+        match self {
+            Self::Closest => Box::new(Closest),
+            Self::ClosestReachable => Box::new(ClosestReachable),
+            Self::LowestLatency => Box::new(LowestLatency),
+            Self::Custom(selection) => selection,
+        }
+    }
+}
+
+impl fmt::Debug for Selection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Closest => write!(f, "Closest"),
+            Self::ClosestReachable => write!(f, "ClosestReachable"),
+            Self::LowestLatency => write!(f, "LowestLatency"),
+            Self::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+impl PartialEq for Selection {
+    /// Custom policies can't be compared, so they are never equal.
+    fn eq(&self, other: &Self) -> bool {
+        matches!(
+            (self, other),
+            (Self::Closest, Self::Closest)
+                | (Self::ClosestReachable, Self::ClosestReachable)
+                | (Self::LowestLatency, Self::LowestLatency)
+        )
+    }
+}
+
+impl fmt::Display for Signaling {
     /// Turns the two fields into a String used for a TcpStream. The
     /// same as Link::to_string().
-    pub fn to_string(&self) -> String {
-        let elements = [self.server.clone(), self.port.to_string()];
-        elements.join(":")
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", Host::parse(&self.server), self.port)
     }
 }
 
 impl Config {
     /// Manually define the config. This should be used if all values
-    /// are hard coded or obtained through a different way.
+    /// are hard coded or obtained through a different way. Encryption
+    /// and signatures are enabled by default, the timeout is 60
+    /// seconds, connecting, blocking reads and writes time out after
+    /// 5 seconds, cached Transactions expire after 5 minutes, buckets
+    /// and Records get refreshed every hour, subscribers announce
+    /// their presence every 30 seconds, nodes get pinged every 5
+    /// minutes and evicted after 3 failed attempts, the Channels
+    /// hold 1024 messages, up to 10 connections are kept open, peers
+    /// may send Wires of up to 4 MiB and undeliverable Transactions
+    /// are retried for 30 seconds, all of them can be changed through
+    /// the fields.
     pub fn new(
         bucket: usize,
         replication: usize,
@@ -150,6 +724,49 @@ impl Config {
             signaling,
             port,
             cache,
+            ttl: default_ttl(),
+            encryption: true,
+            signatures: true,
+            timeout: default_timeout(),
+            connect_timeout: default_io_timeout(),
+            read_timeout: default_io_timeout(),
+            write_timeout: default_io_timeout(),
+            refresh: default_interval(),
+            republish: default_interval(),
+            transfer: default_transfer(),
+            replicas: default_replicas(),
+            expiry: None,
+            branching: default_branching(),
+            inbox: default_inbox(),
+            presence: default_presence(),
+            ping: default_ping(),
+            attempts: default_attempts(),
+            difficulty: 0,
+            depth: default_depth(),
+            sample: default_sample(),
+            capacity: default_capacity(),
+            connections: default_connections(),
+            pinned: 0,
+            fanout: default_fanout(),
+            message_size: default_message_size(),
+            relay: false,
+            compression: None,
+            coalesce: None,
+            flush: default_flush(),
+            retry: default_retry(),
+            database: None,
+            records: None,
+            outbox: None,
+            outbox_size: default_outbox_size(),
+            values: default_values(),
+            values_size: default_values_size(),
+            discovery: None,
+            status: None,
+            status_address: default_status_address(),
+            bootstrap: Vec::new(),
+            listen: Vec::new(),
+            transport: Transport::Tcp,
+            selection: Selection::Closest,
         }
     }
 
@@ -163,28 +780,519 @@ impl Config {
     /// Should the config already be available as a toml formatted
     /// string it can be parsed directly. In the future this should be
     /// made format independant by removing the hard coded dependancy
-    /// on serde / toml.
+    /// on serde / toml. The config gets validated, a config that
+    /// parses but can't be used fails as well.
+    ///
+    /// ```toml
+    /// [network]
+    /// signaling = "example.com"
+    /// port = 4242
+    ///
+    /// [routing]
+    /// bucket = 20
+    /// replication = 3
+    ///
+    /// [cache]
+    /// size = 1000
+    ///
+    /// [connections]
+    /// limit = 32
+    /// ```
     pub fn from_string(content: String) -> Result<Self, Error> {
         let config: Result<LoadConfig, toml::de::Error> = toml::from_str(&content);
+        let c = match config {
+            Ok(c) => c,
+            Err(e) => {
+                trace::error!("System config is not valid: {}", e);
+                return Err(Error::Config(format!("unable to parse toml: {}", e)));
+            }
+        };
+        let n = c.network;
+        let transport = match n.transport.as_deref() {
+            None | Some("tcp") => Transport::Tcp,
+            Some("udp") => Transport::Udp,
+            Some("websocket") => Transport::WebSocket,
+            Some(other) => {
+                return Err(Error::Config(format!(
+                    "unknown transport '{}', expected 'tcp', 'udp' or 'websocket'",
+                    other
+                )))
+            }
+        };
+        let selection = match c.routing.selection.as_deref() {
+            None | Some("closest") => Selection::Closest,
+            Some("closest-reachable") => Selection::ClosestReachable,
+            Some("lowest-latency") => Selection::LowestLatency,
+            Some(other) => {
+                return Err(Error::Config(format!(
+                    "unknown selection '{}', expected 'closest', 'closest-reachable' or \
+                     'lowest-latency'",
+                    other
+                )))
+            }
+        };
+        let mut bootstrap = Vec::new();
+        for peer in n.bootstrap {
+            check_port("bootstrap port", peer.port)?;
+            let link = Link::new(peer.ip, peer.port);
+            bootstrap.push(Node::new(peer.address, Some(link)));
+        }
+        let mut listen = Vec::new();
+        for link in n.listen {
+            check_port("listen port", link.port)?;
+            listen.push(Link::new(link.ip, link.port));
+        }
+        let d = Self::default();
+        let config = Self {
+            bucket: c.routing.bucket.or(n.bucket).unwrap_or(d.bucket),
+            replication: c
+                .routing
+                .replication
+                .or(n.replication)
+                .unwrap_or(d.replication),
+            signaling: n.signaling.unwrap_or(d.signaling),
+            port: n.port.unwrap_or(d.port),
+            cache: c.cache.size.or(n.cache).unwrap_or(d.cache),
+            ttl: c.cache.ttl.or(n.ttl).unwrap_or(d.ttl),
+            encryption: c
+                .security
+                .encryption
+                .or(n.encryption)
+                .unwrap_or(d.encryption),
+            signatures: c
+                .security
+                .signatures
+                .or(n.signatures)
+                .unwrap_or(d.signatures),
+            timeout: c.connections.timeout.or(n.timeout).unwrap_or(d.timeout),
+            connect_timeout: c
+                .connections
+                .connect_timeout
+                .or(n.connect_timeout)
+                .unwrap_or(d.connect_timeout),
+            read_timeout: c
+                .connections
+                .read_timeout
+                .or(n.read_timeout)
+                .unwrap_or(d.read_timeout),
+            write_timeout: c
+                .connections
+                .write_timeout
+                .or(n.write_timeout)
+                .unwrap_or(d.write_timeout),
+            refresh: c.routing.refresh.or(n.refresh).unwrap_or(d.refresh),
+            republish: c.topics.republish.or(n.republish).unwrap_or(d.republish),
+            transfer: c.topics.transfer.or(n.transfer).unwrap_or(d.transfer),
+            replicas: c.topics.replicas.or(n.replicas).unwrap_or(d.replicas),
+            expiry: c.topics.expiry.or(n.expiry),
+            branching: c.topics.branching.or(n.branching).unwrap_or(d.branching),
+            inbox: c.topics.inbox.unwrap_or(d.inbox),
+            presence: c.topics.presence.or(n.presence).unwrap_or(d.presence),
+            ping: c.routing.ping.or(n.ping).unwrap_or(d.ping),
+            attempts: c.routing.attempts.or(n.attempts).unwrap_or(d.attempts),
+            difficulty: c
+                .routing
+                .difficulty
+                .or(n.difficulty)
+                .unwrap_or(d.difficulty),
+            depth: c.routing.depth.unwrap_or(d.depth),
+            sample: c.routing.sample.unwrap_or(d.sample),
+            capacity: n.capacity.unwrap_or(d.capacity),
+            connections: c
+                .connections
+                .limit
+                .or(n.connections)
+                .unwrap_or(d.connections),
+            pinned: c.connections.pinned.or(n.pinned).unwrap_or(d.pinned),
+            fanout: c.connections.fanout.or(n.fanout).unwrap_or(d.fanout),
+            message_size: c
+                .connections
+                .message_size
+                .or(n.message_size)
+                .unwrap_or(d.message_size),
+            relay: n.relay.unwrap_or(d.relay),
+            compression: c.connections.compression.or(n.compression),
+            coalesce: c.connections.coalesce.or(n.coalesce),
+            flush: c.connections.flush.or(n.flush).unwrap_or(d.flush),
+            retry: c.connections.retry.unwrap_or(d.retry),
+            database: c.storage.database.or(n.database),
+            records: c.storage.records.or(n.records),
+            outbox: c.outbox.messages,
+            outbox_size: c.outbox.size.unwrap_or(d.outbox_size),
+            values: c.values.entries.unwrap_or(d.values),
+            values_size: c.values.size.unwrap_or(d.values_size),
+            discovery: c.discovery.port.or(n.discovery),
+            status: c.status.port,
+            status_address: c.status.address.unwrap_or(d.status_address),
+            bootstrap,
+            listen,
+            transport,
+            selection,
+        };
+        config.validate()?;
+        trace::info!("Successfully loaded system config from file!");
+        Ok(config)
+    }
+
+    /// The timeouts of connecting, blocking reads and writes, as they
+    /// get passed to the Transport.
+    pub fn timeouts(&self) -> Timeouts {
+        Timeouts {
+            connect: Duration::from_secs(self.connect_timeout),
+            read: Duration::from_secs(self.read_timeout),
+            write: Duration::from_secs(self.write_timeout),
+        }
+    }
+
+    /// Checks that the node can run with the config. Each of the
+    /// errors names the setting and the allowed values:
+    ///
+    /// - Ports are between 1 and 65535, the status address is an IP
+    ///   address.
+    ///
+    /// - Sizes, limits and intervals are not zero.
+    ///
+    /// - The replication doesn't exceed the bucket size, since lookups
+    ///   never return more nodes than that.
+    ///
+    /// - No more connections are pinned than can be open.
+    ///
+    /// - The difficulty doesn't exceed the 256 bits of the Address
+    ///   hash, the depth those of the distance.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.signaling.is_empty() {
+            return Err(Error::Config(String::from(
+                "signaling server must not be empty",
+            )));
+        }
+        check_port("port", self.port)?;
+        if let Some(port) = self.discovery {
+            check_port("discovery port", port)?;
+        }
+        if let Some(port) = self.status {
+            check_port("status port", port)?;
+        }
+        if self.status_address.parse::<IpAddr>().is_err() {
+            return Err(Error::Config(format!(
+                "status address ({}) must be an IP address",
+                self.status_address
+            )));
+        }
+        let sizes = [
+            ("bucket", self.bucket),
+            ("replication", self.replication),
+            ("cache", self.cache),
+            ("attempts", self.attempts),
+            ("depth", self.depth),
+            ("sample", self.sample),
+            ("channel capacity", self.capacity),
+            ("connections", self.connections),
+            ("fanout", self.fanout),
+            ("message_size", self.message_size),
+            ("replicas", self.replicas),
+            ("branching", self.branching),
+            ("outbox size", self.outbox_size),
+            ("values", self.values),
+            ("values size", self.values_size),
+        ];
+        for (name, value) in sizes.iter() {
+            if *value == 0 {
+                return Err(Error::Config(format!("{} must not be zero", name)));
+            }
+        }
+        let intervals = [
+            ("ttl", self.ttl),
+            ("timeout", self.timeout),
+            ("connect_timeout", self.connect_timeout),
+            ("read_timeout", self.read_timeout),
+            ("write_timeout", self.write_timeout),
+            ("refresh", self.refresh),
+            ("republish", self.republish),
+            ("transfer", self.transfer),
+            ("presence", self.presence),
+            ("ping", self.ping),
+        ];
+        for (name, value) in intervals.iter() {
+            if *value == 0 {
+                return Err(Error::Config(format!(
+                    "{} must be at least one second",
+                    name
+                )));
+            }
+        }
+        if self.outbox == Some(0) {
+            return Err(Error::Config(String::from("outbox must not be zero")));
+        }
+        if self.coalesce == Some(0) {
+            return Err(Error::Config(String::from("coalesce must not be zero")));
+        }
+        if self.expiry == Some(0) {
+            return Err(Error::Config(String::from(
+                "expiry must be at least one second",
+            )));
+        }
+        if self.replication > self.bucket {
+            return Err(Error::Config(format!(
+                "replication ({}) must not exceed the bucket size ({})",
+                self.replication, self.bucket
+            )));
+        }
+        if self.replicas > self.bucket {
+            return Err(Error::Config(format!(
+                "replicas ({}) must not exceed the bucket size ({})",
+                self.replicas, self.bucket
+            )));
+        }
+        if self.pinned > self.connections {
+            return Err(Error::Config(format!(
+                "pinned ({}) must not exceed connections ({})",
+                self.pinned, self.connections
+            )));
+        }
+        if self.difficulty > 256 {
+            return Err(Error::Config(format!(
+                "difficulty ({}) must not exceed 256 bits",
+                self.difficulty
+            )));
+        }
+        if self.depth > router::DEPTH {
+            return Err(Error::Config(format!(
+                "depth ({}) must not exceed 256 bits",
+                self.depth
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Config {
+    /// A node using the signaling server on port 4242 of the local
+    /// machine with buckets of 20 nodes, a replication of 3 and 1000
+    /// cached Transactions. All other settings are the same as in
+    /// Config::new.
+    fn default() -> Self {
+        Self::new(20, 3, 1000, String::from("127.0.0.1"), 4242)
+    }
+}
+
+impl ConfigDelta {
+    /// Collects all settings that differ between the two Configs and
+    /// can be changed at runtime.
+    pub fn between(old: &Config, new: &Config) -> Self {
+        Self {
+            bucket: changed(old.bucket, new.bucket),
+            replication: changed(old.replication, new.replication),
+            cache: changed(old.cache, new.cache),
+            ttl: changed(old.ttl, new.ttl),
+            timeout: changed(old.timeout, new.timeout),
+            presence: changed(old.presence, new.presence),
+            connections: changed(old.connections, new.connections),
+            pinned: changed(old.pinned, new.pinned),
+            message_size: changed(old.message_size, new.message_size),
+        }
+    }
+
+    /// Checks if nothing would be changed.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Fails for values no node could run with.
+    pub fn validate(&self) -> Result<(), Error> {
+        let zero = [
+            ("bucket", self.bucket),
+            ("replication", self.replication),
+            ("cache", self.cache),
+            ("connections", self.connections),
+            ("message_size", self.message_size),
+        ];
+        for (name, value) in zero.iter() {
+            if *value == Some(0) {
+                return Err(Error::Config(format!("{} must not be zero", name)));
+            }
+        }
+        if self.timeout == Some(0) || self.presence == Some(0) {
+            return Err(Error::Config(String::from("intervals must not be zero")));
+        }
+        Ok(())
+    }
+}
+
+impl Watcher {
+    /// Reads the config file, it fails if the file isn't a valid
+    /// Config.
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let modified = fs::metadata(path)?.modified().ok();
+        let config = Config::from_file(path)?;
+        Ok(Self {
+            path: path.to_string(),
+            modified,
+            config,
+        })
+    }
+
+    /// Returns the changes should the file have been modified since
+    /// it was last read. Invalid versions get skipped, the next valid
+    /// one is compared to the last valid one.
+    pub fn poll(&mut self) -> Option<ConfigDelta> {
+        let modified = fs::metadata(&self.path).ok()?.modified().ok();
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        let config = Config::from_file(&self.path).and_then(|config| {
+            let delta = ConfigDelta::between(&self.config, &config);
+            delta.validate().map(|_| (config, delta))
+        });
         match config {
-            Ok(c) => {
-                log::info!("Successfully loaded system config from file!");
-                return Ok(Self {
-                    bucket: c.network.bucket,
-                    replication: c.network.replication,
-                    signaling: c.network.signaling,
-                    port: c.network.port,
-                    cache: c.network.cache,
-                });
+            Ok((config, delta)) => {
+                self.config = config;
+                Some(delta).filter(|delta| !delta.is_empty())
             }
             Err(e) => {
-                log::error!("System config is not valid: {}", e);
-                return Err(Error::Config(String::from("unable to parse toml")));
+                trace::warn!("ignoring invalid config file: {}", e);
+                None
             }
         }
     }
 }
 
+/// Fails unless the port is between 1 and 65535.
+fn check_port(name: &str, port: usize) -> Result<(), Error> {
+    if port == 0 || port > 65535 {
+        return Err(Error::Config(format!(
+            "{} ({}) must be between 1 and 65535",
+            name, port
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the new value if it differs from the old one.
+fn changed<T: PartialEq>(old: T, new: T) -> Option<T> {
+    if old != new {
+        Some(new)
+    } else {
+        None
+    }
+}
+
+/// Default idle timeout of connections in seconds.
+fn default_timeout() -> u64 {
+    60
+}
+
+/// Default timeout of connecting, blocking reads and writes in
+/// seconds.
+fn default_io_timeout() -> u64 {
+    5
+}
+
+/// Default TTL of the Transaction cache in seconds.
+fn default_ttl() -> u64 {
+    300
+}
+
+/// Default refresh and republish interval in seconds.
+fn default_interval() -> u64 {
+    3600
+}
+
+/// Default Record transfer interval in seconds.
+fn default_transfer() -> u64 {
+    60
+}
+
+/// Default number of nodes hosting each Record.
+fn default_replicas() -> usize {
+    3
+}
+
+/// Default number of subscribers Records send messages to directly.
+fn default_branching() -> usize {
+    32
+}
+
+/// Default presence announcement interval in seconds.
+fn default_presence() -> u64 {
+    30
+}
+
+/// Default liveness check interval in seconds.
+fn default_ping() -> u64 {
+    300
+}
+
+/// Default number of failed attempts before a node is evicted.
+fn default_attempts() -> usize {
+    router::ATTEMPTS
+}
+
+/// Default maximum depth of the routing tree.
+fn default_depth() -> usize {
+    router::DEPTH
+}
+
+/// Default maximum number of nodes in bootstrap answers.
+fn default_sample() -> usize {
+    32
+}
+
+/// Default maximum number of open connections.
+fn default_connections() -> usize {
+    10
+}
+
+/// Default number of threads dialing peers.
+fn default_fanout() -> usize {
+    4
+}
+
+/// Default flush delay of coalesced Wires in milliseconds.
+fn default_flush() -> u64 {
+    1
+}
+
+/// Default number of seconds undeliverable Transactions are retried.
+fn default_retry() -> u64 {
+    30
+}
+
+/// Default largest Wire accepted from peers.
+fn default_message_size() -> usize {
+    transaction::MESSAGE_SIZE
+}
+
+/// Default total size of the messages kept for offline subscribers.
+fn default_inbox() -> usize {
+    16 * 1024 * 1024
+}
+
+/// Default total size of the messages queued while offline.
+fn default_outbox_size() -> usize {
+    16 * 1024 * 1024
+}
+
+/// Default maximum number of stored values.
+fn default_values() -> usize {
+    10_000
+}
+
+/// Default total size of the stored values.
+fn default_values_size() -> usize {
+    16 * 1024 * 1024
+}
+
+/// Default address of the status endpoint, only reachable locally.
+fn default_status_address() -> String {
+    String::from("127.0.0.1")
+}
+
+/// Default capacity of the internal Channels.
+fn default_capacity() -> usize {
+    1024
+}
+
 impl CenterConfig {
     /// Should the config be optained through a custom method or all
     /// be hard hard coded (?) a new config can be created directly.
@@ -199,6 +1307,8 @@ impl CenterConfig {
             port,
             secret: Some(secret),
             hostname,
+            key: None,
+            passphrase: None,
         }
     }
 
@@ -217,25 +1327,49 @@ impl CenterConfig {
         let config: Result<LoadCenter, toml::de::Error> = toml::from_str(&config);
         match config {
             Ok(c) => {
-                log::info!("Successfully loaded center config from file!");
-                return Ok(Self {
+                let config = Self {
                     ip: c.ip,
                     port: c.port,
                     secret: None,
                     hostname: c.hostname,
-                });
+                    key: c.key,
+                    passphrase: None,
+                };
+                config.validate()?;
+                trace::info!("Successfully loaded center config from file!");
+                Ok(config)
             }
             Err(e) => {
-                log::error!("Config is not valid: {}", e);
-                return Err(Error::Config(String::from(
-                    "unable to parse config from toml",
-                )));
+                trace::error!("Config is not valid: {}", e);
+                Err(Error::Config(format!(
+                    "unable to parse config from toml: {}",
+                    e
+                )))
             }
         }
     }
 
+    /// Checks that the IP isn't empty and the port is between 1 and
+    /// 65535.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.ip.is_empty() {
+            return Err(Error::Config(String::from("ip must not be empty")));
+        }
+        check_port("port", self.port)
+    }
+
+    /// Uses the key file at the path for the identity of the node,
+    /// it gets encrypted if a passphrase is given. An explicitly set
+    /// secret key takes precedence.
+    pub fn with_key(mut self, path: String, passphrase: Option<String>) -> Self {
+        self.key = Some(path);
+        self.passphrase = passphrase;
+        self
+    }
+
     /// The secret key can't be formatted as UTF-8 and if stored as a
     /// file it needs to be encoded / decoded using special methods.
+    /// The key files of the keys module are preferred.
     pub fn load_key(path: &str) -> Result<[u8; 32], Error> {
         let file = File::open(path)?;
         let reader = std::io::BufReader::new(file);
@@ -251,11 +1385,9 @@ impl CenterConfig {
                 for (i, j) in key.iter().enumerate() {
                     bytes[i] = *j;
                 }
-                return Ok(bytes);
-            }
-            None => {
-                return Err(Error::Config(String::from("key file is empty")));
+                Ok(bytes)
             }
+            None => Err(Error::Config(String::from("key file is empty"))),
         }
     }
 }
@@ -263,6 +1395,7 @@ impl CenterConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::node::Center;
 
     #[test]
     fn test_system_parse() {
@@ -280,6 +1413,360 @@ mod tests {
         assert_eq!(config, created);
     }
 
+    #[test]
+    fn test_system_parse_encryption() {
+        let c = "# Example Actaeon config.
+[network]
+        bucket = 32
+        signaling = '127.0.0.1'
+        replication = 3
+        port = 4242
+        cache = 32
+        encryption = false
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert!(!config.encryption);
+        assert!(config.signatures);
+    }
+
+    #[test]
+    fn test_system_parse_timeout() {
+        let c = "# Example Actaeon config.
+[network]
+        bucket = 32
+        signaling = '127.0.0.1'
+        replication = 3
+        port = 4242
+        cache = 32
+        timeout = 10
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(config.timeout, 10);
+        assert_eq!(config.database, None);
+    }
+
+    #[test]
+    fn test_system_parse_intervals() {
+        let c = "# Example Actaeon config.
+[network]
+        bucket = 32
+        signaling = '127.0.0.1'
+        replication = 3
+        port = 4242
+        cache = 32
+        refresh = 600
+        ttl = 30
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(config.refresh, 600);
+        assert_eq!(config.ttl, 30);
+        assert_eq!(config.republish, 3600);
+        assert_eq!((config.transfer, config.replicas), (60, 3));
+        assert_eq!(config.expiry, None);
+        assert_eq!(config.branching, 32);
+        assert_eq!(config.presence, 30);
+        assert_eq!(config.ping, 300);
+        assert_eq!(config.attempts, 3);
+        assert_eq!(config.difficulty, 0);
+        assert_eq!((config.depth, config.sample), (256, 32));
+        assert_eq!(config.capacity, 1024);
+        assert_eq!((config.connections, config.pinned), (10, 0));
+        assert_eq!(config.fanout, 4);
+        assert_eq!(config.message_size, 4 * 1024 * 1024);
+        assert!(!config.relay);
+        assert_eq!(config.compression, None);
+        assert_eq!((config.coalesce, config.flush), (None, 1));
+        assert_eq!(config.retry, 30);
+        assert_eq!(config.inbox, 16 * 1024 * 1024);
+        assert_eq!(
+            (config.outbox, config.outbox_size),
+            (None, 16 * 1024 * 1024)
+        );
+        assert_eq!(
+            (config.values, config.values_size),
+            (10_000, 16 * 1024 * 1024)
+        );
+        assert_eq!((config.discovery, config.status), (None, None));
+        assert_eq!(config.status_address, "127.0.0.1");
+        assert!(config.bootstrap.is_empty());
+    }
+
+    #[test]
+    fn test_system_parse_bootstrap() {
+        let address = Address::random();
+        let c = format!(
+            "# Example Actaeon config.
+[network]
+        bucket = 32
+        signaling = '127.0.0.1'
+        replication = 3
+        port = 4242
+        cache = 32
+
+[[network.bootstrap]]
+        address = '{}'
+        ip = '10.0.0.2'
+        port = 4243
+",
+            address.to_hex()
+        );
+        let config = Config::from_string(c.clone()).unwrap();
+        assert_eq!(config.bootstrap, vec![Node::new(address.clone(), None)]);
+        assert_eq!(
+            config.bootstrap[0].link,
+            Some(Link::new(String::from("10.0.0.2"), 4243))
+        );
+        let c = c.replace(&address.to_hex(), "actaeon");
+        assert!(Config::from_string(c).is_err());
+    }
+
+    #[test]
+    fn test_system_parse_listen() {
+        let c = "[network]
+        signaling = '127.0.0.1'
+
+[[network.listen]]
+        ip = '10.0.0.2'
+        port = 4243
+
+[[network.listen]]
+        ip = '::1'
+        port = 4244
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(
+            config.listen,
+            vec![
+                Link::new(String::from("10.0.0.2"), 4243),
+                Link::new(String::from("::1"), 4244),
+            ]
+        );
+        assert!(Config::default().listen.is_empty());
+        let c = c.replace("4244", "0");
+        assert!(Config::from_string(c).is_err());
+    }
+
+    #[test]
+    fn test_system_parse_transport() {
+        let c = "# Example Actaeon config.
+[network]
+        bucket = 32
+        signaling = '127.0.0.1'
+        replication = 3
+        port = 4242
+        cache = 32
+        transport = 'udp'
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(config.transport, Transport::Udp);
+        let config = Config::from_string(c.replace("udp", "websocket")).unwrap();
+        assert_eq!(config.transport, Transport::WebSocket);
+        let config =
+            Config::from_string(c.replace("transport = 'udp'", "compression = 512")).unwrap();
+        assert_eq!(config.transport, Transport::Tcp);
+        assert_eq!(config.compression, Some(512));
+        let c = c.replace("udp", "quic");
+        assert!(Config::from_string(c).is_err());
+    }
+
+    #[test]
+    fn test_system_parse_selection() {
+        let c = "[routing]
+        selection = 'lowest-latency'
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(config.selection, Selection::LowestLatency);
+        let config = Config::from_string(c.replace("lowest-latency", "closest-reachable")).unwrap();
+        assert_eq!(config.selection, Selection::ClosestReachable);
+        let config = Config::from_string(String::new()).unwrap();
+        assert_eq!(config.selection, Selection::Closest);
+        let c = c.replace("lowest-latency", "fastest");
+        assert!(Config::from_string(c).is_err());
+    }
+
+    #[test]
+    fn test_system_parse_sections() {
+        assert_eq!(
+            Config::from_string(String::new()).unwrap(),
+            Config::default()
+        );
+        let c = "# Example Actaeon config.
+[network]
+        signaling = 'example.com'
+        port = 4242
+        cache = 64
+
+[routing]
+        bucket = 32
+        replication = 4
+        difficulty = 8
+        depth = 8
+        sample = 16
+
+[cache]
+        size = 128
+        ttl = 60
+
+[connections]
+        limit = 32
+        pinned = 4
+        compression = 1024
+        coalesce = 1400
+        flush = 5
+        retry = 10
+        connect_timeout = 2
+
+[security]
+        signatures = false
+
+[topics]
+        presence = 10
+        transfer = 120
+        replicas = 5
+        expiry = 86400
+        branching = 8
+        inbox = 4096
+
+[storage]
+        database = 'table.db'
+
+[outbox]
+        messages = 100
+        size = 65536
+
+[values]
+        entries = 500
+        size = 32768
+
+[discovery]
+        port = 42430
+
+[status]
+        port = 42431
+        address = '0.0.0.0'
+";
+        let config = Config::from_string(c.to_string()).unwrap();
+        assert_eq!(config.signaling, "example.com");
+        assert_eq!((config.bucket, config.replication), (32, 4));
+        assert_eq!((config.difficulty, config.depth), (8, 8));
+        assert_eq!(config.sample, 16);
+        assert_eq!((config.cache, config.ttl), (128, 60));
+        assert_eq!((config.connections, config.pinned), (32, 4));
+        assert_eq!(config.compression, Some(1024));
+        assert_eq!((config.coalesce, config.flush), (Some(1400), 5));
+        assert_eq!(config.retry, 10);
+        assert_eq!(config.timeouts().connect, Duration::from_secs(2));
+        assert_eq!(config.timeouts().read, Duration::from_secs(5));
+        assert!(config.encryption && !config.signatures);
+        assert_eq!((config.presence, config.transfer), (10, 120));
+        assert_eq!(config.replicas, 5);
+        assert_eq!(config.expiry, Some(86400));
+        assert_eq!((config.branching, config.inbox), (8, 4096));
+        assert_eq!(config.database, Some(String::from("table.db")));
+        assert_eq!((config.outbox, config.outbox_size), (Some(100), 65536));
+        assert_eq!((config.values, config.values_size), (500, 32768));
+        assert_eq!(config.discovery, Some(42430));
+        assert_eq!(config.status, Some(42431));
+        assert_eq!(config.status_address, "0.0.0.0");
+        assert_eq!(config.timeout, Config::default().timeout);
+
+        let c = c.replace("[topics]", "[topic]");
+        assert!(Config::from_string(c).is_err());
+    }
+
+    #[test]
+    fn test_system_validate() {
+        assert!(Config::default().validate().is_ok());
+        let message = |config: Config| match config.validate() {
+            Err(Error::Config(message)) => message,
+            _ => panic!("expected an invalid config"),
+        };
+        let config = Config {
+            replication: 21,
+            ..Default::default()
+        };
+        assert_eq!(
+            message(config),
+            "replication (21) must not exceed the bucket size (20)"
+        );
+        let config = Config {
+            port: 70000,
+            ..Default::default()
+        };
+        assert_eq!(message(config), "port (70000) must be between 1 and 65535");
+        let config = Config {
+            status: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            message(config),
+            "status port (0) must be between 1 and 65535"
+        );
+        let config = Config {
+            status_address: String::from("localhost"),
+            ..Default::default()
+        };
+        assert_eq!(
+            message(config),
+            "status address (localhost) must be an IP address"
+        );
+        let config = Config {
+            pinned: 11,
+            ..Default::default()
+        };
+        assert_eq!(
+            message(config),
+            "pinned (11) must not exceed connections (10)"
+        );
+        let config = Config {
+            cache: 0,
+            ..Default::default()
+        };
+        assert_eq!(message(config), "cache must not be zero");
+        let config = Config {
+            ping: 0,
+            ..Default::default()
+        };
+        assert_eq!(message(config), "ping must be at least one second");
+        let config = Config {
+            expiry: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(message(config), "expiry must be at least one second");
+        let config = Config {
+            coalesce: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(message(config), "coalesce must not be zero");
+        let config = Config {
+            outbox: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(message(config), "outbox must not be zero");
+        let config = Config {
+            depth: 257,
+            ..Default::default()
+        };
+        assert_eq!(message(config), "depth (257) must not exceed 256 bits");
+
+        let c = "[routing]
+        bucket = 2
+        replication = 3
+";
+        assert!(Config::from_string(c.to_string()).is_err());
+        let c = "[routing]
+        bucket = 2
+        replication = 2
+[topics]
+        replicas = 3
+";
+        assert!(Config::from_string(c.to_string()).is_err());
+        let c = "[network]
+        port = 0
+";
+        assert!(Config::from_string(c.to_string()).is_err());
+    }
+
     #[test]
     fn test_center_parse() {
         let c = "# Example Actaeon config.
@@ -291,5 +1778,96 @@ mod tests {
         let config = CenterConfig::from_string(c.to_string()).unwrap();
         let created = CenterConfig::new("127.0.0.1".to_owned(), 42, [0; 32], "actaeon".to_owned());
         assert_eq!(config.ip, created.ip);
+        assert!(CenterConfig::from_string(c.replace("42", "0")).is_err());
+        assert!(CenterConfig::from_string(c.replace("port", "prot")).is_err());
+    }
+
+    #[test]
+    fn test_center_key() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.key", rand::random::<u64>()));
+        let path = path.to_string_lossy().to_string();
+        let c = format!(
+            "ip = '127.0.0.1'
+        port = 42
+        hostname = 'actaeon'
+        key = '{}'
+",
+            path
+        );
+        let config = CenterConfig::from_string(c.clone()).unwrap();
+        assert_eq!(config.key, Some(path.clone()));
+        let first = Center::from_config(config).unwrap();
+        let second = Center::from_config(CenterConfig::from_string(c).unwrap()).unwrap();
+        assert_eq!(first.public, second.public);
+        std::fs::remove_file(&path).unwrap();
+
+        let config = CenterConfig::from_string(String::from(
+            "ip = '127.0.0.1'
+        port = 42
+        hostname = 'actaeon'",
+        ))
+        .unwrap();
+        assert!(Center::from_config(config).is_err());
+    }
+
+    #[test]
+    fn test_config_delta() {
+        let old = Config::new(32, 3, 32, "127.0.0.1".to_owned(), 4242);
+        let mut new = Config::new(32, 3, 32, "127.0.0.1".to_owned(), 4242);
+        assert!(ConfigDelta::between(&old, &new).is_empty());
+        new.cache = 64;
+        new.port = 4343;
+        let delta = ConfigDelta::between(&old, &new);
+        assert_eq!(delta.cache, Some(64));
+        assert_eq!(
+            delta,
+            ConfigDelta {
+                cache: Some(64),
+                ..Default::default()
+            }
+        );
+        assert!(delta.validate().is_ok());
+        new.connections = 0;
+        assert!(ConfigDelta::between(&old, &new).validate().is_err());
+    }
+
+    #[test]
+    fn test_config_watcher() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.toml", rand::random::<u64>()));
+        let path = path.to_string_lossy().to_string();
+        let content = |cache: usize| {
+            format!(
+                "[network]\nbucket = 32\nsignaling = '127.0.0.1'\nreplication = 3\nport = 4242\ncache = {}\n",
+                cache
+            )
+        };
+        let touch = |content: String, secs: u64| {
+            fs::write(&path, content).unwrap();
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        touch(content(32), 1);
+        let mut watcher = Watcher::new(&path).unwrap();
+        assert_eq!(watcher.poll(), None);
+
+        touch(content(64), 2);
+        let delta = watcher.poll().unwrap();
+        assert_eq!(delta.cache, Some(64));
+        assert_eq!(watcher.poll(), None);
+
+        touch(String::from("invalid"), 3);
+        assert_eq!(watcher.poll(), None);
+        touch(content(0), 4);
+        assert_eq!(watcher.poll(), None);
+        touch(content(16), 5);
+        assert_eq!(watcher.poll().unwrap().cache, Some(16));
+        fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,259 @@
+//! # Connection
+//!
+//! Direct and persistent sessions with a single node. Unlike normal
+//! messages, which get routed through the nodes closest to the
+//! target, everything sent through a Connection goes straight to the
+//! peer over a dedicated connection. The Listener keeps that
+//! connection open and dials it again with an exponential backoff
+//! should it get lost.
+
+use crate::error::Error;
+use crate::handshake::Features;
+use crate::node::Address;
+use crate::trace;
+use crate::transaction::Transaction;
+use crate::util::Channel;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Waker};
+use std::time::{Duration, Instant};
+
+/// Delay before the first attempt to dial the peer again.
+const RETRY_MIN: Duration = Duration::from_millis(500);
+
+/// Upper limit of the delay between two attempts.
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// User side of a direct session with a single node, created through
+/// the Interface. Dropping it ends the session, but the underlying
+/// connection might still be used by the rest of the system.
+pub struct Connection {
+    /// Address of the peer, with signatures enabled the remote node
+    /// has to prove it owns the Address during the handshake.
+    address: Address,
+    /// Connection to the Session on the Listener.
+    channel: Channel<Command>,
+    /// Shared with the Session, set while the peer is connected.
+    alive: Arc<AtomicBool>,
+    /// Shared with the Session, negotiated during the handshake.
+    features: Arc<AtomicU8>,
+}
+
+/// Listener side of a Connection. It tracks the state of the peer
+/// and when it should be dialed next.
+pub struct Session {
+    /// Matches the Address of the user Connection.
+    pub address: Address,
+    /// Connection to the user Connection.
+    pub channel: Channel<Command>,
+    /// Shared with the Connection, set while the peer is connected.
+    alive: Arc<AtomicBool>,
+    /// Shared with the Connection, negotiated during the handshake.
+    features: Arc<AtomicU8>,
+    /// Current delay between two attempts to dial the peer.
+    retry: Duration,
+    /// Earliest time for the next attempt.
+    next: Instant,
+}
+
+/// Everything that gets passed between a Connection and its Session.
+/// Like the Topic Commands they are never seen by the user.
+#[derive(Debug)]
+pub enum Command {
+    /// Body of an Action the user wants to send to the peer.
+    Send(Vec<u8>),
+    /// Passed on to the user.
+    Notify(Notification),
+}
+
+/// Everything the user can receive through a Connection.
+#[derive(Debug)]
+pub enum Notification {
+    /// A message from the peer.
+    Message(Box<Transaction>),
+    /// The connection to the peer has been established.
+    Connected,
+    /// The connection to the peer was lost, it gets dialed again.
+    Disconnected,
+    /// A body the user sent could not be delivered to the peer.
+    Failed(Vec<u8>, Error),
+}
+
+impl Connection {
+    /// Creates a linked Connection and Session, the Session has to be
+    /// passed to the Listener.
+    pub fn new(
+        address: Address,
+        channel: Channel<Command>,
+        remote: Channel<Command>,
+    ) -> (Self, Session) {
+        let alive = Arc::new(AtomicBool::new(false));
+        let features = Arc::new(AtomicU8::new(0));
+        let connection = Self {
+            address: address.clone(),
+            channel,
+            alive: alive.clone(),
+            features: features.clone(),
+        };
+        let session = Session {
+            address,
+            channel: remote,
+            alive,
+            features,
+            retry: RETRY_MIN,
+            next: Instant::now(),
+        };
+        (connection, session)
+    }
+
+    /// Address of the peer.
+    pub fn address(&self) -> Address {
+        self.address.clone()
+    }
+
+    /// True while there is an open connection to the peer.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    /// Features negotiated with the peer during the last handshake.
+    /// They are empty until the peer has been connected once.
+    pub fn features(&self) -> Features {
+        Features::from_bits(self.features.load(Ordering::Relaxed))
+    }
+
+    /// Sends the body as an Action directly to the peer. Fails if the
+    /// peer is currently not connected or with Busy should the
+    /// Listener not keep up.
+    pub fn send(&self, body: Vec<u8>) -> Result<(), Error> {
+        if !self.is_alive() {
+            return Err(Error::Connection(String::from("peer is not connected")));
+        }
+        self.channel.try_send(Command::Send(body))
+    }
+
+    /// Waits for the next message or change of the connection state.
+    /// None means the system has been shut down.
+    pub async fn recv(&self) -> Option<Notification> {
+        loop {
+            if let Command::Notify(notification) = self.channel.recv_async().await? {
+                return Some(notification);
+            }
+        }
+    }
+
+    /// Non-blocking version of recv.
+    pub fn try_recv(&self) -> Option<Notification> {
+        loop {
+            if let Command::Notify(notification) = self.channel.try_recv()? {
+                return Some(notification);
+            }
+        }
+    }
+}
+
+impl Session {
+    /// True once the user dropped the Connection.
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_closed()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::Relaxed)
+    }
+
+    /// Checks if the peer should be dialed again.
+    pub fn is_due(&self) -> bool {
+        !self.is_alive() && Instant::now() >= self.next
+    }
+
+    /// Updates the state of the peer, the user only gets notified
+    /// about actual changes. A new connection resets the backoff.
+    pub fn update(&mut self, alive: bool) {
+        if self.alive.swap(alive, Ordering::Relaxed) == alive {
+            return;
+        }
+        let notification = if alive {
+            self.retry = RETRY_MIN;
+            Notification::Connected
+        } else {
+            self.next = Instant::now() + self.retry;
+            Notification::Disconnected
+        };
+        self.notify(notification);
+    }
+
+    /// Stores the Features negotiated with the peer.
+    pub fn negotiated(&self, features: Features) {
+        self.features.store(features.bits(), Ordering::Relaxed);
+    }
+
+    /// Dialing the peer failed, the delay until the next attempt gets
+    /// doubled.
+    pub fn failed(&mut self) {
+        self.next = Instant::now() + self.retry;
+        self.retry = (self.retry * 2).min(RETRY_MAX);
+    }
+
+    /// Passes a message or state change on to the user, it gets
+    /// dropped if the user doesn't keep up.
+    pub fn notify(&self, notification: Notification) {
+        if let Err(e) = self.channel.try_send(Command::Notify(notification)) {
+            trace::warn!("dropping connection notification: {}", e);
+        }
+    }
+
+    /// Returns the next body the user wants to send, if any.
+    pub fn try_recv(&self) -> Option<Vec<u8>> {
+        self.recv_with(&mut Context::from_waker(Waker::noop()))
+    }
+
+    /// Like try_recv, but should no body be waiting the waker of the
+    /// context gets woken once the user sends one.
+    pub fn recv_with(&self, cx: &mut Context<'_>) -> Option<Vec<u8>> {
+        loop {
+            if let Command::Send(body) = self.channel.recv_with(cx)? {
+                return Some(body);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_backoff() {
+        let (c1, c2) = Channel::new();
+        let (connection, mut session) = Connection::new(Address::random(), c1, c2);
+        assert!(session.is_due());
+        assert!(connection.send(vec![42]).is_err());
+        session.failed();
+        assert!(!session.is_due());
+        assert_eq!(session.retry, RETRY_MIN * 2);
+        for _ in 0..10 {
+            session.failed();
+        }
+        assert_eq!(session.retry, RETRY_MAX);
+        session.update(true);
+        assert_eq!(session.retry, RETRY_MIN);
+        assert!(connection.is_alive());
+        assert!(matches!(
+            connection.try_recv(),
+            Some(Notification::Connected)
+        ));
+        connection.send(vec![42]).unwrap();
+        assert_eq!(session.try_recv(), Some(vec![42]));
+        session.update(true);
+        assert!(connection.try_recv().is_none());
+        session.update(false);
+        assert!(!session.is_due());
+        assert!(matches!(
+            connection.try_recv(),
+            Some(Notification::Disconnected)
+        ));
+        drop(connection);
+        assert!(session.is_closed());
+    }
+}
@@ -0,0 +1,743 @@
+//! # Database
+//!
+//! Simple file based persistence for the state of a node. The routing
+//! Table gets stored as a snapshot, which allows a restarted node to
+//! rejoin the network through the Nodes it already knows instead of
+//! having to rely on the signaling server every time. Banned
+//! Addresses are stored next to the snapshot. Records are
+//! stored in an append-only Journal of DataTopics, so every change is
+//! written through to disk immediately. Instead of files both can
+//! also be kept in a Storage, see the backend module.
+
+use crate::backend::{Storage, TABLE};
+use crate::error::Error;
+use crate::node::{Address, Node};
+use crate::record::Policy;
+use crate::router::Safe;
+use crate::trace;
+use crate::util;
+use sodiumoxide::crypto::hash::sha256;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Identifies routing Table snapshot files.
+const MAGIC: &[u8; 4] = b"ACTT";
+
+/// Identifies files of banned Addresses.
+const BANNED_MAGIC: &[u8; 4] = b"ACTB";
+
+/// Identifies Record Journal files.
+const JOURNAL_MAGIC: &[u8; 4] = b"ACTR";
+
+/// Version of the snapshot and Journal formats, stored after the
+/// magic bytes.
+const VERSION: u8 = 1;
+
+/// Length of the checksum following each Journal entry.
+const CHECKSUM_LENGTH: usize = 8;
+
+/// Interval in which the routing Table gets stored while the system
+/// is running.
+const INTERVAL: Duration = Duration::from_secs(60);
+
+/// Binary representation of a Record as it gets stored in the
+/// Journal. Removed Records are stored as well, so they don't get
+/// restored from older entries. Structure:
+/// 1 byte: Flag (1 = exists, 0 = removed, 2 = exists with owner,
+/// 3 = exists with roles, 4 = exists with owner and roles, 5 to 8 =
+/// the same with a version),
+/// 32 bytes: Address,
+/// Only with a version:
+///   8 bytes: Version,
+/// Only with an owner:
+///   32 bytes: Owner,
+///   2 bytes: Length of the Policy,
+///   n bytes: Policy,
+/// Only with roles:
+///   2 bytes: Number of publishers,
+///   n * 32 bytes: Publishers,
+///   2 bytes: Number of read-only subscribers,
+///   n * 32 bytes: Read-only subscribers,
+/// n * 32 bytes: Subscribers
+#[derive(Debug, Clone, PartialEq)]
+pub struct DataTopic {
+    /// Address of the Record.
+    pub address: Address,
+    /// All current subscribers, empty if the Record was removed.
+    pub subscribers: Vec<Address>,
+    /// Nodes that only publish, see Record::publishers.
+    pub publishers: Vec<Address>,
+    /// Read-only subscribers, also part of the subscribers.
+    pub readers: Vec<Address>,
+    /// Marks the Record as removed.
+    pub removed: bool,
+    /// Owner of the Record, if it has one.
+    pub owner: Option<Address>,
+    /// Access control of the Record, only stored with an owner.
+    pub policy: Policy,
+    /// Number of changes of the members, Records stored before it
+    /// existed have version zero.
+    pub version: u64,
+}
+
+/// Append-only file of DataTopics. Every entry is prefixed with its
+/// length (4 bytes) and followed by a checksum (8 bytes), which
+/// allows detecting truncated or corrupted entries on load. Later
+/// entries replace earlier ones for the same Address, so the file
+/// has to be compacted from time to time.
+#[derive(Debug)]
+pub struct Journal {
+    /// Path of the Journal file.
+    path: PathBuf,
+    /// The open file, all writes get appended.
+    file: File,
+    /// Number of entries currently in the file.
+    entries: usize,
+}
+
+/// Location of a routing Table snapshot on disk. The file starts with
+/// four magic bytes and a version byte, followed by all Nodes in the
+/// same format used for bootstrap responses. Banned Addresses are
+/// stored in a second file with the extension "banned". With a
+/// Storage both are stored in its table namespace instead.
+#[derive(Debug, Clone)]
+pub struct Database {
+    target: Target,
+}
+
+/// Where a Database keeps the snapshot.
+#[derive(Clone)]
+enum Target {
+    /// Path of the snapshot file, it doesn't have to exist yet.
+    File(PathBuf),
+    Storage(Arc<dyn Storage>),
+}
+
+impl Database {
+    /// Creates a new Database around the given file path. Nothing
+    /// gets read or written until save or load are called.
+    pub fn new(path: &str) -> Self {
+        Self {
+            target: Target::File(PathBuf::from(path)),
+        }
+    }
+
+    /// Creates a Database that keeps the snapshot and the banned
+    /// Addresses in the Storage, in the same format as the files.
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Self {
+        Self {
+            target: Target::Storage(storage),
+        }
+    }
+
+    /// Replaces the stored data with the name, files get written to a
+    /// temporary file first and then moved, so a crash while saving
+    /// never leaves a partial file behind.
+    fn write(&self, name: &str, data: Vec<u8>) -> Result<(), Error> {
+        match &self.target {
+            Target::File(path) => {
+                let (path, tmp) = match name {
+                    "banned" => (
+                        path.with_extension("banned"),
+                        path.with_extension("banned.tmp"),
+                    ),
+                    _ => (path.clone(), path.with_extension("tmp")),
+                };
+                fs::write(&tmp, &data)?;
+                fs::rename(&tmp, &path)?;
+                Ok(())
+            }
+            Target::Storage(storage) => storage.put(TABLE, name.as_bytes(), data),
+        }
+    }
+
+    /// Reads the stored data with the name, None if it doesn't exist.
+    fn read(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        match &self.target {
+            Target::File(path) => {
+                let path = match name {
+                    "banned" => path.with_extension("banned"),
+                    _ => path.clone(),
+                };
+                match fs::read(path) {
+                    Ok(data) => Ok(Some(data)),
+                    Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            Target::Storage(storage) => storage.get(TABLE, name.as_bytes()),
+        }
+    }
+
+    /// Stores all Nodes of the Table including their Link data. The
+    /// snapshot is written to a temporary file first and then moved,
+    /// so a crash while saving never leaves a partial file behind.
+    pub fn save(&self, table: &Safe) -> Result<(), Error> {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        data.append(&mut table.export());
+        self.write("snapshot", data)?;
+        self.save_banned(table)
+    }
+
+    /// Stores all banned Addresses of the Table, the same way the
+    /// snapshot is written. Structure:
+    /// 4 bytes: Magic,
+    /// 1 byte: Version,
+    /// n * 32 bytes: Addresses
+    pub fn save_banned(&self, table: &Safe) -> Result<(), Error> {
+        let mut data = BANNED_MAGIC.to_vec();
+        data.push(VERSION);
+        for address in table.banned() {
+            data.extend_from_slice(&address.as_bytes());
+        }
+        self.write("banned", data)
+    }
+
+    /// Reads all banned Addresses, a missing file means none are.
+    pub fn load_banned(&self) -> Result<Vec<Address>, Error> {
+        let data = match self.read("banned")? {
+            Some(data) => data,
+            None => return Ok(Vec::new()),
+        };
+        if data.len() < 5 || &data[..4] != BANNED_MAGIC || data[4] != VERSION {
+            return Err(Error::Invalid(String::from("file is not a ban list")));
+        }
+        if !(data.len() - 5).is_multiple_of(32) {
+            return Err(Error::Invalid(String::from("invalid number of bytes")));
+        }
+        data[5..].chunks(32).map(Address::from_slice).collect()
+    }
+
+    /// Reads all Nodes from the snapshot. A missing file is not an
+    /// error, it simply doesn't contain any Nodes.
+    pub fn load(&self) -> Result<Vec<Node>, Error> {
+        let data = match self.read("snapshot")? {
+            Some(data) => data,
+            None => return Ok(Vec::new()),
+        };
+        if data.len() < 5 || &data[..4] != MAGIC {
+            return Err(Error::Invalid(String::from("file is not a table snapshot")));
+        }
+        if data[4] != VERSION {
+            return Err(Error::Invalid(String::from(
+                "unsupported table snapshot version",
+            )));
+        }
+        Ok(Node::from_bulk(data[5..].to_vec()))
+    }
+
+    /// Adds all Nodes from the snapshot to the Table, after restoring
+    /// the banned Addresses. The system can also start without them,
+    /// so errors only get logged.
+    pub fn restore(&self, table: &Safe) {
+        match self.load_banned() {
+            Ok(banned) => {
+                for address in banned {
+                    table.ban(address);
+                }
+            }
+            Err(e) => trace::warn!("unable to load banned addresses: {}", e),
+        }
+        match self.load() {
+            Ok(nodes) => {
+                trace::info!("restoring {} nodes from table snapshot.", nodes.len());
+                for node in nodes {
+                    table.add(node);
+                }
+            }
+            Err(e) => trace::warn!("unable to load table snapshot: {}", e),
+        }
+    }
+
+    /// Spawns a tokio task that periodically saves the Table. It runs
+    /// until it gets aborted.
+    pub fn start(self, table: Safe) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(INTERVAL);
+            // The first tick completes immediately.
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = self.save(&table) {
+                    trace::warn!("unable to save table snapshot: {}", e);
+                }
+            }
+        })
+    }
+}
+
+impl fmt::Debug for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "File({:?})", path),
+            Self::Storage(_) => write!(f, "Storage"),
+        }
+    }
+}
+
+impl DataTopic {
+    /// Creates a DataTopic of an existing Record.
+    pub fn new(address: Address, subscribers: Vec<Address>) -> Self {
+        Self {
+            address,
+            subscribers,
+            publishers: Vec::new(),
+            readers: Vec::new(),
+            removed: false,
+            owner: None,
+            policy: Policy::Open,
+            version: 0,
+        }
+    }
+
+    /// Creates a DataTopic marking the Record as removed.
+    pub fn removed(address: Address) -> Self {
+        Self {
+            address,
+            subscribers: Vec::new(),
+            publishers: Vec::new(),
+            readers: Vec::new(),
+            removed: true,
+            owner: None,
+            policy: Policy::Open,
+            version: 0,
+        }
+    }
+
+    /// Serializes the DataTopic, the structure is described on the
+    /// struct itself.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut data = vec![!self.removed as u8];
+        data.append(&mut self.address.as_bytes().to_vec());
+        let versioned = !self.removed && self.version > 0;
+        if versioned {
+            data.extend_from_slice(&self.version.to_be_bytes());
+        }
+        if let (false, Some(owner)) = (self.removed, &self.owner) {
+            data[0] = 2;
+            let mut policy = self.policy.as_bytes();
+            data.append(&mut owner.as_bytes().to_vec());
+            data.extend_from_slice(&util::compute_length(&policy));
+            data.append(&mut policy);
+        }
+        if !self.removed && (!self.publishers.is_empty() || !self.readers.is_empty()) {
+            data[0] += 2;
+            for addresses in [&self.publishers, &self.readers] {
+                let count = addresses.len();
+                data.extend_from_slice(&[(count / 255) as u8, (count % 255) as u8]);
+                for address in addresses {
+                    data.append(&mut address.as_bytes().to_vec());
+                }
+            }
+        }
+        for subscriber in &self.subscribers {
+            data.append(&mut subscriber.as_bytes().to_vec());
+        }
+        if versioned {
+            data[0] += 4;
+        }
+        data
+    }
+
+    /// Parses a serialized DataTopic. Fails if the flag is unknown or
+    /// the number of bytes doesn't match.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < 33 {
+            return Err(Error::Invalid(String::from("invalid number of bytes")));
+        }
+        let (removed, flag) = match data[0] {
+            0 => (true, 0),
+            1..=4 => (false, data[0]),
+            5..=8 => (false, data[0] - 4),
+            _ => return Err(Error::Invalid(String::from("unknown topic flag"))),
+        };
+        let address = Address::from_slice(&data[1..33])?;
+        let mut start = 33;
+        let mut version = 0;
+        if data[0] >= 5 {
+            if data.len() < 41 {
+                return Err(Error::Invalid(String::from("invalid number of bytes")));
+            }
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&data[33..41]);
+            version = u64::from_be_bytes(bytes);
+            start = 41;
+        }
+        let mut owner = None;
+        let mut policy = Policy::Open;
+        if flag == 2 || flag == 4 {
+            if data.len() < start + 34 {
+                return Err(Error::Invalid(String::from("invalid number of bytes")));
+            }
+            owner = Some(Address::from_slice(&data[start..start + 32])?);
+            let length = util::integer([data[start + 32], data[start + 33]]);
+            let end = start + 34 + length;
+            if data.len() < end {
+                return Err(Error::Invalid(String::from("invalid number of bytes")));
+            }
+            policy = Policy::from_bytes(&data[start + 34..end])?;
+            start = end;
+        }
+        let mut roles = [Vec::new(), Vec::new()];
+        if flag >= 3 {
+            for addresses in roles.iter_mut() {
+                if data.len() < start + 2 {
+                    return Err(Error::Invalid(String::from("invalid number of bytes")));
+                }
+                let count = util::integer([data[start], data[start + 1]]);
+                start += 2;
+                if data.len() < start + count * 32 {
+                    return Err(Error::Invalid(String::from("invalid number of bytes")));
+                }
+                for chunk in data[start..start + count * 32].chunks(32) {
+                    addresses.push(Address::from_slice(chunk)?);
+                }
+                start += count * 32;
+            }
+        }
+        let [publishers, readers] = roles;
+        if !(data.len() - start).is_multiple_of(32) {
+            return Err(Error::Invalid(String::from("invalid number of bytes")));
+        }
+        let mut subscribers = Vec::new();
+        for chunk in data[start..].chunks(32) {
+            subscribers.push(Address::from_slice(chunk)?);
+        }
+        Ok(Self {
+            address,
+            subscribers,
+            publishers,
+            readers,
+            removed,
+            owner,
+            policy,
+            version,
+        })
+    }
+}
+
+impl Journal {
+    /// Opens the Journal and replays all entries. Should an entry be
+    /// corrupted it and everything after it gets dropped. The file is
+    /// compacted right away, which also removes the corrupted bytes.
+    /// A missing file results in an empty Journal.
+    pub fn open(path: &str) -> Result<(Self, Vec<DataTopic>), Error> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let mut topics: HashMap<Address, DataTopic> = HashMap::new();
+        if !data.is_empty() {
+            if data.len() < 5 || &data[..4] != JOURNAL_MAGIC {
+                return Err(Error::Invalid(String::from("file is not a record journal")));
+            }
+            if data[4] != VERSION {
+                return Err(Error::Invalid(String::from(
+                    "unsupported record journal version",
+                )));
+            }
+            let mut rest = &data[5..];
+            while !rest.is_empty() {
+                match Journal::read_entry(rest) {
+                    Some((topic, length)) => {
+                        if topic.removed {
+                            topics.remove(&topic.address);
+                        } else {
+                            topics.insert(topic.address.clone(), topic);
+                        }
+                        rest = &rest[length..];
+                    }
+                    None => {
+                        trace::warn!(
+                            "record journal is corrupted, dropping the last {} bytes.",
+                            rest.len()
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        let topics: Vec<DataTopic> = topics.into_values().collect();
+        let journal = Journal::create(PathBuf::from(path), &topics)?;
+        Ok((journal, topics))
+    }
+
+    /// Appends a single entry, it replaces all earlier entries with
+    /// the same Address.
+    pub fn append(&mut self, topic: &DataTopic) -> Result<(), Error> {
+        self.file.write_all(&Journal::entry(topic))?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Replaces the entire file with one entry per given DataTopic.
+    /// Like table snapshots it gets written to a temporary file first.
+    pub fn compact(&mut self, topics: &[DataTopic]) -> Result<(), Error> {
+        *self = Journal::create(self.path.clone(), topics)?;
+        Ok(())
+    }
+
+    /// Number of entries currently stored in the file.
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// Writes a new Journal file and opens it for appending.
+    fn create(path: PathBuf, topics: &[DataTopic]) -> Result<Self, Error> {
+        let mut data = JOURNAL_MAGIC.to_vec();
+        data.push(VERSION);
+        for topic in topics {
+            data.append(&mut Journal::entry(topic));
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &data)?;
+        fs::rename(&tmp, &path)?;
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Self {
+            path,
+            file,
+            entries: topics.len(),
+        })
+    }
+
+    /// Serializes a single entry including length and checksum.
+    fn entry(topic: &DataTopic) -> Vec<u8> {
+        let mut payload = topic.as_bytes();
+        let mut data = (payload.len() as u32).to_le_bytes().to_vec();
+        let checksum = sha256::hash(&payload);
+        data.append(&mut payload);
+        data.extend_from_slice(&checksum.0[..CHECKSUM_LENGTH]);
+        data
+    }
+
+    /// Parses the first entry of the data and returns it together
+    /// with its total length. None if it is truncated or corrupted.
+    fn read_entry(data: &[u8]) -> Option<(DataTopic, usize)> {
+        if data.len() < 4 {
+            return None;
+        }
+        let mut length = [0; 4];
+        length.copy_from_slice(&data[..4]);
+        let length = u32::from_le_bytes(length) as usize;
+        let total = 4 + length + CHECKSUM_LENGTH;
+        if data.len() < total {
+            return None;
+        }
+        let payload = &data[4..4 + length];
+        let checksum = sha256::hash(payload);
+        if checksum.0[..CHECKSUM_LENGTH] != data[4 + length..total] {
+            return None;
+        }
+        DataTopic::from_bytes(payload)
+            .ok()
+            .map(|topic| (topic, total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryStorage;
+    use crate::node::{Center, Link};
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn test_database_roundtrip() {
+        let path = temp_path();
+        let (_, s) = box_::gen_keypair();
+        let center = Center::new(s, String::from("127.0.0.1"), 4242);
+        let table = Safe::new(20, center.clone());
+        let node = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("::1"), 4242)),
+        );
+        table.add(node.clone());
+        let db = Database::new(&path);
+        db.save(&table).unwrap();
+
+        // The snapshot also contains the center, which gets ignored.
+        let restored = Safe::new(20, center);
+        db.restore(&restored);
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored.get_copy(&node.address, 1)[0], node);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(PathBuf::from(path).with_extension("banned"));
+    }
+
+    #[test]
+    fn test_database_banned() {
+        let path = temp_path();
+        let (_, s) = box_::gen_keypair();
+        let center = Center::new(s, String::from("127.0.0.1"), 4242);
+        let table = Safe::new(20, center.clone());
+        let node = Node::new(Address::random(), None);
+        table.add(node.clone());
+        let db = Database::new(&path);
+        db.save(&table).unwrap();
+        table.ban(node.address.clone());
+        assert!(table.is_empty());
+        db.save_banned(&table).unwrap();
+        assert_eq!(db.load_banned().unwrap(), vec![node.address.clone()]);
+
+        // The older snapshot still contains the banned node.
+        let restored = Safe::new(20, center);
+        db.restore(&restored);
+        assert!(restored.is_empty());
+        assert_eq!(restored.banned(), vec![node.address]);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(PathBuf::from(path).with_extension("banned"));
+    }
+
+    #[test]
+    fn test_database_storage() {
+        let storage = MemoryStorage::new();
+        let (_, s) = box_::gen_keypair();
+        let center = Center::new(s, String::from("127.0.0.1"), 4242);
+        let table = Safe::new(20, center.clone());
+        let node = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("::1"), 4242)),
+        );
+        let banned = Address::random();
+        table.add(node.clone());
+        table.ban(banned.clone());
+        let db = Database::with_storage(Arc::new(storage.clone()));
+        assert_eq!(db.load().unwrap().len(), 0);
+        db.save(&table).unwrap();
+        assert_eq!(storage.iterate(TABLE).unwrap().len(), 2);
+
+        let restored = Safe::new(20, center);
+        Database::with_storage(Arc::new(storage)).restore(&restored);
+        assert_eq!(restored.get_copy(&node.address, 1)[0], node);
+        assert_eq!(restored.banned(), vec![banned]);
+    }
+
+    #[test]
+    fn test_database_missing() {
+        let db = Database::new(&temp_path());
+        assert_eq!(db.load().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_database_invalid() {
+        let path = temp_path();
+        fs::write(&path, [0, 1, 2, 3, 4, 5]).unwrap();
+        assert!(Database::new(&path).load().is_err());
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_topic_serialize() {
+        let topic = DataTopic::new(
+            Address::random(),
+            vec![Address::random(), Address::random()],
+        );
+        assert_eq!(DataTopic::from_bytes(&topic.as_bytes()).unwrap(), topic);
+        let removed = DataTopic::removed(Address::random());
+        assert_eq!(DataTopic::from_bytes(&removed.as_bytes()).unwrap(), removed);
+        assert!(DataTopic::from_bytes(&[1; 40]).is_err());
+        let mut owned = topic.clone();
+        owned.owner = Some(Address::random());
+        owned.policy = Policy::Allowlist(vec![Address::random()]);
+        let data = owned.as_bytes();
+        assert_eq!(data[0], 2);
+        assert_eq!(DataTopic::from_bytes(&data).unwrap(), owned);
+        assert!(DataTopic::from_bytes(&data[..70]).is_err());
+    }
+
+    #[test]
+    fn test_topic_serialize_roles() {
+        let mut topic = DataTopic::new(Address::random(), vec![Address::random()]);
+        topic.readers = topic.subscribers.clone();
+        topic.publishers = vec![Address::random(), Address::random()];
+        let data = topic.as_bytes();
+        assert_eq!(data[0], 3);
+        assert_eq!(DataTopic::from_bytes(&data).unwrap(), topic);
+        topic.owner = Some(Address::random());
+        topic.policy = Policy::OwnerPublish;
+        let data = topic.as_bytes();
+        assert_eq!(data[0], 4);
+        assert_eq!(DataTopic::from_bytes(&data).unwrap(), topic);
+        assert!(DataTopic::from_bytes(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn test_topic_serialize_version() {
+        let mut topic = DataTopic::new(Address::random(), vec![Address::random()]);
+        topic.version = 42;
+        let data = topic.as_bytes();
+        assert_eq!(data[0], 5);
+        assert_eq!(DataTopic::from_bytes(&data).unwrap(), topic);
+        topic.owner = Some(Address::random());
+        topic.policy = Policy::Allowlist(vec![Address::random()]);
+        topic.publishers = vec![Address::random()];
+        let data = topic.as_bytes();
+        assert_eq!(data[0], 8);
+        assert_eq!(DataTopic::from_bytes(&data).unwrap(), topic);
+        assert!(DataTopic::from_bytes(&data[..38]).is_err());
+        let mut removed = DataTopic::removed(topic.address.clone());
+        removed.version = 42;
+        assert_eq!(removed.as_bytes()[0], 0);
+    }
+
+    #[test]
+    fn test_journal_replay() {
+        let path = temp_path();
+        let (mut journal, topics) = Journal::open(&path).unwrap();
+        assert!(topics.is_empty());
+        let kept = DataTopic::new(Address::random(), vec![Address::random()]);
+        let dropped = Address::random();
+        journal
+            .append(&DataTopic::new(kept.address.clone(), Vec::new()))
+            .unwrap();
+        journal
+            .append(&DataTopic::new(dropped.clone(), Vec::new()))
+            .unwrap();
+        journal.append(&kept).unwrap();
+        journal.append(&DataTopic::removed(dropped)).unwrap();
+        drop(journal);
+
+        let (journal, topics) = Journal::open(&path).unwrap();
+        assert_eq!(topics, vec![kept]);
+        assert_eq!(journal.len(), 1);
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_journal_corrupted() {
+        let path = temp_path();
+        let (mut journal, _) = Journal::open(&path).unwrap();
+        let kept = DataTopic::new(Address::random(), vec![Address::random()]);
+        journal.append(&kept).unwrap();
+        let mut entry = Journal::entry(&DataTopic::new(Address::random(), Vec::new()));
+        let last = entry.len() - 1;
+        entry[last] ^= 1;
+        journal.file.write_all(&entry).unwrap();
+        drop(journal);
+
+        let (_, topics) = Journal::open(&path).unwrap();
+        assert_eq!(topics, vec![kept.clone()]);
+        // The corrupted bytes are gone after opening once.
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 5 + Journal::entry(&kept).len());
+        let _ = fs::remove_file(path);
+    }
+
+    fn temp_path() -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.table", rand::random::<u64>()));
+        path.to_string_lossy().to_string()
+    }
+}
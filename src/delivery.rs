@@ -0,0 +1,401 @@
+//! # Delivery
+//!
+//! Topics in at-least-once mode don't send their messages to the
+//! subscribers directly. Instead each one is passed to the node
+//! hosting the Record of the Topic, which stores it until the
+//! subscriber acknowledged it and retries with an increasing backoff.
+//! The publisher itself keeps retrying until the Record accepted the
+//! message. Both sides keep this state in an Outbox, with a Storage
+//! it is written to the delivery namespace, so the retries continue
+//! after a restart. Messages the Record gave up on can be kept for
+//! the subscriber, see the inbox module.
+//!
+//! Since messages can arrive more than once, receivers deduplicate
+//! them by publisher and sequence number with a sliding Window.
+
+use crate::backend::{Storage, DELIVERY};
+use crate::error::Error;
+use crate::message::Message;
+use crate::node::Address;
+use crate::trace;
+use crate::transaction::Transaction;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Delay before the first retry, it doubles with every attempt.
+const RETRY_BASE: Duration = Duration::from_secs(1);
+
+/// Upper limit for the delay between two retries.
+const RETRY_MAX: Duration = Duration::from_secs(16);
+
+/// Number of retries before a message gets dropped.
+pub const RETRY_LIMIT: u32 = 6;
+
+/// Number of sequence numbers below the highest one a Window keeps
+/// track of.
+const WINDOW: u64 = 64;
+
+/// Identifies a single message of a publisher to one subscriber,
+/// it is the body of Ack messages.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Receipt {
+    pub topic: Address,
+    pub publisher: Address,
+    pub subscriber: Address,
+    pub sequence: u64,
+}
+
+/// Messages waiting to be acknowledged by their target.
+#[derive(Default)]
+pub struct Outbox {
+    pending: HashMap<(Address, Receipt), Entry>,
+    /// Only available if the messages are persisted.
+    storage: Option<Arc<dyn Storage>>,
+}
+
+/// A stored message with its retry state.
+#[derive(Debug)]
+struct Entry {
+    message: Message,
+    attempts: u32,
+    due: Instant,
+}
+
+/// Sequence numbers already received from a single publisher on a
+/// Topic.
+#[derive(Debug, Default)]
+pub struct Window {
+    /// Highest sequence number received so far.
+    highest: Option<u64>,
+    /// Bit n is set if highest - n was received.
+    seen: u64,
+}
+
+impl Receipt {
+    pub fn new(topic: Address, publisher: Address, subscriber: Address, sequence: u64) -> Self {
+        Self {
+            topic,
+            publisher,
+            subscriber,
+            sequence,
+        }
+    }
+
+    /// Receipt of an Unsubscribe, which gets retried the same way
+    /// until the Record acknowledged it. The subscriber is also the
+    /// publisher, which never happens for published messages.
+    pub fn unsubscribe(topic: Address, subscriber: Address) -> Self {
+        Self::new(topic, subscriber.clone(), subscriber, 0)
+    }
+
+    /// Receipt of a Subscribe, like the one of an Unsubscribe but with
+    /// a different sequence number.
+    pub fn subscribe(topic: Address, subscriber: Address) -> Self {
+        Self::new(topic, subscriber.clone(), subscriber, 1)
+    }
+
+    /// Serializes the Receipt. Structure:
+    /// 32 bytes: Topic,
+    /// 32 bytes: Publisher,
+    /// 32 bytes: Subscriber,
+    /// 8 bytes: Sequence number
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut data = self.topic.as_bytes().to_vec();
+        data.extend_from_slice(&self.publisher.as_bytes());
+        data.extend_from_slice(&self.subscriber.as_bytes());
+        data.extend_from_slice(&self.sequence.to_be_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != 104 {
+            return Err(Error::Invalid(String::from("receipt is not valid")));
+        }
+        let mut sequence = [0; 8];
+        sequence.copy_from_slice(&data[96..]);
+        Ok(Self::new(
+            Address::from_slice(&data[..32])?,
+            Address::from_slice(&data[32..64])?,
+            Address::from_slice(&data[64..96])?,
+            u64::from_be_bytes(sequence),
+        ))
+    }
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Restores the messages stored in the Storage, every change is
+    /// written through to it. Restored messages start over with their
+    /// retries, invalid ones get removed.
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Result<Self, Error> {
+        let mut outbox = Self::new();
+        for (key, value) in storage.iterate(DELIVERY)? {
+            let parsed = Address::from_slice(key.get(..32).unwrap_or_default())
+                .ok()
+                .zip(Receipt::from_bytes(key.get(32..).unwrap_or_default()).ok())
+                .zip(Transaction::from_bytes(&value).ok());
+            match parsed {
+                Some(((target, receipt), t)) if t.message.target == target => {
+                    outbox
+                        .pending
+                        .insert((target, receipt), Entry::new(t.message));
+                }
+                _ => {
+                    trace::warn!("dropping invalid stored delivery.");
+                    let _ = storage.delete(DELIVERY, &key);
+                }
+            }
+        }
+        trace::info!("restoring {} pending deliveries.", outbox.pending.len());
+        outbox.storage = Some(storage);
+        Ok(outbox)
+    }
+
+    /// Stores a message until the Receipt gets acknowledged by the
+    /// target of the message. Should it already be stored nothing
+    /// changes and false is returned.
+    pub fn insert(&mut self, receipt: Receipt, message: Message) -> bool {
+        let key = (message.target.clone(), receipt);
+        if self.pending.contains_key(&key) {
+            return false;
+        }
+        if let Some(storage) = &self.storage {
+            let data = Transaction::new(message.clone()).as_bytes();
+            if let Err(e) = storage.put(DELIVERY, &Outbox::key(&key), data) {
+                trace::warn!("unable to store delivery: {}", e);
+            }
+        }
+        self.pending.insert(key, Entry::new(message));
+        true
+    }
+
+    /// Removes the message the target acknowledged, returns false if
+    /// it wasn't stored.
+    pub fn acknowledge(&mut self, target: &Address, receipt: &Receipt) -> bool {
+        let key = (target.clone(), receipt.clone());
+        let removed = self.pending.remove(&key).is_some();
+        if removed {
+            self.forget(&key);
+        }
+        removed
+    }
+
+    /// Removes the message from the Storage.
+    fn forget(&self, key: &(Address, Receipt)) {
+        if let Some(storage) = &self.storage {
+            if let Err(e) = storage.delete(DELIVERY, &Outbox::key(key)) {
+                trace::warn!("unable to remove delivery: {}", e);
+            }
+        }
+    }
+
+    /// Key of a message in the Storage, the target followed by the
+    /// Receipt.
+    fn key((target, receipt): &(Address, Receipt)) -> Vec<u8> {
+        let mut key = target.as_bytes().to_vec();
+        key.extend_from_slice(&receipt.as_bytes());
+        key
+    }
+
+    /// Returns all messages that have to be sent again at the time,
+    /// the delay until their next retry doubles. Messages that
+    /// already reached the RETRY_LIMIT get removed instead, they are
+    /// returned with their Receipt as well.
+    pub fn due(&mut self, now: Instant) -> (Vec<Message>, Vec<(Receipt, Message)>) {
+        let mut messages = Vec::new();
+        let mut dropped = Vec::new();
+        let mut expired = Vec::new();
+        self.pending.retain(|key, entry| {
+            if entry.due > now {
+                return true;
+            }
+            if entry.attempts >= RETRY_LIMIT {
+                dropped.push((key.1.clone(), entry.message.clone()));
+                expired.push(key.clone());
+                return false;
+            }
+            entry.attempts += 1;
+            let delay = RETRY_BASE * 2u32.pow(entry.attempts);
+            entry.due = now + delay.min(RETRY_MAX);
+            messages.push(entry.message.clone());
+            true
+        });
+        for key in expired {
+            self.forget(&key);
+        }
+        (messages, dropped)
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Entry {
+    fn new(message: Message) -> Self {
+        Self {
+            message,
+            attempts: 0,
+            due: Instant::now() + RETRY_BASE,
+        }
+    }
+}
+
+impl Window {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks if the sequence number has been received before.
+    pub fn contains(&self, sequence: u64) -> bool {
+        match self.highest {
+            Some(highest) if sequence <= highest && highest - sequence < WINDOW => {
+                self.seen & (1 << (highest - sequence)) != 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Marks the sequence number as received, returns false if it
+    /// already was. Numbers far below the highest one mean the
+    /// publisher started over, so the Window gets reset.
+    pub fn insert(&mut self, sequence: u64) -> bool {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(sequence);
+                self.seen = 1;
+                return true;
+            }
+        };
+        if sequence > highest {
+            let shift = sequence - highest;
+            self.seen = if shift >= WINDOW {
+                0
+            } else {
+                self.seen << shift
+            };
+            self.seen |= 1;
+            self.highest = Some(sequence);
+            return true;
+        }
+        let offset = highest - sequence;
+        if offset >= WINDOW {
+            self.highest = Some(sequence);
+            self.seen = 1;
+            return true;
+        }
+        let bit = 1 << offset;
+        if self.seen & bit != 0 {
+            return false;
+        }
+        self.seen |= bit;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryStorage;
+    use crate::transaction::Class;
+
+    fn gen_message(target: Address) -> Message {
+        Message::new(
+            Class::Action,
+            Address::random(),
+            target,
+            Address::random(),
+            vec![42],
+        )
+    }
+
+    #[test]
+    fn test_receipt_bytes() {
+        let receipt = Receipt::new(Address::random(), Address::random(), Address::random(), 7);
+        let bytes = receipt.as_bytes();
+        assert_eq!(Receipt::from_bytes(&bytes).unwrap(), receipt);
+        assert!(Receipt::from_bytes(&bytes[1..]).is_err());
+    }
+
+    #[test]
+    fn test_outbox_retry() {
+        let mut outbox = Outbox::new();
+        let target = Address::random();
+        let receipt = Receipt::new(Address::random(), Address::random(), target.clone(), 0);
+        assert!(outbox.insert(receipt.clone(), gen_message(target.clone())));
+        assert!(!outbox.insert(receipt.clone(), gen_message(target.clone())));
+        assert_eq!(outbox.due(Instant::now()).0.len(), 0);
+
+        let mut now = Instant::now();
+        for _ in 0..RETRY_LIMIT {
+            now += RETRY_MAX;
+            let (messages, dropped) = outbox.due(now);
+            assert_eq!((messages.len(), dropped.len()), (1, 0));
+            assert_eq!(messages[0].target, target);
+        }
+        now += RETRY_MAX;
+        let (messages, dropped) = outbox.due(now);
+        assert!(messages.is_empty());
+        assert_eq!(dropped.len(), 1);
+        assert_eq!((&dropped[0].0, &dropped[0].1.target), (&receipt, &target));
+        assert!(outbox.is_empty());
+
+        assert!(outbox.insert(receipt.clone(), gen_message(target.clone())));
+        assert!(!outbox.acknowledge(&Address::random(), &receipt));
+        assert!(outbox.acknowledge(&target, &receipt));
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn test_outbox_storage() {
+        let storage = Arc::new(MemoryStorage::new());
+        let mut outbox = Outbox::with_storage(storage.clone()).unwrap();
+        let target = Address::random();
+        let receipt = Receipt::new(Address::random(), Address::random(), target.clone(), 3);
+        let other = Receipt::new(Address::random(), Address::random(), target.clone(), 4);
+        assert!(outbox.insert(receipt.clone(), gen_message(target.clone())));
+        assert!(outbox.insert(other.clone(), gen_message(target.clone())));
+        assert!(outbox.acknowledge(&target, &other));
+        storage.put(DELIVERY, b"invalid", vec![1, 2]).unwrap();
+
+        let mut restored = Outbox::with_storage(storage.clone()).unwrap();
+        assert_eq!(restored.len(), 1);
+        assert_eq!(storage.iterate(DELIVERY).unwrap().len(), 1);
+        assert!(!restored.insert(receipt.clone(), gen_message(target.clone())));
+        let (messages, _) = restored.due(Instant::now() + RETRY_MAX);
+        assert_eq!(messages[0].body.as_bytes(), vec![42]);
+        assert!(restored.acknowledge(&target, &receipt));
+        assert!(storage.iterate(DELIVERY).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_window_duplicates() {
+        let mut window = Window::new();
+        assert!(!window.contains(5));
+        assert!(window.insert(5));
+        assert!(window.contains(5));
+        assert!(!window.insert(5));
+        assert!(window.insert(3));
+        assert!(window.insert(7));
+        assert!(!window.insert(3));
+        assert!(window.insert(6));
+        assert!(!window.insert(7));
+        assert!(window.insert(7 + WINDOW));
+        assert!(!window.insert(7 + WINDOW));
+        assert!(window.insert(8 + WINDOW));
+
+        // The publisher started over.
+        assert!(window.insert(0));
+        assert!(!window.insert(0));
+        assert!(window.insert(1));
+    }
+}
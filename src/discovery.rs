@@ -0,0 +1,185 @@
+//! # Discovery
+//!
+//! For local testing and LAN deployments a signaling server isn't
+//! required. Instead nodes periodically announce themselves through
+//! UDP multicast. Anybody on the network can send announcements, so
+//! announced nodes are only proposed to the routing table, which
+//! adds them once the handshake verified them.
+//!
+//! Only one node per host can bind the discovery port, all others
+//! fall back to a random port and only send announcements. Nodes
+//! answer the announcements of nodes they don't know yet directly, so
+//! they still discover each other.
+
+use crate::error::Error;
+use crate::node::{Center, Node};
+use crate::router::Safe;
+use crate::trace;
+use std::convert::TryFrom;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Identifies announcements, other datagrams on the port get ignored.
+const MAGIC: &[u8; 4] = b"ACTD";
+
+/// Multicast group all announcements are sent to.
+pub const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 65, 67);
+
+/// Time between two announcements of a node.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time between checks for incoming announcements.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Announces the node on the local network and proposes the ones it
+/// discovers to the routing table.
+pub struct Discovery {
+    socket: UdpSocket,
+    /// Port of the multicast group.
+    port: u16,
+    /// The own node, which gets announced.
+    node: Node,
+    table: Safe,
+}
+
+impl Discovery {
+    /// Binds the discovery port and joins the multicast group, should
+    /// the port already be in use a random one gets bound instead.
+    pub fn new(center: &Center, table: Safe, port: usize) -> Result<Self, Error> {
+        let port = u16::try_from(port)
+            .map_err(|_| Error::Config(String::from("discovery port is not valid")))?;
+        let socket = match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, port)) {
+            Ok(socket) => {
+                socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+                socket
+            }
+            Err(e) if e.kind() == ErrorKind::AddrInUse => {
+                trace::info!("discovery port is in use, only sending announcements");
+                UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+            }
+            Err(e) => return Err(e.into()),
+        };
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            port,
+            node: Node::new(center.public.clone(), Some(center.link.clone())),
+            table,
+        })
+    }
+
+    /// Spawns a tokio task that periodically announces the node and
+    /// handles incoming announcements. It runs until it gets aborted.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(POLL);
+            let rounds = (INTERVAL.as_millis() / POLL.as_millis()) as usize;
+            for round in 0.. {
+                if round % rounds == 0 {
+                    self.announce((GROUP, self.port).into());
+                }
+                self.receive();
+                interval.tick().await;
+            }
+        })
+    }
+
+    /// Serializes a Node for announcing it. Structure:
+    /// 4 bytes: Magic,
+    /// n bytes: Node
+    pub fn encode(node: &Node) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.append(&mut node.as_bytes());
+        data
+    }
+
+    /// Parses an announcement, fails for all other data and
+    /// announcements without a Link.
+    pub fn decode(data: &[u8]) -> Result<Node, Error> {
+        match data.strip_prefix(MAGIC) {
+            Some(data) => {
+                let node = Node::from_bytes(data.to_vec())?;
+                match node.link {
+                    Some(_) => Ok(node),
+                    None => Err(Error::Invalid(String::from("announcement has no link"))),
+                }
+            }
+            None => Err(Error::Invalid(String::from("announcement is not valid"))),
+        }
+    }
+
+    fn announce(&self, target: SocketAddr) {
+        if let Err(e) = self.socket.send_to(&Self::encode(&self.node), target) {
+            trace::warn!("unable to send discovery announcement: {}", e);
+        }
+    }
+
+    /// Handles all waiting announcements, unknown nodes get the own
+    /// announcement as a reply.
+    fn receive(&self) {
+        let mut buffer = [0; 1024];
+        while let Ok((length, origin)) = self.socket.recv_from(&mut buffer) {
+            let node = match Self::decode(&buffer[..length]) {
+                Ok(node) => node,
+                Err(_) => continue,
+            };
+            if node.address == self.node.address {
+                continue;
+            }
+            let known = self.table.find(&node.address).is_some();
+            self.table.propose(node);
+            if !known {
+                trace::info!("discovered new node on the local network");
+                self.announce(origin);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::{Address, Link};
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn test_discovery_announcement() {
+        let node = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("127.0.0.1"), 4242)),
+        );
+        let data = Discovery::encode(&node);
+        let decoded = Discovery::decode(&data).unwrap();
+        assert_eq!(decoded.address, node.address);
+        assert_eq!(decoded.link, node.link);
+        assert!(Discovery::decode(&data[4..]).is_err());
+        let node = Node::new(Address::random(), None);
+        assert!(Discovery::decode(&Discovery::encode(&node)).is_err());
+    }
+
+    #[test]
+    fn test_discovery_unverified() {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("127.0.0.1"), 4242);
+        let table = Safe::new(20, center.clone());
+        let discovery = Discovery::new(&center, table.clone(), 0).unwrap();
+        let port = discovery.socket.local_addr().unwrap().port();
+
+        let node = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("127.0.0.1"), 4243)),
+        );
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        socket
+            .send_to(&Discovery::encode(&node), (Ipv4Addr::LOCALHOST, port))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        discovery.receive();
+        assert!(table.find(&node.address).is_none());
+        let unverified = table.unverified(8);
+        assert_eq!(unverified.len(), 1);
+        assert_eq!(unverified[0].address, node.address);
+    }
+}
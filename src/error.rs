@@ -1,15 +1,24 @@
 //! # Error
 //!
-//! Internal error enum
+//! Internal error enum. Errors of the standard library and other
+//! crates are kept as the source, so the original cause is still
+//! available through std::error::Error::source.
 
+use std::error;
 use std::fmt;
+use std::io;
 use std::net::AddrParseError;
 use std::string::FromUtf8Error;
 use std::sync::mpsc::SendError;
+use std::sync::Arc;
 
-/// Collection of error types of the entire system. There is currently
-/// no kind of smart conversion and this error do not interact with
-/// other crates or the built in types.
+/// The underlying error of another crate, it is shared so the Error
+/// can still be cloned.
+pub type Source = Arc<dyn error::Error + Send + Sync>;
+
+/// Collection of error types of the entire system. Most variants
+/// describe what went wrong in which subsystem, the ones converted
+/// from other errors keep them as their source.
 #[derive(Clone, Debug)]
 pub enum Error {
     /// If the network is experiencing issues connecting to other
@@ -24,6 +33,9 @@ pub enum Error {
     Config(String),
     /// Should messages or transactions not be valid or corrupted.
     Invalid(String),
+    /// If a peer violates the protocol, for example by sending a
+    /// Wire larger than the limit. Its connection gets closed.
+    Protocol(String),
     /// Should there be any issues with the local system, for example
     /// permissions or issues with the local system time.
     System(String),
@@ -33,25 +45,52 @@ pub enum Error {
     Full,
     /// Unknown error
     Unknown,
+    /// Reading or writing a file or socket failed. The io::Error is
+    /// kept, so its kind can still be checked.
+    Io(Arc<io::Error>),
+    /// The handshake with a peer failed, for example because it
+    /// couldn't prove that it owns its Address.
+    Handshake(String),
+    /// The routing table can't be used for the operation, for example
+    /// because an Address isn't accepted or no nodes are known.
+    Routing(String),
+    /// Encryption, decryption, signatures or deriving keys failed.
+    Crypto(String),
+    /// A peer or component didn't answer in time.
+    Timeout(String),
+    /// Data couldn't be encoded or decoded, the error of the format
+    /// is the source.
+    Serialization(Source),
+    /// The Transport failed for a reason other than IO, the error of
+    /// the underlying crate is the source.
+    Transport(Source),
 }
 
-impl std::error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e.as_ref()),
+            Self::Serialization(e) | Self::Transport(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
 
-impl From<std::io::Error> for Error {
-    fn from(_err: std::io::Error) -> Self {
-        Self::System(String::from("generic IO error"))
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(Arc::new(err))
     }
 }
 
 impl From<FromUtf8Error> for Error {
-    fn from(_err: FromUtf8Error) -> Self {
-        Self::System(String::from("address is not valid"))
+    fn from(err: FromUtf8Error) -> Self {
+        Self::Serialization(Arc::new(err))
     }
 }
 
 impl From<AddrParseError> for Error {
-    fn from(_err: AddrParseError) -> Self {
-        Self::Invalid(String::from("ip address is not a valid ipv4 format"))
+    fn from(err: AddrParseError) -> Self {
+        Self::Serialization(Arc::new(err))
     }
 }
 
@@ -61,6 +100,30 @@ impl From<()> for Error {
     }
 }
 
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Serialization(Arc::new(err))
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(err: toml::de::Error) -> Self {
+        Self::Serialization(Arc::new(err))
+    }
+}
+
+impl From<tungstenite::Error> for Error {
+    /// IO errors of the WebSocket are kept as they are, messages
+    /// exceeding the limits violate the protocol.
+    fn from(err: tungstenite::Error) -> Self {
+        match err {
+            tungstenite::Error::Io(e) => Self::Io(Arc::new(e)),
+            tungstenite::Error::Capacity(e) => Self::Protocol(e.to_string()),
+            e => Self::Transport(Arc::new(e)),
+        }
+    }
+}
+
 impl<T> From<SendError<T>> for Error {
     fn from(_err: SendError<T>) -> Self {
         Self::Connection(String::from("channel is no longer available"))
@@ -74,10 +137,41 @@ impl fmt::Display for Error {
             Self::Signaling(s) => write!(f, "signaling server is unavailable: {}", s),
             Self::Config(s) => write!(f, "local configuration is not valid: {}", s),
             Self::Invalid(s) => write!(f, "message is not valid: {}", s),
+            Self::Protocol(s) => write!(f, "peer violated the protocol: {}", s),
             Self::System(s) => write!(f, "operating system error: {}", s),
             Self::Busy(s) => write!(f, "process is busy or unavailable: {}", s),
             Self::Full => write!(f, "item limit has been reached"),
             Self::Unknown => write!(f, "unknown error"),
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Handshake(s) => write!(f, "handshake failed: {}", s),
+            Self::Routing(s) => write!(f, "routing failed: {}", s),
+            Self::Crypto(s) => write!(f, "cryptographic operation failed: {}", s),
+            Self::Timeout(s) => write!(f, "operation timed out: {}", s),
+            Self::Serialization(e) => write!(f, "unable to encode or decode data: {}", e),
+            Self::Transport(e) => write!(f, "transport failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn test_error_source() {
+        let err = Error::from(io::Error::new(io::ErrorKind::NotFound, "table.db"));
+        match &err {
+            Error::Io(e) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            _ => panic!("expected an io error"),
         }
+        assert_eq!(err.source().unwrap().to_string(), "table.db");
+        assert_eq!(err.to_string(), "io error: table.db");
+
+        let json = serde_json::from_slice::<u32>(b"actaeon").unwrap_err();
+        let err = Error::from(json);
+        assert!(matches!(err.clone(), Error::Serialization(_)));
+        assert!(err.source().is_some());
+        assert!(Error::Timeout(String::from("ping")).source().is_none());
     }
 }
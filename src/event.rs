@@ -0,0 +1,151 @@
+//! # Event
+//!
+//! Lifecycle events of the system, like nodes joining the routing
+//! table or connections being dropped. They are emitted by the
+//! Switch, the Listener and the routing table and can be received by
+//! any number of EventReceivers.
+
+use crate::node::Address;
+use crate::trace;
+use tokio::sync::broadcast::{self, error::RecvError, error::TryRecvError};
+use uuid::Uuid;
+
+/// Number of Events kept for each receiver. Receivers that fall
+/// further behind miss the oldest Events.
+const CAPACITY: usize = 256;
+
+/// Everything that can happen to the state of the protocol.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// A new node was added to the routing table.
+    NodeAdded(Address),
+    /// A node was removed from the routing table.
+    NodeRemoved(Address),
+    /// A connection to the node was opened.
+    Connected(Address),
+    /// The connection to the node was closed, usually because it
+    /// stopped responding.
+    Disconnected(Address),
+    /// The bootstrap through the node succeeded.
+    BootstrapCompleted(Address),
+    /// The signaling server could not be reached.
+    BootstrapFailed,
+    /// The node responsible for the Topic confirmed the subscription
+    /// or sent an updated list of subscribers.
+    Subscribed(Address),
+    /// The node responsible for the Topic rejected a subscription or
+    /// message because of its Policy.
+    Denied(Address),
+    /// No node confirmed the subscription to the Topic, not even
+    /// after retrying the Subscribe.
+    SubscriptionFailed(Address),
+    /// A Record hosted on this node was garbage collected, either
+    /// because its last subscriber left or it wasn't used for too
+    /// long.
+    RecordExpired(Address),
+    /// The connection to the node was closed because it violated the
+    /// protocol, for example by sending a Wire larger than the limit.
+    ProtocolError(Address),
+    /// No node could be found for the Transaction with the UUID, not
+    /// even after retrying it for Config::retry seconds.
+    DeliveryFailed(Uuid),
+}
+
+/// Sending side of the Events, cloned into every component that
+/// emits them.
+#[derive(Clone, Debug)]
+pub struct Events(broadcast::Sender<Event>);
+
+/// Receives all Events emitted after it was created.
+#[derive(Debug)]
+pub struct EventReceiver(broadcast::Receiver<Event>);
+
+impl Events {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CAPACITY);
+        Self(sender)
+    }
+
+    /// Passes the Event to all current receivers. Without any
+    /// receivers the Event simply gets dropped.
+    pub fn emit(&self, event: Event) {
+        let _ = self.0.send(event);
+    }
+
+    /// Creates a new receiver for all future Events.
+    pub fn subscribe(&self) -> EventReceiver {
+        EventReceiver(self.0.subscribe())
+    }
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventReceiver {
+    /// Waits for the next Event. Should the receiver have fallen
+    /// behind the missed Events are skipped. None is only returned
+    /// once the system has been shut down.
+    pub async fn recv(&mut self) -> Option<Event> {
+        loop {
+            match self.0.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(n)) => {
+                    trace::warn!("event receiver fell behind, skipped {} events", n);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the next Event if one is available without waiting.
+    pub fn try_recv(&mut self) -> Option<Event> {
+        loop {
+            match self.0.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(n)) => {
+                    trace::warn!("event receiver fell behind, skipped {} events", n);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_broadcast() {
+        let events = Events::new();
+        events.emit(Event::BootstrapFailed);
+        let mut first = events.subscribe();
+        let mut second = events.subscribe();
+        let address = Address::random();
+        events.emit(Event::NodeAdded(address.clone()));
+        assert_eq!(first.try_recv(), Some(Event::NodeAdded(address.clone())));
+        assert_eq!(first.try_recv(), None);
+        assert_eq!(second.try_recv(), Some(Event::NodeAdded(address)));
+    }
+
+    #[test]
+    fn test_events_lagged() {
+        let events = Events::new();
+        let mut receiver = events.subscribe();
+        for _ in 0..CAPACITY + 1 {
+            events.emit(Event::BootstrapFailed);
+        }
+        events.emit(Event::Disconnected(Address::default()));
+        let mut count = 0;
+        while let Some(event) = receiver.try_recv() {
+            count += 1;
+            if count == CAPACITY {
+                assert_eq!(event, Event::Disconnected(Address::default()));
+            }
+        }
+        assert_eq!(count, CAPACITY);
+    }
+}
@@ -0,0 +1,93 @@
+//! # Filter
+//!
+//! Operators can exclude peers from the system without restarting it,
+//! for example malicious ones or nodes of a test setup. Banned
+//! Addresses never get added to the routing table and their
+//! connections get dropped. Alternatively an allowlist can be set, in
+//! which case only the listed Addresses are accepted. The Filter
+//! lives in the routing Table, so it is shared by all components.
+
+use crate::node::Address;
+use std::collections::HashSet;
+
+/// Decides which Addresses are accepted, bans always take precedence
+/// over the allowlist.
+#[derive(Debug, Default, Clone)]
+pub struct Filter {
+    /// Addresses that are never accepted.
+    banned: HashSet<Address>,
+    /// If it is set only these Addresses are accepted.
+    allowed: Option<HashSet<Address>>,
+}
+
+impl Filter {
+    /// Creates a Filter that accepts every Address.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks if the Address is neither banned nor missing from the
+    /// allowlist.
+    pub fn permits(&self, address: &Address) -> bool {
+        if self.banned.contains(address) {
+            return false;
+        }
+        match &self.allowed {
+            Some(allowed) => allowed.contains(address),
+            None => true,
+        }
+    }
+
+    /// Bans the Address, returns false if it already was.
+    pub fn ban(&mut self, address: Address) -> bool {
+        self.banned.insert(address)
+    }
+
+    /// Lifts the ban of the Address, returns false if it wasn't
+    /// banned.
+    pub fn unban(&mut self, address: &Address) -> bool {
+        self.banned.remove(address)
+    }
+
+    /// Only accepts the given Addresses from now on, replacing any
+    /// previous allowlist.
+    pub fn allow_only(&mut self, addresses: Vec<Address>) {
+        self.allowed = Some(addresses.into_iter().collect());
+    }
+
+    /// Removes the allowlist, all Addresses that aren't banned get
+    /// accepted again.
+    pub fn allow_all(&mut self) {
+        self.allowed = None;
+    }
+
+    /// Returns all banned Addresses, in no particular order.
+    pub fn banned(&self) -> Vec<Address> {
+        self.banned.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_permits() {
+        let mut filter = Filter::new();
+        let first = Address::random();
+        let second = Address::random();
+        assert!(filter.permits(&first));
+        assert!(filter.ban(first.clone()));
+        assert!(!filter.ban(first.clone()));
+        assert!(!filter.permits(&first));
+        assert_eq!(filter.banned(), vec![first.clone()]);
+
+        filter.allow_only(vec![first.clone()]);
+        assert!(!filter.permits(&first));
+        assert!(!filter.permits(&second));
+        assert!(filter.unban(&first));
+        assert!(filter.permits(&first));
+        filter.allow_all();
+        assert!(filter.permits(&second));
+    }
+}
@@ -0,0 +1,193 @@
+//! # Gossip
+//!
+//! Topics in gossip mode don't send their messages to every
+//! subscriber. The publisher only pushes a message to a few random
+//! subscribers, after that all subscribers that know it periodically
+//! send the IDs of their recent messages to a few random others. They
+//! reply with the IDs they are missing and receive those messages in
+//! return. Each message is only advertised for a limited number of
+//! rounds, its ID is remembered a while longer to filter out
+//! duplicates.
+//!
+//! Messages are relayed by subscribers, so they are signed by the
+//! relaying node and not encrypted end-to-end.
+
+use crate::error::Error;
+use crate::node::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Time the ID of a message is remembered after its last round, it
+/// gets delivered again should it arrive after that.
+const MEMORY: Duration = Duration::from_secs(60);
+
+/// Length of a serialized message ID.
+const ID_LENGTH: usize = 16;
+
+/// A single message of a Topic in gossip mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rumor {
+    /// Identifies the message on all subscribers.
+    pub id: Uuid,
+    /// Node that broadcasted the message, it is delivered with it as
+    /// source.
+    pub publisher: Address,
+    pub body: Vec<u8>,
+}
+
+/// Recent messages of a single Topic.
+#[derive(Debug, Default)]
+pub struct Rumors {
+    /// Messages that are still advertised with their remaining
+    /// rounds.
+    messages: HashMap<Uuid, (Rumor, usize)>,
+    /// IDs of all known messages with the time of their last round.
+    seen: HashMap<Uuid, Instant>,
+}
+
+impl Rumor {
+    pub fn new(publisher: Address, body: Vec<u8>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            publisher,
+            body,
+        }
+    }
+
+    /// Serializes the Rumor. Structure:
+    /// 16 bytes: ID,
+    /// 32 bytes: Publisher,
+    /// Rest: Body
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut data = self.id.as_bytes().to_vec();
+        data.extend_from_slice(&self.publisher.as_bytes());
+        data.extend_from_slice(&self.body);
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < ID_LENGTH + 32 {
+            return Err(Error::Invalid(String::from("rumor is not valid")));
+        }
+        Ok(Self {
+            id: Uuid::from_slice(&data[..ID_LENGTH])
+                .map_err(|_| Error::Invalid(String::from("rumor is not valid")))?,
+            publisher: Address::from_slice(&data[ID_LENGTH..ID_LENGTH + 32])?,
+            body: data[ID_LENGTH + 32..].to_vec(),
+        })
+    }
+}
+
+impl Rumors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores a new message, it gets advertised for the number of
+    /// rounds. Known messages are ignored and false is returned.
+    pub fn insert(&mut self, rumor: Rumor, rounds: usize) -> bool {
+        if self.contains(&rumor.id) {
+            return false;
+        }
+        self.seen.insert(rumor.id, Instant::now());
+        self.messages.insert(rumor.id, (rumor, rounds));
+        true
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.seen.contains_key(id)
+    }
+
+    /// Returns the message if it is still advertised.
+    pub fn get(&self, id: &Uuid) -> Option<&Rumor> {
+        self.messages.get(id).map(|(rumor, _)| rumor)
+    }
+
+    /// Filters out the IDs of all known messages.
+    pub fn missing(&self, ids: &[Uuid]) -> Vec<Uuid> {
+        ids.iter()
+            .filter(|id| !self.contains(id))
+            .cloned()
+            .collect()
+    }
+
+    /// Starts a new round and returns the IDs to advertise in it.
+    /// Messages without rounds left are no longer advertised and
+    /// IDs older than MEMORY are forgotten.
+    pub fn round(&mut self, now: Instant) -> Vec<Uuid> {
+        let mut ids = Vec::new();
+        let seen = &mut self.seen;
+        self.messages.retain(|id, (_, rounds)| {
+            seen.insert(*id, now);
+            if *rounds == 0 {
+                return false;
+            }
+            *rounds -= 1;
+            ids.push(*id);
+            true
+        });
+        self.seen
+            .retain(|_, last| now.saturating_duration_since(*last) < MEMORY);
+        ids
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.seen.is_empty()
+    }
+}
+
+/// Serializes a list of message IDs, used by digests and requests.
+pub fn encode_ids(ids: &[Uuid]) -> Vec<u8> {
+    ids.iter().flat_map(|id| id.as_bytes().to_vec()).collect()
+}
+
+pub fn decode_ids(data: &[u8]) -> Result<Vec<Uuid>, Error> {
+    if !data.len().is_multiple_of(ID_LENGTH) {
+        return Err(Error::Invalid(String::from("message ids are not valid")));
+    }
+    data.chunks(ID_LENGTH)
+        .map(|chunk| {
+            Uuid::from_slice(chunk)
+                .map_err(|_| Error::Invalid(String::from("message ids are not valid")))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rumor_bytes() {
+        let rumor = Rumor::new(Address::random(), vec![1, 2, 3]);
+        assert_eq!(Rumor::from_bytes(&rumor.as_bytes()).unwrap(), rumor);
+        assert!(Rumor::from_bytes(&rumor.as_bytes()[..40]).is_err());
+
+        let ids = vec![Uuid::new_v4(), Uuid::new_v4()];
+        assert_eq!(decode_ids(&encode_ids(&ids)).unwrap(), ids);
+        assert!(decode_ids(&[0; 17]).is_err());
+    }
+
+    #[test]
+    fn test_rumors_round() {
+        let mut rumors = Rumors::new();
+        let rumor = Rumor::new(Address::random(), vec![42]);
+        let id = rumor.id;
+        assert!(rumors.insert(rumor.clone(), 2));
+        assert!(!rumors.insert(rumor, 2));
+        let other = Uuid::new_v4();
+        assert_eq!(rumors.missing(&[id, other]), vec![other]);
+
+        let now = Instant::now();
+        assert_eq!(rumors.round(now), vec![id]);
+        assert_eq!(rumors.round(now), vec![id]);
+        assert!(rumors.round(now).is_empty());
+        assert!(rumors.get(&id).is_none());
+        assert!(rumors.contains(&id));
+
+        rumors.round(now + MEMORY);
+        assert!(!rumors.contains(&id));
+        assert!(rumors.is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! # Group
+//!
+//! Broadcasts are only encrypted between the publisher and each
+//! subscriber, nodes passing them on (like the Record in
+//! at-least-once mode or other subscribers in gossip mode) can read
+//! them. Topics with group encryption encrypt every body with a
+//! symmetric key shared by all members before it gets sent, so only
+//! members can read it.
+//!
+//! The Topic enabling group encryption manages the key: It is sealed
+//! to every subscriber individually and sent to it like a broadcast.
+//! Whenever a subscriber joins or leaves a new key gets generated and
+//! distributed to the current subscribers, so former members can't
+//! read new messages. Members have to join the group of the node
+//! distributing the keys, they switch to group encryption once they
+//! receive their first key from it and only accept keys signed by
+//! it. Subscribers of subtrees and wildcards never receive a key.
+//!
+//! Every subscriber known to the Topic becomes a member, a
+//! TrustPolicy can restrict who is able to subscribe.
+
+use crate::error::Error;
+use crate::node::Address;
+use sodiumoxide::crypto::box_::SecretKey;
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::{sealedbox, secretbox};
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Number of keys a member keeps, messages encrypted with older
+/// ones can't be read anymore.
+pub const KEEP: usize = 4;
+
+/// Marks a body encrypted with a GroupKey.
+const DATA: u8 = 1;
+
+/// Marks a GroupKey sealed to a single member.
+const KEY: u8 = 2;
+
+/// Length of the key ID in front of every encrypted body.
+const ID_LENGTH: usize = 8;
+
+/// Length of a sealed GroupKey including its marker.
+const SEALED_LENGTH: usize = 1 + sealedbox::SEALBYTES + secretbox::KEYBYTES;
+
+/// Symmetric key shared by all members of a group.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GroupKey(secretbox::Key);
+
+/// Group encryption state of a single Topic.
+#[derive(Debug)]
+pub struct Group {
+    /// All known keys, the newest one first. There is always at least
+    /// one.
+    keys: VecDeque<GroupKey>,
+    /// Node new keys are accepted from, None if this Topic manages
+    /// the group itself.
+    distributor: Option<Address>,
+}
+
+impl GroupKey {
+    pub fn generate() -> Self {
+        Self(secretbox::gen_key())
+    }
+
+    pub fn from_slice(data: &[u8]) -> Result<Self, Error> {
+        secretbox::Key::from_slice(data)
+            .map(Self)
+            .ok_or_else(|| Error::Invalid(String::from("group key is not valid")))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Identifies the key in encrypted bodies, the first bytes of its
+    /// hash.
+    pub fn id(&self) -> [u8; ID_LENGTH] {
+        let digest = sha256::hash(self.as_bytes());
+        let mut id = [0; ID_LENGTH];
+        id.copy_from_slice(&digest.as_ref()[..ID_LENGTH]);
+        id
+    }
+
+    /// Seals the key to the member, only its secret key can open it.
+    /// Structure:
+    /// 1 byte: Marker,
+    /// Rest: Sealed key
+    pub fn seal(&self, member: &Address) -> Vec<u8> {
+        let mut data = vec![KEY];
+        data.append(&mut sealedbox::seal(self.as_bytes(), &member.key));
+        data
+    }
+
+    /// Opens a key sealed to this node, None if the body isn't a
+    /// sealed key or was sealed to another node.
+    pub fn open(body: &[u8], public: &Address, secret: &SecretKey) -> Option<Self> {
+        if !is_key(body) {
+            return None;
+        }
+        let key = sealedbox::open(&body[1..], &public.key, secret).ok()?;
+        Self::from_slice(&key).ok()
+    }
+}
+
+impl fmt::Debug for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GroupKey").finish_non_exhaustive()
+    }
+}
+
+impl Group {
+    /// A group managed by this Topic, it distributes its keys.
+    pub fn managed(key: GroupKey) -> Self {
+        Self {
+            keys: VecDeque::from(vec![key]),
+            distributor: None,
+        }
+    }
+
+    /// A group managed by the distributor, which sent the first key.
+    pub fn member(distributor: Address, key: GroupKey) -> Self {
+        Self {
+            keys: VecDeque::from(vec![key]),
+            distributor: Some(distributor),
+        }
+    }
+
+    pub fn is_managed(&self) -> bool {
+        self.distributor.is_none()
+    }
+
+    /// The key used for outgoing bodies.
+    pub fn current(&self) -> &GroupKey {
+        &self.keys[0]
+    }
+
+    /// Replaces the current key with a new one and returns it, older
+    /// keys are kept for messages still in flight.
+    pub fn rekey(&mut self) -> GroupKey {
+        let key = GroupKey::generate();
+        self.insert(key.clone());
+        key
+    }
+
+    /// Accepts a new key from the source, fails if it isn't the
+    /// distributor of the group.
+    pub fn accept(&mut self, source: &Address, key: GroupKey) -> Result<(), Error> {
+        if self.distributor.as_ref() != Some(source) {
+            return Err(Error::Crypto(String::from(
+                "group key is from an unknown distributor",
+            )));
+        }
+        self.insert(key);
+        Ok(())
+    }
+
+    /// Encrypts the body with the current key. Structure:
+    /// 1 byte: Marker,
+    /// 8 bytes: Key ID,
+    /// 24 bytes: Nonce,
+    /// Rest: Encrypted body
+    pub fn encrypt(&self, body: &[u8]) -> Vec<u8> {
+        let key = self.current();
+        let nonce = secretbox::gen_nonce();
+        let mut data = vec![DATA];
+        data.extend_from_slice(&key.id());
+        data.extend_from_slice(nonce.as_ref());
+        data.append(&mut secretbox::seal(body, &nonce, &key.0));
+        data
+    }
+
+    /// Decrypts a body with the key it was encrypted with, fails if
+    /// that key is unknown.
+    pub fn decrypt(&self, body: &[u8]) -> Result<Vec<u8>, Error> {
+        let split = 1 + ID_LENGTH + secretbox::NONCEBYTES;
+        if body.len() < split || body[0] != DATA {
+            return Err(Error::Crypto(String::from("body is not group encrypted")));
+        }
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.id()[..] == body[1..1 + ID_LENGTH])
+            .ok_or_else(|| Error::Crypto(String::from("group key is unknown")))?;
+        let nonce = secretbox::Nonce::from_slice(&body[1 + ID_LENGTH..split])
+            .ok_or_else(|| Error::Crypto(String::from("nonce is not valid")))?;
+        secretbox::open(&body[split..], &nonce, &key.0)
+            .map_err(|_| Error::Crypto(String::from("unable to decrypt group message")))
+    }
+
+    fn insert(&mut self, key: GroupKey) {
+        if self.keys.contains(&key) {
+            return;
+        }
+        self.keys.push_front(key);
+        self.keys.truncate(KEEP);
+    }
+}
+
+/// True if the body has the length and marker of a sealed key.
+pub fn is_key(body: &[u8]) -> bool {
+    body.len() == SEALED_LENGTH && body[0] == KEY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn test_group_seal() {
+        let (public, secret) = box_::gen_keypair();
+        let member = Address::new(public);
+        let key = GroupKey::generate();
+        let sealed = key.seal(&member);
+        assert!(is_key(&sealed));
+        assert_eq!(GroupKey::open(&sealed, &member, &secret), Some(key));
+        let (_, other) = box_::gen_keypair();
+        assert!(GroupKey::open(&sealed, &member, &other).is_none());
+        assert!(GroupKey::open(&sealed[1..], &member, &secret).is_none());
+    }
+
+    #[test]
+    fn test_group_rekey() {
+        let distributor = Address::random();
+        let first = GroupKey::generate();
+        let mut managed = Group::managed(first.clone());
+        let mut member = Group::member(distributor.clone(), first);
+        assert!(managed.is_managed() && !member.is_managed());
+
+        let old = managed.encrypt(&[1, 2, 3]);
+        assert_eq!(member.decrypt(&old).unwrap(), vec![1, 2, 3]);
+        let key = managed.rekey();
+        let new = managed.encrypt(&[4]);
+        assert!(member.decrypt(&new).is_err());
+        assert!(member.accept(&Address::random(), key.clone()).is_err());
+        member.accept(&distributor, key).unwrap();
+        assert_eq!(member.decrypt(&new).unwrap(), vec![4]);
+        assert_eq!(member.decrypt(&old).unwrap(), vec![1, 2, 3]);
+
+        for _ in 0..KEEP {
+            member.insert(GroupKey::generate());
+        }
+        assert!(member.decrypt(&old).is_err());
+        assert!(member.decrypt(&[DATA, 0, 0]).is_err());
+    }
+}
@@ -1,184 +1,729 @@
-//! # TCP
+//! # Handler
 //!
-//! TCP interface for connecting to the other nodes. (The handlers
-//! should get modularized in the future, currently almost everything
-//! is hard coded.)
+//! Interface for connecting to the other nodes. The actual
+//! connections are established through the configured Transport,
+//! all of them are handled by a small, fixed pool of I/O threads.
+//! New connections are dialed, their handshakes exchanged and
+//! bootstraps requested by a separate pool, so the Listener never
+//! waits for a peer.
 
-use crate::config::Signaling;
+use crate::cache::Cache;
+use crate::config::{Config, ConfigDelta, Signaling};
+use crate::connection::{Notification, Session};
 use crate::error::Error;
+use crate::event::{self, Events};
+use crate::handshake::{Features, Hello};
+use crate::message::Message;
+use crate::metrics::{Event, Metrics};
 use crate::node::{Address, Center, Link, Node};
+use crate::retry::Retries;
 use crate::router::Safe;
-use crate::transaction::{Transaction, Wire};
-use crate::util::{self, Channel};
-use std::cell::RefCell;
-use std::io::prelude::*;
-use std::net::{TcpListener, TcpStream};
+use crate::selection::{Deliveries, SelectionPolicy};
+use crate::trace::{self, Decision, Reason, Tracer};
+use crate::transaction::{Class, Fragments, Priority, Transaction, Wire, MESSAGE_SIZE};
+use crate::transport::{Stream, Transport};
+use crate::trust::{AllowAll, TrustPolicy};
+use crate::util::Channel;
+#[cfg(unix)]
+use mio::unix::SourceFd;
+#[cfg(unix)]
+use mio::Interest;
+use mio::{Registry, Token, Waker};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
 use std::sync::{Arc, Mutex};
-use std::thread;
+use std::task::{self, Context, Wake};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-/// Represents the TCP listener and exposes certain functions to
-/// interact with the outside world. They are mostly just wrappers
-/// around the underlying TCP modules.
+/// Upper limit of the number of I/O threads.
+const WORKERS: usize = 4;
+
+/// Interval in which the I/O threads poll Streams without a socket
+/// of their own. All others wake the thread up once readable.
+const IDLE_MAX: Duration = Duration::from_millis(5);
+
+/// Longest time the idle Listener waits. Timers of Sessions, the
+/// relay, pending dials and nodes waiting for verification get
+/// checked at least that often.
+const TICK: Duration = Duration::from_millis(100);
+
+/// The I/O threads poll all of their Handlers at once, so all sockets
+/// share a single Token. The same goes for the sockets of the
+/// Transport the Listener waits on.
+const SOCKET: Token = Token(0);
+
+/// Token of the Waker of each I/O thread.
+const WAKER: Token = Token(1);
+
+/// Maximum number of low priority Transactions waiting in the Lanes,
+/// the rest stays in the Channel so the Switch still has to wait.
+const LANE_LIMIT: usize = 64;
+
+/// Maximum number of unverified nodes dialed at once.
+const VERIFYING: usize = 16;
+
+/// Largest accepted Proof Wire, it only contains a nonce and the
+/// signature.
+const PROOF_SIZE: usize = 1024;
+
+/// Delay between two attempts to register with the relay again.
+const RELAY_RETRY: Duration = Duration::from_secs(2);
+
+/// Represents the listener and exposes certain functions to interact
+/// with the outside world. They are mostly just wrappers around the
+/// underlying Transport.
 pub struct Listener {
     center: Center,
-    listener: TcpListener,
+    /// Shared with the threads of the Fanout, which dial through it.
+    transport: Arc<dyn Transport>,
     connections: RefCell<ConnectionBucket>,
     channel: Channel<Transaction>,
     limit: usize,
+    /// Maximum number of nodes in answers to bootstrap requests.
+    sample: usize,
     table: Safe,
     cache: Cache,
     signaling: Signaling,
+    signers: Signers,
+    timeout: Duration,
+    /// If Actions of Sessions get encrypted, the Switch handles this
+    /// for all other messages.
+    encryption: bool,
+    /// New Sessions from the Interface, only available if it was
+    /// passed through "with_sessions".
+    incoming: Option<Channel<Session>>,
+    /// Direct Connections of the user, their peers are kept
+    /// connected.
+    sessions: RefCell<Vec<Session>>,
+    /// If this node registers with the signaling server as its relay
+    /// after the bootstrap.
+    relay: bool,
+    /// Next time the node registers with its relay again, while the
+    /// connection to it is lost.
+    reattach: Cell<Instant>,
+    /// If a bootstrap through the signaling server is running, the
+    /// relay isn't reattached meanwhile.
+    attaching: Cell<bool>,
+    /// If the Listener bootstraps through the signaling server when
+    /// it starts.
+    bootstrap: bool,
+    /// Known nodes from the Config, the Listener bootstraps through
+    /// them as well.
+    peers: Vec<Node>,
+    /// Known nodes the bootstrap through is still running, they
+    /// don't get dialed for verification meanwhile.
+    bootstrapping: RefCell<HashSet<Address>>,
+    /// Nodes registered with this node as their relay, Wires for them
+    /// get forwarded through their connection.
+    clients: RefCell<HashSet<Address>>,
+    /// I/O threads handling all connections.
+    pool: Pool,
+    /// Everything this node supports, offered in every handshake.
+    features: Features,
+    /// Further Links the Transport is bound to, advertised in every
+    /// handshake.
+    links: Vec<Link>,
+    /// Outgoing bodies larger than this get compressed, if the peer
+    /// supports it.
+    compression: Option<usize>,
+    /// Small outgoing Wires get written together until this many
+    /// bytes are waiting or the flush delay has passed.
+    coalesce: Option<usize>,
+    /// Longest time a coalesced Wire waits before it gets written.
+    flush: Duration,
+    /// Number of nodes closest to the Center whose connections never
+    /// get closed to make room for others.
+    pinned: usize,
+    /// Transactions from the Switch waiting to be distributed.
+    lanes: RefCell<Lanes>,
+    /// Dials peers without a connection in the background.
+    fanout: RefCell<Fanout>,
+    /// Largest Wire accepted from peers, the connections of peers
+    /// sending larger ones get closed.
+    message_size: usize,
+    /// Changes to the settings at runtime, forwarded by the Switch.
+    settings: Option<Channel<ConfigDelta>>,
+    /// Decides which peers are accepted in the handshake and provides
+    /// the own token.
+    trust: Arc<dyn TrustPolicy>,
+    /// Transactions no node was found for, they get distributed again
+    /// with an increasing backoff.
+    retries: RefCell<Retries>,
+    /// Orders the closest nodes of each distributed Wire.
+    selection: Box<dyn SelectionPolicy>,
+    /// Whether the last Wires handed to each node got through, it
+    /// feeds back into the selection.
+    deliveries: RefCell<Deliveries>,
+    /// Nodes other nodes told about that are being dialed, they get
+    /// added to the table once the handshake succeeded.
+    verifying: RefCell<HashMap<Address, Node>>,
+    /// Lets the idle Listener wait until a socket of the Transport
+    /// becomes readable or its Waker gets woken.
+    poll: RefCell<mio::Poll>,
+    /// Wakes the Listener up once any of the Channels it receives
+    /// from gets a message, see Bell.
+    bell: task::Waker,
+    /// If the Transport has sockets to wait on, otherwise accept gets
+    /// called every IDLE_MAX.
+    registered: bool,
+}
+
+/// Passes wake ups of Channels on to the Waker of the Listener, so it
+/// can wait on them together with the sockets of the Transport.
+struct Bell(Arc<Waker>);
+
+/// Outgoing Transactions split by their Priority, all waiting ones of
+/// high priority get distributed before the next one of low priority.
+#[derive(Debug, Default)]
+struct Lanes {
+    high: VecDeque<Transaction>,
+    low: VecDeque<Transaction>,
 }
 
 #[derive(Debug)]
 struct Connection {
     address: Address,
     channel: Channel<Action>,
+    /// Wakes the I/O thread of the Handler up for every outgoing
+    /// Action.
+    waker: Option<Arc<Waker>>,
+    /// Negotiated during the handshake, supported by both sides.
+    features: Features,
+    /// Last time a Wire was sent or received, once the limit is
+    /// reached the connection idle for the longest gets closed.
+    used: Cell<Instant>,
+    /// If this node opened the connection, otherwise the peer did.
+    outbound: bool,
 }
 
+/// State of a single connection, polled by one of the I/O threads.
 struct Handler {
     channel: Channel<Action>,
-    socket: TcpStream,
+    socket: Box<dyn Stream>,
     cache: Cache,
+    signers: Signers,
+    /// Time without any incoming data after which the peer is
+    /// considered dead. Keep-alive frames get sent three times in
+    /// that interval should nothing else be sent.
+    timeout: Duration,
+    /// Every sent and received Wire gets reported.
+    metrics: Metrics,
+    /// Last time anything was received from the peer.
+    received: Instant,
+    /// Last time anything was sent to the peer.
+    sent: Instant,
+    /// If the peer supports heartbeats, they get sent instead of the
+    /// keep-alive frames regardless of other traffic.
+    heartbeat: bool,
+    /// Last time a heartbeat was sent, the first one gets sent right
+    /// away.
+    beat: Option<Instant>,
+    /// Time stamps of heartbeats are relative to this.
+    epoch: Instant,
+    /// Compression threshold, only set if both sides support it.
+    compression: Option<usize>,
+    /// Incoming fragments of large Wires.
+    fragments: Fragments,
+    /// Largest incoming Wire, larger ones close the connection.
+    message_size: usize,
+    /// Outgoing Wires waiting to be written together, only set if
+    /// coalescing is enabled.
+    coalescer: Option<Coalescer>,
+    /// Dropped incoming Wires get reported.
+    tracer: Tracer,
+    /// If the I/O thread waits for the socket to become readable,
+    /// otherwise the Handler gets polled every IDLE_MAX.
+    registered: bool,
+}
+
+/// Collects small outgoing Wires of a Handler, so they can be written
+/// with a single syscall instead of one each.
+struct Coalescer {
+    wires: Vec<Wire>,
+    /// Combined size of the waiting Wires in bytes.
+    size: usize,
+    /// Once the waiting Wires reach this size they get written.
+    threshold: usize,
+    /// Longest time the first waiting Wire waits.
+    delay: Duration,
+    /// Time the first waiting Wire was added.
+    since: Instant,
+}
+
+/// Result of polling a Handler once.
+#[derive(Debug, PartialEq)]
+enum Poll {
+    /// Something was read or written.
+    Busy,
+    /// Nothing happened.
+    Idle,
+    /// The connection is closed, the Handler can be dropped.
+    Done,
+}
+
+/// Fixed number of I/O threads, each of them handles the non-blocking
+/// Streams of many Handlers. Idle threads wait until one of their
+/// sockets becomes readable, an outgoing Action arrives or a timer of
+/// a Handler is due, so idle connections don't use any CPU.
+struct Pool {
+    workers: Vec<Worker>,
+    /// Index of the Worker the next Handler gets assigned to.
+    next: RefCell<usize>,
+}
+
+/// A single I/O thread and the queue for passing new Handlers to it.
+struct Worker {
+    queue: Sender<Handler>,
+    /// Wakes the thread up while it waits for readiness.
+    waker: Arc<Waker>,
+    thread: JoinHandle<()>,
+}
+
+/// Threads dialing peers for the Listener, so Wires get sent to
+/// several peers without a connection at the same time. They also
+/// complete the handshakes of incoming connections and bootstrap
+/// through other nodes. The Listener only collects the results, one
+/// slow or unreachable peer therefore doesn't delay everything else.
+struct Fanout {
+    jobs: Sender<Job>,
+    results: Receiver<Done>,
+    /// Received results waiting to be collected, each kind is taken
+    /// on its own.
+    dialed: Vec<Dialed>,
+    accepted: Vec<Accepted>,
+    bootstrapped: Vec<Bootstrapped>,
+    /// Hops currently being dialed.
+    pending: HashMap<Address, Pending>,
+    /// Identifies the next Dial, late results of expired ones must
+    /// not be mistaken for a newer Dial of the same hop.
+    next: u64,
+    /// Time a peer has to accept the connection, complete the
+    /// handshake and receive the Wire before the Dial counts as
+    /// failed.
+    expiry: Duration,
+    /// Passed on to every Dial, rejected peers count as unreachable.
+    trust: Arc<dyn TrustPolicy>,
+}
+
+/// A Dial the Fanout is waiting for.
+struct Pending {
+    id: u64,
+    /// Address of the dialed Node.
+    address: Address,
+    started: Instant,
+    /// Wires for the same hop that have to wait for the connection.
+    waiting: Vec<Wire>,
+}
+
+/// Dials the Node, sends the Wire through the new connection and
+/// reports the result.
+struct Dial {
+    id: u64,
+    hop: Address,
+    node: Node,
+    wire: Wire,
+    /// The own handshake at the time the dial was started.
+    hello: Hello,
+    trust: Arc<dyn TrustPolicy>,
+}
+
+/// Result of a Dial, the connection is handed back to the Listener.
+struct Dialed {
+    id: u64,
+    hop: Address,
+    /// Address of the dialed Node, which differs from the hop for
+    /// relayed nodes.
+    address: Address,
+    result: Result<(Box<dyn Stream>, Features), Error>,
+    waiting: Vec<Wire>,
+}
+
+/// Exchanges the handshake with a peer that connected to this node.
+struct Accept {
+    stream: Box<dyn Stream>,
+    hello: Hello,
+    trust: Arc<dyn TrustPolicy>,
+}
+
+/// Result of an Accept, the connection is handed back to the
+/// Listener.
+struct Accepted {
+    result: Result<(Box<dyn Stream>, Node, Features), Error>,
+}
+
+/// Connects to the node at the Link and requests its routing table.
+struct Bootstrap {
+    link: Link,
+    origin: Origin,
+    hello: Hello,
+    trust: Arc<dyn TrustPolicy>,
+    /// Largest Wire accepted while waiting for the reply.
+    message_size: usize,
+}
+
+/// Result of a Bootstrap, the connection is handed back to the
+/// Listener.
+struct Bootstrapped {
+    origin: Origin,
+    result: Result<(Box<dyn Stream>, Node, Features), Error>,
+    /// Nodes in the reply.
+    nodes: Vec<Node>,
+    /// Wires the peer sent before the reply, their signatures aren't
+    /// checked yet.
+    wires: Vec<Wire>,
+}
+
+/// Why the Listener bootstraps through a node, it decides what
+/// happens with the connection afterwards.
+#[derive(Clone, Debug, PartialEq)]
+enum Origin {
+    /// The signaling server, once the Listener starts.
+    Server,
+    /// The signaling server again, after the connection to the relay
+    /// was lost.
+    Reattach,
+    /// A known node from the Config, it has to answer with this
+    /// Address.
+    Peer(Address),
+}
+
+/// Work for the threads of the Fanout.
+enum Job {
+    Dial(Box<Dial>),
+    Accept(Accept),
+    Bootstrap(Bootstrap),
+}
+
+/// Result of a Job.
+enum Done {
+    Dialed(Dialed),
+    Accepted(Accepted),
+    Bootstrapped(Bootstrapped),
 }
 
 /// TODO: Reduce dependance on dedicated channel enums.
 #[derive(Clone, Debug, PartialEq)]
 enum Action {
     Message(Wire),
+    /// A Wire for a relay client or another node, it was already
+    /// cached on arrival.
+    Forward(Wire),
     Shutdown,
+    /// The peer violated the protocol, the Handler already stopped.
+    Violation,
+    /// Round-trip time measured by a heartbeat, only ever sent to the
+    /// Listener.
+    Heartbeat(Duration),
 }
 
-/// A cache of recent Transaction. Since each message might get
-/// received multiple times, to avoid processing it more than once a
-/// cache is introduced, that stores all recent messages. It has a
-/// maximum number of elemets, once that size has been reached the
-/// oldest elements will get dropped. This doesn't guarantee each
-/// event will only be handled once but it should prevent any
-/// duplication under good network conditions. Should a message be
-/// delayed by a lot it still possible it gets processed more than
-/// once.
+/// Signs outgoing and checks incoming Wires. Addresses are derived
+/// from the signing keys, so every signature can be attributed to its
+/// source without knowing it beforehand.
 #[derive(Clone)]
-struct Cache {
-    /// All current Transactions in the cache. Instead of only storing
-    /// the messages the entire transactions will get stored, which
-    /// should make comparisons faster for larger objects. The array
-    /// will be sorted by age on every update.
-    elements: Arc<Mutex<Vec<[u8; 16]>>>,
-    /// The maximum size of the cache in number of elements. Once the
-    /// size has been reached the oldest element will get dropped to
-    /// make space for new Transactions.
-    limit: usize,
+struct Signers {
+    /// If outgoing Wires get signed and unsigned ones get rejected.
+    /// Disabling this allows communication with unsigned nodes.
+    required: bool,
 }
 
 #[derive(Debug)]
 struct ConnectionBucket {
     pub connections: Vec<Connection>,
     pub limit: usize,
+    /// The number of connections gets reported on every change.
+    metrics: Metrics,
+    /// Opened and closed connections get emitted as Events.
+    events: Events,
+    /// Address of the center, it decides which connection is kept
+    /// should two nodes connect to each other at the same time.
+    center: Address,
 }
 
 impl Connection {
-    fn new(address: Address, socket: TcpStream, cache: Cache) -> (Self, Handler) {
+    fn new(
+        address: Address,
+        socket: Box<dyn Stream>,
+        cache: Cache,
+        signers: Signers,
+        timeout: Duration,
+        metrics: Metrics,
+    ) -> (Self, Handler) {
         let (c1, c2) = Channel::new();
         let connection = Connection {
             address,
             channel: c1,
+            waker: None,
+            features: Features::empty(),
+            used: Cell::new(Instant::now()),
+            outbound: false,
         };
         let handler = Handler {
             channel: c2,
             socket,
             cache,
+            signers,
+            timeout,
+            metrics,
+            received: Instant::now(),
+            sent: Instant::now(),
+            heartbeat: false,
+            beat: None,
+            epoch: Instant::now(),
+            compression: None,
+            fragments: Fragments::new(),
+            message_size: MESSAGE_SIZE,
+            coalescer: None,
+            tracer: Tracer::default(),
+            registered: false,
         };
         (connection, handler)
     }
 
     /// Since there is no reason to use a blocking function on the
-    /// Connection directly only the non-blocking function is exposed.
-    pub fn try_recv(&self) -> Option<Action> {
-        self.channel.try_recv()
+    /// Connection directly only the non-blocking function is exposed,
+    /// the waker of the context gets woken once an Action arrives.
+    /// Heartbeats don't count as use, otherwise no connection would
+    /// ever be idle.
+    pub fn recv_with(&self, cx: &mut Context<'_>) -> Option<Action> {
+        let action = self.channel.recv_with(cx);
+        if let Some(Action::Message(_) | Action::Forward(_)) = &action {
+            self.used.set(Instant::now());
+        }
+        action
     }
 
     pub fn send(&self, wire: Wire) -> Result<(), Error> {
-        self.channel.send(Action::Message(wire))
+        self.channel.send(Action::Message(wire))?;
+        self.used.set(Instant::now());
+        self.wake();
+        Ok(())
+    }
+
+    /// Sends the Wire without checking the cache.
+    fn forward(&self, wire: Wire) -> Result<(), Error> {
+        self.channel.send(Action::Forward(wire))?;
+        self.used.set(Instant::now());
+        self.wake();
+        Ok(())
+    }
+
+    /// Time since the last Wire was sent or received.
+    fn idle(&self) -> Duration {
+        self.used.get().elapsed()
+    }
+
+    /// Makes the I/O thread poll the Handler right away.
+    fn wake(&self) {
+        if let Some(waker) = &self.waker {
+            let _ = waker.wake();
+        }
     }
 
     fn address(&self) -> Address {
         self.address.clone()
     }
+
+    /// Tells the Handler to stop, the I/O thread drops it with the
+    /// next poll.
+    fn shutdown(self) {
+        let _ = self.channel.send(Action::Shutdown);
+        self.wake();
+    }
 }
 
 impl Listener {
-    /// Spaws a new TCP listener based on the link details of the
-    /// center.
+    /// Binds the configured Transport based on the link details of
+    /// the center. If signatures are enabled all outgoing Wires get
+    /// signed and unsigned incoming ones get dropped. Connections
+    /// without any incoming data for the duration of the timeout get
+    /// closed.
     pub fn new(
         center: Center,
         channel: Channel<Transaction>,
-        limit: usize,
         table: Safe,
-        signaling: Signaling,
+        config: Config,
     ) -> Result<Self, Error> {
-        let listener = TcpListener::bind(center.link.to_string())?;
-        listener.set_nonblocking(true)?;
+        let timeouts = config.timeouts();
+        let selection = config.selection.build();
+        let mut transport = config.transport.build();
+        transport.set_timeouts(timeouts);
+        transport.bind(&center.link)?;
+        for link in &config.listen {
+            transport.bind(link)?;
+        }
+        let transport: Arc<dyn Transport> = Arc::from(transport);
+        let poll = mio::Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let registered = listen(transport.as_ref(), poll.registry());
+        let fanout = Fanout::new(
+            config.fanout,
+            center.clone(),
+            transport.clone(),
+            config.difficulty,
+            table.metrics(),
+            timeouts.dial(),
+            waker.clone(),
+        );
+        let mut features = Features::empty();
+        if config.encryption {
+            features.insert(Features::ENCRYPTION);
+        }
+        if config.compression.is_some() {
+            features.insert(Features::COMPRESSION);
+        }
+        features.insert(Features::HEARTBEAT);
+        if config.signatures {
+            features.insert(Features::PROOF);
+        }
+        let connections = ConnectionBucket::new(
+            config.connections,
+            table.metrics(),
+            table.events(),
+            center.public.clone(),
+        );
         let listener = Self {
             center,
-            listener,
-            // TODO: Add params
-            cache: Cache::new(100),
-            connections: RefCell::new(ConnectionBucket::new(10)),
+            transport,
+            cache: Cache::new(config.cache, Duration::from_secs(config.ttl)),
+            connections: RefCell::new(connections),
             channel,
-            limit,
+            limit: config.replication,
+            sample: config.sample,
             table,
-            signaling,
+            signaling: Signaling::new(config.signaling, config.port),
+            signers: Signers::new(config.signatures),
+            timeout: Duration::from_secs(config.timeout),
+            encryption: config.encryption,
+            incoming: None,
+            sessions: RefCell::new(Vec::new()),
+            relay: config.relay,
+            reattach: Cell::new(Instant::now() + RELAY_RETRY),
+            attaching: Cell::new(false),
+            bootstrap: true,
+            peers: config.bootstrap,
+            bootstrapping: RefCell::new(HashSet::new()),
+            clients: RefCell::new(HashSet::new()),
+            pool: Pool::new()?,
+            features,
+            links: config.listen,
+            compression: config.compression,
+            coalesce: config.coalesce,
+            flush: Duration::from_millis(config.flush),
+            pinned: config.pinned,
+            lanes: RefCell::new(Lanes::default()),
+            fanout: RefCell::new(fanout),
+            message_size: config.message_size,
+            settings: None,
+            trust: Arc::new(AllowAll),
+            retries: RefCell::new(Retries::new(
+                config.capacity,
+                Duration::from_secs(config.retry),
+            )),
+            selection,
+            deliveries: RefCell::new(Deliveries::new()),
+            verifying: RefCell::new(HashMap::new()),
+            poll: RefCell::new(poll),
+            bell: task::Waker::from(Arc::new(Bell(waker))),
+            registered,
         };
         Ok(listener)
     }
 
-    pub fn start(self) {
+    /// Accepts new Sessions through the Channel, the Listener keeps
+    /// their peers connected until the user drops the Connection.
+    pub fn with_sessions(mut self, channel: Channel<Session>) -> Self {
+        self.incoming = Some(channel);
+        self
+    }
+
+    /// Applies the changes to the settings received through the
+    /// Channel while running.
+    pub fn with_settings(mut self, channel: Channel<ConfigDelta>) -> Self {
+        self.settings = Some(channel);
+        self
+    }
+
+    /// Only accepts peers trusted by the policy, see the trust module.
+    pub fn with_trust(mut self, trust: Arc<dyn TrustPolicy>) -> Self {
+        self.fanout.get_mut().trust = trust.clone();
+        self.trust = trust;
+        self
+    }
+
+    /// Skips the bootstrap through the signaling server, the routing
+    /// table then only contains restored or manually added nodes.
+    pub fn without_bootstrap(mut self) -> Self {
+        self.bootstrap = false;
+        self
+    }
+
+    /// Starts the Listener thread. It stops once the Switch is no
+    /// longer available, in which case all Handlers and I/O threads
+    /// get stopped as well and the Transport is closed.
+    pub fn start(mut self) -> JoinHandle<()> {
         thread::spawn(move || {
-            if let Ok((socket, node)) =
-                Listener::bootstrap(self.signaling, &self.table, &self.center)
-            {
-                log::info!("actaeon bootstrap completed!");
-                let (conn, handler) = Connection::new(node.address, socket, self.cache.clone());
-                handler.spawn();
-                self.connections.borrow_mut().add(conn);
+            let server = Link::new(self.signaling.server(), self.signaling.port());
+            if self.bootstrap {
+                self.attaching.set(true);
+                self.fanout.borrow_mut().bootstrap(
+                    server.clone(),
+                    Origin::Server,
+                    self.hello(),
+                    self.message_size,
+                );
             } else {
-                log::error!("actaeon bootstrap failed");
+                trace::info!("bootstrap through the signaling server is disabled");
             }
+            self.peers();
+            let bell = self.bell.clone();
+            let mut cx = Context::from_waker(&bell);
+            let mut events = mio::Events::with_capacity(64);
             // TODO: Error handler
             loop {
-                // 1. Read from Channel (non-blocking)
-                if let Some(t) = self.channel.try_recv() {
+                if self.channel.is_closed() {
+                    trace::trace!("switch is unavailable, terminating listener.");
+                    break;
+                }
+
+                // 1. Read from Channel (non-blocking), control traffic
+                // goes first.
+                let (batch, mut busy) = {
+                    let mut lanes = self.lanes.borrow_mut();
+                    lanes.fill(&self.channel, &mut cx);
+                    let batch = lanes.batch();
+                    (batch, !lanes.is_empty())
+                };
+                for t in batch {
+                    let _entered = trace::transaction(&t).entered();
                     if t.target() == self.center.public {
                         let _ = self.channel.send(t);
-                    } else {
-                        let _ = Listener::distribute(
-                            t,
-                            &self.table,
-                            &self.cache,
-                            &mut self.connections.borrow_mut(),
-                            &self.center,
-                            self.limit,
-                        );
+                    } else if let Err(e) = self.distribute(&t) {
+                        trace::debug!("unable to distribute message: {}", e);
+                        match e {
+                            Error::Routing(_) => self.defer(t),
+                            _ => {
+                                let decision = Decision::Dropped(Reason::Busy);
+                                self.table.tracer().transaction(&t, decision);
+                            }
+                        }
                     }
                 }
+                self.retry();
+                self.dialed();
+                self.bootstrapped();
+                self.verify();
 
-                // 2. Read from TCP listener
-                match self.listener.accept() {
-                    Ok((mut stream, _addr)) => {
-                        log::info!("new incoming TCP connection.");
-                        if let Ok(node) = Handler::read_node(&mut stream) {
-                            let _ = Handler::write_node(&mut stream, &self.center);
-                            let addr = node.address.clone();
-                            self.table.add(node);
-                            let (conn, handler) = Connection::new(addr, stream, self.cache.clone());
-                            handler.spawn();
-                            self.connections.borrow_mut().add(conn);
-                        }
-                        // if any of the steps fail the connection gets dropped.
-                    }
-                    Err(_) => {}
+                // 2. Read from Transport, the handshake happens on the
+                // Fanout.
+                if let Some(stream) = self.transport.accept() {
+                    trace::info!("new incoming connection.");
+                    busy = true;
+                    let hello = self.hello();
+                    self.fanout.borrow_mut().accept(stream, hello);
                 }
+                self.accepted();
 
                 // 3. Read from Connection channels
                 {
@@ -186,327 +731,1830 @@ impl Listener {
                     let mut addr = Address::random();
                     // 3. Read from each Connection Channel.
                     for conn in self.connections.borrow().connections.iter() {
-                        if let Some(action) = conn.try_recv() {
+                        // Peers that got banned after they connected.
+                        if !self.table.accepts(&conn.address()) {
+                            drop = true;
+                            addr = conn.address();
+                            continue;
+                        }
+                        if let Some(action) = conn.recv_with(&mut cx) {
+                            busy = true;
                             match action {
                                 Action::Message(wire) => {
-                                    if wire.is_empty() {
-                                        let response = Wire::bootstrap(self.table.export());
+                                    if wire.is_empty() && wire.body().is_empty() {
+                                        let response =
+                                            Wire::bootstrap(self.table.sample(self.sample));
                                         let _ = conn.send(response);
+                                    } else if wire.is_empty() {
+                                        // A late bootstrap reply, its nodes
+                                        // only get added once verified.
+                                        for node in Node::from_bulk(wire.body().to_vec()) {
+                                            self.table.propose(node);
+                                        }
                                     } else {
-                                        let t = Transaction::from_wire(&wire).unwrap();
-                                        let _ = self.channel.send(t);
+                                        let mut t = match Transaction::from_wire(&wire) {
+                                            Ok(t) => t,
+                                            Err(e) => {
+                                                trace::warn!("dropping incoming wire: {}", e);
+                                                let decision = Decision::Dropped(Reason::Malformed);
+                                                self.table.tracer().wire(&wire, decision);
+                                                continue;
+                                            }
+                                        };
+                                        t.set_ingress(conn.address());
+                                        let _entered = trace::transaction(&t).entered();
+                                        let target = t.target();
+                                        if t.class() == Class::Relay && target == self.center.public
+                                        {
+                                            let _route =
+                                                trace::route("register", &target).entered();
+                                            self.register(t, &conn.address());
+                                        } else if self.clients.borrow().contains(&target) {
+                                            let _route = trace::route("relay", &target).entered();
+                                            self.forward(wire, &target);
+                                        } else if let Some(t) = self.deliver(t) {
+                                            let _route = trace::route("switch", &target).entered();
+                                            let _ = self.channel.send(t);
+                                        }
                                     }
                                 }
+                                // Only ever sent to the Handler.
+                                Action::Forward(_) => {}
                                 Action::Shutdown => {
-                                    //self.connections.borrow_mut().remove(&addr);
+                                    // The Handler only stops by itself if
+                                    // the peer is no longer reachable.
+                                    drop = true;
+                                    addr = conn.address();
+                                }
+                                Action::Violation => {
+                                    self.table
+                                        .events()
+                                        .emit(event::Event::ProtocolError(conn.address()));
                                     drop = true;
                                     addr = conn.address();
                                 }
+                                Action::Heartbeat(rtt) => {
+                                    self.table.measure(&conn.address(), rtt);
+                                }
                             }
                         }
                     }
                     if drop {
                         self.connections.borrow_mut().remove(&addr);
+                        self.clients.borrow_mut().remove(&addr);
+                        self.table.status(&addr, false);
                     }
                 }
+
+                // 4. Keep the Sessions and the relay connected
+                busy |= self.sessions(&mut cx);
+                self.reattach(&server);
+
+                // 5. Apply changed settings
+                self.reconfigure(&mut cx);
+
+                // 6. Wait for more work, unless some is left over
+                if !busy {
+                    self.wait(&mut events);
+                }
             }
-        });
+            self.connections.borrow_mut().shutdown();
+            self.pool.shutdown();
+        })
     }
 
-    fn distribute(
-        t: Transaction,
-        table: &Safe,
-        cache: &Cache,
-        conns: &mut ConnectionBucket,
-        center: &Center,
-        limit: usize,
-    ) -> Result<(), Error> {
+    /// Applies all waiting changes to the settings. Connections above
+    /// a lowered limit get closed right away, all other settings of
+    /// connections only apply to new ones.
+    fn reconfigure(&mut self, cx: &mut Context<'_>) {
+        let settings = match &self.settings {
+            Some(settings) => settings,
+            None => return,
+        };
+        while let Some(delta) = settings.recv_with(cx) {
+            trace::info!("applying changed settings");
+            if delta.cache.is_some() || delta.ttl.is_some() {
+                let (limit, ttl) = self.cache.limits();
+                let ttl = delta.ttl.map(Duration::from_secs).unwrap_or(ttl);
+                self.cache.resize(delta.cache.unwrap_or(limit), ttl);
+            }
+            if let Some(replication) = delta.replication {
+                self.limit = replication;
+            }
+            if let Some(timeout) = delta.timeout {
+                self.timeout = Duration::from_secs(timeout);
+            }
+            if let Some(message_size) = delta.message_size {
+                self.message_size = message_size;
+            }
+            if let Some(pinned) = delta.pinned {
+                self.pinned = pinned;
+            }
+            if let Some(connections) = delta.connections {
+                let pinned = self.pinned();
+                let mut conns = self.connections.borrow_mut();
+                conns.limit = connections;
+                while conns.len() > conns.limit && conns.evict(&pinned) {}
+            }
+        }
+    }
+
+    /// Waits until a Channel, the Fanout or a socket of the Transport
+    /// has something for the Listener, at most until the next retry or
+    /// for TICK. Without sockets accept gets called every IDLE_MAX.
+    fn wait(&self, events: &mut mio::Events) {
+        let mut timeout = TICK;
+        if let Some(next) = self.retries.borrow().next() {
+            timeout = timeout.min(next.saturating_duration_since(Instant::now()));
+        }
+        if !self.registered {
+            timeout = timeout.min(IDLE_MAX);
+        }
+        if let Err(e) = self.poll.borrow_mut().poll(events, Some(timeout)) {
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                trace::error!("unable to wait for readiness: {}", e);
+                thread::sleep(IDLE_MAX);
+            }
+        }
+    }
+
+    /// Hands the Stream to one of the I/O threads and returns the
+    /// Connection to it.
+    fn open(
+        &self,
+        address: Address,
+        stream: Box<dyn Stream>,
+        features: Features,
+        outbound: bool,
+    ) -> Connection {
+        let (mut conn, mut handler) = Connection::new(
+            address,
+            stream,
+            self.cache.clone(),
+            self.signers.clone(),
+            self.timeout,
+            self.table.metrics(),
+        );
+        handler.compression = self
+            .compression
+            .filter(|_| features.contains(Features::COMPRESSION));
+        handler.heartbeat = features.contains(Features::HEARTBEAT);
+        handler.message_size = self.message_size;
+        handler.fragments = Fragments::with_limit(self.message_size);
+        handler.coalescer = self
+            .coalesce
+            .map(|threshold| Coalescer::new(threshold, self.flush));
+        handler.tracer = self.table.tracer();
+        conn.waker = Some(self.pool.spawn(handler));
+        conn.features = features;
+        conn.outbound = outbound;
+        conn
+    }
+
+    /// Sends the Transaction to the closest node with a connection.
+    /// All closer nodes without one get dialed in the background and
+    /// receive it as well once the connection is established.
+    fn distribute(&self, t: &Transaction) -> Result<(), Error> {
+        let center = &self.center;
+        let conns = self.connections.borrow();
+        let mut wire = t.to_wire();
+        self.signers.sign(&mut wire, center);
         let target = t.target();
-        let targets = table.get_copy(&target, limit);
-        if targets.len() == 0 {
-            return Err(Error::System("no target nodes found".to_string()));
+        let _route = trace::route("distribute", &target).entered();
+        let mut targets = self
+            .table
+            .get_copy(&target, self.selection.candidates(self.limit));
+        // Only nodes closer to the target than the Center can take
+        // over forwarded Transactions, otherwise they could circle.
+        if t.is_forwarded() {
+            let distance = &center.public ^ &target;
+            targets.retain(|node| (&node.address ^ &target) < distance);
         }
+        let mut targets = self
+            .selection
+            .select(&target, targets, &self.deliveries.borrow());
+        targets.truncate(self.limit);
+        if targets.is_empty() {
+            return Err(Error::Routing(String::from("no target nodes found")));
+        }
+        let mut fanout = self.fanout.borrow_mut();
         for node in targets {
-            let addr = node.address.clone();
+            let addr = self.hop(&node);
             if let Some(conn) = conns.get(&addr) {
-                return conn.send(t.to_wire());
-            } else {
-                if conns.len() >= conns.limit {
-                    return Listener::write(t.to_wire(), node, center);
+                // Forwarded Wires are already in the cache.
+                let result = if t.is_forwarded() {
+                    conn.forward(wire)
                 } else {
-                    if let Ok(stream) = Listener::activate(t.to_wire(), node, center) {
-                        let (conn, handler) = Connection::new(addr, stream, cache.clone());
-                        handler.spawn();
-                        conns.add(conn);
-                    } else {
-                        // TODO: Update RT, deactivate
-                    }
-                }
+                    conn.send(wire)
+                };
+                self.deliveries
+                    .borrow_mut()
+                    .record(&node.address, result.is_ok());
+                return result;
             }
+            fanout.dial(addr, node, wire.clone(), self.hello());
         }
         Ok(())
     }
 
-    fn write(wire: Wire, node: Node, center: &Center) -> Result<(), Error> {
-        match node.link {
-            Some(link) => {
-                let mut stream = TcpStream::connect(link.to_string())?;
-                let _ = Handler::write_node(&mut stream, center);
-                // The node link is already known and correct.
-                let _ = Handler::read_node(&mut stream)?;
-                stream.write(&wire.as_bytes())?;
-                return Ok(());
-            }
-            None => {
-                return Err(Error::Connection(String::from("no link data exists")));
-            }
+    /// Queues a Transaction no node was found for, it fails right
+    /// away if the queue is full.
+    fn defer(&self, t: Transaction) {
+        if let Some(t) = self.retries.borrow_mut().push(t, Instant::now()) {
+            self.fail(&t);
         }
     }
 
-    fn activate(wire: Wire, node: Node, center: &Center) -> Result<TcpStream, Error> {
-        match node.link {
-            Some(link) => {
-                let mut stream = TcpStream::connect(link.to_string())?;
-                let _ = Handler::write_node(&mut stream, center);
-                // The node link is already known and correct.
-                let _ = Handler::read_node(&mut stream)?;
-                stream.write(&wire.as_bytes())?;
-                return Ok(stream);
-            }
-            None => {
-                return Err(Error::Connection(String::from("no link data exists")));
+    /// Distributes all queued Transactions that are due again, the
+    /// ones still without a node stay queued until they expire.
+    fn retry(&self) {
+        let (due, expired) = self.retries.borrow_mut().due(Instant::now());
+        for t in due {
+            match self.distribute(&t) {
+                Err(Error::Routing(_)) => {}
+                Err(e) => {
+                    trace::debug!("unable to distribute message: {}", e);
+                    self.retries.borrow_mut().remove(&t.uuid);
+                    let decision = Decision::Dropped(Reason::Busy);
+                    self.table.tracer().transaction(&t, decision);
+                }
+                Ok(()) => {
+                    trace::info!("distributed message after retrying");
+                    self.retries.borrow_mut().remove(&t.uuid);
+                }
             }
         }
-    }
-
-    fn bootstrap(
-        signaling: Signaling,
-        table: &Safe,
-        center: &Center,
-    ) -> Result<(TcpStream, Node), Error> {
-        let mut stream = TcpStream::connect(signaling.to_string())?;
-        let _ = Handler::write_node(&mut stream, center);
-        let node = Handler::read_node(&mut stream)?;
-        let _ = stream.write(&[0; 142])?;
-        let wire = Handler::read_wire(&mut stream)?;
-        let nodes = Node::from_bulk(wire.body().to_vec());
-        for node in nodes {
-            table.add(node);
+        for t in expired {
+            self.fail(&t);
         }
-        Ok((stream, node))
     }
-}
 
-impl Handler {
-    fn spawn(mut self) {
-        thread::spawn(move || {
-            // Otherwise the read_wire will be blocking and only allow
-            // one iteration for each incoming message.
-            let _ = self.socket.set_nonblocking(true);
-            // Dedicated thread per socket.
-            loop {
-                // Incoming TCP
-                if let Ok(wire) = Handler::read_wire(&mut self.socket) {
-                    log::info!("received message through existing connection.");
-                    if !self.cache.exists(&wire.uuid) || wire.is_empty() {
-                        self.cache.add(&wire.uuid);
-                        let _ = self.channel.send(Action::Message(wire));
-                    }
-                }
+    /// Gives up on the Transaction, no node could be found for it.
+    fn fail(&self, t: &Transaction) {
+        trace::warn!("dropping undeliverable message: {:?}", t.uuid);
+        self.table
+            .tracer()
+            .transaction(t, Decision::Dropped(Reason::NoRoute));
+        self.table
+            .events()
+            .emit(event::Event::DeliveryFailed(t.uuid));
+    }
 
-                // Channel messages
-                if let Some(action) = self.channel.try_recv() {
-                    match action {
-                        Action::Message(wire) => {
-                            if !self.cache.exists(&wire.uuid) || wire.is_empty() {
-                                self.cache.add(&wire.uuid);
-                                let message = wire.as_bytes();
-                                let e = self.socket.write(&message);
-                                if e.is_err() {
-                                    let _ = self.channel.send(Action::Shutdown);
-                                    break;
-                                }
-                            }
+    /// Handles the results of all completed dials. Successful ones
+    /// become connections unless all of them are pinned, in which
+    /// case the connection only delivered its Wire. Connections to
+    /// the peers of Sessions are kept regardless. Dials that take
+    /// longer than the dial timeout count as failed right away, the
+    /// Wires waiting for failed dials get queued for another attempt.
+    fn dialed(&self) {
+        let completed = self.fanout.borrow_mut().collect();
+        for dialed in completed {
+            let verified = self.verifying.borrow_mut().remove(&dialed.address);
+            let (mut stream, features) = match dialed.result {
+                Ok(connected) => connected,
+                Err(e) => {
+                    trace::warn!("unable to reach peer: {}", e);
+                    self.table.status(&dialed.address, false);
+                    self.deliveries.borrow_mut().record(&dialed.address, false);
+                    for session in self.sessions.borrow_mut().iter_mut() {
+                        if session.address == dialed.address {
+                            session.failed();
                         }
-                        Action::Shutdown => {
-                            break;
+                    }
+                    for wire in dialed.waiting.iter().filter(|w| !w.is_keepalive()) {
+                        match Transaction::from_wire(wire) {
+                            Ok(t) => self.defer(t),
+                            Err(e) => trace::debug!("dropping waiting wire: {}", e),
                         }
                     }
+                    continue;
                 }
+            };
+            if let Some(node) = verified {
+                self.table.add(node);
             }
-        });
-    }
-
-    fn read_wire(stream: &mut TcpStream) -> Result<Wire, Error> {
-        let mut header = [0; 142];
-        match stream.read(&mut header) {
-            Ok(read_len) => {
-                if read_len != 142 {
-                    return Err(Error::Connection("unable to read header bytes".to_string()));
+            self.table.status(&dialed.address, true);
+            self.deliveries.borrow_mut().record(&dialed.address, true);
+            // Peers of Sessions are always kept connected.
+            let address = &dialed.address;
+            let session = self
+                .sessions
+                .borrow()
+                .iter()
+                .any(|session| &session.address == address);
+            let mut conns = self.connections.borrow_mut();
+            // The peer might have connected in the meantime.
+            let replaces = conns.replaces(&dialed.hop, true);
+            if let Some(conn) = conns.get(&dialed.hop).filter(|_| !replaces) {
+                for wire in dialed.waiting {
+                    let _ = conn.send(wire);
+                }
+            } else if replaces
+                || session
+                || conns.len() < conns.limit
+                || conns.evict(&self.pinned())
+            {
+                let conn = self.open(dialed.hop, stream, features, true);
+                for wire in dialed.waiting {
+                    let _ = conn.send(wire);
+                }
+                conns.add(conn);
+            } else {
+                // All connections are pinned, this one only delivers
+                // the waiting Wires.
+                for wire in dialed.waiting {
+                    let _ = transmit(stream.as_mut(), wire, None);
                 }
-                let length = util::get_length(&header);
-                let mut body = vec![0; length];
-                stream.read_exact(&mut body)?;
-
-                let mut message = Vec::new();
-                message.append(&mut header.to_vec());
-                message.append(&mut body);
-
-                let wire = Wire::from_bytes(&message)?;
-                Ok(wire)
-            }
-            Err(_) => {
-                return Err(Error::Connection("unable to read header bytes".to_string()));
             }
         }
     }
 
-    fn read_node(stream: &mut TcpStream) -> Result<Node, Error> {
-        let mut header = [0; 34];
-        let header_length = stream.read(&mut header)?;
-        if header_length != 34 {
-            return Err(Error::Connection("unable to read header bytes".to_string()));
+    /// Dials nodes other nodes told about, so they only get added to
+    /// the table once they completed a handshake at their Link. Nodes
+    /// with an open connection already did, known nodes still being
+    /// bootstrapped through will.
+    fn verify(&self) {
+        let mut verifying = self.verifying.borrow_mut();
+        let free = VERIFYING.saturating_sub(verifying.len());
+        if free == 0 {
+            return;
         }
-        let length = util::get_length(&header);
-        let mut link = vec![0; length];
-        stream.read_exact(&mut link)?;
+        for node in self.table.unverified(free) {
+            if self.connections.borrow().get(&node.address).is_some() {
+                self.table.add(node);
+            } else if !verifying.contains_key(&node.address)
+                && !self.bootstrapping.borrow().contains(&node.address)
+            {
+                let address = node.address.clone();
+                verifying.insert(address.clone(), node.clone());
+                let hello = self.hello();
+                self.fanout
+                    .borrow_mut()
+                    .dial(address, node, Wire::keepalive(), hello);
+            }
+        }
+    }
 
-        let addr = Address::from_slice(&header[2..])?;
-        let link = Link::from_bytes(link)?;
-        let node = Node::new(addr, Some(link));
-        Ok(node)
+    /// Addresses of all connections that don't get closed once the
+    /// limit is reached: The closest nodes to the Center, the peers
+    /// of Sessions, relay clients and the own relay.
+    fn pinned(&self) -> HashSet<Address> {
+        let mut pinned: HashSet<Address> = self
+            .table
+            .get_copy(&self.center.public, self.pinned)
+            .iter()
+            .map(|node| self.hop(node))
+            .collect();
+        for session in self.sessions.borrow().iter() {
+            match self.table.find(&session.address) {
+                Some(node) => pinned.insert(self.hop(&node)),
+                None => pinned.insert(session.address.clone()),
+            };
+        }
+        pinned.extend(self.clients.borrow().iter().cloned());
+        if let Some(relay) = self.table.link().relay {
+            pinned.insert(relay);
+        }
+        pinned
     }
 
-    fn write_node(stream: &mut TcpStream, center: &Center) -> Result<(), Error> {
-        let node = Node::new(center.public.clone(), Some(center.link.clone()));
-        stream.write(&node.as_bytes())?;
-        Ok(())
+    /// Accepts new Sessions, drops closed ones and sends the messages
+    /// of the user. Peers without a connection get dialed through the
+    /// Fanout once their backoff has passed. Returns true if anything
+    /// was received.
+    fn sessions(&self, cx: &mut Context<'_>) -> bool {
+        let mut busy = false;
+        if let Some(incoming) = &self.incoming {
+            while let Some(session) = incoming.recv_with(cx) {
+                trace::info!("new direct session requested.");
+                self.sessions.borrow_mut().push(session);
+                busy = true;
+            }
+        }
+        let mut sessions = self.sessions.borrow_mut();
+        sessions.retain(|session| !session.is_closed());
+        for session in sessions.iter_mut() {
+            let hop = match self.table.find(&session.address) {
+                Some(node) => self.hop(&node),
+                None => session.address.clone(),
+            };
+            let features = self.connections.borrow().get(&hop).map(|c| c.features);
+            if let Some(features) = features {
+                session.negotiated(features);
+            }
+            session.update(features.is_some());
+            if session.is_due() && !self.fanout.borrow().is_pending(&hop) {
+                match self.table.find(&session.address) {
+                    Some(node) => {
+                        let hello = self.hello();
+                        self.fanout
+                            .borrow_mut()
+                            .dial(hop.clone(), node, Wire::keepalive(), hello);
+                    }
+                    None => {
+                        trace::warn!("unable to dial unknown session peer.");
+                        session.failed();
+                    }
+                }
+            }
+            while let Some(body) = session.recv_with(cx) {
+                busy = true;
+                let message = Message::new(
+                    Class::Action,
+                    self.center.public.clone(),
+                    session.address.clone(),
+                    Address::default(),
+                    body.clone(),
+                );
+                let mut t = Transaction::new(message);
+                if self.encryption {
+                    t.message.encrypt(&self.center);
+                }
+                let mut wire = t.to_wire();
+                self.signers.sign(&mut wire, &self.center);
+                let result = match self.connections.borrow().get(&hop) {
+                    Some(conn) => conn.send(wire),
+                    None => Err(Error::Connection(String::from("peer is not connected"))),
+                };
+                if let Err(e) = result {
+                    trace::warn!("unable to send session message: {}", e);
+                    session.notify(Notification::Failed(body, e));
+                }
+            }
+        }
+        busy
     }
-}
 
-impl ConnectionBucket {
-    /// Creates a new SubscriberBucket. Currently there are no limits
-    /// or other properties so the Bucket is simply an unlimited
-    /// Vec.
-    fn new(limit: usize) -> Self {
-        Self {
-            connections: Vec::new(),
-            limit,
+    /// Actions from the peer of a Session are passed on to the user
+    /// directly, everything else is returned for the Switch.
+    fn deliver(&self, mut t: Transaction) -> Option<Transaction> {
+        if t.class() != Class::Action || t.target() != self.center.public {
+            return Some(t);
+        }
+        let sessions = self.sessions.borrow();
+        let session = match sessions.iter().find(|s| s.address == t.source()) {
+            Some(session) => session,
+            None => return Some(t),
+        };
+        if self.encryption {
+            if let Err(e) = t.message.open(&self.center) {
+                trace::warn!("dropping incoming session message: {}", e);
+                let decision = Decision::Dropped(Reason::Undeliverable);
+                self.table.tracer().transaction(&t, decision);
+                return None;
+            }
         }
+        self.table.tracer().transaction(&t, Decision::Delivered);
+        session.notify(Notification::Message(Box::new(t)));
+        None
     }
 
-    fn add(&mut self, connection: Connection) {
-        match self.get(&connection.address) {
-            Some(_) => {}
-            None => self.connections.push(connection),
+    /// Registers the peer of the connection as a client of this node,
+    /// all Wires for it get forwarded through that connection. The
+    /// Node in the body must belong to the peer and use this node as
+    /// its relay, relayed nodes can't act as relays themselves.
+    fn register(&self, t: Transaction, peer: &Address) {
+        if self.relay {
+            trace::warn!("relayed nodes can't act as relays.");
+            return;
+        }
+        match Node::from_bytes(t.message.body.as_bytes()) {
+            Ok(node)
+                if &node.address == peer
+                    && node.address == t.source()
+                    && node.link.as_ref().and_then(|l| l.relay.as_ref())
+                        == Some(&self.center.public) =>
+            {
+                trace::info!("registered new relay client.");
+                self.clients.borrow_mut().insert(node.address.clone());
+                self.table.replace(node);
+            }
+            _ => trace::warn!("rejecting invalid relay registration."),
         }
     }
 
-    /// Returns a reference to a specific subscriber with a matching
-    /// Address. There isn't really a reason for an end user to use
-    /// this (but it is possible for unusual use cases). It will be
-    /// called by the "add" function.
-    fn get(&self, search: &Address) -> Option<&Connection> {
-        let index = self.connections.iter().position(|e| &e.address == search);
-        match index {
-            Some(i) => self.connections.get(i),
-            None => None,
+    /// Passes the Wire on to the connection of the relay client.
+    fn forward(&self, wire: Wire, client: &Address) {
+        match self.connections.borrow().get(client) {
+            Some(conn) => {
+                let _ = conn.forward(wire);
+            }
+            None => trace::warn!("relay client is no longer connected."),
         }
     }
 
-    /// Drops a subscriber from the Bucket should an Unsubscribe event
-    /// come in.
-    fn remove(&mut self, target: &Address) {
-        let index = self.connections.iter().position(|e| &e.address == target);
-        match index {
-            Some(i) => {
-                self.connections.remove(i);
+    /// Registers with the signaling server again once the connection
+    /// to the relay is lost. Until that succeeds the own Link gets
+    /// advertised, so other nodes can at least try to reach this node
+    /// directly instead of through a relay that no longer forwards.
+    fn reattach(&self, server: &Link) {
+        if !self.relay || !self.bootstrap {
+            return;
+        }
+        if let Some(relay) = self.table.link().relay {
+            if self.connections.borrow().get(&relay).is_some() {
+                return;
             }
-            None => {}
+            trace::warn!("lost the connection to the relay.");
+            self.table.set_link(self.center.link.clone());
         }
+        if self.attaching.get() || Instant::now() < self.reattach.get() {
+            return;
+        }
+        self.reattach.set(Instant::now() + RELAY_RETRY);
+        self.attaching.set(true);
+        self.fanout.borrow_mut().bootstrap(
+            server.clone(),
+            Origin::Reattach,
+            self.hello(),
+            self.message_size,
+        );
     }
 
-    fn len(&self) -> usize {
-        self.connections.len()
+    /// Switches the advertised Link to the relayed one and registers
+    /// with the peer of the connection as the relay. Other nodes then
+    /// reach this node through it.
+    fn announce(&self, conn: &Connection) -> Result<(), Error> {
+        let link = Link::relayed(
+            self.signaling.server(),
+            self.signaling.port(),
+            conn.address(),
+        );
+        self.table.set_link(link.clone());
+        let node = Node::new(self.center.public.clone(), Some(link));
+        let message = Message::new(
+            Class::Relay,
+            self.center.public.clone(),
+            conn.address(),
+            Address::default(),
+            node.as_bytes(),
+        );
+        let mut wire = Transaction::new(message).to_wire();
+        self.signers.sign(&mut wire, &self.center);
+        conn.send(wire)
     }
-}
 
-impl Cache {
-    /// Creates a new empty cache with a fixed size limit. In the
-    /// future it might be helpful to dynamically change the cache
-    /// limit, currently that is not implemented.
-    fn new(limit: usize) -> Self {
-        Self {
-            elements: Arc::new(Mutex::new(Vec::new())),
-            limit,
-        }
+    /// The handshake of this node. It contains the Link other nodes
+    /// should use, which is the relayed one once this node registered
+    /// with a relay, and all further Links it listens on.
+    fn hello(&self) -> Hello {
+        let mut center = self.center.clone();
+        center.link = self.table.link();
+        Hello::new(&center, self.features)
+            .with_token(self.trust.token(&center.public))
+            .with_links(self.links.clone())
     }
 
-    /// Adds a new element to the cache. If the cache is full the
-    /// oldest element will get removed and the new element gets
-    /// added.
-    fn add(&self, uuid: &[u8; 16]) {
-        let mut cache = self.elements.lock().unwrap();
-        (*cache).push(uuid.clone());
-        (*cache).truncate(self.limit);
+    /// Address of the peer a connection for the Node has to go to,
+    /// which is its relay should it have one.
+    fn hop(&self, node: &Node) -> Address {
+        node.link
+            .as_ref()
+            .and_then(|link| link.relay.clone())
+            .filter(|relay| relay != &self.center.public)
+            .unwrap_or_else(|| node.address.clone())
     }
 
-    /// Checks if a transaction is already in the cache.
-    fn exists(&self, id: &[u8; 16]) -> bool {
-        match self.find(id) {
-            Some(_) => true,
-            None => false,
+    /// Bootstraps through every known node from the Config in the
+    /// background, nodes without a Link are skipped.
+    fn peers(&self) {
+        for peer in &self.peers {
+            if let Some(link) = &peer.link {
+                self.bootstrapping.borrow_mut().insert(peer.address.clone());
+                self.fanout.borrow_mut().bootstrap(
+                    link.clone(),
+                    Origin::Peer(peer.address.clone()),
+                    self.hello(),
+                    self.message_size,
+                );
+            }
         }
     }
 
-    /// Returns a pointer to a transaction should the same uuid be
-    /// stored in the cache. In the future the entire cache could get
-    /// restructured to only keep track of uuids.
-    fn find(&self, id: &[u8; 16]) -> Option<[u8; 16]> {
-        let cache = self.elements.lock().unwrap();
-        let index = (*cache).iter().position(|uuid| uuid == id);
-        match index {
-            Some(i) => {
-                let elem = (*cache).get(i).unwrap();
-                return Some(elem.clone());
+    /// Handles the completed handshakes of incoming connections,
+    /// filtered peers get dropped.
+    fn accepted(&self) {
+        let accepted = self.fanout.borrow_mut().accepted();
+        for accepted in accepted {
+            match accepted.result {
+                Ok((_, node, _)) if !self.table.accepts(&node.address) => {
+                    trace::warn!("rejecting connection of filtered peer.");
+                }
+                Ok((stream, node, features)) => {
+                    let addr = node.address.clone();
+                    self.table.add(node);
+                    let conn = self.open(addr, stream, features, false);
+                    let mut conns = self.connections.borrow_mut();
+                    if conns.len() >= conns.limit {
+                        conns.evict(&self.pinned());
+                    }
+                    conns.add(conn);
+                }
+                Err(e) => trace::warn!("incoming handshake failed: {}", e),
             }
-            None => None,
+        }
+    }
+
+    /// Handles the completed bootstraps. The peer gets added to the
+    /// routing table, the nodes of its reply are proposed to it and
+    /// the Wires sent before the reply are passed on. The connection
+    /// stays open. After a bootstrap
+    /// through the signaling server this node registers with it as
+    /// its relay, if enabled.
+    fn bootstrapped(&self) {
+        let bootstrapped = self.fanout.borrow_mut().bootstrapped();
+        for bootstrapped in bootstrapped {
+            let Bootstrapped {
+                origin,
+                result,
+                nodes,
+                wires,
+            } = bootstrapped;
+            match &origin {
+                Origin::Server | Origin::Reattach => self.attaching.set(false),
+                Origin::Peer(address) => {
+                    self.bootstrapping.borrow_mut().remove(address);
+                }
+            }
+            let (socket, node, features) = match result {
+                Ok(connected) => connected,
+                Err(e) => {
+                    match origin {
+                        Origin::Server => {
+                            trace::error!("actaeon bootstrap failed: {}", e);
+                            self.table.events().emit(event::Event::BootstrapFailed);
+                        }
+                        Origin::Reattach => trace::warn!("unable to reach the relay: {}", e),
+                        Origin::Peer(_) => {
+                            trace::warn!("unable to bootstrap through known node: {}", e)
+                        }
+                    }
+                    continue;
+                }
+            };
+            if let Origin::Peer(address) = &origin {
+                if address != &node.address {
+                    trace::warn!("known node answered with a different address");
+                    continue;
+                }
+            }
+            for wire in wires {
+                if let Err(e) = self.signers.check(&wire) {
+                    trace::warn!("dropping incoming wire: {}", e);
+                    self.table
+                        .tracer()
+                        .wire(&wire, Decision::Dropped(Reason::Signature));
+                    continue;
+                }
+                match Transaction::from_wire(&wire) {
+                    Ok(mut t) => {
+                        t.set_ingress(node.address.clone());
+                        let _ = self.channel.send(t);
+                    }
+                    Err(e) => {
+                        trace::warn!("dropping incoming wire: {}", e);
+                        let decision = Decision::Dropped(Reason::Malformed);
+                        self.table.tracer().wire(&wire, decision);
+                    }
+                }
+            }
+            // Only the peer itself completed a handshake, all other
+            // nodes get verified first.
+            self.table.add(node.clone());
+            for node in nodes {
+                self.table.propose(node);
+            }
+            let address = node.address;
+            let conn = self.open(address.clone(), socket, features, true);
+            match origin {
+                Origin::Server => {
+                    trace::info!("actaeon bootstrap completed!");
+                    self.table
+                        .events()
+                        .emit(event::Event::BootstrapCompleted(address));
+                    if self.relay {
+                        if let Err(e) = self.announce(&conn) {
+                            trace::error!("unable to register with relay: {}", e);
+                        }
+                    }
+                }
+                Origin::Reattach => match self.announce(&conn) {
+                    Ok(()) => trace::info!("registered with the relay again."),
+                    Err(e) => trace::error!("unable to register with relay: {}", e),
+                },
+                Origin::Peer(_) => {
+                    trace::info!("bootstrap through known node completed!");
+                    self.table
+                        .events()
+                        .emit(event::Event::BootstrapCompleted(address));
+                }
+            }
+            self.connections.borrow_mut().add(conn);
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::message::Message;
-    use crate::transaction::{Class, Transaction};
+impl Wake for Bell {
+    fn wake(self: Arc<Self>) {
+        let _ = self.0.wake();
+    }
 
-    #[test]
-    fn test_connection_life() {
-        let local = TcpListener::bind("127.0.0.1:45600").unwrap();
-        let stream = TcpStream::connect("127.0.0.1:45600").unwrap();
-        let addr = Address::random();
+    fn wake_by_ref(self: &Arc<Self>) {
+        let _ = self.0.wake();
+    }
+}
 
-        let message = Message::new(
-            Class::Action,
-            Address::random(),
-            Address::random(),
-            Address::random(),
-            vec![42],
+/// Registers the sockets of the Transport with the Poll of the
+/// Listener, returns false if it has none.
+#[cfg(unix)]
+fn listen(transport: &dyn Transport, registry: &Registry) -> bool {
+    let fds = transport.raw_fds();
+    for fd in &fds {
+        if let Err(e) = registry.register(&mut SourceFd(fd), SOCKET, Interest::READABLE) {
+            trace::warn!("unable to register listening socket: {}", e);
+            return false;
+        }
+    }
+    !fds.is_empty()
+}
+
+#[cfg(not(unix))]
+fn listen(_transport: &dyn Transport, _registry: &Registry) -> bool {
+    false
+}
+
+impl Handler {
+    /// Reads at most one incoming Wire, sends keep-alives and writes
+    /// at most one outgoing Wire. With coalescing all waiting Wires
+    /// get queued instead and written together once there are enough
+    /// or the first one waited too long. Should the peer not respond
+    /// or the Connection be dropped the Handler is done.
+    fn poll(&mut self) -> Poll {
+        if self.channel.is_closed() {
+            return Poll::Done;
+        }
+        let mut result = Poll::Idle;
+
+        // Incoming Wires
+        match self.socket.read_wire(self.message_size) {
+            Ok(wire) => {
+                result = Poll::Busy;
+                self.received = Instant::now();
+                if let Some((stamp, reply)) = wire.heartbeat_stamp() {
+                    if self.heartbeat(stamp, reply).is_err() {
+                        let _ = self.channel.send(Action::Shutdown);
+                        return Poll::Done;
+                    }
+                } else if !wire.is_keepalive() {
+                    if let Err(e) = self.receive(wire) {
+                        return self.violation(e);
+                    }
+                }
+            }
+            Err(e @ Error::Protocol(_)) => return self.violation(e),
+            Err(_) => {}
+        }
+
+        // Data the socket didn't accept earlier
+        if let Err(e) = self.socket.drain() {
+            trace::warn!("closing connection: {}", e);
+            let _ = self.channel.send(Action::Shutdown);
+            return Poll::Done;
+        }
+
+        // Keep-alive
+        if self.received.elapsed() >= self.timeout {
+            trace::warn!("connection timed out, peer is not responding.");
+            let _ = self.channel.send(Action::Shutdown);
+            return Poll::Done;
+        }
+        let beat = self
+            .beat
+            .is_none_or(|beat| beat.elapsed() >= self.timeout / 3);
+        if self.heartbeat && beat {
+            let stamp = self.epoch.elapsed().as_micros() as u64;
+            if self
+                .socket
+                .write_wire(&Wire::heartbeat(stamp, false))
+                .is_err()
+            {
+                let _ = self.channel.send(Action::Shutdown);
+                return Poll::Done;
+            }
+            self.beat = Some(Instant::now());
+            self.sent = Instant::now();
+        } else if self.sent.elapsed() >= self.timeout / 3 {
+            if self.socket.write_wire(&Wire::keepalive()).is_err() {
+                let _ = self.channel.send(Action::Shutdown);
+                return Poll::Done;
+            }
+            self.sent = Instant::now();
+        }
+
+        // Channel messages
+        while let Some(action) = self.channel.try_recv() {
+            result = Poll::Busy;
+            let wire = match action {
+                Action::Message(wire) => {
+                    if wire.is_empty() || self.cache.insert(&wire.uuid) {
+                        Some(wire)
+                    } else {
+                        None
+                    }
+                }
+                Action::Forward(wire) => Some(wire),
+                Action::Shutdown => {
+                    let _ = self.flush();
+                    return Poll::Done;
+                }
+                // Only ever sent to the Listener.
+                Action::Violation | Action::Heartbeat(_) => None,
+            };
+            if let Some(wire) = wire {
+                let class = wire.class();
+                if self.write(wire).is_err() {
+                    let _ = self.channel.send(Action::Shutdown);
+                    return Poll::Done;
+                }
+                self.metrics.record(Event::Sent(class));
+                self.sent = Instant::now();
+            }
+            if self.coalescer.is_none() {
+                break;
+            }
+        }
+        if let Some(coalescer) = &mut self.coalescer {
+            if let Some(wires) = coalescer.due() {
+                if self.socket.write_wires(&wires).is_err() {
+                    let _ = self.channel.send(Action::Shutdown);
+                    return Poll::Done;
+                }
+            } else if !coalescer.is_empty() {
+                // Keeps the thread from sleeping past the delay.
+                result = Poll::Busy;
+            }
+        }
+        result
+    }
+
+    /// Time until the next timer of the Handler is due, at most
+    /// IDLE_MAX if the socket isn't registered.
+    fn wait(&self) -> Duration {
+        let interval = self.timeout / 3;
+        let mut due = self.received + self.timeout;
+        if self.heartbeat {
+            due = due.min(self.beat.map_or_else(Instant::now, |beat| beat + interval));
+        } else {
+            due = due.min(self.sent + interval);
+        }
+        if let Some(coalescer) = &self.coalescer {
+            if !coalescer.is_empty() {
+                due = due.min(coalescer.since + coalescer.delay);
+            }
+        }
+        let wait = due.saturating_duration_since(Instant::now());
+        if self.registered {
+            wait
+        } else {
+            wait.min(IDLE_MAX)
+        }
+    }
+
+    /// Lets the I/O thread wait for the socket to become readable or
+    /// writable.
+    #[cfg(unix)]
+    fn register(&mut self, registry: &Registry) {
+        if let Some(fd) = self.socket.raw_fd() {
+            match registry.register(
+                &mut SourceFd(&fd),
+                SOCKET,
+                Interest::READABLE | Interest::WRITABLE,
+            ) {
+                Ok(()) => self.registered = true,
+                Err(e) => trace::warn!("unable to register socket: {}", e),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn register(&mut self, _registry: &Registry) {}
+
+    /// Removes the socket before it gets closed.
+    #[cfg(unix)]
+    fn deregister(&mut self, registry: &Registry) {
+        if let (true, Some(fd)) = (self.registered, self.socket.raw_fd()) {
+            let _ = registry.deregister(&mut SourceFd(&fd));
+            self.registered = false;
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn deregister(&mut self, _registry: &Registry) {}
+
+    /// Answers a heartbeat of the peer right away, for replies the
+    /// round-trip time is passed on to the Listener. Fails if the
+    /// reply can't be written.
+    fn heartbeat(&mut self, stamp: u64, reply: bool) -> Result<(), Error> {
+        if reply {
+            let sent = Duration::from_micros(stamp);
+            let rtt = self.epoch.elapsed().saturating_sub(sent);
+            let _ = self.channel.send(Action::Heartbeat(rtt));
+            return Ok(());
+        }
+        self.socket.write_wire(&Wire::heartbeat(stamp, true))?;
+        self.sent = Instant::now();
+        Ok(())
+    }
+
+    /// Writes the Wire, split into fragments should it be too large.
+    /// Each fragment gets compressed on its own if a threshold is
+    /// set. With coalescing they only get queued, unless enough Wires
+    /// are waiting.
+    fn write(&mut self, wire: Wire) -> Result<(), Error> {
+        let fragments = prepare(wire, self.compression)?;
+        match &mut self.coalescer {
+            Some(coalescer) => {
+                for fragment in fragments {
+                    if let Some(wires) = coalescer.push(fragment) {
+                        self.socket.write_wires(&wires)?;
+                    }
+                }
+            }
+            None => {
+                for fragment in fragments {
+                    self.socket.write_wire(&fragment)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes all queued Wires right away.
+    fn flush(&mut self) -> Result<(), Error> {
+        match &mut self.coalescer {
+            Some(coalescer) if !coalescer.is_empty() => self.socket.write_wires(&coalescer.take()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Stops the Handler because the peer violated the protocol, the
+    /// Listener drops the connection.
+    fn violation(&mut self, e: Error) -> Poll {
+        trace::warn!("closing connection: {}", e);
+        let _ = self.channel.send(Action::Violation);
+        Poll::Done
+    }
+
+    /// Decompresses, reassembles and checks the signature of an
+    /// incoming Wire and passes it on to the Listener, unless it is
+    /// already in the cache. Only protocol violations are returned,
+    /// all other invalid Wires get dropped.
+    fn receive(&mut self, mut wire: Wire) -> Result<(), Error> {
+        trace::info!("received message through existing connection.");
+        // The signature covers the original body.
+        if let Err(e) = wire.decompress() {
+            trace::warn!("dropping incoming wire: {}", e);
+            self.tracer
+                .wire(&wire, Decision::Dropped(Reason::Malformed));
+            return Ok(());
+        }
+        let wire = match self.fragments.insert(wire) {
+            Ok(Some(wire)) => wire,
+            Ok(None) => return Ok(()),
+            Err(e @ Error::Protocol(_)) => return Err(e),
+            Err(e) => {
+                trace::warn!("dropping incoming fragment: {}", e);
+                return Ok(());
+            }
+        };
+        if !wire.is_empty() {
+            if let Err(e) = self.signers.check(&wire) {
+                trace::warn!("dropping incoming wire: {}", e);
+                self.tracer
+                    .wire(&wire, Decision::Dropped(Reason::Signature));
+                return Ok(());
+            }
+        }
+        self.metrics.record(Event::Received(wire.class()));
+        if wire.is_empty() {
+            let _ = self.channel.send(Action::Message(wire));
+        } else if self.cache.insert(&wire.uuid) {
+            self.metrics.record(Event::CacheMiss);
+            let _ = self.channel.send(Action::Message(wire));
+        } else {
+            self.metrics.record(Event::CacheHit);
+            self.tracer.wire(&wire, Decision::Dropped(Reason::CacheHit));
+        }
+        Ok(())
+    }
+}
+
+/// Exchanges the handshake with the peer, the accepting side reads
+/// first. Returns the Node of the peer and the Features both sides
+/// support, incompatible peers, untrusted ones and ones without
+/// enough proof of work get rejected. Rejected incoming peers don't
+/// receive the own handshake. Should the own Hello offer the Proof
+/// Feature both sides prove that they own their Address afterwards,
+/// peers without it are rejected.
+fn exchange(
+    stream: &mut Box<dyn Stream>,
+    hello: &Hello,
+    center: &Center,
+    incoming: bool,
+    difficulty: u32,
+    trust: &dyn TrustPolicy,
+) -> Result<(Node, Features), Error> {
+    let peer = if incoming {
+        let peer = stream.read_node()?;
+        admit(&peer, &hello.node.address, difficulty, trust)?;
+        stream.write_node(hello)?;
+        peer
+    } else {
+        stream.write_node(hello)?;
+        let peer = stream.read_node()?;
+        admit(&peer, &hello.node.address, difficulty, trust)?;
+        peer
+    };
+    let features = hello.negotiate(&peer)?;
+    if hello.features.contains(Features::PROOF) {
+        if !features.contains(Features::PROOF) {
+            return Err(Error::Handshake(String::from(
+                "peer does not prove its address",
+            )));
+        }
+        stream.write_wire(&hello.prove(center, &peer)?)?;
+        hello.verify(&peer, &stream.read_wire(PROOF_SIZE)?)?;
+    }
+    trace::info!(
+        "negotiated {:?} with protocol version {}.",
+        features,
+        peer.version
+    );
+    Ok((peer.reachable(hello), features))
+}
+
+/// Checks the proof of work of the peer and if the TrustPolicy
+/// accepts it. Peers claiming the Address of the center are always
+/// rejected, they are either this node itself or an impostor.
+fn admit(
+    peer: &Hello,
+    center: &Address,
+    difficulty: u32,
+    trust: &dyn TrustPolicy,
+) -> Result<(), Error> {
+    if &peer.node.address == center {
+        return Err(Error::Handshake(String::from(
+            "peer claims the address of the center",
+        )));
+    }
+    if peer.node.address.work() < difficulty {
+        return Err(Error::Handshake(String::from(
+            "peer address does not satisfy the difficulty",
+        )));
+    }
+    if !trust.admit(&peer.node.address, peer.token.as_deref()) {
+        return Err(Error::Handshake(String::from("peer is not trusted")));
+    }
+    Ok(())
+}
+
+/// Writes the Wire, split into fragments should it be too large.
+/// Each fragment gets compressed on its own if a threshold is set.
+fn transmit(stream: &mut dyn Stream, wire: Wire, compression: Option<usize>) -> Result<(), Error> {
+    for fragment in prepare(wire, compression)? {
+        stream.write_wire(&fragment)?;
+    }
+    Ok(())
+}
+
+/// Splits the Wire into fragments should it be too large, each of
+/// them gets compressed on its own if a threshold is set.
+fn prepare(wire: Wire, compression: Option<usize>) -> Result<Vec<Wire>, Error> {
+    let mut fragments = wire.fragment()?;
+    if let Some(threshold) = compression {
+        for fragment in fragments.iter_mut() {
+            fragment.compress(threshold);
+        }
+    }
+    Ok(fragments)
+}
+
+impl Coalescer {
+    fn new(threshold: usize, delay: Duration) -> Self {
+        Self {
+            wires: Vec::new(),
+            size: 0,
+            threshold,
+            delay,
+            since: Instant::now(),
+        }
+    }
+
+    /// Queues the Wire. Once the threshold is reached all waiting
+    /// Wires are returned to be written.
+    fn push(&mut self, wire: Wire) -> Option<Vec<Wire>> {
+        if self.wires.is_empty() {
+            self.since = Instant::now();
+        }
+        self.size += wire.size();
+        self.wires.push(wire);
+        if self.size >= self.threshold {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Returns the waiting Wires if the first one waited for the
+    /// delay.
+    fn due(&mut self) -> Option<Vec<Wire>> {
+        if !self.wires.is_empty() && self.since.elapsed() >= self.delay {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Removes all waiting Wires.
+    fn take(&mut self) -> Vec<Wire> {
+        self.size = 0;
+        std::mem::take(&mut self.wires)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.wires.is_empty()
+    }
+}
+
+impl Pool {
+    /// Starts one I/O thread per available CPU, but at most WORKERS.
+    fn new() -> Result<Self, Error> {
+        let count = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1)
+            .min(WORKERS);
+        Self::with_workers(count)
+    }
+
+    fn with_workers(count: usize) -> Result<Self, Error> {
+        let workers = (0..count.max(1))
+            .map(|_| Worker::start())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            workers,
+            next: RefCell::new(0),
+        })
+    }
+
+    /// Passes the Handler to the next I/O thread and returns its
+    /// Waker, which has to be used for outgoing Actions.
+    fn spawn(&self, mut handler: Handler) -> Arc<Waker> {
+        // Otherwise the read_wire will be blocking and stall all
+        // other Handlers of the thread.
+        let _ = handler.socket.set_nonblocking(true);
+        let mut next = self.next.borrow_mut();
+        let worker = &self.workers[*next % self.workers.len()];
+        *next += 1;
+        let _ = worker.queue.send(handler);
+        let _ = worker.waker.wake();
+        worker.waker.clone()
+    }
+
+    /// Waits for all I/O threads. They only stop once all of their
+    /// Handlers are done, so the Connections have to be shut down
+    /// first.
+    fn shutdown(self) {
+        for worker in self.workers {
+            drop(worker.queue);
+            let _ = worker.waker.wake();
+            let _ = worker.thread.join();
+        }
+    }
+}
+
+impl Worker {
+    fn start() -> Result<Self, Error> {
+        let (queue, incoming) = mpsc::channel();
+        let poll = mio::Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+        let thread = thread::spawn(move || Worker::run(poll, incoming));
+        Ok(Self {
+            queue,
+            waker,
+            thread,
+        })
+    }
+
+    /// Polls all Handlers in turns. Once none of them has anything to
+    /// do the thread waits until one of the sockets becomes readable,
+    /// it gets woken up or the next timer of a Handler is due.
+    fn run(mut poll: mio::Poll, incoming: Receiver<Handler>) {
+        let mut handlers: Vec<Handler> = Vec::new();
+        let mut events = mio::Events::with_capacity(64);
+        loop {
+            let closed = loop {
+                match incoming.try_recv() {
+                    Ok(mut handler) => {
+                        handler.register(poll.registry());
+                        handlers.push(handler);
+                    }
+                    Err(TryRecvError::Empty) => break false,
+                    Err(TryRecvError::Disconnected) => break true,
+                }
+            };
+            let mut busy = false;
+            handlers.retain_mut(|handler| match handler.poll() {
+                Poll::Busy => {
+                    busy = true;
+                    true
+                }
+                Poll::Idle => true,
+                Poll::Done => {
+                    handler.deregister(poll.registry());
+                    false
+                }
+            });
+            if closed && handlers.is_empty() {
+                break;
+            }
+            if !busy {
+                let timeout = handlers.iter().map(Handler::wait).min();
+                if let Err(e) = poll.poll(&mut events, timeout) {
+                    if e.kind() != std::io::ErrorKind::Interrupted {
+                        trace::error!("unable to wait for readiness: {}", e);
+                        thread::sleep(IDLE_MAX);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Fanout {
+    /// Starts the dialing threads, at least one. They stop once the
+    /// Fanout gets dropped and their current Job is done. Dials
+    /// taking longer than the expiry count as failed. The waker gets
+    /// woken for every result.
+    fn new(
+        count: usize,
+        center: Center,
+        transport: Arc<dyn Transport>,
+        difficulty: u32,
+        metrics: Metrics,
+        expiry: Duration,
+        waker: Arc<Waker>,
+    ) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (reports, results) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..count.max(1) {
+            let queue = queue.clone();
+            let reports = reports.clone();
+            let transport = transport.clone();
+            let metrics = metrics.clone();
+            let center = center.clone();
+            let waker = waker.clone();
+            thread::spawn(move || loop {
+                let job = match queue.lock().unwrap().recv() {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+                let done = match job {
+                    Job::Dial(dial) => Done::Dialed(Fanout::connect(
+                        *dial,
+                        &center,
+                        transport.as_ref(),
+                        difficulty,
+                        &metrics,
+                    )),
+                    Job::Accept(accept) => {
+                        Done::Accepted(Fanout::handshake(accept, &center, difficulty))
+                    }
+                    Job::Bootstrap(bootstrap) => Done::Bootstrapped(Fanout::request(
+                        bootstrap,
+                        &center,
+                        transport.as_ref(),
+                        difficulty,
+                    )),
+                };
+                if reports.send(done).is_err() {
+                    break;
+                }
+                let _ = waker.wake();
+            });
+        }
+        Self {
+            jobs,
+            results,
+            dialed: Vec::new(),
+            accepted: Vec::new(),
+            bootstrapped: Vec::new(),
+            pending: HashMap::new(),
+            next: 0,
+            expiry,
+            trust: Arc::new(AllowAll),
+        }
+    }
+
+    /// Starts dialing the hop for the Node and sending it the Wire.
+    /// Should the hop already be dialed the Wire waits for that
+    /// connection instead.
+    fn dial(&mut self, hop: Address, node: Node, wire: Wire, hello: Hello) {
+        if let Some(pending) = self.pending.get_mut(&hop) {
+            pending.waiting.push(wire);
+            return;
+        }
+        let id = self.next;
+        self.next += 1;
+        let pending = Pending {
+            id,
+            address: node.address.clone(),
+            started: Instant::now(),
+            waiting: Vec::new(),
+        };
+        self.pending.insert(hop.clone(), pending);
+        let dial = Dial {
+            id,
+            hop,
+            node,
+            wire,
+            hello,
+            trust: self.trust.clone(),
+        };
+        let _ = self.jobs.send(Job::Dial(Box::new(dial)));
+    }
+
+    /// If the hop is currently being dialed.
+    fn is_pending(&self, hop: &Address) -> bool {
+        self.pending.contains_key(hop)
+    }
+
+    /// Starts the handshake of the incoming connection.
+    fn accept(&mut self, stream: Box<dyn Stream>, hello: Hello) {
+        let accept = Accept {
+            stream,
+            hello,
+            trust: self.trust.clone(),
+        };
+        let _ = self.jobs.send(Job::Accept(accept));
+    }
+
+    /// Starts bootstrapping through the node at the Link.
+    fn bootstrap(&mut self, link: Link, origin: Origin, hello: Hello, message_size: usize) {
+        let bootstrap = Bootstrap {
+            link,
+            origin,
+            hello,
+            trust: self.trust.clone(),
+            message_size,
+        };
+        let _ = self.jobs.send(Job::Bootstrap(bootstrap));
+    }
+
+    /// Returns all completed handshakes of incoming connections.
+    fn accepted(&mut self) -> Vec<Accepted> {
+        self.receive();
+        std::mem::take(&mut self.accepted)
+    }
+
+    /// Returns all completed bootstraps.
+    fn bootstrapped(&mut self) -> Vec<Bootstrapped> {
+        self.receive();
+        std::mem::take(&mut self.bootstrapped)
+    }
+
+    /// Returns all completed Dials together with the Wires that were
+    /// waiting for them. Dials taking longer than the expiry are
+    /// returned as failed with their waiting Wires, their late results
+    /// get dropped.
+    fn collect(&mut self) -> Vec<Dialed> {
+        let mut completed = Vec::new();
+        self.receive();
+        for mut dialed in std::mem::take(&mut self.dialed) {
+            match self.pending.get(&dialed.hop) {
+                Some(pending) if pending.id == dialed.id => {}
+                _ => continue,
+            }
+            if let Some(pending) = self.pending.remove(&dialed.hop) {
+                dialed.waiting = pending.waiting;
+                completed.push(dialed);
+            }
+        }
+        let expired: Vec<Address> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.started.elapsed() >= self.expiry)
+            .map(|(hop, _)| hop.clone())
+            .collect();
+        for hop in expired {
+            if let Some(pending) = self.pending.remove(&hop) {
+                completed.push(Dialed {
+                    id: pending.id,
+                    address: pending.address,
+                    hop,
+                    result: Err(Error::Timeout(String::from("peer did not answer in time"))),
+                    waiting: pending.waiting,
+                });
+            }
+        }
+        completed
+    }
+
+    /// Receives all results of the threads, they wait until their
+    /// kind gets taken.
+    fn receive(&mut self) {
+        while let Ok(done) = self.results.try_recv() {
+            match done {
+                Done::Dialed(dialed) => self.dialed.push(dialed),
+                Done::Accepted(accepted) => self.accepted.push(accepted),
+                Done::Bootstrapped(bootstrapped) => self.bootstrapped.push(bootstrapped),
+            }
+        }
+    }
+
+    /// Dials the Node and sends it the Wire, runs on the dialing
+    /// threads.
+    fn connect(
+        dial: Dial,
+        center: &Center,
+        transport: &dyn Transport,
+        difficulty: u32,
+        metrics: &Metrics,
+    ) -> Dialed {
+        let Dial {
+            id,
+            hop,
+            node,
+            wire,
+            hello,
+            trust,
+        } = dial;
+        let result = match &node.link {
+            Some(link) => transport.connect(link).and_then(|mut stream| {
+                let (peer, features) = exchange(
+                    &mut stream,
+                    &hello,
+                    center,
+                    false,
+                    difficulty,
+                    trust.as_ref(),
+                )?;
+                if peer.address != hop {
+                    return Err(Error::Handshake(String::from(
+                        "peer answered with a different address",
+                    )));
+                }
+                let class = wire.class();
+                transmit(stream.as_mut(), wire, None)?;
+                metrics.record(Event::Sent(class));
+                Ok((stream, features))
+            }),
+            None => Err(Error::Connection(String::from("no link data exists"))),
+        };
+        Dialed {
+            id,
+            hop,
+            address: node.address,
+            result,
+            waiting: Vec::new(),
+        }
+    }
+
+    /// Exchanges the handshake with the peer of an incoming
+    /// connection, runs on the dialing threads.
+    fn handshake(accept: Accept, center: &Center, difficulty: u32) -> Accepted {
+        let Accept {
+            mut stream,
+            hello,
+            trust,
+        } = accept;
+        let result = exchange(
+            &mut stream,
+            &hello,
+            center,
+            true,
+            difficulty,
+            trust.as_ref(),
+        )
+        .map(|(node, features)| (stream, node, features));
+        Accepted { result }
+    }
+
+    /// Connects to the node at the Link and requests its routing
+    /// table, runs on the dialing threads.
+    fn request(
+        bootstrap: Bootstrap,
+        center: &Center,
+        transport: &dyn Transport,
+        difficulty: u32,
+    ) -> Bootstrapped {
+        let Bootstrap {
+            link,
+            origin,
+            hello,
+            trust,
+            message_size,
+        } = bootstrap;
+        let mut wires = Vec::new();
+        let result = transport.connect(&link).and_then(|mut stream| {
+            let (node, features) = exchange(
+                &mut stream,
+                &hello,
+                center,
+                false,
+                difficulty,
+                trust.as_ref(),
+            )?;
+            stream.write_wire(&Wire::bootstrap(Vec::new()))?;
+            // The other side might already send messages through the
+            // stream before the bootstrap reply, those get handed back
+            // instead of being mistaken for the reply.
+            let mut fragments = Fragments::with_limit(message_size);
+            let reply = loop {
+                // Blocking Streams only run out of data once the read
+                // timeout expired.
+                let mut wire = stream.read_wire(message_size).map_err(|e| match e {
+                    Error::Busy(_) => Error::Timeout(String::from("bootstrap reply is missing")),
+                    e => e,
+                })?;
+                wire.decompress()?;
+                let wire = match fragments.insert(wire)? {
+                    Some(wire) => wire,
+                    None => continue,
+                };
+                if wire.is_empty() {
+                    break wire;
+                }
+                if wire.is_keepalive() {
+                    continue;
+                }
+                // The Handler of the other side starts with a heartbeat,
+                // it gets answered so its first measurement isn't lost.
+                if let Some((stamp, reply)) = wire.heartbeat_stamp() {
+                    if !reply {
+                        stream.write_wire(&Wire::heartbeat(stamp, true))?;
+                    }
+                    continue;
+                }
+                wires.push(wire);
+            };
+            let nodes = Node::from_bulk(reply.body().to_vec());
+            Ok((stream, node, features, nodes))
+        });
+        let (result, nodes) = match result {
+            Ok((stream, node, features, nodes)) => (Ok((stream, node, features)), nodes),
+            Err(e) => (Err(e), Vec::new()),
+        };
+        Bootstrapped {
+            origin,
+            result,
+            nodes,
+            wires,
+        }
+    }
+}
+
+impl ConnectionBucket {
+    /// Creates a new ConnectionBucket. The limit isn't enforced by
+    /// the Bucket itself, the Listener evicts connections before it
+    /// gets exceeded.
+    fn new(limit: usize, metrics: Metrics, events: Events, center: Address) -> Self {
+        Self {
+            connections: Vec::new(),
+            limit,
+            metrics,
+            events,
+            center,
+        }
+    }
+
+    /// Adds the connection unless there already is one to the same
+    /// Address, in which case the new one gets dropped. The only
+    /// exception are two nodes connecting to each other at the same
+    /// time, see "replaces".
+    fn add(&mut self, connection: Connection) {
+        let index = self
+            .connections
+            .iter()
+            .position(|e| e.address == connection.address);
+        match index {
+            Some(i) if self.replaces(&connection.address, connection.outbound) => {
+                trace::info!("replacing connection opened at the same time.");
+                let previous = std::mem::replace(&mut self.connections[i], connection);
+                previous.shutdown();
+            }
+            Some(_) => {}
+            None => {
+                self.events
+                    .emit(event::Event::Connected(connection.address.clone()));
+                self.connections.push(connection);
+            }
+        }
+        self.metrics.record(Event::Connections(self.len()));
+    }
+
+    /// True if a new connection to the Address would replace the
+    /// existing one. Connections get used in both directions, but two
+    /// nodes can still connect to each other at the same time. Both
+    /// then keep the connection opened by the lower Address and close
+    /// the other one.
+    fn replaces(&self, address: &Address, outbound: bool) -> bool {
+        match self.get(address) {
+            Some(existing) => existing.outbound != outbound && outbound == (&self.center < address),
+            None => false,
+        }
+    }
+
+    /// Returns a reference to a specific subscriber with a matching
+    /// Address. There isn't really a reason for an end user to use
+    /// this (but it is possible for unusual use cases). It will be
+    /// called by the "add" function.
+    fn get(&self, search: &Address) -> Option<&Connection> {
+        let index = self.connections.iter().position(|e| &e.address == search);
+        match index {
+            Some(i) => self.connections.get(i),
+            None => None,
+        }
+    }
+
+    /// Closes the connection that has been idle for the longest time
+    /// and isn't pinned, to make room for a new one. Returns false if
+    /// all connections are pinned.
+    fn evict(&mut self, pinned: &HashSet<Address>) -> bool {
+        let index = self
+            .connections
+            .iter()
+            .enumerate()
+            .filter(|(_, conn)| !pinned.contains(&conn.address))
+            .max_by_key(|(_, conn)| conn.idle())
+            .map(|(i, _)| i);
+        match index {
+            Some(i) => {
+                let conn = self.connections.remove(i);
+                trace::info!("closing idle connection to make room.");
+                self.events.emit(event::Event::Disconnected(conn.address()));
+                self.metrics.record(Event::Connections(self.len()));
+                conn.shutdown();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drops a subscriber from the Bucket should an Unsubscribe event
+    /// come in.
+    fn remove(&mut self, target: &Address) {
+        let index = self.connections.iter().position(|e| &e.address == target);
+        if let Some(i) = index {
+            // The Handler only notices the closed Channel once polled.
+            let conn = self.connections.remove(i);
+            let waker = conn.waker.clone();
+            drop(conn);
+            if let Some(waker) = waker {
+                let _ = waker.wake();
+            }
+            self.events.emit(event::Event::Disconnected(target.clone()));
+        }
+        self.metrics.record(Event::Connections(self.len()));
+    }
+
+    fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Stops all Handlers and empties the Bucket.
+    fn shutdown(&mut self) {
+        for conn in self.connections.drain(..) {
+            conn.shutdown();
+        }
+        self.metrics.record(Event::Connections(0));
+    }
+}
+
+impl Lanes {
+    /// Takes Transactions from the Channel until it is empty or the
+    /// low priority Lane is full.
+    fn fill(&mut self, channel: &Channel<Transaction>, cx: &mut Context<'_>) {
+        while self.low.len() < LANE_LIMIT {
+            match channel.recv_with(cx) {
+                Some(t) => self.push(t),
+                None => break,
+            }
+        }
+    }
+
+    fn push(&mut self, t: Transaction) {
+        match t.priority {
+            Priority::High => self.high.push_back(t),
+            Priority::Low => self.low.push_back(t),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.high.is_empty() && self.low.is_empty()
+    }
+
+    /// Returns all waiting Transactions of high priority and at most
+    /// one of low priority.
+    fn batch(&mut self) -> Vec<Transaction> {
+        let mut batch: Vec<Transaction> = self.high.drain(..).collect();
+        batch.extend(self.low.pop_front());
+        batch
+    }
+}
+
+impl Signers {
+    /// Creates the signers, required enforces signatures.
+    fn new(required: bool) -> Self {
+        Self { required }
+    }
+
+    /// Signs an outgoing Wire, but only if signatures are required
+    /// and the Wire isn't signed yet. Forwarded Wires keep the
+    /// signature of their source.
+    fn sign(&self, wire: &mut Wire, center: &Center) {
+        if self.required && wire.signature().is_none() {
+            wire.sign(center);
+        }
+    }
+
+    /// Checks an incoming Wire. The signature has to be valid and
+    /// the signing key has to belong to the Address of the source.
+    /// Unsigned Wires are only accepted if signatures aren't
+    /// required.
+    fn check(&self, wire: &Wire) -> Result<(), Error> {
+        let signature = match wire.signature() {
+            Some(signature) => signature,
+            None if self.required => {
+                return Err(Error::Crypto(String::from("wire is not signed")));
+            }
+            None => return Ok(()),
+        };
+        wire.verify()?;
+        if wire.source().is_signing_key(&signature.key()) {
+            Ok(())
+        } else {
+            Err(Error::Crypto(String::from(
+                "source was signed with a foreign key",
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::metrics::Metrics;
+    use crate::transaction::{Class, Transaction};
+    use crate::transport::{BufferedTcp, Tcp, Timeouts};
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    /// Context for receiving without ever being woken up.
+    fn noop() -> Context<'static> {
+        Context::from_waker(task::Waker::noop())
+    }
+
+    #[test]
+    fn test_connection_life() {
+        let local = TcpListener::bind("127.0.0.1:45600").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect("127.0.0.1:45600").unwrap(),
+        ));
+        let addr = Address::random();
+
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
         );
 
         let t = Transaction::new(message);
 
-        let (conn, handler) = Connection::new(addr.clone(), stream, Cache::new(100));
+        let (mut conn, handler) = Connection::new(
+            addr.clone(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_secs(60),
+            Metrics::new(),
+        );
 
-        handler.spawn();
+        let pool = Pool::with_workers(1).unwrap();
+        conn.waker = Some(pool.spawn(handler));
 
         let (mut s, _) = local.accept().unwrap();
         let _ = s.write(&t.as_bytes());
 
         std::thread::sleep(std::time::Duration::from_millis(16));
 
-        assert_eq!(conn.try_recv().unwrap(), Action::Message(t.to_wire()));
+        assert_eq!(
+            conn.recv_with(&mut noop()).unwrap(),
+            Action::Message(t.to_wire())
+        );
 
         let message = Message::new(
             Class::Action,
@@ -518,7 +2566,647 @@ mod tests {
         let t = Transaction::new(message);
         let _ = conn.send(t.to_wire());
 
-        let wire = Handler::read_wire(&mut s).unwrap();
+        let wire = BufferedTcp::new(s).read_wire(MESSAGE_SIZE).unwrap();
         assert_eq!(wire, t.to_wire());
     }
+
+    #[test]
+    fn test_connection_coalesce() {
+        let local = TcpListener::bind("127.0.0.1:45646").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect("127.0.0.1:45646").unwrap(),
+        ));
+        let (conn, mut handler) = Connection::new(
+            Address::random(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_secs(60),
+            Metrics::new(),
+        );
+        handler.socket.set_nonblocking(true).unwrap();
+        let mut s = BufferedTcp::new(local.accept().unwrap().0);
+        s.set_nonblocking(true).unwrap();
+
+        let wires: Vec<Wire> = (0..3)
+            .map(|i| {
+                let message = Message::new(
+                    Class::Action,
+                    Address::random(),
+                    Address::random(),
+                    Address::random(),
+                    vec![i],
+                );
+                Transaction::new(message).to_wire()
+            })
+            .collect();
+        let delay = Duration::from_millis(50);
+        handler.coalescer = Some(Coalescer::new(2 * wires[0].size(), delay));
+        for wire in &wires {
+            conn.send(wire.clone()).unwrap();
+        }
+
+        // The first two reach the threshold, the last one waits.
+        assert_eq!(handler.poll(), Poll::Busy);
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(s.read_wire(MESSAGE_SIZE).unwrap(), wires[0]);
+        assert_eq!(s.read_wire(MESSAGE_SIZE).unwrap(), wires[1]);
+        assert!(s.read_wire(MESSAGE_SIZE).is_err());
+        assert_eq!(handler.poll(), Poll::Busy);
+
+        std::thread::sleep(delay);
+        handler.poll();
+        std::thread::sleep(Duration::from_millis(10));
+        assert_eq!(s.read_wire(MESSAGE_SIZE).unwrap(), wires[2]);
+        assert_eq!(handler.poll(), Poll::Idle);
+    }
+
+    #[test]
+    fn test_connection_timeout() {
+        let local = TcpListener::bind("127.0.0.1:45601").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect("127.0.0.1:45601").unwrap(),
+        ));
+
+        let (mut conn, handler) = Connection::new(
+            Address::random(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_millis(300),
+            Metrics::new(),
+        );
+
+        let pool = Pool::with_workers(1).unwrap();
+        conn.waker = Some(pool.spawn(handler));
+
+        // The peer never writes anything but receives keep-alives.
+        let mut s = BufferedTcp::new(local.accept().unwrap().0);
+        let wire = s.read_wire(MESSAGE_SIZE).unwrap();
+        assert!(wire.is_keepalive());
+
+        let start = Instant::now();
+        let mut action = None;
+        while action.is_none() && start.elapsed() < Duration::from_secs(5) {
+            action = conn.recv_with(&mut noop());
+        }
+        assert_eq!(action, Some(Action::Shutdown));
+    }
+
+    #[test]
+    fn test_connection_heartbeat() {
+        let local = TcpListener::bind("127.0.0.1:45667").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect("127.0.0.1:45667").unwrap(),
+        ));
+
+        let (conn, mut handler) = Connection::new(
+            Address::random(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_secs(60),
+            Metrics::new(),
+        );
+        handler.heartbeat = true;
+        let pool = Pool::with_workers(1).unwrap();
+        let _waker = pool.spawn(handler);
+
+        // The first heartbeat gets sent right away, the reply is
+        // measured.
+        let mut s = BufferedTcp::new(local.accept().unwrap().0);
+        let wire = s.read_wire(MESSAGE_SIZE).unwrap();
+        let (stamp, reply) = wire.heartbeat_stamp().unwrap();
+        assert!(!reply);
+        std::thread::sleep(Duration::from_millis(20));
+        s.write_wire(&Wire::heartbeat(stamp, true)).unwrap();
+        let start = Instant::now();
+        let mut action = None;
+        while action.is_none() && start.elapsed() < Duration::from_secs(5) {
+            action = conn.recv_with(&mut noop());
+        }
+        match action {
+            Some(Action::Heartbeat(rtt)) => assert!(rtt >= Duration::from_millis(20)),
+            action => panic!("unexpected action: {:?}", action),
+        }
+
+        // Heartbeats of the peer are answered with the same stamp.
+        s.write_wire(&Wire::heartbeat(42, false)).unwrap();
+        let wire = s.read_wire(MESSAGE_SIZE).unwrap();
+        assert_eq!(wire.heartbeat_stamp(), Some((42, true)));
+        drop(conn);
+        pool.shutdown();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_handler_wait() {
+        let local = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect(local.local_addr().unwrap()).unwrap(),
+        ));
+        let (_conn, mut handler) = Connection::new(
+            Address::random(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_secs(60),
+            Metrics::new(),
+        );
+        // Unregistered sockets get polled, registered ones only wake
+        // the thread once readable or a timer is due.
+        assert!(handler.wait() <= IDLE_MAX);
+        let poll = mio::Poll::new().unwrap();
+        handler.register(poll.registry());
+        assert!(handler.registered);
+        assert!(handler.wait() > Duration::from_secs(10));
+        handler.deregister(poll.registry());
+        assert!(!handler.registered);
+    }
+
+    #[test]
+    fn test_listener_wait() {
+        let center = Center::generate(String::from("127.0.0.1"), 45679, 0);
+        let table = Safe::new(42, center.clone());
+        let (c1, c2) = Channel::new();
+        let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 45680);
+        let listener = Listener::new(center.clone(), c1, table, config).unwrap();
+        assert!(listener.registered);
+        let mut events = mio::Events::with_capacity(8);
+        let bell = listener.bell.clone();
+        let mut cx = Context::from_waker(&bell);
+
+        // Without anything to do it waits for an entire tick.
+        let start = Instant::now();
+        listener.wait(&mut events);
+        assert!(start.elapsed() >= TICK / 2);
+
+        // Messages of the Switch and new connections wake it up.
+        listener.lanes.borrow_mut().fill(&listener.channel, &mut cx);
+        let message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::default(),
+            vec![42],
+        );
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            c2.send(Transaction::new(message)).unwrap();
+            c2
+        });
+        let start = Instant::now();
+        listener.wait(&mut events);
+        assert!(start.elapsed() < TICK);
+        let _c2 = sender.join().unwrap();
+        listener.lanes.borrow_mut().fill(&listener.channel, &mut cx);
+        assert!(!listener.lanes.borrow().is_empty());
+
+        let _stream = TcpStream::connect("127.0.0.1:45679").unwrap();
+        let start = Instant::now();
+        listener.wait(&mut events);
+        assert!(start.elapsed() < TICK);
+    }
+
+    #[test]
+    fn test_connection_violation() {
+        let local = TcpListener::bind("127.0.0.1:45644").unwrap();
+        let stream = Box::new(BufferedTcp::new(
+            TcpStream::connect("127.0.0.1:45644").unwrap(),
+        ));
+
+        let (mut conn, mut handler) = Connection::new(
+            Address::random(),
+            stream,
+            Cache::new(100, Duration::from_secs(60)),
+            Signers::new(false),
+            Duration::from_secs(60),
+            Metrics::new(),
+        );
+        handler.message_size = 512;
+
+        let pool = Pool::with_workers(1).unwrap();
+        conn.waker = Some(pool.spawn(handler));
+
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42; 1024],
+        );
+        let t = Transaction::new(message);
+        let (mut s, _) = local.accept().unwrap();
+        let _ = s.write(&t.to_wire().as_bytes());
+
+        let start = Instant::now();
+        let mut action = None;
+        while action.is_none() && start.elapsed() < Duration::from_secs(5) {
+            action = conn.recv_with(&mut noop());
+        }
+        assert_eq!(action, Some(Action::Violation));
+    }
+
+    #[test]
+    fn test_bucket_evict() {
+        let _local = TcpListener::bind("127.0.0.1:45641").unwrap();
+        let mut bucket = ConnectionBucket::new(3, Metrics::new(), Events::new(), Address::random());
+        let mut addresses = Vec::new();
+        for idle in [30, 60, 10].iter() {
+            let stream = Box::new(BufferedTcp::new(
+                TcpStream::connect("127.0.0.1:45641").unwrap(),
+            ));
+            let (conn, _) = Connection::new(
+                Address::random(),
+                stream,
+                Cache::new(100, Duration::from_secs(60)),
+                Signers::new(false),
+                Duration::from_secs(60),
+                Metrics::new(),
+            );
+            conn.used.set(Instant::now() - Duration::from_secs(*idle));
+            addresses.push(conn.address());
+            bucket.add(conn);
+        }
+
+        // The connection idle for the longest time is pinned.
+        let mut pinned = HashSet::new();
+        pinned.insert(addresses[1].clone());
+        assert!(bucket.evict(&pinned));
+        assert_eq!(bucket.len(), 2);
+        assert!(bucket.get(&addresses[0]).is_none());
+
+        pinned.insert(addresses[2].clone());
+        assert!(!bucket.evict(&pinned));
+        assert!(bucket.evict(&HashSet::new()));
+        assert!(bucket.get(&addresses[1]).is_none());
+    }
+
+    #[test]
+    fn test_bucket_simultaneous() {
+        let _local = TcpListener::bind("127.0.0.1:45662").unwrap();
+        let center = Address::from_bytes([127; 32]);
+        let mut bucket = ConnectionBucket::new(4, Metrics::new(), Events::new(), center);
+        let connection = |address: &Address, outbound: bool| {
+            let stream = Box::new(BufferedTcp::new(
+                TcpStream::connect("127.0.0.1:45662").unwrap(),
+            ));
+            let (mut conn, _) = Connection::new(
+                address.clone(),
+                stream,
+                Cache::new(100, Duration::from_secs(60)),
+                Signers::new(false),
+                Duration::from_secs(60),
+                Metrics::new(),
+            );
+            conn.outbound = outbound;
+            conn
+        };
+
+        // The center has the lower Address and keeps its own.
+        let higher = Address::from_bytes([255; 32]);
+        bucket.add(connection(&higher, true));
+        assert!(!bucket.replaces(&higher, false));
+        bucket.add(connection(&higher, false));
+        assert!(bucket.get(&higher).unwrap().outbound);
+
+        // The peer has the lower Address, its connection is kept.
+        let lower = Address::from_bytes([0; 32]);
+        bucket.add(connection(&lower, true));
+        assert!(bucket.replaces(&lower, false));
+        bucket.add(connection(&lower, false));
+        assert!(!bucket.get(&lower).unwrap().outbound);
+        assert!(!bucket.replaces(&lower, true));
+        assert_eq!(bucket.len(), 2);
+    }
+
+    #[test]
+    fn test_lanes_priority() {
+        let (c1, c2) = Channel::new();
+        let gen_transaction = |class| {
+            Transaction::new(Message::new(
+                class,
+                Address::random(),
+                Address::random(),
+                Address::random(),
+                vec![42],
+            ))
+        };
+        let first = gen_transaction(Class::Action);
+        let second = gen_transaction(Class::Action);
+        let ping = gen_transaction(Class::Ping);
+        for t in [first.clone(), second.clone(), ping.clone()].iter() {
+            c2.send(t.clone()).unwrap();
+        }
+        let mut lanes = Lanes::default();
+        lanes.fill(&c1, &mut noop());
+        assert_eq!(lanes.batch(), vec![ping, first]);
+        assert_eq!(lanes.batch(), vec![second]);
+        assert!(lanes.batch().is_empty());
+
+        for _ in 0..LANE_LIMIT + 1 {
+            c2.send(gen_transaction(Class::Action)).unwrap();
+        }
+        lanes.fill(&c1, &mut noop());
+        assert_eq!(lanes.low.len(), LANE_LIMIT);
+        assert!(c1.try_recv().is_some());
+    }
+
+    #[test]
+    fn test_pool_shared() {
+        // A single I/O thread handles all connections.
+        let local = TcpListener::bind("127.0.0.1:45614").unwrap();
+        let pool = Pool::with_workers(1).unwrap();
+        let mut pairs = Vec::new();
+        for _ in 0..20 {
+            let stream = Box::new(BufferedTcp::new(
+                TcpStream::connect("127.0.0.1:45614").unwrap(),
+            ));
+            let (mut conn, handler) = Connection::new(
+                Address::random(),
+                stream,
+                Cache::new(100, Duration::from_secs(60)),
+                Signers::new(false),
+                Duration::from_secs(60),
+                Metrics::new(),
+            );
+            conn.waker = Some(pool.spawn(handler));
+            let s = BufferedTcp::new(local.accept().unwrap().0);
+            pairs.push((conn, s));
+        }
+        for (conn, s) in pairs.iter_mut() {
+            let t = Transaction::new(Message::new(
+                Class::Action,
+                Address::random(),
+                Address::random(),
+                Address::random(),
+                vec![42],
+            ));
+            let _ = conn.send(t.to_wire());
+            assert_eq!(s.read_wire(MESSAGE_SIZE).unwrap(), t.to_wire());
+        }
+        for (conn, _) in pairs {
+            conn.shutdown();
+        }
+        pool.shutdown();
+    }
+
+    #[test]
+    fn test_handshake_admit() {
+        let center = Center::generate(String::from("127.0.0.1"), 45660, 0);
+        let peer = Center::generate(String::from("127.0.0.1"), 45661, 0);
+        let hello = Hello::new(&peer, Features::empty());
+        assert!(admit(&hello, &center.public, 0, &AllowAll).is_ok());
+        assert!(admit(&hello, &peer.public, 0, &AllowAll).is_err());
+    }
+
+    #[test]
+    fn test_signers_source() {
+        let center = Center::generate(String::from("127.0.0.1"), 0, 0);
+        let other = Center::generate(String::from("127.0.0.1"), 0, 0);
+        let signers = Signers::new(true);
+        let gen_wire = |source: &Address| {
+            let message = Message::new(
+                Class::Action,
+                source.clone(),
+                Address::random(),
+                Address::random(),
+                vec![42],
+            );
+            Transaction::new(message).to_wire()
+        };
+        let mut wire = gen_wire(&center.public);
+        assert!(signers.check(&wire).is_err());
+        signers.sign(&mut wire, &center);
+        assert!(signers.check(&wire).is_ok());
+
+        // Signing with the own key doesn't allow claiming to be
+        // another source.
+        let mut forged = gen_wire(&other.public);
+        forged.sign(&center);
+        assert!(forged.verify().is_ok());
+        assert!(signers.check(&forged).is_err());
+    }
+
+    #[test]
+    fn test_fanout_dial() {
+        let local = TcpListener::bind("127.0.0.1:45642").unwrap();
+        let peer = Center::generate(String::from("127.0.0.1"), 45642, 0);
+        let hello = Hello::new(&peer, Features::empty());
+        let remote = std::thread::spawn(move || {
+            let mut s = BufferedTcp::new(local.accept().unwrap().0);
+            s.read_node().unwrap();
+            s.write_node(&hello).unwrap();
+            s.read_wire(MESSAGE_SIZE).unwrap()
+        });
+
+        let expiry = Timeouts::default().dial();
+        let center = Center::generate(String::from("127.0.0.1"), 45643, 0);
+        let poll = mio::Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+        let mut fanout = Fanout::new(
+            2,
+            center.clone(),
+            Arc::new(Tcp::new()),
+            0,
+            Metrics::new(),
+            expiry,
+            waker,
+        );
+        let hello = Hello::new(&center, Features::empty());
+        let wires: Vec<Wire> = (0..3)
+            .map(|body| {
+                Transaction::new(Message::new(
+                    Class::Action,
+                    center.public.clone(),
+                    peer.public.clone(),
+                    Address::random(),
+                    vec![body],
+                ))
+                .to_wire()
+            })
+            .collect();
+        let node = Node::new(peer.public.clone(), Some(peer.link.clone()));
+        fanout.dial(
+            peer.public.clone(),
+            node.clone(),
+            wires[0].clone(),
+            hello.clone(),
+        );
+        // Waits for the connection of the first Dial.
+        fanout.dial(peer.public.clone(), node, wires[1].clone(), hello.clone());
+        // Nothing listens on the port.
+        let unreachable = Node::new(Address::random(), Some(center.link.clone()));
+        fanout.dial(
+            unreachable.address.clone(),
+            unreachable,
+            wires[2].clone(),
+            hello,
+        );
+
+        let mut completed = Vec::new();
+        let started = Instant::now();
+        while completed.len() < 2 && started.elapsed() < expiry {
+            completed.append(&mut fanout.collect());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(completed.len(), 2);
+        assert!(fanout.pending.is_empty());
+        for dialed in completed {
+            if dialed.address == peer.public {
+                assert!(dialed.result.is_ok());
+                assert_eq!(dialed.waiting, vec![wires[1].clone()]);
+            } else {
+                assert!(dialed.result.is_err());
+            }
+        }
+        assert_eq!(remote.join().unwrap(), wires[0]);
+    }
+
+    #[test]
+    fn test_fanout_accept() {
+        let local = TcpListener::bind("127.0.0.1:45681").unwrap();
+        let peer = Center::generate(String::from("127.0.0.1"), 45682, 0);
+        let hello = Hello::new(&peer, Features::empty());
+        let remote = std::thread::spawn(move || {
+            let mut s = BufferedTcp::new(TcpStream::connect("127.0.0.1:45681").unwrap());
+            s.write_node(&hello).unwrap();
+            s.read_node().unwrap()
+        });
+
+        let center = Center::generate(String::from("127.0.0.1"), 45681, 0);
+        let poll = mio::Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+        let mut fanout = Fanout::new(
+            1,
+            center.clone(),
+            Arc::new(Tcp::new()),
+            0,
+            Metrics::new(),
+            Timeouts::default().dial(),
+            waker,
+        );
+        let stream = Box::new(BufferedTcp::new(local.accept().unwrap().0));
+        fanout.accept(stream, Hello::new(&center, Features::empty()));
+
+        let mut accepted = Vec::new();
+        let started = Instant::now();
+        while accepted.is_empty() && started.elapsed() < Duration::from_secs(2) {
+            accepted.append(&mut fanout.accepted());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(accepted.len(), 1);
+        let (_, node, _) = accepted.pop().unwrap().result.unwrap();
+        assert_eq!(node.address, peer.public);
+        assert_eq!(remote.join().unwrap().node.address, center.public);
+        // Other results are kept until they get collected.
+        assert!(fanout.collect().is_empty());
+    }
+
+    #[test]
+    fn test_fanout_bootstrap() {
+        let local = TcpListener::bind("127.0.0.1:45683").unwrap();
+        let peer = Center::generate(String::from("127.0.0.1"), 45683, 0);
+        let hello = Hello::new(&peer, Features::empty());
+        let known = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("127.0.0.1"), 45685)),
+        );
+        let message = Transaction::new(Message::new(
+            Class::Action,
+            peer.public.clone(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        ))
+        .to_wire();
+        let (reply, early) = (known.as_bytes(), message.clone());
+        let remote = std::thread::spawn(move || {
+            let mut s = BufferedTcp::new(local.accept().unwrap().0);
+            s.read_node().unwrap();
+            s.write_node(&hello).unwrap();
+            let request = s.read_wire(MESSAGE_SIZE).unwrap();
+            // Messages sent before the reply are handed back.
+            s.write_wire(&early).unwrap();
+            s.write_wire(&Wire::bootstrap(reply)).unwrap();
+            request
+        });
+
+        let center = Center::generate(String::from("127.0.0.1"), 45684, 0);
+        let poll = mio::Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+        let mut fanout = Fanout::new(
+            1,
+            center.clone(),
+            Arc::new(Tcp::new()),
+            0,
+            Metrics::new(),
+            Timeouts::default().dial(),
+            waker,
+        );
+        let origin = Origin::Peer(peer.public.clone());
+        fanout.bootstrap(
+            peer.link.clone(),
+            origin.clone(),
+            Hello::new(&center, Features::empty()),
+            MESSAGE_SIZE,
+        );
+
+        let mut bootstrapped = Vec::new();
+        let started = Instant::now();
+        while bootstrapped.is_empty() && started.elapsed() < Duration::from_secs(2) {
+            bootstrapped.append(&mut fanout.bootstrapped());
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(bootstrapped.len(), 1);
+        let bootstrapped = bootstrapped.pop().unwrap();
+        assert_eq!(bootstrapped.origin, origin);
+        assert_eq!(bootstrapped.wires, vec![message]);
+        assert_eq!(bootstrapped.nodes.len(), 1);
+        assert_eq!(bootstrapped.nodes[0].address, known.address);
+        let (_, node, _) = bootstrapped.result.unwrap();
+        assert_eq!(node.address, peer.public);
+        assert!(remote.join().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fanout_expired() {
+        let center = Center::generate(String::from("127.0.0.1"), 45644, 0);
+        let poll = mio::Poll::new().unwrap();
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER).unwrap());
+        let mut fanout = Fanout::new(
+            1,
+            center.clone(),
+            Arc::new(Tcp::new()),
+            0,
+            Metrics::new(),
+            Duration::ZERO,
+            waker,
+        );
+        let hello = Hello::new(&center, Features::empty());
+        let unreachable = Node::new(Address::random(), Some(center.link.clone()));
+        let hop = unreachable.address.clone();
+        fanout.dial(
+            hop.clone(),
+            unreachable.clone(),
+            Wire::keepalive(),
+            hello.clone(),
+        );
+        let waiting = Transaction::new(Message::new(
+            Class::Action,
+            center.public.clone(),
+            hop.clone(),
+            Address::random(),
+            vec![1],
+        ))
+        .to_wire();
+        fanout.dial(hop, unreachable, waiting.clone(), hello);
+
+        // The Wires of expired dials are handed back.
+        let completed = fanout.collect();
+        assert_eq!(completed.len(), 1);
+        assert!(completed[0].result.is_err());
+        assert_eq!(completed[0].waiting, vec![waiting]);
+        assert!(fanout.pending.is_empty());
+    }
 }
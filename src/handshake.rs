@@ -0,0 +1,531 @@
+//! # Handshake
+//!
+//! The first thing sent through every new connection. Both sides
+//! exchange a Hello containing the protocol version, the Features
+//! they support and their Node. Each Connection only uses the
+//! Features both sides support, nodes that still send the plain Node
+//! bytes are accepted without any Features.
+//!
+//! A Hello can carry a token for the TrustPolicy of the peer, it is
+//! appended after the Node bytes and marked with its own Feature.
+//! Nodes listening on several Links advertise the others after it,
+//! the peer keeps the one it most likely reaches.
+//!
+//! With the Proof Feature the Hello ends with a random nonce. Right
+//! after the Hellos both sides send a Proof Wire, the nonce of the
+//! other side signed with the key their Address is derived from. Only
+//! then a peer is known to own the Address it claims.
+
+use crate::error::Error;
+use crate::node::{Center, Host, Link, Node};
+use crate::transaction::{Class, Wire};
+use crate::util;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Marks the start of a versioned handshake. Plain Node bytes always
+/// start with the length of the Link, which is never this large.
+pub const MAGIC: [u8; 3] = *b"ACT";
+
+/// Current version of the protocol.
+pub const VERSION: u8 = 1;
+
+/// Version of nodes that only send their Node bytes.
+pub const LEGACY: u8 = 0;
+
+/// Length of the magic bytes, version and Features in front of the
+/// Node bytes.
+pub const PREFIX: usize = 5;
+
+/// Length of the nonce the peer has to sign.
+pub const NONCE_LENGTH: usize = 32;
+
+/// Optional parts of the protocol, stored as one bit each.
+#[derive(Clone, Copy, Default, Eq, PartialEq)]
+pub struct Features(u8);
+
+/// Contents of a handshake.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hello {
+    /// Protocol version of the sender.
+    pub version: u8,
+    /// Everything the sender supports.
+    pub features: Features,
+    /// The node of the sender.
+    pub node: Node,
+    /// Proves that the sender may join, checked by the TrustPolicy
+    /// of the peer.
+    pub token: Option<Vec<u8>>,
+    /// Further Links the sender listens on, besides the one of its
+    /// Node.
+    pub links: Vec<Link>,
+    /// Random challenge the peer signs to prove that it owns its
+    /// Address, only sent with the Proof Feature.
+    pub nonce: Option<[u8; NONCE_LENGTH]>,
+}
+
+impl Features {
+    /// Action bodies are encrypted end-to-end. Both sides have to
+    /// agree on this, otherwise they can't read each others messages.
+    pub const ENCRYPTION: Self = Self(1);
+    /// Large Wire bodies can be compressed.
+    pub const COMPRESSION: Self = Self(1 << 1);
+    /// Wires can be acknowledged by the receiver.
+    pub const ACK: Self = Self(1 << 2);
+    /// The handshake contains a token after the Node bytes.
+    pub const TOKEN: Self = Self(1 << 3);
+    /// Heartbeats measuring the round-trip time replace the
+    /// keep-alive frames.
+    pub const HEARTBEAT: Self = Self(1 << 4);
+    /// The handshake contains further Links after the token.
+    pub const LINKS: Self = Self(1 << 5);
+    /// The handshake ends with a nonce and both sides prove that they
+    /// own their Address afterwards.
+    pub const PROOF: Self = Self(1 << 6);
+
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    pub fn from_bits(bits: u8) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    /// True if all of the other Features are set.
+    pub fn contains(&self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: Self) {
+        self.0 &= !other.0;
+    }
+
+    /// Only the Features set in both.
+    pub fn intersection(&self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let names: Vec<&str> = [
+            (Self::ENCRYPTION, "encryption"),
+            (Self::COMPRESSION, "compression"),
+            (Self::ACK, "ack"),
+            (Self::TOKEN, "token"),
+            (Self::HEARTBEAT, "heartbeat"),
+            (Self::LINKS, "links"),
+            (Self::PROOF, "proof"),
+        ]
+        .iter()
+        .filter(|(feature, _)| self.contains(*feature))
+        .map(|(_, name)| *name)
+        .collect();
+        write!(f, "Features({})", names.join(", "))
+    }
+}
+
+impl Hello {
+    /// Creates the Hello of the Center with the current version. With
+    /// the Proof Feature a new nonce is generated for it.
+    pub fn new(center: &Center, features: Features) -> Self {
+        Self {
+            version: VERSION,
+            features,
+            node: Node::new(center.public.clone(), Some(center.link.clone())),
+            token: None,
+            links: Vec::new(),
+            nonce: match features.contains(Features::PROOF) {
+                true => Some(rand::random()),
+                false => None,
+            },
+        }
+    }
+
+    /// Sends the token in the handshake, if there is one.
+    pub fn with_token(mut self, token: Option<Vec<u8>>) -> Self {
+        match token {
+            Some(_) => self.features.insert(Features::TOKEN),
+            None => self.features.remove(Features::TOKEN),
+        }
+        self.token = token;
+        self
+    }
+
+    /// Advertises further Links the sender listens on.
+    pub fn with_links(mut self, links: Vec<Link>) -> Self {
+        match links.is_empty() {
+            true => self.features.remove(Features::LINKS),
+            false => self.features.insert(Features::LINKS),
+        }
+        self.links = links;
+        self
+    }
+
+    /// Magic bytes, version and Features followed by the Node bytes.
+    /// Structure of the optional token:
+    /// 2 bytes: Length,
+    /// Rest: Token
+    ///
+    /// The optional Links follow, at most 255 of them:
+    /// 1 byte: Number of Links,
+    /// Rest: Each Link with its length in front, like the token
+    ///
+    /// The optional nonce comes last:
+    /// 32 bytes: Nonce
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut features = self.features;
+        features.remove(Features::TOKEN);
+        features.remove(Features::LINKS);
+        features.remove(Features::PROOF);
+        if self.token.is_some() {
+            features.insert(Features::TOKEN);
+        }
+        if !self.links.is_empty() {
+            features.insert(Features::LINKS);
+        }
+        if self.nonce.is_some() {
+            features.insert(Features::PROOF);
+        }
+        let mut data = MAGIC.to_vec();
+        data.push(self.version);
+        data.push(features.bits());
+        data.append(&mut self.node.as_bytes());
+        if let Some(token) = &self.token {
+            data.extend_from_slice(&util::compute_length(token));
+            data.extend_from_slice(token);
+        }
+        if !self.links.is_empty() {
+            data.push(self.links.len().min(255) as u8);
+        }
+        for link in self.links.iter().take(255) {
+            let link = link.as_bytes();
+            data.extend_from_slice(&util::compute_length(&link));
+            data.extend_from_slice(&link);
+        }
+        if let Some(nonce) = &self.nonce {
+            data.extend_from_slice(nonce);
+        }
+        data
+    }
+
+    /// Parses both versioned handshakes and plain Node bytes, which
+    /// get the legacy version without any Features.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Self, Error> {
+        if data.len() > PREFIX && data[..3] == MAGIC {
+            let features = Features::from_bits(data[4]);
+            let mut node = data[PREFIX..].to_vec();
+            let mut rest = split_node(&mut node)?;
+            let token = if features.contains(Features::TOKEN) {
+                let invalid = || Error::Handshake(String::from("handshake token is not valid"));
+                Some(split_part(&mut rest).ok_or_else(invalid)?)
+            } else {
+                None
+            };
+            let mut links = Vec::new();
+            if features.contains(Features::LINKS) {
+                let invalid = || Error::Handshake(String::from("handshake links are not valid"));
+                if rest.is_empty() {
+                    return Err(invalid());
+                }
+                let count = rest.remove(0);
+                for _ in 0..count {
+                    let link = split_part(&mut rest).ok_or_else(invalid)?;
+                    links.push(Link::from_bytes(link)?);
+                }
+            }
+            let mut nonce = None;
+            if features.contains(Features::PROOF) {
+                if rest.len() < NONCE_LENGTH {
+                    return Err(Error::Handshake(String::from(
+                        "handshake nonce is not valid",
+                    )));
+                }
+                let mut bytes = [0; NONCE_LENGTH];
+                bytes.copy_from_slice(&rest[..NONCE_LENGTH]);
+                rest.drain(..NONCE_LENGTH);
+                nonce = Some(bytes);
+            }
+            if !rest.is_empty() {
+                return Err(Error::Handshake(String::from(
+                    "handshake contains unknown data",
+                )));
+            }
+            Ok(Self {
+                version: data[3],
+                features,
+                node: Node::from_bytes(node)?,
+                token,
+                links,
+                nonce,
+            })
+        } else {
+            Ok(Self {
+                version: LEGACY,
+                features: Features::empty(),
+                node: Node::from_bytes(data)?,
+                token: None,
+                links: Vec::new(),
+                nonce: None,
+            })
+        }
+    }
+
+    /// Returns the Node of the sender with the Link the receiver of
+    /// the Hello most likely reaches: The first one of the sender in
+    /// the same network as one of the own Links, otherwise the Link
+    /// of the Node. Relayed Nodes keep their Link.
+    pub fn reachable(&self, own: &Hello) -> Node {
+        let mut node = self.node.clone();
+        if self.links.is_empty() || node.link.iter().any(|link| link.relay.is_some()) {
+            return node;
+        }
+        let local: Vec<&Link> = own.node.link.iter().chain(own.links.iter()).collect();
+        let nearby = node
+            .link
+            .iter()
+            .chain(self.links.iter())
+            .find(|link| local.iter().any(|own| is_nearby(link, own)));
+        if let Some(link) = nearby {
+            node.link = Some(link.clone());
+        }
+        node
+    }
+
+    /// Creates the proof of the Center for the peer, its nonce signed
+    /// by the Center. Fails if the peer didn't send a nonce.
+    pub fn prove(&self, center: &Center, peer: &Hello) -> Result<Wire, Error> {
+        match &peer.nonce {
+            Some(nonce) => Ok(Wire::proof(center, &peer.node.address, nonce)),
+            None => Err(Error::Handshake(String::from("peer did not send a nonce"))),
+        }
+    }
+
+    /// Checks the proof of the peer: It has to contain the own nonce
+    /// and has to be signed with the key the Address of the peer is
+    /// derived from.
+    pub fn verify(&self, peer: &Hello, proof: &Wire) -> Result<(), Error> {
+        let invalid = || Error::Handshake(String::from("peer proof is not valid"));
+        let nonce = self.nonce.as_ref().ok_or_else(invalid)?;
+        if proof.class() != Some(Class::Proof)
+            || proof.body() != &nonce[..]
+            || proof.source() != peer.node.address
+            || proof.target() != self.node.address
+        {
+            return Err(invalid());
+        }
+        proof.verify().map_err(|_| invalid())?;
+        match proof.signature() {
+            Some(signature) if peer.node.address.is_signing_key(&signature.key()) => Ok(()),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Checks if the peer is compatible and returns the Features both
+    /// sides support. Legacy peers are accepted without any
+    /// Features, newer peers have to fall back to this version. Peers
+    /// that disagree on encryption get rejected.
+    pub fn negotiate(&self, peer: &Hello) -> Result<Features, Error> {
+        if peer.version == LEGACY {
+            return Ok(Features::empty());
+        }
+        if self.features.contains(Features::ENCRYPTION)
+            != peer.features.contains(Features::ENCRYPTION)
+        {
+            return Err(Error::Handshake(String::from(
+                "peer uses different encryption settings",
+            )));
+        }
+        Ok(self.features.intersection(peer.features))
+    }
+}
+
+/// Removes everything after the Node bytes and returns it.
+fn split_node(node: &mut Vec<u8>) -> Result<Vec<u8>, Error> {
+    if node.len() < 34 {
+        return Err(Error::Handshake(String::from(
+            "handshake node is not valid",
+        )));
+    }
+    let end = 34 + util::get_length(node);
+    if node.len() < end {
+        return Err(Error::Handshake(String::from(
+            "handshake node is not valid",
+        )));
+    }
+    Ok(node.split_off(end))
+}
+
+/// Removes the first part with its length in front, None if there
+/// aren't enough bytes left.
+fn split_part(data: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if data.len() < 2 {
+        return None;
+    }
+    let end = 2 + util::get_length(data);
+    if data.len() < end {
+        return None;
+    }
+    let rest = data.split_off(end);
+    let part = data[2..].to_vec();
+    *data = rest;
+    Some(part)
+}
+
+/// True if both Links are IP addresses in the same network, a /24
+/// for IPv4 and a /64 for IPv6.
+fn is_nearby(link: &Link, other: &Link) -> bool {
+    match (&link.host, &other.host) {
+        (Host::Ip(IpAddr::V4(ip)), Host::Ip(IpAddr::V4(other))) => {
+            ip.octets()[..3] == other.octets()[..3]
+        }
+        (Host::Ip(IpAddr::V6(ip)), Host::Ip(IpAddr::V6(other))) => {
+            ip.segments()[..4] == other.segments()[..4]
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Address;
+    use sodiumoxide::crypto::box_;
+
+    fn hello(features: Features) -> Hello {
+        let (_, secret) = box_::gen_keypair();
+        Hello::new(
+            &Center::new(secret, String::from("127.0.0.1"), 4242),
+            features,
+        )
+    }
+
+    #[test]
+    fn test_hello_serialize() {
+        let mut features = Features::ENCRYPTION;
+        features.insert(Features::ACK);
+        let hello = hello(features);
+        let parsed = Hello::from_bytes(hello.as_bytes()).unwrap();
+        assert_eq!(parsed, hello);
+        assert!(parsed.features.contains(Features::ACK));
+        assert!(!parsed.features.contains(Features::COMPRESSION));
+    }
+
+    #[test]
+    fn test_hello_token() {
+        let hello = hello(Features::ENCRYPTION).with_token(Some(vec![7; 300]));
+        assert!(hello.features.contains(Features::TOKEN));
+        let data = hello.as_bytes();
+        assert_eq!(Hello::from_bytes(data.clone()).unwrap(), hello);
+        assert!(Hello::from_bytes(data[..data.len() - 1].to_vec()).is_err());
+
+        let plain = hello.clone().with_token(None);
+        assert!(!plain.features.contains(Features::TOKEN));
+        assert_eq!(Hello::from_bytes(plain.as_bytes()).unwrap(), plain);
+    }
+
+    #[test]
+    fn test_hello_links() {
+        let links = vec![
+            Link::new(String::from("10.0.0.2"), 4243),
+            Link::new(String::from("example.com"), 4244),
+        ];
+        let hello = hello(Features::ENCRYPTION)
+            .with_token(Some(vec![7; 3]))
+            .with_links(links.clone());
+        assert!(hello.features.contains(Features::LINKS));
+        let data = hello.as_bytes();
+        let parsed = Hello::from_bytes(data.clone()).unwrap();
+        assert_eq!(parsed, hello);
+        assert_eq!(parsed.links, links);
+        assert!(Hello::from_bytes(data[..data.len() - 1].to_vec()).is_err());
+
+        let plain = hello.with_token(None).with_links(Vec::new());
+        assert!(!plain.features.contains(Features::LINKS));
+        assert_eq!(Hello::from_bytes(plain.as_bytes()).unwrap(), plain);
+        let mut data = plain.as_bytes();
+        data.push(0);
+        assert!(Hello::from_bytes(data).is_err());
+    }
+
+    #[test]
+    fn test_hello_reachable() {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("203.0.113.7"), 4242);
+        let lan = Link::new(String::from("192.168.1.7"), 4243);
+        let peer = Hello::new(&center, Features::empty()).with_links(vec![lan.clone()]);
+
+        // Nodes in the same LAN use the LAN Link, all others the
+        // public one.
+        let (_, secret) = box_::gen_keypair();
+        let local = Center::new(secret, String::from("198.51.100.1"), 4242);
+        let own = Hello::new(&local, Features::empty());
+        assert_eq!(peer.reachable(&own).link, Some(center.link.clone()));
+        let own = own.with_links(vec![Link::new(String::from("192.168.1.9"), 4242)]);
+        assert_eq!(peer.reachable(&own).link, Some(lan));
+        assert_eq!(peer.reachable(&own).address, center.public);
+    }
+
+    #[test]
+    fn test_hello_legacy() {
+        let node = Node::new(Address::random(), None);
+        let parsed = Hello::from_bytes(node.as_bytes()).unwrap();
+        assert_eq!(parsed.version, LEGACY);
+        assert_eq!(parsed.node, node);
+        assert_eq!(
+            hello(Features::ENCRYPTION).negotiate(&parsed).unwrap(),
+            Features::empty()
+        );
+    }
+
+    #[test]
+    fn test_hello_negotiate() {
+        let mut features = Features::ENCRYPTION;
+        features.insert(Features::COMPRESSION);
+        let local = hello(features);
+        let mut peer = hello(Features::ENCRYPTION);
+        peer.version = VERSION + 1;
+        assert_eq!(local.negotiate(&peer).unwrap(), Features::ENCRYPTION);
+        assert!(local.negotiate(&hello(Features::COMPRESSION)).is_err());
+        assert_eq!(
+            format!("{:?}", local.features),
+            "Features(encryption, compression)"
+        );
+    }
+
+    #[test]
+    fn test_hello_proof() {
+        let (_, secret) = box_::gen_keypair();
+        let alice = Center::new(secret, String::from("127.0.0.1"), 4242);
+        let (_, secret) = box_::gen_keypair();
+        let bob = Center::new(secret, String::from("127.0.0.1"), 4243);
+        let a = Hello::new(&alice, Features::PROOF);
+        let b = Hello::new(&bob, Features::PROOF);
+        assert!(a.nonce.is_some());
+        assert_eq!(Hello::from_bytes(a.as_bytes()).unwrap(), a);
+        assert!(Hello::new(&alice, Features::empty()).nonce.is_none());
+
+        let proof = b.prove(&bob, &a).unwrap();
+        assert!(a.verify(&b, &proof).is_ok());
+
+        // A proof for another nonce or from a node that only claims
+        // the Address gets rejected.
+        let other = Hello::new(&alice, Features::PROOF);
+        assert!(other.verify(&b, &proof).is_err());
+        let (_, secret) = box_::gen_keypair();
+        let mallory = Center::new(secret, String::from("127.0.0.1"), 4244);
+        let forged = b.prove(&mallory, &a).unwrap();
+        assert!(a.verify(&b, &forged).is_err());
+        assert!(b
+            .prove(&bob, &Hello::new(&alice, Features::empty()))
+            .is_err());
+    }
+}
@@ -0,0 +1,370 @@
+//! # Inbox
+//!
+//! Records retry messages in at-least-once mode only for a while,
+//! after that they are lost for subscribers that are temporarily
+//! unreachable. Subscribers can ask the Record to keep their messages
+//! instead by subscribing with a Retention. The node hosting the
+//! Record then buffers the messages it gave up on in an Inbox for
+//! each subscriber and Topic, bounded by the Retention. Once the
+//! subscriber is seen again, because it answered a Ping, opened a
+//! connection or announced its presence, the buffered messages get
+//! delivered again.
+//!
+//! The Retention is sent in front of the Policy in the Subscribe
+//! body. Inboxes only live in memory and the total size of all of
+//! them is limited by Config::inbox.
+
+use crate::delivery::Receipt;
+use crate::error::Error;
+use crate::message::Message;
+use crate::node::Address;
+use crate::record::Policy;
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+/// Marks a Subscribe body starting with a Retention, no Policy starts
+/// with it.
+const RETAINED: u8 = 0xff;
+
+/// Limits of the messages kept for a subscriber that can't be
+/// reached. Once any of them is exceeded the oldest messages get
+/// dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Maximum number of kept messages.
+    pub messages: u32,
+    /// Maximum total size of the kept messages in bytes.
+    pub size: u64,
+    /// Maximum time a message is kept.
+    pub age: Duration,
+}
+
+/// Messages Records hosted on this node gave up on, for every
+/// subscriber that asked for them to be kept.
+#[derive(Debug, Default)]
+pub struct Inbox {
+    boxes: HashMap<(Address, Address), Mailbox>,
+    /// Total size of all kept messages in bytes.
+    bytes: usize,
+    /// Maximum total size of all kept messages.
+    capacity: usize,
+}
+
+/// Kept messages of a single subscriber on a Topic, oldest first.
+#[derive(Debug)]
+struct Mailbox {
+    retention: Retention,
+    entries: VecDeque<Entry>,
+    bytes: usize,
+}
+
+/// A kept message with the Receipt it gets acknowledged with.
+#[derive(Debug)]
+struct Entry {
+    receipt: Receipt,
+    message: Message,
+    size: usize,
+    kept: Instant,
+}
+
+impl Retention {
+    pub fn new(messages: u32, size: u64, age: Duration) -> Self {
+        Self {
+            messages,
+            size,
+            age,
+        }
+    }
+
+    /// Serializes the Retention. Structure:
+    /// 4 bytes: Number of messages,
+    /// 8 bytes: Size in bytes,
+    /// 8 bytes: Age in seconds
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut data = self.messages.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.size.to_be_bytes());
+        data.extend_from_slice(&self.age.as_secs().to_be_bytes());
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() != 20 {
+            return Err(Error::Invalid(String::from("retention is not valid")));
+        }
+        let number = |range: std::ops::Range<usize>| {
+            let mut bytes = [0; 8];
+            bytes[8 - range.len()..].copy_from_slice(&data[range]);
+            u64::from_be_bytes(bytes)
+        };
+        Ok(Self::new(
+            number(0..4).try_into().unwrap_or(u32::MAX),
+            number(4..12),
+            Duration::from_secs(number(12..20)),
+        ))
+    }
+
+    /// Creates the body of a Subscribe message with the optional
+    /// Policy and Retention.
+    pub fn subscribe(policy: Option<&Policy>, retention: Option<&Retention>) -> Vec<u8> {
+        let mut body = Vec::new();
+        if let Some(retention) = retention {
+            body.push(RETAINED);
+            body.append(&mut retention.as_bytes());
+        }
+        if let Some(policy) = policy {
+            body.append(&mut policy.as_bytes());
+        }
+        body
+    }
+
+    /// Splits the body of a Subscribe message into the Retention and
+    /// the Policy, invalid ones are ignored.
+    pub fn split(body: &[u8]) -> (Option<Self>, Option<Policy>) {
+        match body.split_first() {
+            Some((&RETAINED, rest)) if rest.len() >= 20 => (
+                Self::from_bytes(&rest[..20]).ok(),
+                Policy::from_bytes(&rest[20..]).ok(),
+            ),
+            _ => (None, Policy::from_bytes(body).ok()),
+        }
+    }
+}
+
+impl Inbox {
+    /// Creates an empty Inbox, which keeps at most capacity bytes of
+    /// messages in total.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            boxes: HashMap::new(),
+            bytes: 0,
+            capacity,
+        }
+    }
+
+    /// Sets the Retention of the subscriber on the Topic, without one
+    /// its kept messages get dropped.
+    pub fn configure(
+        &mut self,
+        topic: &Address,
+        subscriber: &Address,
+        retention: Option<Retention>,
+    ) {
+        let key = (topic.clone(), subscriber.clone());
+        match retention {
+            Some(retention) => {
+                self.boxes
+                    .entry(key)
+                    .or_insert_with(|| Mailbox {
+                        retention,
+                        entries: VecDeque::new(),
+                        bytes: 0,
+                    })
+                    .retention = retention;
+            }
+            None => {
+                if let Some(mailbox) = self.boxes.remove(&key) {
+                    self.bytes -= mailbox.bytes;
+                }
+            }
+        }
+    }
+
+    /// Keeps the message for the subscriber of the Receipt, returns
+    /// false if the subscriber didn't ask for it or it exceeds the
+    /// limits. Older messages of the subscriber make room for it.
+    pub fn push(&mut self, receipt: Receipt, message: Message, now: Instant) -> bool {
+        let key = (receipt.topic.clone(), receipt.subscriber.clone());
+        let mailbox = match self.boxes.get_mut(&key) {
+            Some(mailbox) => mailbox,
+            None => return false,
+        };
+        let size = message.body.as_slice().len();
+        let retention = mailbox.retention;
+        if retention.messages == 0
+            || size as u64 > retention.size
+            || self.bytes + size > self.capacity
+        {
+            return false;
+        }
+        while mailbox.entries.len() >= retention.messages as usize
+            || (mailbox.bytes + size) as u64 > retention.size
+        {
+            match mailbox.entries.pop_front() {
+                Some(entry) => {
+                    mailbox.bytes -= entry.size;
+                    self.bytes -= entry.size;
+                }
+                None => break,
+            }
+        }
+        mailbox.bytes += size;
+        self.bytes += size;
+        mailbox.entries.push_back(Entry {
+            receipt,
+            message,
+            size,
+            kept: now,
+        });
+        true
+    }
+
+    /// Removes all kept messages of the subscriber on all Topics, its
+    /// Retentions stay.
+    pub fn take(&mut self, subscriber: &Address) -> Vec<(Receipt, Message)> {
+        let mut messages = Vec::new();
+        for ((_, address), mailbox) in self.boxes.iter_mut() {
+            if address != subscriber {
+                continue;
+            }
+            self.bytes -= mailbox.bytes;
+            mailbox.bytes = 0;
+            messages.extend(
+                mailbox
+                    .entries
+                    .drain(..)
+                    .map(|entry| (entry.receipt, entry.message)),
+            );
+        }
+        messages
+    }
+
+    /// Drops all messages kept longer than their Retention allows,
+    /// returns their number.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut expired = 0;
+        for mailbox in self.boxes.values_mut() {
+            let age = mailbox.retention.age;
+            while let Some(entry) = mailbox.entries.front() {
+                if now.duration_since(entry.kept) < age {
+                    break;
+                }
+                mailbox.bytes -= entry.size;
+                self.bytes -= entry.size;
+                mailbox.entries.pop_front();
+                expired += 1;
+            }
+        }
+        expired
+    }
+
+    /// Drops the Retentions and messages of all subscribers of the
+    /// Topic, once its Record is gone.
+    pub fn remove(&mut self, topic: &Address) {
+        let bytes = &mut self.bytes;
+        self.boxes.retain(|(address, _), mailbox| {
+            if address == topic {
+                *bytes -= mailbox.bytes;
+            }
+            address != topic
+        });
+    }
+
+    /// Number of kept messages.
+    pub fn len(&self) -> usize {
+        self.boxes
+            .values()
+            .map(|mailbox| mailbox.entries.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::Class;
+
+    fn gen_message(subscriber: &Address, size: usize) -> (Receipt, Message) {
+        let topic = Address::default();
+        let receipt = Receipt::new(topic.clone(), Address::random(), subscriber.clone(), 0);
+        let message = Message::new(
+            Class::Deliver,
+            topic.clone(),
+            subscriber.clone(),
+            topic,
+            vec![0; size],
+        );
+        (receipt, message)
+    }
+
+    #[test]
+    fn test_retention_bytes() {
+        let retention = Retention::new(8, 1024, Duration::from_secs(60));
+        assert_eq!(
+            Retention::from_bytes(&retention.as_bytes()).unwrap(),
+            retention
+        );
+        assert!(Retention::from_bytes(&[0; 19]).is_err());
+
+        let policy = Policy::Allowlist(vec![Address::random()]);
+        let body = Retention::subscribe(Some(&policy), Some(&retention));
+        assert_eq!(
+            Retention::split(&body),
+            (Some(retention), Some(policy.clone()))
+        );
+        let body = Retention::subscribe(Some(&policy), None);
+        assert_eq!(Retention::split(&body), (None, Some(policy)));
+        let body = Retention::subscribe(None, Some(&retention));
+        assert_eq!(Retention::split(&body), (Some(retention), None));
+        assert_eq!(Retention::split(&[]), (None, None));
+    }
+
+    #[test]
+    fn test_inbox_limits() {
+        let now = Instant::now();
+        let mut inbox = Inbox::new(4096);
+        let subscriber = Address::random();
+        let (receipt, message) = gen_message(&subscriber, 100);
+        assert!(!inbox.push(receipt.clone(), message.clone(), now));
+        let retention = Retention::new(2, 250, Duration::from_secs(10));
+        inbox.configure(&Address::default(), &subscriber, Some(retention));
+        for _ in 0..3 {
+            let (receipt, message) = gen_message(&subscriber, 100);
+            assert!(inbox.push(receipt, message, now));
+        }
+        assert_eq!(inbox.len(), 2);
+        let (receipt, message) = gen_message(&subscriber, 300);
+        assert!(!inbox.push(receipt, message, now));
+        let (receipt, message) = gen_message(&subscriber, 200);
+        assert!(inbox.push(receipt, message, now));
+        assert_eq!(inbox.len(), 1);
+
+        let mut full = Inbox::new(150);
+        full.configure(&Address::default(), &subscriber, Some(retention));
+        let (receipt, message) = gen_message(&subscriber, 100);
+        assert!(full.push(receipt, message, now));
+        let (receipt, message) = gen_message(&subscriber, 100);
+        assert!(!full.push(receipt, message, now));
+    }
+
+    #[test]
+    fn test_inbox_take() {
+        let now = Instant::now();
+        let mut inbox = Inbox::new(4096);
+        let subscriber = Address::random();
+        let retention = Retention::new(8, 1024, Duration::from_secs(10));
+        inbox.configure(&Address::default(), &subscriber, Some(retention));
+        let (receipt, message) = gen_message(&subscriber, 10);
+        assert!(inbox.push(receipt.clone(), message.clone(), now));
+        assert!(inbox.take(&Address::random()).is_empty());
+        assert_eq!(
+            inbox.take(&subscriber),
+            vec![(receipt.clone(), message.clone())]
+        );
+        assert!(inbox.is_empty());
+
+        assert!(inbox.push(receipt.clone(), message.clone(), now));
+        assert_eq!(inbox.expire(now + Duration::from_secs(5)), 0);
+        assert_eq!(inbox.expire(now + Duration::from_secs(10)), 1);
+        assert!(inbox.is_empty());
+
+        assert!(inbox.push(receipt.clone(), message.clone(), now));
+        inbox.remove(&Address::default());
+        assert!(inbox.is_empty());
+        assert!(!inbox.push(receipt, message, now));
+    }
+}
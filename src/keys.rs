@@ -0,0 +1,181 @@
+//! # Keys
+//!
+//! The identity of a node is its secret key, the Address is derived
+//! from it. Key files keep the identity across restarts, optionally
+//! encrypted with a passphrase. The encryption key is derived from it
+//! with Argon2id, so guessing the passphrase of a stolen file is
+//! expensive.
+//!
+//! Key files start with a magic number, the version and the mode,
+//! followed by the secret key in plain files or the salt, the nonce
+//! and the sealed secret key in encrypted ones. Files containing
+//! nothing but the 32 bytes of the key are accepted as well.
+
+use crate::error::Error;
+use sodiumoxide::crypto::box_::{self, SecretKey};
+use sodiumoxide::crypto::pwhash::argon2id13 as pwhash;
+use sodiumoxide::crypto::secretbox;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Identifies key files.
+const MAGIC: &[u8; 4] = b"ACTK";
+
+/// Current version of the key file format.
+const VERSION: u8 = 1;
+
+/// The secret key is stored as it is.
+const PLAIN: u8 = 0;
+
+/// The secret key is sealed with a key derived from a passphrase.
+const ENCRYPTED: u8 = 1;
+
+/// Length of magic number, version and mode.
+const HEADER: usize = 6;
+
+/// Encodes the secret key for storing it, it only gets encrypted if
+/// a passphrase is given.
+pub fn encode(secret: &SecretKey, passphrase: Option<&str>) -> Result<Vec<u8>, Error> {
+    let mut data = MAGIC.to_vec();
+    data.push(VERSION);
+    match passphrase {
+        Some(passphrase) => {
+            let salt = pwhash::gen_salt();
+            let nonce = secretbox::gen_nonce();
+            let key = derive(passphrase, &salt)?;
+            data.push(ENCRYPTED);
+            data.extend_from_slice(&salt.0);
+            data.extend_from_slice(&nonce.0);
+            data.append(&mut secretbox::seal(&secret.0, &nonce, &key));
+        }
+        None => {
+            data.push(PLAIN);
+            data.extend_from_slice(&secret.0);
+        }
+    }
+    Ok(data)
+}
+
+/// Restores the secret key from its encoded form. Encrypted keys
+/// fail without the correct passphrase.
+pub fn decode(data: &[u8], passphrase: Option<&str>) -> Result<SecretKey, Error> {
+    let invalid = || Error::Config(String::from("key file is not valid"));
+    if data.len() == box_::SECRETKEYBYTES {
+        return SecretKey::from_slice(data).ok_or_else(invalid);
+    }
+    if data.len() < HEADER || &data[..4] != MAGIC || data[4] != VERSION {
+        return Err(invalid());
+    }
+    let body = &data[HEADER..];
+    match data[5] {
+        PLAIN => SecretKey::from_slice(body).ok_or_else(invalid),
+        ENCRYPTED => {
+            let passphrase = passphrase
+                .ok_or_else(|| Error::Config(String::from("key file requires a passphrase")))?;
+            let split = pwhash::SALTBYTES + secretbox::NONCEBYTES;
+            if body.len() != split + box_::SECRETKEYBYTES + secretbox::MACBYTES {
+                return Err(invalid());
+            }
+            let salt = pwhash::Salt::from_slice(&body[..pwhash::SALTBYTES]).ok_or_else(invalid)?;
+            let nonce = secretbox::Nonce::from_slice(&body[pwhash::SALTBYTES..split])
+                .ok_or_else(invalid)?;
+            let key = derive(passphrase, &salt)?;
+            let secret = secretbox::open(&body[split..], &nonce, &key)
+                .map_err(|_| Error::Crypto(String::from("passphrase is not correct")))?;
+            SecretKey::from_slice(&secret).ok_or_else(invalid)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Writes the secret key to the file, replacing it should it already
+/// exist. On Unix only the owner can read it.
+pub fn save(path: &str, secret: &SecretKey, passphrase: Option<&str>) -> Result<(), Error> {
+    let data = encode(secret, passphrase)?;
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Reads the secret key from the file.
+pub fn load(path: &str, passphrase: Option<&str>) -> Result<SecretKey, Error> {
+    decode(&fs::read(path)?, passphrase)
+}
+
+/// Loads the secret key from the file, should it not exist yet a new
+/// one gets generated and stored in it. This way the identity of the
+/// node stays the same across restarts.
+pub fn persistent(path: &str, passphrase: Option<&str>) -> Result<SecretKey, Error> {
+    if Path::new(path).exists() {
+        return load(path, passphrase);
+    }
+    let (_, secret) = box_::gen_keypair();
+    save(path, &secret, passphrase)?;
+    Ok(secret)
+}
+
+/// Derives the key sealing the secret key from the passphrase.
+fn derive(passphrase: &str, salt: &pwhash::Salt) -> Result<secretbox::Key, Error> {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    pwhash::derive_key(
+        &mut key.0,
+        passphrase.as_bytes(),
+        salt,
+        pwhash::OPSLIMIT_INTERACTIVE,
+        pwhash::MEMLIMIT_INTERACTIVE,
+    )
+    .map_err(|_| Error::Crypto(String::from("unable to derive key from passphrase")))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.key", rand::random::<u64>()));
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn test_keys_encode() {
+        let (_, secret) = box_::gen_keypair();
+        let plain = encode(&secret, None).unwrap();
+        assert_eq!(decode(&plain, None).unwrap(), secret);
+        assert_eq!(decode(&plain, Some("ignored")).unwrap(), secret);
+        assert_eq!(decode(&secret.0, None).unwrap(), secret);
+        assert!(decode(&plain[..HEADER + 4], None).is_err());
+
+        let encrypted = encode(&secret, Some("actaeon")).unwrap();
+        assert!(!encrypted.windows(32).any(|w| w == &secret.0[..]));
+        assert_eq!(decode(&encrypted, Some("actaeon")).unwrap(), secret);
+        assert!(decode(&encrypted, Some("wrong")).is_err());
+        assert!(decode(&encrypted, None).is_err());
+    }
+
+    #[test]
+    fn test_keys_persistent() {
+        let path = path();
+        let secret = persistent(&path, Some("actaeon")).unwrap();
+        assert_eq!(persistent(&path, Some("actaeon")).unwrap(), secret);
+        assert_eq!(load(&path, Some("actaeon")).unwrap(), secret);
+        assert!(load(&path, None).is_err());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -8,6 +8,9 @@
 //! `Topic`. From there most interactions with other users can be done
 //! through a `Topic`.
 //!
+//! The `Interface` is async and runs the internal Switch as a tokio
+//! task, so it has to be created from within a tokio runtime.
+//!
 //! Example:
 //! ``` rust
 //! use actaeon::{
@@ -17,12 +20,13 @@
 //! };
 //! use sodiumoxide::crypto::box_;
 //!
-//! fn main() {
+//! #[tokio::main]
+//! async fn main() {
 //!     let config = Config::new(20, 1, 100, "example.com".to_string(), 4242);
 //!     let (_, secret) = box_::gen_keypair();
 //!     let center = Center::new(secret, String::from("127.0.0.1"), 1235);
 //!
-//!     let interface = Interface::new(config, center).unwrap();
+//!     let interface = Interface::new(config, center).await.unwrap();
 //!
 //!     let mut topic = interface.subscribe(&"example".to_string().to_address());
 //!
@@ -30,36 +34,87 @@
 //! }
 //! ```
 
+pub mod backend;
 pub mod bucket;
+pub mod cache;
+pub mod compression;
 pub mod config;
+pub mod connection;
+pub mod database;
+pub mod delivery;
+pub mod discovery;
 pub mod error;
+pub mod event;
+pub mod filter;
+pub mod gossip;
+pub mod group;
 pub mod handler;
+pub mod handshake;
+pub mod inbox;
+pub mod keys;
 pub mod message;
+pub mod metrics;
 pub mod node;
+pub mod offline;
+pub mod payload;
+pub mod presence;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
 pub mod record;
+pub mod retry;
 pub mod router;
+pub mod selection;
 pub mod signaling;
+pub mod status;
+pub mod storage;
 pub mod switch;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod topic;
+pub mod trace;
 pub mod transaction;
+pub mod transport;
+pub mod trust;
 pub mod util;
 
-use config::Config;
-use config::Signaling as CSig;
+use backend::Storage;
+use config::{Config, ConfigDelta, Selection, Transport, Watcher};
+use connection::{Connection, Session};
+use database::Database;
+use delivery::Outbox;
+use discovery::Discovery;
 use error::Error;
+use event::{Event, EventReceiver};
+use futures_core::Stream;
+use futures_sink::Sink;
 use handler::Listener;
 use message::Message;
+use metrics::{Metrics, Snapshot};
 use node::Address;
-pub use node::{Center, ToAddress};
-use record::RecordBucket;
-use router::Safe;
+pub use node::{Center, Node, ToAddress};
+use offline::Queue;
+use payload::Payload;
+use record::{Policy, RecordBucket, Role};
+pub use router::{BucketView, Closest, NodeView, RoutingSnapshot, Safe};
 use signaling::Signaling;
+use status::Status;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
 use switch::Switch;
-use topic::Simple;
-pub use topic::Topic;
+use topic::{Command, Delivery, Simple};
+pub use topic::{
+    Filter, Publisher, Subscription, Topic, TopicOptions, TopicPath, TopicSet, TopicStream,
+    Wildcard,
+};
+use trace::{TraceSink, Tracer};
 use transaction::Class;
 pub use transaction::Transaction;
-use util::Channel;
+use trust::TrustPolicy;
+use util::{Channel, Inlet};
 
 /// Starting the switch will create both Interface and Switch objects.
 /// The Interface will be passed up and to the user / instance. From
@@ -68,17 +123,88 @@ pub struct Interface {
     /// Center used for getting message origins. Is currently public
     /// to allow the user to read the center Address.
     pub center: Center,
-    /// Channel to communicate with the Switch. The Interface is only
-    /// connected with the Switch and none of the other threads, even
-    /// though it starts them.
+    /// Channel to communicate with the Switch. Apart from the
+    /// Sessions the Interface is only connected with the Switch and
+    /// none of the other threads, even though it starts them.
     switch: Channel<InterfaceAction>,
+    /// Handles of the started components, used to wait for them
+    /// during shutdown. They are only None after shutdown.
+    handles: Option<Handles>,
+    /// Routing table snapshot, only set if a database path is
+    /// configured.
+    database: Option<Database>,
+    /// Shared routing table, required for storing the final snapshot
+    /// during shutdown.
+    table: Safe,
+    /// Records hosted on this node, shared with the Switch.
+    records: RecordBucket,
+    /// Capacity of the Channels of new Topics, from the Config.
+    capacity: usize,
+    /// Creates the Channels of new Topics, their Commands all arrive
+    /// at the Switch through a single queue.
+    topics: Inlet<Command>,
+    /// Shared with all components, which report their Events to it.
+    metrics: Metrics,
+    /// Passes the Sessions of new direct Connections to the Listener.
+    /// This is the only Channel not going through the Switch, since
+    /// Connections bypass the routing entirely.
+    sessions: Channel<Session>,
+    /// Messages sent while offline, shared with the Switch. Only set
+    /// if the outbox is enabled.
+    offline: Option<Queue>,
+}
+
+/// Collection of the handles of all components started by the
+/// Interface.
+struct Handles {
+    /// The Switch tokio task.
+    switch: tokio::task::JoinHandle<()>,
+    /// The TCP Listener thread, it owns the I/O threads.
+    listener: thread::JoinHandle<()>,
+    /// The Signaling thread, unless it was disabled.
+    signaling: Option<thread::JoinHandle<()>>,
+    /// The task periodically storing table snapshots.
+    snapshot: Option<tokio::task::JoinHandle<()>>,
+    /// The task discovering nodes on the local network.
+    discovery: Option<tokio::task::JoinHandle<()>>,
+    /// The task serving the status endpoint.
+    status: Option<tokio::task::JoinHandle<()>>,
+}
+
+/// Composes an Interface from the Config and optional components.
+/// Without any changes it creates the same node as Interface::new:
+/// The configured Transport gets bound, the node bootstraps through
+/// the signaling server and the Signaling thread keeps the routing
+/// table and the Records up to date.
+pub struct InterfaceBuilder {
+    config: Config,
+    center: Center,
+    /// Shared with all components, a new one by default.
+    metrics: Metrics,
+    /// If the Signaling thread gets started and the node bootstraps
+    /// through the signaling server.
+    signaling: bool,
+    /// If the node bootstraps through the signaling server, the
+    /// Signaling thread is still started.
+    bootstrap: bool,
+    /// Path of the config file reloaded while running, if any.
+    watch: Option<String>,
+    /// Persists Records and the Table instead of the files of the
+    /// Config.
+    storage: Option<Arc<dyn Storage>>,
+    /// Decides which peers may connect, all of them by default.
+    trust: Option<Arc<dyn TrustPolicy>>,
+    /// Receives the decisions about all messages, disabled by
+    /// default.
+    tracer: Tracer,
 }
 
 /// Each module that wants to interact with the Switch has a custom
 /// enum of possible cases. This is to avoid having to handle a lot of
 /// impossible cases in the Switch loop.
 pub enum InterfaceAction {
-    /// Will shut down the Switch thread. (But nothing else).
+    /// Will shut down the Switch task. All other threads stop once
+    /// the Switch is no longer available.
     Shutdown,
     /// Send a complete Transaction to the Switch (and to the
     /// TcpHandler from there). The restrictions and rules described
@@ -86,73 +212,206 @@ pub enum InterfaceAction {
     Message(Transaction),
     /// Passes a new Simple (minified version of the Topic) to the
     /// Switch, from where the Subscribe info will be distributed
-    /// through the system.
-    Subscribe(Simple),
+    /// through the system. The optional Policy gets applied to the
+    /// Record, should this node own it.
+    Subscribe(Simple, Option<Policy>),
+    /// Registers a Wildcard on the Switch, from then on it receives
+    /// all messages matching its Filter that have no dedicated Topic.
+    Wildcard(Wildcard),
+    /// Starts an iterative lookup for the Address, the closest nodes
+    /// found get sent back through the Channel.
+    Lookup(Address, Channel<Vec<Node>>),
+    /// Stores the value under the key on the closest nodes for the
+    /// duration.
+    Put(Address, Vec<u8>, Duration),
+    /// Reads the value of the key from the closest nodes, it gets
+    /// sent back through the Channel.
+    Get(Address, Channel<Option<Vec<u8>>>),
+    /// Registers the handler for all incoming messages of the Custom
+    /// Class with the number, replacing any previous one.
+    Register(u16, Hook),
+    /// Applies the changed settings to the running Switch and
+    /// Listener.
+    Reconfigure(ConfigDelta),
 }
 
+/// Handler for incoming messages of a Custom Class. It is called on
+/// the Switch task, so it must not block.
+pub type Hook = Box<dyn Fn(Transaction) + Send>;
+
 impl Interface {
-    /// Currently there are no dedicated functions for creating and
-    /// starting the components. Instead this function does both:
-    ///
-    /// - It creates all the internally shared components like the
-    /// RecordBucket and the Table.
-    ///
-    /// - It creates all the thread objects required.
-    ///
-    /// - It starts all threads.
+    /// Creates and starts all components with their default settings,
+    /// see the InterfaceBuilder for what happens exactly and how to
+    /// change it.
     ///
     /// Should any of the steps fail the entire function fails, which
-    /// means the system is unable to start.
-    pub fn new(config: Config, center: Center) -> Result<Self, Error> {
-        // initialize
-        let bucket = RecordBucket::new();
-        let (switch1, switch2) = Channel::<InterfaceAction>::new();
-        let (listener1, listener2) = Channel::<Transaction>::new();
-        let (signaling1, signaling2) = Channel::<signaling::SignalingAction>::new();
-        let table = Safe::new(config.replication, center.clone());
-        let signaling = CSig::new(config.signaling, config.port);
-        let listener = Listener::new(
-            center.clone(),
-            listener1,
-            config.replication,
-            table.clone(),
-            signaling,
-        )?;
-        let switch = Switch::new(
-            listener2,
-            switch1,
-            signaling1,
-            center.clone(),
-            table.clone(),
-            bucket.clone(),
-        )?;
-        let signaling = Signaling::new(signaling2, table.clone());
+    /// means the system is unable to start. The Switch is spawned onto
+    /// the current tokio runtime, so this has to be awaited from
+    /// within one.
+    pub async fn new(config: Config, center: Center) -> Result<Self, Error> {
+        Self::builder(config, center).build().await
+    }
 
-        log::info!("actaeon is starting up!");
+    /// Same as new, but all components report to the given Metrics.
+    /// This allows forwarding all Events to a custom MetricsSink.
+    pub async fn with_metrics(
+        config: Config,
+        center: Center,
+        metrics: Metrics,
+    ) -> Result<Self, Error> {
+        Self::builder(config, center).metrics(metrics).build().await
+    }
 
-        // startup
-        listener.start();
-        switch.start();
-        signaling.start();
+    /// Starts an InterfaceBuilder, which allows selecting the
+    /// components of the node before starting it.
+    pub fn builder(config: Config, center: Center) -> InterfaceBuilder {
+        InterfaceBuilder::new(config, center)
+    }
 
-        // return
-        Ok(Self {
-            center,
-            switch: switch2,
-        })
+    /// Stops the entire system and only returns once all components
+    /// have terminated. The Switch gets shut down first, once it is
+    /// gone the Listener (and all of its connections) and the
+    /// Signaling thread stop as well. Dropping the Interface also
+    /// stops the system but without waiting for it. In both cases a
+    /// final table snapshot gets stored.
+    pub async fn shutdown(mut self) {
+        let _ = self.switch.send_async(InterfaceAction::Shutdown).await;
+        if let Some(Handles {
+            switch,
+            listener,
+            signaling,
+            snapshot,
+            discovery,
+            status,
+        }) = self.handles.take()
+        {
+            // Aborted tasks only release their sockets once they are
+            // dropped, which happens asynchronously.
+            for task in snapshot.into_iter().chain(discovery).chain(status) {
+                task.abort();
+                let _ = task.await;
+            }
+            let _ = switch.await;
+            let _ = tokio::task::spawn_blocking(move || {
+                let _ = listener.join();
+                if let Some(signaling) = signaling {
+                    let _ = signaling.join();
+                }
+            })
+            .await;
+        }
+        self.save();
+        trace::info!("actaeon has been shut down!");
     }
 
     /// Creates a new Topic, both locally, on the Switch thread and
     /// (possilby) remotely. The local topic returned contains a list
     /// of subscribers (that will get updated and refreshed on demand)
     /// as well as a Channel to the Switch. From there any updates are
-    /// processed.
+    /// processed. Should the Topic not be read the Switch drops
+    /// messages for it once its Channel is full. It returns before
+    /// the subscription reached the node hosting the Record,
+    /// Topic::wait_ready waits for its confirmation. Should the Switch
+    /// not keep up the subscription fails right away, which is
+    /// reported the same way as a rejected one.
     pub fn subscribe(&self, addr: &Address) -> Topic {
-        let (c1, c2) = Channel::new();
-        let local = Topic::new(addr.clone(), c1, Vec::new(), self.center.public.clone());
-        let remote = Simple::new(addr.clone(), c2);
-        let _ = self.switch.send(InterfaceAction::Subscribe(remote));
-        local
+        self.subscribe_topic(addr, TopicOptions::default())
+    }
+
+    /// Same as subscribe, but with TopicOptions, which can also be
+    /// just a Policy. Whoever subscribes to a Topic first becomes its
+    /// owner and only the owner can set or change the Policy, for
+    /// everybody else it gets ignored. Should the subscription be
+    /// rejected a Denied Event is emitted. Ordering and delivery
+    /// only apply to broadcasts of the returned Topic.
+    pub fn subscribe_with(&self, addr: &Address, options: impl Into<TopicOptions>) -> Topic {
+        self.subscribe_topic(addr, options.into())
+    }
+
+    /// Registers as publisher of the Topic without subscribing to it.
+    /// The Publisher can broadcast to all subscribers, but doesn't
+    /// appear in the list of subscribers and receives no messages.
+    /// Consuming a Topic without publishing works through
+    /// subscribe_with and Role::Subscriber.
+    pub fn publisher(&self, addr: &Address) -> Publisher {
+        Publisher::new(self.subscribe_topic(addr, TopicOptions::from(Role::Publisher)))
+    }
+
+    /// Subscribes to the Topic of the path. Besides its subscribers
+    /// every broadcast also reaches the subscribers of the subtrees
+    /// of the path and all of its parents.
+    pub fn subscribe_path(&self, path: &TopicPath) -> Topic {
+        let mut topic = self.subscribe(&path.address());
+        topic.path = Some(path.clone());
+        topic
+    }
+
+    /// Subscribes to the subtree of the path, which receives the
+    /// messages published on the path and all of its children. The
+    /// topic of each message is the Address of the path it was
+    /// published on. Propagated messages are not encrypted
+    /// end-to-end, the nodes hosting the subtree can read them.
+    pub fn subscribe_subtree(&self, path: &TopicPath) -> Topic {
+        self.subscribe(&path.subtree())
+    }
+
+    /// Subscribes to all of the Addresses at once, the Topics are
+    /// returned as a TopicSet. From there the messages of all Topics
+    /// can be received together, each one tagged with its Topic.
+    pub fn subscribe_many(&self, addrs: &[Address]) -> TopicSet {
+        TopicSet::new(addrs.iter().map(|addr| self.subscribe(addr)).collect())
+    }
+
+    /// Subscribes to the Topic and passes every message to the
+    /// handler, which runs on a dispatcher task of the crate instead
+    /// of the Topic being polled by the user. The handler stays
+    /// subscribed until the returned Subscription is dropped. Like
+    /// the Switch the dispatcher is spawned onto the current tokio
+    /// runtime.
+    pub fn subscribe_with_handler<F>(&self, addr: &Address, handler: F) -> Subscription
+    where
+        F: FnMut(Transaction) + Send + 'static,
+    {
+        Subscription::new(self.subscribe(addr), handler)
+    }
+
+    /// Creates a Wildcard, which receives all incoming messages with
+    /// a Topic Address matching the Filter, unless there is a
+    /// dedicated Topic for that Address. No subscription gets sent
+    /// into the network, so only messages that reach this node
+    /// anyways are caught. Fails with Busy if the Switch can't keep
+    /// up.
+    pub fn subscribe_wildcard(&self, filter: Filter) -> Result<Wildcard, Error> {
+        let (c1, c2) = Channel::bounded(self.capacity);
+        let local = Wildcard::new(filter.clone(), c1);
+        let remote = Wildcard::new(filter, c2);
+        self.switch.try_send(InterfaceAction::Wildcard(remote))?;
+        Ok(local)
+    }
+
+    /// Registers a handler for incoming messages with the Custom Class
+    /// of the number, it replaces any previous handler for it.
+    /// Messages of Custom Classes without a handler are delivered like
+    /// any other message. The handler runs on the Switch task, longer
+    /// work has to be passed on to another thread. Fails with Busy if
+    /// the Switch can't keep up.
+    pub fn register<F>(&self, number: u16, handler: F) -> Result<(), Error>
+    where
+        F: Fn(Transaction) + Send + 'static,
+    {
+        let action = InterfaceAction::Register(number, Box::new(handler));
+        self.switch.try_send(action)
+    }
+
+    /// Changes the settings of the running node without restarting
+    /// it. Only the settings present in the ConfigDelta change, it
+    /// can be created by comparing two Configs. Limits that were
+    /// lowered apply to existing connections and Cache entries, all
+    /// other settings only to new ones. Fails if any of the values is
+    /// invalid or with Busy if the Switch can't keep up.
+    pub fn reconfigure(&self, delta: ConfigDelta) -> Result<(), Error> {
+        delta.validate()?;
+        self.switch.try_send(InterfaceAction::Reconfigure(delta))
     }
 
     /// It is possible to ignore the entire PubSub architecture and
@@ -160,14 +419,15 @@ impl Interface {
     /// exact Address has to be known. From there a Transaction can be
     /// constructed and distributed through the system. This function
     /// is only recommended for specific, special reasons, otherwise
-    /// the `message` function can be used.
+    /// the `message` function can be used. Fails with Busy if the
+    /// Switch can't keep up.
     pub fn send(&self, transaction: Transaction) -> Result<(), Error> {
         let action = InterfaceAction::Message(transaction);
-        self.switch.send(action)
+        self.switch.try_send(action)
     }
 
     /// Tries to read a message from the Interface Channel without
-    /// blocking. It only returns a transaction if a Message event was
+    /// waiting. It only returns a transaction if a Message event was
     /// received, any other type will be ignored.
     pub fn try_recv(&self) -> Option<Transaction> {
         if let Some(action) = self.switch.try_recv() {
@@ -180,12 +440,23 @@ impl Interface {
         }
     }
 
-    /// Mostly the same as try_recv(), but it blocks until a Message
-    /// event is available. Should it ever return None it is likely,
-    /// that the Switch is no longer available.
-    pub fn recv(&self) -> Option<Transaction> {
+    /// Like recv, but it waits for at most the timeout. None is
+    /// returned if no Message arrived in time or the Switch is no
+    /// longer available.
+    pub async fn recv_timeout(&self, timeout: Duration) -> Option<Transaction> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Mostly the same as try_recv(), but it waits until a Message
+    /// event is available without blocking the thread. Should it ever
+    /// return None it is likely, that the Switch is no longer
+    /// available.
+    pub async fn recv(&self) -> Option<Transaction> {
         loop {
-            if let Some(action) = self.switch.recv() {
+            if let Some(action) = self.switch.recv_async().await {
                 match action {
                     InterfaceAction::Message(t) => {
                         return Some(t);
@@ -200,9 +471,81 @@ impl Interface {
         }
     }
 
+    /// Returns a Sink for sending Transactions to the Switch. Unlike
+    /// send it waits for space in the Channel instead of failing with
+    /// Busy, so it can be used at the end of async pipelines.
+    pub fn sink(&self) -> InterfaceSink<'_> {
+        InterfaceSink {
+            interface: self,
+            pending: None,
+        }
+    }
+
+    /// Runs an iterative lookup for the given Address and returns the
+    /// closest nodes found. All nodes discovered along the way get
+    /// added to the routing table. If the Switch is no longer
+    /// available an empty Vec is returned.
+    pub async fn lookup(&self, addr: &Address) -> Vec<Node> {
+        let (c1, c2) = Channel::new();
+        if self
+            .switch
+            .try_send(InterfaceAction::Lookup(addr.clone(), c2))
+            .is_err()
+        {
+            return Vec::new();
+        }
+        c1.recv_async().await.unwrap_or_default()
+    }
+
+    /// Stores the value under the key on the closest nodes to it, see
+    /// the storage module. The value is dropped after the TTL, which
+    /// is at most a day, so it has to be stored again before that.
+    /// Until then only this node can replace it. Fails with Busy if
+    /// the Switch can't keep up.
+    pub fn put(&self, key: Address, value: Vec<u8>, ttl: Duration) -> Result<(), Error> {
+        self.switch.try_send(InterfaceAction::Put(key, value, ttl))
+    }
+
+    /// Reads the value stored under the key, either locally or from
+    /// the closest nodes to it. None is returned if none of them has
+    /// it or the Switch is no longer available.
+    pub async fn get(&self, key: &Address) -> Option<Vec<u8>> {
+        let (c1, c2) = Channel::new();
+        if self
+            .switch
+            .try_send(InterfaceAction::Get(key.clone(), c2))
+            .is_err()
+        {
+            return None;
+        }
+        c1.recv_async().await.flatten()
+    }
+
+    /// Opens a direct Connection to the node with the Address, which
+    /// bypasses the routing entirely. Unknown nodes get looked up
+    /// first, should that fail so does this function with a Routing
+    /// error. With signatures enabled the peer has to prove it owns
+    /// the Address during the handshake. It gets dialed again with an
+    /// exponential backoff whenever the connection is lost, until the
+    /// Connection is dropped.
+    pub async fn connect(&self, addr: &Address) -> Result<Connection, Error> {
+        if self.table.find(addr).is_none() {
+            self.lookup(addr).await;
+        }
+        if self.table.find(addr).is_none() {
+            return Err(Error::Routing(String::from("node is unknown")));
+        }
+        let (c1, c2) = Channel::bounded(self.capacity);
+        let (connection, session) = Connection::new(addr.clone(), c1, c2);
+        self.sessions.send(session)?;
+        Ok(connection)
+    }
+
     /// Constructs a new Transaction from the provided target and body
     /// and completes the missing values. The created Transaction will
-    /// be distributed automatically.
+    /// be distributed automatically. The body can be of any size,
+    /// large ones get sent in fragments. Like send it fails with Busy
+    /// if the Switch can't keep up.
     pub fn message(&self, target: Address, body: Vec<u8>) -> Result<(), Error> {
         let message = Message::new(
             Class::Action,
@@ -212,6 +555,505 @@ impl Interface {
             body,
         );
         let action = InterfaceAction::Message(Transaction::new(message));
-        self.switch.send(action)
+        self.switch.try_send(action)
+    }
+
+    /// Encodes the value and sends it to the target like message.
+    /// Fails if the value can't be encoded.
+    pub fn message_typed<T: Payload>(&self, target: Address, value: &T) -> Result<(), Error> {
+        self.message(target, value.to_bytes()?)
+    }
+
+    /// Returns the current state of all metrics, like the size of the
+    /// routing table, the number of connections and the number of
+    /// messages sent and received.
+    pub fn metrics(&self) -> Snapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Returns a copy of the routing table with all buckets and the
+    /// details of their nodes, it can be serialized for debugging or
+    /// dashboards.
+    pub fn routing_snapshot(&self) -> RoutingSnapshot {
+        self.table.inspect()
+    }
+
+    /// Calls the visitor with a read-only copy of every bucket of the
+    /// routing table, from near to far, see Safe::for_each_bucket.
+    pub fn for_each_bucket<F>(&self, visitor: F)
+    where
+        F: FnMut(BucketView),
+    {
+        self.table.for_each_bucket(visitor)
+    }
+
+    /// Returns the messages sent while the node was offline, which are
+    /// still waiting for it to join the network, oldest first. It is
+    /// always empty unless Config::outbox is set.
+    pub fn pending(&self) -> Vec<Transaction> {
+        self.offline
+            .as_ref()
+            .map(|queue| queue.pending())
+            .unwrap_or_default()
+    }
+
+    /// Adds the nodes to the routing table, for example to warm start
+    /// a test cluster. Connections to them get opened on demand.
+    pub fn import_nodes(&self, nodes: Vec<Node>) {
+        for node in nodes {
+            self.table.add(node);
+        }
+    }
+
+    /// Returns the Addresses of all Topics whose Record is hosted on
+    /// this node.
+    pub fn hosted_topics(&self) -> Vec<Address> {
+        self.records.addresses()
+    }
+
+    /// Returns the subscribers of a Topic whose Record is hosted on
+    /// this node, None if it isn't hosted here.
+    pub fn hosted_subscribers(&self, topic: &Address) -> Option<Vec<Address>> {
+        self.records.get(topic).map(|record| record.subscribers)
+    }
+
+    /// Renders the metrics of the node in the Prometheus text format,
+    /// see the prometheus module. The status endpoint already serves
+    /// them on /metrics.
+    #[cfg(feature = "prometheus")]
+    pub fn prometheus(&self) -> String {
+        prometheus::render(&self.metrics, &self.table.inspect(), &self.records)
+    }
+
+    /// Checks if this node is responsible for the Address, meaning no
+    /// known node is closer to it. Topics with the Address have their
+    /// Record hosted here.
+    pub fn is_responsible_for(&self, address: &Address) -> bool {
+        self.table.should_be_local(address)
+    }
+
+    /// Returns the Addresses of the k closest known nodes to the
+    /// Address including this one, sorted by distance. Records are
+    /// replicated on the first ones, data placed on the same nodes
+    /// is colocated with the Topic.
+    pub fn closest(&self, address: &Address, k: usize) -> Vec<Address> {
+        self.table.replicas(address, k)
+    }
+
+    /// Iterates over all known nodes in increasing distance to the
+    /// target, for building overlays on top of the routing table. The
+    /// nodes are copied when it gets created, later changes to the
+    /// table aren't reflected.
+    pub fn iter_closest(&self, target: &Address) -> Closest {
+        self.table.closest(target)
+    }
+
+    /// Bans the Address, the node gets removed from the routing table
+    /// and its connections get dropped. Should a database be
+    /// configured the ban is stored right away, so it survives
+    /// restarts.
+    pub fn ban(&self, address: Address) {
+        self.table.ban(address);
+        self.store_banned();
+    }
+
+    /// Lifts the ban of the Address, the node has to be discovered
+    /// again.
+    pub fn unban(&self, address: &Address) {
+        self.table.unban(address);
+        self.store_banned();
+    }
+
+    /// Only accepts the given nodes from now on, all others get
+    /// removed from the routing table and their connections get
+    /// dropped. Bans still apply and the allowlist isn't stored.
+    pub fn allow_only(&self, addresses: Vec<Address>) {
+        self.table.allow_only(addresses);
+    }
+
+    /// Removes the allowlist, all nodes that aren't banned get
+    /// accepted again.
+    pub fn allow_all(&self) {
+        self.table.allow_all();
+    }
+
+    fn store_banned(&self) {
+        if let Some(database) = &self.database {
+            if let Err(e) = database.save_banned(&self.table) {
+                trace::warn!("unable to store banned addresses: {}", e);
+            }
+        }
+    }
+
+    /// Creates a new receiver for the lifecycle Events of the system,
+    /// like nodes joining the routing table or connections getting
+    /// dropped. Only Events emitted after this call are received and
+    /// receivers that can't keep up miss the oldest ones.
+    pub fn events(&self) -> EventReceiver {
+        self.table.events().subscribe()
+    }
+
+    /// Creates the local Topic and passes its Simple to the Switch.
+    fn subscribe_topic(&self, addr: &Address, options: TopicOptions) -> Topic {
+        let (c1, c2) = self
+            .topics
+            .pair(self.center.public.clone(), addr.clone(), self.capacity);
+        let mut local = Topic::new(addr.clone(), c1, Vec::new(), self.center.public.clone())
+            .with_secret(self.center.encryption());
+        local.set_ordered(options.ordered || options.delivery == Delivery::AtLeastOnce);
+        local.dissemination = options.dissemination;
+        local.role = options.role;
+        let mut remote = Simple::new(addr.clone(), c2);
+        remote.delivery = options.delivery;
+        remote.dissemination = options.dissemination;
+        remote.retention = options.retention;
+        remote.role = options.role;
+        let action = InterfaceAction::Subscribe(remote, options.policy);
+        if let Err(e) = self.switch.try_send(action) {
+            trace::warn!("unable to pass subscription on to the switch: {}", e);
+            local.fail(e);
+            self.table
+                .events()
+                .emit(Event::SubscriptionFailed(addr.clone()));
+        }
+        local
+    }
+
+    /// Stores the table snapshot once, later calls have no effect.
+    fn save(&mut self) {
+        if let Some(database) = self.database.take() {
+            if let Err(e) = database.save(&self.table) {
+                trace::warn!("unable to save table snapshot: {}", e);
+            }
+        }
+    }
+}
+
+impl Stream for Interface {
+    type Item = Transaction;
+
+    /// Same as recv, all other actions from the Switch are ignored.
+    /// The Stream ends once the Switch is no longer available.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Transaction>> {
+        loop {
+            match self.switch.poll_recv(cx) {
+                Poll::Ready(Some(InterfaceAction::Message(t))) => return Poll::Ready(Some(t)),
+                Poll::Ready(Some(_)) => continue,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Sends Transactions through the Interface, created by
+/// Interface::sink. Only one Transaction is in flight at a time, the
+/// next one is accepted once the Switch took the previous one.
+pub struct InterfaceSink<'a> {
+    interface: &'a Interface,
+    pending: Option<Pending<'a>>,
+}
+
+/// Transaction of an InterfaceSink waiting for space in the Channel.
+type Pending<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+impl InterfaceSink<'_> {
+    /// Drives the Transaction currently in flight to completion.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if let Some(pending) = &mut self.pending {
+            let result = futures_core::ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            return Poll::Ready(result);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<'a> Sink<Transaction> for InterfaceSink<'a> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, transaction: Transaction) -> Result<(), Error> {
+        let sink = self.get_mut();
+        let action = InterfaceAction::Message(transaction);
+        sink.pending = Some(Box::pin(sink.interface.switch.send_async(action)));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.get_mut().poll_pending(cx)
+    }
+}
+
+impl Drop for Interface {
+    /// Informs the Switch about the shutdown, the rest of the system
+    /// will stop by itself.
+    fn drop(&mut self) {
+        let _ = self.switch.try_send(InterfaceAction::Shutdown);
+        if let Some(handles) = &self.handles {
+            let tasks = handles.snapshot.iter().chain(&handles.discovery);
+            for task in tasks.chain(&handles.status) {
+                task.abort();
+            }
+        }
+        self.save();
+    }
+}
+
+impl InterfaceBuilder {
+    /// Starts with all components enabled, each of them configured
+    /// through the Config.
+    pub fn new(config: Config, center: Center) -> Self {
+        Self {
+            config,
+            center,
+            metrics: Metrics::new(),
+            signaling: true,
+            bootstrap: true,
+            watch: None,
+            storage: None,
+            trust: None,
+            tracer: Tracer::default(),
+        }
+    }
+
+    /// Replaces the Transport of the Config.
+    pub fn transport(mut self, transport: Transport) -> Self {
+        self.config.transport = transport;
+        self
+    }
+
+    /// Replaces the Selection of the Config.
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.config.selection = selection;
+        self
+    }
+
+    /// All components report to the given Metrics instead of a new
+    /// one, which allows forwarding all Events to a custom
+    /// MetricsSink.
+    pub fn metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Neither starts the Signaling thread nor bootstraps through the
+    /// signaling server. Without it nodes don't get pinged, buckets
+    /// and Records aren't refreshed and subscribers don't announce
+    /// their presence, so it is mostly useful for small or static
+    /// networks in combination with bootstrap_nodes.
+    pub fn disable_signaling(mut self) -> Self {
+        self.signaling = false;
+        self
+    }
+
+    /// Skips the bootstrap through the signaling server but keeps the
+    /// Signaling thread running. Useful for the first node of a
+    /// network, which other nodes use as their signaling server.
+    pub fn disable_bootstrap(mut self) -> Self {
+        self.bootstrap = false;
+        self
+    }
+
+    /// Adds the nodes to the known nodes of the Config, the node
+    /// bootstraps through all of them when it starts. Can be called
+    /// multiple times.
+    pub fn bootstrap_nodes(mut self, nodes: Vec<Node>) -> Self {
+        self.config.bootstrap.extend(nodes);
+        self
+    }
+
+    /// Watches the config file at the path while running, changes to
+    /// it get applied like the ones passed to Interface::reconfigure.
+    /// Settings that can't be changed at runtime are ignored and
+    /// invalid files are skipped until they get fixed.
+    pub fn watch(mut self, path: &str) -> Self {
+        self.watch = Some(path.to_string());
+        self
+    }
+
+    /// Persists the Records, the routing Table and the outbox through
+    /// the Storage, see the backend module. The record and database
+    /// paths of the Config are ignored then.
+    pub fn storage(mut self, storage: Arc<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Only accepts peers trusted by the policy during the handshake,
+    /// see the trust module. The token of the policy gets sent in the
+    /// own handshakes.
+    pub fn trust(mut self, trust: Arc<dyn TrustPolicy>) -> Self {
+        self.trust = Some(trust);
+        self
+    }
+
+    /// Passes a Verdict to the sink every time a message gets
+    /// dropped, forwarded or delivered, see the trace module.
+    pub fn trace(mut self, sink: Arc<dyn TraceSink>) -> Self {
+        self.tracer = Tracer::new(sink);
+        self
+    }
+
+    /// Creates and starts all selected components:
+    ///
+    /// - It validates the Config, see Config::validate.
+    ///
+    /// - It creates all the internally shared components like the
+    ///   RecordBucket and the Table. If a database is configured the
+    ///   Table gets restored from the last snapshot, the same goes
+    ///   for Records and their journal. A Storage replaces both.
+    ///   Should the snapshot contain nodes the bootstrap through the
+    ///   signaling server gets skipped.
+    ///
+    /// - It creates all the thread objects required.
+    ///
+    /// - It starts all threads.
+    ///
+    /// Should any of the steps fail the entire function fails, which
+    /// means the system is unable to start. The Switch is spawned onto
+    /// the current tokio runtime, so this has to be awaited from
+    /// within one.
+    pub async fn build(self) -> Result<Interface, Error> {
+        let Self {
+            config,
+            center,
+            metrics,
+            signaling,
+            bootstrap,
+            watch,
+            storage,
+            trust,
+            tracer,
+        } = self;
+        config.validate()?;
+        if center.public.work() < config.difficulty {
+            return Err(Error::Config(String::from(
+                "center address does not satisfy the difficulty",
+            )));
+        }
+        // initialize
+        let bucket = match (&storage, &config.records) {
+            (Some(storage), _) => RecordBucket::with_storage(storage.clone())?,
+            (None, Some(path)) => RecordBucket::open(path)?,
+            (None, None) => RecordBucket::new(),
+        };
+        let (switch1, switch2) = Channel::<InterfaceAction>::bounded(config.capacity);
+        let (listener1, listener2) = Channel::<Transaction>::bounded(config.capacity);
+        let (signaling1, signaling2) = Channel::<signaling::SignalingAction>::new();
+        let (sessions1, sessions2) = Channel::<Session>::new();
+        let (settings1, settings2) = Channel::<ConfigDelta>::new();
+        let table = Safe::with_metrics(config.replication, center.clone(), metrics.clone())
+            .with_attempts(config.attempts)
+            .with_difficulty(config.difficulty)
+            .with_depth(config.depth)
+            .with_tracer(tracer);
+        let offline = match (&storage, config.outbox) {
+            (Some(storage), Some(limit)) => Some(Queue::with_storage(
+                limit,
+                config.outbox_size,
+                storage.clone(),
+            )?),
+            (None, Some(limit)) => Some(Queue::new(limit, config.outbox_size)),
+            (_, None) => None,
+        };
+        let outbox = match &storage {
+            Some(storage) => Some(Outbox::with_storage(storage.clone())?),
+            None => None,
+        };
+        let database = match storage {
+            Some(storage) => Some(Database::with_storage(storage)),
+            None => config.database.as_deref().map(Database::new),
+        };
+        if let Some(database) = &database {
+            database.restore(&table);
+        }
+        let mut switch = Switch::new(
+            listener2,
+            switch1,
+            signaling1,
+            center.clone(),
+            table.clone(),
+            bucket.clone(),
+            &config,
+        )?
+        .with_settings(settings2);
+        if let Some(path) = watch {
+            switch = switch.with_watcher(Watcher::new(&path)?);
+        }
+        if let Some(queue) = &offline {
+            switch = switch.with_offline(queue.clone());
+        }
+        if let Some(outbox) = outbox {
+            switch = switch.with_outbox(outbox);
+        }
+        let signaling = signaling.then(|| Signaling::new(signaling2, table.clone(), &config));
+        let discovery = match config.discovery {
+            Some(port) => Some(Discovery::new(&center, table.clone(), port)?),
+            None => None,
+        };
+        // Nodes restored from the snapshot replace the bootstrap
+        // through the signaling server, unless it is also the relay.
+        let restored = database.is_some() && !table.is_empty();
+        let bootstrap = signaling.is_some() && bootstrap && (!restored || config.relay);
+        let status = match config.status {
+            Some(port) => Some(Status::new(
+                table.clone(),
+                metrics.clone(),
+                bucket.clone(),
+                &config.status_address,
+                port,
+                bootstrap,
+            )?),
+            None => None,
+        };
+        let capacity = config.capacity;
+        let topics = switch.inlet();
+        let mut listener = Listener::new(center.clone(), listener1, table.clone(), config)?
+            .with_sessions(sessions1)
+            .with_settings(settings1);
+        if let Some(trust) = trust {
+            listener = listener.with_trust(trust);
+        }
+        if signaling.is_none() {
+            switch = switch.without_signaling();
+        }
+        if !bootstrap {
+            listener = listener.without_bootstrap();
+        }
+
+        trace::info!("actaeon is starting up!");
+
+        // startup
+        let handles = Handles {
+            listener: listener.start(),
+            switch: switch.start(),
+            signaling: signaling.map(|signaling| signaling.start()),
+            snapshot: database.clone().map(|db| db.start(table.clone())),
+            discovery: discovery.map(|discovery| discovery.start()),
+            status: status.map(|status| status.start()),
+        };
+
+        // return
+        Ok(Interface {
+            capacity,
+            topics,
+            metrics,
+            sessions: sessions2,
+            center,
+            switch: switch2,
+            handles: Some(handles),
+            database,
+            table,
+            records: bucket,
+            offline,
+        })
     }
 }
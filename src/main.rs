@@ -0,0 +1,197 @@
+//! # actaeon-node
+//!
+//! Runs a standalone node, for example as the signaling server of a
+//! network. The node is configured through the same toml files the
+//! library reads: The system config and the center config, which
+//! points to the key file holding the identity of the node. It runs
+//! until it receives SIGINT, then it shuts down gracefully.
+//!
+//! Only built with the "cli" feature:
+//!
+//! ``` text
+//! cargo run --features cli --bin actaeon-node -- --config config.toml
+//! ```
+
+use actaeon::config::{CenterConfig, Config};
+use actaeon::error::Error;
+use actaeon::node::Center;
+use actaeon::Interface;
+use std::env;
+use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: actaeon-node [OPTIONS]
+
+Options:
+    --config <PATH>    System config file [default: config.toml]
+    --center <PATH>    Center config file [default: center.toml]
+    --key <PATH>       Key file, overrides the one of the center config
+    --no-bootstrap     Don't bootstrap through the signaling server,
+                       for the first node of a network
+    --relay            Register with the signaling server as the relay,
+                       for nodes that aren't publicly reachable
+    --stats <SECONDS>  Interval of the statistics, 0 disables them
+                       [default: 60]
+    --help             Print this message
+
+The key file gets created on the first start. It is encrypted with
+the passphrase in ACTAEON_PASSPHRASE, if set.";
+
+/// Options parsed from the command line.
+#[derive(Debug, PartialEq)]
+struct Options {
+    config: String,
+    center: String,
+    key: Option<String>,
+    no_bootstrap: bool,
+    relay: bool,
+    stats: u64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config: String::from("config.toml"),
+            center: String::from("center.toml"),
+            key: None,
+            no_bootstrap: false,
+            relay: false,
+            stats: 60,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments without the name of the binary, None if
+    /// only the usage was requested.
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut options = Self::default();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("{} requires a value", arg))
+            };
+            match arg.as_str() {
+                "--config" => options.config = value()?,
+                "--center" => options.center = value()?,
+                "--key" => options.key = Some(value()?),
+                "--stats" => {
+                    options.stats = value()?
+                        .parse()
+                        .map_err(|_| String::from("--stats must be a number of seconds"))?;
+                }
+                "--no-bootstrap" => options.no_bootstrap = true,
+                "--relay" => options.relay = true,
+                "--help" | "-h" => return Ok(None),
+                _ => return Err(format!("unknown argument {}", arg)),
+            }
+        }
+        Ok(Some(options))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("actaeon-node: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(options).await {
+        eprintln!("actaeon-node: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Starts the node and keeps it running until SIGINT.
+async fn run(options: Options) -> Result<(), Error> {
+    let mut config = Config::from_file(&options.config)?;
+    config.relay |= options.relay;
+    let mut center = CenterConfig::from_file(&options.center)?;
+    if let Some(key) = options.key {
+        center.key = Some(key);
+    }
+    center.passphrase = env::var("ACTAEON_PASSPHRASE").ok();
+    let center = Center::from_config(center)?;
+    println!("starting node {} on {}", center.public, center.link);
+
+    let mut builder = Interface::builder(config, center);
+    if options.no_bootstrap {
+        builder = builder.disable_bootstrap();
+    }
+    let interface = builder.build().await?;
+
+    // A zero interval would panic, disabled statistics wait forever.
+    let period = Duration::from_secs(options.stats.max(1));
+    let mut stats = tokio::time::interval(period);
+    stats.tick().await;
+    loop {
+        tokio::select! {
+            signal = tokio::signal::ctrl_c() => {
+                signal?;
+                break;
+            }
+            _ = stats.tick(), if options.stats > 0 => print(&interface),
+        }
+    }
+    println!("shutting down");
+    interface.shutdown().await;
+    Ok(())
+}
+
+/// Prints the peers, Topics and traffic of the node.
+fn print(interface: &Interface) {
+    let metrics = interface.metrics();
+    let nodes: Vec<_> = interface
+        .routing_snapshot()
+        .buckets
+        .into_iter()
+        .flat_map(|bucket| bucket.nodes)
+        .collect();
+    let reachable = nodes.iter().filter(|node| node.reachable).count();
+    println!(
+        "peers: {} known, {} reachable, {} connected | topics: {} hosted | \
+         wires: {} received, {} sent | messages: {} delivered, {} dropped",
+        nodes.len(),
+        reachable,
+        metrics.connections,
+        interface.hosted_topics().len(),
+        metrics.received,
+        metrics.sent,
+        metrics.delivered,
+        metrics.dropped,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_options_parse() {
+        assert_eq!(parse(&[]).unwrap(), Some(Options::default()));
+        let options = parse(&["--config", "a.toml", "--key", "key", "--no-bootstrap"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.config, "a.toml");
+        assert_eq!(options.key, Some(String::from("key")));
+        assert!(options.no_bootstrap && !options.relay);
+        assert_eq!(parse(&["--stats", "0"]).unwrap().unwrap().stats, 0);
+        assert_eq!(parse(&["--help"]).unwrap(), None);
+        assert!(parse(&["--stats", "soon"]).is_err());
+        assert!(parse(&["--config"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+    }
+}
@@ -10,6 +10,7 @@ use crate::error::Error;
 use crate::node::Address;
 use crate::node::Center;
 use crate::transaction::Class;
+use bytes::Bytes;
 use sodiumoxide::crypto::box_::{self, curve25519xsalsa20poly1305::Nonce};
 
 /// Represents a single message, but not the Wire format. It will
@@ -36,6 +37,9 @@ pub struct Message {
     /// a vector of bytes. This might later get replaced by a trait
     /// object to allow for smarter custom data formats.
     pub body: Body,
+    /// Position of the message among all messages of its source on
+    /// the Topic, only set by Topics in ordered mode.
+    pub sequence: Option<u64>,
 }
 
 /// The actual body of the messages usually is just binarry data, but
@@ -46,8 +50,9 @@ pub struct Body {
     /// Usually this is always true, unless specifically set
     /// otherwise (by encrypting the data).
     is_plain: bool,
-    /// The actual body data.
-    bytes: Vec<u8>,
+    /// The actual body data, shared with the Wires it was parsed
+    /// from or gets sent in.
+    bytes: Bytes,
 }
 
 /// The sodiumoxide Nonce does not support the same functions as are
@@ -73,6 +78,7 @@ impl Message {
             topic,
             seed: Seed::new(box_::gen_nonce()),
             body: Body::new(body),
+            sequence: None,
         }
     }
 
@@ -83,7 +89,7 @@ impl Message {
         target: Address,
         topic: Address,
         seed: Seed,
-        body: Vec<u8>,
+        body: impl Into<Bytes>,
     ) -> Self {
         Self {
             class,
@@ -92,13 +98,14 @@ impl Message {
             topic,
             seed,
             body: Body::new(body),
+            sequence: None,
         }
     }
 
     /// Encrypt the message using the secret of the center (this
     /// node) and the PublicKey of the target.
     pub fn encrypt(&mut self, center: &Center) {
-        self.body.encrypt(&self.seed, &center, &self.target);
+        self.body.encrypt(&self.seed, center, &self.target);
     }
 
     /// This function does not do the opposite of "encrypt". To get
@@ -106,7 +113,16 @@ impl Message {
     /// target node and the public key of the source node have to be
     /// used.
     pub fn decrypt(&mut self, center: &Center) -> Result<(), Error> {
-        self.body.decrypt(&self.seed, &center, &self.source)
+        self.body.decrypt(&self.seed, center, &self.source)
+    }
+
+    /// Messages parsed from the Wire don't know if their body is
+    /// encrypted or not. This function assumes it is and decrypts it
+    /// the same way as "decrypt". It fails if the body wasn't
+    /// encrypted for this node by the source.
+    pub fn open(&mut self, center: &Center) -> Result<(), Error> {
+        self.body.is_plain = false;
+        self.decrypt(center)
     }
 
     pub fn len(&self) -> [u8; 2] {
@@ -116,16 +132,27 @@ impl Message {
 
 impl Body {
     /// Creates the body with the given bytes.
-    pub fn new(bytes: Vec<u8>) -> Self {
+    pub fn new(bytes: impl Into<Bytes>) -> Self {
         Self {
             is_plain: true,
-            bytes,
+            bytes: bytes.into(),
         }
     }
 
-    /// Returns the bytes currently in the body without changing the
-    /// encryption.
+    /// Returns a copy of the bytes currently in the body without
+    /// changing the encryption.
     pub fn as_bytes(&self) -> Vec<u8> {
+        self.bytes.to_vec()
+    }
+
+    /// Returns the bytes currently in the body without copying them.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes currently in the body, they are shared
+    /// instead of copied.
+    pub fn to_bytes(&self) -> Bytes {
         self.bytes.clone()
     }
 
@@ -135,8 +162,8 @@ impl Body {
     /// valid, no it is possible to loose data by encrypting it.
     fn encrypt(&mut self, seed: &Seed, center: &Center, target: &Address) {
         if self.is_plain {
-            let enc = box_::seal(&self.bytes, &seed.0, &target.key, &center.secret);
-            self.bytes = enc;
+            let enc = box_::seal(&self.bytes, &seed.0, &target.key, &center.encryption());
+            self.bytes = Bytes::from(enc);
             self.is_plain = false;
         }
     }
@@ -148,8 +175,9 @@ impl Body {
     /// otherwise an error will be returned.
     fn decrypt(&mut self, seed: &Seed, center: &Center, source: &Address) -> Result<(), Error> {
         if !self.is_plain {
-            let dec = box_::open(&self.bytes, &seed.0, &source.key, &center.secret)?;
-            self.bytes = dec;
+            let dec = box_::open(&self.bytes, &seed.0, &source.key, &center.encryption())
+                .map_err(|_| Error::Crypto(String::from("body can't be decrypted")))?;
+            self.bytes = Bytes::from(dec);
             self.is_plain = true;
             Ok(())
         } else {
@@ -188,10 +216,10 @@ impl Seed {
     /// and populating it with elements from the Nonce.
     pub fn as_bytes(&self) -> [u8; 24] {
         let mut bytes: [u8; 24] = [0; 24];
-        for (i, j) in self.0.as_ref().into_iter().enumerate() {
+        for (i, j) in self.0.as_ref().iter().enumerate() {
             bytes[i] = *j;
         }
-        return bytes;
+        bytes
     }
 }
 
@@ -222,22 +250,51 @@ mod tests {
     }
     #[test]
     fn test_message_decrypt() {
-        let (theirpk, theirsk) = box_::gen_keypair();
-        let (ourpk, oursk) = box_::gen_keypair();
+        let theircenter = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let ourcenter = Center::new(box_::gen_keypair().1, String::from(""), 0);
         let mut m = Message::new(
             Class::Ping,
-            Address::new(theirpk),
-            Address::new(ourpk),
+            theircenter.public.clone(),
+            ourcenter.public.clone(),
             Address::random(),
             [111, 42].to_vec(),
         );
-        let theircenter = Center::new(theirsk, String::from(""), 0);
-        let ourcenter = Center::new(oursk, String::from(""), 0);
         m.encrypt(&theircenter);
         m.decrypt(&ourcenter).unwrap();
         assert_eq!(m.body.as_bytes(), [111, 42]);
     }
 
+    #[test]
+    fn test_message_open_wire() {
+        let theircenter = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let ourcenter = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let mut m = Message::new(
+            Class::Action,
+            theircenter.public.clone(),
+            ourcenter.public.clone(),
+            Address::random(),
+            [111, 42].to_vec(),
+        );
+        m.encrypt(&theircenter);
+        let t = crate::transaction::Transaction::new(m);
+        let mut parsed = crate::transaction::Transaction::from_wire(&t.to_wire()).unwrap();
+        parsed.message.open(&ourcenter).unwrap();
+        assert_eq!(parsed.message.body.as_bytes(), [111, 42]);
+    }
+
+    #[test]
+    fn test_message_open_invalid() {
+        let mut m = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            [111, 42].to_vec(),
+        );
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        assert!(m.open(&center).is_err());
+    }
+
     #[test]
     fn test_length_empty() {
         let body = Body::new(Vec::new());
@@ -0,0 +1,313 @@
+//! # Metrics
+//!
+//! Hooks for observing the system from the outside. All instrumented
+//! components share a single Metrics handle, which keeps a set of
+//! atomic counters and forwards every Event to an optional custom
+//! MetricsSink.
+
+use crate::transaction::Class;
+#[cfg(feature = "prometheus")]
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "prometheus")]
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Receives every Event of the system. Implementations get called
+/// from multiple threads and the Switch task, so they should never
+/// block.
+pub trait MetricsSink: Send + Sync {
+    fn record(&self, event: &Event);
+}
+
+/// Everything that gets reported by the instrumented components.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The number of Nodes in the routing table changed.
+    Table(usize),
+    /// The number of open connections changed.
+    Connections(usize),
+    /// A Wire was received from another node, None if its Class
+    /// isn't valid.
+    Received(Option<Class>),
+    /// A Wire was sent to another node, None if its Class isn't
+    /// valid.
+    Sent(Option<Class>),
+    /// A message was passed on to the user or a Topic.
+    Delivered,
+    /// A message was dropped because a Channel was full.
+    Dropped,
+    /// A Wire was already in the Transaction cache.
+    CacheHit,
+    /// A Wire was not yet in the Transaction cache.
+    CacheMiss,
+}
+
+/// Default MetricsSink, it simply keeps the current value of every
+/// metric in an atomic counter.
+#[derive(Debug)]
+pub struct Counters {
+    table: AtomicUsize,
+    connections: AtomicUsize,
+    received: AtomicU64,
+    sent: AtomicU64,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    /// Received and sent Wires of each Class, Custom Classes are
+    /// counted together. Only kept for the exporter, since it is
+    /// locked for every Wire.
+    #[cfg(feature = "prometheus")]
+    classes: Mutex<HashMap<Class, (u64, u64)>>,
+    /// Time the counters were created, used for computing rates.
+    started: Instant,
+}
+
+/// Copy of all counters at a single point in time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// Number of Nodes in the routing table.
+    pub table: usize,
+    /// Number of open connections.
+    pub connections: usize,
+    /// Total number of Wires received from other nodes.
+    pub received: u64,
+    /// Total number of Wires sent to other nodes.
+    pub sent: u64,
+    /// Total number of messages passed to the user or a Topic.
+    pub delivered: u64,
+    /// Total number of messages dropped because of full Channels.
+    pub dropped: u64,
+    /// Total number of Wires that were already in the cache.
+    pub cache_hits: u64,
+    /// Total number of Wires that were not yet in the cache.
+    pub cache_misses: u64,
+    /// Time since the counters were created.
+    pub elapsed: Duration,
+}
+
+/// Shared handle used by all instrumented components. Cloning it is
+/// cheap and all clones report to the same counters and sink.
+#[derive(Clone)]
+pub struct Metrics {
+    counters: Arc<Counters>,
+    sink: Option<Arc<dyn MetricsSink>>,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self {
+            table: AtomicUsize::new(0),
+            connections: AtomicUsize::new(0),
+            received: AtomicU64::new(0),
+            sent: AtomicU64::new(0),
+            delivered: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            #[cfg(feature = "prometheus")]
+            classes: Mutex::new(HashMap::new()),
+            started: Instant::now(),
+        }
+    }
+
+    /// Reads all counters. They are read one after another, so the
+    /// Snapshot might be slightly inconsistent under load.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            table: self.table.load(Ordering::Relaxed),
+            connections: self.connections.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            delivered: self.delivered.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            cache_hits: self.hits.load(Ordering::Relaxed),
+            cache_misses: self.misses.load(Ordering::Relaxed),
+            elapsed: self.started.elapsed(),
+        }
+    }
+
+    /// Returns the number of received and sent Wires of every Class
+    /// seen so far.
+    #[cfg(feature = "prometheus")]
+    pub fn classes(&self) -> Vec<(Class, u64, u64)> {
+        let classes = self.classes.lock().unwrap();
+        classes
+            .iter()
+            .map(|(class, (received, sent))| (class.clone(), *received, *sent))
+            .collect()
+    }
+
+    #[cfg(feature = "prometheus")]
+    fn count(&self, class: &Option<Class>, received: bool) {
+        let class = match class {
+            Some(Class::Custom(_)) => Class::Custom(0),
+            Some(class) => class.clone(),
+            None => return,
+        };
+        let mut classes = self.classes.lock().unwrap();
+        let entry = classes.entry(class).or_insert((0, 0));
+        match received {
+            true => entry.0 += 1,
+            false => entry.1 += 1,
+        }
+    }
+}
+
+impl Default for Counters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricsSink for Counters {
+    fn record(&self, event: &Event) {
+        match event {
+            Event::Table(size) => self.table.store(*size, Ordering::Relaxed),
+            Event::Connections(count) => self.connections.store(*count, Ordering::Relaxed),
+            Event::Received(_class) => {
+                self.received.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "prometheus")]
+                self.count(_class, true);
+            }
+            Event::Sent(_class) => {
+                self.sent.fetch_add(1, Ordering::Relaxed);
+                #[cfg(feature = "prometheus")]
+                self.count(_class, false);
+            }
+            Event::Delivered => {
+                self.delivered.fetch_add(1, Ordering::Relaxed);
+            }
+            Event::Dropped => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Event::CacheHit => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+            }
+            Event::CacheMiss => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl Snapshot {
+    /// Average number of Wires sent and received per second since
+    /// the counters were created.
+    pub fn messages_per_second(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 {
+            return 0.0;
+        }
+        (self.received + self.sent) as f64 / seconds
+    }
+
+    /// Share of Wires that were already in the cache, zero if no
+    /// Wire has been checked yet.
+    pub fn cache_hit_rate(&self) -> f64 {
+        let total = self.cache_hits + self.cache_misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.cache_hits as f64 / total as f64
+    }
+}
+
+impl Metrics {
+    /// Creates a new handle that only keeps the default counters.
+    pub fn new() -> Self {
+        Self {
+            counters: Arc::new(Counters::new()),
+            sink: None,
+        }
+    }
+
+    /// Creates a new handle that also forwards every Event to the
+    /// custom sink.
+    pub fn with_sink(sink: Arc<dyn MetricsSink>) -> Self {
+        Self {
+            counters: Arc::new(Counters::new()),
+            sink: Some(sink),
+        }
+    }
+
+    /// Updates the counters and passes the Event on to the sink.
+    pub fn record(&self, event: Event) {
+        self.counters.record(&event);
+        if let Some(sink) = &self.sink {
+            sink.record(&event);
+        }
+    }
+
+    /// Returns the current state of the counters.
+    pub fn snapshot(&self) -> Snapshot {
+        self.counters.snapshot()
+    }
+
+    /// Returns the received and sent Wires of every Class.
+    #[cfg(feature = "prometheus")]
+    pub fn classes(&self) -> Vec<(Class, u64, u64)> {
+        self.counters.classes()
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Metrics({:?})", self.counters.snapshot())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collector(Mutex<Vec<Event>>);
+
+    impl MetricsSink for Collector {
+        fn record(&self, event: &Event) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_metrics_counters() {
+        let metrics = Metrics::new();
+        let clone = metrics.clone();
+        metrics.record(Event::Table(4));
+        metrics.record(Event::Table(3));
+        clone.record(Event::Received(Some(Class::Ping)));
+        clone.record(Event::CacheHit);
+        clone.record(Event::CacheMiss);
+        clone.record(Event::CacheMiss);
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.table, 3);
+        assert_eq!(snapshot.received, 1);
+        assert_eq!(snapshot.sent, 0);
+        assert!((snapshot.cache_hit_rate() - 1.0 / 3.0).abs() < f64::EPSILON);
+        assert!(snapshot.messages_per_second() > 0.0);
+    }
+
+    #[test]
+    fn test_metrics_sink() {
+        let collector = Arc::new(Collector::default());
+        let metrics = Metrics::with_sink(collector.clone());
+        metrics.record(Event::Connections(1));
+        metrics.record(Event::Dropped);
+        assert_eq!(
+            *collector.0.lock().unwrap(),
+            vec![Event::Connections(1), Event::Dropped]
+        );
+        assert_eq!(metrics.snapshot().dropped, 1);
+    }
+}
@@ -18,12 +18,23 @@
 
 use crate::config::CenterConfig;
 use crate::error::Error;
+use crate::keys;
+use crate::trace;
 use crate::util;
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use sodiumoxide::crypto::box_;
 use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::{PublicKey, SecretKey};
+use sodiumoxide::crypto::sign;
 use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, ToSocketAddrs};
 use std::ops::BitXor;
-use std::time::SystemTime;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
 /// Represents a singe Node in the system. It simply stores the
 /// (optional) connection details, the routing Address and a
@@ -36,6 +47,9 @@ pub struct Node {
     timestamp: SystemTime,
     pub address: Address,
     pub link: Option<Link>,
+    /// Smoothed round-trip time of the connection to the Node, only
+    /// kept in memory.
+    rtt: Option<Duration>,
 }
 
 /// Config for self / this node, currently as part of the Node module,
@@ -47,8 +61,9 @@ pub struct Center {
     /// The public key / address of this node / self, which gets
     /// automatically generated from the secret key.
     pub public: Address,
-    /// The base of the entire object / center calculation. It has to
-    /// be stored for encyption but should never be read by anybody
+    /// The base of the entire object / center calculation. It is the
+    /// seed of the signing keys, the encryption keys and the Address
+    /// are derived from those. It should never be read by anybody
     /// except for the crypto module.
     pub secret: SecretKey,
     /// The time this node was started, used to compare values in the
@@ -80,14 +95,15 @@ pub struct Address {
 /// periodically until the node has been reached or the number of
 /// attempts exceeds a set maximum.
 ///
-/// Currently only IPV4 is supported, but this will have to be updated
-/// as soon as possible. Any given IP address must be publicly
-/// reachable, proxy modes are not yet supported.
+/// The host can be an IPv4 or IPv6 address or a hostname, which only
+/// gets resolved once a connection is established. Nodes that aren't
+/// publicly reachable use a relayed Link, which contains the details
+/// of their relay and its Address.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Link {
-    /// IPV4 connection details which will be used by the TCP system
-    /// to establish a direct connections.
-    pub ip: String,
+    /// Connection details which will be used by the TCP system to
+    /// establish a direct connections.
+    pub host: Host,
     /// The port could be represented as just a u16 but is currently
     /// unlimited, since it does not get verified as an acutally
     /// possible port.
@@ -97,12 +113,48 @@ pub struct Link {
     /// nodes. Changing it requires the node to be mutable, this might
     /// get replaced by interior mutability in the future.
     pub reachable: bool,
-    /// Stores the nuber of attemps that have been made to connect to
-    /// a node. Once it exceeds a limit the link / node will be
-    /// discarded.
+    /// Stores the number of failed attempts in a row to reach the
+    /// node. Once it reaches the limit of the routing table the node
+    /// gets discarded.
     attempts: usize,
+    /// Set if the node is only reachable through the relay with this
+    /// Address, host and port are the ones of the relay.
+    pub relay: Option<Address>,
 }
 
+/// The host part of a Link. IP addresses are stored parsed, anything
+/// else is treated as a hostname.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Host {
+    /// IPv4 or IPv6 address, it can be used without any lookups.
+    Ip(IpAddr),
+    /// DNS name, it gets resolved every time a connection is
+    /// established, so changes are picked up without updating the
+    /// Link.
+    Name(String),
+}
+
+/// First byte of serialized Links. The old format started directly
+/// with the UTF-8 encoded IP, which can never contain this byte, so
+/// both formats can be told apart.
+const LINK_VERSION: u8 = 0xff;
+
+/// Type byte following the version for hostnames.
+const LINK_NAME: u8 = 0;
+
+/// Type byte following the version for IPv4 addresses.
+const LINK_IPV4: u8 = 4;
+
+/// Type byte following the version for IPv6 addresses.
+const LINK_IPV6: u8 = 6;
+
+/// Type byte following the version for relayed Links.
+const LINK_RELAY: u8 = 1;
+
+/// Every new round-trip time sample makes up this fraction of the
+/// smoothed one, same as for the TCP SRTT.
+const RTT_WEIGHT: u32 = 8;
+
 impl Node {
     /// Creates a new Node with the current timestamp. The Link can be
     /// None but should be provided.
@@ -111,9 +163,16 @@ impl Node {
             address,
             timestamp: SystemTime::now(),
             link,
+            rtt: None,
         }
     }
 
+    /// Time since the Node was created, for Nodes in the routing table
+    /// this is the time since they were first added.
+    pub fn age(&self) -> Duration {
+        self.timestamp.elapsed().unwrap_or_default()
+    }
+
     /// Returns the link status of a node. Should no link be available
     /// it is treated as if the node is unavailable.
     pub fn is_reachable(&self) -> bool {
@@ -123,24 +182,31 @@ impl Node {
         }
     }
 
+    /// Number of failed attempts in a row to reach the node, always
+    /// zero without a link.
+    pub fn failures(&self) -> usize {
+        self.link.as_ref().map_or(0, Link::failures)
+    }
+
+    /// Smoothed round-trip time of the connection to the node, None
+    /// until it was measured.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Adds a round-trip time sample, the first one is taken as is.
+    pub fn measure(&mut self, sample: Duration) {
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => (rtt * (RTT_WEIGHT - 1) + sample) / RTT_WEIGHT,
+            None => sample,
+        });
+    }
+
     /// Update the link status of a node even if there is no link
     /// available.
     pub fn update(&mut self, value: bool) {
-        match &mut self.link {
-            Some(link) => link.update(value),
-            None => {}
-        }
-    }
-
-    /// A shorthand for a (mostly useless) empty zero Node with an
-    /// invalid timestamp.
-    pub fn default() -> Node {
-        let bytes = [0; 32];
-        let address = Address::from_bytes(bytes);
-        Node {
-            address,
-            link: None,
-            timestamp: SystemTime::UNIX_EPOCH,
+        if let Some(link) = &mut self.link {
+            link.update(value)
         }
     }
 
@@ -152,29 +218,29 @@ impl Node {
                 let mut data = util::compute_length(&link).to_vec();
                 data.append(&mut self.address.as_bytes().to_vec());
                 data.append(&mut link);
-                return data;
+                data
             }
             None => {
                 let mut data = vec![0, 0];
                 data.append(&mut self.address.as_bytes().to_vec());
-                return data;
+                data
             }
         }
     }
 
-    /// Turns the bytes back into a Node object. Currently this
-    /// function can't fail, if the given data is invalid the default
-    /// (empty) Node gets returned.
+    /// Turns the bytes back into a Node object. It fails if the
+    /// Address is incomplete or zero or the length in front doesn't
+    /// match the Link.
     pub fn from_bytes(mut bytes: Vec<u8>) -> Result<Node, Error> {
-        if bytes.len() < 32 {
-            Err(Error::Invalid(String::from("node address is not valid")))
+        let node = if bytes.len() < 32 {
+            return Err(Error::Invalid(String::from("node address is not valid")));
         } else if bytes.len() == 32 {
             let address = Address::from_slice(&bytes)?;
-            Ok(Node::new(address, None))
+            Node::new(address, None)
         } else if bytes.len() == 34 {
             let addr = bytes.split_off(2);
             let addr = Address::from_slice(&addr)?;
-            Ok(Node::new(addr, None))
+            Node::new(addr, None)
         } else {
             let mut length = [0; 2];
             let mut addr = [0; 32];
@@ -182,62 +248,64 @@ impl Node {
             for (i, j) in bytes.iter().enumerate() {
                 if i <= 1 {
                     length[i] = *j;
-                } else if i >= 2 && i <= 33 {
+                } else if (2..=33).contains(&i) {
                     addr[i - 2] = *j;
                 } else {
                     link.push(*j);
                 }
             }
+            if util::integer(length) != link.len() {
+                return Err(Error::Invalid(String::from("link length is not valid")));
+            }
             let address = Address::from_bytes(addr);
             let link = Link::from_bytes(link)?;
-            Ok(Node::new(address, Some(link)))
+            Node::new(address, Some(link))
+        };
+        if node.address == Address::default() {
+            return Err(Error::Invalid(String::from("node address is zero")));
         }
+        Ok(node)
     }
 
-    /// Parses a convertet Vec of serialized nodes, most likely from a bootstrap
-    /// response, into a Vec of actual Nodes.
+    /// Parses a convertet Vec of serialized nodes, most likely from a
+    /// bootstrap response, into a Vec of actual Nodes. Every entry is
+    /// parsed with from_bytes, invalid ones get skipped. Should the
+    /// data end in the middle of an entry the rest is ignored.
     pub fn from_bulk(bytes: Vec<u8>) -> Vec<Node> {
-        if bytes.len() < 34 {
-            return Vec::new();
-        } else if bytes.len() == 34 {
-            if let Ok(node) = Node::from_bytes(bytes.clone()) {
-                return vec![node];
+        let mut nodes = Vec::new();
+        let mut rest = &bytes[..];
+        while !rest.is_empty() {
+            if rest.len() < 34 {
+                trace::warn!("ignoring {} trailing bytes of nodes.", rest.len());
+                break;
             }
-        }
-        Node::recursive_parse(bytes)
-    }
-
-    /// Used to recursively walk through the bytes and convert them
-    /// into Nodes. This has to be done, since the length of the Link
-    /// can't be known at compile time and is encoded at
-    /// serialization.
-    fn recursive_parse(data: Vec<u8>) -> Vec<Node> {
-        if data.len() == 0 {
-            return Vec::new();
-        }
-        let mut len = [0; 2];
-        let mut address = [0; 32];
-        let mut link = Vec::new();
-        let mut rest = Vec::new();
-        for (i, j) in data.iter().enumerate() {
-            if i < 2 {
-                len[i] = *j;
-            } else if i >= 2 && i <= 33 {
-                address[i - 2] = *j;
-            } else if i >= 34 && i <= (34 + util::integer(len) - 1) {
-                link.push(*j);
-            } else {
-                rest.push(*j);
+            let end = 34 + util::integer([rest[0], rest[1]]);
+            if rest.len() < end {
+                trace::warn!("ignoring truncated node.");
+                break;
             }
+            match Node::from_bytes(rest[..end].to_vec()) {
+                Ok(node) => nodes.push(node),
+                Err(e) => trace::warn!("skipping invalid node: {}", e),
+            }
+            rest = &rest[end..];
         }
-        let addr = Address::from_bytes(address);
-        if let Ok(link) = Link::from_bytes(link) {
-            let node = Node::new(addr, Some(link));
-            let mut nodes = vec![node];
-            nodes.append(&mut Node::recursive_parse(rest));
-            return nodes;
+        nodes
+    }
+}
+
+impl Default for Node {
+    /// A shorthand for a (mostly useless) empty zero Node with an
+    /// invalid timestamp.
+    fn default() -> Node {
+        let bytes = [0; 32];
+        let address = Address::from_bytes(bytes);
+        Node {
+            address,
+            link: None,
+            timestamp: SystemTime::UNIX_EPOCH,
+            rtt: None,
         }
-        return Node::recursive_parse(rest);
     }
 }
 
@@ -272,22 +340,77 @@ impl Center {
     /// get generated from the secret and the current time is stored
     /// for the router.
     pub fn new(secret: SecretKey, ip: String, port: usize) -> Self {
+        let (public, _) = sign::keypair_from_seed(&sign::Seed(secret.0));
+        let public = sign::ed25519::to_curve25519_pk(&public)
+            .expect("signing keys derived from a seed are always valid");
         Self {
-            public: Address::new(secret.public_key()),
+            public: Address::new(public),
             secret,
             uptime: SystemTime::now(),
             link: Link::new(ip, port),
         }
     }
 
-    /// Create a Center instance from the CenterConfig.
+    /// Generates new keys until the Address satisfies the difficulty,
+    /// every additional bit doubles the expected number of attempts.
+    pub fn generate(ip: String, port: usize, difficulty: u32) -> Self {
+        loop {
+            let (_, secret) = box_::gen_keypair();
+            let center = Self::new(secret, ip.clone(), port);
+            if center.public.work() >= difficulty {
+                return center;
+            }
+        }
+    }
+
+    /// Signing keys used to sign outgoing Wires. They are derived
+    /// from the secret key, so they don't have to be stored
+    /// separately.
+    pub fn signing(&self) -> (sign::PublicKey, sign::SecretKey) {
+        sign::keypair_from_seed(&sign::Seed(self.secret.0))
+    }
+
+    /// Secret key used to encrypt and decrypt messages, the matching
+    /// public key is the Address. It is converted from the signing
+    /// key, so every node can check that a signing key belongs to an
+    /// Address.
+    pub fn encryption(&self) -> SecretKey {
+        let (_, secret) = self.signing();
+        sign::ed25519::to_curve25519_sk(&secret)
+            .expect("signing keys derived from a seed are always valid")
+    }
+
+    /// Stores the secret key in the key file, it gets encrypted if a
+    /// passphrase is given.
+    pub fn save(&self, path: &str, passphrase: Option<&str>) -> Result<(), Error> {
+        keys::save(path, &self.secret, passphrase)
+    }
+
+    /// Restores the Center from the key file, the Link isn't part of
+    /// it.
+    pub fn load(
+        path: &str,
+        passphrase: Option<&str>,
+        ip: String,
+        port: usize,
+    ) -> Result<Self, Error> {
+        Ok(Self::new(keys::load(path, passphrase)?, ip, port))
+    }
+
+    /// Create a Center instance from the CenterConfig. Without a
+    /// secret key in it the key file gets used, which is created on
+    /// the first start.
     pub fn from_config(config: CenterConfig) -> Result<Self, Error> {
-        match config.secret {
-            Some(bytes) => match SecretKey::from_slice(&bytes) {
+        match (config.secret, config.key) {
+            (Some(bytes), _) => match SecretKey::from_slice(&bytes) {
                 Some(key) => Ok(Self::new(key, config.ip, config.port)),
                 None => Err(Error::Config(String::from("invalid config"))),
             },
-            None => Err(Error::Config(String::from("invalid config"))),
+            (None, Some(path)) => {
+                let secret = keys::persistent(&path, config.passphrase.as_deref())?;
+                Ok(Self::new(secret, config.ip, config.port))
+            }
+            (None, None) => Err(Error::Config(String::from("invalid config"))),
         }
     }
 }
@@ -321,6 +444,29 @@ impl Address {
         }
     }
 
+    /// Parses the hex representation of an Address, which is used
+    /// in config files. Upper and lower case are both accepted.
+    pub fn from_hex(hex: &str) -> Result<Self, Error> {
+        let invalid = || Error::Invalid(String::from("address is not valid hex"));
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Self::from_slice(&bytes)
+    }
+
+    /// Lower case hex representation of the Address, the opposite of
+    /// from_hex.
+    pub fn to_hex(&self) -> String {
+        self.as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
     /// Convert an array of bytes into an array of Addresses. Any
     /// invalid ones will be dropped.
     pub fn from_bulk(data: Vec<u8>) -> Vec<Address> {
@@ -329,10 +475,10 @@ impl Address {
             .for_each(|x| match Address::from_slice(x) {
                 Ok(address) => ret.push(address),
                 Err(e) => {
-                    log::warn!("received invalid Addres data: {:?}", e);
+                    trace::warn!("received invalid Addres data: {:?}", e);
                 }
             });
-        return ret;
+        ret
     }
 
     /// Returns an array of bytes of the public key / address.
@@ -343,10 +489,10 @@ impl Address {
     pub fn as_bytes(&self) -> [u8; 32] {
         let mut bytes: [u8; 32] = [0; 32];
         let key = self.key.as_ref();
-        for (i, j) in key.into_iter().enumerate() {
+        for (i, j) in key.iter().enumerate() {
             bytes[i] = *j;
         }
-        return bytes;
+        bytes
     }
 
     /// Instead of creating a new array of fixed length this simply
@@ -363,6 +509,62 @@ impl Address {
         Address::from_bytes(bytes)
     }
 
+    /// Proof of work of the Address, the number of leading zero bits
+    /// of the hash of the public key. Since it can't be chosen freely
+    /// generating many Addresses close to a target gets expensive.
+    pub fn work(&self) -> u32 {
+        let hash = blake3::hash(self.as_slice());
+        let mut work = 0;
+        for byte in hash.as_bytes() {
+            work += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        work
+    }
+
+    /// Checks if the signing key belongs to the Address, the Address
+    /// of a node is derived from its signing key. Signatures can only
+    /// be attributed to a source if this holds.
+    pub fn is_signing_key(&self, key: &[u8; 32]) -> bool {
+        match sign::ed25519::to_curve25519_pk(&sign::PublicKey(*key)) {
+            Ok(public) => public == self.key,
+            Err(_) => false,
+        }
+    }
+
+    /// XOR distance to the other Address, the same as the XOR
+    /// operator. Distances compare like the big-endian numbers they
+    /// represent.
+    pub fn distance(&self, other: &Address) -> [u8; 32] {
+        self ^ other
+    }
+
+    /// Number of leading bits shared with the other Address, which is
+    /// the index of the Kademlia bucket it falls into. Equal Addresses
+    /// share all 256.
+    pub fn leading_zeros(&self, other: &Address) -> u32 {
+        let mut zeros = 0;
+        for byte in self.distance(other).iter() {
+            zeros += byte.leading_zeros();
+            if *byte != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+
+    /// Orders the two Addresses by their distance to this one, the
+    /// closer one comes first. Meant for sorting by closeness:
+    ///
+    /// ``` ignore
+    /// addresses.sort_by(|a, b| target.cmp_distance(a, b));
+    /// ```
+    pub fn cmp_distance(&self, a: &Address, b: &Address) -> Ordering {
+        self.distance(a).cmp(&self.distance(b))
+    }
+
     /// Since the bucket ID (first byte of distance) is dependant on
     /// the distance from the Center it has to be computed. Currently
     /// this function uses as_bytes()/0 on both addresses, which
@@ -376,14 +578,16 @@ impl Address {
     /// Generate a random Address
     pub fn random() -> Address {
         let mut bytes = [0; 32];
-        for i in 0..31 {
-            bytes[i] = rand::random::<u8>();
+        for byte in bytes.iter_mut().take(31) {
+            *byte = rand::random::<u8>();
         }
         Address::from_bytes(bytes)
     }
+}
 
+impl Default for Address {
     /// Generates a new "zero" Address with all bytes being 0.
-    pub fn default() -> Address {
+    fn default() -> Address {
         Address::from_bytes([0; 32])
     }
 }
@@ -403,7 +607,7 @@ impl BitXor for Address {
         for i in 0..31 {
             bytes[i] = target[i] ^ source[i];
         }
-        return bytes;
+        bytes
     }
 }
 
@@ -422,7 +626,7 @@ impl BitXor for &Address {
         for i in 0..31 {
             bytes[i] = target[i] ^ source[i];
         }
-        return bytes;
+        bytes
     }
 }
 
@@ -432,6 +636,74 @@ impl Hash for Address {
     }
 }
 
+/// Addresses are displayed in their canonical hex representation,
+/// the same one used in config files.
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+/// Parses the canonical hex representation, unlike ToAddress the
+/// string isn't hashed.
+impl FromStr for Address {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s)
+    }
+}
+
+impl TryFrom<&str> for Address {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        Self::from_hex(value)
+    }
+}
+
+/// Human readable formats like JSON or TOML get the hex
+/// representation, binary formats the raw bytes.
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.to_hex())
+        } else {
+            serializer.serialize_bytes(self.as_slice())
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Address {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(AddressVisitor)
+        } else {
+            deserializer.deserialize_bytes(AddressVisitor)
+        }
+    }
+}
+
+/// Accepts both representations of an Address, independent of the
+/// format.
+struct AddressVisitor;
+
+impl<'de> Visitor<'de> for AddressVisitor {
+    type Value = Address;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a hex encoded Address or its 32 bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Address, E> {
+        Address::from_hex(value).map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Address, E> {
+        Address::from_slice(value).map_err(E::custom)
+    }
+}
+
 /// Since sometimes the user has to interact with Addresses directly,
 /// it might be easier to use a trait object, so that a number of
 /// different types can be used to create Addresses.
@@ -466,59 +738,198 @@ impl ToAddress for usize {
 
 impl Link {
     /// Creates new connection details (Link). It sets both the
-    /// reachable and attempts values to teh default.
-    pub fn new(ip: String, port: usize) -> Self {
+    /// reachable and attempts values to teh default. The host can be
+    /// an IP address (IPv6 with or without brackets) or a hostname.
+    pub fn new(host: String, port: usize) -> Self {
         Self {
-            ip,
+            host: Host::parse(&host),
             port,
             reachable: false,
             attempts: 0,
+            relay: None,
         }
     }
 
-    /// Returns a new String of the connection details, usable by the
-    /// TCP handler. (This still doesn't validtate the values, it
-    /// simply concats them. There is no guarantee it will be usable
-    /// by IpV4.)
-    pub fn to_string(&self) -> String {
-        let elements = [self.ip.clone(), self.port.to_string()];
-        elements.join(":")
+    /// Creates a Link of a node that is only reachable through the
+    /// relay with the given connection details and Address.
+    pub fn relayed(host: String, port: usize, relay: Address) -> Self {
+        Self {
+            relay: Some(relay),
+            ..Self::new(host, port)
+        }
     }
 
     /// This single function can be used to both incease the count of
-    /// the attempts and set it as true should it has been reached.
-    /// The counter will currently not be reset if the status is true,
-    /// this might help to sort out unreliable nodes.
+    /// the failed attempts and set it as true should it has been
+    /// reached, which resets the counter.
     pub fn update(&mut self, status: bool) {
-        self.attempts += 1;
+        match status {
+            true => self.attempts = 0,
+            false => self.attempts += 1,
+        }
         self.reachable = status;
     }
 
+    /// Number of failed attempts in a row to reach the node.
+    pub fn failures(&self) -> usize {
+        self.attempts
+    }
+
+    /// True if both Links lead to the same node, their status is
+    /// ignored.
+    pub fn is_same(&self, other: &Link) -> bool {
+        self.host == other.host && self.port == other.port && self.relay == other.relay
+    }
+
     /// Exports the link details to bytes that can be sent over the
     /// wire. Structure:
-    /// IP Address data,
+    /// 1 byte: Version (always 0xff),
+    /// 1 byte: Type (0 = hostname, 4 = IPv4, 6 = IPv6),
+    /// Address data (UTF-8 hostname, 4 or 16 bytes IP),
     /// Last 8 bytes: Port number
+    ///
+    /// Relayed Links start with the version, type 1 and the Address
+    /// of the relay, followed by the Link of the relay itself.
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut data = Vec::new();
-        let address = self.ip.as_bytes();
-        let port = self.port.to_le_bytes();
-        data.append(&mut address.to_vec());
-        data.append(&mut port.to_vec());
-        return data;
-    }
-
-    pub fn from_bytes(mut data: Vec<u8>) -> Result<Link, Error> {
-        data.reverse();
-        let mut address = data.split_off(8);
-        data.reverse();
-        address.reverse();
-        let ip = String::from_utf8(address)?;
+        if let Some(relay) = &self.relay {
+            let mut data = vec![LINK_VERSION, LINK_RELAY];
+            data.append(&mut relay.as_bytes().to_vec());
+            let direct = Self {
+                relay: None,
+                ..self.clone()
+            };
+            data.append(&mut direct.as_bytes());
+            return data;
+        }
+        let mut data = vec![LINK_VERSION];
+        match &self.host {
+            Host::Name(name) => {
+                data.push(LINK_NAME);
+                data.append(&mut name.as_bytes().to_vec());
+            }
+            Host::Ip(IpAddr::V4(ip)) => {
+                data.push(LINK_IPV4);
+                data.append(&mut ip.octets().to_vec());
+            }
+            Host::Ip(IpAddr::V6(ip)) => {
+                data.push(LINK_IPV6);
+                data.append(&mut ip.octets().to_vec());
+            }
+        }
+        data.append(&mut (self.port as u64).to_le_bytes().to_vec());
+        data
+    }
+
+    /// Parses both the current and the old format, which was just
+    /// the UTF-8 encoded IP followed by the port.
+    pub fn from_bytes(data: Vec<u8>) -> Result<Link, Error> {
+        if data.len() > 34 && data[..2] == [LINK_VERSION, LINK_RELAY] {
+            let relay = Address::from_slice(&data[2..34])?;
+            let link = Self::from_bytes(data[34..].to_vec())?;
+            if link.relay.is_some() {
+                return Err(Error::Invalid(String::from("nested relay link")));
+            }
+            return Ok(Link {
+                relay: Some(relay),
+                ..link
+            });
+        }
+        if data.len() < 8 {
+            return Err(Error::Invalid(String::from("link is too short")));
+        }
+        let (address, port) = data.split_at(data.len() - 8);
         let mut port_bytes = [0; 8];
-        for (i, j) in data.iter().enumerate() {
-            port_bytes[i] = *j;
+        port_bytes.copy_from_slice(port);
+        let port = u64::from_le_bytes(port_bytes) as usize;
+        let host = match address.split_first() {
+            Some((&LINK_VERSION, rest)) => match rest.split_first() {
+                Some((&LINK_NAME, name)) => Host::Name(String::from_utf8(name.to_vec())?),
+                Some((&LINK_IPV4, ip)) if ip.len() == 4 => {
+                    let mut octets = [0; 4];
+                    octets.copy_from_slice(ip);
+                    Host::Ip(IpAddr::from(octets))
+                }
+                Some((&LINK_IPV6, ip)) if ip.len() == 16 => {
+                    let mut octets = [0; 16];
+                    octets.copy_from_slice(ip);
+                    Host::Ip(IpAddr::from(octets))
+                }
+                _ => return Err(Error::Invalid(String::from("unknown link format"))),
+            },
+            _ => Host::parse(&String::from_utf8(address.to_vec())?),
+        };
+        Ok(Link {
+            host,
+            port,
+            reachable: false,
+            attempts: 0,
+            relay: None,
+        })
+    }
+}
+
+impl From<SocketAddr> for Link {
+    fn from(addr: SocketAddr) -> Self {
+        Self {
+            host: Host::Ip(addr.ip()),
+            port: addr.port() as usize,
+            reachable: false,
+            attempts: 0,
+            relay: None,
+        }
+    }
+}
+
+impl ToSocketAddrs for Link {
+    type Iter = std::vec::IntoIter<SocketAddr>;
+
+    /// Resolves the Link into the addresses used by the TCP system.
+    /// Hostnames get looked up on every call, IP addresses are used
+    /// directly. Fails if the port is out of range.
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        let port = u16::try_from(self.port)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "port is out of range"))?;
+        match &self.host {
+            Host::Ip(ip) => Ok(vec![SocketAddr::new(*ip, port)].into_iter()),
+            Host::Name(name) => (name.as_str(), port).to_socket_addrs(),
+        }
+    }
+}
+
+impl fmt::Display for Link {
+    /// Returns a new String of the connection details. IPv6
+    /// addresses are put in brackets, so the String can be parsed
+    /// again. (This still doesn't validtate the values, hostnames
+    /// might not resolve.)
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.host, self.port)
+    }
+}
+
+impl Host {
+    /// Turns a String into a Host. Everything that isn't a valid IP
+    /// address (IPv6 can be in brackets) is treated as a hostname.
+    pub fn parse(host: &str) -> Self {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Self::Ip(ip);
+        }
+        let inner = host.strip_prefix('[').and_then(|h| h.strip_suffix(']'));
+        match inner.map(|h| h.parse::<Ipv6Addr>()) {
+            Some(Ok(ip)) => Self::Ip(IpAddr::V6(ip)),
+            _ => Self::Name(host.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Host {
+    /// IPv6 addresses are put in brackets, everything else is
+    /// returned as is.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ip(IpAddr::V6(ip)) => write!(f, "[{}]", ip),
+            Self::Ip(ip) => write!(f, "{}", ip),
+            Self::Name(name) => write!(f, "{}", name),
         }
-        let port = u64::from_le_bytes(port_bytes);
-        Ok(Link::new(ip, port as usize))
     }
 }
 
@@ -530,11 +941,27 @@ mod tests {
     #[test]
     fn test_address_from_bytes() {
         let (p, _s) = box_::gen_keypair();
-        let real = Address::new(p.clone());
+        let real = Address::new(p);
         let test = Address::from_bytes(p.0);
         assert_eq!(real.key.0, test.key.0);
     }
 
+    #[test]
+    fn test_address_display() {
+        let address = Address::random();
+        let text = address.to_string();
+        assert_eq!(text, address.to_hex());
+        assert_eq!(text.parse::<Address>().unwrap(), address);
+        assert_eq!(Address::try_from(text.as_str()).unwrap(), address);
+        assert!("actaeon".parse::<Address>().is_err());
+
+        let json = serde_json::to_string(&vec![address.clone()]).unwrap();
+        assert_eq!(json, format!("[\"{}\"]", text));
+        let parsed: Vec<Address> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, vec![address]);
+        assert!(serde_json::from_str::<Address>("\"actaeon\"").is_err());
+    }
+
     #[test]
     fn test_center_new() {
         let (_, s) = box_::gen_keypair();
@@ -542,6 +969,13 @@ mod tests {
         assert_ne!(c.public.as_bytes(), [0; 32]);
     }
 
+    #[test]
+    fn test_center_generate() {
+        let c = Center::generate(String::from("abc"), 0, 8);
+        assert!(c.public.work() >= 8);
+        assert_eq!(blake3::hash(c.public.as_slice()).as_bytes()[0], 0);
+    }
+
     #[test]
     fn test_link_new() {
         let l = Link::new("127.0.0.1".to_string(), 42);
@@ -554,6 +988,28 @@ mod tests {
         assert_eq!(l.to_string(), String::from("127.0.0.1:42"));
     }
 
+    #[test]
+    fn test_link_ipv6() {
+        let l = Link::new("::1".to_string(), 42);
+        assert_eq!(l.host, Host::Ip("::1".parse().unwrap()));
+        assert_eq!(l.to_string(), String::from("[::1]:42"));
+        assert_eq!(Link::new("[::1]".to_string(), 42), l);
+    }
+
+    #[test]
+    fn test_link_resolve() {
+        let l = Link::new("localhost".to_string(), 42);
+        assert_eq!(l.host, Host::Name("localhost".to_string()));
+        let addrs: Vec<SocketAddr> = l.to_socket_addrs().unwrap().collect();
+        assert!(addrs.iter().all(|a| a.ip().is_loopback() && a.port() == 42));
+    }
+
+    #[test]
+    fn test_link_resolve_port() {
+        let l = Link::new("127.0.0.1".to_string(), 70000);
+        assert!(l.to_socket_addrs().is_err());
+    }
+
     #[test]
     fn test_address_xor() {
         let a1 = Address::generate("test1");
@@ -567,6 +1023,26 @@ mod tests {
         assert_eq!(a.clone() ^ a, [0; 32]);
     }
 
+    #[test]
+    fn test_address_distance() {
+        let a = Address::from_bytes([0; 32]);
+        let mut bytes = [0; 32];
+        bytes[1] = 0b0010_0000;
+        let b = Address::from_bytes(bytes);
+        bytes[0] = 1;
+        let c = Address::from_bytes(bytes);
+        assert_eq!(a.distance(&b), a.clone() ^ b.clone());
+        assert_eq!(a.leading_zeros(&b), 10);
+        assert_eq!(a.leading_zeros(&c), 7);
+        assert_eq!(c.leading_zeros(&c), 256);
+        assert_eq!(a.cmp_distance(&b, &c), Ordering::Less);
+        assert_eq!(c.cmp_distance(&b, &a), Ordering::Less);
+        assert_eq!(a.cmp_distance(&b, &b), Ordering::Equal);
+        let mut addresses = vec![c.clone(), a.clone(), b.clone()];
+        addresses.sort_by(|x, y| a.cmp_distance(x, y));
+        assert_eq!(addresses, vec![a, b, c]);
+    }
+
     #[test]
     fn test_to_address_bytes() {
         let mut bytes = [0; 32];
@@ -590,6 +1066,41 @@ mod tests {
         assert_eq!(l, c);
     }
 
+    #[test]
+    fn test_link_serialize_ipv6() {
+        let l = Link::from("[2001:db8::1]:4242".parse::<SocketAddr>().unwrap());
+        let c = Link::from_bytes(l.as_bytes()).unwrap();
+        assert_eq!(l, c);
+        assert_eq!(c.to_string(), String::from("[2001:db8::1]:4242"));
+    }
+
+    #[test]
+    fn test_link_serialize_relay() {
+        let l = Link::relayed(String::from("127.0.0.1"), 12345, Address::random());
+        let c = Link::from_bytes(l.as_bytes()).unwrap();
+        assert_eq!(l, c);
+        let mut nested = vec![LINK_VERSION, LINK_RELAY];
+        nested.append(&mut Address::random().as_bytes().to_vec());
+        nested.append(&mut l.as_bytes());
+        assert!(Link::from_bytes(nested).is_err());
+    }
+
+    #[test]
+    fn test_link_serialize_legacy() {
+        let mut b = "127.0.0.1".as_bytes().to_vec();
+        b.append(&mut 12345_u64.to_le_bytes().to_vec());
+        let c = Link::from_bytes(b).unwrap();
+        assert_eq!(c, Link::new(String::from("127.0.0.1"), 12345));
+    }
+
+    #[test]
+    fn test_link_serialize_invalid() {
+        assert!(Link::from_bytes(vec![0; 4]).is_err());
+        assert!(
+            Link::from_bytes(vec![LINK_VERSION, LINK_IPV4, 1, 0, 0, 0, 0, 0, 0, 0, 0]).is_err()
+        );
+    }
+
     #[test]
     fn test_link_serialize_more() {
         for i in 100..1000 {
@@ -614,6 +1125,17 @@ mod tests {
         assert_ne!(Address::random(), Address::random());
     }
 
+    #[test]
+    fn test_address_hex() {
+        let address = Address::random();
+        let hex = address.to_hex();
+        assert_eq!(hex.len(), 64);
+        assert_eq!(Address::from_hex(&hex).unwrap(), address);
+        assert_eq!(Address::from_hex(&hex.to_uppercase()).unwrap(), address);
+        assert!(Address::from_hex(&hex[1..]).is_err());
+        assert!(Address::from_hex(&hex.replace(&hex[..2], "zz")).is_err());
+    }
+
     #[test]
     fn test_node_length() {
         let l = Link::new("192.168.1.42".to_string(), 2424);
@@ -623,6 +1145,16 @@ mod tests {
         assert_eq!(ser[0..1], len[0..1]);
     }
 
+    #[test]
+    fn test_node_rtt() {
+        let mut node = Node::new(Address::random(), None);
+        assert_eq!(node.rtt(), None);
+        node.measure(Duration::from_millis(80));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(80)));
+        node.measure(Duration::from_millis(160));
+        assert_eq!(node.rtt(), Some(Duration::from_millis(90)));
+    }
+
     #[test]
     fn test_node_bulk() {
         let mut bytes = Vec::new();
@@ -649,6 +1181,43 @@ mod tests {
         assert_eq!(nodes, re);
     }
 
+    #[test]
+    fn test_node_invalid() {
+        assert!(Node::from_bytes(vec![0; 31]).is_err());
+        assert!(Node::from_bytes(vec![0; 34]).is_err());
+        let node = gen_node(4);
+        let mut bytes = node.as_bytes();
+        bytes.push(0);
+        assert!(Node::from_bytes(bytes).is_err());
+        let mut bytes = node.as_bytes();
+        bytes[35] = 0xee;
+        assert!(Node::from_bytes(bytes).is_err());
+    }
+
+    #[test]
+    fn test_node_bulk_corrupted() {
+        let nodes = vec![gen_node(4), Node::new(Address::random(), None), gen_node(4)];
+        let zero = Node::new(Address::default(), Some(Link::new(String::from("a"), 1)));
+        let mut bytes = Vec::new();
+        bytes.append(&mut nodes[0].as_bytes());
+        bytes.append(&mut zero.as_bytes());
+        let mut broken = nodes[0].as_bytes();
+        broken[35] = 0xee;
+        bytes.append(&mut broken);
+        bytes.append(&mut nodes[1].as_bytes());
+        bytes.append(&mut nodes[2].as_bytes());
+        // Invalid entries get skipped, the others are still found.
+        assert_eq!(Node::from_bulk(bytes.clone()), nodes);
+
+        let complete = bytes.len();
+        bytes.append(&mut gen_node(4).as_bytes());
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(Node::from_bulk(bytes.clone()), nodes);
+        bytes.truncate(complete + 20);
+        assert_eq!(Node::from_bulk(bytes), nodes);
+        assert!(Node::from_bulk(Vec::new()).is_empty());
+    }
+
     fn gen_node(len: usize) -> Node {
         let addr = Address::random();
         let mut ip = Vec::new();
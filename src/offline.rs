@@ -0,0 +1,237 @@
+//! # Offline
+//!
+//! Nodes on mobile or edge devices are often disconnected, but their
+//! users still compose messages. While the routing table is empty
+//! the Switch keeps the messages of the user in an offline Queue
+//! instead of handing them to the Listener, once the node knows other
+//! nodes again they get sent in the order they were queued.
+//!
+//! The Queue is limited both in the number of messages and their
+//! total size. With a Storage every queued message is also written to
+//! its outbox namespace, so messages composed before a restart are
+//! still sent afterwards. The messages are stored the way they go
+//! out, encrypted and signed.
+
+use crate::backend::{Storage, OUTBOX};
+use crate::error::Error;
+use crate::trace;
+use crate::transaction::Transaction;
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Messages of the user waiting for the node to join the network.
+/// Clones share the same messages.
+#[derive(Clone)]
+pub struct Queue(Arc<Mutex<Queued>>);
+
+/// Content of the Queue.
+struct Queued {
+    /// Queued messages with their key and size, oldest first.
+    entries: VecDeque<(u64, Transaction, usize)>,
+    /// Total size of all queued messages in bytes.
+    bytes: usize,
+    /// Key of the next queued message, keys only ever increase.
+    next: u64,
+    /// Maximum number of queued messages.
+    limit: usize,
+    /// Maximum total size of the queued messages in bytes.
+    size: usize,
+    /// Only available if the messages are persisted.
+    storage: Option<Arc<dyn Storage>>,
+}
+
+impl Queue {
+    /// Creates an empty Queue, which only lives in memory.
+    pub fn new(limit: usize, size: usize) -> Self {
+        Self(Arc::new(Mutex::new(Queued {
+            entries: VecDeque::new(),
+            bytes: 0,
+            next: 0,
+            limit,
+            size,
+            storage: None,
+        })))
+    }
+
+    /// Restores the messages queued in the Storage, every change is
+    /// written through to it. Invalid stored messages get removed,
+    /// valid ones are kept even if they exceed the limits.
+    pub fn with_storage(
+        limit: usize,
+        size: usize,
+        storage: Arc<dyn Storage>,
+    ) -> Result<Self, Error> {
+        let queue = Self::new(limit, size);
+        {
+            let mut queued = queue.lock()?;
+            for (key, value) in storage.iterate(OUTBOX)? {
+                let parsed = key
+                    .as_slice()
+                    .try_into()
+                    .ok()
+                    .map(u64::from_be_bytes)
+                    .zip(Transaction::from_bytes(&value).ok());
+                match parsed {
+                    Some((index, transaction)) => {
+                        queued.bytes += value.len();
+                        queued.next = index + 1;
+                        queued.entries.push_back((index, transaction, value.len()));
+                    }
+                    None => {
+                        trace::warn!("dropping invalid stored message.");
+                        let _ = storage.delete(OUTBOX, &key);
+                    }
+                }
+            }
+            trace::info!("restoring {} queued messages.", queued.entries.len());
+            queued.storage = Some(storage);
+        }
+        Ok(queue)
+    }
+
+    /// Adds the message to the end of the Queue, fails with Full if
+    /// it would exceed any of the limits.
+    pub fn push(&self, transaction: Transaction) -> Result<(), Error> {
+        let mut queued = self.lock()?;
+        let data = transaction.as_bytes();
+        if queued.entries.len() >= queued.limit || queued.bytes + data.len() > queued.size {
+            return Err(Error::Full);
+        }
+        let key = queued.next;
+        if let Some(storage) = &queued.storage {
+            storage.put(OUTBOX, &key.to_be_bytes(), data.clone())?;
+        }
+        queued.next += 1;
+        queued.bytes += data.len();
+        queued.entries.push_back((key, transaction, data.len()));
+        Ok(())
+    }
+
+    /// Passes the queued messages to the sender in order and removes
+    /// them, until the sender returns false for one. That one and all
+    /// later ones stay queued. Returns the number of sent messages.
+    pub fn flush<F>(&self, mut send: F) -> usize
+    where
+        F: FnMut(&Transaction) -> bool,
+    {
+        let mut queued = match self.lock() {
+            Ok(queued) => queued,
+            Err(_) => return 0,
+        };
+        let mut sent = 0;
+        while let Some((key, transaction, size)) = queued.entries.front() {
+            if !send(transaction) {
+                break;
+            }
+            let (key, size) = (*key, *size);
+            queued.entries.pop_front();
+            queued.bytes -= size;
+            if let Some(storage) = &queued.storage {
+                if let Err(e) = storage.delete(OUTBOX, &key.to_be_bytes()) {
+                    trace::warn!("unable to remove sent message: {}", e);
+                }
+            }
+            sent += 1;
+        }
+        sent
+    }
+
+    /// Copies of all queued messages, oldest first.
+    pub fn pending(&self) -> Vec<Transaction> {
+        match self.lock() {
+            Ok(queued) => queued.entries.iter().map(|(_, t, _)| t.clone()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().map(|queued| queued.entries.len()).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Queued>, Error> {
+        self.0
+            .lock()
+            .map_err(|_| Error::System(String::from("queue lock is poisoned")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::MemoryStorage;
+    use crate::message::Message;
+    use crate::node::Address;
+    use crate::transaction::Class;
+
+    fn transaction(body: Vec<u8>) -> Transaction {
+        Transaction::new(Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::default(),
+            body,
+        ))
+    }
+
+    #[test]
+    fn test_queue_limits() {
+        let queue = Queue::new(2, 1024);
+        queue.push(transaction(vec![1])).unwrap();
+        queue.push(transaction(vec![2])).unwrap();
+        assert!(matches!(queue.push(transaction(vec![3])), Err(Error::Full)));
+        assert_eq!(queue.len(), 2);
+
+        let small = Queue::new(8, 200);
+        assert!(matches!(
+            small.push(transaction(vec![0; 256])),
+            Err(Error::Full)
+        ));
+        assert!(small.is_empty());
+    }
+
+    #[test]
+    fn test_queue_flush() {
+        let queue = Queue::new(8, 4096);
+        let ts: Vec<_> = (0..3).map(|i| transaction(vec![i])).collect();
+        for t in &ts {
+            queue.push(t.clone()).unwrap();
+        }
+        assert_eq!(queue.pending(), ts);
+        let mut sent = Vec::new();
+        assert_eq!(
+            queue.flush(|t| {
+                sent.push(t.clone());
+                sent.len() < 2
+            }),
+            1
+        );
+        assert_eq!(queue.pending(), ts[1..].to_vec());
+        assert_eq!(queue.flush(|_| true), 2);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_queue_storage() {
+        let storage = Arc::new(MemoryStorage::new());
+        let queue = Queue::with_storage(8, 4096, storage.clone()).unwrap();
+        let ts: Vec<_> = (0..3).map(|i| transaction(vec![i])).collect();
+        for t in &ts {
+            queue.push(t.clone()).unwrap();
+        }
+        queue.flush(|t| t == &ts[0]);
+        storage.put(OUTBOX, b"invalid", vec![1, 2]).unwrap();
+
+        let restored = Queue::with_storage(8, 4096, storage.clone()).unwrap();
+        assert_eq!(restored.pending(), ts[1..].to_vec());
+        assert_eq!(storage.iterate(OUTBOX).unwrap().len(), 2);
+        let t = transaction(vec![3]);
+        restored.push(t.clone()).unwrap();
+        let restored = Queue::with_storage(8, 4096, storage).unwrap();
+        assert_eq!(restored.pending().last(), Some(&t));
+    }
+}
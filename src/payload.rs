@@ -0,0 +1,65 @@
+//! # Payload
+//!
+//! Bodies are always sent as plain bytes, the Payload trait converts
+//! user types from and to them. Every type implementing the serde
+//! traits is a Payload and gets encoded as JSON, so most structs only
+//! need the derive macros.
+
+use crate::error::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Conversion of a value into the body of a message and back.
+pub trait Payload: Sized {
+    /// Encodes the value into the bytes sent as body.
+    fn to_bytes(&self) -> Result<Vec<u8>, Error>;
+
+    /// Decodes a received body, fails if it doesn't contain a valid
+    /// value of the type.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error>;
+}
+
+impl<T: Serialize + DeserializeOwned> Payload for T {
+    fn to_bytes(&self) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Position {
+        name: String,
+        x: i64,
+        y: i64,
+    }
+
+    #[test]
+    fn test_payload_struct() {
+        let position = Position {
+            name: String::from("actaeon"),
+            x: 4,
+            y: -2,
+        };
+        let bytes = position.to_bytes().unwrap();
+        assert_eq!(Position::from_bytes(&bytes).unwrap(), position);
+    }
+
+    #[test]
+    fn test_payload_invalid() {
+        assert!(matches!(
+            Position::from_bytes(&[42, 0, 1]),
+            Err(Error::Serialization(_))
+        ));
+        let bytes = 42u32.to_bytes().unwrap();
+        assert!(Position::from_bytes(&bytes).is_err());
+        assert_eq!(u32::from_bytes(&bytes).unwrap(), 42);
+    }
+}
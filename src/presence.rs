@@ -0,0 +1,103 @@
+//! # Presence
+//!
+//! Subscribers of a Topic periodically announce themselves to the
+//! node hosting its Record, optionally with some metadata like a
+//! nickname. The Record keeps track of when each subscriber was last
+//! seen and forgets the ones that stopped announcing. Subscribers get
+//! the list of present subscribers in response to each announcement
+//! and are informed about everyone joining or leaving.
+
+use crate::error::Error;
+use crate::node::Address;
+use std::time::Duration;
+
+/// Subscribers that haven't announced themselves for this many
+/// intervals are no longer present.
+pub const PRESENCE_FACTOR: u32 = 3;
+
+/// A present subscriber of a Topic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presence {
+    /// Address of the subscriber.
+    pub address: Address,
+    /// Whatever the subscriber announced itself with, usually
+    /// encoded through a Payload.
+    pub metadata: Vec<u8>,
+    /// Time since the subscriber last announced itself.
+    pub age: Duration,
+}
+
+impl Presence {
+    pub fn new(address: Address, metadata: Vec<u8>, age: Duration) -> Self {
+        Self {
+            address,
+            metadata,
+            age,
+        }
+    }
+
+    /// Serializes the Presence. Structure:
+    /// 32 bytes: Address,
+    /// 4 bytes: Age in seconds,
+    /// 2 bytes: Length of the metadata,
+    /// n bytes: Metadata (at most u16::MAX bytes, the rest is cut off)
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let metadata = &self.metadata[..self.metadata.len().min(u16::MAX as usize)];
+        let age = self.age.as_secs().min(u32::MAX as u64) as u32;
+        let mut data = self.address.as_bytes().to_vec();
+        data.extend_from_slice(&age.to_be_bytes());
+        data.extend_from_slice(&(metadata.len() as u16).to_be_bytes());
+        data.extend_from_slice(metadata);
+        data
+    }
+
+    /// Parses a single Presence from the start of the data and returns
+    /// it with the number of bytes it used.
+    pub fn from_bytes(data: &[u8]) -> Result<(Self, usize), Error> {
+        let invalid = || Error::Invalid(String::from("presence is not valid"));
+        let header = data.get(..38).ok_or_else(invalid)?;
+        let address = Address::from_slice(&header[..32])?;
+        let age = u32::from_be_bytes([header[32], header[33], header[34], header[35]]);
+        let length = u16::from_be_bytes([header[36], header[37]]) as usize;
+        let metadata = data.get(38..38 + length).ok_or_else(invalid)?;
+        let presence = Self::new(address, metadata.to_vec(), Duration::from_secs(age as u64));
+        Ok((presence, 38 + length))
+    }
+
+    /// Serializes any number of Presences after each other.
+    pub fn to_bulk(presences: &[Presence]) -> Vec<u8> {
+        presences.iter().flat_map(|p| p.as_bytes()).collect()
+    }
+
+    /// Opposite of to_bulk, fails if any of the Presences is invalid.
+    pub fn from_bulk(mut data: &[u8]) -> Result<Vec<Self>, Error> {
+        let mut presences = Vec::new();
+        while !data.is_empty() {
+            let (presence, length) = Self::from_bytes(data)?;
+            presences.push(presence);
+            data = &data[length..];
+        }
+        Ok(presences)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_presence_bulk() {
+        let presences = vec![
+            Presence::new(
+                Address::random(),
+                b"actaeon".to_vec(),
+                Duration::from_secs(4),
+            ),
+            Presence::new(Address::random(), Vec::new(), Duration::from_secs(0)),
+        ];
+        let data = Presence::to_bulk(&presences);
+        assert_eq!(Presence::from_bulk(&data).unwrap(), presences);
+        assert!(Presence::from_bulk(&data[..data.len() - 1]).is_err());
+        assert!(Presence::from_bulk(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,211 @@
+//! # Prometheus
+//!
+//! Renders the state of the node in the Prometheus text format, so
+//! it can be scraped in production. It is only built with the
+//! "prometheus" feature, which also enables counting the Wires of
+//! each Class in the Metrics. The status endpoint serves it on
+//! /metrics, Interface::prometheus returns it for serving it
+//! elsewhere.
+//!
+//! All metrics are prefixed with "actaeon_":
+//!
+//! - wires_received_total / wires_sent_total: Counters of the Wires
+//!   exchanged with other nodes, labeled by their Class.
+//!
+//! - messages_delivered_total / messages_dropped_total: Counters of
+//!   the messages passed on to the user or a Topic.
+//!
+//! - connections: Gauge of the open connections.
+//!
+//! - bucket_nodes: Gauge of the nodes in each bucket of the routing
+//!   table, labeled by the depth and the limits of the bucket.
+//!
+//! - cache_hit_ratio: Gauge of the share of Wires that were already
+//!   in the Transaction cache.
+//!
+//! - topic_subscribers: Gauge of the subscribers of every Topic whose
+//!   Record is hosted on this node, labeled by its Address.
+
+use crate::metrics::Metrics;
+use crate::record::RecordBucket;
+use crate::router::RoutingSnapshot;
+use crate::transaction::Class;
+use std::fmt::Write;
+
+/// Renders all metrics of the node in the Prometheus text format.
+pub fn render(metrics: &Metrics, table: &RoutingSnapshot, records: &RecordBucket) -> String {
+    let snapshot = metrics.snapshot();
+    let mut classes: Vec<_> = metrics
+        .classes()
+        .into_iter()
+        .map(|(class, received, sent)| (label(&class), received, sent))
+        .collect();
+    classes.sort();
+    let mut out = String::new();
+
+    family(
+        &mut out,
+        "wires_received_total",
+        "counter",
+        "Wires received from other nodes.",
+    );
+    for (class, received, _) in &classes {
+        sample(&mut out, "wires_received_total", ("class", class), received);
+    }
+    family(
+        &mut out,
+        "wires_sent_total",
+        "counter",
+        "Wires sent to other nodes.",
+    );
+    for (class, _, sent) in &classes {
+        sample(&mut out, "wires_sent_total", ("class", class), sent);
+    }
+
+    family(
+        &mut out,
+        "messages_delivered_total",
+        "counter",
+        "Messages passed on to the user or a Topic.",
+    );
+    value(&mut out, "messages_delivered_total", snapshot.delivered);
+    family(
+        &mut out,
+        "messages_dropped_total",
+        "counter",
+        "Messages dropped because of full Channels.",
+    );
+    value(&mut out, "messages_dropped_total", snapshot.dropped);
+    family(&mut out, "connections", "gauge", "Open connections.");
+    value(&mut out, "connections", snapshot.connections);
+
+    family(
+        &mut out,
+        "bucket_nodes",
+        "gauge",
+        "Nodes in each bucket of the routing table.",
+    );
+    for bucket in &table.buckets {
+        let _ = writeln!(
+            out,
+            "actaeon_bucket_nodes{{depth=\"{}\",lower=\"{}\",upper=\"{}\"}} {}",
+            bucket.depth,
+            bucket.lower,
+            bucket.upper,
+            bucket.nodes.len()
+        );
+    }
+
+    family(
+        &mut out,
+        "cache_hit_ratio",
+        "gauge",
+        "Share of Wires that were already in the cache.",
+    );
+    value(&mut out, "cache_hit_ratio", snapshot.cache_hit_rate());
+
+    family(
+        &mut out,
+        "topic_subscribers",
+        "gauge",
+        "Subscribers of the Topics hosted on this node.",
+    );
+    let mut topics: Vec<_> = records
+        .addresses()
+        .into_iter()
+        .filter_map(|address| records.get(&address))
+        .map(|record| (record.address.to_string(), record.subscribers.len()))
+        .collect();
+    topics.sort();
+    for (topic, subscribers) in &topics {
+        sample(&mut out, "topic_subscribers", ("topic", topic), subscribers);
+    }
+    out
+}
+
+/// Label value of the Class, all Custom Classes share one.
+fn label(class: &Class) -> String {
+    let name = match class {
+        Class::Custom(_) => String::from("Custom"),
+        class => format!("{:?}", class),
+    };
+    let mut label = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            label.push('_');
+        }
+        label.push(c.to_ascii_lowercase());
+    }
+    label
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP actaeon_{} {}", name, help);
+    let _ = writeln!(out, "# TYPE actaeon_{} {}", name, kind);
+}
+
+fn value(out: &mut String, name: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "actaeon_{} {}", name, value);
+}
+
+fn sample(out: &mut String, name: &str, label: (&str, &str), value: impl std::fmt::Display) {
+    let _ = writeln!(
+        out,
+        "actaeon_{}{{{}=\"{}\"}} {}",
+        name, label.0, label.1, value
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Event;
+    use crate::node::Address;
+    use crate::record::Record;
+    use crate::router::BucketSnapshot;
+
+    #[test]
+    fn test_prometheus_label() {
+        assert_eq!(label(&Class::Action), "action");
+        assert_eq!(label(&Class::RecordTransfer), "record_transfer");
+        assert_eq!(label(&Class::Custom(7)), "custom");
+    }
+
+    #[test]
+    fn test_prometheus_render() {
+        let metrics = Metrics::new();
+        metrics.record(Event::Received(Some(Class::Action)));
+        metrics.record(Event::Received(Some(Class::Action)));
+        metrics.record(Event::Sent(Some(Class::Custom(3))));
+        metrics.record(Event::Sent(Some(Class::Custom(4))));
+        metrics.record(Event::Sent(None));
+        metrics.record(Event::Connections(2));
+        metrics.record(Event::CacheHit);
+        let table = RoutingSnapshot {
+            buckets: vec![BucketSnapshot {
+                lower: 0,
+                upper: 127,
+                depth: 1,
+                capacity: 20,
+                nodes: Vec::new(),
+                candidates: 0,
+            }],
+        };
+        let records = RecordBucket::new();
+        let topic = Address::random();
+        let mut record = Record::new(topic.clone());
+        record.subscribe(Address::random());
+        records.add(record);
+
+        let out = render(&metrics, &table, &records);
+        assert!(out.contains("# TYPE actaeon_wires_received_total counter\n"));
+        assert!(out.contains("actaeon_wires_received_total{class=\"action\"} 2\n"));
+        assert!(out.contains("actaeon_wires_sent_total{class=\"action\"} 0\n"));
+        assert!(out.contains("actaeon_wires_sent_total{class=\"custom\"} 2\n"));
+        assert!(out.contains("actaeon_connections 2\n"));
+        assert!(out.contains("actaeon_bucket_nodes{depth=\"1\",lower=\"0\",upper=\"127\"} 0\n"));
+        assert!(out.contains("actaeon_cache_hit_ratio 1\n"));
+        let line = format!("actaeon_topic_subscribers{{topic=\"{}\"}} 1\n", topic);
+        assert!(out.contains(&line));
+    }
+}
@@ -1,13 +1,43 @@
 //! # Records
 //!
-//! Represent a PubSub Topic this Node is responsible for. Currently
-//! this thread only has a common hashmap impl., in the future this
-//! will have to be extended with a dedicated thread and a file system
-//! interaction.
+//! Represent a PubSub Topic this Node is responsible for. The Records
+//! are kept in a common hashmap, optionally every change is also
+//! written through to a Journal on disk or a Storage, so they survive
+//! restarts.
+//!
+//! Each Record can have an owner and a Policy, which restrict who is
+//! allowed to subscribe to and publish on the Topic. Records also
+//! track which of their subscribers are present, this is only kept
+//! in memory. Nodes can register with a Role, publishers only get the
+//! list of subscribers without appearing in it themselves, read-only
+//! subscribers receive the broadcasts but can't publish.
+//!
+//! Records without subscribers get removed right away, optionally
+//! the ones without any activity for a while as well. The time of the
+//! last activity is also only kept in memory, restarting the node
+//! resets it.
 
+use crate::backend::{Storage, RECORDS};
+use crate::database::{DataTopic, Journal};
+use crate::error::Error;
 use crate::node::Address;
+use crate::presence::Presence;
+use crate::trace;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The Journal gets compacted once it has more entries than this
+/// factor times the number of Records.
+const COMPACTION_FACTOR: usize = 4;
+
+/// Minimum number of Journal entries before it gets compacted, avoids
+/// rewriting the file all the time with only few Records.
+const COMPACTION_MINIMUM: usize = 64;
+
+/// Marks the Role at the start of the body of a Subscribe message,
+/// subscribers with both Roles leave it out.
+const ROLE: u8 = 0xfe;
 
 /// Represents a Topic the Center Node is responsible for. The fields
 /// are basically identical to a normal Topic but the Records aren't
@@ -16,21 +46,85 @@ use std::sync::{Arc, Mutex};
 #[derive(Clone)]
 pub struct Record {
     /// The Address of the Record, that should satisfy
-    /// "should_be_local" with the current RT. At the moment there is
-    /// no system of republishing them or invalidating them later
-    /// since this implementation is (right now at least) only
-    /// suitable for small clusters.
+    /// "should_be_local" with the current RT. Records get republished
+    /// to the closest nodes periodically and garbage collected once
+    /// they are no longer used.
     pub address: Address,
     /// List of subscribers as a Vec of Addresses. The actual Link
     /// data will be fetched from the RT or messages will be
     /// distributed indirectly.
     pub subscribers: Vec<Address>,
+    /// Nodes that only publish on the Topic. They receive the list of
+    /// subscribers but are not part of it.
+    pub publishers: Vec<Address>,
+    /// Subscribers that only receive broadcasts, they don't get the
+    /// list of subscribers and can't publish. Always a subset of the
+    /// subscribers.
+    pub readers: Vec<Address>,
+    /// The node that created the Record with its first Subscribe.
+    /// Records that were created before access control existed don't
+    /// have an owner.
+    pub owner: Option<Address>,
+    /// Restricts who can subscribe to and publish on the Topic, only
+    /// the owner can change it.
+    pub policy: Policy,
+    /// Metadata and the time of the last announcement of every
+    /// present subscriber.
+    pub presence: HashMap<Address, (Vec<u8>, Instant)>,
+    /// Time of the last change, announcement or message on the Topic.
+    pub active: Instant,
+    /// Increases with every change of the members. Replicas only take
+    /// over the members of copies with a higher version, so stale
+    /// copies can't bring back nodes that left.
+    pub version: u64,
+}
+
+/// Access control for a Record. Publishing is enforced through the
+/// subscriber lists: Only nodes allowed to publish receive the full
+/// list, everybody else only gets the subscription confirmed. Actions
+/// reaching the node hosting the Record get checked directly.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Policy {
+    /// Everybody can subscribe and publish.
+    #[default]
+    Open,
+    /// Everybody can subscribe, only the owner can publish.
+    OwnerPublish,
+    /// Only the owner and the listed Addresses can subscribe and
+    /// publish.
+    Allowlist(Vec<Address>),
+}
+
+/// Role of a node on a Topic, requested with its Subscribe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Role {
+    /// Publishes and receives broadcasts.
+    #[default]
+    Both,
+    /// Only publishes, the node doesn't appear in the list of
+    /// subscribers and receives no broadcasts.
+    Publisher,
+    /// Only receives broadcasts, the Record rejects its messages.
+    Subscriber,
 }
 
 /// Multi "threadable" collection of all locally registered Records.
 /// TODO: Check if it has to be thread safe.
 #[derive(Clone)]
-pub struct RecordBucket(Arc<Mutex<HashMap<Address, Record>>>);
+pub struct RecordBucket(Arc<Mutex<Records>>);
+
+/// Content of the RecordBucket. The Journal is kept behind the same
+/// lock as the Records, so the order of the entries on disk always
+/// matches the order of the changes.
+struct Records {
+    /// All Records currently hosted on this node.
+    records: HashMap<Address, Record>,
+    /// Only available if persistence is enabled.
+    journal: Option<Journal>,
+    /// Replaces the Journal if the Records are persisted through a
+    /// Storage.
+    storage: Option<Arc<dyn Storage>>,
+}
 
 impl Record {
     /// Creates a new Record without subscribers.
@@ -38,25 +132,193 @@ impl Record {
         Self {
             address,
             subscribers: Vec::new(),
+            publishers: Vec::new(),
+            readers: Vec::new(),
+            owner: None,
+            policy: Policy::Open,
+            presence: HashMap::new(),
+            active: Instant::now(),
+            version: 0,
+        }
+    }
+
+    /// Creates a new Record owned by the given Address, usually the
+    /// source of the first Subscribe message.
+    pub fn owned(address: Address, owner: Address, policy: Policy) -> Self {
+        Self {
+            address,
+            subscribers: Vec::new(),
+            publishers: Vec::new(),
+            readers: Vec::new(),
+            owner: Some(owner),
+            policy,
+            presence: HashMap::new(),
+            active: Instant::now(),
+            version: 0,
         }
     }
 
     /// Adds the provided Address to the list of subscribers.
     pub fn subscribe(&mut self, subscriber: Address) {
         if !self.contains(&subscriber) {
-            self.subscribers.push(subscriber)
+            self.subscribers.push(subscriber);
+            self.version += 1;
         }
+        self.touch();
+    }
+
+    /// Adds the Address with the Role, replacing the one it had
+    /// before. Publishers get removed from the list of subscribers.
+    pub fn register(&mut self, address: Address, role: Role) {
+        if self.role(&address) != Some(role) {
+            self.version += 1;
+        }
+        self.readers.retain(|reader| reader != &address);
+        match role {
+            Role::Publisher => {
+                self.subscribers.retain(|subscriber| subscriber != &address);
+                self.presence.remove(&address);
+                if !self.publishers.contains(&address) {
+                    self.publishers.push(address);
+                }
+            }
+            Role::Both | Role::Subscriber => {
+                self.publishers.retain(|publisher| publisher != &address);
+                if role == Role::Subscriber {
+                    self.readers.push(address.clone());
+                }
+                self.subscribe(address);
+            }
+        }
+        self.touch();
     }
 
     /// Removes the provided Address to the list of subscribers.
     pub fn unsubscribe(&mut self, subscriber: &Address) {
+        if self.is_member(subscriber) {
+            self.version += 1;
+        }
         let index = self.subscribers.iter().position(|e| e == subscriber);
-        match index {
-            Some(i) => {
-                self.subscribers.remove(i);
+        if let Some(i) = index {
+            self.subscribers.remove(i);
+        }
+        self.publishers.retain(|publisher| publisher != subscriber);
+        self.readers.retain(|reader| reader != subscriber);
+        self.presence.remove(subscriber);
+        self.touch();
+    }
+
+    /// Takes over the members of another copy of the Record with a
+    /// higher version, older copies are ignored. Copies with the same
+    /// version changed concurrently, everybody the other one knows
+    /// about gets added and the version increased should that change
+    /// anything. Addresses that are already known keep their Role,
+    /// ones the local Policy doesn't allow to subscribe are left out.
+    pub fn merge(&mut self, other: &Record) {
+        if other.version < self.version {
+            return;
+        }
+        if other.version > self.version {
+            self.subscribers = other.subscribers.clone();
+            self.publishers = other.publishers.clone();
+            self.readers = other.readers.clone();
+            self.enforce();
+            let subscribers = &self.subscribers;
+            self.presence
+                .retain(|address, _| subscribers.contains(address));
+            self.version = other.version;
+            self.touch();
+            return;
+        }
+        let version = self.version;
+        for subscriber in &other.subscribers {
+            if !self.is_member(subscriber) && self.may_subscribe(subscriber) {
+                self.subscribe(subscriber.clone());
+                if other.readers.contains(subscriber) {
+                    self.readers.push(subscriber.clone());
+                }
+            }
+        }
+        for publisher in &other.publishers {
+            if !self.is_member(publisher) && self.may_subscribe(publisher) {
+                self.publishers.push(publisher.clone());
+                self.version += 1;
             }
-            None => {}
         }
+        // Every added member increased it, the merge only counts once.
+        self.version = version + (self.version > version) as u64;
+    }
+
+    /// Removes all members the Policy doesn't allow to subscribe,
+    /// copies from other nodes might contain them.
+    pub fn enforce(&mut self) {
+        let denied: Vec<Address> = self
+            .members()
+            .into_iter()
+            .filter(|member| !self.may_subscribe(member))
+            .collect();
+        for list in [
+            &mut self.subscribers,
+            &mut self.publishers,
+            &mut self.readers,
+        ] {
+            list.retain(|address| !denied.contains(address));
+        }
+        self.presence.retain(|address, _| !denied.contains(address));
+    }
+
+    /// Marks the Record as active, which delays its garbage
+    /// collection.
+    pub fn touch(&mut self) {
+        self.active = Instant::now();
+    }
+
+    /// Checks if there hasn't been any activity on the Record for at
+    /// least the given Duration.
+    pub fn is_idle(&self, expiry: Duration) -> bool {
+        self.active.elapsed() >= expiry
+    }
+
+    /// Marks the subscriber as present with the metadata. Returns true
+    /// if it just joined or changed its metadata, announcements of
+    /// everybody else are ignored.
+    pub fn announce(&mut self, subscriber: Address, metadata: Vec<u8>) -> bool {
+        if !self.contains(&subscriber) {
+            return false;
+        }
+        self.touch();
+        match self
+            .presence
+            .insert(subscriber, (metadata.clone(), Instant::now()))
+        {
+            Some((previous, _)) => previous != metadata,
+            None => true,
+        }
+    }
+
+    /// Removes all subscribers that haven't announced themselves for
+    /// the TTL and returns their Addresses.
+    pub fn expire(&mut self, ttl: Duration) -> Vec<Address> {
+        let expired: Vec<Address> = self
+            .presence
+            .iter()
+            .filter(|(_, (_, seen))| seen.elapsed() >= ttl)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in &expired {
+            self.presence.remove(address);
+        }
+        expired
+    }
+
+    /// All present subscribers with their metadata.
+    pub fn present(&self) -> Vec<Presence> {
+        self.presence
+            .iter()
+            .map(|(address, (metadata, seen))| {
+                Presence::new(address.clone(), metadata.clone(), seen.elapsed())
+            })
+            .collect()
     }
 
     /// Checks if the Address (the Subscriber) exists in the list of
@@ -64,13 +326,278 @@ impl Record {
     pub fn contains(&self, query: &Address) -> bool {
         self.subscribers.contains(query)
     }
+
+    /// Checks if the Address is a subscriber or a publisher.
+    pub fn is_member(&self, query: &Address) -> bool {
+        self.contains(query) || self.publishers.contains(query)
+    }
+
+    /// Everybody who gets informed about changes of the subscribers,
+    /// the subscribers followed by the publishers.
+    pub fn members(&self) -> Vec<Address> {
+        let mut members = self.subscribers.clone();
+        members.extend(self.publishers.iter().cloned());
+        members
+    }
+
+    /// Checks if neither subscribers nor publishers are left.
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty() && self.publishers.is_empty()
+    }
+
+    /// Role the Address registered with, None if it isn't a member.
+    pub fn role(&self, query: &Address) -> Option<Role> {
+        if self.publishers.contains(query) {
+            Some(Role::Publisher)
+        } else if self.readers.contains(query) {
+            Some(Role::Subscriber)
+        } else if self.contains(query) {
+            Some(Role::Both)
+        } else {
+            None
+        }
+    }
+
+    /// Hash of the set of subscribers, independent of their order.
+    /// Replicas of a Record compare it to find out if they differ.
+    /// Roles only get included if there are any, so it stays the same
+    /// for Records without them.
+    pub fn digest(&self) -> [u8; 32] {
+        let sorted = |addresses: &Vec<Address>| {
+            let mut sorted: Vec<Address> = addresses.clone();
+            sorted.sort();
+            sorted
+        };
+        let mut hasher = blake3::Hasher::new();
+        for subscriber in sorted(&self.subscribers) {
+            hasher.update(&subscriber.as_bytes());
+        }
+        if !self.publishers.is_empty() || !self.readers.is_empty() {
+            hasher.update(&[ROLE]);
+            for publisher in sorted(&self.publishers) {
+                hasher.update(&publisher.as_bytes());
+            }
+            hasher.update(&[ROLE]);
+            for reader in sorted(&self.readers) {
+                hasher.update(&reader.as_bytes());
+            }
+        }
+        *hasher.finalize().as_bytes()
+    }
+
+    /// Checks if the owner of the Record matches the Address.
+    pub fn is_owner(&self, query: &Address) -> bool {
+        self.owner.as_ref() == Some(query)
+    }
+
+    /// Checks if the Address is allowed to subscribe to the Topic.
+    pub fn may_subscribe(&self, query: &Address) -> bool {
+        match &self.policy {
+            Policy::Open | Policy::OwnerPublish => true,
+            Policy::Allowlist(allowed) => self.is_owner(query) || allowed.contains(query),
+        }
+    }
+
+    /// Checks if the Address is allowed to publish on the Topic,
+    /// read-only subscribers never are.
+    pub fn may_publish(&self, query: &Address) -> bool {
+        if self.readers.contains(query) {
+            return false;
+        }
+        match &self.policy {
+            Policy::Open => true,
+            Policy::OwnerPublish => self.is_owner(query),
+            Policy::Allowlist(allowed) => self.is_owner(query) || allowed.contains(query),
+        }
+    }
+}
+
+impl Policy {
+    /// Serializes the Policy. Structure:
+    /// 1 byte: Kind (0 = Open, 1 = OwnerPublish, 2 = Allowlist),
+    /// n * 32 bytes: Allowed Addresses (only for Allowlist)
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Self::Open => vec![0],
+            Self::OwnerPublish => vec![1],
+            Self::Allowlist(allowed) => {
+                let mut data = vec![2];
+                for address in allowed {
+                    data.append(&mut address.as_bytes().to_vec());
+                }
+                data
+            }
+        }
+    }
+
+    /// Parses a serialized Policy, fails if the kind is unknown or
+    /// the number of bytes doesn't match.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        match data.first() {
+            Some(0) if data.len() == 1 => Ok(Self::Open),
+            Some(1) if data.len() == 1 => Ok(Self::OwnerPublish),
+            Some(2) if (data.len() - 1).is_multiple_of(32) => {
+                let mut allowed = Vec::new();
+                for chunk in data[1..].chunks(32) {
+                    allowed.push(Address::from_slice(chunk)?);
+                }
+                Ok(Self::Allowlist(allowed))
+            }
+            _ => Err(Error::Invalid(String::from("invalid policy"))),
+        }
+    }
+}
+
+impl Role {
+    /// Puts the Role in front of the body of a Subscribe message,
+    /// nothing is added for Both.
+    pub fn prefix(&self, body: Vec<u8>) -> Vec<u8> {
+        let kind = match self {
+            Self::Both => return body,
+            Self::Publisher => 1,
+            Self::Subscriber => 2,
+        };
+        let mut data = vec![ROLE, kind];
+        data.extend(body);
+        data
+    }
+
+    /// Splits the Role off the body of a Subscribe message, bodies
+    /// without one (or an unknown one) belong to subscribers with
+    /// both Roles.
+    pub fn split(body: &[u8]) -> (Self, &[u8]) {
+        match body {
+            [ROLE, 1, rest @ ..] => (Self::Publisher, rest),
+            [ROLE, 2, rest @ ..] => (Self::Subscriber, rest),
+            _ => (Self::Both, body),
+        }
+    }
+}
+
+impl Default for RecordBucket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<&Record> for DataTopic {
+    fn from(record: &Record) -> Self {
+        let mut topic = DataTopic::new(record.address.clone(), record.subscribers.clone());
+        topic.publishers = record.publishers.clone();
+        topic.readers = record.readers.clone();
+        topic.owner = record.owner.clone();
+        topic.policy = record.policy.clone();
+        topic.version = record.version;
+        topic
+    }
+}
+
+impl From<DataTopic> for Record {
+    fn from(topic: DataTopic) -> Self {
+        Self {
+            address: topic.address,
+            subscribers: topic.subscribers,
+            publishers: topic.publishers,
+            readers: topic.readers,
+            owner: topic.owner,
+            policy: topic.policy,
+            presence: HashMap::new(),
+            active: Instant::now(),
+            version: topic.version,
+        }
+    }
+}
+
+impl Records {
+    /// Writes the current state of a Record (or its removal) to the
+    /// Journal and compacts it should it have grown too large. Errors
+    /// only get logged, the in-memory Records stay usable.
+    fn persist(&mut self, address: &Address) {
+        if let Some(storage) = &self.storage {
+            let result = match self.records.get(address) {
+                Some(record) => storage.put(
+                    RECORDS,
+                    &address.as_bytes(),
+                    DataTopic::from(record).as_bytes(),
+                ),
+                None => storage.delete(RECORDS, &address.as_bytes()),
+            };
+            if let Err(e) = result {
+                trace::warn!("unable to store record: {}", e);
+            }
+        }
+        if let Some(journal) = &mut self.journal {
+            let topic = match self.records.get(address) {
+                Some(record) => DataTopic::from(record),
+                None => DataTopic::removed(address.clone()),
+            };
+            if let Err(e) = journal.append(&topic) {
+                trace::warn!("unable to write record journal: {}", e);
+            }
+            let limit = COMPACTION_MINIMUM.max(COMPACTION_FACTOR * self.records.len());
+            if journal.len() > limit {
+                let topics: Vec<DataTopic> = self.records.values().map(DataTopic::from).collect();
+                if let Err(e) = journal.compact(&topics) {
+                    trace::warn!("unable to compact record journal: {}", e);
+                }
+            }
+        }
+    }
 }
 
 impl RecordBucket {
     /// Creates a new RecordBucket. It contains thread safety and a
     /// Mutex, so it doesn't have to be wrappen again.
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(HashMap::new())))
+        Self(Arc::new(Mutex::new(Records {
+            records: HashMap::new(),
+            journal: None,
+            storage: None,
+        })))
+    }
+
+    /// Creates a RecordBucket that is backed by the Journal at the
+    /// given path. All Records stored in it are loaded and every
+    /// later change gets written through to it. Fails if the file
+    /// can't be accessed or isn't a Journal, corrupted entries only
+    /// get dropped.
+    pub fn open(path: &str) -> Result<Self, Error> {
+        let (journal, topics) = Journal::open(path)?;
+        trace::info!("restoring {} records from journal.", topics.len());
+        let records = topics
+            .into_iter()
+            .map(|topic| (topic.address.clone(), Record::from(topic)))
+            .collect();
+        Ok(Self(Arc::new(Mutex::new(Records {
+            records,
+            journal: Some(journal),
+            storage: None,
+        }))))
+    }
+
+    /// Creates a RecordBucket that is backed by the Storage, every
+    /// Record is stored under its Address. All Records in it are
+    /// loaded, invalid ones get dropped. Fails if the Storage can't
+    /// be read.
+    pub fn with_storage(storage: Arc<dyn Storage>) -> Result<Self, Error> {
+        let mut records = HashMap::new();
+        for (key, value) in storage.iterate(RECORDS)? {
+            match DataTopic::from_bytes(&value) {
+                Ok(topic) if !topic.removed => {
+                    records.insert(topic.address.clone(), Record::from(topic));
+                }
+                _ => {
+                    trace::warn!("dropping invalid stored record.");
+                    let _ = storage.delete(RECORDS, &key);
+                }
+            }
+        }
+        trace::info!("restoring {} records from storage.", records.len());
+        Ok(Self(Arc::new(Mutex::new(Records {
+            records,
+            journal: None,
+            storage: Some(storage),
+        }))))
     }
 
     /// Adds a new record to the Bucket. An internal thread error will
@@ -79,10 +606,12 @@ impl RecordBucket {
     pub fn add(&self, record: Record) {
         match self.0.lock() {
             Ok(mut records) => {
-                records.insert(record.address.clone(), record);
+                let address = record.address.clone();
+                records.records.insert(address.clone(), record);
+                records.persist(&address);
             }
             Err(e) => {
-                log::warn!(
+                trace::warn!(
                     "unable to lock thread, another thread has encountered an error: {}",
                     e
                 );
@@ -96,10 +625,12 @@ impl RecordBucket {
     pub fn remove(&self, address: &Address) {
         match self.0.lock() {
             Ok(mut records) => {
-                records.remove(address);
+                if records.records.remove(address).is_some() {
+                    records.persist(address);
+                }
             }
             Err(e) => {
-                log::warn!(
+                trace::warn!(
                     "unable to lock thread, another thread has encountered an error: {}",
                     e
                 );
@@ -113,9 +644,9 @@ impl RecordBucket {
     /// globally restarting the core threads.
     pub fn contains(&self, address: &Address) -> bool {
         match self.0.lock() {
-            Ok(records) => records.contains_key(address),
+            Ok(records) => records.records.contains_key(address),
             Err(e) => {
-                log::warn!(
+                trace::warn!(
                     "unable to lock thread, another thread
         has encountered an error: {}",
                     e
@@ -130,12 +661,9 @@ impl RecordBucket {
     /// method of globally restarting the core threads.
     pub fn get(&self, address: &Address) -> Option<Record> {
         match self.0.lock() {
-            Ok(records) => match records.get(address) {
-                Some(record) => Some(record.clone()),
-                None => None,
-            },
+            Ok(records) => records.records.get(address).cloned(),
             Err(e) => {
-                log::warn!(
+                trace::warn!(
                     "unable to lock thread, another thread has encountered an error: {}",
                     e
                 );
@@ -144,6 +672,20 @@ impl RecordBucket {
         }
     }
 
+    /// Returns the Addresses of all Records in the Bucket.
+    pub fn addresses(&self) -> Vec<Address> {
+        match self.0.lock() {
+            Ok(records) => records.records.keys().cloned().collect(),
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
     /// Since getting a mutable reference to the Record isn't possible
     /// outside the lock, direct functions on the RecordBucket can be
     /// used. They take in the Address of the Record as their first
@@ -153,14 +695,149 @@ impl RecordBucket {
     /// core threads.
     pub fn subscribe(&self, record: &Address, subscriber: Address) {
         match self.0.lock() {
-            Ok(mut records) => match (*records).get_mut(&record) {
-                Some(record) => {
-                    (*record).subscribe(subscriber);
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(record) {
+                    found.subscribe(subscriber);
+                    records.persist(record);
                 }
-                None => {}
+            }
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Adds the Address to the Record with the Role, see
+    /// Record::register.
+    pub fn register(&self, record: &Address, address: Address, role: Role) {
+        match self.0.lock() {
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(record) {
+                    found.register(address, role);
+                    records.persist(record);
+                }
+            }
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Merges another copy of the Record into the local one, see
+    /// Record::merge. Nothing happens if it doesn't exist locally.
+    pub fn merge(&self, other: &Record) {
+        match self.0.lock() {
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(&other.address) {
+                    found.merge(other);
+                    records.persist(&other.address);
+                }
+            }
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Marks the subscriber of the Record as present, see
+    /// Record::announce. Presence isn't written to the Journal.
+    pub fn announce(&self, record: &Address, subscriber: Address, metadata: Vec<u8>) -> bool {
+        match self.0.lock() {
+            Ok(mut records) => match records.records.get_mut(record) {
+                Some(found) => found.announce(subscriber, metadata),
+                None => false,
             },
             Err(e) => {
-                log::warn!(
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// Expires the presence of all Records and returns the Addresses
+    /// of each Record with the subscribers that are no longer
+    /// present, Records without any are left out.
+    pub fn expire(&self, ttl: Duration) -> Vec<(Address, Vec<Address>)> {
+        match self.0.lock() {
+            Ok(mut records) => records
+                .records
+                .iter_mut()
+                .map(|(address, record)| (address.clone(), record.expire(ttl)))
+                .filter(|(_, expired)| !expired.is_empty())
+                .collect(),
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Marks the Record as active, see Record::touch. Activity isn't
+    /// written to the Journal.
+    pub fn touch(&self, record: &Address) {
+        match self.0.lock() {
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(record) {
+                    found.touch();
+                }
+            }
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+            }
+        }
+    }
+
+    /// Returns the Addresses of all Records that haven't been active
+    /// for at least the given Duration. They stay in the Bucket, the
+    /// caller decides what to do with them.
+    pub fn idle(&self, expiry: Duration) -> Vec<Address> {
+        match self.0.lock() {
+            Ok(records) => records
+                .records
+                .values()
+                .filter(|record| record.is_idle(expiry))
+                .map(|record| record.address.clone())
+                .collect(),
+            Err(e) => {
+                trace::warn!(
+                    "unable to lock thread, another thread has encountered an error: {}",
+                    e
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Replaces the Policy of an existing Record, the caller has to
+    /// make sure the change was requested by the owner.
+    pub fn set_policy(&self, record: &Address, policy: Policy) {
+        match self.0.lock() {
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(record) {
+                    found.policy = policy;
+                    records.persist(record);
+                }
+            }
+            Err(e) => {
+                trace::warn!(
                     "unable to lock thread, another thread has encountered an error: {}",
                     e
                 );
@@ -177,14 +854,14 @@ impl RecordBucket {
     /// core threads.
     pub fn unsubscribe(&self, record: &Address, subscriber: &Address) {
         match self.0.lock() {
-            Ok(mut records) => match (*records).get_mut(&record) {
-                Some(record) => {
-                    (*record).unsubscribe(subscriber);
+            Ok(mut records) => {
+                if let Some(found) = records.records.get_mut(record) {
+                    found.unsubscribe(subscriber);
+                    records.persist(record);
                 }
-                None => {}
-            },
+            }
             Err(e) => {
-                log::warn!(
+                trace::warn!(
                     "unable to lock thread, another thread has encountered an error: {}",
                     e
                 );
@@ -196,12 +873,112 @@ impl RecordBucket {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::backend::MemoryStorage;
+
+    #[test]
+    fn test_record_digest() {
+        let (first, second) = (Address::random(), Address::random());
+        let mut left = Record::new(Address::random());
+        left.subscribe(first.clone());
+        left.subscribe(second.clone());
+        let mut right = Record::new(left.address.clone());
+        right.subscribe(second);
+        assert_ne!(left.digest(), right.digest());
+        right.subscribe(first);
+        assert_eq!(left.digest(), right.digest());
+        right.unsubscribe(&left.subscribers[0]);
+        assert_ne!(left.digest(), right.digest());
+    }
+
+    #[test]
+    fn test_record_version() {
+        let (first, second) = (Address::random(), Address::random());
+        let mut record = Record::new(Address::random());
+        record.subscribe(first.clone());
+        record.subscribe(first.clone());
+        assert_eq!(record.version, 1);
+        let stale = record.clone();
+        record.subscribe(second.clone());
+        record.unsubscribe(&first);
+        assert_eq!(record.version, 3);
+
+        let mut copy = record.clone();
+        copy.merge(&stale);
+        assert_eq!(copy.subscribers, vec![second.clone()]);
+        assert_eq!(copy.version, 3);
+
+        let mut copy = stale.clone();
+        copy.announce(first.clone(), Vec::new());
+        copy.merge(&record);
+        assert_eq!(copy.subscribers, vec![second.clone()]);
+        assert!(copy.present().is_empty());
+        assert_eq!(copy.version, 3);
+
+        let mut concurrent = stale;
+        concurrent.subscribe(Address::random());
+        concurrent.version = record.version;
+        let mut copy = record.clone();
+        copy.merge(&concurrent);
+        assert_eq!(copy.subscribers.len(), 3);
+        assert_eq!(copy.version, 4);
+        copy.merge(&concurrent);
+        assert_eq!(copy.version, 4);
+    }
+
+    #[test]
+    fn test_record_roles() {
+        let (publisher, reader, both) = (Address::random(), Address::random(), Address::random());
+        let mut record = Record::new(Address::random());
+        let digest = record.digest();
+        record.register(publisher.clone(), Role::Publisher);
+        assert_ne!(record.digest(), digest);
+        record.register(reader.clone(), Role::Subscriber);
+        record.register(both.clone(), Role::Both);
+        assert_eq!(record.subscribers, vec![reader.clone(), both.clone()]);
+        assert_eq!(record.members().len(), 3);
+        assert_eq!(record.role(&publisher), Some(Role::Publisher));
+        assert_eq!(record.role(&reader), Some(Role::Subscriber));
+        assert_eq!(record.role(&both), Some(Role::Both));
+        assert!(record.may_publish(&publisher));
+        assert!(!record.may_publish(&reader));
+        assert!(!record.announce(publisher.clone(), Vec::new()));
+
+        // Concurrent changes, the known Role is kept.
+        let mut copy = Record::new(record.address.clone());
+        copy.register(reader.clone(), Role::Both);
+        copy.version = record.version;
+        copy.merge(&record);
+        assert_eq!(copy.role(&reader), Some(Role::Both));
+        assert_eq!(copy.role(&publisher), Some(Role::Publisher));
+        copy.register(reader.clone(), Role::Subscriber);
+        assert_eq!(copy.digest(), record.digest());
+
+        record.register(reader.clone(), Role::Publisher);
+        assert!(!record.contains(&reader));
+        record.unsubscribe(&reader);
+        record.unsubscribe(&publisher);
+        assert!(!record.is_member(&reader));
+        assert!(!record.is_empty());
+        record.unsubscribe(&both);
+        assert!(record.is_empty());
+    }
+
+    #[test]
+    fn test_role_prefix() {
+        let body = Policy::OwnerPublish.as_bytes();
+        assert_eq!(Role::Both.prefix(body.clone()), body);
+        for role in [Role::Both, Role::Publisher, Role::Subscriber] {
+            let data = role.prefix(body.clone());
+            assert_eq!(Role::split(&data), (role, body.as_slice()));
+        }
+        assert_eq!(Role::split(&[]), (Role::Both, &[][..]));
+    }
 
     #[test]
     fn test_bucket_empty() {
         let bucket = RecordBucket::new();
         let query = Address::random();
-        assert_eq!(bucket.get(&query).is_none(), true);
+        assert!(bucket.get(&query).is_none());
     }
 
     #[test]
@@ -210,8 +987,9 @@ mod tests {
         let addr = Address::random();
         let record = Record::new(addr.clone());
         bucket.add(record);
-        assert_eq!(bucket.contains(&addr), true);
-        assert_eq!(bucket.contains(&Address::random()), false);
+        assert!(bucket.contains(&addr));
+        assert!(!bucket.contains(&Address::random()));
+        assert_eq!(bucket.addresses(), vec![addr]);
     }
 
     #[test]
@@ -223,8 +1001,8 @@ mod tests {
         let subscriber = Address::random();
         bucket.subscribe(&record_addr, subscriber.clone());
         let record = bucket.get(&record_addr);
-        assert_eq!(record.is_none(), false);
-        assert_eq!(record.unwrap().contains(&subscriber), true);
+        assert!(record.is_some());
+        assert!(record.unwrap().contains(&subscriber));
     }
 
     #[test]
@@ -237,6 +1015,198 @@ mod tests {
         bucket.subscribe(&record_addr, subscriber.clone());
         bucket.unsubscribe(&record_addr, &subscriber);
         let record = bucket.get(&record_addr);
-        assert_eq!(record.unwrap().contains(&subscriber), false);
+        assert!(!record.unwrap().contains(&subscriber));
+    }
+
+    #[test]
+    fn test_bucket_presence() {
+        let bucket = RecordBucket::new();
+        let record_addr = Address::random();
+        bucket.add(Record::new(record_addr.clone()));
+        let subscriber = Address::random();
+        assert!(!bucket.announce(&record_addr, subscriber.clone(), vec![1]));
+        bucket.subscribe(&record_addr, subscriber.clone());
+        assert!(bucket.announce(&record_addr, subscriber.clone(), vec![1]));
+        assert!(!bucket.announce(&record_addr, subscriber.clone(), vec![1]));
+        assert!(bucket.announce(&record_addr, subscriber.clone(), vec![2]));
+        let present = bucket.get(&record_addr).unwrap().present();
+        assert_eq!(present.len(), 1);
+        assert_eq!(present[0].address, subscriber);
+        assert_eq!(present[0].metadata, vec![2]);
+
+        assert!(bucket.expire(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        let expired = bucket.expire(Duration::from_millis(10));
+        assert_eq!(
+            expired,
+            vec![(record_addr.clone(), vec![subscriber.clone()])]
+        );
+        assert!(bucket.get(&record_addr).unwrap().present().is_empty());
+
+        bucket.announce(&record_addr, subscriber.clone(), Vec::new());
+        bucket.unsubscribe(&record_addr, &subscriber);
+        assert!(bucket.get(&record_addr).unwrap().presence.is_empty());
+    }
+
+    #[test]
+    fn test_bucket_idle() {
+        let bucket = RecordBucket::new();
+        let (active, idle) = (Address::random(), Address::random());
+        bucket.add(Record::new(active.clone()));
+        bucket.add(Record::new(idle.clone()));
+        assert!(bucket.idle(Duration::from_secs(60)).is_empty());
+        std::thread::sleep(Duration::from_millis(20));
+        bucket.touch(&active);
+        assert_eq!(bucket.idle(Duration::from_millis(10)), vec![idle.clone()]);
+        bucket.subscribe(&idle, Address::random());
+        assert!(bucket.idle(Duration::from_millis(10)).is_empty());
+    }
+
+    #[test]
+    fn test_bucket_persist() {
+        let path = temp_path();
+        let bucket = RecordBucket::open(&path).unwrap();
+        let record_addr = Address::random();
+        let removed_addr = Address::random();
+        bucket.add(Record::new(record_addr.clone()));
+        bucket.add(Record::new(removed_addr.clone()));
+        let subscriber = Address::random();
+        bucket.subscribe(&record_addr, subscriber.clone());
+        bucket.remove(&removed_addr);
+        drop(bucket);
+
+        let bucket = RecordBucket::open(&path).unwrap();
+        assert!(bucket.get(&record_addr).unwrap().contains(&subscriber));
+        assert!(!bucket.contains(&removed_addr));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_bucket_storage() {
+        let storage = MemoryStorage::new();
+        let bucket = RecordBucket::with_storage(Arc::new(storage.clone())).unwrap();
+        let record_addr = Address::random();
+        let removed_addr = Address::random();
+        bucket.add(Record::owned(
+            record_addr.clone(),
+            Address::random(),
+            Policy::OwnerPublish,
+        ));
+        bucket.add(Record::new(removed_addr.clone()));
+        let subscriber = Address::random();
+        bucket.subscribe(&record_addr, subscriber.clone());
+        bucket.remove(&removed_addr);
+        drop(bucket);
+        assert_eq!(storage.iterate(RECORDS).unwrap().len(), 1);
+
+        let bucket = RecordBucket::with_storage(Arc::new(storage)).unwrap();
+        let record = bucket.get(&record_addr).unwrap();
+        assert!(record.contains(&subscriber));
+        assert_eq!(record.policy, Policy::OwnerPublish);
+        assert!(!bucket.contains(&removed_addr));
+    }
+
+    #[test]
+    fn test_bucket_compaction() {
+        let path = temp_path();
+        let bucket = RecordBucket::open(&path).unwrap();
+        let record_addr = Address::random();
+        bucket.add(Record::new(record_addr.clone()));
+        let subscriber = Address::random();
+        for _ in 0..1000 {
+            bucket.subscribe(&record_addr, subscriber.clone());
+            bucket.unsubscribe(&record_addr, &subscriber);
+        }
+        let length = std::fs::metadata(&path).unwrap().len();
+        assert!(length < (COMPACTION_MINIMUM as u64 + 1) * 100);
+        drop(bucket);
+
+        let bucket = RecordBucket::open(&path).unwrap();
+        assert!(!bucket.get(&record_addr).unwrap().contains(&subscriber));
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_record_policy() {
+        let owner = Address::random();
+        let other = Address::random();
+        let mut record = Record::owned(Address::random(), owner.clone(), Policy::Open);
+        assert!(record.may_publish(&other));
+        record.policy = Policy::OwnerPublish;
+        assert!(record.may_subscribe(&other));
+        assert!(!record.may_publish(&other));
+        assert!(record.may_publish(&owner));
+        record.policy = Policy::Allowlist(vec![other.clone()]);
+        assert!(record.may_publish(&other));
+        assert!(record.may_subscribe(&owner));
+        assert!(!record.may_subscribe(&Address::random()));
+        assert!(!Record::new(Address::random()).is_owner(&owner));
+    }
+
+    #[test]
+    fn test_record_enforce() {
+        let (owner, allowed, denied) = (Address::random(), Address::random(), Address::random());
+        let policy = Policy::Allowlist(vec![allowed.clone()]);
+        let mut record = Record::owned(Address::random(), owner.clone(), policy);
+        record.subscribe(owner.clone());
+        let mut remote = Record::new(record.address.clone());
+        remote.subscribe(allowed.clone());
+        remote.register(denied.clone(), Role::Publisher);
+        remote.version = record.version;
+        record.merge(&remote);
+        assert_eq!(record.members(), vec![owner.clone(), allowed.clone()]);
+
+        remote.subscribe(Address::random());
+        remote.version = record.version + 1;
+        record.merge(&remote);
+        assert_eq!(record.members(), vec![allowed.clone()]);
+        assert!(record.is_owner(&owner));
+
+        let mut copy = Record::from(DataTopic::from(&remote));
+        copy.owner = Some(owner);
+        copy.policy = record.policy.clone();
+        copy.enforce();
+        assert_eq!(copy.members(), vec![allowed]);
+    }
+
+    #[test]
+    fn test_policy_bytes() {
+        for policy in [
+            Policy::Open,
+            Policy::OwnerPublish,
+            Policy::Allowlist(vec![Address::random(), Address::random()]),
+        ] {
+            assert_eq!(Policy::from_bytes(&policy.as_bytes()).unwrap(), policy);
+        }
+        assert!(Policy::from_bytes(&[]).is_err());
+        assert!(Policy::from_bytes(&[1, 0]).is_err());
+        assert!(Policy::from_bytes(&[3]).is_err());
+    }
+
+    #[test]
+    fn test_bucket_persist_policy() {
+        let path = temp_path();
+        let bucket = RecordBucket::open(&path).unwrap();
+        let record_addr = Address::random();
+        let owner = Address::random();
+        bucket.add(Record::owned(
+            record_addr.clone(),
+            owner.clone(),
+            Policy::OwnerPublish,
+        ));
+        bucket.set_policy(&record_addr, Policy::Allowlist(vec![owner.clone()]));
+        drop(bucket);
+
+        let bucket = RecordBucket::open(&path).unwrap();
+        let record = bucket.get(&record_addr).unwrap();
+        assert!(record.is_owner(&owner));
+        assert_eq!(record.policy, Policy::Allowlist(vec![owner]));
+        let _ = std::fs::remove_file(path);
+    }
+
+    fn temp_path() -> String {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+        path.to_string_lossy().to_string()
     }
 }
@@ -0,0 +1,161 @@
+//! # Retry
+//!
+//! Right after startup the routing table is often still empty, so
+//! the Listener can't find any node for outgoing Transactions. Instead
+//! of dropping them it keeps them in a bounded Retries queue and
+//! tries again with an exponential backoff. Once a Transaction has
+//! been waiting for longer than the configured TTL (or its own
+//! deadline passed) it is given up and reported as failed.
+
+use crate::transaction::Transaction;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Delay before the first retry, it doubles with every attempt.
+const RETRY_BASE: Duration = Duration::from_millis(250);
+
+/// Upper limit for the delay between two retries.
+const RETRY_MAX: Duration = Duration::from_secs(8);
+
+/// Transactions waiting for another attempt at finding a node.
+#[derive(Debug)]
+pub struct Retries {
+    entries: Vec<Entry>,
+    /// Maximum number of waiting Transactions.
+    capacity: usize,
+    /// Time after which a Transaction is given up.
+    ttl: Duration,
+}
+
+/// A waiting Transaction with its retry state.
+#[derive(Debug)]
+struct Entry {
+    transaction: Transaction,
+    attempts: u32,
+    due: Instant,
+    expires: Instant,
+}
+
+impl Retries {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            entries: Vec::new(),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Queues the Transaction for its first retry. It gets returned
+    /// should the queue be full or retrying be disabled.
+    pub fn push(&mut self, transaction: Transaction, now: Instant) -> Option<Transaction> {
+        if self.entries.len() >= self.capacity || self.ttl.is_zero() {
+            return Some(transaction);
+        }
+        self.entries.push(Entry {
+            transaction,
+            attempts: 0,
+            due: now + RETRY_BASE,
+            expires: now + self.ttl,
+        });
+        None
+    }
+
+    /// Returns copies of all Transactions that are due again and
+    /// schedules their next attempt, they stay queued until they are
+    /// removed. Expired Transactions get removed and returned
+    /// separately.
+    pub fn due(&mut self, now: Instant) -> (Vec<Transaction>, Vec<Transaction>) {
+        let mut due = Vec::new();
+        let mut expired = Vec::new();
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &mut self.entries[i];
+            if entry.expires <= now || entry.transaction.is_expired() {
+                expired.push(self.entries.swap_remove(i).transaction);
+                continue;
+            }
+            if entry.due <= now {
+                entry.attempts += 1;
+                let delay = RETRY_BASE * 2u32.saturating_pow(entry.attempts);
+                entry.due = now + delay.min(RETRY_MAX);
+                due.push(entry.transaction.clone());
+            }
+            i += 1;
+        }
+        (due, expired)
+    }
+
+    /// Time the next Transaction is due or expires, if any is
+    /// waiting.
+    pub fn next(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .map(|entry| entry.due.min(entry.expires))
+            .min()
+    }
+
+    /// Removes the Transaction once a node was found for it.
+    pub fn remove(&mut self, uuid: &Uuid) {
+        self.entries.retain(|entry| &entry.transaction.uuid != uuid);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::node::Address;
+    use crate::transaction::Class;
+
+    fn transaction() -> Transaction {
+        Transaction::new(Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::default(),
+            vec![1],
+        ))
+    }
+
+    #[test]
+    fn test_retries_backoff() {
+        let now = Instant::now();
+        let mut retries = Retries::new(4, Duration::from_secs(10));
+        let t = transaction();
+        assert!(retries.push(t.clone(), now).is_none());
+        assert_eq!(retries.due(now), (vec![], vec![]));
+        assert_eq!(retries.next(), Some(now + RETRY_BASE));
+        let (due, _) = retries.due(now + RETRY_BASE);
+        assert_eq!(due, vec![t.clone()]);
+        // The delay doubled, so nothing is due right away.
+        assert!(retries.due(now + RETRY_BASE * 2).0.is_empty());
+        assert_eq!(retries.due(now + RETRY_BASE * 3).0, vec![t.clone()]);
+        assert_eq!(retries.len(), 1);
+        retries.remove(&t.uuid);
+        assert!(retries.is_empty());
+    }
+
+    #[test]
+    fn test_retries_expire() {
+        let now = Instant::now();
+        let mut retries = Retries::new(1, Duration::from_secs(1));
+        let t = transaction();
+        assert!(retries.push(t.clone(), now).is_none());
+        assert!(retries.push(transaction(), now).is_some());
+        let (due, expired) = retries.due(now + Duration::from_secs(1));
+        assert!(due.is_empty());
+        assert_eq!(expired, vec![t]);
+        assert!(retries.is_empty());
+
+        let mut disabled = Retries::new(1, Duration::from_secs(0));
+        assert!(disabled.push(transaction(), now).is_some());
+    }
+}
@@ -6,8 +6,34 @@
 
 use crate::bucket::Bucket;
 use crate::error::Error;
-use crate::node::{Address, Center, Node};
-use std::sync::{Arc, Mutex};
+use crate::event::{self, Events};
+use crate::filter::Filter;
+use crate::metrics::{Event, Metrics};
+use crate::node::{Address, Center, Link, Node};
+use crate::trace;
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::ops::RangeInclusive;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+/// Number of buckets activity is tracked for, one for each possible
+/// length of the common prefix with the Center.
+const BUCKETS: usize = 256;
+
+/// Default number of failed attempts in a row after which a Node gets
+/// evicted.
+pub const ATTEMPTS: usize = 3;
+
+/// Default maximum depth of the routing tree in bits, the tree can
+/// split on every bit of the distance to the Center.
+pub const DEPTH: usize = 256;
+
+/// Maximum number of unverified Nodes waiting to be dialed, further
+/// ones get ignored until some were verified.
+const UNVERIFIED: usize = 256;
 
 /// The entry and interaction point for the binary routing tree. It
 /// holds the root of the tree and is mainly a nice interface for the
@@ -22,39 +48,142 @@ pub struct Table {
     /// Since many of the distance calculations require the Center, it
     /// is stored here and will be passed to the functions internally.
     center: Center,
+    /// Last time a Node got added to each bucket or it got refreshed,
+    /// indexed by the length of the common prefix with the Center.
+    /// These are the Kademlia buckets, the Elements of the tree only
+    /// match them down to its depth.
+    activity: Vec<Option<Instant>>,
+    /// Maximum number of leading bits of the distance to the Center
+    /// the tree splits on.
+    depth: usize,
+    /// Nodes that failed this many connection attempts or Pings in a
+    /// row get evicted.
+    attempts: usize,
+    /// Proof of work every Address needs to be accepted, Nodes below
+    /// it get rejected.
+    difficulty: u32,
+    /// Banned or explicitly allowed Addresses, Nodes it doesn't
+    /// permit get rejected.
+    filter: Filter,
+    /// Links that conflict with the known one of a Node. They only
+    /// replace it once the Node failed to answer at its known Link.
+    conflicts: HashMap<Address, Link>,
+    /// Nodes other nodes told about, they only get added once the
+    /// Listener completed a handshake with them.
+    unverified: HashMap<Address, Node>,
 }
 
-/// Thread safe wrapper around the core Table struct.
-/// TODO: Refactor out / remove requirement.
+/// Thread safe wrapper around the core Table struct. Since it is
+/// shared by all components it also carries the Metrics handle, the
+/// sending side of the Events and the Tracer. The Table is behind a
+/// RwLock: The Listener and the Switch look up nodes for every
+/// message, those reads run concurrently and only wait for changes
+/// to the Table. Clones share the same Table.
 #[derive(Clone)]
 pub struct Safe {
-    table: Arc<Mutex<Table>>,
+    table: Arc<RwLock<Table>>,
     center: Center,
+    metrics: Metrics,
+    events: Events,
+    tracer: trace::Tracer,
+}
+
+/// Read-only copy of a single bucket of the routing tree, for walking
+/// the table through Safe::for_each_bucket, for example for custom
+/// refreshing or exports. Changes to it don't affect the table.
+#[derive(Clone, Debug)]
+pub struct BucketView {
+    range: RangeInclusive<u8>,
+    depth: usize,
+    capacity: usize,
+    nodes: Vec<NodeView>,
+    candidates: usize,
+}
+
+/// Read-only copy of a Node in a BucketView. Unlike the Node itself
+/// its link status can't be changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NodeView(Node);
+
+/// Iterator over copies of the known nodes in increasing distance to
+/// a target Address. The nodes are copied up front, but only sorted
+/// as far as they are consumed, so taking the first few is cheap even
+/// for large tables.
+#[derive(Debug)]
+pub struct Closest {
+    nodes: Vec<Option<Node>>,
+    order: BinaryHeap<Reverse<([u8; 32], usize)>>,
+}
+
+/// Copy of the state of the routing table meant for inspecting it,
+/// for example for debugging or dashboards. It can be serialized with
+/// any serde format.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoutingSnapshot {
+    /// All buckets of the table, from near to far.
+    pub buckets: Vec<BucketSnapshot>,
+}
+
+/// A single bucket of the RoutingSnapshot.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BucketSnapshot {
+    /// Lower limit of the first byte of the distance to the center.
+    pub lower: u8,
+    /// Upper limit of the first byte of the distance to the center.
+    pub upper: u8,
+    /// Number of leading bits of the distance to the center the nodes
+    /// in the bucket share, the buckets of a table all differ in it.
+    pub depth: usize,
+    /// Maximum number of nodes in the bucket.
+    pub capacity: usize,
+    /// The nodes in the bucket, from old to new.
+    pub nodes: Vec<NodeSnapshot>,
+    /// Number of nodes in the replacement cache.
+    pub candidates: usize,
+}
+
+/// A single node of the RoutingSnapshot.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NodeSnapshot {
+    /// Hex encoded Address of the node.
+    pub address: String,
+    /// Connection details of the node, if they are known.
+    pub link: Option<String>,
+    /// Seconds since the node was added to the table.
+    pub age: u64,
+    /// If the last attempt to reach the node succeeded.
+    pub reachable: bool,
+    /// Number of failed attempts in a row to reach the node.
+    pub failures: usize,
+    /// Smoothed round-trip time of the connection to the node in
+    /// microseconds, if it was measured.
+    pub rtt: Option<u64>,
 }
 
 /// In order to simplify and modularize the binary tree the Elements
 /// don't store the necessary metadata themselves. Instead in each
 /// Element the Properties will be stored separately. The Properties
-/// describe the range of each Element, as expressed through the lower
-/// and upper limit index. The limits determine which Nodes can be
-/// stored in a specific element, meaning the first byte of the
-/// distance of any Node for a given Element must be between the lower
-/// and upper limit. The root Element will always have limits of 0 and
-/// 255 since it covers the entire range. When the root element gets
-/// split the Properties will also be split automatically, meaning the
-/// two lower Elements will have limits of 0, 127 and 128, 255. If the
-/// lower limit is zero the Element is "near", if it is anything but
-/// zero it is "far". This simply describes what side of the tree any
-/// element is on. Any element that would contain the center is
-/// considered "near", all other elements are "far". Only "near"
-/// Elements can get split, Nodes in "far" Elements will get replaced.
+/// describe the range of each Element, as expressed through the
+/// number of leading bits the distance of its Nodes to the Center
+/// shares with it. The root Element has a depth of zero, since it
+/// covers the entire range. When the root element gets split the
+/// Properties will also be split automatically: The "near" Element
+/// gets all Nodes with at least one leading zero bit in their
+/// distance and a depth of one, the "far" one the others and a depth
+/// of zero. This simply describes what side of the tree any element
+/// is on. Any element that would contain the center is considered
+/// "near", all other elements are "far". Only "near" Elements can get
+/// split, Nodes in "far" Elements will get replaced. The tree splits
+/// on every bit of the full 256 bit distance, not just its first
+/// byte, up to the depth configured for the Table.
 #[derive(Clone, Debug)]
 struct Property {
-    /// The lower limit of the Element, zero means the Element is "near"
-    lower: u8,
-    /// The upper limit of the Element maximum is 255, only the root
-    /// and the first "far" split can have that.
-    upper: u8,
+    /// Number of leading zero bits in the distance to the Center. All
+    /// Nodes in a "far" Element have exactly that many, in a "near"
+    /// one at least that many.
+    depth: usize,
+    /// If the Element would contain the Center.
+    near: bool,
 }
 
 /// The mail component of the binary routing tree. Each /node/ (binary
@@ -97,14 +226,15 @@ impl Table {
     /// to 255).
     pub fn new(limit: usize, center: Center) -> Self {
         Table {
-            root: Element::Leaf(
-                Bucket::new(limit),
-                Property {
-                    lower: 0,
-                    upper: 255,
-                },
-            ),
+            root: Element::Leaf(Bucket::new(limit), Property::root()),
             center,
+            activity: vec![None; BUCKETS],
+            depth: DEPTH,
+            attempts: ATTEMPTS,
+            difficulty: 0,
+            filter: Filter::new(),
+            conflicts: HashMap::new(),
+            unverified: HashMap::new(),
         }
     }
 
@@ -116,8 +246,16 @@ impl Table {
     /// oldest, non reachable Node in the Table or disregard the new
     /// Node. There is no guarantee a new Node will actually get
     /// added. This follows the Kademlia rules of preferring old,
-    /// available Nodes over new ones.
+    /// available Nodes over new ones. Nodes without enough proof of
+    /// work or ones the Filter doesn't permit are rejected, as is the
+    /// Center itself.
     pub fn try_add(&mut self, node: Node) -> Result<(), Error> {
+        if node.address == self.center.public {
+            return Err(Error::Routing(String::from("node is the center")));
+        }
+        if !self.accepts(&node.address) {
+            return Err(Error::Routing(String::from("address is not accepted")));
+        }
         self.root.try_add(node, &self.center)
     }
 
@@ -127,26 +265,116 @@ impl Table {
     /// get changed for "near" nodes. If the new Node belongs into an
     /// Element at maximum capacity it will get split into two new
     /// Leaves. If the Node already exists in the table nothing will
-    /// change except for the Link details.
+    /// change except for the Link details, see "relink". Either way
+    /// the bucket of the Node counts as active. Nodes without enough
+    /// proof of work or ones the Filter doesn't permit get ignored,
+    /// as does the Center itself.
     pub fn add(&mut self, node: Node) {
-        if &node.address != &self.center.public {
-            match self.find_mut(&node.address) {
-                Some(found) => {
-                    found.link = node.link;
-                }
-                None => {
-                    self.root.add(node, &self.center);
-                }
+        if node.address != self.center.public && self.accepts(&node.address) {
+            self.touch(&node.address);
+            if self.find(&node.address).is_some() {
+                self.relink(node);
+            } else {
+                self.root.add(node, &self.center, self.depth);
             }
         }
     }
 
+    /// Like "add", but the Link of a known Node gets replaced right
+    /// away. Only meant for Links confirmed by the Node itself over
+    /// an established connection.
+    pub fn replace(&mut self, node: Node) {
+        if node.address == self.center.public || !self.accepts(&node.address) {
+            return;
+        }
+        self.touch(&node.address);
+        self.conflicts.remove(&node.address);
+        match self.root.find_mut(&node.address, &self.center) {
+            Some(found) => found.link = node.link,
+            None => self.root.add(node, &self.center, self.depth),
+        }
+    }
+
+    /// Updates the Link of a known Node. A Link leading somewhere else
+    /// could also come from another node claiming the same Address,
+    /// so it is only kept as a conflict. It replaces the known one
+    /// once the Node fails to answer the Ping verifying it there,
+    /// should it answer the conflict gets dropped.
+    fn relink(&mut self, node: Node) {
+        let found = match self.root.find_mut(&node.address, &self.center) {
+            Some(found) => found,
+            None => return,
+        };
+        match (&found.link, node.link) {
+            (Some(known), Some(link)) if !known.is_same(&link) => {
+                trace::warn!("received a conflicting link for a known node");
+                self.conflicts.insert(node.address, link);
+            }
+            (_, link) => found.link = link,
+        }
+    }
+
+    /// Addresses of all Nodes with a conflicting Link, they have to
+    /// be verified at their known one.
+    pub fn conflicts(&self) -> Vec<Address> {
+        self.conflicts.keys().cloned().collect()
+    }
+
+    /// Replaces the known Link of the Node with the conflicting one,
+    /// once it failed to answer the Ping verifying it at the known
+    /// one. The conflicting Link gets a chance before the Node counts
+    /// as unreachable. Returns false if the Node has no conflict.
+    pub fn resolve(&mut self, address: &Address) -> bool {
+        let link = match self.conflicts.remove(address) {
+            Some(link) => link,
+            None => return false,
+        };
+        if let Some(node) = self.root.find_mut(address, &self.center) {
+            trace::info!("replacing the link of an unreachable node");
+            node.link = Some(link);
+        }
+        true
+    }
+
+    /// Adds a Node another node told about. Known Nodes get updated
+    /// like with "add", so a different Link is only a conflict. New
+    /// ones have to be verified first, they are kept until the
+    /// Listener takes them. Relayed Nodes can't be dialed directly,
+    /// so they are ignored.
+    pub fn propose(&mut self, node: Node) {
+        if node.address == self.center.public || !self.accepts(&node.address) {
+            return;
+        }
+        if self.find(&node.address).is_some() {
+            self.relink(node);
+            return;
+        }
+        match &node.link {
+            Some(link) if link.relay.is_none() => {}
+            _ => return,
+        }
+        if self.unverified.len() < UNVERIFIED || self.unverified.contains_key(&node.address) {
+            self.unverified.insert(node.address.clone(), node);
+        }
+    }
+
+    /// Removes and returns up to limit unverified Nodes, see
+    /// "propose".
+    pub fn unverified(&mut self, limit: usize) -> Vec<Node> {
+        let addresses: Vec<Address> = self.unverified.keys().take(limit).cloned().collect();
+        addresses
+            .iter()
+            .filter_map(|address| self.unverified.remove(address))
+            .collect()
+    }
+
     /// Opposite of "try_add", will remove a Node with the matching
     /// Address from the table. Since that might make parts of the
     /// tree under used, the shape can get updated after removal.
     /// Should the Address not be in the Table this function will
     /// fail.
     pub fn remove(&mut self, address: &Address) -> Result<(), Error> {
+        self.conflicts.remove(address);
         self.root.remove(address, &self.center)
     }
 
@@ -188,7 +416,7 @@ impl Table {
         for n in refs {
             nodes.push(n.clone());
         }
-        return nodes;
+        nodes
     }
 
     /// Returns the current maximum capacity of the tree. The capacity
@@ -202,13 +430,118 @@ impl Table {
     /// Change the link state of a Node in the Table. This function
     /// can both be used to change the state of the link and also to
     /// update the state after no change was found. This will update
-    /// the internal counter for how many times attempts have been
-    /// made to reach a Node.
-    pub fn status(&mut self, address: &Address, status: bool) {
-        match self.root.find_mut(address, &self.center) {
-            Some(node) => node.update(status),
-            None => (),
+    /// the internal counter for how many times in a row attempts to
+    /// reach a Node have failed. Reachable Nodes mark their bucket as
+    /// active and drop a conflicting Link, failures keep it until the
+    /// Ping verifying it timed out, see "resolve". Returns true if the
+    /// Node reached the limit of failed attempts and should be
+    /// evicted.
+    pub fn status(&mut self, address: &Address, status: bool) -> bool {
+        let failures = match self.root.find_mut(address, &self.center) {
+            Some(node) => {
+                node.update(status);
+                node.failures()
+            }
+            None => 0,
+        };
+        if status {
+            self.conflicts.remove(address);
+            self.touch(address);
         }
+        failures >= self.attempts
+    }
+
+    /// Adds a round-trip time sample to the Node, unknown Addresses
+    /// are ignored.
+    pub fn measure(&mut self, address: &Address, sample: Duration) {
+        if let Some(node) = self.root.find_mut(address, &self.center) {
+            node.measure(sample);
+        }
+    }
+
+    /// Removes the Node and fills its place with the most recently
+    /// seen candidate of the bucket, should there be one. Returns the
+    /// Address of the promoted candidate.
+    pub fn evict(&mut self, address: &Address) -> Result<Option<Address>, Error> {
+        self.remove(address)?;
+        // Candidates were remembered before they might have been
+        // filtered out, those get dropped instead of promoted.
+        loop {
+            match self.root.promote(address, &self.center) {
+                Some(promoted) if !self.accepts(&promoted) => {
+                    let _ = self.remove(&promoted);
+                }
+                promoted => return Ok(promoted),
+            }
+        }
+    }
+
+    /// Evicts every Node that is no longer accepted, for example
+    /// after the Filter changed. Returns the Addresses of the evicted
+    /// Nodes with the candidates promoted in their place.
+    pub fn purge(&mut self) -> Vec<(Address, Option<Address>)> {
+        let rejected: Vec<Address> = self
+            .get(&self.center.public, self.len())
+            .into_iter()
+            .filter(|node| !self.accepts(&node.address))
+            .map(|node| node.address.clone())
+            .collect();
+        let mut evicted = Vec::new();
+        for address in rejected {
+            if let Ok(promoted) = self.evict(&address) {
+                evicted.push((address, promoted));
+            }
+        }
+        evicted
+    }
+
+    /// Creates a RoutingSnapshot of the entire Table.
+    pub fn inspect(&self) -> RoutingSnapshot {
+        let mut buckets = Vec::new();
+        self.root.inspect(&mut buckets);
+        RoutingSnapshot { buckets }
+    }
+
+    /// Returns read-only copies of all buckets, from near to far.
+    pub fn buckets(&self) -> Vec<BucketView> {
+        let mut buckets = Vec::new();
+        self.root.views(&mut buckets);
+        buckets
+    }
+
+    /// Changes the number of failed attempts in a row after which
+    /// Nodes get evicted, it is at least one.
+    pub fn set_attempts(&mut self, attempts: usize) {
+        self.attempts = attempts.max(1);
+    }
+
+    /// Changes the proof of work every Address needs to be accepted,
+    /// with zero all Addresses are.
+    pub fn set_difficulty(&mut self, difficulty: u32) {
+        self.difficulty = difficulty;
+    }
+
+    /// Changes the maximum depth of the tree in bits, Elements that
+    /// already got split deeper stay as they are.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// Returns the Filter, changes only apply to Nodes added later
+    /// unless the Table gets purged.
+    pub fn filter_mut(&mut self) -> &mut Filter {
+        &mut self.filter
+    }
+
+    /// Returns all banned Addresses.
+    pub fn banned(&self) -> Vec<Address> {
+        self.filter.banned()
+    }
+
+    /// Checks if the Address has enough proof of work and is
+    /// permitted by the Filter.
+    pub fn accepts(&self, address: &Address) -> bool {
+        address.work() >= self.difficulty && self.filter.permits(address)
     }
 
     /// Returns the total number of Nodes in the entire Table.
@@ -216,6 +549,11 @@ impl Table {
         self.root.len()
     }
 
+    /// Returns true if there are no Nodes in the Table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     /// Creates a Vec of bytes of all addresses in the table.
     /// Currently not the most efficient method is used, since all the
     /// Link data is not transmitted, this needs to be fixed in the
@@ -228,7 +566,42 @@ impl Table {
         }
         let center = Node::new(self.center.public.clone(), Some(self.center.link.clone()));
         data.append(&mut center.as_bytes());
-        return data;
+        data
+    }
+
+    /// Serializes up to limit Nodes for answering bootstrap requests,
+    /// followed by the Center. Instead of the Nodes close to the
+    /// Center they are taken from all buckets in turns, randomly
+    /// within each bucket, so the new node learns about all parts of
+    /// the network. Nodes without a Link or whose last attempt to
+    /// reach them failed are left out.
+    pub fn sample(&self, limit: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut buckets: Vec<Vec<Node>> = self
+            .buckets()
+            .into_iter()
+            .map(|bucket| {
+                let mut nodes: Vec<Node> = bucket
+                    .nodes()
+                    .iter()
+                    .filter(|node| node.link().is_some() && node.failures() == 0)
+                    .map(NodeView::to_node)
+                    .collect();
+                nodes.shuffle(&mut rng);
+                nodes
+            })
+            .collect();
+        let mut data = Vec::new();
+        let mut count = 0;
+        while count < limit && buckets.iter().any(|bucket| !bucket.is_empty()) {
+            for node in buckets.iter_mut().filter_map(Vec::pop).take(limit - count) {
+                data.append(&mut node.as_bytes());
+                count += 1;
+            }
+        }
+        let center = Node::new(self.center.public.clone(), Some(self.center.link.clone()));
+        data.append(&mut center.as_bytes());
+        data
     }
 
     /// Tries to determine whether a given Address is local or not.
@@ -237,28 +610,39 @@ impl Table {
     /// node than Center is found. (This has to be expanded to
     /// multiple nodes based on the replication factor of the system).
     ///
-    /// It works by getting the five closest nodes to the address.
-    /// Then the distances get calculated. The function returns true
-    /// if the distance between the address and the Center is smaller
-    /// than atleast one of the fetched Nodes.
+    /// Buckets aren't ordered by distance, so all known nodes get
+    /// compared. The function returns true if none of them is closer
+    /// to the address than the Center.
     pub fn should_be_local(&self, address: &Address) -> bool {
         if address == &self.center.public {
             return true;
         }
-        // 1. Find the closest known node to the target.
-        let nodes = self.get(address, 1);
-
-        if let Some(node) = nodes.first() {
-            // 2. Compute the distance between the target and the found node.
-            let d1 = address ^ &node.address;
-            // 3. Compute the distance between local and the target.
-            let d2 = address ^ &self.center.public;
-            // 4. Check which one is larger.
-            d1 >= d2
-        } else {
-            // Edge case: if no node exists everything should be local.
-            true
-        }
+        let local = address ^ &self.center.public;
+        self.get(address, self.len())
+            .iter()
+            .all(|node| (address ^ &node.address) >= local)
+    }
+
+    /// Returns the Addresses of the count closest nodes to the
+    /// address including the Center, sorted by distance. These are
+    /// the nodes that host the Record of the address. Since buckets
+    /// aren't ordered by distance all nodes have to be compared.
+    pub fn replicas(&self, address: &Address, count: usize) -> Vec<Address> {
+        let mut replicas: Vec<Address> = self
+            .get(address, self.len())
+            .into_iter()
+            .map(|node| node.address.clone())
+            .collect();
+        replicas.push(self.center.public.clone());
+        replicas.sort_by_key(|replica| replica ^ address);
+        replicas.truncate(count);
+        replicas
+    }
+
+    /// Returns all known nodes in increasing distance to the target,
+    /// unlike get the order is exact. The Center isn't included.
+    pub fn closest(&self, target: &Address) -> Closest {
+        Closest::new(target, self.get_copy(target, self.len()))
     }
 
     /// Return the Address of the Center. Shorthand for the public
@@ -266,66 +650,465 @@ impl Table {
     pub fn center(&self) -> Address {
         self.center.public.clone()
     }
+
+    /// The Link other nodes should use to reach the Center. It only
+    /// differs from the configured one once a relay is used.
+    pub fn link(&self) -> Link {
+        self.center.link.clone()
+    }
+
+    /// Replaces the advertised Link of the Center, for example with
+    /// a relayed one.
+    pub fn set_link(&mut self, link: Link) {
+        self.center.link = link;
+    }
+
+    /// Returns the index of the Kademlia bucket an Address belongs
+    /// to, which is the number of leading bits it shares with the
+    /// Center.
+    pub fn index(&self, address: &Address) -> usize {
+        let index = address.leading_zeros(&self.center.public) as usize;
+        index.min(BUCKETS - 1)
+    }
+
+    /// Marks the bucket of the Address as active.
+    pub fn touch(&mut self, address: &Address) {
+        let index = self.index(address);
+        self.activity[index] = Some(Instant::now());
+    }
+
+    /// Returns the indices of all buckets without any activity in the
+    /// given interval. Only buckets up to the deepest one that has
+    /// ever been active are considered, all buckets after that are
+    /// almost certainly empty.
+    pub fn stale(&self, interval: Duration) -> Vec<usize> {
+        let deepest = match self.activity.iter().rposition(Option::is_some) {
+            Some(deepest) => deepest,
+            None => return Vec::new(),
+        };
+        (0..=deepest)
+            .filter(|i| match self.activity[*i] {
+                Some(time) => time.elapsed() >= interval,
+                None => true,
+            })
+            .collect()
+    }
+
+    /// Generates a random Address that falls into the bucket with
+    /// the given index, used as the target of refresh lookups.
+    pub fn random(&self, index: usize) -> Address {
+        let mut distance = Address::random().as_bytes();
+        let (byte, bit) = (index / 8, index % 8);
+        for b in distance.iter_mut().take(byte) {
+            *b = 0;
+        }
+        // The leading bits are cleared, the one right after them is
+        // the first difference to the Center.
+        distance[byte] = (distance[byte] & (0xff >> bit)) | (0x80 >> bit);
+        let center = self.center.public.as_bytes();
+        let mut bytes = [0; 32];
+        for i in 0..32 {
+            bytes[i] = center[i] ^ distance[i];
+        }
+        Address::from_bytes(bytes)
+    }
 }
 
 impl Safe {
+    /// Creates a new Safe around an empty Table, each bucket holds at
+    /// most limit Nodes.
     pub fn new(limit: usize, center: Center) -> Self {
+        Self::with_metrics(limit, center, Metrics::new())
+    }
+
+    /// Creates a new Safe that reports its size to the Metrics, all
+    /// other components get the Metrics from here.
+    pub fn with_metrics(limit: usize, center: Center, metrics: Metrics) -> Self {
         Self {
-            table: Arc::new(Mutex::new(Table::new(limit, center.clone()))),
-            center: center,
+            table: Arc::new(RwLock::new(Table::new(limit, center.clone()))),
+            center,
+            metrics,
+            events: Events::new(),
+            tracer: trace::Tracer::default(),
         }
     }
 
+    /// Adds the Node without changing the shape of the Table, see
+    /// Table::try_add. An Event is emitted if it is new.
     pub fn try_add(&self, node: Node) -> Result<(), Error> {
-        let mut table = self.table.lock().unwrap();
-        (*table).try_add(node)
+        let mut table = self.write();
+        let address = node.address.clone();
+        let known = (*table).find(&address).is_some();
+        let result = (*table).try_add(node);
+        self.added(&table, address, known);
+        result
     }
 
+    /// Adds the Node, the Table gets split if necessary, see
+    /// Table::add. An Event is emitted if it is new.
     pub fn add(&self, node: Node) {
-        let mut table = self.table.lock().unwrap();
+        let mut table = self.write();
+        let address = node.address.clone();
+        let known = (*table).find(&address).is_some();
         (*table).add(node);
+        self.added(&table, address, known);
     }
 
+    /// Adds the Node and replaces the Link of a known one right away,
+    /// see Table::replace.
+    pub fn replace(&self, node: Node) {
+        let mut table = self.write();
+        let address = node.address.clone();
+        let known = (*table).find(&address).is_some();
+        (*table).replace(node);
+        self.added(&table, address, known);
+    }
+
+    /// Removes the Node with the Address, it fails if there is none.
     pub fn remove(&self, address: &Address) -> Result<(), Error> {
-        let mut table = self.table.lock().unwrap();
-        (*table).remove(address)
+        let mut table = self.write();
+        let result = (*table).remove(address);
+        self.metrics.record(Event::Table((*table).len()));
+        if result.is_ok() {
+            self.events.emit(event::Event::NodeRemoved(address.clone()));
+        }
+        result
     }
 
+    /// Returns a copy of the Node with exactly that Address.
+    pub fn find(&self, address: &Address) -> Option<Node> {
+        let table = self.read();
+        (*table).find(address).cloned()
+    }
+
+    /// Returns copies of up to limit Nodes close to the Address,
+    /// roughly ordered by distance. Used for choosing the targets of
+    /// every message, so it only needs a read lock.
     pub fn get_copy(&self, address: &Address, limit: usize) -> Vec<Node> {
-        let table = self.table.lock().unwrap();
+        let table = self.read();
         (*table).get_copy(address, limit)
     }
 
+    /// Maximum number of Nodes the Table can currently hold.
     pub fn capacity(&self) -> usize {
-        let table = self.table.lock().unwrap();
+        let table = self.read();
         (*table).capacity()
     }
 
+    /// Updates the link state of the Node, once it failed too many
+    /// times in a row it gets evicted and replaced by a candidate.
     pub fn status(&self, address: &Address, status: bool) {
-        let mut table = self.table.lock().unwrap();
-        (*table).status(address, status);
+        let mut table = self.write();
+        if !(*table).status(address, status) {
+            return;
+        }
+        if let Ok(promoted) = (*table).evict(address) {
+            trace::info!("evicted unreachable node from the table");
+            self.metrics.record(Event::Table((*table).len()));
+            self.events.emit(event::Event::NodeRemoved(address.clone()));
+            if let Some(promoted) = promoted {
+                self.events.emit(event::Event::NodeAdded(promoted));
+            }
+        }
+    }
+
+    /// Adds a round-trip time sample to the Node, see Table::measure.
+    pub fn measure(&self, address: &Address, sample: Duration) {
+        let mut table = self.write();
+        (*table).measure(address, sample);
+    }
+
+    /// Returns a serializable copy of all buckets and their Nodes.
+    pub fn inspect(&self) -> RoutingSnapshot {
+        let table = self.read();
+        (*table).inspect()
+    }
+
+    /// Calls the visitor with every bucket of the table, from near to
+    /// far. The buckets are copied first, so the visitor can use the
+    /// Safe itself without deadlocking.
+    pub fn for_each_bucket<F>(&self, visitor: F)
+    where
+        F: FnMut(BucketView),
+    {
+        let buckets = self.read().buckets();
+        buckets.into_iter().for_each(visitor);
+    }
+
+    /// Iterates over the ranges of the first byte of the distance to
+    /// the Center each bucket covers together with its nodes, from
+    /// near to far.
+    pub fn buckets(&self) -> impl Iterator<Item = (RangeInclusive<u8>, Vec<NodeView>)> {
+        let buckets = self.read().buckets();
+        buckets
+            .into_iter()
+            .map(|bucket| (bucket.range, bucket.nodes))
+    }
+
+    /// Nodes get evicted once they failed this many connection
+    /// attempts or Pings in a row.
+    pub fn with_attempts(self, attempts: usize) -> Self {
+        self.write().set_attempts(attempts);
+        self
+    }
+
+    /// Only Nodes whose Address has at least this much proof of work
+    /// get added.
+    pub fn with_difficulty(self, difficulty: u32) -> Self {
+        self.write().set_difficulty(difficulty);
+        self
+    }
+
+    /// The tree splits on at most this many leading bits of the
+    /// distance to the Center.
+    pub fn with_depth(self, depth: usize) -> Self {
+        self.write().set_depth(depth);
+        self
+    }
+
+    /// All components pass their decisions about messages to the
+    /// Tracer.
+    pub fn with_tracer(mut self, tracer: trace::Tracer) -> Self {
+        self.tracer = tracer;
+        self
+    }
+
+    /// If a Node with the Address would be accepted, considering its
+    /// proof of work and the Filter.
+    pub fn accepts(&self, address: &Address) -> bool {
+        let table = self.read();
+        (*table).accepts(address)
+    }
+
+    /// Addresses of the Nodes that got announced with a conflicting
+    /// Link.
+    pub fn conflicts(&self) -> Vec<Address> {
+        let table = self.read();
+        (*table).conflicts()
+    }
+
+    /// Replaces the Link of a Node that failed its verification, see
+    /// Table::resolve.
+    pub fn resolve(&self, address: &Address) -> bool {
+        let mut table = self.write();
+        (*table).resolve(address)
+    }
+
+    /// Keeps a Node another node told about until it is verified,
+    /// see Table::propose.
+    pub fn propose(&self, node: Node) {
+        let mut table = self.write();
+        (*table).propose(node);
+    }
+
+    /// Takes up to limit Nodes waiting to be verified.
+    pub fn unverified(&self, limit: usize) -> Vec<Node> {
+        let mut table = self.write();
+        (*table).unverified(limit)
+    }
+
+    /// Bans the Address, should the Node be in the Table it gets
+    /// evicted right away.
+    pub fn ban(&self, address: Address) {
+        let mut table = self.write();
+        (*table).filter_mut().ban(address);
+        self.purge(&mut table);
     }
 
+    /// Lifts the ban of the Address, the Node has to be added again.
+    pub fn unban(&self, address: &Address) {
+        let mut table = self.write();
+        (*table).filter_mut().unban(address);
+    }
+
+    /// Only accepts Nodes with the given Addresses from now on, all
+    /// others get evicted right away.
+    pub fn allow_only(&self, addresses: Vec<Address>) {
+        let mut table = self.write();
+        (*table).filter_mut().allow_only(addresses);
+        self.purge(&mut table);
+    }
+
+    /// Removes the allowlist, bans still apply.
+    pub fn allow_all(&self) {
+        let mut table = self.write();
+        (*table).filter_mut().allow_all();
+    }
+
+    /// Returns all banned Addresses.
+    pub fn banned(&self) -> Vec<Address> {
+        let table = self.read();
+        (*table).banned()
+    }
+
+    /// Evicts all Nodes the Table no longer accepts and emits the
+    /// matching Events.
+    fn purge(&self, table: &mut Table) {
+        let evicted = table.purge();
+        if evicted.is_empty() {
+            return;
+        }
+        self.metrics.record(Event::Table(table.len()));
+        for (address, promoted) in evicted {
+            self.events.emit(event::Event::NodeRemoved(address));
+            if let Some(promoted) = promoted {
+                self.events.emit(event::Event::NodeAdded(promoted));
+            }
+        }
+    }
+
+    /// Number of Nodes in the Table.
     pub fn len(&self) -> usize {
-        let table = self.table.lock().unwrap();
+        let table = self.read();
         (*table).len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Serializes all Nodes followed by the Center, used for the
+    /// snapshots of the Table.
     pub fn export(&self) -> Vec<u8> {
-        let table = self.table.lock().unwrap();
+        let table = self.read();
         (*table).export()
     }
 
+    /// Serializes a sample of up to limit Nodes from all buckets for
+    /// answering bootstrap requests, see Table::sample.
+    pub fn sample(&self, limit: usize) -> Vec<u8> {
+        let table = self.read();
+        (*table).sample(limit)
+    }
+
+    /// Returns true if no known Node is closer to the Address than
+    /// the Center, see Table::should_be_local.
     pub fn should_be_local(&self, address: &Address) -> bool {
-        let table = self.table.lock().unwrap();
+        let table = self.read();
         (*table).should_be_local(address)
     }
 
+    /// Addresses of the count closest Nodes including the Center,
+    /// sorted by distance.
+    pub fn replicas(&self, address: &Address, count: usize) -> Vec<Address> {
+        let table = self.read();
+        (*table).replicas(address, count)
+    }
+
+    /// Iterates over copies of all Nodes in increasing distance to
+    /// the target.
+    pub fn closest(&self, target: &Address) -> Closest {
+        let table = self.read();
+        (*table).closest(target)
+    }
+
+    /// Address of the Center, it never changes so no lock is needed.
     pub fn center(&self) -> Address {
         self.center.public.clone()
     }
+
+    /// The Link other nodes should use to reach the Center.
+    pub fn link(&self) -> Link {
+        let table = self.read();
+        (*table).link()
+    }
+
+    /// Replaces the advertised Link of the Center.
+    pub fn set_link(&self, link: Link) {
+        let mut table = self.write();
+        (*table).set_link(link);
+    }
+
+    /// Returns a handle to the shared Metrics.
+    pub fn metrics(&self) -> Metrics {
+        self.metrics.clone()
+    }
+
+    /// Returns the sending side of the shared Events.
+    pub fn events(&self) -> Events {
+        self.events.clone()
+    }
+
+    /// Returns the shared Tracer.
+    pub fn tracer(&self) -> trace::Tracer {
+        self.tracer.clone()
+    }
+
+    /// Index of the Kademlia bucket of the Address, the number of
+    /// leading bits it shares with the Center.
+    pub fn index(&self, address: &Address) -> usize {
+        let table = self.read();
+        (*table).index(address)
+    }
+
+    /// Marks the bucket of the Address as active.
+    pub fn touch(&self, address: &Address) {
+        let mut table = self.write();
+        (*table).touch(address);
+    }
+
+    /// Indices of the buckets without activity in the interval.
+    pub fn stale(&self, interval: Duration) -> Vec<usize> {
+        let table = self.read();
+        (*table).stale(interval)
+    }
+
+    /// Random Address in the bucket with the index.
+    pub fn random(&self, index: usize) -> Address {
+        let table = self.read();
+        (*table).random(index)
+    }
+
+    /// Shared access to the Table, any number of readers can hold it
+    /// at the same time.
+    fn read(&self) -> RwLockReadGuard<'_, Table> {
+        self.table.read().unwrap()
+    }
+
+    /// Exclusive access to the Table for changing it.
+    fn write(&self) -> RwLockWriteGuard<'_, Table> {
+        self.table.write().unwrap()
+    }
+
+    /// Reports the new size of the Table and emits an Event if the
+    /// Node wasn't known before but is now part of the Table.
+    fn added(&self, table: &Table, address: Address, known: bool) {
+        self.metrics.record(Event::Table(table.len()));
+        if !known && table.find(&address).is_some() {
+            self.events.emit(event::Event::NodeAdded(address));
+        }
+    }
+}
+
+impl Closest {
+    fn new(target: &Address, nodes: Vec<Node>) -> Self {
+        let order = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| Reverse((target.distance(&node.address), i)))
+            .collect();
+        Self {
+            nodes: nodes.into_iter().map(Some).collect(),
+            order,
+        }
+    }
 }
 
+impl Iterator for Closest {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let Reverse((_, i)) = self.order.pop()?;
+        self.nodes[i].take()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.order.len(), Some(self.order.len()))
+    }
+}
+
+impl ExactSizeIterator for Closest {}
+
 impl Element {
     /// Add to an element if possible. If the far bucket is full a
     /// node will get replaced following kademlia rules. This function
@@ -333,19 +1116,24 @@ impl Element {
     fn try_add(&mut self, node: Node, center: &Center) -> Result<(), Error> {
         match self {
             Self::Split(s, p) => {
-                if !p.in_range(&node.address, &center) {
-                    return Err(Error::Invalid(String::from("not in range")));
+                if !p.in_range(&node.address, center) {
+                    return Err(Error::Routing(String::from("not in range")));
                 }
                 if p.is_near() {
                     s.try_add(node, center)
                 } else {
-                    s.add(node, center);
+                    s.add(node, center, p.depth);
                     Ok(())
                 }
             }
             Self::Leaf(b, p) => {
-                if !p.in_range(&node.address, &center) {
-                    return Err(Error::Invalid(String::from("not in range")));
+                if !p.in_range(&node.address, center) {
+                    return Err(Error::Routing(String::from("not in range")));
+                }
+                if b.len() >= b.capacity() && b.find(&node.address).is_none() {
+                    // Kept as a replacement for evicted nodes.
+                    b.remember(node);
+                    return Err(Error::Full);
                 }
                 b.try_add(node)
             }
@@ -354,20 +1142,21 @@ impl Element {
 
     /// Adds the Node to the Element. If the "near" Element is already
     /// full it gets split and the Element gets added to the new
-    /// Split.
-    fn add(&mut self, node: Node, center: &Center) {
+    /// Split. Elements at the maximum depth don't get split anymore,
+    /// they get treated like "far" ones.
+    fn add(&mut self, node: Node, center: &Center, depth: usize) {
         match self {
-            Self::Split(s, _) => s.add(node, center),
+            Self::Split(s, _) => s.add(node, center, depth),
             Self::Leaf(b, p) => {
-                if p.is_near() {
+                if p.is_near() && p.depth < depth {
                     match b.try_add(node.clone()) {
-                        Ok(()) => return,
+                        Ok(()) => (),
                         Err(_) => {
                             // bucket is full => split it. unwrap is
                             // not an issue, the split only fails if
                             // the element is not near.
                             *self = self.clone().split(center).unwrap();
-                            self.add(node, center);
+                            self.add(node, center, depth);
                         }
                     }
                 } else {
@@ -383,8 +1172,8 @@ impl Element {
     /// split is required a new Element is generated and this current
     /// object is replaced with the new one.
     fn remove(&mut self, address: &Address, center: &Center) -> Result<(), Error> {
-        if let None = self.find(address, center) {
-            return Err(Error::Unknown);
+        if self.find(address, center).is_none() {
+            return Err(Error::Routing(String::from("node is not in the table")));
         }
         match self {
             Self::Split(s, _) => {
@@ -417,13 +1206,13 @@ impl Element {
     /// all have their properties calculated automatically.
     fn split(self, center: &Center) -> Option<Self> {
         match self {
-            Self::Split(_, _) => return None,
+            Self::Split(_, _) => None,
             Self::Leaf(b, p) => {
                 // Only "near" elements can be split.
-                if p.lower != 0 {
+                if !p.is_near() {
                     return None;
                 }
-                let (near, far) = b.split(center, p.upper);
+                let (near, far) = b.split(center, p.depth);
                 let (near_p, far_p) = p.split();
                 let split = Split {
                     near: Box::new(Self::Leaf(near, near_p)),
@@ -446,6 +1235,64 @@ impl Element {
         }
     }
 
+    /// Adds the BucketSnapshots of all Leaves below the Element, the
+    /// near side first.
+    fn inspect(&self, buckets: &mut Vec<BucketSnapshot>) {
+        match self {
+            Self::Split(s, _) => {
+                s.near.inspect(buckets);
+                s.far.inspect(buckets);
+            }
+            Self::Leaf(b, p) => buckets.push(BucketSnapshot {
+                lower: p.lower(),
+                upper: p.upper(),
+                depth: p.depth,
+                capacity: b.capacity(),
+                nodes: b
+                    .get(b.capacity())
+                    .into_iter()
+                    .map(NodeSnapshot::from)
+                    .collect(),
+                candidates: b.candidates().len(),
+            }),
+        }
+    }
+
+    /// Same as inspect, but collects BucketViews.
+    fn views(&self, buckets: &mut Vec<BucketView>) {
+        match self {
+            Self::Split(s, _) => {
+                s.near.views(buckets);
+                s.far.views(buckets);
+            }
+            Self::Leaf(b, p) => buckets.push(BucketView {
+                range: p.lower()..=p.upper(),
+                depth: p.depth,
+                capacity: b.capacity(),
+                nodes: b
+                    .get(b.capacity())
+                    .into_iter()
+                    .map(|node| NodeView(node.clone()))
+                    .collect(),
+                candidates: b.candidates().len(),
+            }),
+        }
+    }
+
+    /// Promotes a candidate of the bucket the Address belongs to.
+    fn promote(&mut self, address: &Address, center: &Center) -> Option<Address> {
+        match self {
+            Self::Split(s, _) => {
+                if s.near.in_range(address, center) {
+                    s.near.promote(address, center)
+                } else {
+                    s.far.promote(address, center)
+                }
+            }
+            Self::Leaf(b, _) => b.promote(),
+        }
+    }
+
     /// Returns a pointer to a Node if the provided Address exists in
     /// the Table.
     fn find_mut(&mut self, search: &Address, center: &Center) -> Option<&mut Node> {
@@ -482,15 +1329,15 @@ impl Element {
             Self::Split(s, _) => sum += s.capacity(),
             Self::Leaf(b, _) => sum += b.capacity(),
         }
-        return sum;
+        sum
     }
 
     /// Uses the properties of an Element to determine if an Address
     /// can be stored in this Element (or below it).
     fn in_range(&self, address: &Address, center: &Center) -> bool {
         match self {
-            Self::Split(_, p) => p.in_range(&address, center),
-            Self::Leaf(_, p) => p.in_range(&address, center),
+            Self::Split(_, p) => p.in_range(address, center),
+            Self::Leaf(_, p) => p.in_range(address, center),
         }
     }
 
@@ -518,11 +1365,11 @@ impl Split {
 
     /// Recursive function that calls add on the "near" or "far" side
     /// the Node belongs to.
-    fn add(&mut self, node: Node, center: &Center) {
+    fn add(&mut self, node: Node, center: &Center, depth: usize) {
         if self.near.in_range(&node.address, center) {
-            self.near.add(node, center)
+            self.near.add(node, center, depth)
         } else {
-            self.far.add(node, center)
+            self.far.add(node, center, depth)
         }
     }
 
@@ -552,25 +1399,25 @@ impl Split {
     /// is available.
     fn get(&self, target: &Address, center: &Center, limit: usize) -> Vec<&Node> {
         let mut nodes = Vec::new();
-        if self.near.in_range(&target, &center) {
+        if self.near.in_range(target, center) {
             nodes.append(&mut self.near.get(target, center, limit));
             if nodes.len() >= limit {
                 nodes.truncate(limit);
-                return nodes;
+                nodes
             } else {
                 nodes.append(&mut self.far.get(target, center, limit));
                 nodes.truncate(limit);
-                return nodes;
+                nodes
             }
         } else {
             nodes.append(&mut self.far.get(target, center, limit));
             if nodes.len() >= limit {
                 nodes.truncate(limit);
-                return nodes;
+                nodes
             } else {
                 nodes.append(&mut self.near.get(target, center, limit));
                 nodes.truncate(limit);
-                return nodes;
+                nodes
             }
         }
     }
@@ -592,19 +1439,20 @@ impl Split {
     /// Elements are Leafs.
     fn collapse(&self) -> Result<Element, Error> {
         let mut nodes = Vec::new();
-        let lower;
-        let upper;
+        let mut candidates = Vec::new();
+        let depth;
         let limit;
-        if let Element::Leaf(b, p) = &*self.near {
+        if let Element::Leaf(b, _) = &*self.near {
             nodes.append(&mut b.get(b.capacity()));
-            lower = p.lower;
+            candidates.extend_from_slice(b.candidates());
             limit = b.capacity();
         } else {
             return Err(Error::Unknown);
         }
         if let Element::Leaf(b, p) = &*self.far {
             nodes.append(&mut b.get(b.capacity()));
-            upper = p.upper;
+            candidates.extend_from_slice(b.candidates());
+            depth = p.depth;
         } else {
             return Err(Error::Unknown);
         }
@@ -615,7 +1463,10 @@ impl Split {
         for i in nodes.into_iter() {
             bucket.add(i.clone());
         }
-        let prop = Property { lower, upper };
+        for i in candidates {
+            bucket.remember(i);
+        }
+        let prop = Property { depth, near: true };
         Ok(Element::Leaf(bucket, prop))
     }
 
@@ -626,7 +1477,7 @@ impl Split {
             Element::Leaf(b, _) => length += b.len(),
             Element::Split(s, _) => length += s.len(),
         }
-        return length;
+        length
     }
 
     /// Sums up the capacity of all Elements below the Split
@@ -634,7 +1485,7 @@ impl Split {
     fn capacity(&self) -> usize {
         let mut sum = self.near.capacity();
         sum += self.far.capacity();
-        return sum;
+        sum
     }
 
     fn is_final(&self) -> bool {
@@ -642,14 +1493,116 @@ impl Split {
     }
 }
 
+impl BucketView {
+    /// Range of the first byte of the distance to the Center the
+    /// bucket covers.
+    pub fn range(&self) -> RangeInclusive<u8> {
+        self.range.clone()
+    }
+
+    /// Number of leading bits of the distance to the Center the nodes
+    /// in the bucket share. Unlike the range it differs for all
+    /// buckets.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Maximum number of nodes in the bucket.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The nodes in the bucket, from old to new.
+    pub fn nodes(&self) -> &[NodeView] {
+        &self.nodes
+    }
+
+    /// Number of nodes in the replacement cache.
+    pub fn candidates(&self) -> usize {
+        self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.nodes.len() >= self.capacity
+    }
+}
+
+impl NodeView {
+    pub fn address(&self) -> &Address {
+        &self.0.address
+    }
+
+    pub fn link(&self) -> Option<&Link> {
+        self.0.link.as_ref()
+    }
+
+    /// Time since the node was added to the table.
+    pub fn age(&self) -> Duration {
+        self.0.age()
+    }
+
+    /// If the last attempt to reach the node succeeded.
+    pub fn is_reachable(&self) -> bool {
+        self.0.is_reachable()
+    }
+
+    /// Number of failed attempts in a row to reach the node.
+    pub fn failures(&self) -> usize {
+        self.0.failures()
+    }
+
+    /// Smoothed round-trip time of the connection to the node.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.0.rtt()
+    }
+
+    /// Returns a copy of the Node, for example for importing it
+    /// somewhere else.
+    pub fn to_node(&self) -> Node {
+        self.0.clone()
+    }
+}
+
+impl From<&Node> for NodeSnapshot {
+    fn from(node: &Node) -> Self {
+        Self {
+            address: node.address.to_hex(),
+            link: node.link.as_ref().map(|link| link.to_string()),
+            age: node.age().as_secs(),
+            reachable: node.is_reachable(),
+            failures: node.failures(),
+            rtt: node.rtt().map(|rtt| rtt.as_micros() as u64),
+        }
+    }
+}
+
 impl Property {
+    /// Property of the root Element, covering all Addresses.
+    fn root() -> Self {
+        Self {
+            depth: 0,
+            near: true,
+        }
+    }
+
     /// Determines whether an address is within range of the given
     /// Property. It does this by calculating the XOR Distance between
-    /// the Node and the Center. If the first significant byte falls
-    /// within the range it will return true.
+    /// the Node and the Center. If the number of its leading zero
+    /// bits matches the depth it will return true.
     fn in_range(&self, address: &Address, center: &Center) -> bool {
-        let index = (address.clone() ^ center.public.clone())[0];
-        self.lower <= index && self.upper > index
+        let zeros = address.leading_zeros(&center.public) as usize;
+        match self.near {
+            true => zeros >= self.depth,
+            false => zeros == self.depth,
+        }
     }
 
     /// Splits the Property of an Element. Unlike the similar function
@@ -659,46 +1612,65 @@ impl Property {
     /// the last one being the "far" Property.
     fn split(&self) -> (Self, Self) {
         let lower = Self {
-            lower: self.lower,
-            upper: self.upper / 2,
+            depth: self.depth + 1,
+            near: true,
         };
         let upper = Self {
-            lower: (self.upper / 2) + 1,
-            upper: self.upper,
+            depth: self.depth,
+            near: false,
         };
         (lower, upper)
     }
 
-    /// Simply checks if the lower property is zero, which means the
-    /// Element is "near".
+    /// Simply returns if the Element is "near".
     fn is_near(&self) -> bool {
-        self.lower == 0
+        self.near
+    }
+
+    /// Lower limit of the first byte of the distance of all Nodes in
+    /// the Element, zero for all Elements deeper than a byte.
+    fn lower(&self) -> u8 {
+        match (self.near, self.depth) {
+            (true, _) => 0,
+            (false, depth) if depth < 8 => 0x80 >> depth,
+            _ => 0,
+        }
+    }
+
+    /// Upper limit of the first byte of the distance of all Nodes in
+    /// the Element.
+    fn upper(&self) -> u8 {
+        match self.depth {
+            depth if depth < 8 => 0xff >> depth,
+            _ => 0,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sodiumoxide::crypto::box_;
     use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::SecretKey;
 
     #[test]
     fn test_full_duplicate() {
         let b = gen_bucket();
         let p = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(b, p);
         let center = gen_center();
 
         for i in 0..40 {
-            elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         assert_eq!(elem.len(), 40);
 
         for i in 0..40 {
-            let _ = elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         assert_eq!(elem.len(), 40);
@@ -708,14 +1680,14 @@ mod tests {
     fn test_full_stress() {
         let b = gen_bucket();
         let p = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(b, p);
         let center = gen_center();
 
         for i in 0..1000 {
-            elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         for i in 100..1100 {
@@ -723,7 +1695,7 @@ mod tests {
         }
 
         for i in 0..1000 {
-            elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         for i in 100..1100 {
@@ -732,21 +1704,21 @@ mod tests {
 
         let a = elem.len() <= elem.capacity();
 
-        assert_eq!(a, true);
+        assert!(a);
     }
 
     #[test]
     fn test_capacity_get() {
         let b = gen_bucket();
         let p = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(b, p);
         let center = gen_center();
 
         for i in 0..1000 {
-            elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         let nodes = elem.get(&center.public, &center, elem.len());
@@ -756,74 +1728,100 @@ mod tests {
     #[test]
     fn test_property_in_range() {
         let p = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let node = gen_node_near();
         let center = gen_center_near();
-        assert_eq!(p.in_range(&node.address, &center), true);
+        assert!(p.in_range(&node.address, &center));
         assert_eq!((node.address ^ center.public)[0], 0);
     }
 
     #[test]
     fn test_property_split_root() {
         let p = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let (l, u) = p.split();
-        assert_eq!(l.lower, 0);
-        assert_eq!(l.upper, 127);
-        assert_eq!(u.lower, 128);
-        assert_eq!(u.upper, 255);
+        assert_eq!(l.lower(), 0);
+        assert_eq!(l.upper(), 127);
+        assert_eq!(u.lower(), 128);
+        assert_eq!(u.upper(), 255);
     }
 
     #[test]
     fn test_property_split_lower() {
         let p = Property {
-            lower: 0,
-            upper: 63,
+            depth: 2,
+            near: true,
         };
         let (l, u) = p.split();
-        assert_eq!(l.lower, 0);
-        assert_eq!(l.upper, 31);
-        assert_eq!(u.lower, 32);
-        assert_eq!(u.upper, 63);
+        assert_eq!(l.lower(), 0);
+        assert_eq!(l.upper(), 31);
+        assert_eq!(u.lower(), 32);
+        assert_eq!(u.upper(), 63);
+    }
+
+    #[test]
+    fn test_property_in_range_deep() {
+        let center = gen_center();
+        let mut bytes = center.public.as_bytes();
+        bytes[1] ^= 0b0001_0000;
+        let address = Address::from_bytes(bytes);
+        let p = Property {
+            depth: 11,
+            near: false,
+        };
+        assert!(p.in_range(&address, &center));
+        let p = Property {
+            depth: 11,
+            near: true,
+        };
+        let (near, far) = p.split();
+        assert!(!near.in_range(&address, &center));
+        assert!(far.in_range(&address, &center));
+        let p = Property {
+            depth: 9,
+            near: true,
+        };
+        assert!(p.in_range(&address, &center));
+        assert_eq!((p.lower(), p.upper()), (0, 0));
     }
 
     #[test]
     fn test_property_near() {
         let p = Property {
-            lower: 0,
-            upper: 63,
+            depth: 2,
+            near: true,
         };
         let (l, u) = p.split();
-        assert_eq!(l.is_near(), true);
-        assert_eq!(u.is_near(), false);
+        assert!(l.is_near());
+        assert!(!u.is_near());
     }
 
     #[test]
     fn test_element_try_add() {
         let bucket = Bucket::new(1);
         let prop = Property {
-            lower: 0,
-            upper: 63,
+            depth: 2,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let node = gen_node_near();
         let center = gen_center_near();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let node = gen_node_far();
         let s = elem.try_add(node, &center);
-        assert_eq!(s.is_err(), true);
+        assert!(s.is_err());
     }
 
     #[test]
     fn test_element_split_root() {
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let buck = gen_bucket();
         let elem = Element::Leaf(buck, prop);
@@ -831,7 +1829,7 @@ mod tests {
         let split = elem.split(&center).unwrap();
         match split {
             Element::Split(s, p) => {
-                assert_eq!(p.upper, 255);
+                assert_eq!(p.upper(), 255);
                 assert_eq!(s.len(), 3);
                 assert_eq!(s.near.as_ref().len(), 2);
             }
@@ -842,27 +1840,27 @@ mod tests {
     #[test]
     fn test_element_split_far() {
         let prop = Property {
-            lower: 128,
-            upper: 255,
+            depth: 0,
+            near: false,
         };
         let buck = gen_bucket();
         let elem = Element::Leaf(buck, prop);
         let center = gen_center();
         let split = elem.split(&center).is_none();
-        assert_eq!(split, true);
+        assert!(split);
     }
 
     #[test]
     fn test_element_add_to_leaf() {
         let bucket = gen_bucket();
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let node = gen_node("added");
         let center = gen_center();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         assert_eq!(elem.len(), 4);
     }
 
@@ -870,16 +1868,16 @@ mod tests {
     fn test_element_split() {
         let bucket = Bucket::new(1);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let center = gen_center_near();
         let node = gen_node_near();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let node = gen_node_far();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         assert_eq!(elem.len(), 2);
         match elem {
@@ -896,16 +1894,16 @@ mod tests {
     fn test_element_split_near() {
         let bucket = Bucket::new(1);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let center = gen_center_near();
         let node = gen_node_near();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let node = gen_node_near();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         assert_eq!(elem.len(), 1);
         match elem {
@@ -922,15 +1920,15 @@ mod tests {
     fn test_element_find_top() {
         let bucket = Bucket::new(20);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let center = gen_center_near();
 
         let node = gen_node("searching");
         let searching = node.address.clone();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         assert_eq!(elem.len(), 1);
         let node = elem.find(&searching, &center).unwrap();
@@ -945,42 +1943,42 @@ mod tests {
                     near: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 0,
-                            upper: 63,
+                            depth: 2,
+                            near: true,
                         },
                     )),
                     far: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 64,
-                            upper: 127,
+                            depth: 1,
+                            near: false,
                         },
                     )),
                 },
                 Property {
-                    lower: 0,
-                    upper: 127,
+                    depth: 1,
+                    near: true,
                 },
             )),
             far: Box::new(Element::Leaf(
                 Bucket::new(20),
                 Property {
-                    lower: 128,
-                    upper: 255,
+                    depth: 0,
+                    near: false,
                 },
             )),
         };
 
         let props = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Split(split, props);
         let center = gen_center_near();
 
         let node = gen_node("searching");
         let searching = node.address.clone();
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         assert_eq!(elem.len(), 1);
         let node = elem.find(&searching, &center).unwrap();
@@ -991,22 +1989,22 @@ mod tests {
     fn test_element_get_top() {
         let bucket = Bucket::new(20);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
         let center = gen_center_near();
 
         let node = gen_node("searching");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("random");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("string");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("actaeon");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("data");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let target = gen_node("target").address;
         let targets = elem.get(&target, &center, 5);
@@ -1017,8 +2015,8 @@ mod tests {
     fn test_element_get_empty() {
         let bucket = Bucket::new(20);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let elem = Element::Leaf(bucket, prop);
         let center = gen_center_near();
@@ -1035,60 +2033,60 @@ mod tests {
                     near: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 0,
-                            upper: 63,
+                            depth: 2,
+                            near: true,
                         },
                     )),
                     far: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 64,
-                            upper: 127,
+                            depth: 1,
+                            near: false,
                         },
                     )),
                 },
                 Property {
-                    lower: 0,
-                    upper: 127,
+                    depth: 1,
+                    near: true,
                 },
             )),
             far: Box::new(Element::Leaf(
                 Bucket::new(20),
                 Property {
-                    lower: 128,
-                    upper: 255,
+                    depth: 0,
+                    near: false,
                 },
             )),
         };
 
         let props = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Split(split, props);
         let center = gen_center_near();
 
         let node = gen_node("searching");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("random");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("string");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("actaeon");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("data");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let node = gen_node("searching2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("random2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("string2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("actaeon2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("maybe use a loop for this?");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let target = gen_node("target").address;
         let targets = elem.get(&target, &center, 5);
@@ -1099,15 +2097,15 @@ mod tests {
     fn test_element_remove_root() {
         let bucket = Bucket::new(20);
         let prop = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Leaf(bucket, prop);
 
         let center = gen_center();
 
         let node = gen_node("test");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         assert_eq!(elem.len(), 1);
 
@@ -1124,60 +2122,60 @@ mod tests {
                     near: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 0,
-                            upper: 63,
+                            depth: 2,
+                            near: true,
                         },
                     )),
                     far: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 64,
-                            upper: 127,
+                            depth: 1,
+                            near: false,
                         },
                     )),
                 },
                 Property {
-                    lower: 0,
-                    upper: 127,
+                    depth: 1,
+                    near: true,
                 },
             )),
             far: Box::new(Element::Leaf(
                 Bucket::new(20),
                 Property {
-                    lower: 128,
-                    upper: 255,
+                    depth: 0,
+                    near: false,
                 },
             )),
         };
 
         let props = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
         let mut elem = Element::Split(split, props);
         let center = gen_center_near();
 
         let node = gen_node("searching");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("random");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("string");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("actaeon");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("data");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let node = gen_node("searching2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("random2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("string2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("actaeon2");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
         let node = gen_node("maybe use a loop for this?");
-        elem.add(node, &center);
+        elem.add(node, &center, DEPTH);
 
         let target = gen_node("random");
         assert_eq!(elem.len(), 10);
@@ -1193,42 +2191,42 @@ mod tests {
                     near: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 0,
-                            upper: 63,
+                            depth: 2,
+                            near: true,
                         },
                     )),
                     far: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 64,
-                            upper: 127,
+                            depth: 1,
+                            near: false,
                         },
                     )),
                 },
                 Property {
-                    lower: 0,
-                    upper: 127,
+                    depth: 1,
+                    near: true,
                 },
             )),
             far: Box::new(Element::Leaf(
                 Bucket::new(20),
                 Property {
-                    lower: 128,
-                    upper: 255,
+                    depth: 0,
+                    near: false,
                 },
             )),
         };
 
         let props = Property {
-            lower: 0,
-            upper: 255,
+            depth: 0,
+            near: true,
         };
 
         let mut elem = Element::Split(split, props);
         let center = gen_center_near();
 
         for i in 0..40 {
-            elem.add(gen_node(&i.to_string()), &center);
+            elem.add(gen_node(&i.to_string()), &center, DEPTH);
         }
 
         assert_eq!(elem.len(), 40);
@@ -1249,7 +2247,7 @@ mod tests {
         let mut split = gen_split();
         let node = gen_node_near();
         let center = gen_center_near();
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         assert_eq!(split.len(), 1);
         assert_eq!(split.near.len(), 1);
         assert_eq!(split.far.len(), 0);
@@ -1261,7 +2259,7 @@ mod tests {
         let node = gen_node_far();
         let center = gen_center_near();
         let a = (node.address.clone() ^ center.public.clone())[0];
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         assert_eq!(split.len(), 1);
         assert_eq!(a, 255);
         assert_eq!(split.far.len(), 1);
@@ -1275,28 +2273,28 @@ mod tests {
                     near: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 0,
-                            upper: 63,
+                            depth: 2,
+                            near: true,
                         },
                     )),
                     far: Box::new(Element::Leaf(
                         Bucket::new(20),
                         Property {
-                            lower: 64,
-                            upper: 127,
+                            depth: 1,
+                            near: false,
                         },
                     )),
                 },
                 Property {
-                    lower: 0,
-                    upper: 127,
+                    depth: 1,
+                    near: true,
                 },
             )),
             far: Box::new(Element::Leaf(
                 Bucket::new(20),
                 Property {
-                    lower: 128,
-                    upper: 255,
+                    depth: 0,
+                    near: false,
                 },
             )),
         };
@@ -1304,13 +2302,13 @@ mod tests {
 
         let center = gen_center_near();
         let node = gen_node_near();
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         assert_eq!(split.len(), 1);
         assert_eq!(split.near.as_ref().len(), 1);
         assert_eq!(split.far.as_ref().len(), 0);
 
         let node = gen_node_far();
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         assert_eq!(split.len(), 2);
         assert_eq!(split.near.as_ref().len(), 1);
         assert_eq!(split.far.as_ref().len(), 1);
@@ -1321,7 +2319,7 @@ mod tests {
         let split = gen_split();
         let center = gen_center_near();
         let node = gen_node_near();
-        assert_eq!(split.near.in_range(&node.address, &center), true);
+        assert!(split.near.in_range(&node.address, &center));
     }
 
     #[test]
@@ -1329,13 +2327,13 @@ mod tests {
         let mut split = gen_split();
         let center = gen_center_near();
         let node = gen_node("first");
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         let node = gen_node("second");
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         let node = gen_node_far();
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         let node = gen_node_near();
-        split.add(node, &center);
+        split.add(node, &center, DEPTH);
         assert_eq!(split.len(), 4);
         let e = split.collapse().unwrap();
         assert_eq!(e.len(), 4);
@@ -1346,14 +2344,14 @@ mod tests {
         let split = gen_split();
         let center = gen_center_near();
         let node = gen_node_far();
-        assert_eq!(split.near.in_range(&node.address, &center), false);
+        assert!(!split.near.in_range(&node.address, &center));
     }
 
     #[test]
     fn test_should_be_local_manual() {
         let center = gen_center_near();
         let address = gen_node_near().address;
-        let nodes = vec![
+        let nodes = [
             gen_node("first"),
             gen_node("second"),
             gen_node("third"),
@@ -1372,7 +2370,66 @@ mod tests {
             Some(i) => i <= 3,
             None => false,
         };
-        assert_eq!(res, true);
+        assert!(res);
+    }
+
+    #[test]
+    fn test_table_replicas() {
+        let center = gen_center();
+        let safe = Safe::new(20, center.clone());
+        let mut addresses = vec![center.public.clone()];
+        for _ in 0..10 {
+            let node = Node::new(Address::random(), None);
+            addresses.push(node.address.clone());
+            let _ = safe.try_add(node);
+        }
+        let target = Address::random();
+        addresses.retain(|address| safe.find(address).is_some() || address == &center.public);
+        addresses.sort_by_key(|address| address ^ &target);
+        assert_eq!(safe.replicas(&target, 3), addresses[..3].to_vec());
+        assert_eq!(
+            safe.replicas(&center.public, 1),
+            vec![center.public.clone()]
+        );
+        assert_eq!(safe.replicas(&target, 100).len(), addresses.len());
+    }
+
+    #[test]
+    fn test_table_closest() {
+        let safe = Safe::new(20, gen_center());
+        assert_eq!(safe.closest(&Address::random()).next(), None);
+        for _ in 0..30 {
+            let _ = safe.try_add(Node::new(Address::random(), None));
+        }
+        let target = Address::random();
+        let closest = safe.closest(&target);
+        assert_eq!(closest.len(), safe.len());
+        let addresses: Vec<Address> = closest.map(|node| node.address).collect();
+        let mut sorted = addresses.clone();
+        sorted.sort_by(|a, b| target.cmp_distance(a, b));
+        assert_eq!(addresses, sorted);
+        let mut replicas = safe.replicas(&target, 4);
+        replicas.retain(|address| address != &safe.center());
+        assert_eq!(addresses[..3].to_vec(), replicas[..3]);
+    }
+
+    #[test]
+    fn test_table_should_be_local() {
+        let center = gen_center();
+        let safe = Safe::new(20, center.clone());
+        let target = Address::random();
+        assert!(safe.should_be_local(&target));
+        // The node of the target is the closest one, no matter how
+        // many nodes got added after it.
+        let _ = safe.try_add(Node::new(target.clone(), None));
+        for _ in 0..10 {
+            let _ = safe.try_add(Node::new(Address::random(), None));
+        }
+        assert!(!safe.should_be_local(&target));
+        assert!(safe.should_be_local(&center.public));
+        let other = Address::random();
+        let closest = safe.replicas(&other, 1);
+        assert_eq!(safe.should_be_local(&other), closest[0] == center.public);
     }
 
     #[test]
@@ -1388,6 +2445,51 @@ mod tests {
         assert_eq!(safe.len(), 1);
     }
 
+    #[test]
+    fn test_safe_concurrent_reads() {
+        let safe = Safe::new(20, gen_center());
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let guard = safe.read();
+        let inner = safe.clone();
+        std::thread::spawn(move || {
+            // Only waits for writers, not for other readers.
+            let _ = sender.send(inner.get_copy(&Address::random(), 20).len());
+        });
+        let copied = receiver.recv_timeout(std::time::Duration::from_secs(1));
+        assert_eq!(copied, Ok(0));
+        drop(guard);
+
+        let writer = safe.clone();
+        let writer = std::thread::spawn(move || {
+            for i in 0..200 {
+                let node = gen_node(&i.to_string());
+                writer.add(node.clone());
+                writer.status(&node.address, true);
+            }
+        });
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = safe.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        let target = Address::random();
+                        let nodes = reader.get_copy(&target, 20);
+                        assert!(nodes.len() <= 20);
+                        reader.should_be_local(&target);
+                        assert!(!reader.export().is_empty() || reader.is_empty());
+                    }
+                })
+            })
+            .collect();
+        writer.join().unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let nodes = safe.get_copy(&Address::random(), 500);
+        assert_eq!(nodes.len(), safe.len());
+        assert!(nodes.iter().all(|node| safe.find(&node.address).is_some()));
+    }
+
     #[test]
     fn test_safe_random() {
         let center = gen_center();
@@ -1402,17 +2504,56 @@ mod tests {
         assert_eq!(safe.len(), 100);
     }
 
+    #[test]
+    fn test_table_random_index() {
+        let table = Table::new(20, gen_center());
+        for index in [0, 1, 7, 8, 42, 200] {
+            assert_eq!(table.index(&table.random(index)), index);
+        }
+        assert_eq!(table.index(&table.center()), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_table_stale() {
+        let mut table = Table::new(20, gen_center());
+        assert!(table.stale(Duration::from_secs(0)).is_empty());
+        let address = table.random(3);
+        table.add(Node::new(address.clone(), None));
+        assert_eq!(table.stale(Duration::from_secs(0)), vec![0, 1, 2, 3]);
+        assert_eq!(table.stale(Duration::from_secs(60)), vec![0, 1, 2]);
+        table.touch(&table.random(1));
+        assert_eq!(table.stale(Duration::from_secs(60)), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_safe_events() {
+        let safe = Safe::new(20, gen_center());
+        let mut events = safe.events().subscribe();
+        let node = gen_node("first");
+        let address = node.address.clone();
+        safe.add(node.clone());
+        safe.add(node);
+        safe.remove(&address).unwrap();
+        assert!(safe.remove(&address).is_err());
+        assert_eq!(
+            events.try_recv(),
+            Some(event::Event::NodeAdded(address.clone()))
+        );
+        assert_eq!(events.try_recv(), Some(event::Event::NodeRemoved(address)));
+        assert_eq!(events.try_recv(), None);
+    }
+
     fn gen_split() -> Split {
         let near = Bucket::new(20);
         let np = Property {
-            lower: 0,
-            upper: 127,
+            depth: 1,
+            near: true,
         };
         let near = Element::Leaf(near, np);
         let far = Bucket::new(20);
         let fp = Property {
-            lower: 128,
-            upper: 255,
+            depth: 0,
+            near: false,
         };
         let far = Element::Leaf(far, fp);
         Split {
@@ -1429,6 +2570,265 @@ mod tests {
         root
     }
 
+    #[test]
+    fn test_table_depth() {
+        let center = gen_center();
+        let mut deep = Table::new(2, center.clone());
+        let mut shallow = Table::new(2, center);
+        shallow.set_depth(8);
+        // All nodes share at least 12 bits with the Center.
+        for index in 12..20 {
+            let node = Node::new(deep.random(index), None);
+            deep.add(node.clone());
+            shallow.add(node);
+        }
+        assert_eq!(deep.len(), 8);
+        let buckets = deep.buckets();
+        assert!(buckets.iter().any(|bucket| bucket.depth() > 8));
+        for bucket in &buckets {
+            for node in bucket.nodes() {
+                let zeros = node.address().leading_zeros(&deep.center()) as usize;
+                assert!(zeros >= bucket.depth());
+            }
+        }
+
+        // The near bucket can't split anymore and stays full.
+        assert_eq!(shallow.len(), 2);
+        let buckets = shallow.buckets();
+        assert_eq!(buckets.len(), 9);
+        assert_eq!(buckets[0].depth(), 8);
+        assert_eq!(buckets[0].range(), 0..=0);
+        assert_eq!(buckets[8].range(), 128..=255);
+    }
+
+    #[test]
+    fn test_table_sample() {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("127.0.0.1"), 4242);
+        let mut table = Table::new(3, center.clone());
+        let link = Link::new(String::from("127.0.0.1"), 4243);
+        for index in 0..6 {
+            for _ in 0..3 {
+                table.add(Node::new(table.random(index), Some(link.clone())));
+            }
+        }
+        let failed = Node::new(table.random(0), Some(link.clone()));
+        table.add(failed.clone());
+        table.status(&failed.address, false);
+        let hidden = Node::new(table.random(1), None);
+        table.add(hidden.clone());
+
+        let filled: Vec<_> = table
+            .buckets()
+            .into_iter()
+            .filter(|bucket| !bucket.is_empty())
+            .collect();
+        assert!(filled.len() > 2);
+        let nodes = Node::from_bulk(table.sample(filled.len()));
+        assert_eq!(nodes.len(), filled.len() + 1);
+        assert_eq!(nodes.last().unwrap().address, center.public);
+        // One node of every bucket.
+        for bucket in &filled {
+            let found = nodes
+                .iter()
+                .filter(|node| bucket.nodes().iter().any(|n| n.address() == &node.address))
+                .count();
+            assert_eq!(found, 1);
+        }
+
+        let nodes = Node::from_bulk(table.sample(100));
+        let reachable = table
+            .get(&center.public, table.len())
+            .into_iter()
+            .filter(|node| node.link.is_some() && node.failures() == 0)
+            .count();
+        assert_eq!(nodes.len(), reachable + 1);
+        assert!(table.find(&failed.address).is_some());
+        assert!(!nodes.contains(&failed));
+        assert!(!nodes.contains(&hidden));
+        assert_eq!(Node::from_bulk(table.sample(0)).len(), 1);
+    }
+
+    #[test]
+    fn test_table_inspect() {
+        let mut table = Table::new(20, gen_center());
+        assert_eq!(table.inspect().buckets.len(), 1);
+        let mut node = gen_node("first");
+        node.link = Some(Link::new(String::from("127.0.0.1"), 4242));
+        table.add(node.clone());
+        table.add(gen_node("second"));
+        table.status(&node.address, false);
+        table.measure(&node.address, Duration::from_millis(3));
+
+        let snapshot = table.inspect();
+        assert_eq!(snapshot.buckets[0].capacity, 20);
+        let nodes = &snapshot.buckets[0].nodes;
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].address, node.address.to_hex());
+        assert_eq!(nodes[0].link, Some(String::from("127.0.0.1:4242")));
+        assert_eq!(nodes[0].failures, 1);
+        assert!(!nodes[0].reachable);
+        assert_eq!(nodes[0].rtt, Some(3000));
+        assert_eq!(nodes[1].link, None);
+        assert_eq!(nodes[1].rtt, None);
+    }
+
+    #[test]
+    fn test_safe_buckets() {
+        let safe = Safe::new(4, gen_center());
+        for _ in 0..40 {
+            let _ = safe.try_add(Node::new(Address::random(), None));
+        }
+        let mut next = 0;
+        let mut count = 0;
+        safe.for_each_bucket(|bucket| {
+            assert_eq!(*bucket.range().start(), next as u8);
+            next = *bucket.range().end() as usize + 1;
+            assert!(bucket.len() <= bucket.capacity());
+            for node in bucket.nodes() {
+                // The table can be used while visiting it.
+                assert_eq!(safe.find(node.address()), Some(node.to_node()));
+                assert!(!node.is_reachable());
+            }
+            count += bucket.len();
+        });
+        assert_eq!((next, count), (256, safe.len()));
+
+        let buckets: Vec<_> = safe.buckets().collect();
+        assert_eq!(buckets.len(), safe.inspect().buckets.len());
+        let nodes: usize = buckets.iter().map(|(_, nodes)| nodes.len()).sum();
+        assert_eq!(nodes, safe.len());
+    }
+
+    #[test]
+    fn test_table_replacement() {
+        let mut table = Table::new(1, gen_center());
+        let first = gen_node("first");
+        let second = gen_node("second");
+        table.try_add(first.clone()).unwrap();
+        assert!(table.try_add(second.clone()).is_err());
+        assert_eq!(table.len(), 1);
+
+        assert_eq!(
+            table.evict(&first.address).unwrap(),
+            Some(second.address.clone())
+        );
+        assert!(table.find(&second.address).is_some());
+        assert_eq!(table.evict(&second.address).unwrap(), None);
+        assert!(table.evict(&second.address).is_err());
+    }
+
+    #[test]
+    fn test_safe_eviction() {
+        let safe = Safe::new(20, gen_center()).with_attempts(2);
+        let mut node = gen_node("first");
+        node.link = Some(Link::new(String::from("127.0.0.1"), 4242));
+        let address = node.address.clone();
+        safe.add(node);
+        let mut events = safe.events().subscribe();
+
+        safe.status(&address, false);
+        safe.status(&address, true);
+        safe.status(&address, false);
+        assert!(safe.find(&address).is_some());
+        safe.status(&address, false);
+        assert!(safe.find(&address).is_none());
+        assert_eq!(events.try_recv(), Some(event::Event::NodeRemoved(address)));
+        assert_eq!(events.try_recv(), None);
+    }
+
+    #[test]
+    fn test_table_difficulty() {
+        let mut table = Table::new(20, gen_center());
+        table.set_difficulty(8);
+        let weak = (0..)
+            .map(|i| Address::generate(&i.to_string()))
+            .find(|address| address.work() < 8)
+            .unwrap();
+        assert!(!table.accepts(&weak));
+        assert!(table.try_add(Node::new(weak.clone(), None)).is_err());
+        table.add(Node::new(weak.clone(), None));
+        assert!(table.find(&weak).is_none());
+
+        let strong = Center::generate(String::from(""), 0, 8).public;
+        table.add(Node::new(strong.clone(), None));
+        assert!(table.find(&strong).is_some());
+        table.set_difficulty(0);
+        table.add(Node::new(weak.clone(), None));
+        assert!(table.find(&weak).is_some());
+    }
+
+    #[test]
+    fn test_table_conflict() {
+        let center = gen_center();
+        let mut table = Table::new(20, center.clone());
+        table.add(Node::new(center.public.clone(), None));
+        assert!(table
+            .try_add(Node::new(center.public.clone(), None))
+            .is_err());
+        assert!(table.is_empty());
+
+        let mut node = gen_node("first");
+        node.link = Some(Link::new(String::from("127.0.0.1"), 4242));
+        let address = node.address.clone();
+        table.add(node.clone());
+        let mut conflicting = node.clone();
+        conflicting.link = Some(Link::new(String::from("127.0.0.1"), 4343));
+        table.add(conflicting.clone());
+        assert_eq!(table.find(&address).unwrap().link, node.link);
+        assert_eq!(table.conflicts(), vec![address.clone()]);
+
+        // The Node answered at its known Link.
+        assert!(!table.status(&address, true));
+        assert!(table.conflicts().is_empty());
+        let link = table.find(&address).unwrap().link.clone().unwrap();
+        assert!(link.is_same(node.link.as_ref().unwrap()));
+
+        // A failed connection or dial doesn't verify anything, the
+        // conflict stays until its Ping timed out.
+        table.add(conflicting.clone());
+        assert!(!table.status(&address, false));
+        assert_eq!(table.conflicts(), vec![address.clone()]);
+        let link = table.find(&address).unwrap().link.clone().unwrap();
+        assert!(link.is_same(node.link.as_ref().unwrap()));
+
+        // The Node didn't answer, the conflicting Link replaces it.
+        assert!(table.resolve(&address));
+        assert!(table.conflicts().is_empty());
+        assert_eq!(table.find(&address).unwrap().link, conflicting.link);
+        assert!(!table.resolve(&address));
+
+        // Links confirmed by the Node itself replace the known one.
+        table.add(node.clone());
+        table.replace(node.clone());
+        assert!(table.conflicts().is_empty());
+        assert_eq!(table.find(&address).unwrap().link, node.link);
+    }
+
+    #[test]
+    fn test_table_propose() {
+        let center = gen_center();
+        let mut table = Table::new(20, center.clone());
+        let mut node = gen_node("first");
+        node.link = Some(Link::new(String::from("127.0.0.1"), 4242));
+        table.propose(node.clone());
+        table.propose(gen_node("unreachable"));
+        table.propose(Node::new(center.public.clone(), node.link.clone()));
+        assert!(table.is_empty());
+        assert_eq!(table.unverified(10), vec![node.clone()]);
+        assert!(table.unverified(10).is_empty());
+
+        // Known Nodes don't have to be verified again, but their Link
+        // isn't replaced either.
+        table.add(node.clone());
+        let mut conflicting = node.clone();
+        conflicting.link = Some(Link::new(String::from("127.0.0.1"), 4343));
+        table.propose(conflicting);
+        assert!(table.unverified(10).is_empty());
+        assert_eq!(table.find(&node.address).unwrap().link, node.link);
+        assert_eq!(table.conflicts(), vec![node.address]);
+    }
+
     fn gen_node(s: &str) -> Node {
         Node::new(Address::generate(s), None)
     }
@@ -1447,7 +2847,11 @@ mod tests {
         let mut b = [0; 32];
         b[0] = 42;
         let s = SecretKey::from_slice(&b).unwrap();
-        Center::new(s, String::from(""), 8080)
+        // The tests depend on the Address of this key.
+        Center {
+            public: Address::new(s.public_key()),
+            ..Center::new(s, String::from(""), 8080)
+        }
     }
 
     fn gen_center_near() -> Center {
@@ -0,0 +1,265 @@
+//! # Selection
+//!
+//! Wires are sent to the nodes closest to their target. The routing
+//! table only returns the candidates, a SelectionPolicy decides in
+//! which order the Listener tries them. By default the closest node
+//! is always tried first, the other policies also take the
+//! reachability or the round-trip time of the nodes into account.
+//!
+//! The Listener records for each node whether handing a Wire to it
+//! succeeded, which includes dialing it if there was no connection
+//! yet. Policies get these Deliveries to avoid nodes that failed
+//! recently.
+
+use crate::node::{Address, Node};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Maximum number of nodes the Deliveries keep track of.
+const TRACKED: usize = 1024;
+
+/// Orders the candidates for a target. It is owned by the Listener
+/// thread and consulted for every distributed Wire.
+pub trait SelectionPolicy: Send + Sync {
+    /// Number of candidates requested from the routing table should
+    /// the Wire go to at most limit nodes.
+    fn candidates(&self, limit: usize) -> usize {
+        limit
+    }
+
+    /// Returns the candidates in the order they should be tried,
+    /// left out ones don't get used. Only the first limit nodes are
+    /// kept afterwards.
+    fn select(&self, target: &Address, candidates: Vec<Node>, deliveries: &Deliveries)
+        -> Vec<Node>;
+}
+
+/// Closest nodes first, after the closest one faster links come
+/// first among nodes in the same bucket of the target. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Closest;
+
+/// Like Closest, but nodes that failed to respond or to receive the
+/// last Wire are only tried once no other node is left. That might
+/// even be the closest node.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClosestReachable;
+
+/// Fastest links among twice as many of the closest nodes, nodes
+/// without a measured round-trip time come last. The closest node
+/// only comes first if it is the fastest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestLatency;
+
+/// Number of failed deliveries in a row for each node, a successful
+/// one resets it. Once the limit is reached the nodes with the
+/// fewest failures are forgotten first.
+#[derive(Debug, Clone)]
+pub struct Deliveries {
+    failures: HashMap<Address, usize>,
+    limit: usize,
+}
+
+impl SelectionPolicy for Closest {
+    fn select(
+        &self,
+        target: &Address,
+        mut candidates: Vec<Node>,
+        _deliveries: &Deliveries,
+    ) -> Vec<Node> {
+        sort(target, &mut candidates);
+        candidates
+    }
+}
+
+impl SelectionPolicy for ClosestReachable {
+    fn select(
+        &self,
+        target: &Address,
+        mut candidates: Vec<Node>,
+        deliveries: &Deliveries,
+    ) -> Vec<Node> {
+        sort(target, &mut candidates);
+        // The sort is stable, so both groups stay ordered.
+        candidates.sort_by_key(|node| node.failures() > 0 || deliveries.failed(&node.address));
+        candidates
+    }
+}
+
+impl SelectionPolicy for LowestLatency {
+    fn candidates(&self, limit: usize) -> usize {
+        limit * 2
+    }
+
+    fn select(
+        &self,
+        target: &Address,
+        mut candidates: Vec<Node>,
+        _deliveries: &Deliveries,
+    ) -> Vec<Node> {
+        candidates
+            .sort_by_key(|node| (node.rtt().unwrap_or(Duration::MAX), &node.address ^ target));
+        candidates
+    }
+}
+
+impl Deliveries {
+    pub fn new() -> Self {
+        Self::with_limit(TRACKED)
+    }
+
+    /// Keeps track of at most limit nodes.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            failures: HashMap::new(),
+            limit,
+        }
+    }
+
+    /// Records whether handing a Wire to the node succeeded.
+    pub fn record(&mut self, address: &Address, delivered: bool) {
+        if delivered {
+            self.failures.remove(address);
+            return;
+        }
+        if !self.failures.contains_key(address) && self.failures.len() >= self.limit {
+            let fewest = self
+                .failures
+                .iter()
+                .min_by_key(|(_, failures)| **failures)
+                .map(|(address, _)| address.clone());
+            if let Some(fewest) = fewest {
+                self.failures.remove(&fewest);
+            }
+        }
+        *self.failures.entry(address.clone()).or_insert(0) += 1;
+    }
+
+    /// Number of failed deliveries to the node in a row.
+    pub fn failures(&self, address: &Address) -> usize {
+        self.failures.get(address).copied().unwrap_or(0)
+    }
+
+    /// True if the last delivery to the node failed.
+    pub fn failed(&self, address: &Address) -> bool {
+        self.failures.contains_key(address)
+    }
+}
+
+impl Default for Deliveries {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Orders the nodes by their distance to the target. The table only
+/// roughly orders the nodes, so a known target might come after
+/// other nodes. The closest node is responsible for the target and
+/// stays first, the others in the same bucket of the target are
+/// equally close, among those faster links come first.
+fn sort(target: &Address, nodes: &mut [Node]) {
+    nodes.sort_by_key(|node| &node.address ^ target);
+    if let Some(others) = nodes.get_mut(1..) {
+        others.sort_by_key(|node| {
+            (
+                Reverse(node.address.leading_zeros(target)),
+                node.rtt().unwrap_or(Duration::MAX),
+            )
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Link;
+
+    fn gen_node(first: u8, rtt: Option<u64>) -> Node {
+        let mut bytes = [0; 32];
+        bytes[0] = first;
+        bytes[31] = first;
+        let link = Link::new(String::from("127.0.0.1"), 4242);
+        let mut node = Node::new(Address::from_bytes(bytes), Some(link));
+        if let Some(rtt) = rtt {
+            node.measure(Duration::from_millis(rtt));
+        }
+        node
+    }
+
+    fn addresses(nodes: &[Node]) -> Vec<u8> {
+        nodes
+            .iter()
+            .map(|node| node.address.as_bytes()[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_selection_closest() {
+        let target = Address::from_bytes([0; 32]);
+        let candidates = vec![
+            gen_node(128, Some(1)),
+            gen_node(4, Some(20)),
+            gen_node(5, Some(10)),
+            gen_node(3, Some(50)),
+            gen_node(2, Some(90)),
+        ];
+        let selected = Closest.select(&target, candidates, &Deliveries::new());
+        // The closest node stays first, the others in the same bucket
+        // are ordered by their round-trip time.
+        assert_eq!(addresses(&selected), vec![2, 3, 5, 4, 128]);
+        assert_eq!(Closest.candidates(3), 3);
+    }
+
+    #[test]
+    fn test_selection_closest_reachable() {
+        let target = Address::from_bytes([0; 32]);
+        let mut unreachable = gen_node(1, None);
+        unreachable.update(false);
+        let candidates = vec![gen_node(4, None), unreachable, gen_node(2, None)];
+        let mut deliveries = Deliveries::new();
+        deliveries.record(&gen_node(2, None).address, false);
+        let selected = ClosestReachable.select(&target, candidates.clone(), &deliveries);
+        assert_eq!(addresses(&selected), vec![4, 1, 2]);
+
+        deliveries.record(&gen_node(2, None).address, true);
+        let selected = ClosestReachable.select(&target, candidates, &deliveries);
+        assert_eq!(addresses(&selected), vec![2, 4, 1]);
+    }
+
+    #[test]
+    fn test_selection_lowest_latency() {
+        let target = Address::from_bytes([0; 32]);
+        let candidates = vec![
+            gen_node(1, None),
+            gen_node(2, Some(30)),
+            gen_node(64, Some(5)),
+            gen_node(3, None),
+        ];
+        let selected = LowestLatency.select(&target, candidates, &Deliveries::new());
+        assert_eq!(addresses(&selected), vec![64, 2, 1, 3]);
+        assert_eq!(LowestLatency.candidates(3), 6);
+    }
+
+    #[test]
+    fn test_deliveries() {
+        let mut deliveries = Deliveries::with_limit(2);
+        let first = gen_node(1, None).address;
+        let second = gen_node(2, None).address;
+        let third = gen_node(3, None).address;
+        deliveries.record(&first, false);
+        deliveries.record(&first, false);
+        deliveries.record(&second, false);
+        assert_eq!(deliveries.failures(&first), 2);
+        assert!(deliveries.failed(&second));
+
+        // The node with the fewest failures makes room.
+        deliveries.record(&third, false);
+        assert!(!deliveries.failed(&second));
+        assert_eq!(deliveries.failures(&third), 1);
+
+        deliveries.record(&first, true);
+        assert_eq!(deliveries.failures(&first), 0);
+        assert!(!deliveries.failed(&first));
+    }
+}
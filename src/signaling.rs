@@ -1,28 +1,74 @@
 //! # Signaling
 //!
 //! Responsible for Kademlia background tasks and bootstrapping the
-//! Instance.
+//! Instance. It also contains the state of iterative node lookups,
+//! which are driven by the Switch.
 
+use crate::config::Config;
 use crate::message::Message;
-use crate::node::Address;
+use crate::node::{Address, Node};
 use crate::router::Safe;
+use crate::trace;
 use crate::transaction::{Class, Transaction};
 use crate::util::Channel;
 use std::cell::RefCell;
-use std::thread;
-use std::time::{Duration, SystemTime};
+use std::collections::{HashMap, HashSet};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 
+/// Number of FindNode requests a single lookup can have in flight at
+/// the same time, known as "alpha" in Kademlia.
+pub const ALPHA: usize = 3;
+
+/// Time after which an unanswered FindNode request is considered
+/// failed and the node gets removed from the lookup.
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum time between checks for stale buckets, shorter refresh
+/// intervals are checked more often.
+const REFRESH_CHECK: Duration = Duration::from_secs(60);
+
+/// Time after which a node that hasn't answered a Ping gets removed
+/// from the routing table.
+pub const PING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval in which pending actions get sent to the Switch again.
+const PROCESS_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Represents all the fields needed to run the Signaling thread.
 pub struct Signaling {
     /// Connection to the Switch.
     channel: Channel<SignalingAction>,
     /// Time of the last action.
-    last: SystemTime,
+    last: Instant,
     /// The routing table to update the statup of Nodes.
     table: Safe,
     /// List of active actions.
     bucket: RefCell<ActionBucket>,
+    /// Buckets without activity for this long get refreshed.
+    refresh: Duration,
+    /// Time of the last check for stale buckets.
+    refreshed: Instant,
+    /// Interval in which all Records get republished.
+    republish: Duration,
+    /// Time Records were last republished.
+    republished: Instant,
+    /// Interval in which Records get transferred and synchronized.
+    transfer: Duration,
+    /// Time Records were last transferred and synchronized.
+    transferred: Instant,
+    /// Interval in which subscribers announce their presence.
+    presence: Duration,
+    /// Time of the last presence announcement.
+    announced: Instant,
+    /// Interval in which all nodes in the routing table get pinged.
+    ping: Duration,
+    /// Time of the last liveness check.
+    pinged: Instant,
+    /// Nodes that haven't answered their Ping yet and the time it was
+    /// sent.
+    unanswered: HashMap<Address, Instant>,
 }
 
 /// Instead of storing transactions this thread uses a custom type to
@@ -42,6 +88,28 @@ pub struct ActionBucket {
     actions: Vec<SignalingAction>,
 }
 
+/// State of a single iterative lookup. Starting from the closest
+/// nodes in the local table it repeatedly asks the closest nodes not
+/// yet queried for even closer ones. The lookup is done once the
+/// closest nodes found have all been queried and none of the requests
+/// are still pending.
+#[derive(Debug)]
+pub struct Lookup {
+    /// Address being looked up.
+    target: Address,
+    /// Address of the local node, it never gets queried or returned.
+    center: Address,
+    /// Number of closest nodes the lookup tries to find, comparable
+    /// to the Kademlia "k".
+    limit: usize,
+    /// All nodes found so far, sorted by distance to the target.
+    closest: Vec<Node>,
+    /// Addresses of all nodes that have already been asked.
+    queried: HashSet<Address>,
+    /// Requests without a response and the time they were sent.
+    pending: HashMap<Address, Instant>,
+}
+
 /// Not the same as the Transaction Class, since not all Classes are
 /// possible for Signaling actions.
 #[derive(Eq, PartialEq, Clone)]
@@ -50,30 +118,63 @@ pub enum Type {
     Pong,
     Lookup,
     Details,
+    /// Starts an iterative lookup of the target to refresh its
+    /// bucket.
+    Refresh,
+    /// Republishes all Records, the target is irrelevant.
+    Republish,
+    /// Transfers Records to closer nodes and synchronizes the replicas
+    /// of all others, the target is irrelevant.
+    Transfer,
+    /// Announces the presence on all Topics and expires the presence
+    /// of Records, the target is irrelevant.
+    Presence,
 }
 
 impl Signaling {
     /// Creates a new Signaling object without starting the thread.
-    pub fn new(channel: Channel<SignalingAction>, table: Safe) -> Self {
+    /// The refresh, republish, transfer, presence and ping intervals
+    /// are taken from the Config.
+    pub fn new(channel: Channel<SignalingAction>, table: Safe, config: &Config) -> Self {
         Self {
             channel,
-            last: SystemTime::now(),
+            last: Instant::now(),
             table,
             bucket: RefCell::new(ActionBucket::new()),
+            refresh: Duration::from_secs(config.refresh),
+            refreshed: Instant::now(),
+            republish: Duration::from_secs(config.republish),
+            republished: Instant::now(),
+            transfer: Duration::from_secs(config.transfer),
+            transferred: Instant::now(),
+            presence: Duration::from_secs(config.presence.max(1)),
+            announced: Instant::now(),
+            ping: Duration::from_secs(config.ping.max(1)),
+            pinged: Instant::now(),
+            unanswered: HashMap::new(),
         }
     }
 
-    /// Starts the signaling thread.
-    pub fn start(mut self) {
+    /// Starts the signaling thread. The thread stops once the Switch
+    /// is no longer available. Instead of polling the Channel it
+    /// blocks until either a new Action arrives or the next periodic
+    /// task is due.
+    pub fn start(mut self) -> JoinHandle<()> {
         thread::spawn(move || {
             loop {
-                // 1. Try to read from Channel for new Actions.
-                if let Some(action) = self.channel.try_recv() {
+                if self.channel.is_closed() {
+                    trace::trace!("switch is unavailable, terminating signaling.");
+                    break;
+                }
+
+                // 1. Wait for new Actions on the Channel.
+                if let Some(action) = self.channel.recv_timeout(self.due()) {
                     match action.action {
                         Type::Ping => {
                             // Unable to handle
                         }
                         Type::Pong => {
+                            self.unanswered.remove(&action.target);
                             self.table.status(&action.target, true);
                             self.bucket.borrow_mut().remove(action.uuid);
                         }
@@ -84,24 +185,138 @@ impl Signaling {
                             // TODO: Add lookup result to RT
                             self.bucket.borrow_mut().remove(action.uuid);
                         }
+                        Type::Refresh | Type::Republish | Type::Transfer | Type::Presence => {
+                            // Only sent to the Switch
+                        }
                     }
                 }
 
-                // 2. Process an item from the Bucket.
-                if self.last.elapsed().unwrap() >= Duration::new(60, 0) {
-                    self.last = SystemTime::now();
+                // 2. Refresh stale buckets through lookups of random
+                // Addresses in them.
+                if self.refreshed.elapsed() >= self.refresh.min(REFRESH_CHECK) {
+                    self.refreshed = Instant::now();
+                    for index in self.table.stale(self.refresh) {
+                        let target = self.table.random(index);
+                        // Marks the bucket as active, so it only gets
+                        // refreshed once per interval.
+                        self.table.touch(&target);
+                        let _ = self
+                            .channel
+                            .send(SignalingAction::new(Type::Refresh, target));
+                    }
+                }
+
+                // 3. Republish all Records.
+                if self.republished.elapsed() >= self.republish {
+                    self.republished = Instant::now();
+                    let action = SignalingAction::new(Type::Republish, Address::default());
+                    let _ = self.channel.send(action);
+                }
+
+                // 4. Transfer and synchronize Records.
+                if self.transferred.elapsed() >= self.transfer {
+                    self.transferred = Instant::now();
+                    let action = SignalingAction::new(Type::Transfer, Address::default());
+                    let _ = self.channel.send(action);
+                }
+
+                // 5. Announce the presence on all Topics.
+                if self.announced.elapsed() >= self.presence {
+                    self.announced = Instant::now();
+                    let action = SignalingAction::new(Type::Presence, Address::default());
+                    let _ = self.channel.send(action);
+                }
+
+                // 6. Ping all nodes and the ones with a conflicting
+                // Link, remove the ones that didn't answer the last
+                // Ping in time.
+                if self.pinged.elapsed() >= self.ping {
+                    self.pinged = Instant::now();
+                    self.check();
+                }
+                self.verify();
+                self.evict();
+
+                // 7. Process an item from the Bucket.
+                if self.last.elapsed() >= PROCESS_INTERVAL {
+                    self.last = Instant::now();
                     if let Some(action) = self.bucket.borrow().get() {
                         let _ = self.channel.send(action.clone());
                     }
                 }
 
-                if self.bucket.borrow().len() == 0 {
+                if self.bucket.borrow().is_empty() {
                     let action = SignalingAction::new(Type::Lookup, Address::random());
                     self.bucket.borrow_mut().add(action);
                 }
             }
+        })
+    }
+
+    /// Sends a Ping to every node in the routing table that doesn't
+    /// have one pending already.
+    fn check(&mut self) {
+        let center = self.table.center();
+        for node in self.table.get_copy(&center, self.table.len()) {
+            if self.unanswered.contains_key(&node.address) {
+                continue;
+            }
+            self.unanswered.insert(node.address.clone(), Instant::now());
+            let _ = self
+                .channel
+                .send(SignalingAction::new(Type::Ping, node.address));
+        }
+    }
+
+    /// Pings the nodes with a conflicting Link at their known one,
+    /// should they not answer in time the conflicting Link replaces
+    /// it.
+    fn verify(&mut self) {
+        for address in self.table.conflicts() {
+            if self.unanswered.contains_key(&address) {
+                continue;
+            }
+            trace::info!("verifying the link of a node");
+            self.unanswered.insert(address.clone(), Instant::now());
+            let _ = self.channel.send(SignalingAction::new(Type::Ping, address));
+        }
+    }
+
+    /// Marks all nodes that didn't answer their Ping within the
+    /// PING_TIMEOUT as unreachable, the table evicts them once they
+    /// failed too often. Nodes with a conflicting Link get it instead.
+    fn evict(&mut self) {
+        let table = &self.table;
+        self.unanswered.retain(|address, sent| {
+            if sent.elapsed() < PING_TIMEOUT {
+                return true;
+            }
+            trace::info!("node didn't answer the ping");
+            if !table.resolve(address) {
+                table.status(address, false);
+            }
+            false
         });
     }
+
+    /// Time until the next periodic task is due.
+    fn due(&self) -> Duration {
+        let remaining =
+            |since: Instant, interval: Duration| interval.saturating_sub(since.elapsed());
+        remaining(self.refreshed, self.refresh.min(REFRESH_CHECK))
+            .min(remaining(self.republished, self.republish))
+            .min(remaining(self.transferred, self.transfer))
+            .min(remaining(self.announced, self.presence))
+            .min(remaining(self.pinged, self.ping))
+            .min(
+                self.unanswered
+                    .values()
+                    .map(|sent| PING_TIMEOUT.saturating_sub(sent.elapsed()))
+                    .min()
+                    .unwrap_or(PROCESS_INTERVAL),
+            )
+            .min(remaining(self.last, PROCESS_INTERVAL))
+    }
 }
 
 impl SignalingAction {
@@ -149,6 +364,10 @@ impl SignalingAction {
             Type::Details => Class::Details,
             Type::Ping => Class::Ping,
             Type::Pong => Class::Pong,
+            Type::Refresh => Class::FindNode,
+            Type::Republish => Class::Record,
+            Type::Transfer => Class::RecordTransfer,
+            Type::Presence => Class::Announce,
         };
         let body = Vec::new();
         Transaction::new(Message::new(
@@ -161,6 +380,109 @@ impl SignalingAction {
     }
 }
 
+impl Lookup {
+    /// Creates a new lookup for the target, the nodes should be the
+    /// closest ones known locally.
+    pub fn new(target: Address, center: Address, nodes: Vec<Node>, limit: usize) -> Self {
+        let mut lookup = Self {
+            target,
+            center,
+            limit,
+            closest: Vec::new(),
+            queried: HashSet::new(),
+            pending: HashMap::new(),
+        };
+        lookup.insert(nodes);
+        lookup
+    }
+
+    /// Address being looked up.
+    pub fn target(&self) -> &Address {
+        &self.target
+    }
+
+    /// Returns the Addresses of the nodes that should be asked next
+    /// and marks them as pending. At most ALPHA requests are pending
+    /// at the same time and only the closest nodes get queried.
+    pub fn queries(&mut self) -> Vec<Address> {
+        let free = ALPHA.saturating_sub(self.pending.len());
+        let next: Vec<Address> = self
+            .closest
+            .iter()
+            .take(self.limit)
+            .filter(|node| !self.queried.contains(&node.address))
+            .take(free)
+            .map(|node| node.address.clone())
+            .collect();
+        for address in &next {
+            self.queried.insert(address.clone());
+            self.pending.insert(address.clone(), Instant::now());
+        }
+        next
+    }
+
+    /// Processes the response of a queried node. Returns false if no
+    /// request to that node is pending, in which case the nodes get
+    /// ignored.
+    pub fn response(&mut self, source: &Address, nodes: &[Node]) -> bool {
+        if self.pending.remove(source).is_none() {
+            return false;
+        }
+        self.insert(nodes.to_vec());
+        true
+    }
+
+    /// Removes all requests that have been pending for longer than
+    /// the timeout, the nodes are treated as unreachable and dropped
+    /// from the closest nodes.
+    pub fn expire(&mut self, timeout: Duration) {
+        let expired: Vec<Address> = self
+            .pending
+            .iter()
+            .filter(|(_, sent)| sent.elapsed() >= timeout)
+            .map(|(address, _)| address.clone())
+            .collect();
+        for address in expired {
+            self.pending.remove(&address);
+            self.closest.retain(|node| node.address != address);
+        }
+    }
+
+    /// The lookup is done once nothing is pending and all of the
+    /// closest nodes have been queried.
+    pub fn is_done(&self) -> bool {
+        self.pending.is_empty()
+            && self
+                .closest
+                .iter()
+                .take(self.limit)
+                .all(|node| self.queried.contains(&node.address))
+    }
+
+    /// The closest nodes found, sorted by distance to the target.
+    pub fn result(&self) -> Vec<Node> {
+        self.closest.iter().take(self.limit).cloned().collect()
+    }
+
+    /// Adds new nodes and keeps them sorted by distance. Duplicates
+    /// and the local node are ignored.
+    fn insert(&mut self, nodes: Vec<Node>) {
+        for node in nodes {
+            if node.address != self.center && !self.closest.contains(&node) {
+                self.closest.push(node);
+            }
+        }
+        let target = &self.target;
+        self.closest.sort_by_key(|node| &node.address ^ target);
+    }
+}
+
+impl Default for ActionBucket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ActionBucket {
     pub fn new() -> Self {
         Self {
@@ -181,12 +503,87 @@ impl ActionBucket {
 
     pub fn remove(&mut self, uuid: Uuid) {
         let index = self.actions.iter().position(|e| e.uuid == uuid);
-        if index.is_none() {
-            self.actions.remove(index.unwrap());
+        if let Some(i) = index {
+            self.actions.remove(i);
         }
     }
 
     pub fn len(&self) -> usize {
         self.actions.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Center;
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn test_lookup_converges() {
+        let target = Address::random();
+        let nodes: Vec<Node> = (0..10)
+            .map(|_| Node::new(Address::random(), None))
+            .collect();
+        let mut lookup = Lookup::new(target.clone(), Address::random(), vec![nodes[0].clone()], 4);
+        while !lookup.is_done() {
+            let next = lookup.queries();
+            assert!(next.len() <= ALPHA);
+            for address in next {
+                // Every node knows every other node.
+                assert!(lookup.response(&address, &nodes));
+            }
+        }
+        let mut expected = nodes.clone();
+        expected.sort_by_key(|node| &node.address ^ &target);
+        expected.truncate(4);
+        assert_eq!(lookup.result(), expected);
+    }
+
+    #[test]
+    fn test_lookup_expire() {
+        let center = Address::random();
+        let nodes = vec![
+            Node::new(Address::random(), None),
+            Node::new(center.clone(), None),
+        ];
+        let mut lookup = Lookup::new(Address::random(), center, nodes.clone(), 4);
+        assert_eq!(lookup.result().len(), 1);
+        assert_eq!(lookup.queries(), vec![nodes[0].address.clone()]);
+        assert!(!lookup.is_done());
+        assert!(!lookup.response(&Address::random(), &[]));
+        lookup.expire(Duration::from_secs(0));
+        assert!(lookup.is_done());
+        assert!(lookup.result().is_empty());
+    }
+
+    #[test]
+    fn test_signaling_refresh() {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("127.0.0.1"), 0);
+        let table = Safe::new(20, center);
+        table.add(Node::new(table.random(2), None));
+        let mut config = Config::new(20, 5, 100, String::from("127.0.0.1"), 0);
+        config.refresh = 1;
+        let (c1, c2) = Channel::new();
+        Signaling::new(c2, table.clone(), &config).start();
+
+        let mut refreshed = Vec::new();
+        let start = Instant::now();
+        while refreshed.len() < 3 && start.elapsed() < Duration::from_secs(5) {
+            match c1.try_recv() {
+                Some(action) if action.action == Type::Refresh => refreshed.push(action.target),
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        // Bucket 2 is the deepest active one, all of them are stale.
+        let mut buckets: Vec<usize> = refreshed.iter().map(|a| table.index(a)).collect();
+        buckets.sort_unstable();
+        assert_eq!(buckets, vec![0, 1, 2]);
+        assert!(table.stale(Duration::from_secs(1)).is_empty());
+    }
 }
@@ -0,0 +1,423 @@
+//! # Status
+//!
+//! Monitoring systems and orchestrators need to know if a node is
+//! alive without joining the network themselves. The status endpoint
+//! is a minimal HTTP server on a separate TCP port that answers every
+//! GET request with a JSON report of the node: Its uptime, the size
+//! of the routing table, the open connections, the hosted Records and
+//! the result of the last bootstrap.
+//!
+//! ``` text
+//! $ curl http://127.0.0.1:42431/
+//! {"uptime":73,"table":12,"connections":4,"records":2,"bootstrap":"completed"}
+//! ```
+//!
+//! Every request path returns the same report, except for /metrics
+//! with the "prometheus" feature, which returns the metrics of the
+//! node in the Prometheus text format. The endpoint only binds to
+//! localhost unless another address is configured. A few clients get
+//! served at once on blocking threads, so a slow one doesn't hold up
+//! the others, further ones wait until one of them is done. Report and
+//! metrics are rendered at most once per second, no matter how often
+//! they are requested.
+
+use crate::error::Error;
+use crate::event::{Event, EventReceiver};
+use crate::metrics::Metrics;
+use crate::record::RecordBucket;
+use crate::router::Safe;
+use crate::trace;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
+
+/// Time to wait after accepting a request failed, running out of file
+/// descriptors for example doesn't resolve itself right away.
+const BACKOFF: Duration = Duration::from_millis(100);
+
+/// Maximum time a client gets for sending its request and receiving
+/// the report.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Requests larger than this are cut off, only the request line
+/// matters anyway.
+const REQUEST_SIZE: usize = 1024;
+
+/// Maximum number of clients served at once.
+const CLIENTS: usize = 4;
+
+/// Report and metrics are reused for requests within this time.
+const REFRESH: Duration = Duration::from_secs(1);
+
+/// Result of the last bootstrap of the node.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bootstrap {
+    /// The node doesn't bootstrap through the signaling server.
+    Disabled,
+    /// No bootstrap has finished yet.
+    Pending,
+    /// The last bootstrap succeeded.
+    Completed,
+    /// The signaling server could not be reached.
+    Failed,
+}
+
+/// Health of the node at a single point in time, served as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    /// Seconds since the node was started.
+    pub uptime: u64,
+    /// Number of Nodes in the routing table.
+    pub table: usize,
+    /// Number of open connections.
+    pub connections: usize,
+    /// Number of Records hosted on this node.
+    pub records: usize,
+    /// Result of the last bootstrap.
+    pub bootstrap: Bootstrap,
+}
+
+/// Serves the Report of the node to everybody connecting to the
+/// status port.
+pub struct Status {
+    listener: TcpListener,
+    table: Safe,
+    metrics: Metrics,
+    records: RecordBucket,
+    /// Receives the bootstrap Events of the Listener.
+    events: EventReceiver,
+    bootstrap: Bootstrap,
+    started: Instant,
+    /// The last rendered Report and metrics with the time they were
+    /// rendered.
+    rendered: Option<(Instant, Report, Option<String>)>,
+}
+
+impl Status {
+    /// Binds the status port on the address, unlike the discovery
+    /// port it has to be free. Without a bootstrap the Report always
+    /// shows it as disabled.
+    pub fn new(
+        table: Safe,
+        metrics: Metrics,
+        records: RecordBucket,
+        address: &str,
+        port: usize,
+        bootstrap: bool,
+    ) -> Result<Self, Error> {
+        let port = u16::try_from(port)
+            .map_err(|_| Error::Config(String::from("status port is not valid")))?;
+        let address: IpAddr = address
+            .parse()
+            .map_err(|_| Error::Config(String::from("status address is not valid")))?;
+        let listener = TcpListener::bind((address, port))?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            events: table.events().subscribe(),
+            table,
+            metrics,
+            records,
+            bootstrap: match bootstrap {
+                true => Bootstrap::Pending,
+                false => Bootstrap::Disabled,
+            },
+            started: Instant::now(),
+            rendered: None,
+        })
+    }
+
+    /// Spawns a tokio task that waits for incoming requests, each of
+    /// them gets answered on a blocking thread of its own. No more
+    /// than CLIENTS requests are answered at once. It runs until it
+    /// gets aborted.
+    pub fn start(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let listener = match self
+                .listener
+                .try_clone()
+                .and_then(tokio::net::TcpListener::from_std)
+            {
+                Ok(listener) => listener,
+                Err(e) => {
+                    trace::error!("unable to serve status requests: {}", e);
+                    return;
+                }
+            };
+            let clients = Arc::new(Semaphore::new(CLIENTS));
+            loop {
+                // The Semaphore is never closed.
+                let permit = match clients.clone().acquire_owned().await {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                };
+                let accepted = listener.accept().await;
+                let stream = match accepted.and_then(|(stream, _)| stream.into_std()) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        trace::warn!("unable to accept status request: {}", e);
+                        tokio::time::sleep(BACKOFF).await;
+                        continue;
+                    }
+                };
+                let (report, metrics) = self.render();
+                tokio::task::spawn_blocking(move || {
+                    if let Err(e) = Self::respond(stream, &report, metrics) {
+                        trace::warn!("unable to serve status request: {}", e);
+                    }
+                    drop(permit);
+                });
+            }
+        })
+    }
+
+    /// Collects the current state of the node.
+    pub fn report(&self) -> Report {
+        let metrics = self.metrics.snapshot();
+        Report {
+            uptime: self.started.elapsed().as_secs(),
+            table: self.table.len(),
+            connections: metrics.connections,
+            records: self.records.addresses().len(),
+            bootstrap: self.bootstrap,
+        }
+    }
+
+    /// Report and metrics for a request, they are only rendered again
+    /// once the last ones are older than REFRESH.
+    fn render(&mut self) -> (Report, Option<String>) {
+        if let Some((rendered, report, metrics)) = &self.rendered {
+            if rendered.elapsed() < REFRESH {
+                return (report.clone(), metrics.clone());
+            }
+        }
+        self.update();
+        let (report, metrics) = (self.report(), self.metrics());
+        self.rendered = Some((Instant::now(), report.clone(), metrics.clone()));
+        (report, metrics)
+    }
+
+    /// Renders the Prometheus metrics, None without the feature.
+    #[cfg(feature = "prometheus")]
+    fn metrics(&self) -> Option<String> {
+        let table = self.table.inspect();
+        Some(crate::prometheus::render(
+            &self.metrics,
+            &table,
+            &self.records,
+        ))
+    }
+
+    #[cfg(not(feature = "prometheus"))]
+    fn metrics(&self) -> Option<String> {
+        None
+    }
+
+    /// Applies all waiting bootstrap Events, only the last one
+    /// counts.
+    fn update(&mut self) {
+        while let Some(event) = self.events.try_recv() {
+            match event {
+                Event::BootstrapCompleted(_) => self.bootstrap = Bootstrap::Completed,
+                Event::BootstrapFailed => self.bootstrap = Bootstrap::Failed,
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the request and answers it, GET requests with the
+    /// Report or the metrics and all others with an error.
+    fn respond(
+        mut stream: TcpStream,
+        report: &Report,
+        metrics: Option<String>,
+    ) -> Result<(), Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut request = Vec::new();
+        let mut buffer = [0; 256];
+        while !request.ends_with(b"\r\n\r\n") && !request.ends_with(b"\n\n") {
+            match stream.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(length) => request.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+            if request.len() >= REQUEST_SIZE {
+                break;
+            }
+        }
+        stream.write_all(&Self::response(&request, report, metrics))?;
+        Ok(())
+    }
+
+    /// Builds the HTTP response for the raw request, the metrics are
+    /// only served if they are available.
+    fn response(request: &[u8], report: &Report, metrics: Option<String>) -> Vec<u8> {
+        let json = "application/json";
+        let (status, kind, body) = match metrics {
+            Some(metrics) if request.starts_with(b"GET /metrics ") => {
+                ("200 OK", "text/plain; version=0.0.4", metrics)
+            }
+            _ if request.starts_with(b"GET ") => (
+                "200 OK",
+                json,
+                serde_json::to_string(report).unwrap_or_default(),
+            ),
+            _ => (
+                "405 Method Not Allowed",
+                json,
+                String::from("{\"error\":\"only GET is supported\"}"),
+            ),
+        };
+        format!(
+            "HTTP/1.0 {}\r\nContent-Type: {}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            kind,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node::Center;
+    use sodiumoxide::crypto::box_;
+
+    fn create(port: usize, bootstrap: bool) -> Status {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("127.0.0.1"), port);
+        let table = Safe::new(20, center);
+        let records = RecordBucket::new();
+        Status::new(table, Metrics::new(), records, "127.0.0.1", port, bootstrap).unwrap()
+    }
+
+    #[test]
+    fn test_status_report() {
+        let mut status = create(45663, true);
+        assert_eq!(status.report().bootstrap, Bootstrap::Pending);
+        status.table.events().emit(Event::BootstrapFailed);
+        status.update();
+        assert_eq!(status.report().bootstrap, Bootstrap::Failed);
+        let address = status.table.center();
+        status
+            .table
+            .events()
+            .emit(Event::BootstrapCompleted(address));
+        status.update();
+        let report = status.report();
+        assert_eq!(report.bootstrap, Bootstrap::Completed);
+        assert_eq!(
+            (report.table, report.connections, report.records),
+            (0, 0, 0)
+        );
+        assert!(Status::new(
+            status.table.clone(),
+            Metrics::new(),
+            RecordBucket::new(),
+            "127.0.0.1",
+            45663,
+            true
+        )
+        .is_err());
+        assert!(Status::new(
+            status.table.clone(),
+            Metrics::new(),
+            RecordBucket::new(),
+            "localhost",
+            45686,
+            true
+        )
+        .is_err());
+
+        let disabled = create(45664, false);
+        assert_eq!(disabled.report().bootstrap, Bootstrap::Disabled);
+    }
+
+    #[test]
+    fn test_status_response() {
+        let report = create(45665, false).report();
+        let response = Status::response(b"GET / HTTP/1.1\r\n\r\n", &report, None);
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let parsed: Report = serde_json::from_str(body).unwrap();
+        assert_eq!(parsed, report);
+        assert!(body.contains("\"bootstrap\":\"disabled\""));
+
+        let response = Status::response(b"POST / HTTP/1.1\r\n\r\n", &report, None);
+        assert!(response.starts_with(b"HTTP/1.0 405"));
+
+        // Without the metrics /metrics is just another path.
+        let request = b"GET /metrics HTTP/1.1\r\n\r\n";
+        let response = Status::response(request, &report, None);
+        assert!(String::from_utf8(response)
+            .unwrap()
+            .contains("\"bootstrap\""));
+        let metrics = Some(String::from("actaeon_connections 0\n"));
+        let response = String::from_utf8(Status::response(request, &report, metrics)).unwrap();
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        assert!(response.ends_with("\r\n\r\nactaeon_connections 0\n"));
+    }
+
+    #[tokio::test]
+    async fn test_status_concurrent() {
+        let status = create(45687, false);
+        let address = status.listener.local_addr().unwrap();
+        assert!(address.ip().is_loopback());
+        let handle = status.start();
+
+        // A client that never sends its request doesn't delay others.
+        let _slow = TcpStream::connect(address).unwrap();
+        let started = Instant::now();
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(started.elapsed() < TIMEOUT);
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_status_clients() {
+        let status = create(45688, false);
+        let address = status.listener.local_addr().unwrap();
+        let handle = status.start();
+
+        // Further clients wait until the slow ones timed out.
+        let _slow: Vec<TcpStream> = (0..CLIENTS)
+            .map(|_| TcpStream::connect(address).unwrap())
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let started = Instant::now();
+        let response = tokio::task::spawn_blocking(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        })
+        .await
+        .unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(started.elapsed() >= TIMEOUT / 2);
+        handle.abort();
+    }
+}
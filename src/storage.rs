@@ -0,0 +1,266 @@
+//! # Storage
+//!
+//! Besides Topics the network can store plain values under a key,
+//! similar to the STORE and FIND_VALUE requests of Kademlia. A value
+//! gets stored on the closest nodes to its key, which are found with
+//! an iterative lookup. Reading it looks up the same nodes and asks
+//! them for the value.
+//!
+//! Values only live for their TTL, after that they are dropped and
+//! have to be stored again. Like Records they are only kept in
+//! memory. Each node only keeps a limited number and size of values,
+//! and only the node that stored a value can replace it before it
+//! expires.
+
+use crate::error::Error;
+use crate::node::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Upper limit for the TTL of a value, longer ones get shortened.
+pub const MAX_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A stored value with the node that stored it and the time it
+/// expires.
+#[derive(Debug, Clone)]
+struct Value {
+    data: Vec<u8>,
+    owner: Address,
+    expires: Instant,
+}
+
+/// All values stored on this node.
+#[derive(Debug)]
+pub struct ValueStore {
+    values: HashMap<Address, Value>,
+    /// Maximum number of values.
+    entries: usize,
+    /// Maximum total size of all values in bytes.
+    limit: usize,
+    /// Current total size of all values in bytes.
+    size: usize,
+}
+
+impl ValueStore {
+    /// Creates an empty store that keeps at most the number of values
+    /// with at most the total size in bytes.
+    pub fn new(entries: usize, limit: usize) -> Self {
+        Self {
+            values: HashMap::new(),
+            entries,
+            limit,
+            size: 0,
+        }
+    }
+
+    /// Stores the value of the owner under the key. A value that
+    /// hasn't expired yet can only be replaced by its owner. Should
+    /// the store be full even without the expired values the value
+    /// gets rejected.
+    pub fn put(
+        &mut self,
+        key: Address,
+        owner: Address,
+        data: Vec<u8>,
+        ttl: Duration,
+    ) -> Result<(), Error> {
+        let now = Instant::now();
+        if let Some(value) = self.values.get(&key) {
+            if value.expires > now && value.owner != owner {
+                return Err(Error::Invalid(String::from(
+                    "value is owned by another node",
+                )));
+            }
+        }
+        if !self.fits(&key, data.len()) {
+            self.expire(now);
+            if !self.fits(&key, data.len()) {
+                return Err(Error::Full);
+            }
+        }
+        let value = Value {
+            data,
+            owner,
+            expires: now + ttl.min(MAX_TTL),
+        };
+        self.size += value.data.len();
+        if let Some(previous) = self.values.insert(key, value) {
+            self.size -= previous.data.len();
+        }
+        Ok(())
+    }
+
+    /// Returns the value of the key unless it has expired.
+    pub fn get(&self, key: &Address) -> Option<Vec<u8>> {
+        self.values
+            .get(key)
+            .filter(|value| value.expires > Instant::now())
+            .map(|value| value.data.clone())
+    }
+
+    /// Time the value of the key has left.
+    pub fn remaining(&self, key: &Address) -> Option<Duration> {
+        self.values
+            .get(key)
+            .and_then(|value| value.expires.checked_duration_since(Instant::now()))
+    }
+
+    /// Drops all values that expired at the time, returns their
+    /// number.
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let before = self.values.len();
+        let size = &mut self.size;
+        self.values.retain(|_, value| {
+            if value.expires > now {
+                return true;
+            }
+            *size -= value.data.len();
+            false
+        });
+        before - self.values.len()
+    }
+
+    /// Checks if the data fits under the key within the limits, the
+    /// value it replaces no longer counts.
+    fn fits(&self, key: &Address, length: usize) -> bool {
+        let previous = self.values.get(key).map(|value| value.data.len());
+        let entries = self.values.len() + usize::from(previous.is_none());
+        let size = self.size - previous.unwrap_or(0) + length;
+        entries <= self.entries && size <= self.limit
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+/// Serializes a value for Store and Value messages. Structure:
+/// 8 bytes: TTL in milliseconds,
+/// Rest: Data
+pub fn encode(data: &[u8], ttl: Duration) -> Vec<u8> {
+    let mut body = (ttl.as_millis() as u64).to_be_bytes().to_vec();
+    body.extend_from_slice(data);
+    body
+}
+
+pub fn decode(body: &[u8]) -> Result<(Vec<u8>, Duration), Error> {
+    if body.len() < 8 {
+        return Err(Error::Invalid(String::from("value is not valid")));
+    }
+    let mut ttl = [0; 8];
+    ttl.copy_from_slice(&body[..8]);
+    let ttl = Duration::from_millis(u64::from_be_bytes(ttl));
+    Ok((body[8..].to_vec(), ttl))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_bytes() {
+        let body = encode(&[1, 2, 3], Duration::from_secs(5));
+        let (data, ttl) = decode(&body).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+        assert_eq!(ttl, Duration::from_secs(5));
+        assert!(decode(&body[..7]).is_err());
+    }
+
+    #[test]
+    fn test_store_expire() {
+        let mut store = ValueStore::new(10, 1024);
+        let (key, owner) = (Address::random(), Address::random());
+        store
+            .put(
+                key.clone(),
+                owner.clone(),
+                vec![42],
+                Duration::from_secs(10),
+            )
+            .unwrap();
+        assert_eq!(store.get(&key), Some(vec![42]));
+        assert!(store.remaining(&key).unwrap() <= Duration::from_secs(10));
+        assert!(store.get(&Address::random()).is_none());
+
+        store
+            .put(
+                key.clone(),
+                owner,
+                vec![43],
+                Duration::from_secs(u64::MAX / 2),
+            )
+            .unwrap();
+        assert_eq!(store.get(&key), Some(vec![43]));
+        assert!(store.remaining(&key).unwrap() <= MAX_TTL);
+
+        assert_eq!(store.expire(Instant::now()), 0);
+        assert_eq!(store.expire(Instant::now() + MAX_TTL), 1);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_store_limits() {
+        let ttl = Duration::from_secs(10);
+        let owner = Address::random();
+        let mut store = ValueStore::new(2, 8);
+        let (first, second) = (Address::random(), Address::random());
+        store
+            .put(first.clone(), owner.clone(), vec![1; 4], ttl)
+            .unwrap();
+        store
+            .put(second.clone(), owner.clone(), vec![2; 4], ttl)
+            .unwrap();
+        assert!(matches!(
+            store.put(Address::random(), owner.clone(), vec![3], ttl),
+            Err(Error::Full)
+        ));
+        // Replacing a value only counts its new size.
+        assert!(matches!(
+            store.put(first.clone(), owner.clone(), vec![1; 5], ttl),
+            Err(Error::Full)
+        ));
+        store
+            .put(first.clone(), owner.clone(), vec![1; 2], ttl)
+            .unwrap();
+        assert!(matches!(
+            store.put(Address::random(), owner.clone(), vec![3], ttl),
+            Err(Error::Full)
+        ));
+
+        // Expired values make room for new ones.
+        store
+            .put(second, owner.clone(), vec![2; 4], Duration::ZERO)
+            .unwrap();
+        let third = Address::random();
+        store.put(third.clone(), owner, vec![3; 6], ttl).unwrap();
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get(&third), Some(vec![3; 6]));
+    }
+
+    #[test]
+    fn test_store_owner() {
+        let ttl = Duration::from_secs(10);
+        let mut store = ValueStore::new(10, 1024);
+        let (key, owner) = (Address::random(), Address::random());
+        store
+            .put(key.clone(), owner.clone(), vec![42], ttl)
+            .unwrap();
+        assert!(store
+            .put(key.clone(), Address::random(), vec![43], ttl)
+            .is_err());
+        assert_eq!(store.get(&key), Some(vec![42]));
+        store
+            .put(key.clone(), owner, vec![43], Duration::ZERO)
+            .unwrap();
+
+        // Once it expired any node can store a value under the key.
+        store
+            .put(key.clone(), Address::random(), vec![44], ttl)
+            .unwrap();
+        assert_eq!(store.get(&key), Some(vec![44]));
+    }
+}
@@ -5,23 +5,48 @@
 //! Channels and decides the targets based on type, origin, topic or
 //! target.
 
+use crate::config::{Config, ConfigDelta, Watcher};
+use crate::database::DataTopic;
+use crate::delivery::{Outbox, Receipt, Window};
 use crate::error::Error;
-use crate::message::Message;
+use crate::event::{self, EventReceiver};
+use crate::gossip::{self, Rumor, Rumors};
+use crate::inbox::{Inbox, Retention};
+use crate::message::{Body, Message};
+use crate::metrics;
 use crate::node::{Address, Center, Node};
-use crate::record::{Record, RecordBucket};
+use crate::offline::Queue;
+use crate::presence::{Presence, PRESENCE_FACTOR};
+use crate::record::{Record, RecordBucket, Role};
 use crate::router::Safe;
-use crate::signaling::{SignalingAction, Type};
-use crate::topic::{Command, TopicBucket};
-use crate::transaction::{Class, Transaction};
-use crate::util::Channel;
-use crate::InterfaceAction;
-use std::cell::RefCell;
-use std::thread;
-use std::time::SystemTime;
-
-/// Currently the system requires a dedicated thread for the listening
-/// server, which will autoamtically get started. The thread will hold
-/// a Switch object and send messages through the channel.
+use crate::signaling::{Lookup, SignalingAction, Type, LOOKUP_TIMEOUT};
+use crate::storage::{self, ValueStore};
+use crate::topic::{Command, Delivery, Dissemination, Gossip, Simple, TopicBucket};
+use crate::trace::{self, Decision, Reason};
+use crate::transaction::{Class, Signature, Transaction};
+use crate::util::{Channel, Inlet, Tag};
+use crate::{Hook, InterfaceAction};
+use rand::seq::SliceRandom;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::task::JoinHandle;
+
+/// Interval in which pending lookups get checked for expired
+/// requests, stored messages for due retries and queued messages if
+/// the node is back online.
+const LOOKUP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval in which the watched config file gets checked for
+/// changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Interval of the gossip rounds, in each one the IDs of recent
+/// messages get sent to a few subscribers.
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The Switch runs as a dedicated task on the tokio runtime, which
+/// will autoamtically get started. The task will hold a Switch object
+/// and send messages through the channel.
 pub struct Switch {
     /// Channel to the Listener, sends and receives full Transactions,
     /// which get serialized on demand when they are being sent.
@@ -37,11 +62,9 @@ pub struct Switch {
     /// by this Thread. It will have to be wrapped in a Arc Mutex to
     /// allow for the Updater Thread.
     table: Safe,
-    /// Holds a list of all currently active topics. The data is in a
-    /// RefCell in order to make interactions in the Thread closure
-    /// easier. A Topic means a non-"should be local" Address
-    /// subscribed to by the user.
-    topics: RefCell<TopicBucket>,
+    /// Holds a list of all currently active topics. A Topic means a
+    /// non-"should be local" Address subscribed to by the user.
+    topics: TopicBucket,
     /// Topics that aren't created / managed by the user, rather are
     /// part of the Kademlia system. Since the Addresses of Topics
     /// need to be fixed / known the location in the system can't be
@@ -51,11 +74,116 @@ pub struct Switch {
     records: RecordBucket,
     /// Another copy of the Center data used for generating messages.
     center: Center,
+    /// Whether Action messages get encrypted / decrypted, from the
+    /// system Config.
+    encryption: bool,
+    /// Whether messages of the user get signed by their author, from
+    /// the system Config.
+    signatures: bool,
+    /// Number of nodes returned by FindNode requests and lookups,
+    /// the bucket size from the Config.
+    bucket: usize,
+    /// Number of nodes closest to a Record that host it, from the
+    /// Config.
+    replicas: usize,
+    /// Records without activity for this long get garbage collected,
+    /// from the Config.
+    expiry: Option<Duration>,
+    /// Number of subscribers propagated messages are sent to directly,
+    /// from the Config.
+    branching: usize,
+    /// All ongoing iterative lookups and what happens with their
+    /// results once they are done.
+    lookups: Vec<(Lookup, Completion)>,
+    /// Handlers registered by the user for Custom Classes.
+    hooks: HashMap<u16, Hook>,
+    /// Interval in which subscribers announce their presence, from
+    /// the Config.
+    presence: Duration,
+    /// Messages of Topics in at-least-once mode that haven't been
+    /// acknowledged yet, both as publisher and as Record.
+    outbox: Outbox,
+    /// Sequence numbers received in at-least-once mode by Topic and
+    /// publisher.
+    windows: HashMap<(Address, Address), Window>,
+    /// Set if the Signaling thread isn't running, its Channel doesn't
+    /// get polled in that case.
+    standalone: bool,
+    /// Forwards changed settings to the Listener.
+    settings: Option<Channel<ConfigDelta>>,
+    /// Reloads the config file, if it is watched.
+    watcher: Option<Watcher>,
+    /// Records handed over to a closer node that hasn't accepted them
+    /// yet, with the node and the subscribers that were sent.
+    transfers: HashMap<Address, (Address, Vec<Address>)>,
+    /// Recent messages of the Topics in gossip mode.
+    rumors: HashMap<Address, Rumors>,
+    /// Values stored on this node.
+    values: ValueStore,
+    /// Values requested by the user from other nodes.
+    fetches: Vec<Fetch>,
+    /// Messages of the user sent while the routing table was empty,
+    /// only set if the outbox is enabled.
+    offline: Option<Queue>,
+    /// Messages of hosted Records kept for offline subscribers.
+    inbox: Inbox,
+    /// Reports new connections, subscribers with kept messages get
+    /// them once they are connected again.
+    events: EventReceiver,
+}
+
+/// Describes what happens with the result of a finished lookup.
+enum Completion {
+    /// The lookup was started by the user, the result gets sent back
+    /// through the Channel.
+    Interface(Channel<Vec<Node>>),
+    /// Bucket refresh, all found nodes have already been added to the
+    /// table along the way.
+    Refresh,
+    /// The Record with the Address gets sent to the other found
+    /// nodes among the given number of replicas.
+    Republish(Address, usize),
+    /// The value with the key gets stored on the given number of
+    /// closest nodes.
+    Store(Address, Vec<u8>, Duration, usize),
+    /// The value with the key gets requested from the given number of
+    /// closest nodes, the first one found gets sent back through the
+    /// Channel.
+    FindValue(Address, Channel<Option<Vec<u8>>>, usize),
+}
+
+/// A value requested by the user, waiting for the responses of the
+/// closest nodes to its key.
+struct Fetch {
+    key: Address,
+    channel: Channel<Option<Vec<u8>>>,
+    /// Nodes that were asked and haven't responded yet.
+    pending: Vec<Address>,
+    /// Time the requests were sent.
+    sent: Instant,
+}
+
+/// Any of the events the Switch task can be woken up by. Each
+/// variant matches one of the Channels, a None value means the other
+/// side of the Channel is no longer available.
+enum Event {
+    Interface(Option<InterfaceAction>),
+    Topic(Tag, Option<Command>),
+    Signaling(Option<SignalingAction>),
+    Listener(Option<Transaction>),
+    /// Pending lookups have to be checked for expired requests and
+    /// stored messages retried.
+    Tick,
+    /// The watched config file has to be checked for changes.
+    Watch,
+    /// Recent gossip messages have to be advertised.
+    Gossip,
 }
 
 impl Switch {
     /// Creates a new (Switch, Interface) combo, creating the Cache
-    /// and staritng the channel.
+    /// and staritng the channel. Encryption and the bucket size are
+    /// taken from the Config.
     pub fn new(
         listener: Channel<Transaction>,
         interface: Channel<InterfaceAction>,
@@ -63,15 +191,37 @@ impl Switch {
         center: Center,
         table: Safe,
         records: RecordBucket,
+        config: &Config,
     ) -> Result<Self, Error> {
         let switch = Switch {
             listener,
             interface,
             signaling,
-            table,
-            topics: RefCell::new(TopicBucket::new()),
+            table: table.clone(),
+            topics: TopicBucket::new(config.capacity),
             records,
             center,
+            encryption: config.encryption,
+            signatures: config.signatures,
+            bucket: config.bucket,
+            replicas: config.replicas,
+            expiry: config.expiry.map(Duration::from_secs),
+            branching: config.branching.max(1),
+            lookups: Vec::new(),
+            hooks: HashMap::new(),
+            presence: Duration::from_secs(config.presence.max(1)),
+            outbox: Outbox::new(),
+            windows: HashMap::new(),
+            standalone: false,
+            settings: None,
+            watcher: None,
+            transfers: HashMap::new(),
+            rumors: HashMap::new(),
+            values: ValueStore::new(config.values, config.values_size),
+            fetches: Vec::new(),
+            offline: None,
+            inbox: Inbox::new(config.inbox),
+            events: table.events().subscribe(),
         };
         Ok(switch)
     }
@@ -79,125 +229,268 @@ impl Switch {
     /// The switch is responsible for deciding where specific messages
     /// go based on their origin, target and type. It listens on
     /// almost all Channels in the system and can send messages to any
-    /// sink.
-    pub fn start(self) {
-        thread::spawn(move || {
-            // There is currently no method of restarting each of the
-            // threads, all of them simply consist of a while true
-            // loop listening on a number of sources.
-            loop {
+    /// sink. The Switch runs as a task on the tokio runtime of the
+    /// caller, so this has to be called from within a runtime.
+    pub fn start(self) -> JoinHandle<()> {
+        tokio::spawn(self.run())
+    }
+
+    /// Returns the Inlet for creating the Channels of new Topics,
+    /// the Switch receives the Commands of all of them at once.
+    pub fn inlet(&self) -> Inlet<Command> {
+        self.topics.inlet()
+    }
+
+    /// Used when the Signaling thread doesn't get started. Its
+    /// Channel is then no longer polled, so the Switch keeps running
+    /// once it gets closed.
+    pub fn without_signaling(mut self) -> Self {
+        self.standalone = true;
+        self
+    }
+
+    /// Changed settings concerning the Listener get forwarded through
+    /// the Channel.
+    pub fn with_settings(mut self, channel: Channel<ConfigDelta>) -> Self {
+        self.settings = Some(channel);
+        self
+    }
+
+    /// Messages of the user get queued while the routing table is
+    /// empty and sent once it isn't anymore.
+    pub fn with_offline(mut self, queue: Queue) -> Self {
+        self.offline = Some(queue);
+        self
+    }
+
+    /// Replaces the empty Outbox, for example with one restored from
+    /// a Storage.
+    pub fn with_outbox(mut self, outbox: Outbox) -> Self {
+        self.outbox = outbox;
+        self
+    }
+
+    /// Periodically reloads the config file of the Watcher and
+    /// applies the changes.
+    pub fn with_watcher(mut self, watcher: Watcher) -> Self {
+        self.watcher = Some(watcher);
+        self
+    }
+
+    /// Main loop of the Switch task. Instead of polling every Channel
+    /// in a loop it waits until any of them has a new event, which
+    /// means the task doesn't use any resources while the system is
+    /// idle. The loop ends once the Interface, Listener or Signaling
+    /// is no longer available.
+    async fn run(mut self) {
+        let mut interval = tokio::time::interval(LOOKUP_INTERVAL);
+        let mut watch = tokio::time::interval(WATCH_INTERVAL);
+        let mut gossip = tokio::time::interval(GOSSIP_INTERVAL);
+        let watching = self.watcher.is_some();
+        loop {
+            let queued = self.offline.as_ref().is_some_and(|queue| !queue.is_empty());
+            let event = tokio::select! {
+                action = self.interface.recv_async() => Event::Interface(action),
+                (topic, command) = self.topics.recv() => Event::Topic(topic, command),
+                action = self.signaling.recv_async(), if !self.standalone => Event::Signaling(action),
+                t = self.listener.recv_async() => Event::Listener(t),
+                _ = interval.tick(), if queued || !self.inbox.is_empty() || !self.lookups.is_empty() || !self.outbox.is_empty() || !self.fetches.is_empty() => Event::Tick,
+                _ = watch.tick(), if watching => Event::Watch,
+                _ = gossip.tick(), if !self.rumors.is_empty() => Event::Gossip,
+            };
+
+            match event {
                 // 1. Listen on Interface Channel.
-                if let Some(action) = self.interface.try_recv() {
-                    log::info!("received action from the user");
+                Event::Interface(Some(action)) => {
+                    trace::info!("received action from the user");
                     match action {
                         InterfaceAction::Shutdown => {
-                            log::trace!("received shutdown request, terminating switch.");
+                            trace::trace!("received shutdown request, terminating switch.");
                             break;
                         }
-                        InterfaceAction::Message(transaction) => {
-                            log::trace!("received complete message from the user");
-                            let _ = self.listener.send(transaction);
+                        InterfaceAction::Message(transaction)
+                            if transaction.class().is_user()
+                                && transaction.target() == self.center.public =>
+                        {
+                            trace::trace!("received message for the center from the user");
+                            self.loopback(transaction);
+                        }
+                        InterfaceAction::Message(mut transaction) => {
+                            trace::trace!("received complete message from the user");
+                            // Local Topics get their copy before any
+                            // node of the network.
+                            if transaction.class().is_user()
+                                && self.topics.find(&transaction.topic()).is_some()
+                            {
+                                self.loopback(transaction.clone());
+                            }
+                            if self.encryption && transaction.class().is_user() {
+                                transaction.message.encrypt(&self.center);
+                            }
+                            if self.signatures && transaction.class().is_user() {
+                                transaction.sign(&self.center);
+                            }
+                            // Earlier queued messages go out first.
+                            if self.queue(transaction.clone()) {
+                                continue;
+                            }
+                            // Waiting for the Listener could deadlock,
+                            // since it might be waiting for the Switch.
+                            let pending = self.table.tracer().pending(&transaction);
+                            if let Err(e) = self.listener.try_send(transaction) {
+                                trace::warn!("dropping outgoing message: {}", e);
+                                self.table.metrics().record(metrics::Event::Dropped);
+                                pending.decide(Decision::Dropped(Reason::Busy));
+                            }
                         }
-                        InterfaceAction::Subscribe(simple) => {
-                            log::trace!("received subscribe action from the user");
+                        InterfaceAction::Subscribe(simple, policy) => {
+                            trace::trace!("received subscribe action from the user");
                             let topic = simple.address.clone();
-                            self.topics.borrow_mut().add(simple);
+                            let (retention, role) = (simple.retention, simple.role);
+                            self.topics.add(simple);
                             let message = Message::new(
                                 Class::Subscribe,
                                 self.center.public.clone(),
                                 topic.clone(),
                                 topic.clone(),
-                                vec![],
+                                role.prefix(Retention::subscribe(
+                                    policy.as_ref(),
+                                    retention.as_ref(),
+                                )),
                             );
-                            let transaction = Transaction::new(message);
+                            let transaction = Transaction::new(message.clone());
                             if self.table.should_be_local(&topic) {
                                 Switch::handle_subscribe(
                                     transaction,
                                     &self.listener,
                                     &self.records,
-                                    &self.topics,
+                                    &mut self.topics,
                                     &self.center,
                                 );
+                                let subscribed = self
+                                    .records
+                                    .get(&topic)
+                                    .is_some_and(|record| record.is_member(&self.center.public));
+                                self.confirm(&topic, subscribed);
                             } else {
-                                let _ = self.listener.send(transaction);
+                                // Retried until the Record confirms it.
+                                let receipt =
+                                    Receipt::subscribe(topic.clone(), self.center.public.clone());
+                                self.outbox.insert(receipt, message);
+                                let _ = self.listener.try_send(transaction);
                             }
                         }
+                        InterfaceAction::Wildcard(wildcard) => {
+                            trace::trace!("received wildcard subscription from the user");
+                            self.topics.add_wildcard(wildcard);
+                        }
+                        InterfaceAction::Lookup(target, channel) => {
+                            trace::trace!("received lookup request from the user");
+                            self.lookup(target, Completion::Interface(channel));
+                        }
+                        InterfaceAction::Put(key, value, ttl) => {
+                            trace::trace!("received value from the user");
+                            let completion =
+                                Completion::Store(key.clone(), value, ttl, self.replicas);
+                            self.lookup(key, completion);
+                        }
+                        InterfaceAction::Get(key, channel) => {
+                            trace::trace!("received value request from the user");
+                            match self.values.get(&key) {
+                                Some(value) => {
+                                    let _ = channel.send(Some(value));
+                                }
+                                None => {
+                                    let completion =
+                                        Completion::FindValue(key.clone(), channel, self.replicas);
+                                    self.lookup(key, completion);
+                                }
+                            }
+                        }
+                        InterfaceAction::Register(number, hook) => {
+                            trace::trace!("received handler for custom class {}", number);
+                            self.hooks.insert(number, hook);
+                        }
+                        InterfaceAction::Reconfigure(delta) => {
+                            trace::trace!("received changed settings from the user");
+                            self.reconfigure(delta);
+                        }
                     }
                 }
 
-                let mut drop = false;
-                let mut dropper: Address = Address::random();
-
                 // 2. Listen on topics Chanel.
-                for simple in self.topics.borrow().topics.iter() {
-                    let topic = simple.address.clone();
-                    if let Some(command) = simple.channel.try_recv() {
-                        log::info!("received message from topic");
-                        match command {
-                            Command::Drop(addr) => {
-                                log::info!("topic went out of scope");
-                                // The addr is of the user to send the
-                                // unsubscribe to, not of the topic!
-                                drop = true;
-                                dropper = simple.address.clone();
-                                let topic = simple.address.clone();
-                                if self.table.should_be_local(&topic) {
-                                    let message = Message::new(
-                                        Class::Unsubscribe,
-                                        self.center.public.clone(),
-                                        topic.clone(),
-                                        topic,
-                                        Vec::new(),
-                                    );
-                                    let t = Transaction::new(message);
-
-                                    Switch::handle_unsubscribe(
-                                        t,
-                                        &self.listener,
-                                        &self.records,
-                                        &self.topics,
-                                        &self.center,
-                                    )
-                                } else {
-                                    let message = Message::new(
-                                        Class::Unsubscribe,
-                                        self.center.public.clone(),
-                                        addr,
-                                        topic,
-                                        Vec::new(),
-                                    );
-                                    let t = Transaction::new(message);
-                                    let _ = self.listener.send(t);
+                Event::Topic((_, topic), Some(command)) => {
+                    trace::info!("received message from topic");
+                    match command {
+                        Command::Drop => {
+                            trace::info!("topic went out of scope");
+                            self.unsubscribe(&topic);
+                        }
+                        Command::Broadcast(addr, body) => {
+                            trace::info!("received broadcast from user");
+                            self.broadcast(topic, addr, body, None);
+                        }
+                        Command::Gossip(body) => {
+                            trace::info!("received gossip broadcast from user");
+                            self.gossip(topic, body);
+                        }
+                        Command::Sequenced(addr, sequence, body) => {
+                            trace::info!("received ordered broadcast from user");
+                            match self.topics.find(&topic).map(|s| s.delivery) {
+                                Some(Delivery::AtLeastOnce) => {
+                                    self.publish(topic, addr, body, sequence)
                                 }
+                                _ => self.broadcast(topic, addr, body, Some(sequence)),
                             }
-                            Command::Broadcast(addr, body) => {
-                                log::info!("received broadcast from user");
-                                let message = Message::new(
-                                    Class::Action,
-                                    self.center.public.clone(),
-                                    addr,
-                                    topic,
-                                    body,
-                                );
-                                let t = Transaction::new(message);
-                                let _ = self.listener.send(t);
+                        }
+                        Command::Propagate(subtree, body) => {
+                            trace::info!("received propagation from user");
+                            let mut data = topic.as_bytes().to_vec();
+                            data.extend_from_slice(&body);
+                            // Encrypted bodies need a node as target, so
+                            // it is sent to the closest known node instead
+                            // of being routed towards the subtree.
+                            let target = match self.encryption {
+                                true => self.table.replicas(&subtree, 1).remove(0),
+                                false => subtree.clone(),
+                            };
+                            let message = Message::new(
+                                Class::Propagate,
+                                self.center.public.clone(),
+                                target,
+                                subtree.clone(),
+                                data,
+                            );
+                            let mut t = Transaction::new(message);
+                            if self.signatures {
+                                t.sign(&self.center);
+                            }
+                            if self.hosts(&subtree) {
+                                self.handle_propagate(t);
+                            } else {
+                                if self.encryption {
+                                    t.message.encrypt(&self.center);
+                                }
+                                let _ = self.listener.try_send(t);
                             }
-                            _ => {}
                         }
-                    } else {
+                        Command::Announce(metadata) => {
+                            trace::info!("received presence metadata from user");
+                            if let Some(simple) = self.topics.find_mut(&topic) {
+                                simple.metadata = Some(metadata);
+                            }
+                            self.announce(&topic);
+                        }
+                        _ => {}
                     }
                 }
 
-                if drop {
-                    self.topics.borrow_mut().remove(&dropper);
-                }
-
                 // 3. Listen on Siganling Channel.
-                if let Some(action) = self.signaling.try_recv() {
-                    log::info!("received message from signaling thread");
+                Event::Signaling(Some(action)) => {
+                    trace::info!("received message from signaling thread");
                     match action.action {
                         Type::Ping => {
-                            log::info!("received signaling ping request");
+                            trace::info!("received signaling ping request");
                             let message = Message::new(
                                 Class::Ping,
                                 self.center.public.clone(),
@@ -206,10 +499,10 @@ impl Switch {
                                 Vec::new(),
                             );
                             let t = Transaction::build(action.uuid, SystemTime::now(), message);
-                            let _ = self.listener.send(t);
+                            let _ = self.listener.try_send(t);
                         }
                         Type::Lookup => {
-                            log::info!("received signaling lookup request");
+                            trace::info!("received signaling lookup request");
                             let message = Message::new(
                                 Class::Lookup,
                                 self.center.public.clone(),
@@ -218,62 +511,283 @@ impl Switch {
                                 Vec::new(),
                             );
                             let t = Transaction::build(action.uuid, SystemTime::now(), message);
-                            let _ = self.listener.send(t);
+                            let _ = self.listener.try_send(t);
+                        }
+                        Type::Refresh => {
+                            trace::info!("received signaling refresh request");
+                            self.lookup(action.target, Completion::Refresh);
+                        }
+                        Type::Republish => {
+                            trace::info!("received signaling republish request");
+                            for address in self.records.addresses() {
+                                let completion =
+                                    Completion::Republish(address.clone(), self.replicas);
+                                self.lookup(address, completion);
+                            }
+                        }
+                        Type::Transfer => {
+                            trace::info!("received signaling transfer request");
+                            self.maintain();
+                        }
+                        Type::Presence => {
+                            trace::info!("received signaling presence request");
+                            for topic in self.topics.addresses() {
+                                self.announce(&topic);
+                            }
+                            let ttl = self.presence * PRESENCE_FACTOR;
+                            for (topic, expired) in self.records.expire(ttl) {
+                                Switch::depart(
+                                    &topic,
+                                    expired,
+                                    &self.listener,
+                                    &self.records,
+                                    &self.topics,
+                                    &self.center,
+                                );
+                            }
                         }
                         _ => {}
                     }
                 }
 
                 // 4. Listen on Handler Channel.
-                if let Some(t) = self.listener.try_recv() {
-                    log::info!("received message from listener");
+                Event::Listener(Some(t)) => {
+                    let _entered = trace::transaction(&t).entered();
+                    trace::info!("received message from listener");
                     let target = t.target();
                     if target == self.center.public {
-                        log::info!("handling incoming message locally");
+                        let _route = trace::route("local", &target).entered();
+                        trace::info!("handling incoming message locally");
                         // Handle: Ping, Pong, Lookup, Details, Action, Subscriber, Unsubscriber
                         // Error: Subscriber, Unsubscribe
                         match t.class() {
                             Class::Ping => {
-                                Switch::handle_ping(t, &self.listener, &self.center);
+                                Switch::handle_ping(t, &self.listener, &self.center, &self.table);
                             }
                             Class::Pong => {
+                                self.redeliver(&t.source());
                                 Switch::handle_pong(t, &self.signaling);
                             }
                             Class::Lookup => {
-                                Switch::handle_lookup(t, &self.listener, &self.center);
+                                Switch::handle_lookup(t, &self.listener, &self.center, &self.table);
                             }
                             Class::Details => {
                                 Switch::handle_details(t, &self.signaling, &self.table);
                             }
+                            Class::FindNode => {
+                                Switch::handle_find_node(
+                                    t,
+                                    &self.listener,
+                                    &self.table,
+                                    &self.center,
+                                    self.bucket,
+                                );
+                            }
+                            Class::FoundNodes => {
+                                Switch::handle_found_nodes(
+                                    t,
+                                    &mut self.lookups,
+                                    &self.table,
+                                    &self.center,
+                                );
+                                Switch::drive_lookups(
+                                    &mut self.lookups,
+                                    &self.listener,
+                                    &self.records,
+                                    &mut self.values,
+                                    &mut self.fetches,
+                                    &self.center,
+                                );
+                            }
+                            Class::Record if Switch::is_removal(&t) => {
+                                self.handle_removal(t);
+                            }
+                            Class::Record if self.is_replica(&t.source(), &t.topic()) => {
+                                Switch::handle_record(t, &self.records);
+                            }
+                            Class::Record => {
+                                trace::warn!("rejecting record from non replica: {:?}", t);
+                            }
+                            Class::RecordTransfer => {
+                                self.handle_transfer(t);
+                            }
+                            Class::RecordAck => {
+                                self.handle_record_ack(t);
+                            }
+                            Class::Digest => {
+                                self.handle_digest(t);
+                            }
+                            Class::Reconcile => {
+                                self.handle_reconcile(t);
+                            }
+                            Class::Action if !Switch::may_publish(&t, &self.records) => {
+                                trace::warn!("rejecting unauthorized action: {:?}", t);
+                                let decision = Decision::Dropped(Reason::Unauthorized);
+                                self.table.tracer().transaction(&t, decision);
+                                Switch::deny(&t, &self.listener, &self.center);
+                            }
                             Class::Action => {
-                                Switch::handle_action(t, &self.topics, &self.interface);
+                                let pending = self.table.tracer().pending(&t);
+                                match Switch::handle_action(
+                                    t,
+                                    &self.topics,
+                                    &self.interface,
+                                    &self.center,
+                                    self.encryption,
+                                ) {
+                                    Ok(()) => {
+                                        self.table.metrics().record(metrics::Event::Delivered);
+                                        pending.decide(Decision::Delivered);
+                                    }
+                                    Err(e) => {
+                                        trace::warn!("dropping incoming action: {}", e);
+                                        if let Error::Busy(_) = e {
+                                            self.table.metrics().record(metrics::Event::Dropped);
+                                        }
+                                        pending.decide(Decision::Dropped(Switch::reason(&e)));
+                                    }
+                                }
+                            }
+                            Class::Custom(_) => {
+                                let pending = self.table.tracer().pending(&t);
+                                match Switch::handle_custom(
+                                    t,
+                                    &self.hooks,
+                                    &self.topics,
+                                    &self.interface,
+                                    &self.center,
+                                    self.encryption,
+                                ) {
+                                    Ok(()) => {
+                                        self.table.metrics().record(metrics::Event::Delivered);
+                                        pending.decide(Decision::Delivered);
+                                    }
+                                    Err(e) => {
+                                        trace::warn!("dropping incoming custom message: {}", e);
+                                        if let Error::Busy(_) = e {
+                                            self.table.metrics().record(metrics::Event::Dropped);
+                                        }
+                                        pending.decide(Decision::Dropped(Switch::reason(&e)));
+                                    }
+                                }
                             }
                             Class::Subscriber => {
-                                Switch::handle_subscriber(t, &self.topics, &self.center);
+                                if self.topics.find(&t.topic()).is_some() {
+                                    self.table
+                                        .events()
+                                        .emit(event::Event::Subscribed(t.topic()));
+                                }
+                                Switch::handle_subscriber(t, &mut self.topics, &self.center);
                             }
                             Class::Unsubscriber => {
-                                Switch::handle_unsubscriber(t, &self.topics);
+                                Switch::handle_unsubscriber(t, &mut self.topics);
+                            }
+                            Class::Presence | Class::Joined | Class::Left
+                                if self.is_replica(&t.source(), &t.topic()) =>
+                            {
+                                Switch::handle_presence(t, &self.topics, &self.center);
+                            }
+                            Class::Presence | Class::Joined | Class::Left => {
+                                trace::warn!("rejecting presence from non replica: {:?}", t);
+                            }
+                            Class::Deliver => {
+                                self.handle_deliver(t);
+                            }
+                            Class::Subtree => {
+                                if let Some(t) = self.open(t) {
+                                    Switch::handle_subtree(t, &self.topics, &self.center);
+                                }
+                            }
+                            Class::Delegate => {
+                                if let Some(t) = self.open(t) {
+                                    self.handle_delegate(t);
+                                }
+                            }
+                            Class::Gossip => {
+                                self.handle_gossip(t);
+                            }
+                            Class::Store => {
+                                self.handle_store(t);
+                            }
+                            Class::FindValue => {
+                                self.handle_find_value(t);
+                            }
+                            Class::Value => {
+                                self.handle_value(t);
+                            }
+                            Class::GossipDigest => {
+                                self.handle_gossip_digest(t);
+                            }
+                            Class::GossipRequest => {
+                                self.handle_gossip_request(t);
+                            }
+                            Class::Ack => {
+                                self.handle_ack(t);
+                            }
+                            Class::SubscribeAck => {
+                                self.handle_subscribe_ack(t);
+                            }
+                            Class::Denied => {
+                                trace::warn!("access to topic denied: {:?}", t.topic());
+                                let topic = t.topic();
+                                let receipt =
+                                    Receipt::subscribe(topic.clone(), self.center.public.clone());
+                                if self.outbox.acknowledge(&topic, &receipt) {
+                                    self.confirm(&topic, false);
+                                }
+                                self.table.events().emit(event::Event::Denied(topic));
+                            }
+                            Class::Propagate if self.hosts(&t.topic()) => {
+                                if let Some(t) = self.open(t) {
+                                    self.handle_propagate(t);
+                                }
                             }
                             _ => {
-                                log::warn!("received message to invalid target: {:?}", t);
+                                trace::warn!("received message to invalid target: {:?}", t);
+                                let decision = Decision::Dropped(Reason::Class);
+                                self.table.tracer().transaction(&t, decision);
                             }
                         }
                     } else {
-                        log::info!("target is not local but this node might be responsible");
+                        let _route = trace::route("remote", &target).entered();
+                        trace::info!("target is not local but this node might be responsible");
                         // Forward: Ping, Pong, Details, Action, Subscriber, Unsubscriber,
-                        // Maybe Handle: Subscribe, Unsubscribe, Lookup
+                        // Maybe Handle: Subscribe, Unsubscribe, Announce, Publish, Ack, Propagate
                         match t.class() {
-                            Class::Subscribe => {
+                            Class::Subscribe if self.table.should_be_local(&target) => {
+                                let source = t.source();
+                                let (_, body) = Role::split(t.message.body.as_slice());
+                                let (retention, _) = Retention::split(body);
                                 Switch::handle_subscribe(
                                     t,
                                     &self.listener,
                                     &self.records,
-                                    &self.topics,
+                                    &mut self.topics,
                                     &self.center,
                                 );
+                                let subscribed = self
+                                    .records
+                                    .get(&target)
+                                    .is_some_and(|record| record.contains(&source));
+                                if subscribed && source != self.center.public {
+                                    self.inbox.configure(&target, &source, retention);
+                                }
                             }
-                            Class::Unsubscribe => {
+                            Class::Unsubscribe if self.table.should_be_local(&target) => {
+                                self.inbox.configure(&target, &t.source(), None);
+                                self.confirm_unsubscribe(&t);
                                 Switch::handle_unsubscribe(
+                                    t,
+                                    &self.listener,
+                                    &self.records,
+                                    &mut self.topics,
+                                    &self.center,
+                                );
+                                self.release(&target);
+                            }
+                            Class::Announce if self.table.should_be_local(&target) => {
+                                self.redeliver(&t.source());
+                                Switch::handle_announce(
                                     t,
                                     &self.listener,
                                     &self.records,
@@ -281,193 +795,2023 @@ impl Switch {
                                     &self.center,
                                 );
                             }
-                            // TODO: Handle lookup!
+                            Class::Publish | Class::Ack if self.hosts(&t.target()) => {
+                                self.dispatch(t);
+                            }
+                            Class::Propagate if self.hosts(&t.target()) => {
+                                self.handle_propagate(t);
+                            }
                             _ => {
-                                let _ = self.listener.send(t);
+                                self.forward(t);
                             }
                         }
                     }
                 }
+
+                Event::Tick => {
+                    Switch::drive_lookups(
+                        &mut self.lookups,
+                        &self.listener,
+                        &self.records,
+                        &mut self.values,
+                        &mut self.fetches,
+                        &self.center,
+                    );
+                    self.retry();
+                    self.expire_fetches();
+                    self.flush();
+                    self.reconnected();
+                }
+
+                Event::Gossip => {
+                    self.advertise();
+                }
+
+                Event::Watch => {
+                    if let Some(delta) = self.watcher.as_mut().and_then(|w| w.poll()) {
+                        trace::info!("config file changed, applying new settings");
+                        self.reconfigure(delta);
+                    }
+                }
+
+                // The user dropped the Topic, there is nobody left to
+                // deliver messages to. Usually its Drop already arrived,
+                // but it can get lost should the Channel be full. The
+                // notice can also belong to an earlier Topic with the
+                // same Address, which must not remove the current one.
+                Event::Topic((peer, topic), None) => {
+                    trace::info!("topic channel closed, removing it from the switch");
+                    let closed = self
+                        .topics
+                        .find(&topic)
+                        .is_some_and(|s| s.channel.is_closed());
+                    if peer == self.center.public && closed {
+                        self.unsubscribe(&topic);
+                    }
+                }
+
+                // Without the Interface, Listener or Signaling the
+                // Switch can't do anything meaningful anymore.
+                Event::Interface(None) | Event::Signaling(None) | Event::Listener(None) => {
+                    trace::trace!("channel closed, terminating switch.");
+                    break;
+                }
             }
-        });
+        }
     }
 
-    fn handle_ping(t: Transaction, channel: &Channel<Transaction>, center: &Center) {
-        log::info!("incoming ping message");
-        let node = Node::new(center.public.clone(), Some(center.link.clone()));
-        let message = Message::new(
-            Class::Details,
-            center.public.clone(),
-            t.source(),
-            Address::default(),
-            node.as_bytes(),
-        );
-        let transaction = Transaction::new(message);
-        let _ = channel.send(transaction);
+    /// Passes a Transaction for another node on towards its target.
+    /// It only gets forwarded if a known node is strictly closer to
+    /// the target than this one, so every hop gets closer and the
+    /// Transaction can't circle. Without any hops left it gets
+    /// dropped as well.
+    fn forward(&self, mut t: Transaction) {
+        let target = t.target();
+        let distance = &self.center.public ^ &target;
+        let closer = self
+            .table
+            .get_copy(&target, self.bucket)
+            .iter()
+            .any(|node| (&node.address ^ &target) < distance);
+        let tracer = self.table.tracer();
+        if !closer {
+            trace::warn!(
+                "no node is closer to the target, dropping message: {:?}",
+                t.uuid
+            );
+            self.table.metrics().record(metrics::Event::Dropped);
+            tracer.transaction(&t, Decision::Dropped(Reason::NoRoute));
+            return;
+        }
+        if !t.hop() {
+            trace::warn!("dropping expired message: {:?}", t.uuid);
+            self.table.metrics().record(metrics::Event::Dropped);
+            tracer.transaction(&t, Decision::Dropped(Reason::Expired));
+            return;
+        }
+        let pending = tracer.pending(&t);
+        match self.listener.try_send(t) {
+            Ok(()) => pending.decide(Decision::Forwarded),
+            Err(e) => {
+                trace::warn!("unable to forward message: {}", e);
+                self.table.metrics().record(metrics::Event::Dropped);
+                pending.decide(Decision::Dropped(Reason::Busy));
+            }
+        }
     }
 
-    fn handle_pong(t: Transaction, channel: &Channel<SignalingAction>) {
-        log::info!("incoming pong message");
-        let _ = channel.send(SignalingAction::pong(t.source(), t.uuid));
+    /// Queues the message of the user if the node is offline or
+    /// earlier messages are still waiting, returns false if it can be
+    /// sent right away. Messages exceeding the limits of the outbox
+    /// get dropped.
+    fn queue(&self, t: Transaction) -> bool {
+        let queue = match &self.offline {
+            Some(queue) => queue,
+            None => return false,
+        };
+        if self.flush() && !self.table.is_empty() {
+            return false;
+        }
+        let tracer = self.table.tracer();
+        let pending = tracer.pending(&t);
+        match queue.push(t) {
+            Ok(()) => trace::info!("node is offline, queueing message"),
+            Err(e) => {
+                trace::warn!("dropping outgoing message: {}", e);
+                self.table.metrics().record(metrics::Event::Dropped);
+                pending.decide(Decision::Dropped(Reason::Undeliverable));
+            }
+        }
+        true
     }
 
-    fn handle_lookup(t: Transaction, listener: &Channel<Transaction>, center: &Center) {
-        log::info!("incoming lookup message");
-        let node = Node::new(center.public.clone(), Some(center.link.clone()));
-        let message = Message::new(
-            Class::Details,
-            center.public.clone(),
-            t.source(),
-            Address::default(),
-            node.as_bytes(),
-        );
-        let transaction = Transaction::new(message);
-        let _ = listener.send(transaction);
+    /// Passes all queued messages to the Listener once the routing
+    /// table isn't empty, returns true if none are left.
+    fn flush(&self) -> bool {
+        let queue = match &self.offline {
+            Some(queue) if !queue.is_empty() => queue,
+            _ => return true,
+        };
+        if self.table.is_empty() {
+            return false;
+        }
+        let sent = queue.flush(|t| self.listener.try_send(t.clone()).is_ok());
+        if sent > 0 {
+            trace::info!("sent {} queued messages", sent);
+        }
+        queue.is_empty()
     }
 
-    fn handle_details(t: Transaction, channel: &Channel<SignalingAction>, table: &Safe) {
-        log::info!("incoming details message");
-        if let Ok(node) = Node::from_bytes(t.message.body.as_bytes()) {
-            table.add(node);
-            let action = SignalingAction::pong(t.source(), t.uuid);
-            let _ = channel.send(action);
-        } else {
-            log::warn!("received invalid node details: {:?}", t);
+    /// Reason for a message of the user that could not be delivered.
+    fn reason(e: &Error) -> Reason {
+        match e {
+            Error::Busy(_) => Reason::Busy,
+            _ => Reason::Undeliverable,
         }
     }
 
-    fn handle_action(
-        t: Transaction,
-        topics: &RefCell<TopicBucket>,
-        interface: &Channel<InterfaceAction>,
-    ) {
-        log::info!("incoming details message");
-        if let Some(simple) = topics.borrow().find(&t.topic()) {
-            let command = Command::Message(t);
-            let _ = simple.channel.send(command);
-        } else {
-            let action = InterfaceAction::Message(t);
-            let _ = interface.send(action);
+    /// Starts a new lookup for the target, starting from the closest
+    /// nodes in the table. The bucket of the target counts as active.
+    fn lookup(&mut self, target: Address, completion: Completion) {
+        self.table.touch(&target);
+        let nodes = self.table.get_copy(&target, self.bucket);
+        let lookup = Lookup::new(target, self.center.public.clone(), nodes, self.bucket);
+        self.lookups.push((lookup, completion));
+        Switch::drive_lookups(
+            &mut self.lookups,
+            &self.listener,
+            &self.records,
+            &mut self.values,
+            &mut self.fetches,
+            &self.center,
+        );
+    }
+
+    /// Sends a message of the user on the Topic to one of its
+    /// subscribers.
+    fn broadcast(&self, topic: Address, target: Address, body: Vec<u8>, sequence: Option<u64>) {
+        let t = self.action(topic, target, body, sequence);
+        let _ = self.listener.try_send(t);
+    }
+
+    /// Creates an Action of the user, its body gets encrypted if
+    /// encryption is enabled.
+    fn action(
+        &self,
+        topic: Address,
+        target: Address,
+        body: Vec<u8>,
+        sequence: Option<u64>,
+    ) -> Transaction {
+        let mut message = Message::new(
+            Class::Action,
+            self.center.public.clone(),
+            target,
+            topic,
+            body,
+        );
+        message.sequence = sequence;
+        let mut t = Transaction::new(message);
+        if self.encryption {
+            t.message.encrypt(&self.center);
         }
+        if self.signatures {
+            t.sign(&self.center);
+        }
+        t
     }
 
-    fn handle_subscriber(t: Transaction, topics: &RefCell<TopicBucket>, center: &Center) {
-        log::info!("incoming subscriber message");
-        if let Some(simple) = topics.borrow().find(&t.topic()) {
-            let addrs = Address::from_bulk(t.message.body.as_bytes());
-            for sub in addrs {
-                if sub != center.public {
-                    let action = Command::Subscriber(sub);
-                    let _ = simple.channel.send(action);
+    /// Delivers a message of the user to this node right away instead
+    /// of passing it through the Listener. It never leaves the node,
+    /// so it is neither encrypted nor signed.
+    fn loopback(&mut self, mut t: Transaction) {
+        trace::info!("delivering message locally");
+        t.set_loopback(true);
+        let pending = self.table.tracer().pending(&t);
+        let result = match t.class() {
+            Class::Custom(_) => Switch::handle_custom(
+                t,
+                &self.hooks,
+                &self.topics,
+                &self.interface,
+                &self.center,
+                false,
+            ),
+            _ => Switch::handle_action(t, &self.topics, &self.interface, &self.center, false),
+        };
+        match result {
+            Ok(()) => {
+                self.table.metrics().record(metrics::Event::Delivered);
+                pending.decide(Decision::Delivered);
+            }
+            Err(e) => {
+                trace::warn!("dropping local message: {}", e);
+                if let Error::Busy(_) = e {
+                    self.table.metrics().record(metrics::Event::Dropped);
                 }
+                pending.decide(Decision::Dropped(Switch::reason(&e)));
             }
         }
     }
 
-    fn handle_unsubscriber(t: Transaction, topics: &RefCell<TopicBucket>) {
-        log::info!("incoming unsubscriber message");
-        if let Some(simple) = topics.borrow().find(&t.topic()) {
-            let action = Command::Subscriber(t.source());
-            let _ = simple.channel.send(action);
+    /// Sends a message of a Topic in at-least-once mode to the
+    /// Record, it gets retried until the Record acknowledged it.
+    fn publish(&mut self, topic: Address, target: Address, body: Vec<u8>, sequence: u64) {
+        let action = self.action(topic.clone(), target.clone(), body, Some(sequence));
+        let message = Message::new(
+            Class::Publish,
+            self.center.public.clone(),
+            topic.clone(),
+            topic.clone(),
+            action.as_bytes(),
+        );
+        let receipt = Receipt::new(topic, self.center.public.clone(), target, sequence);
+        self.outbox.insert(receipt, message.clone());
+        self.dispatch(Transaction::new(message));
+    }
+
+    /// Informs the Topic whether the node hosting its Record accepted
+    /// the subscription. Rejections are reported as Denied Event by
+    /// the Denied message itself.
+    fn confirm(&self, topic: &Address, accepted: bool) {
+        let command = match accepted {
+            true => Command::Confirmed,
+            false => Command::Failed(Error::Invalid(String::from(
+                "subscription was denied by the policy of the topic",
+            ))),
+        };
+        if let Some(simple) = self.topics.find(topic) {
+            let _ = simple.channel.try_send(command);
+            if accepted {
+                self.table
+                    .events()
+                    .emit(event::Event::Subscribed(topic.clone()));
+            }
         }
     }
 
-    fn handle_subscribe(
-        t: Transaction,
-        listener: &Channel<Transaction>,
-        records: &RecordBucket,
-        topics: &RefCell<TopicBucket>,
-        center: &Center,
-    ) {
-        log::info!("incoming subscribe message for local topic");
+    /// No node confirmed the subscription even after retrying it.
+    fn fail(&self, topic: &Address) {
+        if let Some(simple) = self.topics.find(topic) {
+            let e = Error::Timeout(String::from("subscription was never confirmed"));
+            let _ = simple.channel.try_send(Command::Failed(e));
+        }
+        self.table
+            .events()
+            .emit(event::Event::SubscriptionFailed(topic.clone()));
+    }
+
+    /// The node hosting the Record confirmed the subscription, the
+    /// body contains its subscribers. They get passed to the Topic
+    /// before the confirmation, so they are known once it is ready.
+    fn handle_subscribe_ack(&mut self, t: Transaction) {
+        trace::info!("incoming subscribe ack message");
         let topic = t.topic();
-        match records.get(&topic) {
-            Some(record) => {
-                records.subscribe(&record.address, t.source());
-                let record = records.get(&topic).unwrap();
-                let subscribers = record.subscribers.clone();
-                let mut subscribers_vec = Vec::new();
-                subscribers
-                    .iter()
-                    .for_each(|x| subscribers_vec.append(&mut x.as_bytes().to_vec()));
-                for subscriber in record.subscribers {
-                    if subscriber == center.public {
-                        if let Some(simple) = topics.borrow().find(&topic) {
-                            for sub in &subscribers {
-                                let _ = simple.channel.send(Command::Subscriber(sub.clone()));
-                            }
-                        }
-                    } else {
-                        let message = Message::new(
-                            Class::Subscriber,
-                            t.topic(),
-                            subscriber,
-                            t.topic(),
-                            subscribers_vec.clone(),
-                        );
-                        let transaction = Transaction::new(message);
-                        let _ = listener.send(transaction);
-                    }
-                }
+        let receipt = Receipt::subscribe(topic.clone(), self.center.public.clone());
+        self.outbox.acknowledge(&topic, &receipt);
+        Switch::handle_subscriber(t, &mut self.topics, &self.center);
+        self.confirm(&topic, true);
+    }
+
+    /// Removes the Simple of the Topic and sends a single Unsubscribe
+    /// to the node hosting its Record, which informs the other
+    /// subscribers. It gets retried until the Record acknowledged it.
+    /// Topics that were already removed are ignored, so both the Drop
+    /// and the closed Channel can trigger it.
+    fn unsubscribe(&mut self, topic: &Address) {
+        if self.topics.find(topic).is_none() {
+            return;
+        }
+        self.topics.remove(topic);
+        let pending = Receipt::subscribe(topic.clone(), self.center.public.clone());
+        self.outbox.acknowledge(topic, &pending);
+        let receipt = Receipt::unsubscribe(topic.clone(), self.center.public.clone());
+        let message = Message::new(
+            Class::Unsubscribe,
+            self.center.public.clone(),
+            topic.clone(),
+            topic.clone(),
+            receipt.as_bytes(),
+        );
+        let t = Transaction::new(message.clone());
+        if self.table.should_be_local(topic) {
+            Switch::handle_unsubscribe(
+                t,
+                &self.listener,
+                &self.records,
+                &mut self.topics,
+                &self.center,
+            );
+            self.release(topic);
+        } else {
+            self.outbox.insert(receipt, message);
+            let _ = self.listener.try_send(t);
+        }
+    }
+
+    /// Acknowledges an Unsubscribe, so its source stops retrying it.
+    /// Older nodes send it without a Receipt and don't expect an Ack.
+    fn confirm_unsubscribe(&self, t: &Transaction) {
+        let receipt = match Receipt::from_bytes(t.message.body.as_slice()) {
+            Ok(receipt) if receipt == Receipt::unsubscribe(t.topic(), t.source()) => receipt,
+            _ => return,
+        };
+        let ack = Message::new(
+            Class::Ack,
+            self.center.public.clone(),
+            t.source(),
+            t.topic(),
+            receipt.as_bytes(),
+        );
+        let _ = self.listener.try_send(Transaction::new(ack));
+    }
+
+    /// Checks if this node is responsible for the Record of the Topic.
+    fn hosts(&self, topic: &Address) -> bool {
+        self.records.contains(topic) && self.table.should_be_local(topic)
+    }
+
+    /// Checks if the node is one of the replicas of the Record, as far
+    /// as the local table knows. Nodes that aren't in it yet count as
+    /// well, as long as not enough known ones are closer.
+    fn is_replica(&self, node: &Address, topic: &Address) -> bool {
+        let distance = node ^ topic;
+        let closer = self
+            .table
+            .replicas(topic, self.replicas)
+            .iter()
+            .filter(|replica| (*replica ^ topic) < distance)
+            .count();
+        closer < self.replicas
+    }
+
+    /// Checks if the node would be one of the replicas of the Record
+    /// without this node. Nodes handing a Record over are no longer
+    /// replicas because of it, but they have been before.
+    fn was_replica(&self, node: &Address, topic: &Address) -> bool {
+        let distance = node ^ topic;
+        let closer = self
+            .table
+            .replicas(topic, self.replicas + 1)
+            .iter()
+            .filter(|replica| **replica != self.center.public)
+            .filter(|replica| (*replica ^ topic) < distance)
+            .count();
+        closer < self.replicas
+    }
+
+    /// Sends a Publish, Deliver or Ack message, should this node be
+    /// the target it gets handled directly.
+    fn dispatch(&mut self, t: Transaction) {
+        match t.class() {
+            Class::Publish if self.hosts(&t.target()) => self.handle_publish(t),
+            Class::Deliver if t.target() == self.center.public => self.handle_deliver(t),
+            Class::Ack if t.target() == self.center.public || self.hosts(&t.target()) => {
+                self.handle_ack(t)
             }
-            None => {
-                let mut record = Record::new(topic.clone());
-                record.subscribe(t.source());
-                records.add(record);
-                let message =
-                    Message::new(Class::Subscriber, t.topic(), t.source(), t.topic(), vec![]);
-                let transaction = Transaction::new(message);
-                // TODO: Handle error
-                let _ = listener.send(transaction);
+            _ => {
+                let _ = self.listener.try_send(t);
             }
         }
     }
 
-    fn handle_unsubscribe(
-        t: Transaction,
+    /// Applies the settings used by the Switch itself, the entire
+    /// delta gets forwarded to the Listener, which ignores the ones
+    /// it doesn't use.
+    fn reconfigure(&mut self, delta: ConfigDelta) {
+        if let Some(bucket) = delta.bucket {
+            self.bucket = bucket;
+        }
+        if let Some(presence) = delta.presence {
+            self.presence = Duration::from_secs(presence.max(1));
+        }
+        if let Some(settings) = &self.settings {
+            if settings.try_send(delta).is_err() {
+                trace::warn!("unable to forward changed settings to the listener");
+            }
+        }
+    }
+
+    /// Sends all stored messages that are due again. Messages that
+    /// were retried too often get dropped, unless their subscriber
+    /// asked the Record to keep them.
+    fn retry(&mut self) {
+        let now = Instant::now();
+        let (messages, dropped) = self.outbox.due(now);
+        for message in messages {
+            self.dispatch(Transaction::new(message));
+        }
+        let mut lost = 0;
+        for (receipt, message) in dropped {
+            if message.class == Class::Subscribe {
+                trace::warn!("subscription to {} was never confirmed", message.topic);
+                self.fail(&message.topic);
+                continue;
+            }
+            if message.class != Class::Deliver || !self.inbox.push(receipt, message, now) {
+                lost += 1;
+            }
+        }
+        lost += self.inbox.expire(now);
+        if lost > 0 {
+            trace::warn!("dropping {} unacknowledged messages", lost);
+        }
+        for _ in 0..lost {
+            self.table.metrics().record(metrics::Event::Dropped);
+        }
+    }
+
+    /// Delivers the kept messages of all subscribers that opened a
+    /// connection since the last check.
+    fn reconnected(&mut self) {
+        while let Some(event) = self.events.try_recv() {
+            if let event::Event::Connected(address) = event {
+                self.redeliver(&address);
+            }
+        }
+    }
+
+    /// The subscriber can be reached again, the messages kept for it
+    /// are delivered like new ones.
+    fn redeliver(&mut self, subscriber: &Address) {
+        let kept = self.inbox.take(subscriber);
+        if !kept.is_empty() {
+            trace::info!("delivering {} kept messages", kept.len());
+        }
+        for (receipt, message) in kept {
+            if self.outbox.insert(receipt, message.clone()) {
+                self.dispatch(Transaction::new(message));
+            }
+        }
+    }
+
+    /// The Record accepts a message of a publisher, acknowledges it
+    /// and stores it until the subscriber acknowledged it as well.
+    /// Publishers that aren't allowed to publish get a Denied
+    /// response.
+    fn handle_publish(&mut self, t: Transaction) {
+        trace::info!("incoming publish message for local topic");
+        let topic = t.topic();
+        match self.records.get(&topic) {
+            Some(record) if record.may_publish(&t.source()) => self.records.touch(&topic),
+            Some(_) => {
+                trace::warn!("rejecting unauthorized publish: {:?}", t);
+                Switch::deny(&t, &self.listener, &self.center);
+                return;
+            }
+            None => return,
+        }
+        let body = t.message.body.as_bytes();
+        let (action, sequence) = match Transaction::from_bytes(&body) {
+            Ok(action)
+                if action.class() == Class::Action
+                    && action.source() == t.source()
+                    && action.topic() == topic =>
+            {
+                match action.message.sequence {
+                    Some(sequence) => (action, sequence),
+                    None => return,
+                }
+            }
+            _ => {
+                trace::warn!("received invalid publish message: {:?}", t);
+                return;
+            }
+        };
+        let receipt = Receipt::new(topic.clone(), t.source(), action.target(), sequence);
+        let ack = Message::new(
+            Class::Ack,
+            self.center.public.clone(),
+            t.source(),
+            topic.clone(),
+            receipt.as_bytes(),
+        );
+        self.dispatch(Transaction::new(ack));
+        let message = Message::new(
+            Class::Deliver,
+            self.center.public.clone(),
+            action.target(),
+            topic,
+            body,
+        );
+        if self.outbox.insert(receipt, message.clone()) {
+            self.dispatch(Transaction::new(message));
+        }
+    }
+
+    /// Passes a message delivered by the Record on to the user unless
+    /// it has been received before, either way it gets acknowledged.
+    /// Should the Topic not keep up the message isn't acknowledged,
+    /// so the Record sends it again later. Only replicas of the Record
+    /// can deliver messages, which have to be Actions for this node
+    /// with a valid signature should they be signed.
+    fn handle_deliver(&mut self, t: Transaction) {
+        trace::info!("incoming deliver message");
+        let topic = t.topic();
+        if !self.is_replica(&t.source(), &topic) {
+            trace::warn!("rejecting deliver message from non replica: {:?}", t);
+            return;
+        }
+        let (action, sequence) = match Transaction::from_bytes(&t.message.body.as_bytes()) {
+            Ok(action)
+                if action.class() == Class::Action
+                    && action.topic() == topic
+                    && action.target() == self.center.public
+                    && action.author().is_none_or(|_| action.verify().is_ok()) =>
+            {
+                match action.message.sequence {
+                    Some(sequence) => (action, sequence),
+                    None => return,
+                }
+            }
+            _ => {
+                trace::warn!("received invalid deliver message: {:?}", t);
+                return;
+            }
+        };
+        let publisher = action.source();
+        let key = (topic.clone(), publisher.clone());
+        if !self.windows.get(&key).is_some_and(|w| w.contains(sequence)) {
+            let pending = self.table.tracer().pending(&action);
+            match Switch::handle_action(
+                action,
+                &self.topics,
+                &self.interface,
+                &self.center,
+                self.encryption,
+            ) {
+                Ok(()) => {
+                    self.table.metrics().record(metrics::Event::Delivered);
+                    pending.decide(Decision::Delivered);
+                }
+                Err(Error::Busy(_)) => {
+                    self.table.metrics().record(metrics::Event::Dropped);
+                    pending.decide(Decision::Dropped(Reason::Busy));
+                    return;
+                }
+                Err(e) => {
+                    trace::warn!("dropping delivered action: {}", e);
+                    pending.decide(Decision::Dropped(Reason::Undeliverable));
+                }
+            }
+            self.windows.entry(key).or_default().insert(sequence);
+        }
+        let receipt = Receipt::new(
+            topic.clone(),
+            publisher,
+            self.center.public.clone(),
+            sequence,
+        );
+        let ack = Message::new(
+            Class::Ack,
+            self.center.public.clone(),
+            topic.clone(),
+            topic,
+            receipt.as_bytes(),
+        );
+        self.dispatch(Transaction::new(ack));
+    }
+
+    /// Passes a message published on a child of the subtree on to all
+    /// subscribers of the Record except the publisher. Publishers
+    /// that aren't allowed to publish get a Denied response.
+    fn handle_propagate(&self, t: Transaction) {
+        trace::info!("incoming propagate message for local topic");
+        let subtree = t.topic();
+        let record = match self.records.get(&subtree) {
+            Some(record) => record,
+            None => return,
+        };
+        if !record.may_publish(&t.source()) {
+            trace::warn!("rejecting unauthorized propagation: {:?}", t);
+            Switch::deny(&t, &self.listener, &self.center);
+            return;
+        }
+        self.records.touch(&subtree);
+        // The signature of the publisher is passed on, so that the
+        // subscribers can check it themselves.
+        let author = t.author().unwrap_or(Signature::new([0; 32], [0; 64]));
+        let mut body = t.source().as_bytes().to_vec();
+        body.extend_from_slice(&author.key());
+        body.extend_from_slice(&author.bytes());
+        body.append(&mut t.message.body.as_bytes());
+        let subscribers = record
+            .subscribers
+            .into_iter()
+            .filter(|subscriber| subscriber != &t.source())
+            .collect();
+        self.fan_out(&subtree, subscribers, &body);
+    }
+
+    /// Sends the Subtree body to all subscribers. Up to the branching
+    /// factor they receive it directly, otherwise the subscribers get
+    /// split into that many partitions of neighbouring Addresses. The
+    /// first subscriber of each partition receives the message
+    /// together with the rest of its partition and passes it on.
+    /// Delegates send at most branching copies themselves, so Records
+    /// with more subscribers than that use additional partitions.
+    fn fan_out(&self, subtree: &Address, mut subscribers: Vec<Address>, body: &[u8]) {
+        if subscribers.len() <= self.branching {
+            for subscriber in subscribers {
+                self.send_subtree(Class::Subtree, subtree, subscriber, body.to_vec());
+            }
+            return;
+        }
+        subscribers.sort();
+        let size = subscribers
+            .len()
+            .div_ceil(self.branching)
+            .min(self.branching + 1);
+        for partition in subscribers.chunks(size) {
+            let (delegate, rest) = partition.split_first().unwrap();
+            let mut data = (rest.len() as u32).to_be_bytes().to_vec();
+            for subscriber in rest {
+                data.extend_from_slice(&subscriber.as_bytes());
+            }
+            data.extend_from_slice(body);
+            self.send_subtree(Class::Delegate, subtree, delegate.clone(), data);
+        }
+    }
+
+    /// Sends a Subtree or Delegate message to the subscriber, the
+    /// local one gets it right away.
+    fn send_subtree(&self, class: Class, subtree: &Address, subscriber: Address, body: Vec<u8>) {
+        // Wires from the same source have to be signed with the same
+        // key, so delegates can't use the subtree as source.
+        let message = Message::new(
+            class,
+            self.center.public.clone(),
+            subscriber.clone(),
+            subtree.clone(),
+            body,
+        );
+        let mut t = Transaction::new(message);
+        if subscriber != self.center.public {
+            if self.encryption {
+                t.message.encrypt(&self.center);
+            }
+            let _ = self.listener.try_send(t);
+        } else if t.class() == Class::Delegate {
+            self.handle_delegate(t);
+        } else {
+            Switch::handle_subtree(t, &self.topics, &self.center);
+        }
+    }
+
+    /// Delivers a delegated message like a Subtree message and passes
+    /// it on to the partition of subscribers it contains. Delegates
+    /// are only accepted from replicas of the subtree Record by nodes
+    /// subscribed to it, partitions can't exceed the branching factor
+    /// and only known subscribers of the subtree receive a copy.
+    /// Otherwise anybody could use them to multiply messages or send
+    /// them to arbitrary nodes.
+    fn handle_delegate(&self, mut t: Transaction) {
+        trace::info!("incoming delegate message");
+        let subtree = t.topic();
+        let simple = match self.topics.find(&subtree) {
+            Some(simple) => simple,
+            None => {
+                trace::warn!("rejecting delegate message for unknown topic: {:?}", t);
+                return;
+            }
+        };
+        if !self.is_replica(&t.source(), &subtree) {
+            trace::warn!("rejecting delegate message from non replica: {:?}", t);
+            return;
+        }
+        let data = t.message.body.as_bytes();
+        let split = match data.get(..4) {
+            Some(&[a, b, c, d]) => Some(u32::from_be_bytes([a, b, c, d]) as usize)
+                .filter(|length| *length <= self.branching)
+                .map(|length| length * 32 + 4)
+                .filter(|split| *split <= data.len()),
+            _ => None,
+        };
+        let split = match split {
+            Some(split) => split,
+            None => {
+                trace::warn!("received invalid delegate message: {:?}", t);
+                return;
+            }
+        };
+        let record = self.records.get(&subtree);
+        let partition: Vec<Address> = Address::from_bulk(data[4..split].to_vec())
+            .into_iter()
+            .filter(|subscriber| {
+                simple.peers.contains(subscriber)
+                    || record.as_ref().is_some_and(|r| r.contains(subscriber))
+            })
+            .collect();
+        let body = data[split..].to_vec();
+        t.message.class = Class::Subtree;
+        t.message.body = Body::new(body.clone());
+        Switch::handle_subtree(t, &self.topics, &self.center);
+        for subscriber in partition {
+            self.send_subtree(Class::Subtree, &subtree, subscriber, body.clone());
+        }
+    }
+
+    /// Passes a propagated message on to the subtree Topic. It gets
+    /// delivered like an Action with the Topic it was published on as
+    /// topic. The publisher only becomes the source if its signature
+    /// is valid, otherwise the node passing the message on remains
+    /// the source.
+    fn handle_subtree(mut t: Transaction, topics: &TopicBucket, center: &Center) {
+        trace::info!("incoming subtree message");
+        let simple = match topics.find(&t.topic()) {
+            Some(simple) => simple,
+            None => return,
+        };
+        let body = t.message.body.as_bytes();
+        if body.len() < 160 {
+            trace::warn!("received invalid subtree message: {:?}", t);
+            return;
+        }
+        let (publisher, topic) = match (
+            Address::from_slice(&body[..32]),
+            Address::from_slice(&body[128..160]),
+        ) {
+            (Ok(publisher), Ok(topic)) => (publisher, topic),
+            _ => return,
+        };
+        if publisher == center.public {
+            return;
+        }
+        let mut key = [0; 32];
+        let mut bytes = [0; 64];
+        key.copy_from_slice(&body[32..64]);
+        bytes.copy_from_slice(&body[64..128]);
+        let message = Message::new(
+            Class::Propagate,
+            publisher.clone(),
+            t.topic(),
+            t.topic(),
+            body[128..].to_vec(),
+        );
+        let mut original = Transaction::new(message);
+        original.set_author(Some(Signature::new(key, bytes)));
+        if original.verify().is_ok() && publisher.is_signing_key(&key) {
+            t.message.source = publisher;
+            t.set_verified(true);
+        } else {
+            trace::warn!("unable to verify the publisher of a subtree message");
+        }
+        t.message.class = Class::Action;
+        t.message.topic = topic;
+        t.message.body = Body::new(body[160..].to_vec());
+        let _ = simple.channel.try_send(Command::Message(t));
+    }
+
+    /// Decrypts a message the Switch exchanges with other nodes on
+    /// behalf of the user should encryption be enabled, messages that
+    /// can't be decrypted are dropped.
+    fn open(&self, mut t: Transaction) -> Option<Transaction> {
+        if self.encryption {
+            if let Err(e) = t.message.open(&self.center) {
+                trace::warn!("dropping message that can't be decrypted: {}", e);
+                return None;
+            }
+            t.set_encrypted(true);
+        }
+        Some(t)
+    }
+
+    /// Stores a value of another node, as long as this node is one
+    /// of the replicas of its key. Values of other nodes under the
+    /// same key are kept until they expire.
+    fn handle_store(&mut self, t: Transaction) {
+        trace::info!("incoming store message");
+        let key = t.topic();
+        if !self.is_replica(&self.center.public, &key) {
+            trace::warn!("rejecting value for a key this node isn't a replica of");
+            return;
+        }
+        let result = storage::decode(&t.message.body.as_bytes())
+            .and_then(|(data, ttl)| self.values.put(key, t.source(), data, ttl));
+        if let Err(e) = result {
+            trace::warn!("unable to store value: {}", e);
+        }
+    }
+
+    /// Responds with the requested value and its remaining TTL, the
+    /// body is empty if it isn't stored.
+    fn handle_find_value(&self, t: Transaction) {
+        trace::info!("incoming find value message");
+        let key = t.topic();
+        let body = match (self.values.get(&key), self.values.remaining(&key)) {
+            (Some(data), Some(ttl)) => storage::encode(&data, ttl),
+            _ => Vec::new(),
+        };
+        let message = Message::new(
+            Class::Value,
+            self.center.public.clone(),
+            t.source(),
+            key,
+            body,
+        );
+        let _ = self.listener.try_send(Transaction::new(message));
+    }
+
+    /// Passes a found value to all requests waiting for it. Should
+    /// the source not have it the request only fails once none of
+    /// the asked nodes is left.
+    fn handle_value(&mut self, t: Transaction) {
+        trace::info!("incoming value message");
+        let (key, source) = (t.topic(), t.source());
+        let body = t.message.body.as_bytes();
+        let found = storage::decode(&body).ok();
+        let mut i = 0;
+        while i < self.fetches.len() {
+            let fetch = &mut self.fetches[i];
+            if fetch.key != key || !fetch.pending.contains(&source) {
+                i += 1;
+                continue;
+            }
+            fetch.pending.retain(|node| node != &source);
+            match &found {
+                Some((data, _)) => {
+                    let _ = fetch.channel.send(Some(data.clone()));
+                }
+                None if fetch.pending.is_empty() => {
+                    let _ = fetch.channel.send(None);
+                }
+                None => {
+                    i += 1;
+                    continue;
+                }
+            }
+            self.fetches.remove(i);
+        }
+    }
+
+    /// Fails all value requests that didn't receive a value in time.
+    fn expire_fetches(&mut self) {
+        self.fetches.retain(|fetch| {
+            if fetch.sent.elapsed() < LOOKUP_TIMEOUT {
+                return true;
+            }
+            let _ = fetch.channel.send(None);
+            false
+        });
+    }
+
+    /// Returns the gossip settings of the local Topic, None if it
+    /// doesn't exist or isn't in gossip mode.
+    fn gossip_mode(&self, topic: &Address) -> Option<Gossip> {
+        match self.topics.find(topic).map(|simple| simple.dissemination) {
+            Some(Dissemination::Gossip(gossip)) => Some(gossip),
+            _ => None,
+        }
+    }
+
+    /// Sends a broadcast of the user in gossip mode, it only gets
+    /// pushed to a few subscribers and advertised to the others.
+    fn gossip(&mut self, topic: Address, body: Vec<u8>) {
+        let gossip = match self.gossip_mode(&topic) {
+            Some(gossip) => gossip,
+            None => return,
+        };
+        let rumor = Rumor::new(self.center.public.clone(), body);
+        let data = rumor.as_bytes();
+        self.rumors
+            .entry(topic.clone())
+            .or_default()
+            .insert(rumor, gossip.rounds);
+        self.spread(&topic, Class::Gossip, data, gossip.fanout);
+    }
+
+    /// Sends the body to random subscribers of the local Topic.
+    fn spread(&self, topic: &Address, class: Class, body: Vec<u8>, fanout: usize) {
+        let simple = match self.topics.find(topic) {
+            Some(simple) => simple,
+            None => return,
+        };
+        let peers = simple
+            .peers
+            .choose_multiple(&mut rand::thread_rng(), fanout);
+        for peer in peers {
+            self.send_gossip(class.clone(), topic, peer.clone(), body.clone());
+        }
+    }
+
+    fn send_gossip(&self, class: Class, topic: &Address, target: Address, body: Vec<u8>) {
+        let message = Message::new(
+            class,
+            self.center.public.clone(),
+            target,
+            topic.clone(),
+            body,
+        );
+        let _ = self.listener.try_send(Transaction::new(message));
+    }
+
+    /// Starts a new gossip round for all Topics, each one advertises
+    /// its recent messages to random subscribers. Topics that are no
+    /// longer in gossip mode or don't have any messages left are
+    /// removed.
+    fn advertise(&mut self) {
+        let now = Instant::now();
+        let topics: Vec<Address> = self.rumors.keys().cloned().collect();
+        for topic in topics {
+            let gossip = self.gossip_mode(&topic);
+            let rumors = match (gossip, self.rumors.get_mut(&topic)) {
+                (Some(_), Some(rumors)) => rumors,
+                _ => {
+                    self.rumors.remove(&topic);
+                    continue;
+                }
+            };
+            let ids = rumors.round(now);
+            if rumors.is_empty() {
+                self.rumors.remove(&topic);
+            }
+            if let (Some(gossip), false) = (gossip, ids.is_empty()) {
+                let body = gossip::encode_ids(&ids);
+                self.spread(&topic, Class::GossipDigest, body, gossip.fanout);
+            }
+        }
+    }
+
+    /// Delivers a gossip message to the local Topic, unless it was
+    /// already received before. Afterwards it gets advertised like
+    /// the messages of the Topic itself.
+    fn handle_gossip(&mut self, mut t: Transaction) {
+        trace::info!("incoming gossip message");
+        let topic = t.topic();
+        let gossip = match self.gossip_mode(&topic) {
+            Some(gossip) => gossip,
+            None => return,
+        };
+        let rumor = match Rumor::from_bytes(&t.message.body.as_bytes()) {
+            Ok(rumor) => rumor,
+            Err(e) => {
+                trace::warn!("received invalid gossip message: {}", e);
+                return;
+            }
+        };
+        if rumor.publisher == self.center.public {
+            return;
+        }
+        let rumors = self.rumors.entry(topic.clone()).or_default();
+        if !rumors.insert(rumor.clone(), gossip.rounds) {
+            return;
+        }
+        t.message.class = Class::Action;
+        t.message.source = rumor.publisher;
+        t.message.body = Body::new(rumor.body);
+        if let Some(simple) = self.topics.find(&topic) {
+            let _ = simple.channel.try_send(Command::Message(t));
+        }
+    }
+
+    /// Requests all advertised messages that haven't been received
+    /// yet from the source of the digest.
+    fn handle_gossip_digest(&self, t: Transaction) {
+        trace::info!("incoming gossip digest");
+        let topic = t.topic();
+        if self.gossip_mode(&topic).is_none() {
+            return;
+        }
+        let ids = match gossip::decode_ids(&t.message.body.as_bytes()) {
+            Ok(ids) => ids,
+            Err(e) => {
+                trace::warn!("received invalid gossip digest: {}", e);
+                return;
+            }
+        };
+        let missing = match self.rumors.get(&topic) {
+            Some(rumors) => rumors.missing(&ids),
+            None => ids,
+        };
+        if !missing.is_empty() {
+            let body = gossip::encode_ids(&missing);
+            self.send_gossip(Class::GossipRequest, &topic, t.source(), body);
+        }
+    }
+
+    /// Sends the requested messages that are still stored to the
+    /// source.
+    fn handle_gossip_request(&self, t: Transaction) {
+        trace::info!("incoming gossip request");
+        let topic = t.topic();
+        let (rumors, ids) = match (
+            self.rumors.get(&topic),
+            gossip::decode_ids(&t.message.body.as_bytes()),
+        ) {
+            (Some(rumors), Ok(ids)) => (rumors, ids),
+            _ => return,
+        };
+        for rumor in ids.iter().filter_map(|id| rumors.get(id)) {
+            self.send_gossip(Class::Gossip, &topic, t.source(), rumor.as_bytes());
+        }
+    }
+
+    /// Removes an acknowledged message from the Outbox, either as
+    /// publisher or as Record. Subscribers can only acknowledge their
+    /// own messages.
+    fn handle_ack(&mut self, t: Transaction) {
+        trace::info!("incoming ack message");
+        let receipt = match Receipt::from_bytes(&t.message.body.as_bytes()) {
+            Ok(receipt) => receipt,
+            Err(_) => {
+                trace::warn!("received invalid ack message: {:?}", t);
+                return;
+            }
+        };
+        // Only the target of a message can acknowledge it, for the
+        // publisher that is the node hosting the Record.
+        if t.target() == self.center.public && receipt.publisher == self.center.public {
+            if self.is_replica(&t.source(), &receipt.topic) {
+                self.outbox.acknowledge(&receipt.topic, &receipt);
+            } else {
+                trace::warn!("rejecting ack from non replica: {:?}", t);
+            }
+        } else if t.source() == receipt.subscriber {
+            self.outbox.acknowledge(&receipt.subscriber, &receipt);
+        }
+    }
+
+    /// Announces the presence of the user on the Topic with the
+    /// metadata stored in its Simple, unless the user never announced
+    /// itself.
+    fn announce(&self, topic: &Address) {
+        let metadata = match self.topics.find(topic) {
+            Some(Simple {
+                metadata: Some(metadata),
+                ..
+            }) => metadata.clone(),
+            _ => return,
+        };
+        let message = Message::new(
+            Class::Announce,
+            self.center.public.clone(),
+            topic.clone(),
+            topic.clone(),
+            metadata,
+        );
+        let t = Transaction::new(message);
+        if self.table.should_be_local(topic) {
+            Switch::handle_announce(t, &self.listener, &self.records, &self.topics, &self.center);
+        } else {
+            let _ = self.listener.try_send(t);
+        }
+    }
+
+    fn handle_ping(t: Transaction, channel: &Channel<Transaction>, center: &Center, table: &Safe) {
+        trace::info!("incoming ping message");
+        let node = Node::new(center.public.clone(), Some(table.link()));
+        let message = Message::new(
+            Class::Details,
+            center.public.clone(),
+            t.source(),
+            Address::default(),
+            node.as_bytes(),
+        );
+        let transaction = Transaction::new(message);
+        let _ = channel.try_send(transaction);
+    }
+
+    fn handle_pong(t: Transaction, channel: &Channel<SignalingAction>) {
+        trace::info!("incoming pong message");
+        let _ = channel.send(SignalingAction::pong(t.source(), t.uuid));
+    }
+
+    fn handle_lookup(
+        t: Transaction,
+        listener: &Channel<Transaction>,
+        center: &Center,
+        table: &Safe,
+    ) {
+        trace::info!("incoming lookup message");
+        let node = Node::new(center.public.clone(), Some(table.link()));
+        let message = Message::new(
+            Class::Details,
+            center.public.clone(),
+            t.source(),
+            Address::default(),
+            node.as_bytes(),
+        );
+        let transaction = Transaction::new(message);
+        let _ = listener.try_send(transaction);
+    }
+
+    fn handle_details(t: Transaction, channel: &Channel<SignalingAction>, table: &Safe) {
+        trace::info!("incoming details message");
+        match Node::from_bytes(t.message.body.as_bytes()) {
+            Ok(node) => {
+                table.add(node);
+                let action = SignalingAction::pong(t.source(), t.uuid);
+                let _ = channel.send(action);
+            }
+            Err(e) => trace::warn!("received invalid node details: {}", e),
+        }
+    }
+
+    /// Responds to a FindNode request with the closest known nodes to
+    /// the topic of the request. The requesting node itself is never
+    /// part of the response.
+    fn handle_find_node(
+        t: Transaction,
+        listener: &Channel<Transaction>,
+        table: &Safe,
+        center: &Center,
+        bucket: usize,
+    ) {
+        trace::info!("incoming find node message");
+        let source = t.source();
+        let mut body = Vec::new();
+        for node in table.get_copy(&t.topic(), bucket + 1) {
+            if node.address != source {
+                body.append(&mut node.as_bytes());
+            }
+        }
+        let message = Message::new(
+            Class::FoundNodes,
+            center.public.clone(),
+            source,
+            t.topic(),
+            body,
+        );
+        let _ = listener.try_send(Transaction::new(message));
+    }
+
+    /// Adds the nodes of a FoundNodes response to the table and
+    /// passes them to the lookup waiting for them. Responses are
+    /// matched by their source and topic, since the Transaction
+    /// cache doesn't allow reusing the request UUID.
+    fn handle_found_nodes(
+        t: Transaction,
+        lookups: &mut [(Lookup, Completion)],
+        table: &Safe,
+        center: &Center,
+    ) {
+        trace::info!("incoming found nodes message");
+        // Nodes from the answer are only added to the table once the
+        // Listener reached them.
+        let nodes = Node::from_bulk(t.message.body.as_bytes());
+        for node in &nodes {
+            if node.address != center.public {
+                table.propose(node.clone());
+            }
+        }
+        let (source, topic) = (t.source(), t.topic());
+        for (lookup, _) in lookups.iter_mut() {
+            if lookup.target() == &topic && lookup.response(&source, &nodes) {
+                break;
+            }
+        }
+    }
+
+    /// Advances all ongoing lookups: Expired requests get dropped,
+    /// new FindNode requests sent and the results of finished
+    /// lookups passed on.
+    fn drive_lookups(
+        lookups: &mut Vec<(Lookup, Completion)>,
         listener: &Channel<Transaction>,
         records: &RecordBucket,
-        topics: &RefCell<TopicBucket>,
+        values: &mut ValueStore,
+        fetches: &mut Vec<Fetch>,
         center: &Center,
     ) {
-        log::info!("incoming unsubscribe message for local topic");
-        let topic = t.target();
+        let mut i = 0;
+        while i < lookups.len() {
+            let lookup = &mut lookups[i].0;
+            lookup.expire(LOOKUP_TIMEOUT);
+            for address in lookup.queries() {
+                let message = Message::new(
+                    Class::FindNode,
+                    center.public.clone(),
+                    address,
+                    lookup.target().clone(),
+                    Vec::new(),
+                );
+                let _ = listener.try_send(Transaction::new(message));
+            }
+            if lookup.is_done() {
+                trace::info!("lookup completed");
+                let (lookup, completion) = lookups.remove(i);
+                match completion {
+                    Completion::Interface(channel) => {
+                        let _ = channel.send(lookup.result());
+                    }
+                    Completion::Refresh => {}
+                    Completion::Republish(address, replicas) => {
+                        let mut nodes = lookup.result();
+                        let distance = &center.public ^ &address;
+                        let closer = nodes
+                            .iter()
+                            .filter(|node| (&node.address ^ &address) < distance)
+                            .count();
+                        // The local node is one of the replicas itself.
+                        nodes.truncate(if closer < replicas {
+                            replicas - 1
+                        } else {
+                            replicas
+                        });
+                        Switch::republish(&address, nodes, listener, records, center);
+                    }
+                    Completion::Store(key, data, ttl, replicas) => {
+                        let mut nodes = lookup.result();
+                        let distance = &center.public ^ &key;
+                        let closer = nodes
+                            .iter()
+                            .filter(|node| (&node.address ^ &key) < distance)
+                            .count();
+                        if closer < replicas {
+                            nodes.truncate(replicas - 1);
+                            let owner = center.public.clone();
+                            if let Err(e) = values.put(key.clone(), owner, data.clone(), ttl) {
+                                trace::warn!("unable to store value: {}", e);
+                            }
+                        } else {
+                            nodes.truncate(replicas);
+                        }
+                        let body = storage::encode(&data, ttl);
+                        for node in nodes {
+                            let message = Message::new(
+                                Class::Store,
+                                center.public.clone(),
+                                node.address,
+                                key.clone(),
+                                body.clone(),
+                            );
+                            let _ = listener.try_send(Transaction::new(message));
+                        }
+                    }
+                    Completion::FindValue(key, channel, replicas) => {
+                        let mut nodes = lookup.result();
+                        nodes.truncate(replicas);
+                        if nodes.is_empty() {
+                            let _ = channel.send(None);
+                            continue;
+                        }
+                        for node in &nodes {
+                            let message = Message::new(
+                                Class::FindValue,
+                                center.public.clone(),
+                                node.address.clone(),
+                                key.clone(),
+                                Vec::new(),
+                            );
+                            let _ = listener.try_send(Transaction::new(message));
+                        }
+                        fetches.push(Fetch {
+                            key,
+                            channel,
+                            pending: nodes.into_iter().map(|node| node.address).collect(),
+                            sent: Instant::now(),
+                        });
+                    }
+                }
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Sends the current state of the Record to all of the nodes.
+    /// Records that have been removed in the meantime are skipped.
+    fn republish(
+        address: &Address,
+        nodes: Vec<Node>,
+        listener: &Channel<Transaction>,
+        records: &RecordBucket,
+        center: &Center,
+    ) {
+        if let Some(record) = records.get(address) {
+            let body = DataTopic::from(&record).as_bytes();
+            for node in nodes {
+                let message = Message::new(
+                    Class::Record,
+                    center.public.clone(),
+                    node.address,
+                    address.clone(),
+                    body.clone(),
+                );
+                let _ = listener.try_send(Transaction::new(message));
+            }
+        }
+    }
+
+    /// Stores a republished Record, it gets merged with the local copy
+    /// depending on their versions. Returns false if the Record was
+    /// invalid.
+    fn handle_record(t: Transaction, records: &RecordBucket) -> bool {
+        trace::info!("incoming record message");
+        let topic = match DataTopic::from_bytes(&t.message.body.as_bytes()) {
+            Ok(topic) if !topic.removed && topic.address == t.topic() => topic,
+            _ => {
+                trace::warn!("received invalid record: {:?}", t);
+                return false;
+            }
+        };
+        // Owner and Policy of a known Record never change through
+        // other replicas.
+        if records.contains(&topic.address) {
+            records.merge(&Record::from(topic));
+        } else {
+            let mut record = Record::from(topic);
+            record.enforce();
+            records.add(record);
+        }
+        true
+    }
+
+    /// Checks every Record against the routing table. Records this
+    /// node is no longer a replica of get handed over to the closest
+    /// known node, the local copy is kept until the node accepts it,
+    /// so unanswered transfers get repeated. For all other Records the
+    /// digest of their subscribers is sent to the other replicas.
+    /// Should an expiry be set idle Records get garbage collected
+    /// first.
+    fn maintain(&mut self) {
+        let expired = self.values.expire(Instant::now());
+        if expired > 0 {
+            trace::info!("dropped {} expired values", expired);
+        }
+        if let Some(expiry) = self.expiry {
+            for address in self.records.idle(expiry) {
+                trace::info!("record expired without activity");
+                self.collect(&address);
+            }
+        }
+        for address in self.records.addresses() {
+            let record = match self.records.get(&address) {
+                Some(record) => record,
+                None => continue,
+            };
+            let replicas = self.table.replicas(&address, self.replicas);
+            if !replicas.contains(&self.center.public) {
+                self.send_transfer(&record, replicas[0].clone());
+                continue;
+            }
+            self.transfers.remove(&address);
+            let digest = record.digest().to_vec();
+            for replica in replicas {
+                if replica == self.center.public {
+                    continue;
+                }
+                let message = Message::new(
+                    Class::Digest,
+                    self.center.public.clone(),
+                    replica,
+                    address.clone(),
+                    digest.clone(),
+                );
+                let _ = self.listener.try_send(Transaction::new(message));
+            }
+        }
+    }
+
+    /// Garbage collects the Record once its last subscriber left.
+    fn release(&mut self, topic: &Address) {
+        match self.records.get(topic) {
+            Some(record) if record.is_empty() => {
+                trace::info!("last subscriber left, removing record");
+                self.collect(topic);
+            }
+            _ => {}
+        }
+    }
+
+    /// Removes the Record and tells the other replicas to remove
+    /// theirs as well, otherwise they would restore it through the
+    /// digest exchange.
+    fn collect(&mut self, topic: &Address) {
+        self.transfers.remove(topic);
+        self.inbox.remove(topic);
+        if !self.expire(topic) {
+            return;
+        }
+        for replica in self.table.replicas(topic, self.replicas) {
+            if replica == self.center.public {
+                continue;
+            }
+            let message = Message::new(
+                Class::Record,
+                self.center.public.clone(),
+                replica,
+                topic.clone(),
+                DataTopic::removed(topic.clone()).as_bytes(),
+            );
+            let _ = self.listener.try_send(Transaction::new(message));
+        }
+    }
+
+    /// Removes the local Record and emits the Event. Returns false if
+    /// it didn't exist.
+    fn expire(&self, topic: &Address) -> bool {
+        if !self.records.contains(topic) {
+            return false;
+        }
+        self.records.remove(topic);
+        self.table
+            .events()
+            .emit(event::Event::RecordExpired(topic.clone()));
+        true
+    }
+
+    /// Checks if the Record message announces the removal of the
+    /// Record instead of containing its current state.
+    fn is_removal(t: &Transaction) -> bool {
+        matches!(
+            DataTopic::from_bytes(&t.message.body.as_bytes()),
+            Ok(topic) if topic.removed
+        )
+    }
+
+    /// Another replica garbage collected the Record, the local copy
+    /// gets removed as well. Only replicas of the Record are allowed
+    /// to do that.
+    fn handle_removal(&mut self, t: Transaction) {
+        let topic = t.topic();
+        if !self.is_replica(&t.source(), &topic) {
+            trace::warn!("rejecting record removal from non replica: {:?}", t);
+            return;
+        }
+        trace::info!("record was removed by another replica");
+        self.transfers.remove(&topic);
+        self.expire(&topic);
+    }
+
+    /// Sends the current state of the Record to the node and
+    /// remembers what was sent.
+    fn send_transfer(&mut self, record: &Record, node: Address) {
+        trace::info!("transferring record to closer node");
+        let message = Message::new(
+            Class::RecordTransfer,
+            self.center.public.clone(),
+            node.clone(),
+            record.address.clone(),
+            DataTopic::from(record).as_bytes(),
+        );
+        let _ = self.listener.try_send(Transaction::new(message));
+        self.transfers
+            .insert(record.address.clone(), (node, record.subscribers.clone()));
+    }
+
+    /// Stores a Record handed over by another node and accepts it, so
+    /// the other node can remove its copy. Only Records this node is a
+    /// replica of get taken over and only from nodes that hosted them
+    /// before, otherwise any node could claim a Topic.
+    fn handle_transfer(&self, t: Transaction) {
+        let (source, topic) = (t.source(), t.topic());
+        if !self.is_replica(&self.center.public, &topic) || !self.was_replica(&source, &topic) {
+            trace::warn!("rejecting record transfer from non replica: {:?}", t);
+            return;
+        }
+        if !Switch::handle_record(t, &self.records) {
+            return;
+        }
+        let message = Message::new(
+            Class::RecordAck,
+            self.center.public.clone(),
+            source,
+            topic.clone(),
+            Vec::new(),
+        );
+        let _ = self.listener.try_send(Transaction::new(message));
+    }
+
+    /// Compares the digest of another replica with the local Record.
+    /// Should they differ the local state is sent back, a missing
+    /// Record counts as one without subscribers. Digests of nodes
+    /// that aren't replicas of the Record are ignored.
+    fn handle_digest(&self, t: Transaction) {
+        let topic = t.topic();
+        if !self.is_replica(&t.source(), &topic) {
+            trace::warn!("rejecting digest from non replica: {:?}", t);
+            return;
+        }
+        // Only the closer replicas keep the Record alive, otherwise
+        // they would never expire as long as there are several.
+        if (&t.source() ^ &topic) < (&self.center.public ^ &topic) {
+            self.records.touch(&topic);
+        }
+        let record = self.records.get(&topic);
+        let digest = record.as_ref().map(|record| record.digest().to_vec());
+        if digest == Some(t.message.body.as_bytes()) {
+            return;
+        }
+        trace::info!("replicas of record differ, reconciling");
+        let body = match record {
+            Some(record) => DataTopic::from(&record),
+            None => DataTopic::new(topic.clone(), Vec::new()),
+        };
+        let message = Message::new(
+            Class::Reconcile,
+            self.center.public.clone(),
+            t.source(),
+            topic,
+            body.as_bytes(),
+        );
+        let _ = self.listener.try_send(Transaction::new(message));
+    }
+
+    /// Merges the subscribers of another replica into the local
+    /// Record. Should it be missing any of the local ones the merged
+    /// Record is sent back as well, after that both are the same.
+    /// Only replicas of the Record can reconcile it, other nodes could
+    /// add arbitrary subscribers.
+    fn handle_reconcile(&self, t: Transaction) {
+        if !self.is_replica(&t.source(), &t.topic()) {
+            trace::warn!("rejecting reconcile from non replica: {:?}", t);
+            return;
+        }
+        let remote = match DataTopic::from_bytes(&t.message.body.as_bytes()) {
+            Ok(remote) if !remote.removed && remote.address == t.topic() => remote,
+            _ => {
+                trace::warn!("received invalid reconcile message: {:?}", t);
+                return;
+            }
+        };
+        let topic = remote.address.clone();
+        let remote = Record::from(remote);
+        let local = match self.records.get(&topic) {
+            Some(record) => record,
+            None => return,
+        };
+        self.records.merge(&remote);
+        if local
+            .members()
+            .iter()
+            .all(|member| remote.is_member(member))
+        {
+            return;
+        }
+        if let Some(record) = self.records.get(&topic) {
+            let message = Message::new(
+                Class::Record,
+                self.center.public.clone(),
+                t.source(),
+                topic,
+                DataTopic::from(&record).as_bytes(),
+            );
+            let _ = self.listener.try_send(Transaction::new(message));
+        }
+    }
+
+    /// Removes a Record once the node it was transferred to accepted
+    /// it. Should subscribers have changed in the meantime the current
+    /// state gets transferred again instead.
+    fn handle_record_ack(&mut self, t: Transaction) {
+        let topic = t.topic();
+        match self.transfers.get(&topic) {
+            Some((node, _)) if node == &t.source() => {}
+            _ => {
+                trace::warn!("received unexpected record ack: {:?}", t.uuid);
+                return;
+            }
+        }
+        let (node, subscribers) = self.transfers.remove(&topic).unwrap();
+        match self.records.get(&topic) {
+            Some(record) if record.subscribers != subscribers => {
+                self.send_transfer(&record, node);
+            }
+            Some(_) => {
+                trace::info!("record was transferred, removing local copy");
+                self.records.remove(&topic);
+            }
+            None => {}
+        }
+    }
+
+    /// Passes an incoming Action to the matching Topic or the user.
+    /// If encryption is enabled the body gets decrypted first, should
+    /// that fail the message is not passed on and an error returned.
+    /// Signed Actions get verified before that.
+    fn handle_action(
+        mut t: Transaction,
+        topics: &TopicBucket,
+        interface: &Channel<InterfaceAction>,
+        center: &Center,
+        encryption: bool,
+    ) -> Result<(), Error> {
+        trace::info!("incoming action message");
+        Switch::authenticate(&mut t);
+        if encryption {
+            t.message.open(center)?;
+            t.set_encrypted(true);
+        }
+        Switch::deliver(t, topics, interface)
+    }
+
+    /// Custom messages go to the handler registered for their Class,
+    /// without one they are delivered like an Action. Unlike Actions
+    /// they are never checked against Records.
+    fn handle_custom(
+        mut t: Transaction,
+        hooks: &HashMap<u16, Hook>,
+        topics: &TopicBucket,
+        interface: &Channel<InterfaceAction>,
+        center: &Center,
+        encryption: bool,
+    ) -> Result<(), Error> {
+        trace::info!("incoming custom message");
+        Switch::authenticate(&mut t);
+        if encryption {
+            t.message.open(center)?;
+            t.set_encrypted(true);
+        }
+        if let Class::Custom(number) = t.class() {
+            if let Some(hook) = hooks.get(&number) {
+                hook(t);
+                return Ok(());
+            }
+        }
+        Switch::deliver(t, topics, interface)
+    }
+
+    /// Marks the source of a message of the user as verified if it
+    /// was signed by its author with the key its Address is derived
+    /// from, the same check Wires go through. Messages failing the
+    /// verification are still delivered, just not as verified.
+    fn authenticate(t: &mut Transaction) {
+        let author = match t.author() {
+            Some(author) => author,
+            None => return,
+        };
+        if let Err(e) = t.verify() {
+            trace::warn!("unable to verify the author of a message: {}", e);
+            return;
+        }
+        if t.source().is_signing_key(&author.key()) {
+            t.set_verified(true);
+        } else {
+            trace::warn!("author of a message signed with a foreign key");
+        }
+    }
+
+    /// Passes a message of the user on to its Topic, a matching
+    /// Wildcard or the Interface.
+    fn deliver(
+        mut t: Transaction,
+        topics: &TopicBucket,
+        interface: &Channel<InterfaceAction>,
+    ) -> Result<(), Error> {
+        if let Some(simple) = topics.find(&t.topic()) {
+            let command = Command::Message(t);
+            simple.channel.try_send(command)
+        } else if let Some(wildcard) = topics.wildcard(&t.topic()) {
+            t.set_wildcard(true);
+            let command = Command::Message(t);
+            wildcard.channel.try_send(command)
+        } else {
+            let action = InterfaceAction::Message(t);
+            interface.try_send(action)
+        }
+    }
+
+    fn handle_subscriber(t: Transaction, topics: &mut TopicBucket, center: &Center) {
+        trace::info!("incoming subscriber message");
+        if let Some(simple) = topics.find_mut(&t.topic()) {
+            let addrs = Address::from_bulk(t.message.body.as_bytes());
+            for sub in addrs {
+                if sub != center.public {
+                    simple.subscriber(sub);
+                }
+            }
+        }
+    }
+
+    /// The Record informs the subscribers about one that left, its
+    /// Address is the body.
+    fn handle_unsubscriber(t: Transaction, topics: &mut TopicBucket) {
+        trace::info!("incoming unsubscriber message");
+        let subscriber = match Address::from_slice(t.message.body.as_slice()) {
+            Ok(subscriber) => subscriber,
+            Err(_) => {
+                trace::warn!("received invalid unsubscriber message: {:?}", t);
+                return;
+            }
+        };
+        if let Some(simple) = topics.find_mut(&t.topic()) {
+            simple.peers.retain(|peer| peer != &subscriber);
+            let action = Command::Unsubscriber(subscriber);
+            let _ = simple.channel.try_send(action);
+        }
+    }
+
+    /// Checks if the source of an Action is allowed to publish on its
+    /// Topic. Only Records hosted on this node can be checked, direct
+    /// messages don't belong to any Topic.
+    fn may_publish(t: &Transaction, records: &RecordBucket) -> bool {
+        let topic = t.topic();
+        topic == Address::default()
+            || records
+                .get(&topic)
+                .is_none_or(|record| record.may_publish(&t.source()))
+    }
+
+    /// Informs the source of a rejected Subscribe or Action.
+    fn deny(t: &Transaction, listener: &Channel<Transaction>, center: &Center) {
+        let message = Message::new(
+            Class::Denied,
+            center.public.clone(),
+            t.source(),
+            t.topic(),
+            Vec::new(),
+        );
+        let _ = listener.try_send(Transaction::new(message));
+    }
+
+    /// The first subscriber creates the Record and becomes its owner,
+    /// its Subscribe body can contain the Policy. Later the owner can
+    /// change the Policy the same way. Sources not allowed to
+    /// subscribe get a Denied response, subscribers not allowed to
+    /// publish only get their subscription confirmed without the
+    /// list of subscribers.
+    fn handle_subscribe(
+        t: Transaction,
+        listener: &Channel<Transaction>,
+        records: &RecordBucket,
+        topics: &mut TopicBucket,
+        center: &Center,
+    ) {
+        trace::info!("incoming subscribe message for local topic");
+        let topic = t.topic();
+        let (role, body) = Role::split(t.message.body.as_slice());
+        let (_, policy) = Retention::split(body);
         match records.get(&topic) {
             Some(record) => {
-                let source = t.source();
-                records.unsubscribe(&topic, &t.source());
-                let mut subscribers = Vec::new();
-                record.subscribers.iter().for_each(|x| {
-                    if x != &source {
-                        subscribers.append(&mut x.as_bytes().to_vec())
+                if !record.may_subscribe(&t.source()) {
+                    trace::warn!("rejecting unauthorized subscribe: {:?}", t);
+                    Switch::deny(&t, listener, center);
+                    return;
+                }
+                if let (true, Some(policy)) = (record.is_owner(&t.source()), policy) {
+                    records.set_policy(&topic, policy);
+                }
+                records.register(&record.address, t.source(), role);
+                let record = records.get(&topic).unwrap();
+                let subscribers = record.subscribers.clone();
+                let mut subscribers_vec = Vec::new();
+                subscribers
+                    .iter()
+                    .for_each(|x| subscribers_vec.append(&mut x.as_bytes().to_vec()));
+                // A new publisher doesn't change the list of
+                // subscribers, so only it needs to get it.
+                let members = match role {
+                    Role::Publisher => vec![t.source()],
+                    _ => record.members(),
+                };
+                for subscriber in &members {
+                    let allowed = record.may_publish(subscriber);
+                    if subscriber == &center.public {
+                        if let (true, Some(simple)) = (allowed, topics.find_mut(&topic)) {
+                            for sub in &subscribers {
+                                simple.subscriber(sub.clone());
+                            }
+                        }
+                    } else {
+                        let body = if allowed {
+                            subscribers_vec.clone()
+                        } else {
+                            Vec::new()
+                        };
+                        // The source gets the confirmation instead.
+                        let class = match subscriber == &t.source() {
+                            true => Class::SubscribeAck,
+                            false => Class::Subscriber,
+                        };
+                        let message = Message::new(
+                            class,
+                            center.public.clone(),
+                            subscriber.clone(),
+                            t.topic(),
+                            body,
+                        );
+                        let transaction = Transaction::new(message);
+                        let _ = listener.try_send(transaction);
                     }
-                });
-                if topics.borrow().is_local(&topic) {
+                }
+            }
+            None => {
+                let mut record =
+                    Record::owned(topic.clone(), t.source(), policy.unwrap_or_default());
+                record.register(t.source(), role);
+                records.add(record);
+                if t.source() == center.public {
+                    return;
+                }
+                let message = Message::new(
+                    Class::SubscribeAck,
+                    center.public.clone(),
+                    t.source(),
+                    t.topic(),
+                    vec![],
+                );
+                let transaction = Transaction::new(message);
+                // TODO: Handle error
+                let _ = listener.try_send(transaction);
+            }
+        }
+    }
+
+    /// Updates the presence of a subscriber on a local Record. The
+    /// subscriber gets the list of everyone present in response, the
+    /// others get informed with a Joined message if it is new or
+    /// changed its metadata. Announcements of non subscribers are
+    /// ignored.
+    fn handle_announce(
+        t: Transaction,
+        listener: &Channel<Transaction>,
+        records: &RecordBucket,
+        topics: &TopicBucket,
+        center: &Center,
+    ) {
+        trace::info!("incoming announce message for local topic");
+        let (topic, source) = (t.topic(), t.source());
+        match records.get(&topic) {
+            Some(record) if record.contains(&source) => {}
+            _ => {
+                trace::warn!("received announcement of non subscriber: {:?}", t);
+                return;
+            }
+        }
+        let joined = records.announce(&topic, source.clone(), t.message.body.as_bytes());
+        let present = records.get(&topic).unwrap().present();
+        let body = Presence::to_bulk(&present);
+        Switch::notify(
+            Class::Presence,
+            &topic,
+            source.clone(),
+            body,
+            listener,
+            topics,
+            center,
+        );
+        if let (true, Some(entry)) = (joined, present.iter().find(|p| p.address == source)) {
+            for p in present.iter().filter(|p| p.address != source) {
+                let body = entry.as_bytes();
+                let target = p.address.clone();
+                Switch::notify(
+                    Class::Joined,
+                    &topic,
+                    target,
+                    body,
+                    listener,
+                    topics,
+                    center,
+                );
+            }
+        }
+    }
+
+    /// Informs the remaining present subscribers of a Record that the
+    /// subscribers have left.
+    fn depart(
+        topic: &Address,
+        left: Vec<Address>,
+        listener: &Channel<Transaction>,
+        records: &RecordBucket,
+        topics: &TopicBucket,
+        center: &Center,
+    ) {
+        let present = match records.get(topic) {
+            Some(record) => record.present(),
+            None => return,
+        };
+        for address in left {
+            let body = Presence::new(address, Vec::new(), Duration::ZERO).as_bytes();
+            for p in &present {
+                let target = p.address.clone();
+                let body = body.clone();
+                Switch::notify(Class::Left, topic, target, body, listener, topics, center);
+            }
+        }
+    }
+
+    /// Sends a presence message from a Record to one of its
+    /// subscribers, which might be the local user.
+    fn notify(
+        class: Class,
+        topic: &Address,
+        target: Address,
+        body: Vec<u8>,
+        listener: &Channel<Transaction>,
+        topics: &TopicBucket,
+        center: &Center,
+    ) {
+        let message = Message::new(
+            class,
+            center.public.clone(),
+            target.clone(),
+            topic.clone(),
+            body,
+        );
+        let t = Transaction::new(message);
+        if target == center.public {
+            Switch::handle_presence(t, topics, center);
+        } else {
+            let _ = listener.try_send(t);
+        }
+    }
+
+    /// Passes presence updates from a Record on to the matching
+    /// Topic. Joined and Left messages are delivered like any other
+    /// message, with the subscriber as source and its metadata as
+    /// body. Since the subscriber is taken from the body, remote
+    /// updates are only accepted from replicas of the Record.
+    fn handle_presence(mut t: Transaction, topics: &TopicBucket, center: &Center) {
+        trace::info!("incoming presence message");
+        let simple = match topics.find(&t.topic()) {
+            Some(simple) => simple,
+            None => return,
+        };
+        let body = t.message.body.as_bytes();
+        let command = match t.class() {
+            Class::Presence => match Presence::from_bulk(&body) {
+                Ok(list) => Command::Presence(list),
+                Err(_) => {
+                    trace::warn!("received invalid presence list: {:?}", t);
+                    return;
+                }
+            },
+            _ => match Presence::from_bytes(&body) {
+                Ok((entry, _)) if entry.address != center.public => {
+                    t.message.source = entry.address;
+                    t.message.body = Body::new(entry.metadata);
+                    Command::Message(t)
+                }
+                Ok(_) => return,
+                Err(_) => {
+                    trace::warn!("received invalid presence entry: {:?}", t);
+                    return;
+                }
+            },
+        };
+        let _ = simple.channel.try_send(command);
+    }
+
+    fn handle_unsubscribe(
+        t: Transaction,
+        listener: &Channel<Transaction>,
+        records: &RecordBucket,
+        topics: &mut TopicBucket,
+        center: &Center,
+    ) {
+        trace::info!("incoming unsubscribe message for local topic");
+        let topic = t.target();
+        if let Some(record) = records.get(&topic) {
+            let source = t.source();
+            records.unsubscribe(&topic, &t.source());
+            if record.presence.contains_key(&source) {
+                Switch::depart(
+                    &topic,
+                    vec![source.clone()],
+                    listener,
+                    records,
+                    topics,
+                    center,
+                );
+            }
+            if let Some(simple) = topics.find_mut(&topic) {
+                simple.peers.retain(|peer| peer != &source);
+            }
+            if topics.is_local(&topic) {
+                let message = Message::new(
+                    Class::Unsubscriber,
+                    center.public.clone(),
+                    center.public.clone(),
+                    topic.clone(),
+                    source.as_bytes().to_vec(),
+                );
+                Switch::handle_unsubscriber(Transaction::new(message), topics);
+            }
+            for addr in record.members() {
+                if addr != source {
                     let message = Message::new(
                         Class::Unsubscriber,
-                        t.topic(),
-                        t.source(),
+                        center.public.clone(),
+                        addr,
                         topic.clone(),
-                        subscribers.clone(),
+                        source.as_bytes().to_vec(),
                     );
                     let transaction = Transaction::new(message);
-                    Switch::handle_subscriber(transaction, topics, center)
-                }
-                for addr in record.subscribers {
-                    if addr != source {
-                        let message = Message::new(
-                            Class::Unsubscriber,
-                            t.source(),
-                            addr,
-                            topic.clone(),
-                            subscribers.clone(),
-                        );
-                        let transaction = Transaction::new(message);
-                        let _ = listener.send(transaction);
-                    }
+                    let _ = listener.try_send(transaction);
                 }
             }
-            None => {}
         }
     }
 }
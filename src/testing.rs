@@ -0,0 +1,736 @@
+//! # Testing
+//!
+//! Helpers for testing applications (and this crate) with several
+//! nodes in a single process, only available with the "testing"
+//! feature. The Memory Transport connects nodes through channels
+//! instead of sockets, so tests neither need free ports nor depend
+//! on the timing of the network stack.
+//!
+//! A Cluster starts a number of Interfaces sharing one Network. Every
+//! node knows all others from the start, so messages can be routed
+//! right away. Nodes can join and leave while the Cluster is running,
+//! which allows testing churn.
+//!
+//! Adverse conditions can be simulated through Faults, which delay,
+//! reorder or drop the frames sent between two nodes, and through
+//! partitions, which cut off groups of nodes from each other until
+//! they are healed. Connections themselves never break because of
+//! them, like with TCP the protocol only notices missing frames.
+
+use crate::config::{self, Config};
+use crate::error::Error;
+use crate::handshake::Hello;
+use crate::node::{Address, Center, Link, Node};
+use crate::transaction::Wire;
+use crate::transport::{Stream, Timeouts, Transport};
+use crate::Interface;
+use rand::Rng;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Host of all Links in a Network, the port identifies the node.
+pub const HOST: &str = "memory";
+
+/// Interval in which a joining node is checked for a completed
+/// bootstrap.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Longest additional delay of a reordered frame.
+const REORDER: Duration = Duration::from_millis(50);
+
+/// Listening Memory Transports by their Link.
+type Listeners = HashMap<String, (u64, Sender<MemoryStream>)>;
+
+/// Connects Memory Transports in the same process. Clones share the
+/// same Links and conditions, Transports of different Networks can't
+/// reach each other.
+#[derive(Debug, Clone, Default)]
+pub struct Network {
+    listeners: Arc<Mutex<Listeners>>,
+    conditions: Arc<Mutex<Conditions>>,
+}
+
+/// Conditions of all frames sent from one Link to another. The
+/// default has no effect at all.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fault {
+    /// Fixed delay of every frame.
+    pub latency: Duration,
+    /// Upper limit of a random delay added to every frame, frames
+    /// can overtake each other because of it.
+    pub jitter: Duration,
+    /// Probability between 0 and 1 that a frame gets lost.
+    pub loss: f64,
+    /// Probability between 0 and 1 that a frame gets held back by up
+    /// to 50 ms, so the following ones arrive first.
+    pub reorder: f64,
+}
+
+/// Faults and partitions of a Network, all keyed by the Links of the
+/// sender and the receiver.
+#[derive(Debug, Default)]
+struct Conditions {
+    /// Applies to all pairs of Links without their own Fault.
+    default: Fault,
+    faults: HashMap<(String, String), Fault>,
+    /// Pairs that can't reach each other, stored in both directions.
+    partitions: HashSet<(String, String)>,
+}
+
+/// Transport accepting and establishing connections inside a
+/// Network. Connecting to a Link nobody is bound to fails right away.
+/// The Links get released once the Transport is dropped, which
+/// happens when the Listener terminates.
+pub struct Memory {
+    network: Network,
+    /// Only available once the Transport is bound.
+    incoming: Option<Mutex<Receiver<MemoryStream>>>,
+    /// Feeds the incoming connections, each further Link gets a copy.
+    sender: Option<Sender<MemoryStream>>,
+    /// Bound Links and the IDs of their bindings, the first one is
+    /// the own Link of outgoing connections.
+    links: Vec<(String, u64)>,
+    timeouts: Timeouts,
+}
+
+/// One side of an in-memory connection. Every Wire is sent as a
+/// single frame, dropping either side closes the connection.
+pub struct MemoryStream {
+    sender: Sender<Frame>,
+    receiver: Receiver<Frame>,
+    /// Frames that were received but aren't due yet, the earliest
+    /// first.
+    pending: BinaryHeap<Reverse<Frame>>,
+    /// Conditions of the connection, None if it doesn't belong to a
+    /// Network.
+    route: Option<Route>,
+    /// Number of frames sent, it keeps frames due at the same time in
+    /// order.
+    sent: u64,
+    /// Mirrors set_nonblocking, since the channel has no such
+    /// setting.
+    nonblocking: bool,
+    /// Read timeout in blocking mode.
+    timeout: Duration,
+}
+
+/// A frame and the time it gets delivered.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Frame {
+    due: Instant,
+    sequence: u64,
+    data: Vec<u8>,
+}
+
+/// Links of both sides of a connection, used to look up the Fault of
+/// every frame.
+struct Route {
+    network: Network,
+    local: String,
+    remote: String,
+}
+
+/// Several nodes connected through the same Network. Nodes are
+/// identified by their index, which stays the same after a node
+/// left.
+pub struct Cluster {
+    network: Network,
+    /// All nodes that ever joined, None once they left.
+    nodes: Vec<Option<Interface>>,
+    centers: Vec<Center>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a Memory Transport in this Network for the Config.
+    pub fn transport(&self) -> config::Transport {
+        config::Transport::Custom(Box::new(Memory::new(self.clone())))
+    }
+
+    /// Stops accepting connections on the Link, connecting to it
+    /// fails from now on. Established connections stay open.
+    pub fn release(&self, link: &Link) {
+        self.listeners.lock().unwrap().remove(&link.to_string());
+    }
+
+    /// Applies the Fault to all frames sent from one Link to the
+    /// other, the opposite direction isn't affected.
+    pub fn set_fault(&self, from: &Link, to: &Link, fault: Fault) {
+        let key = (from.to_string(), to.to_string());
+        self.conditions.lock().unwrap().faults.insert(key, fault);
+    }
+
+    /// Applies the Fault to all pairs of Links without their own.
+    pub fn set_default(&self, fault: Fault) {
+        self.conditions.lock().unwrap().default = fault;
+    }
+
+    /// Removes all Faults, partitions stay in place.
+    pub fn clear_faults(&self) {
+        let mut conditions = self.conditions.lock().unwrap();
+        conditions.default = Fault::default();
+        conditions.faults.clear();
+    }
+
+    /// Cuts off the Links of both sides from each other. Frames
+    /// between them get lost and new connections fail, until the
+    /// Network is healed. Partitions add up, so several groups can be
+    /// isolated.
+    pub fn partition(&self, left: &[Link], right: &[Link]) {
+        let mut conditions = self.conditions.lock().unwrap();
+        for a in left {
+            for b in right {
+                conditions.partitions.insert((a.to_string(), b.to_string()));
+                conditions.partitions.insert((b.to_string(), a.to_string()));
+            }
+        }
+    }
+
+    /// Removes all partitions.
+    pub fn heal(&self) {
+        self.conditions.lock().unwrap().partitions.clear();
+    }
+
+    /// True if the Links are in different partitions.
+    pub fn is_partitioned(&self, from: &Link, to: &Link) -> bool {
+        let key = (from.to_string(), to.to_string());
+        self.conditions.lock().unwrap().partitions.contains(&key)
+    }
+
+    /// Delay of the next frame between the Links, None if it gets
+    /// lost.
+    fn delay(&self, from: &str, to: &str) -> Option<Duration> {
+        let conditions = self.conditions.lock().unwrap();
+        let key = (from.to_string(), to.to_string());
+        if conditions.partitions.contains(&key) {
+            return None;
+        }
+        conditions
+            .faults
+            .get(&key)
+            .unwrap_or(&conditions.default)
+            .delay()
+    }
+
+    /// Number of bound Transports.
+    pub fn len(&self) -> usize {
+        self.listeners.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Fault {
+    /// Draws the delay of a single frame, None if it gets lost.
+    fn delay(&self) -> Option<Duration> {
+        let mut rng = rand::thread_rng();
+        if rng.gen_bool(self.loss.clamp(0.0, 1.0)) {
+            return None;
+        }
+        let mut delay = self.latency + self.jitter.mul_f64(rng.gen());
+        if rng.gen_bool(self.reorder.clamp(0.0, 1.0)) {
+            delay += REORDER.mul_f64(rng.gen());
+        }
+        Some(delay)
+    }
+}
+
+impl Memory {
+    pub fn new(network: Network) -> Self {
+        Self {
+            network,
+            incoming: None,
+            sender: None,
+            links: Vec::new(),
+            timeouts: Timeouts::default(),
+        }
+    }
+}
+
+impl Transport for Memory {
+    fn bind(&mut self, link: &Link) -> Result<(), Error> {
+        let mut listeners = self.network.listeners.lock().unwrap();
+        let key = link.to_string();
+        if listeners.contains_key(&key) {
+            return Err(Error::Connection(String::from("link is already in use")));
+        }
+        let id = rand::random();
+        // All Links share the incoming connections.
+        let sender = match &self.sender {
+            Some(sender) => sender.clone(),
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                self.incoming = Some(Mutex::new(receiver));
+                self.sender = Some(sender.clone());
+                sender
+            }
+        };
+        listeners.insert(key.clone(), (id, sender));
+        self.links.push((key, id));
+        Ok(())
+    }
+
+    fn accept(&self) -> Option<Box<dyn Stream>> {
+        let incoming = self.incoming.as_ref()?.lock().unwrap();
+        let mut stream = incoming.try_recv().ok()?;
+        stream.timeout = self.timeouts.read;
+        Some(Box::new(stream))
+    }
+
+    /// Fails for the own Links, like a node refusing to connect to
+    /// itself, instead of waiting for a handshake that never comes.
+    /// Links in another partition fail as well.
+    fn connect(&self, link: &Link) -> Result<Box<dyn Stream>, Error> {
+        let key = link.to_string();
+        if self.links.iter().any(|(own, _)| own == &key) {
+            return Err(Error::Connection(String::from("link is the own one")));
+        }
+        let own = self
+            .links
+            .first()
+            .map(|(own, _)| own.clone())
+            .unwrap_or_default();
+        let partitioned = (own.clone(), key.clone());
+        if self
+            .network
+            .conditions
+            .lock()
+            .unwrap()
+            .partitions
+            .contains(&partitioned)
+        {
+            return Err(Error::Connection(String::from("link is partitioned")));
+        }
+        let listeners = self.network.listeners.lock().unwrap();
+        let (_, listener) = listeners
+            .get(&key)
+            .ok_or_else(|| Error::Connection(String::from("link is not bound")))?;
+        let (mut local, mut remote) = MemoryStream::pair();
+        local.route = Some(Route {
+            network: self.network.clone(),
+            local: own.clone(),
+            remote: key.clone(),
+        });
+        remote.route = Some(Route {
+            network: self.network.clone(),
+            local: key,
+            remote: own,
+        });
+        listener
+            .send(remote)
+            .map_err(|_| Error::Connection(String::from("link is not accepting")))?;
+        local.timeout = self.timeouts.read;
+        Ok(Box::new(local))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl Drop for Memory {
+    /// Releases the Links, unless they were bound again in the
+    /// meantime.
+    fn drop(&mut self) {
+        let mut listeners = self.network.listeners.lock().unwrap();
+        for (key, id) in self.links.drain(..) {
+            if listeners.get(&key).map(|(bound, _)| *bound) == Some(id) {
+                listeners.remove(&key);
+            }
+        }
+    }
+}
+
+impl MemoryStream {
+    /// Creates both sides of a new connection outside of any
+    /// Network, frames are never delayed or lost.
+    pub fn pair() -> (Self, Self) {
+        let (lsender, rreceiver) = mpsc::channel();
+        let (rsender, lreceiver) = mpsc::channel();
+        let side = |sender, receiver| Self {
+            sender,
+            receiver,
+            pending: BinaryHeap::new(),
+            route: None,
+            sent: 0,
+            nonblocking: false,
+            timeout: Timeouts::default().read,
+        };
+        (side(lsender, lreceiver), side(rsender, rreceiver))
+    }
+
+    /// Receives the next due frame, respecting the blocking mode.
+    /// Frames that are still delayed are kept until they are due.
+    fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let mut closed = false;
+            loop {
+                match self.receiver.try_recv() {
+                    Ok(frame) => self.pending.push(Reverse(frame)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        closed = true;
+                        break;
+                    }
+                }
+            }
+            let now = Instant::now();
+            let due = self.pending.peek().map(|Reverse(frame)| frame.due);
+            match due {
+                Some(due) if due <= now => {
+                    let Reverse(frame) = self.pending.pop().unwrap();
+                    return Ok(frame.data);
+                }
+                None if closed => {
+                    return Err(Error::Connection(String::from("connection is closed")));
+                }
+                _ if self.nonblocking => {
+                    return Err(Error::Busy(String::from("no frame available")));
+                }
+                _ if now >= deadline => {
+                    return Err(Error::Timeout(String::from("no frame received")));
+                }
+                _ => {}
+            }
+            // Waits for a new frame or the earliest delayed one.
+            let wait = due.map_or(deadline, |due| due.min(deadline)) - now;
+            if closed {
+                thread::sleep(wait);
+            } else if let Ok(frame) = self.receiver.recv_timeout(wait) {
+                self.pending.push(Reverse(frame));
+            }
+        }
+    }
+
+    /// Sends a single frame, unless the Fault of the connection drops
+    /// it.
+    fn send(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        let delay = match &self.route {
+            Some(route) => match route.network.delay(&route.local, &route.remote) {
+                Some(delay) => delay,
+                None => return Ok(()),
+            },
+            None => Duration::default(),
+        };
+        self.sent += 1;
+        let frame = Frame {
+            due: Instant::now() + delay,
+            sequence: self.sent,
+            data,
+        };
+        self.sender
+            .send(frame)
+            .map_err(|_| Error::Connection(String::from("connection is closed")))
+    }
+}
+
+impl Stream for MemoryStream {
+    fn read_wire(&mut self, limit: usize) -> Result<Wire, Error> {
+        let frame = self.recv()?;
+        Wire::bounded(frame.len(), limit)?;
+        Wire::parse(frame.into())
+    }
+
+    fn write_wire(&mut self, wire: &Wire) -> Result<(), Error> {
+        self.send(wire.as_bytes())
+    }
+
+    fn read_node(&mut self) -> Result<Hello, Error> {
+        let frame = self.recv()?;
+        Hello::from_bytes(frame)
+    }
+
+    fn write_node(&mut self, hello: &Hello) -> Result<(), Error> {
+        self.send(hello.as_bytes())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Error> {
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+}
+
+impl Cluster {
+    /// Starts the number of nodes with the default Config.
+    pub async fn new(size: usize) -> Result<Self, Error> {
+        Self::with_config(size, |_, _| {}).await
+    }
+
+    /// Starts the number of nodes, the function can change the Config
+    /// of each node by its index. The Transport is always replaced
+    /// and the Center is generated for the configured difficulty.
+    pub async fn with_config<F>(size: usize, mut configure: F) -> Result<Self, Error>
+    where
+        F: FnMut(usize, &mut Config),
+    {
+        let mut cluster = Self {
+            network: Network::new(),
+            nodes: Vec::new(),
+            centers: Vec::new(),
+        };
+        for index in 0..size {
+            cluster.join_with(|config| configure(index, config)).await?;
+        }
+        Ok(cluster)
+    }
+
+    /// Starts a new node with the default Config and returns its
+    /// index.
+    pub async fn join(&mut self) -> Result<usize, Error> {
+        self.join_with(|_| {}).await
+    }
+
+    /// Starts a new node and returns its index. It bootstraps through
+    /// the first running node and gets added to the routing table of
+    /// all others, which are added to its own. Only returns once the
+    /// bootstrap completed, so no other node connects to it at the
+    /// same time.
+    pub async fn join_with<F>(&mut self, configure: F) -> Result<usize, Error>
+    where
+        F: FnOnce(&mut Config),
+    {
+        let index = self.nodes.len();
+        // The first node bootstraps through itself, which fails right
+        // away.
+        let server = self.running().first().map_or(index, |first| *first);
+        let mut config = Config::new(20, 5, 100, HOST.to_string(), server + 1);
+        configure(&mut config);
+        let timeout = Duration::from_secs(config.timeout);
+        let center = Center::generate(HOST.to_string(), index + 1, config.difficulty);
+        let interface = Interface::builder(config, center.clone())
+            .transport(self.network.transport())
+            .build()
+            .await?;
+        if server != index {
+            let started = Instant::now();
+            while interface.metrics().connections == 0 {
+                if started.elapsed() > timeout {
+                    return Err(Error::Timeout(String::from("bootstrap did not complete")));
+                }
+                tokio::time::sleep(POLL).await;
+            }
+        }
+        let node = Node::new(center.public.clone(), Some(center.link.clone()));
+        let mut known = Vec::new();
+        for (other, existing) in self.centers.iter().zip(&self.nodes) {
+            if let Some(existing) = existing {
+                existing.import_nodes(vec![node.clone()]);
+                known.push(Node::new(other.public.clone(), Some(other.link.clone())));
+            }
+        }
+        interface.import_nodes(known);
+        self.nodes.push(Some(interface));
+        self.centers.push(center);
+        Ok(index)
+    }
+
+    /// Shuts the node down and waits for it to terminate, its Link
+    /// gets released right away. The other nodes only notice once
+    /// their connections to it fail or it stops answering Pings.
+    /// Returns false if it already left.
+    pub async fn leave(&mut self, index: usize) -> bool {
+        match self.nodes.get_mut(index).and_then(Option::take) {
+            Some(interface) => {
+                self.network.release(&self.centers[index].link);
+                interface.shutdown().await;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The Interface of the node.
+    ///
+    /// Panics if the node doesn't exist or already left.
+    pub fn node(&self, index: usize) -> &Interface {
+        self.nodes[index]
+            .as_ref()
+            .expect("node has left the cluster")
+    }
+
+    /// The Center of the node, it is kept after the node left.
+    pub fn center(&self, index: usize) -> &Center {
+        &self.centers[index]
+    }
+
+    pub fn address(&self, index: usize) -> Address {
+        self.centers[index].public.clone()
+    }
+
+    /// Indices of all nodes that haven't left.
+    pub fn running(&self) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|index| self.nodes[*index].is_some())
+            .collect()
+    }
+
+    /// Number of nodes that ever joined, including the ones that
+    /// left.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Applies the Fault to all frames sent from one node to the
+    /// other, see Network::set_fault.
+    pub fn set_fault(&self, from: usize, to: usize, fault: Fault) {
+        let (from, to) = (&self.centers[from].link, &self.centers[to].link);
+        self.network.set_fault(from, to, fault);
+    }
+
+    /// Applies the Fault to all frames between nodes without their
+    /// own.
+    pub fn set_default(&self, fault: Fault) {
+        self.network.set_default(fault);
+    }
+
+    /// Cuts off the nodes of both sides from each other until the
+    /// Cluster is healed, see Network::partition.
+    pub fn partition(&self, left: &[usize], right: &[usize]) {
+        let links = |side: &[usize]| -> Vec<Link> {
+            side.iter()
+                .map(|index| self.centers[*index].link.clone())
+                .collect()
+        };
+        self.network.partition(&links(left), &links(right));
+    }
+
+    /// Removes all partitions, Faults stay in place.
+    pub fn heal(&self) {
+        self.network.heal();
+    }
+
+    /// The Network shared by all nodes, additional nodes can join it
+    /// through its Transport.
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Shuts down all running nodes and waits for them to terminate.
+    pub async fn shutdown(mut self) {
+        for index in self.running() {
+            self.leave(index).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::Features;
+    use crate::message::Message;
+    use crate::transaction::{Class, Transaction};
+
+    #[test]
+    fn test_memory_stream() {
+        let (mut left, mut right) = MemoryStream::pair();
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let wire = Transaction::new(message).to_wire();
+        left.write_wire(&wire).unwrap();
+        assert_eq!(right.read_wire(1024).unwrap(), wire);
+        right.set_nonblocking(true).unwrap();
+        assert!(matches!(right.read_wire(1024), Err(Error::Busy(_))));
+        left.write_wire(&wire).unwrap();
+        assert!(matches!(right.read_wire(8), Err(Error::Protocol(_))));
+        drop(left);
+        assert!(matches!(right.read_wire(1024), Err(Error::Connection(_))));
+    }
+
+    #[test]
+    fn test_memory_transport() {
+        let network = Network::new();
+        let link = Link::new(HOST.to_string(), 1);
+        let mut server = Memory::new(network.clone());
+        server.bind(&link).unwrap();
+        assert!(Memory::new(network.clone()).bind(&link).is_err());
+        assert!(server.accept().is_none());
+        assert!(server.connect(&link).is_err());
+
+        let client = Memory::new(network.clone());
+        assert!(client.connect(&Link::new(HOST.to_string(), 2)).is_err());
+        let _stream = client.connect(&link).unwrap();
+        assert!(server.accept().is_some());
+
+        drop(server);
+        assert!(network.is_empty());
+        assert!(client.connect(&link).is_err());
+    }
+
+    #[test]
+    fn test_memory_fault() {
+        let network = Network::new();
+        let (slink, clink) = (
+            Link::new(HOST.to_string(), 1),
+            Link::new(HOST.to_string(), 2),
+        );
+        let mut server = Memory::new(network.clone());
+        server.bind(&slink).unwrap();
+        let mut client = Memory::new(network.clone());
+        client.bind(&clink).unwrap();
+        let mut outgoing = client.connect(&slink).unwrap();
+        let mut incoming = server.accept().unwrap();
+        incoming.set_nonblocking(true).unwrap();
+
+        // Delayed frames get overtaken by later ones.
+        let latency = Duration::from_millis(50);
+        let slow = Fault {
+            latency,
+            ..Fault::default()
+        };
+        network.set_fault(&clink, &slink, slow);
+        let center = Center::generate(HOST.to_string(), 2, 0);
+        let hello = Hello::new(&center, Features::empty());
+        let sent = Instant::now();
+        outgoing.write_node(&hello).unwrap();
+        network.clear_faults();
+        let wire = Wire::bootstrap(Vec::new());
+        outgoing.write_wire(&wire).unwrap();
+        assert_eq!(incoming.read_wire(1024).unwrap(), wire);
+        assert!(matches!(incoming.read_node(), Err(Error::Busy(_))));
+        incoming.set_nonblocking(false).unwrap();
+        assert_eq!(incoming.read_node().unwrap(), hello);
+        assert!(sent.elapsed() >= latency);
+
+        // Lost frames and partitions only affect their direction.
+        let lossy = Fault {
+            loss: 1.0,
+            ..Fault::default()
+        };
+        network.set_fault(&clink, &slink, lossy);
+        outgoing.write_wire(&wire).unwrap();
+        incoming.write_wire(&wire).unwrap();
+        assert_eq!(outgoing.read_wire(1024).unwrap(), wire);
+        incoming.set_nonblocking(true).unwrap();
+        assert!(matches!(incoming.read_wire(1024), Err(Error::Busy(_))));
+        network.clear_faults();
+
+        network.partition(std::slice::from_ref(&slink), std::slice::from_ref(&clink));
+        assert!(network.is_partitioned(&slink, &clink));
+        assert!(client.connect(&slink).is_err());
+        outgoing.write_wire(&wire).unwrap();
+        assert!(matches!(incoming.read_wire(1024), Err(Error::Busy(_))));
+        network.heal();
+        outgoing.write_wire(&wire).unwrap();
+        assert_eq!(incoming.read_wire(1024).unwrap(), wire);
+        assert!(client.connect(&slink).is_ok());
+    }
+}
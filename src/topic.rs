@@ -8,32 +8,245 @@
 //! user.
 
 use crate::error::Error;
+use crate::group::{Group, GroupKey};
+use crate::inbox::Retention;
+use crate::message::Body;
 use crate::node::Address;
-use crate::transaction::Transaction;
-use crate::util::Channel;
+use crate::payload::Payload;
+use crate::presence::Presence;
+use crate::record::{Policy, Role};
+use crate::trace;
+use crate::transaction::{Class, Transaction};
+use crate::util::{self, Channel, Inlet, Mux, Tag};
+use futures_core::Stream;
+use sodiumoxide::crypto::box_::SecretKey;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::future::poll_fn;
+use std::io::{self, Read, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Time recv_ordered waits for a missing message of a publisher
+/// before it gets skipped and the following ones are released.
+pub const ORDER_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Maximum number of messages of a single publisher recv_ordered
+/// holds back, once more arrive the missing ones get skipped.
+pub const ORDER_LIMIT: usize = 256;
+
+/// The upper half of a sequence number is the epoch of the publisher,
+/// which is chosen randomly whenever the ordered mode gets enabled.
+/// The lower half counts the broadcasts of that epoch.
+const EPOCH_SHIFT: u32 = 32;
+
+/// Size of the chunks a TopicStream sends, smaller writes get
+/// buffered until the chunk is full or the stream gets flushed.
+pub const CHUNK_SIZE: usize = 8192;
 
 /// The main structure for representing Topics in the system. It will
 /// be the main interaction point for the user. Each Topic the user
 /// has will also require a copy of the same Topic in the Handler
 /// Thread. The only difference between the two is the opposing
 /// Channel, with which the two can communicate.
-/// TODO: The thread might require a dedicated struct.
-/// TODO: Add methods for fetching fields.
+///
+/// Topics can be cloned and sent to other threads, all clones share
+/// the same Channel and state. Every message is received by only one
+/// of them, whichever calls recv first, so a Topic can for example be
+/// read in one thread and published on from another. The Topic gets
+/// unsubscribed once the last clone is dropped.
+#[derive(Clone)]
 pub struct Topic {
     /// Throughout the entire system all components have the same
     /// Address type. Each Topic also has a uniqe Address, which can
     /// be generated through any number of ways.
     pub address: Address,
+    /// In order to make it easier to keep the stored Addresses clean
+    /// a copy of the Center Public has to be stored in each topic.
+    public: Address,
+    /// Path the Topic was subscribed through, broadcasts also get
+    /// propagated to the subscribers of all of its subtrees.
+    pub path: Option<TopicPath>,
+    /// How broadcasts reach the subscribers, set while subscribing.
+    pub dissemination: Dissemination,
+    /// Role the Topic was subscribed with, read-only Topics fail to
+    /// broadcast.
+    pub role: Role,
+    /// Secret key of the Center, required for opening the keys of
+    /// encrypted groups.
+    secret: Option<SecretKey>,
+    /// Channel and state of all clones of the Topic.
+    shared: Arc<Shared>,
+}
+
+/// The part of a Topic that is shared between all of its clones.
+struct Shared {
     /// Since each Topic can receive messages individually a dedicated
     /// Channel (mpsc connection) is required.
-    pub channel: Channel<Command>,
+    channel: Channel<Command>,
+    /// The lock is never held while waiting on the Channel.
+    state: Mutex<State>,
+}
+
+/// Everything the Commands from the Switch update, shared between all
+/// clones of a Topic.
+struct State {
     /// List of subscribers
-    pub subscribers: SubscriberBucket,
+    subscribers: SubscriberBucket,
     /// The socket can get overread so a cache is required.
-    pub cache: Vec<Transaction>,
-    /// In order to make it easier to keep the stored Addresses clean
-    /// a copy of the Center Public has to be stored in each topic.
-    public: Address,
+    cache: VecDeque<Transaction>,
+    /// Set once the node hosting the Record confirmed or rejected the
+    /// subscription.
+    ready: Option<Result<(), Error>>,
+    /// Present subscribers with their metadata and the time they were
+    /// last seen, kept up to date by the Switch.
+    presence: HashMap<Address, (Vec<u8>, Instant)>,
+    /// Sequence number of the next broadcast, only set in ordered
+    /// mode.
+    sequence: Option<u64>,
+    /// Sequenced messages waiting to be released in order, grouped
+    /// by their publisher.
+    ordered: HashMap<Address, Sequence>,
+    /// Keys of the encrypted group this Topic is part of, see the
+    /// group module.
+    group: Option<Group>,
+    /// Node whose encrypted group the Topic joined, keys are only
+    /// accepted from it.
+    distributor: Option<Address>,
+}
+
+/// Path style name of a Topic like "game/lobby/42". Each segment gets
+/// hashed together with the Address of its parent, so every prefix of
+/// the path has its own Address. Besides the Topic itself every
+/// prefix also has a subtree Address, its subscribers receive the
+/// messages of the prefix and all of its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPath {
+    segments: Vec<String>,
+}
+
+/// Settings of a Topic that are set once while subscribing. A Policy
+/// can be converted into the options directly.
+#[derive(Debug, Clone, Default)]
+pub struct TopicOptions {
+    /// Policy of the Record, only applied if the Topic doesn't exist
+    /// yet or the user is its owner.
+    pub policy: Option<Policy>,
+    /// Broadcasts carry sequence numbers, see Topic::set_ordered.
+    pub ordered: bool,
+    /// Guarantees for broadcasts of this Topic.
+    pub delivery: Delivery,
+    /// How broadcasts spread among the subscribers.
+    pub dissemination: Dissemination,
+    /// Asks the Record to keep the messages it can't deliver while
+    /// the user is offline, see the inbox module. Only messages of
+    /// publishers in at-least-once mode are kept.
+    pub retention: Option<Retention>,
+    /// Whether the Topic publishes, receives or both. Read-only
+    /// Topics can't broadcast, for publishers Interface::publisher
+    /// is easier to use.
+    pub role: Role,
+}
+
+/// How broadcasts of a Topic reach the subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Delivery {
+    /// Messages are sent to each subscriber directly and might get
+    /// lost.
+    #[default]
+    AtMostOnce,
+    /// Messages are passed through the Record, which retries them
+    /// until each subscriber acknowledged them. Receivers filter out
+    /// duplicates, messages always carry sequence numbers.
+    AtLeastOnce,
+}
+
+/// How broadcasts of a Topic spread among its subscribers. All
+/// subscribers of a Topic should use the same mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dissemination {
+    /// The publisher sends each message to every subscriber.
+    #[default]
+    Direct,
+    /// The publisher only sends each message to a few subscribers,
+    /// which pass it on to the others, see the gossip module. It
+    /// replaces the Delivery of the Topic and messages don't carry
+    /// sequence numbers.
+    Gossip(Gossip),
+}
+
+/// Settings of the gossip mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gossip {
+    /// Number of random subscribers each message gets pushed to and
+    /// each digest of recent messages gets sent to.
+    pub fanout: usize,
+    /// Number of rounds each subscriber advertises a message for.
+    pub rounds: usize,
+}
+
+/// Buffers the sequenced messages of a single publisher until they
+/// can be released in order.
+#[derive(Debug, Default)]
+struct Sequence {
+    /// Epoch of the messages currently being released, a different
+    /// one means the publisher started over.
+    epoch: Option<u64>,
+    /// Sequence number of the next message to release, unknown until
+    /// the first one got released.
+    next: Option<u64>,
+    /// Messages that arrived ahead of time with the time they
+    /// arrived.
+    pending: BTreeMap<u64, (Instant, Transaction)>,
+    /// Messages of an earlier epoch that were still pending when the
+    /// publisher started over, they get released first.
+    previous: VecDeque<(Instant, Transaction)>,
+    /// Start of the current wait for the next message.
+    waiting: Option<Instant>,
+}
+
+/// Continuous data over a Topic, created through Topic::stream. The
+/// publisher writes into it and every subscriber reads the same bytes
+/// in the same order. The data is split into chunks, which are sent
+/// as ordered broadcasts, so the Topic should not be used for other
+/// broadcasts at the same time.
+///
+/// Writing blocks while the Switch falls behind instead of buffering
+/// more and more chunks. Subscribers only take chunks from the Topic
+/// while they read, chunks a slow subscriber can't keep up with get
+/// dropped once its Channel is full and the missing bytes are
+/// skipped after ORDER_TIMEOUT.
+pub struct TopicStream<'a> {
+    topic: &'a mut Topic,
+    /// Written bytes that haven't been sent as a chunk yet.
+    outgoing: Vec<u8>,
+    /// The last received chunk.
+    incoming: Vec<u8>,
+    /// Number of bytes of the incoming chunk that were already read.
+    position: usize,
+}
+
+/// Publishes on a Topic without subscribing to it, created through
+/// Interface::publisher. The node isn't part of the list of
+/// subscribers and receives no broadcasts, the Record only sends it
+/// the list. It can be cloned like a Topic, dropping the last clone
+/// unregisters it.
+#[derive(Clone)]
+pub struct Publisher {
+    topic: Topic,
+}
+
+/// A Topic whose messages get passed to a handler instead of being
+/// read by the user, created by Interface::subscribe_with_handler.
+/// The Topic is owned by a dispatcher task, dropping the Subscription
+/// stops it and unsubscribes from the Topic.
+pub struct Subscription {
+    address: Address,
+    task: JoinHandle<()>,
 }
 
 /// Since each Topic can interact with the Switch a dedicated enum is
@@ -52,16 +265,38 @@ pub enum Command {
     /// the subscriber, this message gets sent for every subscriber in
     /// the list.
     Broadcast(Address, Vec<u8>),
+    /// Broadcast of a Topic in gossip mode, it is only sent once and
+    /// the Switch picks the subscribers.
+    Gossip(Vec<u8>),
+    /// Same as Broadcast but for Topics in ordered mode, the message
+    /// gets sent with the sequence number.
+    Sequenced(Address, u64, Vec<u8>),
+    /// Sends a broadcast of a Topic with a path to the Record of one
+    /// of its subtrees, which passes it on to its subscribers.
+    Propagate(Address, Vec<u8>),
     /// Unlike messages from the user, new updates coming from remote
     /// nodes are passed along as entire Transactions, since the user
     /// might want to use values beyond just the body.
     Message(Transaction),
     /// If the Topic goes out of scope the Switch thread (and the rest
     /// of the network) need to be informed. A custom Drop
-    /// implementation will send the Drop message to the thread. The
-    /// address is not of the Topic but of the subscriber, since each
-    /// one gets it individually.
-    Drop(Address),
+    /// implementation will send the Drop message to the thread, which
+    /// sends a single Unsubscribe to the node hosting the Record and
+    /// retries it until the Record acknowledged it. The Record then
+    /// informs the other subscribers.
+    Drop,
+    /// Sets the metadata the user announces itself with and
+    /// announces it right away, goes from the user to the Switch.
+    Announce(Vec<u8>),
+    /// Current list of present subscribers as known by the Record,
+    /// goes from the Switch to the user.
+    Presence(Vec<Presence>),
+    /// The node hosting the Record confirmed the subscription, its
+    /// subscribers were sent right before.
+    Confirmed,
+    /// The subscription was rejected or never confirmed, goes from
+    /// the Switch to the user.
+    Failed(Error),
 }
 
 /// Wrapper structure to enable faster operations on all stored
@@ -72,13 +307,51 @@ pub struct SubscriberBucket {
     subscribers: Vec<Address>,
 }
 
+/// Collection of Topics created together through
+/// "subscribe_many". Each Topic is still subscribed individually, but
+/// messages of all of them can be received through a single call,
+/// which also returns the Address of the Topic.
+pub struct TopicSet {
+    /// All Topics of the set, each one can still be used on its own.
+    pub topics: Vec<Topic>,
+}
+
+/// Describes a family of Topic Addresses, used for Wildcard
+/// subscriptions.
+#[derive(Clone)]
+pub enum Filter {
+    /// Matches all Addresses starting with the bytes.
+    Prefix(Vec<u8>),
+    /// Matches all Addresses the function returns true for.
+    Predicate(Arc<dyn Fn(&Address) -> bool + Send + Sync>),
+}
+
+/// Receives all messages whose Topic matches the Filter and doesn't
+/// have a dedicated Topic on this node. Unlike a Topic it doesn't
+/// subscribe to anything remotely, it only catches messages that
+/// reach this node anyways, for example through "subscribe_many" or
+/// messages sent directly with a Topic Address. The same struct is
+/// used on the Switch, only the Channel is the opposite one.
+pub struct Wildcard {
+    /// Topic Addresses the Wildcard is responsible for.
+    pub filter: Filter,
+    /// Connection between the user and the Switch.
+    pub channel: Channel<Command>,
+}
+
 /// A simple structure to store a collection of Topics. Since the
 /// normal Topics use a custom implementation of Deref the thread has
 /// to use a different structure, which is identically but doesn't
 /// implement the same methods.
 pub struct TopicBucket {
-    /// List of Topics that will be stored on the Handler Thread.
-    pub topics: Vec<Simple>,
+    /// All Topics that will be stored on the Handler Thread.
+    pub topics: HashMap<Address, Simple>,
+    /// Commands of all Topics arrive through the same queue, tagged
+    /// with the Address of the Topic.
+    mux: Mux<Command>,
+    /// Wildcards get checked in order if there is no matching Topic,
+    /// so the first match receives the message.
+    pub wildcards: Vec<Wildcard>,
 }
 
 /// A simplified version of topics that will be used on the Switch
@@ -89,6 +362,21 @@ pub struct Simple {
     pub address: Address,
     /// Connection to the user Topic.
     pub channel: Channel<Command>,
+    /// Metadata included in every presence announcement, None until
+    /// the user announced itself on the Topic.
+    pub metadata: Option<Vec<u8>>,
+    /// How broadcasts of the Topic get sent.
+    pub delivery: Delivery,
+    /// How broadcasts spread among the subscribers.
+    pub dissemination: Dissemination,
+    /// Limits of the messages the Record keeps while the user is
+    /// offline, sent with the Subscribe.
+    pub retention: Option<Retention>,
+    /// Role the Topic registers with at the Record, sent with the
+    /// Subscribe.
+    pub role: Role,
+    /// Subscribers as known by the Switch, gossip gets sent to them.
+    pub peers: Vec<Address>,
 }
 
 impl Topic {
@@ -104,165 +392,1123 @@ impl Topic {
         subscribers: Vec<Address>,
         public: Address,
     ) -> Self {
+        let state = State {
+            subscribers: SubscriberBucket::new(subscribers),
+            cache: VecDeque::new(),
+            ready: None,
+            presence: HashMap::new(),
+            sequence: None,
+            ordered: HashMap::new(),
+            group: None,
+            distributor: None,
+        };
         Self {
             address,
-            channel,
-            subscribers: SubscriberBucket::new(subscribers),
-            cache: Vec::new(),
             public,
+            path: None,
+            dissemination: Dissemination::Direct,
+            role: Role::Both,
+            secret: None,
+            shared: Arc::new(Shared {
+                channel,
+                state: Mutex::new(state),
+            }),
         }
     }
 
+    /// Sets the secret key of the Center, without it the Topic can't
+    /// join encrypted groups. Topics of the Interface always have it.
+    pub fn with_secret(mut self, secret: SecretKey) -> Self {
+        self.secret = Some(secret);
+        self
+    }
+
+    /// Encrypts all broadcasts of this Topic with the key, only
+    /// members of the group can read them. The key gets sealed to
+    /// every subscriber and a new one is distributed whenever a
+    /// subscriber joins or leaves, received messages are decrypted
+    /// transparently. Should the Switch not keep up it fails with
+    /// Busy, the remaining subscribers only receive the next key.
+    pub fn enable_group_encryption(&mut self, key: GroupKey) -> Result<(), Error> {
+        self.state().group = Some(Group::managed(key.clone()));
+        self.distribute(&key)
+    }
+
+    /// Joins the encrypted group managed by the distributor, usually
+    /// the node that enabled group encryption on the Topic. Keys are
+    /// only accepted once their signature proved that they come from
+    /// it, so it requires signatures. Topics that haven't joined a
+    /// group pass all messages on unchanged.
+    pub fn join_group(&mut self, distributor: Address) {
+        self.state().distributor = Some(distributor);
+    }
+
+    /// Generates and distributes a new key for the group, fails if
+    /// this Topic doesn't manage it.
+    pub fn rekey(&mut self) -> Result<(), Error> {
+        self.rotate()
+    }
+
+    /// Generates the new group key, shared by rekey and the automatic
+    /// rekeying after the subscribers changed.
+    fn rotate(&self) -> Result<(), Error> {
+        let key = match &mut self.state().group {
+            Some(group) if group.is_managed() => group.rekey(),
+            _ => {
+                return Err(Error::Invalid(String::from(
+                    "topic does not manage an encrypted group",
+                )))
+            }
+        };
+        self.distribute(&key)
+    }
+
+    /// Current key of the encrypted group, if the Topic is part of
+    /// one.
+    pub fn group_key(&self) -> Option<GroupKey> {
+        self.state()
+            .group
+            .as_ref()
+            .map(|group| group.current().clone())
+    }
+
     /// Blocking call to receive a Message from a Topic. It will only
     /// return once a Message from the system (usually from another
     /// user) is available or the Channel is unavailable. Since
-    /// commands are sent over the same Channel the recv method takes
-    /// a mutable reference to the Topic and can add / remove
-    /// subscribers.
+    /// commands are sent over the same Channel the recv method also
+    /// adds / removes subscribers.
     ///
     /// (Should it receive a Send message it will simply report an
     /// error.)
+    ///
+    /// Signed Messages are verified by the Switch before they reach
+    /// the Topic, Transaction::verified_source returns their publisher
+    /// (also after group decryption).
     pub fn recv(&mut self) -> Option<Transaction> {
-        if self.cache.len() != 0 {
-            return self.cache.pop();
-        }
-        loop {
-            match self.channel.recv() {
-                Some(m) => match m {
-                    Command::Message(t) => {
-                        if t.source() != self.public {
-                            return Some(t);
-                        }
-                    }
-                    Command::Subscriber(addr) => {
-                        if addr != self.address && addr != self.public {
-                            self.subscribers.add(addr);
-                        }
-                    }
-                    Command::Unsubscriber(addr) => {
-                        self.subscribers.remove(&addr);
-                    }
-                    _ => {
-                        continue;
-                    }
-                },
-                None => {
-                    return None;
-                }
-            }
-        }
+        util::block_on(poll_fn(|cx| self.poll_message(cx)))
     }
 
     /// Behaves the same as "recv" but is non-blocking. Internally it
     /// still uses a loop to filter out non-user messages and will
     /// return on a User message or no message at all.
     pub fn try_recv(&mut self) -> Option<Transaction> {
-        if self.cache.len() != 0 {
-            return self.cache.pop();
+        if let Some(t) = self.cached() {
+            return Some(t);
         }
         loop {
-            match self.channel.try_recv() {
-                Some(m) => match m {
-                    Command::Message(t) => {
-                        if t.source() != self.public {
-                            return Some(t);
-                        }
-                    }
-                    Command::Subscriber(addr) => {
-                        if addr != self.address && addr != self.public {
-                            self.subscribers.add(addr);
-                        }
-                    }
-                    Command::Unsubscriber(addr) => {
-                        self.subscribers.remove(&addr);
-                    }
-                    _ => {
-                        continue;
-                    }
-                },
-                None => {
-                    return None;
-                }
+            let command = self.shared.channel.try_recv()?;
+            if let Some(t) = self.process(command) {
+                return Some(t);
             }
         }
     }
 
+    /// Like recv, but it only blocks the current thread for at most
+    /// the timeout. None is returned if no Message arrived in time or
+    /// the Channel is unavailable, which allows waiting for Messages
+    /// in between other work without polling try_recv.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Transaction> {
+        util::block_on_timeout(poll_fn(|cx| self.poll_message(cx)), timeout).flatten()
+    }
+
     /// The main function for sending Messages to all subscribed
     /// users. It takes in a Vec<u8>, which represents the Body. In
     /// the future this has to be replaced by a Body trait object.
     /// There should also be an option to enable / disable encryption
     /// (but that would require integration with the Transaction &
     /// Wire objects for a dedicated field (or to make encryption
-    /// mandatory (will require more tests))).
+    /// mandatory (will require more tests))). Should the Switch not
+    /// keep up it fails with Busy, the remaining subscribers don't
+    /// receive the message. Bodies of any size are supported, large
+    /// ones get sent in fragments. Topics with a path also send the
+    /// message to the subscribers of all subtrees, those copies are
+    /// not encrypted end-to-end and don't carry sequence numbers.
     pub fn broadcast(&mut self, body: Vec<u8>) -> Result<(), Error> {
+        self.writable()?;
+        for action in self.commands(body) {
+            match self.shared.channel.try_send(action) {
+                Err(Error::Busy(e)) => return Err(Error::Busy(e)),
+                Err(_) => {
+                    trace::error!("channel is unavailable, it is possible the thread crashed.")
+                }
+                Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Broadcasts all bodies in their order, like calling broadcast
+    /// for each of them. With coalescing enabled in the Config the
+    /// resulting small Wires get written together. Should the Switch
+    /// not keep up it fails with Busy, everything after that isn't
+    /// sent.
+    pub fn broadcast_batch(&mut self, bodies: Vec<Vec<u8>>) -> Result<(), Error> {
+        for body in bodies {
+            self.broadcast(body)?;
+        }
+        Ok(())
+    }
+
+    /// Fails for read-only Topics, the Record would reject their
+    /// messages anyways.
+    fn writable(&self) -> Result<(), Error> {
+        match self.role {
+            Role::Subscriber => Err(Error::Invalid(String::from("topic is read-only"))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates the Commands broadcasting the body to all subscribers
+    /// and subtrees. Waiting Commands from the Switch get processed
+    /// first, so the list of subscribers is up to date.
+    fn commands(&self, body: Vec<u8>) -> Vec<Command> {
+        self.update();
+        let mut state = self.state();
+        let body = match &state.group {
+            Some(group) => group.encrypt(&body),
+            None => body,
+        };
+        let sequence = state.sequence;
+        state.sequence = sequence.map(|s| s + 1);
+        // TODO: Ownership issues, reduce clone calls.
+        let mut actions: Vec<Command> = match self.dissemination {
+            Dissemination::Gossip(_) => vec![Command::Gossip(body.clone())],
+            Dissemination::Direct => state
+                .subscribers
+                .subscribers
+                .iter()
+                .map(|sub| match sequence {
+                    Some(s) => Command::Sequenced(sub.clone(), s, body.clone()),
+                    None => Command::Broadcast(sub.clone(), body.clone()),
+                })
+                .collect(),
+        };
+        if let Some(path) = &self.path {
+            for subtree in path.subtrees() {
+                actions.push(Command::Propagate(subtree, body.clone()));
+            }
+        }
+        actions
+    }
+
+    /// Encodes the value and broadcasts it like any other body. Fails
+    /// if the value can't be encoded.
+    pub fn broadcast_typed<T: Payload>(&mut self, value: &T) -> Result<(), Error> {
+        self.broadcast(value.to_bytes()?)
+    }
+
+    /// Blocking call like recv, but the body gets decoded into the
+    /// type. Bodies that aren't a valid value of the type are
+    /// returned as error.
+    pub fn recv_typed<T: Payload>(&mut self) -> Option<Result<T, Error>> {
+        self.recv().map(|t| t.payload())
+    }
+
+    /// Unsubscribes from the Topic right away instead of waiting for
+    /// the last clone to be dropped. The Switch sends an Unsubscribe
+    /// to the node hosting the Record, which informs the other
+    /// subscribers. Afterwards no clone of the Topic receives any
+    /// messages. Fails with Busy if the Switch can't keep up, the
+    /// Topic stays subscribed then.
+    pub fn unsubscribe(&mut self) -> Result<(), Error> {
+        self.shared.unsubscribe()
+    }
+
+    /// Enables or disables the ordered mode for broadcasts of this
+    /// Topic. In ordered mode every message carries a sequence number,
+    /// so subscribers using recv_ordered get them in the order they
+    /// were broadcast. Each time it gets enabled the numbers start
+    /// over in a new epoch. Disabling it on a Topic in at-least-once mode
+    /// also disables the delivery guarantees.
+    pub fn set_ordered(&mut self, ordered: bool) {
+        let mut state = self.state();
+        if ordered {
+            state
+                .sequence
+                .get_or_insert_with(|| u64::from(rand::random::<u32>()) << EPOCH_SHIFT);
+        } else {
+            state.sequence = None;
+        }
+    }
+
+    /// Blocking call like recv, but messages of publishers in ordered
+    /// mode are returned in the order they were broadcast. Messages
+    /// arriving ahead of time are held back until the missing ones
+    /// arrive or ORDER_TIMEOUT passed, then the missing ones are
+    /// skipped. Late messages get dropped, unsequenced messages are
+    /// returned right away. Publishers that started over in a new
+    /// epoch are followed from their first message on.
+    pub fn recv_ordered(&mut self) -> Option<Transaction> {
         loop {
-            match self.channel.try_recv() {
-                Some(m) => match m {
-                    Command::Message(t) => {
-                        if t.source() != self.public {
-                            self.cache.push(t);
-                        }
-                    }
-                    Command::Subscriber(addr) => {
-                        if addr != self.address {
-                            self.subscribers.add(addr);
-                        }
+            if let Some(t) = self.release() {
+                return Some(t);
+            }
+            let remaining = self
+                .state()
+                .ordered
+                .values()
+                .filter_map(|s| s.remaining(ORDER_TIMEOUT))
+                .min();
+            let next = poll_fn(|cx| self.poll_message(cx));
+            let t = match remaining {
+                Some(timeout) => match util::block_on_timeout(next, timeout) {
+                    Some(t) => t?,
+                    None => continue,
+                },
+                None => util::block_on(next)?,
+            };
+            if let Some(t) = self.order(t) {
+                return Some(t);
+            }
+        }
+    }
+
+    /// Announces the presence of the user on this Topic with some
+    /// metadata (like a nickname), which is repeated in every later
+    /// announcement. Other present subscribers receive a message of
+    /// class Joined with the metadata as body and the user as source.
+    /// Topics that never announced themselves are not present and
+    /// don't receive any presence updates.
+    pub fn announce(&mut self, metadata: Vec<u8>) -> Result<(), Error> {
+        self.shared.channel.try_send(Command::Announce(metadata))
+    }
+
+    /// Returns the subscribers currently present on the Topic, the
+    /// list gets updated whenever messages are received. Subscribers
+    /// joining or leaving are also returned by recv as messages of
+    /// class Joined and Left.
+    pub fn presence(&mut self) -> Vec<Presence> {
+        self.update();
+        self.state()
+            .presence
+            .iter()
+            .map(|(address, (metadata, seen))| {
+                Presence::new(address.clone(), metadata.clone(), seen.elapsed())
+            })
+            .collect()
+    }
+
+    /// Turns the Topic into a stream of continuous data, for example
+    /// audio or telemetry. It enables the ordered mode, since the
+    /// chunks have to be read in the order they were written.
+    pub fn stream(&mut self) -> TopicStream<'_> {
+        self.set_ordered(true);
+        TopicStream {
+            topic: self,
+            outgoing: Vec::new(),
+            incoming: Vec::new(),
+            position: 0,
+        }
+    }
+
+    /// Shorthand function to get the Address of a Topic.
+    pub fn address(&self) -> Address {
+        self.address.clone()
+    }
+
+    /// Copy of the current subscribers of the Topic, the list gets
+    /// updated whenever messages are received or broadcast.
+    pub fn subscribers(&self) -> SubscriberBucket {
+        self.state().subscribers.clone()
+    }
+
+    /// Checks if the node hosting the Record confirmed the
+    /// subscription, Commands waiting on the Channel are applied
+    /// first. Before that broadcasts might not reach anyone.
+    pub fn is_ready(&self) -> bool {
+        self.update();
+        matches!(self.state().ready, Some(Ok(())))
+    }
+
+    /// Marks the subscription as failed, unless it was confirmed
+    /// already. wait_ready returns the error from then on. The
+    /// Interface uses it should the subscription not reach the
+    /// Switch.
+    pub fn fail(&self, e: Error) {
+        self.state().ready.get_or_insert(Err(e));
+        self.shared.channel.wake();
+    }
+
+    /// Blocks until the node hosting the Record confirmed the
+    /// subscription, at most for the timeout. Once it returns the
+    /// subscribers the Record knew about are available. It fails if
+    /// the subscription was rejected or no node confirmed it, the same
+    /// is reported through Event::Denied and Event::SubscriptionFailed.
+    /// Messages arriving in the meantime are kept for recv.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error> {
+        let ready = poll_fn(|cx| loop {
+            if let Some(ready) = self.state().ready.clone() {
+                return Poll::Ready(ready);
+            }
+            match self.shared.channel.poll_recv(cx) {
+                Poll::Ready(Some(command)) => {
+                    if let Some(t) = self.process(command) {
+                        self.keep(t);
                     }
-                    Command::Unsubscriber(addr) => {
-                        self.subscribers.remove(&addr);
+                }
+                Poll::Ready(None) => {
+                    return Poll::Ready(Err(Error::Connection(String::from(
+                        "channel is not available",
+                    ))))
+                }
+                // Another clone might have received the confirmation.
+                Poll::Pending => {
+                    return match self.state().ready.clone() {
+                        Some(ready) => Poll::Ready(ready),
+                        None => Poll::Pending,
                     }
-                    _ => {
-                        continue;
+                }
+            }
+        });
+        util::block_on_timeout(ready, timeout).unwrap_or_else(|| {
+            Err(Error::Timeout(String::from(
+                "subscription was not confirmed in time",
+            )))
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap()
+    }
+
+    /// Returns the next message that was read during a broadcast.
+    fn cached(&self) -> Option<Transaction> {
+        self.state().cache.pop_front()
+    }
+
+    /// Applies the waiting Commands from the Switch, messages get
+    /// cached for the next recv.
+    fn update(&self) {
+        while let Some(command) = self.shared.channel.try_recv() {
+            if let Some(t) = self.process(command) {
+                self.keep(t);
+            }
+        }
+    }
+
+    /// Caches a message for the next recv. That could be waiting on
+    /// another clone of the Topic, so it gets woken up.
+    fn keep(&self, t: Transaction) {
+        self.state().cache.push_back(t);
+        self.shared.channel.wake();
+    }
+
+    /// Polls for the next message from another user, None once the
+    /// Switch is no longer available. The cache is checked again
+    /// after the waker got registered, so a message cached by another
+    /// clone in between doesn't get missed.
+    fn poll_message(&self, cx: &mut Context<'_>) -> Poll<Option<Transaction>> {
+        if let Some(t) = self.cached() {
+            return Poll::Ready(Some(t));
+        }
+        loop {
+            match self.shared.channel.poll_recv(cx) {
+                Poll::Ready(Some(command)) => {
+                    if let Some(t) = self.process(command) {
+                        return Poll::Ready(Some(t));
                     }
-                },
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {
+                    return self
+                        .cached()
+                        .map_or(Poll::Pending, |t| Poll::Ready(Some(t)))
+                }
+            }
+        }
+    }
+
+    /// Buffers sequenced messages for recv_ordered, others get
+    /// returned.
+    fn order(&self, t: Transaction) -> Option<Transaction> {
+        match t.message.sequence {
+            Some(sequence) => {
+                let source = t.source();
+                self.state()
+                    .ordered
+                    .entry(source)
+                    .or_default()
+                    .insert(sequence, t);
+                None
+            }
+            None => Some(t),
+        }
+    }
+
+    /// Returns the next message of any publisher that is ready to be
+    /// released, the one that arrived first if several are.
+    fn release(&self) -> Option<Transaction> {
+        self.state()
+            .ordered
+            .values_mut()
+            .filter_map(|s| s.ready(ORDER_TIMEOUT).map(|arrived| (arrived, s)))
+            .min_by_key(|(arrived, _)| *arrived)
+            .and_then(|(_, s)| s.release(ORDER_TIMEOUT))
+    }
+
+    /// Seals the key to every subscriber.
+    fn distribute(&self, key: &GroupKey) -> Result<(), Error> {
+        let subscribers = self.state().subscribers.subscribers.clone();
+        for sub in subscribers.iter() {
+            let action = Command::Broadcast(sub.clone(), key.seal(sub));
+            match self.shared.channel.try_send(action) {
+                Err(Error::Busy(e)) => return Err(Error::Busy(e)),
+                Err(_) => {
+                    trace::error!("channel is unavailable, it is possible the thread crashed.")
+                }
+                Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Distributes a new key after the subscribers changed, if this
+    /// Topic manages an encrypted group.
+    fn membership_changed(&self) {
+        if !self.state().group.as_ref().is_some_and(Group::is_managed) {
+            return;
+        }
+        if let Err(e) = self.rotate() {
+            trace::warn!("unable to distribute new group key: {}", e);
+        }
+    }
+
+    /// Consumes keys of encrypted groups and decrypts the bodies of
+    /// their messages. Messages that can't be decrypted get dropped,
+    /// Topics outside of a group pass everything on unchanged. Keys
+    /// are only accepted from the verified source the Topic joined
+    /// the group of.
+    fn unseal(&self, mut t: Transaction) -> Option<Transaction> {
+        let mut state = self.state();
+        if state.group.is_none() && state.distributor.is_none() {
+            return Some(t);
+        }
+        let body = t.message.body.as_slice();
+        let key = self
+            .secret
+            .as_ref()
+            .and_then(|secret| GroupKey::open(body, &self.public, secret));
+        if let Some(key) = key {
+            let source = match t.verified_source() {
+                Some(source) => source,
                 None => {
-                    break;
+                    trace::warn!("dropping group key of an unverified source");
+                    return None;
                 }
+            };
+            let joined = state.distributor.as_ref() == Some(&source);
+            match &mut state.group {
+                Some(group) => {
+                    if let Err(e) = group.accept(&source, key) {
+                        trace::warn!("dropping group key: {}", e);
+                    }
+                }
+                None if joined => state.group = Some(Group::member(source, key)),
+                None => trace::warn!("dropping group key from an unknown distributor"),
             }
+            return None;
         }
-        for sub in &self.subscribers.subscribers {
-            // TODO: Ownership issues, reduce clone calls.
-            let action = Command::Broadcast(sub.clone(), body.clone());
-            let e = self.channel.send(action);
-            if e.is_err() {
-                log::error!("channel is unavailable, it is possible the thread crashed.")
+        let group = match &state.group {
+            Some(group) => group,
+            None => return Some(t),
+        };
+        match group.decrypt(body) {
+            Ok(body) => {
+                t.message.body = Body::new(body);
+                t.set_encrypted(true);
+                Some(t)
+            }
+            Err(e) => {
+                trace::warn!("dropping group message: {}", e);
+                None
             }
         }
-        return Ok(());
     }
 
-    /// In the future this should be replaced by an automatic Drop
-    /// implementation, currently a manual "unsubscribe" function is
-    /// required to inform other users about the change. It simply
-    /// sends an Unsubscribe action to each subscriber.
-    pub fn unsubscribe(&mut self) {
-        for sub in &self.subscribers.subscribers {
-            let action = Command::Drop(sub.clone());
-            let e = self.channel.send(action);
-            if e.is_err() {
-                log::error!("channel is unavailable, it is possible the thread crashed.")
+    /// Applies a Command from the Switch to the Topic, only messages
+    /// from other users get returned.
+    fn process(&self, command: Command) -> Option<Transaction> {
+        match command {
+            // Own messages only arrive if they were sent to this node
+            // on purpose, otherwise they are echoes.
+            Command::Message(t) if t.source() != self.public || t.is_loopback() => {
+                let t = match t.class() {
+                    Class::Action => self.unseal(t)?,
+                    _ => t,
+                };
+                let _entered = trace::transaction(&t).entered();
+                trace::debug!("passing message on to the user");
+                match t.class() {
+                    Class::Joined => {
+                        let seen = Instant::now();
+                        let metadata = t.message.body.as_bytes();
+                        self.state().presence.insert(t.source(), (metadata, seen));
+                    }
+                    Class::Left => {
+                        self.state().presence.remove(&t.source());
+                    }
+                    _ => {}
+                }
+                return Some(t);
+            }
+            Command::Confirmed => {
+                self.state().ready = Some(Ok(()));
+                self.shared.channel.wake();
+            }
+            Command::Failed(e) => {
+                self.state().ready.get_or_insert(Err(e));
+                self.shared.channel.wake();
+            }
+            Command::Presence(list) => {
+                let now = Instant::now();
+                self.state().presence = list
+                    .into_iter()
+                    .map(|p| {
+                        let seen = now.checked_sub(p.age).unwrap_or(now);
+                        (p.address, (p.metadata, seen))
+                    })
+                    .collect();
             }
+            Command::Subscriber(addr) if addr != self.address && addr != self.public => {
+                let mut state = self.state();
+                if state.subscribers.get(&addr).is_none() {
+                    state.subscribers.add(addr);
+                    drop(state);
+                    self.membership_changed();
+                }
+            }
+            Command::Unsubscriber(addr) => {
+                let mut state = self.state();
+                if state.subscribers.get(&addr).is_some() {
+                    state.subscribers.remove(&addr);
+                    drop(state);
+                    self.membership_changed();
+                }
+            }
+            _ => {}
         }
+        None
     }
+}
 
-    /// Shorthand function to get the Address of a Topic.
+impl TopicPath {
+    /// Parses a path of segments separated by slashes, fails if any
+    /// of them is empty.
+    pub fn new(path: &str) -> Result<Self, Error> {
+        let segments: Vec<String> = path.split('/').map(String::from).collect();
+        if segments.iter().any(|s| s.is_empty()) {
+            return Err(Error::Invalid(String::from("topic path is not valid")));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn segments(&self) -> &[String] {
+        &self.segments
+    }
+
+    /// Path without its last segment, None for top level paths.
+    pub fn parent(&self) -> Option<Self> {
+        match self.segments.len() {
+            1 => None,
+            n => Some(Self {
+                segments: self.segments[..n - 1].to_vec(),
+            }),
+        }
+    }
+
+    /// Checks if the path is the same as or a child of the other one.
+    pub fn starts_with(&self, other: &TopicPath) -> bool {
+        self.segments.starts_with(&other.segments)
+    }
+
+    /// Addresses of every prefix of the path, starting with the top
+    /// level one and ending with the Address of the path itself.
+    pub fn chain(&self) -> Vec<Address> {
+        let mut chain: Vec<Address> = Vec::new();
+        for segment in &self.segments {
+            let mut hasher = blake3::Hasher::new();
+            if let Some(parent) = chain.last() {
+                hasher.update(&parent.as_bytes());
+            }
+            hasher.update(segment.as_bytes());
+            chain.push(Address::from_bytes(*hasher.finalize().as_bytes()));
+        }
+        chain
+    }
+
+    /// Address of the Topic itself.
+    pub fn address(&self) -> Address {
+        self.chain().pop().unwrap()
+    }
+
+    /// Address of the subtree starting at this path.
+    pub fn subtree(&self) -> Address {
+        TopicPath::subtree_of(&self.address())
+    }
+
+    /// Subtree Addresses of every prefix, messages published on the
+    /// path get propagated to all of them.
+    pub fn subtrees(&self) -> Vec<Address> {
+        self.chain().iter().map(TopicPath::subtree_of).collect()
+    }
+
+    fn subtree_of(address: &Address) -> Address {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&address.as_bytes());
+        hasher.update(b"/#");
+        Address::from_bytes(*hasher.finalize().as_bytes())
+    }
+}
+
+impl fmt::Display for TopicPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.segments.join("/"))
+    }
+}
+
+impl From<Policy> for TopicOptions {
+    fn from(policy: Policy) -> Self {
+        Self {
+            policy: Some(policy),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<Role> for TopicOptions {
+    fn from(role: Role) -> Self {
+        Self {
+            role,
+            ..Self::default()
+        }
+    }
+}
+
+impl TopicOptions {
+    /// Options with the given dissemination mode, for example
+    /// `TopicOptions::dissemination(Gossip { fanout: 3, rounds: 4 })`.
+    pub fn dissemination(dissemination: impl Into<Dissemination>) -> Self {
+        Self {
+            dissemination: dissemination.into(),
+            ..Self::default()
+        }
+    }
+}
+
+impl From<Gossip> for Dissemination {
+    fn from(gossip: Gossip) -> Self {
+        Self::Gossip(gossip)
+    }
+}
+
+impl Sequence {
+    /// Buffers the message unless it is older than the ones already
+    /// released. A new epoch means the publisher started over, the
+    /// messages still pending from the old one are released before
+    /// the new ones.
+    fn insert(&mut self, sequence: u64, t: Transaction) {
+        let epoch = sequence >> EPOCH_SHIFT;
+        if self.epoch.is_some_and(|e| e != epoch) {
+            let pending = std::mem::take(&mut self.pending);
+            self.previous.extend(pending.into_values());
+            self.next = None;
+            self.waiting = None;
+        }
+        self.epoch = Some(epoch);
+        if self.next.is_some_and(|next| sequence < next) {
+            return;
+        }
+        self.waiting.get_or_insert_with(Instant::now);
+        self.pending.insert(sequence, (Instant::now(), t));
+    }
+
+    /// Returns the time the next message arrived if it can be
+    /// released, which is the case if it is the expected one, the
+    /// wait for the expected one exceeded the timeout or too many
+    /// messages are held back. The first message of an epoch is
+    /// expected to have the counter 0.
+    fn ready(&self, timeout: Duration) -> Option<Instant> {
+        if let Some((arrived, _)) = self.previous.front() {
+            return Some(*arrived);
+        }
+        let (&first, (arrived, _)) = self.pending.first_key_value()?;
+        let expected = self.next.unwrap_or(first >> EPOCH_SHIFT << EPOCH_SHIFT);
+        let waited = self.waiting.is_some_and(|w| w.elapsed() >= timeout);
+        let full = self.pending.len() > ORDER_LIMIT;
+        if first == expected || waited || full {
+            Some(*arrived)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the next message if it is ready, see ready.
+    fn release(&mut self, timeout: Duration) -> Option<Transaction> {
+        self.ready(timeout)?;
+        if let Some((_, t)) = self.previous.pop_front() {
+            return Some(t);
+        }
+        let (first, (_, t)) = self.pending.pop_first()?;
+        self.next = Some(first + 1);
+        self.waiting = Some(Instant::now()).filter(|_| !self.pending.is_empty());
+        Some(t)
+    }
+
+    /// Time left until the next message gets released without the
+    /// missing ones, None if nothing is pending.
+    fn remaining(&self, timeout: Duration) -> Option<Duration> {
+        if !self.previous.is_empty() {
+            return Some(Duration::ZERO);
+        }
+        let waiting = self.waiting.filter(|_| !self.pending.is_empty())?;
+        Some(timeout.saturating_sub(waiting.elapsed()))
+    }
+}
+
+impl TopicStream<'_> {
+    /// Sends the chunk as an ordered broadcast. Unlike broadcast it
+    /// waits for the Switch instead of failing with Busy.
+    fn send(&mut self, chunk: Vec<u8>) -> io::Result<()> {
+        self.topic
+            .writable()
+            .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e.to_string()))?;
+        for command in self.topic.commands(chunk) {
+            self.topic
+                .shared
+                .channel
+                .send(command)
+                .map_err(|e| io::Error::new(io::ErrorKind::BrokenPipe, e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for TopicStream<'_> {
+    /// Buffers the bytes and sends every full chunk.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.outgoing.extend_from_slice(data);
+        while self.outgoing.len() >= CHUNK_SIZE {
+            let rest = self.outgoing.split_off(CHUNK_SIZE);
+            let chunk = std::mem::replace(&mut self.outgoing, rest);
+            self.send(chunk)?;
+        }
+        Ok(data.len())
+    }
+
+    /// Sends the buffered bytes right away, even if the chunk isn't
+    /// full.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.outgoing.is_empty() {
+            let chunk = std::mem::take(&mut self.outgoing);
+            self.send(chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for TopicStream<'_> {
+    /// Reads from the current chunk or waits for the next one. It
+    /// only returns zero once the system shut down.
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.incoming.len() {
+            match self.next() {
+                Some(chunk) => {
+                    self.incoming = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let length = buffer.len().min(self.incoming.len() - self.position);
+        buffer[..length].copy_from_slice(&self.incoming[self.position..][..length]);
+        self.position += length;
+        Ok(length)
+    }
+}
+
+impl Iterator for TopicStream<'_> {
+    type Item = Vec<u8>;
+
+    /// Blocks until the next chunk arrives, other messages on the
+    /// Topic like presence updates are skipped.
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let t = self.topic.recv_ordered()?;
+            if t.class() == Class::Action && !t.message.body.as_bytes().is_empty() {
+                return Some(t.message.body.as_bytes());
+            }
+        }
+    }
+}
+
+impl Drop for TopicStream<'_> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl Stream for Topic {
+    type Item = Transaction;
+
+    /// Same as recv, but it can be awaited and combined with other
+    /// Streams. Subscriber updates get applied along the way and the
+    /// Stream ends once the Switch is no longer available.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Transaction>> {
+        self.poll_message(cx)
+    }
+}
+
+impl Shared {
+    /// Asks the Switch to unsubscribe from the Topic.
+    fn unsubscribe(&self) -> Result<(), Error> {
+        self.channel.try_send(Command::Drop)
+    }
+}
+
+impl Drop for Shared {
+    /// Only runs once the last clone of the Topic is dropped. Should
+    /// the Drop not fit into the Channel the Switch still unsubscribes
+    /// once it notices the closed Channel.
+    fn drop(&mut self) {
+        let _ = self.channel.try_send(Command::Drop);
+    }
+}
+
+impl Publisher {
+    /// Wraps a Topic that was subscribed as publisher.
+    pub fn new(topic: Topic) -> Self {
+        Self { topic }
+    }
+
+    /// Sends the body to all subscribers, see Topic::broadcast.
+    pub fn broadcast(&mut self, body: Vec<u8>) -> Result<(), Error> {
+        self.topic.broadcast(body)
+    }
+
+    /// Encodes the value and broadcasts it, see
+    /// Topic::broadcast_typed.
+    pub fn broadcast_typed<T: Payload>(&mut self, value: &T) -> Result<(), Error> {
+        self.topic.broadcast_typed(value)
+    }
+
+    /// Address of the Topic the Publisher publishes on.
+    pub fn address(&self) -> Address {
+        self.topic.address()
+    }
+
+    /// Copy of the subscribers the Record sent so far, updates
+    /// waiting on the Channel are applied first.
+    pub fn subscribers(&self) -> SubscriberBucket {
+        self.topic.update();
+        self.topic.subscribers()
+    }
+
+    /// Checks if the Record confirmed the Publisher, see
+    /// Topic::is_ready.
+    pub fn is_ready(&self) -> bool {
+        self.topic.is_ready()
+    }
+
+    /// Blocks until the Record confirmed the Publisher, see
+    /// Topic::wait_ready.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error> {
+        self.topic.wait_ready(timeout)
+    }
+}
+
+impl Subscription {
+    /// Spawns the dispatcher task onto the current tokio runtime, it
+    /// calls the handler for every message of the Topic. A panicking
+    /// handler only loses the message it was called with, the
+    /// following ones are still dispatched. The handler runs on the
+    /// runtime, so it must not block for long.
+    pub fn new<F>(mut topic: Topic, mut handler: F) -> Self
+    where
+        F: FnMut(Transaction) + Send + 'static,
+    {
+        let address = topic.address();
+        let task = tokio::spawn(async move {
+            while let Some(t) = poll_fn(|cx| Pin::new(&mut topic).poll_next(cx)).await {
+                if panic::catch_unwind(AssertUnwindSafe(|| handler(t))).is_err() {
+                    trace::error!("handler of topic {} panicked", topic.address());
+                }
+            }
+        });
+        Self { address, task }
+    }
+
+    /// Address of the Topic the handler is subscribed to.
     pub fn address(&self) -> Address {
         self.address.clone()
     }
+
+    /// The dispatcher stops once the Switch is no longer available.
+    pub fn is_active(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    /// Stops the dispatcher and unsubscribes from the Topic, same as
+    /// dropping the Subscription.
+    pub fn unsubscribe(self) {}
 }
 
-impl Drop for Topic {
+impl Drop for Subscription {
     fn drop(&mut self) {
-        for sub in self.subscribers.clone().into_iter() {
-            let command = Command::Drop(sub);
-            let _ = self.channel.send(command);
+        self.task.abort();
+    }
+}
+
+impl TopicSet {
+    pub fn new(topics: Vec<Topic>) -> Self {
+        Self { topics }
+    }
+
+    /// Waits until any of the Topics receives a message from another
+    /// user and returns it together with the Address of the Topic.
+    /// Subscriber updates get applied to the Topics along the way.
+    /// Should any of the Channels be closed None is returned, since
+    /// that means the Switch is no longer available.
+    pub async fn recv(&mut self) -> Option<(Address, Transaction)> {
+        if let Some(message) = self.cached() {
+            return Some(message);
+        }
+        poll_fn(|cx| {
+            for topic in self.topics.iter() {
+                if let Poll::Ready(t) = topic.poll_message(cx) {
+                    return Poll::Ready(t.map(|t| (topic.address(), t)));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Non-blocking version of recv, it returns None if none of the
+    /// Topics has a message available.
+    pub fn try_recv(&mut self) -> Option<(Address, Transaction)> {
+        if let Some(message) = self.cached() {
+            return Some(message);
+        }
+        for topic in self.topics.iter_mut() {
+            if let Some(t) = topic.try_recv() {
+                return Some((topic.address(), t));
+            }
+        }
+        None
+    }
+
+    /// Returns the Topic with the given Address, for example to
+    /// broadcast a message on it.
+    pub fn get(&mut self, address: &Address) -> Option<&mut Topic> {
+        self.topics
+            .iter_mut()
+            .find(|topic| &topic.address == address)
+    }
+
+    /// Addresses of all Topics in the set.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.topics.iter().map(|topic| topic.address()).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.topics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Messages that were read during a broadcast are stored in the
+    /// cache of the Topic, they have to be returned first.
+    fn cached(&mut self) -> Option<(Address, Transaction)> {
+        for topic in self.topics.iter() {
+            if let Some(t) = topic.cached() {
+                return Some((topic.address(), t));
+            }
+        }
+        None
+    }
+}
+
+impl Filter {
+    /// Shorthand for creating a Prefix Filter.
+    pub fn prefix(bytes: &[u8]) -> Self {
+        Self::Prefix(bytes.to_vec())
+    }
+
+    /// Shorthand for creating a Predicate Filter from any function.
+    pub fn predicate<F>(function: F) -> Self
+    where
+        F: Fn(&Address) -> bool + Send + Sync + 'static,
+    {
+        Self::Predicate(Arc::new(function))
+    }
+
+    /// Checks if the Address belongs to the family of the Filter.
+    pub fn matches(&self, address: &Address) -> bool {
+        match self {
+            Self::Prefix(prefix) => address.as_bytes().starts_with(prefix),
+            Self::Predicate(function) => function(address),
+        }
+    }
+}
+
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Prefix(prefix) => write!(f, "Prefix({:?})", prefix),
+            Self::Predicate(_) => write!(f, "Predicate"),
+        }
+    }
+}
+
+impl Wildcard {
+    pub fn new(filter: Filter, channel: Channel<Command>) -> Self {
+        Self { filter, channel }
+    }
+
+    /// Waits for the next message matching the Filter and returns it
+    /// together with its Topic Address. None means the Switch is no
+    /// longer available.
+    pub async fn recv(&self) -> Option<(Address, Transaction)> {
+        loop {
+            if let Command::Message(t) = self.channel.recv_async().await? {
+                return Some((t.topic(), t));
+            }
+        }
+    }
+
+    /// Non-blocking version of recv.
+    pub fn try_recv(&self) -> Option<(Address, Transaction)> {
+        loop {
+            if let Command::Message(t) = self.channel.try_recv()? {
+                return Some((t.topic(), t));
+            }
         }
     }
 }
 
 impl Simple {
     pub fn new(address: Address, channel: Channel<Command>) -> Self {
-        Self { address, channel }
+        Self {
+            address,
+            channel,
+            metadata: None,
+            delivery: Delivery::AtMostOnce,
+            dissemination: Dissemination::Direct,
+            retention: None,
+            role: Role::Both,
+            peers: Vec::new(),
+        }
+    }
+
+    /// Passes a new subscriber on to the Topic and keeps track of it
+    /// as a peer.
+    pub fn subscriber(&mut self, address: Address) {
+        if !self.peers.contains(&address) {
+            self.peers.push(address.clone());
+        }
+        let _ = self.channel.try_send(Command::Subscriber(address));
     }
 }
 
@@ -300,11 +1546,8 @@ impl SubscriberBucket {
     /// come in.
     pub fn remove(&mut self, target: &Address) {
         let index = self.subscribers.iter().position(|e| e == target);
-        match index {
-            Some(i) => {
-                self.subscribers.remove(i);
-            }
-            None => {}
+        if let Some(i) = index {
+            self.subscribers.remove(i);
         }
     }
 
@@ -316,6 +1559,10 @@ impl SubscriberBucket {
     pub fn len(&self) -> usize {
         self.subscribers.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscribers.is_empty()
+    }
 }
 
 impl Iterator for SubscriberBucket {
@@ -327,66 +1574,99 @@ impl Iterator for SubscriberBucket {
 }
 
 impl TopicBucket {
-    pub fn new() -> Self {
-        Self { topics: Vec::new() }
+    /// Creates an empty TopicBucket, at most capacity Commands of all
+    /// Topics combined can wait for the Switch.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            topics: HashMap::new(),
+            mux: Mux::new(capacity),
+            wildcards: Vec::new(),
+        }
+    }
+
+    /// Returns the Inlet for creating the Channels of new Topics,
+    /// their Commands arrive through recv.
+    pub fn inlet(&self) -> Inlet<Command> {
+        self.mux.inlet()
     }
 
     /// Only adds a Simple if it doesn't exist yet, preventing
-    /// duplicates.
+    /// duplicates. A Simple whose user Topic has already been dropped
+    /// gets replaced.
     pub fn add(&mut self, simple: Simple) {
-        // strange namespace issues
-        if TopicBucket::find(&self, &simple.address).is_none() {
-            self.topics.push(simple);
+        match self.topics.get(&simple.address) {
+            Some(existing) if !existing.channel.is_closed() => (),
+            _ => {
+                self.topics.insert(simple.address.clone(), simple);
+            }
         }
     }
 
     /// Normal (custom) Bucket function for finding a Simple.
     pub fn find(&self, search: &Address) -> Option<&Simple> {
-        let index = self.topics.iter().position(|e| &e.address == search);
-        match index {
-            Some(i) => self.topics.get(i),
-            None => None,
-        }
+        self.topics.get(search)
     }
 
     /// Normal (custom) Bucket function for finding a mut Simple.
     pub fn find_mut(&mut self, search: &Address) -> Option<&mut Simple> {
-        let index = self.topics.iter().position(|e| &e.address == search);
-        match index {
-            Some(i) => self.topics.get_mut(i),
-            None => None,
-        }
+        self.topics.get_mut(search)
     }
 
     /// Removes a Simple from the Bucket but won't fail if it doesn't
     /// exist.
     pub fn remove(&mut self, target: &Address) {
-        let index = self.topics.iter().position(|e| &e.address == target);
-        match index {
-            Some(i) => {
-                self.topics.remove(i);
-            }
-            None => {}
-        }
+        self.topics.remove(target);
+    }
+
+    /// Adds a new Wildcard after the existing ones. Wildcards whose
+    /// user side has been dropped get removed at the same time.
+    pub fn add_wildcard(&mut self, wildcard: Wildcard) {
+        self.wildcards.retain(|w| !w.channel.is_closed());
+        self.wildcards.push(wildcard);
+    }
+
+    /// Returns the first Wildcard matching the Address that is still
+    /// in use.
+    pub fn wildcard(&self, address: &Address) -> Option<&Wildcard> {
+        self.wildcards
+            .iter()
+            .find(|w| !w.channel.is_closed() && w.filter.matches(address))
+    }
+
+    /// Addresses of all stored Simples.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.topics.keys().cloned().collect()
     }
 
     /// Checks if an item exists in the list.
     pub fn is_local(&self, query: &Address) -> bool {
-        match self.find(query) {
-            Some(_) => true,
-            None => false,
-        }
+        self.find(query).is_some()
     }
 
     pub fn len(&self) -> usize {
         self.topics.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.topics.is_empty()
+    }
+
+    /// Waits until any of the stored Topics sends a Command and
+    /// returns it together with the peer and the Address of the
+    /// Topic. A None Command means the Channel is closed and the user
+    /// Topic no longer exists. Without any Topics the future never
+    /// completes, so it can safely be used in a select. The number of
+    /// Topics doesn't affect the cost of waiting.
+    pub async fn recv(&self) -> (Tag, Option<Command>) {
+        self.mux.recv().await
+    }
 }
 
 impl Iterator for TopicBucket {
     type Item = Simple;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.topics.pop()
+        let address = self.topics.keys().next()?.clone();
+        self.topics.remove(&address)
     }
 }
@@ -0,0 +1,254 @@
+//! # Trace
+//!
+//! All diagnostics of the crate go through this module. With the
+//! default `tracing` feature every Transaction gets a span carrying
+//! its UUID, source and class, which the Listener, the Switch and the
+//! Topics all create the same way. Operators can follow a single
+//! message through the system by filtering for its UUID. Routing
+//! decisions get their own spans nested below it.
+//!
+//! Without the feature the same macros are provided by `log` and the
+//! spans do nothing, so minimal builds don't need any additional
+//! dependencies. Even with the feature events are passed on to `log`
+//! as long as no `tracing` subscriber is installed.
+//!
+//! Log lines are meant for reading, not for telemetry. A TraceSink
+//! set on the InterfaceBuilder receives a structured Verdict every
+//! time a message gets dropped, forwarded or delivered, with a Reason
+//! for every drop. Verdicts are only created if a sink is set.
+
+use crate::node::Address;
+use crate::transaction::{Class, Transaction, Wire};
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+#[cfg(feature = "tracing")]
+pub use tracing::{debug, error, info, trace, warn, Span};
+
+#[cfg(not(feature = "tracing"))]
+pub use log::{debug, error, info, trace, warn};
+
+/// Stand-in for spans if the `tracing` feature is disabled.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub struct Span;
+
+/// Returned when entering a disabled Span, does nothing on drop.
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug)]
+pub struct EnteredSpan;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    /// Enters the Span until the returned guard gets dropped.
+    pub fn entered(self) -> EnteredSpan {
+        EnteredSpan
+    }
+}
+
+/// Span of a single Transaction, identified by its UUID.
+#[cfg(feature = "tracing")]
+pub fn transaction(t: &Transaction) -> Span {
+    tracing::info_span!(
+        "transaction",
+        uuid = %t.uuid,
+        source = %t.source(),
+        class = ?t.class(),
+    )
+}
+
+/// Span of a single Transaction, identified by its UUID.
+#[cfg(not(feature = "tracing"))]
+pub fn transaction(_t: &Transaction) -> Span {
+    Span
+}
+
+/// Span of a routing decision, the reason describes which path the
+/// Transaction takes.
+#[cfg(feature = "tracing")]
+pub fn route(reason: &'static str, target: &Address) -> Span {
+    tracing::debug_span!("route", reason, target = %target)
+}
+
+/// Span of a routing decision, the reason describes which path the
+/// Transaction takes.
+#[cfg(not(feature = "tracing"))]
+pub fn route(_reason: &'static str, _target: &Address) -> Span {
+    Span
+}
+
+/// Receives the Verdict of every message. Like a MetricsSink it gets
+/// called from the I/O threads and the Switch task, so it should
+/// never block.
+pub trait TraceSink: Send + Sync {
+    fn decide(&self, verdict: &Verdict);
+}
+
+/// What happened to a message on this node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// The message was passed on to the user or a Topic.
+    Delivered,
+    /// The message was passed on towards its target.
+    Forwarded,
+    /// The message was dropped.
+    Dropped(Reason),
+}
+
+/// Why a message was dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reason {
+    /// The Wire was already received before.
+    CacheHit,
+    /// The signature of the Wire is missing or not valid.
+    Signature,
+    /// The Wire could not be decompressed or reassembled.
+    Malformed,
+    /// No known node is closer to the target than this one.
+    NoRoute,
+    /// The Transaction ran out of hops.
+    Expired,
+    /// A Channel on the way was full or closed.
+    Busy,
+    /// The source is not allowed to publish on the Topic.
+    Unauthorized,
+    /// The message could not be delivered, for example because the
+    /// Topic is unknown or the body could not be decrypted.
+    Undeliverable,
+    /// The Class of the message is not handled for this target.
+    Class,
+}
+
+/// A single decision about a message together with the details
+/// identifying it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Verdict {
+    pub uuid: Uuid,
+    /// None if the Class of a Wire isn't valid.
+    pub class: Option<Class>,
+    pub source: Address,
+    pub target: Address,
+    pub decision: Decision,
+}
+
+/// Shared handle passing Verdicts on to the TraceSink, cloning it is
+/// cheap. Without a sink it does nothing.
+#[derive(Clone, Default)]
+pub struct Tracer(Option<Arc<dyn TraceSink>>);
+
+/// Details of a Transaction taken before it gets moved, its Verdict
+/// gets passed on once the decision is known.
+pub struct Pending(Option<(Arc<dyn TraceSink>, Verdict)>);
+
+impl Verdict {
+    pub fn transaction(t: &Transaction, decision: Decision) -> Self {
+        Self {
+            uuid: t.uuid,
+            class: Some(t.class()),
+            source: t.source(),
+            target: t.target(),
+            decision,
+        }
+    }
+
+    pub fn wire(wire: &Wire, decision: Decision) -> Self {
+        Self {
+            uuid: Uuid::from_bytes(wire.uuid),
+            class: wire.class(),
+            source: wire.source(),
+            target: wire.target(),
+            decision,
+        }
+    }
+}
+
+impl Tracer {
+    pub fn new(sink: Arc<dyn TraceSink>) -> Self {
+        Self(Some(sink))
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Passes the decision about the Transaction on to the sink.
+    pub fn transaction(&self, t: &Transaction, decision: Decision) {
+        if let Some(sink) = &self.0 {
+            sink.decide(&Verdict::transaction(t, decision));
+        }
+    }
+
+    /// Passes the decision about the Wire on to the sink.
+    pub fn wire(&self, wire: &Wire, decision: Decision) {
+        if let Some(sink) = &self.0 {
+            sink.decide(&Verdict::wire(wire, decision));
+        }
+    }
+
+    /// Takes the details of the Transaction for deciding about it
+    /// later, only if a sink is set.
+    pub fn pending(&self, t: &Transaction) -> Pending {
+        Pending(
+            self.0
+                .as_ref()
+                .map(|sink| (sink.clone(), Verdict::transaction(t, Decision::Delivered))),
+        )
+    }
+}
+
+impl Pending {
+    pub fn decide(self, decision: Decision) {
+        if let Some((sink, mut verdict)) = self.0 {
+            verdict.decision = decision;
+            sink.decide(&verdict);
+        }
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Tracer({})", self.is_enabled())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collector(Mutex<Vec<Verdict>>);
+
+    impl TraceSink for Collector {
+        fn decide(&self, verdict: &Verdict) {
+            self.0.lock().unwrap().push(verdict.clone());
+        }
+    }
+
+    #[test]
+    fn test_trace_tracer() {
+        let t = Transaction::new(Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::default(),
+            vec![1],
+        ));
+        let disabled = Tracer::default();
+        assert!(!disabled.is_enabled());
+        disabled.pending(&t).decide(Decision::Forwarded);
+
+        let collector = Arc::new(Collector::default());
+        let tracer = Tracer::new(collector.clone());
+        tracer.pending(&t).decide(Decision::Forwarded);
+        tracer.wire(&t.to_wire(), Decision::Dropped(Reason::CacheHit));
+        let verdicts = collector.0.lock().unwrap();
+        assert_eq!(verdicts[0], Verdict::transaction(&t, Decision::Forwarded));
+        assert_eq!(verdicts[1].uuid, t.uuid);
+        assert_eq!(verdicts[1].class, Some(Class::Action));
+        assert_eq!(verdicts[1].target, t.target());
+        assert_eq!(verdicts[1].decision, Decision::Dropped(Reason::CacheHit));
+    }
+}
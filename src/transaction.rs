@@ -11,13 +11,25 @@
 //! redundancy and possible speed while mostly avoiding duplicate
 //! actions or lost transactions. Some form of recent cache will be
 //! required to check for duplicate messages.
+//!
+//! The two length bytes of a Wire can't describe bodies larger than
+//! about 64KB, so larger Wires are split into fragments when they are
+//! sent. Each fragment has the header of the original Wire and they
+//! get reassembled on the receiving side, so Transactions can have
+//! bodies of any size.
 
+use crate::compression;
 use crate::error::Error;
 use crate::message::{Message, Seed};
-use crate::node::Address;
+use crate::node::{Address, Center};
+use crate::payload::Payload;
 use crate::util;
+use bytes::Bytes;
+use sodiumoxide::crypto::sign;
 use std::cmp::Ordering;
-use std::time::{Duration, SystemTime};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant, SystemTime};
 use uuid::Uuid;
 
 /// The main object users will be interacting with to handle messages
@@ -32,6 +44,72 @@ pub struct Transaction {
     created: SystemTime,
     /// The actual message (not just the body but also connection data).
     pub message: Message,
+    /// Signature of the Wire the Transaction was received in. It is
+    /// kept so that forwarded Transactions remain verifiable.
+    signature: Option<Box<Signature>>,
+    /// Signature of the author over the Message, unlike the one of
+    /// the Wire it covers the Message no matter who delivers it.
+    author: Option<Box<Signature>>,
+    /// Set once the signature of the author was checked against the
+    /// key known for the source, never sent.
+    verified: bool,
+    /// Set once the body was decrypted, never sent.
+    encrypted: bool,
+    /// Set if the Transaction was delivered through a Wildcard, never
+    /// sent.
+    wildcard: bool,
+    /// Address of the peer whose connection the Transaction arrived
+    /// through, never sent.
+    ingress: Option<Box<Address>>,
+    /// Set if the Switch delivered the Transaction of the user to this
+    /// node directly, never sent.
+    loopback: bool,
+    /// Transactions of high priority are sent before all others,
+    /// defaults to the priority of the Class.
+    pub priority: Priority,
+    /// Number of times the Transaction can still be forwarded, it
+    /// gets dropped once no hops are left.
+    pub hops: u8,
+    /// Relays discard the Transaction after this point in time.
+    pub deadline: Option<SystemTime>,
+}
+
+/// Details on how a received Transaction got delivered, meant for
+/// custom policies and debugging in the application. None of them are
+/// sent, they are collected by the receiving node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Time the Transaction was received, for local ones the time it
+    /// was created.
+    pub received: SystemTime,
+    /// Number of nodes that forwarded the Transaction on its way.
+    pub hops: u8,
+    /// Topic the Transaction was published on.
+    pub topic: Address,
+    /// If the Topic only matched a Wildcard of the user instead of a
+    /// Topic subscribed to directly.
+    pub wildcard: bool,
+    /// If the body was encrypted, either to this node or for a group.
+    pub encrypted: bool,
+    /// If the signature of the author was verified, see
+    /// Transaction::verified_source.
+    pub verified: bool,
+    /// Address of the peer whose connection the Transaction arrived
+    /// through, which is not necessarily its source. None for
+    /// Transactions that didn't arrive through a connection.
+    pub ingress: Option<Address>,
+    /// If the Transaction was sent by this node to itself and never
+    /// left it.
+    pub loopback: bool,
+}
+
+/// Lane a Transaction is sent through. Control traffic like Pings
+/// and subscriptions is of high priority, so routing maintenance
+/// doesn't get starved by heavy broadcasting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    High,
 }
 
 /// The Transaction and Message data will be converted into "Wire" and
@@ -47,8 +125,20 @@ pub struct Transaction {
 /// 16 bytes: UUID,
 /// 24 bytes: Nonce,
 /// .. bytes: Body,
+/// 96 bytes: Signature (optional),
 ///
-/// Minimum data size: 142 bytes (+ body).
+/// Minimum data size: 142 bytes (+ body). Signed Wires have the
+/// highest bit of the first class byte set and end with the signing
+/// key (32 bytes) and the signature (64 bytes) over header and body.
+/// Sequenced Wires start their body with the sequence number (8
+/// bytes). Limited Wires start it with the remaining hops (1 byte) and
+/// the deadline (8 bytes, milliseconds since the epoch or zero), in
+/// front of the sequence number. Authored Wires have the highest bit
+/// of the second class byte set and end their body with the signing
+/// key and signature of the author.
+///
+/// The body shares the buffer the Wire was parsed from, so cloning
+/// and forwarding Wires doesn't copy it.
 #[derive(Debug, PartialEq, Clone)]
 pub struct Wire {
     length: [u8; 2],
@@ -58,24 +148,140 @@ pub struct Wire {
     target: [u8; 32],
     topic: [u8; 32],
     nonce: [u8; 24],
-    body: Vec<u8>,
+    body: Bytes,
+    signature: Option<Box<Signature>>,
+}
+
+/// Signature of a Wire together with the key it was created with.
+/// Addresses are encryption keys, so the signing key has to be sent
+/// along.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Signature {
+    key: [u8; 32],
+    bytes: [u8; 64],
+}
+
+/// Marks a Wire as signed, stored in the first class byte, which is
+/// otherwise only ever 0 or 1.
+const SIGNED: u8 = 0b1000_0000;
+
+/// Marks a Wire with a compressed body, stored in the first class
+/// byte next to the signed flag.
+const COMPRESSED: u8 = 0b0100_0000;
+
+/// Marks a fragment of a larger Wire, stored in the first class byte
+/// next to the other flags.
+const FRAGMENTED: u8 = 0b0010_0000;
+
+/// Marks a Wire whose body starts with the sequence number of the
+/// Message, stored in the first class byte next to the other flags.
+const SEQUENCED: u8 = 0b0001_0000;
+
+/// Marks a Wire of high priority, stored in the first class byte next
+/// to the other flags.
+const PRIORITY: u8 = 0b0000_1000;
+
+/// Marks a Wire whose body starts with the remaining hops and the
+/// deadline, stored in the first class byte next to the other flags.
+/// Only Wires that were forwarded or have a deadline are limited.
+const LIMITED: u8 = 0b0000_0100;
+
+/// Marks a Wire whose body ends with the signature of its author,
+/// stored in the second class byte, which is otherwise only ever 0 or
+/// 1.
+const AUTHORED: u8 = 0b1000_0000;
+
+/// Length of the hops and the deadline in front of limited bodies.
+const LIMIT_LENGTH: usize = 9;
+
+/// Number of times a new Transaction can be forwarded.
+pub const HOPS: u8 = 16;
+
+/// Length of the sequence number in front of sequenced bodies.
+const SEQUENCE_LENGTH: usize = 8;
+
+/// Wires with larger bodies get split into fragments, each of them
+/// carries at most this many bytes of the original Wire. It leaves
+/// enough room for the header to fit into a single UDP datagram.
+pub const FRAGMENT_SIZE: usize = 32 * 1024;
+
+/// Length of the fixed Wire header in front of the body.
+const HEADER_LENGTH: usize = 142;
+
+/// Index and number of fragments in front of each fragment body.
+const FRAGMENT_HEADER: usize = 4;
+
+/// Incomplete Wires get dropped if no fragment arrived for this long.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Most incomplete Wires reassembled at once, fragments of further
+/// ones get rejected until one of them completes or times out.
+const FRAGMENT_PENDING: usize = 16;
+
+/// Largest body the two length bytes can describe.
+const MAX_LENGTH: usize = 255 * 255 + 254;
+
+/// Length of the signature at the end of signed Wires.
+const SIGNATURE_LENGTH: usize = 96;
+
+/// Default for the largest Wire accepted from peers in bytes,
+/// reassembled fragments included.
+pub const MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Collects incoming fragments until all of them arrived and
+/// restores the original Wire. All fragments of a Wire are sent
+/// through the same connection, so each connection has its own.
+#[derive(Debug)]
+pub struct Fragments {
+    pending: HashMap<[u8; 16], Pending>,
+    /// Largest reassembled Wire in bytes, Wires announcing more
+    /// fragments are a protocol violation.
+    limit: usize,
+}
+
+/// Fragments of a single Wire that hasn't been completed yet.
+#[derive(Debug)]
+struct Pending {
+    /// Bytes of the original Wire by fragment index.
+    parts: Vec<Option<Bytes>>,
+    /// Number of fragments that haven't arrived yet.
+    missing: usize,
+    /// Last time a fragment arrived.
+    updated: Instant,
 }
 
 /// Each message has a type or function. Since "type" is a reserved
-/// keyword this is referred to as "Class". In the future this will be
-/// expanded to custom types using a trait. The class will be
-/// serialized to a single byte and parsed using a simple lookup
-/// table.
-#[derive(Eq, PartialEq, Debug, Clone)]
+/// keyword this is referred to as "Class". Applications can define
+/// their own types through the Custom range. The class will be
+/// serialized to four bytes and parsed using a simple lookup table.
+#[derive(Eq, PartialEq, Hash, Debug, Clone)]
 pub enum Class {
     /// Internal IsAlive check
     Ping,
     /// Return of Ping.
     Pong,
+    /// Measures the round-trip time of a connection, it never leaves
+    /// the connection it was sent through. The body contains a flag
+    /// for replies and the time stamp of the sender, which the reply
+    /// echoes.
+    Heartbeat,
+    /// Proves during the handshake that the source owns its Address,
+    /// the body contains the nonce of the target. Like heartbeats it
+    /// never leaves the connection it was sent through.
+    Proof,
     /// Internal NodeID lookup.
     Lookup,
     /// Return value for Lookup calls.
     Details,
+    /// Asks a node for the closest nodes it knows to the topic
+    /// Address, one step of an iterative lookup.
+    FindNode,
+    /// Return value for FindNode calls, the body contains the found
+    /// nodes.
+    FoundNodes,
+    /// Registers the source with the target as its relay, the body
+    /// contains the Node of the source with its relayed Link.
+    Relay,
     /// Messages coming from a user to the target node.
     Action,
     /// Subscribe to another topic.
@@ -86,9 +292,81 @@ pub enum Class {
     Subscriber,
     /// Informs subscribers about a unsubscribe message.
     Unsubscriber,
+    /// Confirms a Subscribe to its source, the body contains the
+    /// current subscribers like the one of Subscriber.
+    SubscribeAck,
+    /// Republishes a Record to one of the nodes closest to it.
+    Record,
+    /// Hands a Record over to a node closer to it, the body contains
+    /// the Record. The sender removes its copy once it is accepted.
+    RecordTransfer,
+    /// Accepts a RecordTransfer, the topic field contains the Record.
+    RecordAck,
+    /// Sent between the replicas of a Record, the body contains the
+    /// digest of its subscribers.
+    Digest,
+    /// Response to a Digest that didn't match, the body contains the
+    /// Record of the responding replica.
+    Reconcile,
+    /// Rejects a Subscribe or Action the source isn't allowed to
+    /// send, the topic field contains the Topic.
+    Denied,
+    /// Periodic announcement of a subscriber to the Record, the body
+    /// contains its metadata.
+    Announce,
+    /// Response to Announce, the body contains all present
+    /// subscribers of the Topic.
+    Presence,
+    /// Informs present subscribers about one that joined. Topics
+    /// return it with the joined subscriber as source and its
+    /// metadata as body.
+    Joined,
+    /// Opposite of Joined, for subscribers that unsubscribed or
+    /// stopped announcing themselves.
+    Left,
+    /// Passes a message of a Topic in at-least-once mode to the
+    /// Record, the body contains the Action for one subscriber.
+    Publish,
+    /// Sends a stored message from the Record to the subscriber, the
+    /// body contains the Action of the publisher.
+    Deliver,
+    /// Acknowledges a Publish or Deliver, the body contains the
+    /// Receipt of the message.
+    Ack,
+    /// Passes a message published on a path to the Record of one of
+    /// its subtrees, the body contains the Topic and the message.
+    Propagate,
+    /// Sends a propagated message from the Record to a subscriber of
+    /// the subtree, the body contains the publisher, the Topic and the
+    /// message.
+    Subtree,
+    /// Subtree message for a subscriber that passes it on to a
+    /// partition of the other subscribers, the body contains their
+    /// number and Addresses followed by the Subtree body.
+    Delegate,
+    /// Message of a Topic in gossip mode, the body contains its ID,
+    /// the publisher and the message.
+    Gossip,
+    /// IDs of the recent gossip messages of a Topic the source knows.
+    GossipDigest,
+    /// IDs of gossip messages missing on the source, it receives them
+    /// in return.
+    GossipRequest,
+    /// Stores a value on one of the closest nodes to its key, which
+    /// is the topic. The body contains the TTL and the value.
+    Store,
+    /// Requests the value of the key in the topic.
+    FindValue,
+    /// Response to FindValue, the body is empty if the value isn't
+    /// stored and looks like the one of Store otherwise.
+    Value,
     /// Dedicated field for Bootstrap requests / repsonses. Always
     /// only has zero bytes.
     Bootstrap,
+    /// Application defined type, the system only delivers these
+    /// messages without acting on them. Handlers for each number can
+    /// be registered on the Interface.
+    Custom(u16),
 }
 
 impl Transaction {
@@ -98,7 +376,17 @@ impl Transaction {
         Self {
             uuid: Uuid::new_v4(),
             created: SystemTime::now(),
+            priority: message.class.priority(),
             message,
+            signature: None,
+            author: None,
+            verified: false,
+            encrypted: false,
+            wildcard: false,
+            ingress: None,
+            loopback: false,
+            hops: HOPS,
+            deadline: None,
         }
     }
 
@@ -107,13 +395,23 @@ impl Transaction {
         Self {
             uuid,
             created,
+            priority: message.class.priority(),
             message,
+            signature: None,
+            author: None,
+            verified: false,
+            encrypted: false,
+            wildcard: false,
+            ingress: None,
+            loopback: false,
+            hops: HOPS,
+            deadline: None,
         }
     }
 
     /// Parses bytes first into a Wire, then into a Transaction.
     pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
-        let wire = match Wire::from_bytes(&bytes) {
+        let wire = match Wire::from_bytes(bytes) {
             Ok(data) => data,
             Err(e) => {
                 return Err(e);
@@ -122,31 +420,52 @@ impl Transaction {
         wire.convert()
     }
 
-    /// Converts a Transaction into a Wire object. Currently this
-    /// function uses clone on most fields in order to convert between
-    /// the types without having to take ownership. In the future this
-    /// might get changed or a second function will get added, which
-    /// uses fewer allocations.
+    /// Converts a Transaction into a Wire object. The body is shared
+    /// with the Message, it only gets copied if the hops, the deadline
+    /// or the sequence number have to be put in front of it.
     pub fn to_wire(&self) -> Wire {
+        let mut class = self.message.class.as_bytes();
+        let mut prefix = Vec::new();
+        if self.hops != HOPS || self.deadline.is_some() {
+            class[0] |= LIMITED;
+            prefix.push(self.hops);
+            prefix.extend_from_slice(&timestamp(self.deadline).to_be_bytes());
+        }
+        if let Some(sequence) = self.message.sequence {
+            class[0] |= SEQUENCED;
+            prefix.extend_from_slice(&sequence.to_be_bytes());
+        }
+        let body = if prefix.is_empty() && self.author.is_none() {
+            self.message.body.to_bytes()
+        } else {
+            prefix.extend_from_slice(self.message.body.as_slice());
+            if let Some(author) = &self.author {
+                class[1] |= AUTHORED;
+                prefix.extend_from_slice(&author.key);
+                prefix.extend_from_slice(&author.bytes);
+            }
+            Bytes::from(prefix)
+        };
+        if self.priority == Priority::High {
+            class[0] |= PRIORITY;
+        }
         Wire {
-            length: self.len(),
+            length: util::compute_length(&body),
             uuid: *self.uuid.as_bytes(),
-            class: self.message.class.as_bytes(),
+            class,
             source: self.message.source.as_bytes(),
             target: self.message.target.as_bytes(),
             topic: self.message.topic.as_bytes(),
             nonce: self.message.seed.as_bytes(),
-            body: self.message.body.clone().as_bytes(),
+            body,
+            signature: self.signature.clone(),
         }
     }
 
     /// Simple shorthand function for creating a Transaction from an
-    /// existing Wire. It currently is highly memory inefficient,
-    /// since it simply converts the Wire to bytes and turns those
-    /// into a Transaction.
+    /// existing Wire, the body is shared with it.
     pub fn from_wire(wire: &Wire) -> Result<Transaction, Error> {
-        let bytes = wire.as_bytes();
-        Transaction::from_bytes(&bytes)
+        wire.clone().convert()
     }
 
     /// Convert a Transaction into bytes, to be sent over the wire.
@@ -181,12 +500,6 @@ impl Transaction {
         self.message.class.clone()
     }
 
-    /// Computes the length of the message body. The entire length of
-    /// the message would be 142 + len().
-    fn len(&self) -> [u8; 2] {
-        self.message.len()
-    }
-
     /// When a message comes from a user to the record location the
     /// source Address should not change from the original node (maybe
     /// this has to be updated in a future version by including a
@@ -196,7 +509,7 @@ impl Transaction {
     pub fn redirect(&self, target: Address) -> Transaction {
         let mut transaction = self.clone();
         transaction.message.target = target;
-        return transaction;
+        transaction
     }
 
     /// Easy way of creating a "mostly primitive" version of the core
@@ -208,6 +521,11 @@ impl Transaction {
         (self.source(), self.message.body.clone().as_bytes())
     }
 
+    /// Decodes the body of the Message into the type, see Payload.
+    pub fn payload<T: Payload>(&self) -> Result<T, Error> {
+        T::from_bytes(self.message.body.as_slice())
+    }
+
     /// This function returns the duration since the Transaction was
     /// created. While it should mostly be without problems, it can
     /// fail if the OS clock is unreliable.
@@ -217,6 +535,155 @@ impl Transaction {
             Err(_) => Err(Error::System(String::from("transaction time is invalid"))),
         }
     }
+
+    /// Checks if the deadline of the Transaction has passed.
+    pub fn is_expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| deadline < SystemTime::now())
+    }
+
+    /// Uses up one of the remaining hops before the Transaction gets
+    /// forwarded, returns false if none were left or the deadline
+    /// passed, in which case it has to be dropped.
+    pub fn hop(&mut self) -> bool {
+        if self.hops == 0 || self.is_expired() {
+            return false;
+        }
+        self.hops -= 1;
+        true
+    }
+
+    /// Whether the Transaction was relayed by another node, which
+    /// used up one of its hops. Forwarded Transactions only ever move
+    /// on to nodes closer to their target.
+    pub fn is_forwarded(&self) -> bool {
+        self.hops < HOPS
+    }
+
+    /// Signs the Message with the signing key of the Center as its
+    /// author. Class, source, topic, sequence number and body are
+    /// covered, so the body has to be encrypted before. Messages of
+    /// the user are sent to a single node, for them target and UUID
+    /// are covered as well, so they can't be replayed to other nodes.
+    pub fn sign(&mut self, center: &Center) {
+        let (key, secret) = center.signing();
+        let bytes = sign::sign_detached(&self.authored(), &secret).to_bytes();
+        self.author = Some(Box::new(Signature { key: key.0, bytes }));
+    }
+
+    /// Checks if the signature of the author matches the Message. Like
+    /// Wire::verify this only proves that it hasn't been changed since
+    /// it was signed with the key, not who owns the key. Unsigned
+    /// Messages are not valid.
+    pub fn verify(&self) -> Result<(), Error> {
+        let author = self
+            .author
+            .as_deref()
+            .ok_or_else(|| Error::Crypto(String::from("message is not signed")))?;
+        let key = sign::PublicKey(author.key);
+        let bytes = sign::Signature::try_from(&author.bytes[..])
+            .map_err(|_| Error::Crypto(String::from("signature is not valid")))?;
+        if sign::verify_detached(&bytes, &self.authored(), &key) {
+            Ok(())
+        } else {
+            Err(Error::Crypto(String::from("signature is not valid")))
+        }
+    }
+
+    /// Returns the signature of the author should the Message be
+    /// signed.
+    pub fn author(&self) -> Option<Signature> {
+        self.author.as_deref().copied()
+    }
+
+    /// Sets the signature of the author, for Messages that were signed
+    /// by another node and passed on inside the body of a different
+    /// one.
+    pub fn set_author(&mut self, author: Option<Signature>) {
+        self.author = author.map(Box::new);
+    }
+
+    /// Marks the source as verified, the Switch does this for all
+    /// incoming messages of the user signed with the key their source
+    /// is derived from.
+    pub fn set_verified(&mut self, verified: bool) {
+        self.verified = verified;
+    }
+
+    /// The source of the Message if it was signed by it, None for
+    /// unsigned Messages and ones that failed the verification.
+    pub fn verified_source(&self) -> Option<Address> {
+        if self.verified {
+            Some(self.source())
+        } else {
+            None
+        }
+    }
+
+    /// Marks the body as encrypted, the Switch and Topics do this
+    /// once they decrypted it.
+    pub fn set_encrypted(&mut self, encrypted: bool) {
+        self.encrypted = encrypted;
+    }
+
+    /// Marks the Transaction as delivered through a Wildcard.
+    pub fn set_wildcard(&mut self, wildcard: bool) {
+        self.wildcard = wildcard;
+    }
+
+    /// Sets the peer whose connection the Transaction arrived through,
+    /// the Listener does this for all incoming Transactions.
+    pub fn set_ingress(&mut self, ingress: Address) {
+        self.ingress = Some(Box::new(ingress));
+    }
+
+    /// Marks the Transaction as delivered locally by the Switch.
+    pub fn set_loopback(&mut self, loopback: bool) {
+        self.loopback = loopback;
+    }
+
+    /// True if the Transaction was sent by this node to itself.
+    pub fn is_loopback(&self) -> bool {
+        self.loopback
+    }
+
+    /// Collects the details on how the Transaction got delivered.
+    pub fn metadata(&self) -> Metadata {
+        Metadata {
+            received: self.created,
+            hops: HOPS.saturating_sub(self.hops),
+            topic: self.topic(),
+            wildcard: self.wildcard,
+            encrypted: self.encrypted,
+            verified: self.verified,
+            ingress: self.ingress.as_deref().cloned(),
+            loopback: self.loopback,
+        }
+    }
+
+    /// Everything the signature of the author covers.
+    fn authored(&self) -> Vec<u8> {
+        let body = self.message.body.as_slice();
+        let mut data = Vec::with_capacity(4 + 112 + SEQUENCE_LENGTH + body.len());
+        data.extend_from_slice(&self.message.class.as_bytes());
+        data.extend_from_slice(&self.message.source.as_bytes());
+        data.extend_from_slice(&self.message.topic.as_bytes());
+        if self.message.class.is_user() {
+            data.extend_from_slice(&self.message.target.as_bytes());
+            data.extend_from_slice(self.uuid.as_bytes());
+        }
+        if let Some(sequence) = self.message.sequence {
+            data.extend_from_slice(&sequence.to_be_bytes());
+        }
+        data.extend_from_slice(body);
+        data
+    }
+}
+
+/// Milliseconds since the epoch, zero stands for no point in time.
+fn timestamp(time: Option<SystemTime>) -> u64 {
+    time.and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_millis() as u64)
 }
 
 impl Ord for Transaction {
@@ -227,7 +694,7 @@ impl Ord for Transaction {
 
 impl PartialOrd for Transaction {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(&other))
+        Some(self.cmp(other))
     }
 }
 
@@ -239,6 +706,24 @@ impl PartialEq for Transaction {
 
 impl Eq for Transaction {}
 
+impl Signature {
+    /// Creates the signature from the key and the bytes it consists
+    /// of, like they are sent at the end of authored Wires.
+    pub fn new(key: [u8; 32], bytes: [u8; 64]) -> Self {
+        Self { key, bytes }
+    }
+
+    /// The public signing key used to create the signature.
+    pub fn key(&self) -> [u8; 32] {
+        self.key
+    }
+
+    /// The signature itself without the key.
+    pub fn bytes(&self) -> [u8; 64] {
+        self.bytes
+    }
+}
+
 impl Class {
     /// The class is serialized as a single byte, this function
     /// converts that to the object using a simple lookup table.
@@ -247,13 +732,42 @@ impl Class {
             [0, 0, 0, 0] => Ok(Self::Bootstrap),
             [0, 0, 0, 1] => Ok(Self::Ping),
             [0, 0, 0, 2] => Ok(Self::Pong),
+            [0, 0, 0, 3] => Ok(Self::Heartbeat),
+            [0, 0, 0, 4] => Ok(Self::Proof),
             [0, 0, 1, 0] => Ok(Self::Lookup),
             [0, 0, 1, 1] => Ok(Self::Details),
+            [0, 0, 1, 2] => Ok(Self::FindNode),
+            [0, 0, 1, 3] => Ok(Self::FoundNodes),
+            [0, 0, 2, 0] => Ok(Self::Relay),
             [0, 1, 0, 0] => Ok(Self::Subscribe),
             [0, 1, 0, 1] => Ok(Self::Unsubscribe),
             [0, 1, 0, 2] => Ok(Self::Subscriber),
             [0, 1, 0, 3] => Ok(Self::Unsubscriber),
+            [0, 1, 0, 4] => Ok(Self::Record),
+            [0, 1, 0, 5] => Ok(Self::Denied),
+            [0, 1, 0, 6] => Ok(Self::Announce),
+            [0, 1, 0, 7] => Ok(Self::Presence),
+            [0, 1, 0, 8] => Ok(Self::Joined),
+            [0, 1, 0, 9] => Ok(Self::Left),
+            [0, 1, 0, 10] => Ok(Self::Publish),
+            [0, 1, 0, 11] => Ok(Self::Deliver),
+            [0, 1, 0, 12] => Ok(Self::Ack),
+            [0, 1, 0, 13] => Ok(Self::Propagate),
+            [0, 1, 0, 14] => Ok(Self::Subtree),
+            [0, 1, 0, 15] => Ok(Self::RecordTransfer),
+            [0, 1, 0, 16] => Ok(Self::RecordAck),
+            [0, 1, 0, 17] => Ok(Self::Digest),
+            [0, 1, 0, 18] => Ok(Self::Reconcile),
+            [0, 1, 0, 19] => Ok(Self::Delegate),
+            [0, 1, 0, 20] => Ok(Self::Gossip),
+            [0, 1, 0, 21] => Ok(Self::GossipDigest),
+            [0, 1, 0, 22] => Ok(Self::GossipRequest),
+            [0, 1, 0, 23] => Ok(Self::Store),
+            [0, 1, 0, 24] => Ok(Self::FindValue),
+            [0, 1, 0, 25] => Ok(Self::Value),
+            [0, 1, 0, 26] => Ok(Self::SubscribeAck),
             [1, 0, 0, 1] => Ok(Self::Action),
+            [2, 0, high, low] => Ok(Self::Custom(u16::from_be_bytes([high, low]))),
             _ => Err(Error::Invalid(String::from("class serlaization invalid"))),
         }
     }
@@ -267,13 +781,74 @@ impl Class {
             Self::Bootstrap => [0, 0, 0, 0],
             Self::Ping => [0, 0, 0, 1],
             Self::Pong => [0, 0, 0, 2],
+            Self::Heartbeat => [0, 0, 0, 3],
+            Self::Proof => [0, 0, 0, 4],
             Self::Lookup => [0, 0, 1, 0],
             Self::Details => [0, 0, 1, 1],
+            Self::FindNode => [0, 0, 1, 2],
+            Self::FoundNodes => [0, 0, 1, 3],
+            Self::Relay => [0, 0, 2, 0],
             Self::Subscribe => [0, 1, 0, 0],
             Self::Unsubscribe => [0, 1, 0, 1],
             Self::Subscriber => [0, 1, 0, 2],
             Self::Unsubscriber => [0, 1, 0, 3],
+            Self::Record => [0, 1, 0, 4],
+            Self::Denied => [0, 1, 0, 5],
+            Self::Announce => [0, 1, 0, 6],
+            Self::Presence => [0, 1, 0, 7],
+            Self::Joined => [0, 1, 0, 8],
+            Self::Left => [0, 1, 0, 9],
+            Self::Publish => [0, 1, 0, 10],
+            Self::Deliver => [0, 1, 0, 11],
+            Self::Ack => [0, 1, 0, 12],
+            Self::Propagate => [0, 1, 0, 13],
+            Self::Subtree => [0, 1, 0, 14],
+            Self::RecordTransfer => [0, 1, 0, 15],
+            Self::RecordAck => [0, 1, 0, 16],
+            Self::Digest => [0, 1, 0, 17],
+            Self::Reconcile => [0, 1, 0, 18],
+            Self::Delegate => [0, 1, 0, 19],
+            Self::Gossip => [0, 1, 0, 20],
+            Self::GossipDigest => [0, 1, 0, 21],
+            Self::GossipRequest => [0, 1, 0, 22],
+            Self::Store => [0, 1, 0, 23],
+            Self::FindValue => [0, 1, 0, 24],
+            Self::Value => [0, 1, 0, 25],
+            Self::SubscribeAck => [0, 1, 0, 26],
             Self::Action => [1, 0, 0, 1],
+            Self::Custom(number) => {
+                let [high, low] = number.to_be_bytes();
+                [2, 0, high, low]
+            }
+        }
+    }
+
+    /// Messages of the user, their bodies are encrypted end-to-end.
+    pub fn is_user(&self) -> bool {
+        matches!(self, Self::Action | Self::Custom(_))
+    }
+
+    /// Default Priority of Transactions of the Class. Messages of the
+    /// user and everything that carries them are of low priority,
+    /// as are Records, which get republished in bulk. Bootstrap Wires
+    /// need an all zero header, so they are of low priority as well.
+    pub fn priority(&self) -> Priority {
+        match self {
+            Self::Action
+            | Self::Custom(_)
+            | Self::Publish
+            | Self::Deliver
+            | Self::Propagate
+            | Self::Subtree
+            | Self::Delegate
+            | Self::Gossip
+            | Self::Store
+            | Self::Value
+            | Self::Record
+            | Self::RecordTransfer
+            | Self::Reconcile
+            | Self::Bootstrap => Priority::Low,
+            _ => Priority::High,
         }
     }
 }
@@ -290,20 +865,86 @@ impl Wire {
             topic: [0; 32],
             uuid: [0; 16],
             nonce: [0; 24],
-            body,
+            body: Bytes::from(body),
+            signature: None,
         }
     }
 
+    /// Constructs a new keep-alive Wire. It only has the Ping class
+    /// set, all other fields are zero. Unlike a normal Ping it never
+    /// leaves the connection it was sent through.
+    pub fn keepalive() -> Self {
+        let mut wire = Self::bootstrap(Vec::new());
+        wire.class = Class::Ping.as_bytes();
+        wire
+    }
+
+    /// Constructs a heartbeat Wire with the time stamp of the sender,
+    /// replies echo the stamp of the request. Like keep-alives all
+    /// other fields are zero. Structure of the body:
+    /// 1 byte: Reply flag (0 = request, 1 = reply),
+    /// 8 bytes: Time stamp
+    pub fn heartbeat(stamp: u64, reply: bool) -> Self {
+        let mut body = vec![reply as u8];
+        body.extend_from_slice(&stamp.to_be_bytes());
+        let mut wire = Self::bootstrap(body);
+        wire.class = Class::Heartbeat.as_bytes();
+        wire
+    }
+
+    /// Constructs the proof of the Center for the peer, a Wire signed
+    /// by the Center with the nonce of the peer as body. Source and
+    /// target are the Addresses of both sides, so it can't be used
+    /// for another peer.
+    pub fn proof(center: &Center, peer: &Address, nonce: &[u8]) -> Self {
+        let mut wire = Self::bootstrap(nonce.to_vec());
+        wire.class = Class::Proof.as_bytes();
+        wire.source = center.public.as_bytes();
+        wire.target = peer.as_bytes();
+        wire.sign(center);
+        wire
+    }
+
     /// Convert raw bytes coming from the network into a Wire object.
     /// This will not parse them into a transaction, since sone
-    /// decisions can already be made without it. It currently takes a
-    /// Vector of bytes, in the future just referencing the array
-    /// would be better.
+    /// decisions can already be made without it. The bytes get copied
+    /// once, parse avoids that for owned buffers.
     pub fn from_bytes(raw: &[u8]) -> Result<Self, Error> {
-        if raw.len() <= 81 {
-            return Err(Error::Invalid(String::from("invalid number of bytes")));
+        Self::parse(Bytes::copy_from_slice(raw))
+    }
+
+    /// Checks the number of bytes of an incoming Wire against the
+    /// limit before they get allocated or parsed.
+    pub fn bounded(length: usize, limit: usize) -> Result<(), Error> {
+        if length > limit {
+            return Err(Error::Protocol(format!(
+                "wire of {} bytes exceeds the limit of {}",
+                length, limit
+            )));
         }
+        Ok(())
+    }
 
+    /// Same as from_bytes, but the body of the Wire is a slice of the
+    /// buffer instead of a copy. Frames whose length header doesn't
+    /// match the body are rejected, which also bounds the body to
+    /// MAX_LENGTH.
+    pub fn parse(raw: Bytes) -> Result<Self, Error> {
+        let wire = Self::split(raw)?;
+        if util::integer(wire.length) != wire.body.len() {
+            return Err(Error::Invalid(String::from("length does not match body")));
+        }
+        Ok(wire)
+    }
+
+    /// Splits the bytes into the fields of a Wire without checking
+    /// the length header. Reassembled Wires are larger than it can
+    /// describe.
+    fn split(raw: Bytes) -> Result<Self, Error> {
+        if raw.len() < HEADER_LENGTH {
+            return Err(Error::Invalid(String::from("invalid number of bytes")));
+        }
+        let field = |start: usize, end: usize| &raw[start..end];
         let mut length: [u8; 2] = [0; 2];
         let mut class: [u8; 4] = [0; 4];
         let mut source: [u8; 32] = [0; 32];
@@ -311,39 +952,27 @@ impl Wire {
         let mut topic: [u8; 32] = [0; 32];
         let mut uuid: [u8; 16] = [0; 16];
         let mut nonce: [u8; 24] = [0; 24];
-        let mut body: Vec<u8> = Vec::new();
+        length.copy_from_slice(field(0, 2));
+        class.copy_from_slice(field(2, 6));
+        source.copy_from_slice(field(6, 38));
+        target.copy_from_slice(field(38, 70));
+        topic.copy_from_slice(field(70, 102));
+        uuid.copy_from_slice(field(102, 118));
+        nonce.copy_from_slice(field(118, HEADER_LENGTH));
+        let mut body = raw.slice(HEADER_LENGTH..);
 
-        for (i, j) in raw.iter().enumerate() {
-            // bytes 0..1 = Length, len = 2, offset = 0
-            if i <= 1 {
-                length[i] = *j;
-            }
-            // bytes 2..5 = Class, len = 4, offset = 2
-            else if i >= 2 && i <= 5 {
-                class[i - 2] = *j;
-            }
-            // bytes 6..37 = Source, len = 32, offset = 6
-            else if i >= 6 && i <= 37 {
-                source[i - 6] = *j;
-            }
-            // bytes 38..69 = Target, len = 32, offset = 38
-            else if i >= 38 && i <= 69 {
-                target[i - 38] = *j;
-            }
-            // bytes 70..101 = Topic, len = 32, offset = 70
-            else if i >= 70 && i <= 101 {
-                topic[i - 70] = *j;
-            }
-            // bytes 102..117 = UUID, len = 16, offset = 102
-            else if i >= 102 && i <= 117 {
-                uuid[i - 102] = *j;
-            }
-            // bytes 118..142 = Nonce, len = 24, offset = 118
-            else if i >= 118 && i <= 141 {
-                nonce[i - 118] = *j;
-            } else {
-                body.push(*j);
+        let mut signature = None;
+        if class[0] & SIGNED != 0 {
+            class[0] &= !SIGNED;
+            if body.len() < SIGNATURE_LENGTH {
+                return Err(Error::Invalid(String::from("signature is missing")));
             }
+            let trailer = body.split_off(body.len() - SIGNATURE_LENGTH);
+            let mut key = [0; 32];
+            let mut bytes = [0; 64];
+            key.copy_from_slice(&trailer[..32]);
+            bytes.copy_from_slice(&trailer[32..]);
+            signature = Some(Box::new(Signature { key, bytes }));
         }
 
         Ok(Self {
@@ -355,48 +984,297 @@ impl Wire {
             uuid,
             nonce,
             body,
+            signature,
         })
     }
 
+    /// Number of bytes of the Wire once it is converted with
+    /// as_bytes.
+    pub fn size(&self) -> usize {
+        let trailer = if self.signature.is_some() {
+            SIGNATURE_LENGTH
+        } else {
+            0
+        };
+        HEADER_LENGTH + self.body.len() + trailer
+    }
+
     /// Converts a Wire object into the actuall bytes to be sent over
     /// the wire. The function simply pushes the different elements
     /// onto a vector, the only important thing is the order of
-    /// commands.
-    ///
-    /// Currently this function clones the body. It might be more
-    /// performant to remove that, but it would requrie a mutable
-    /// reference to the Wire object.
+    /// commands. The body gets copied exactly once.
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.append(&mut self.length.to_vec());
-        data.append(&mut self.class.to_vec());
-        data.append(&mut self.source.to_vec());
-        data.append(&mut self.target.to_vec());
-        data.append(&mut self.topic.to_vec());
-        data.append(&mut self.uuid.to_vec());
-        data.append(&mut self.nonce.to_vec());
-        data.append(&mut self.body.clone());
+        let trailer = if self.signature.is_some() {
+            SIGNATURE_LENGTH
+        } else {
+            0
+        };
+        let mut data: Vec<u8> = Vec::with_capacity(HEADER_LENGTH + self.body.len() + trailer);
+        let mut class = self.class;
+        if self.signature.is_some() {
+            class[0] |= SIGNED;
+        }
+        data.extend_from_slice(&self.length);
+        data.extend_from_slice(&class);
+        data.extend_from_slice(&self.source);
+        data.extend_from_slice(&self.target);
+        data.extend_from_slice(&self.topic);
+        data.extend_from_slice(&self.uuid);
+        data.extend_from_slice(&self.nonce);
+        data.extend_from_slice(&self.body);
+        if let Some(signature) = &self.signature {
+            data.extend_from_slice(&signature.key);
+            data.extend_from_slice(&signature.bytes);
+        }
+
+        data
+    }
+
+    /// Signs header and body with the signing key of the Center. An
+    /// existing signature gets replaced.
+    pub fn sign(&mut self, center: &Center) {
+        let (key, secret) = center.signing();
+        self.signature = None;
+        let bytes = sign::sign_detached(&self.canonical().as_bytes(), &secret).to_bytes();
+        self.signature = Some(Box::new(Signature { key: key.0, bytes }));
+    }
+
+    /// Checks if the signature matches header and body. This only
+    /// proves that the Wire hasn't been changed since it was signed
+    /// by the owner of the signing key, it does not check who that
+    /// is. Unsigned Wires and ones claiming more than HOPS remaining
+    /// hops are not valid.
+    pub fn verify(&self) -> Result<(), Error> {
+        if self.class[0] & LIMITED != 0 && self.body.first().is_some_and(|hops| *hops > HOPS) {
+            return Err(Error::Crypto(String::from("hops are not valid")));
+        }
+        match self.signature() {
+            Some(signature) => {
+                let mut unsigned = self.clone();
+                unsigned.signature = None;
+                let key = sign::PublicKey(signature.key);
+                let bytes = sign::Signature::try_from(&signature.bytes[..])
+                    .map_err(|_| Error::Crypto(String::from("signature is not valid")))?;
+                if sign::verify_detached(&bytes, &unsigned.canonical().as_bytes(), &key) {
+                    Ok(())
+                } else {
+                    Err(Error::Crypto(String::from("signature is not valid")))
+                }
+            }
+            None => Err(Error::Crypto(String::from("wire is not signed"))),
+        }
+    }
+
+    /// The Wire as it was before it got forwarded, which is what the
+    /// signature covers. The hops get used up on the way, so they are
+    /// reset and Wires that were only limited because of them aren't
+    /// limited at all. Every Wire starts out with HOPS, so a forwarder
+    /// can't give it more than that without breaking the signature,
+    /// see verify.
+    fn canonical(&self) -> Wire {
+        let mut wire = self.clone();
+        if wire.class[0] & LIMITED == 0 || wire.body.len() < LIMIT_LENGTH {
+            return wire;
+        }
+        if wire.body[1..LIMIT_LENGTH] == [0; 8] {
+            wire.class[0] &= !LIMITED;
+            wire.body = wire.body.slice(LIMIT_LENGTH..);
+            wire.length = util::compute_length(&wire.body);
+        } else {
+            let mut body = wire.body.to_vec();
+            body[0] = HOPS;
+            wire.body = Bytes::from(body);
+        }
+        wire
+    }
+
+    /// Compresses the body if it is larger than the threshold and
+    /// actually gets smaller. The signature only covers the
+    /// uncompressed Wire, so it has to be decompressed before it can
+    /// be verified.
+    pub fn compress(&mut self, threshold: usize) {
+        if self.body.len() <= threshold || self.is_compressed() {
+            return;
+        }
+        let body = compression::compress(&self.body);
+        if body.len() < self.body.len() {
+            self.length = util::compute_length(&body);
+            self.body = Bytes::from(body);
+            self.class[0] |= COMPRESSED;
+        }
+    }
+
+    /// Restores the original body of a compressed Wire, uncompressed
+    /// ones stay unchanged.
+    pub fn decompress(&mut self) -> Result<(), Error> {
+        if self.is_compressed() {
+            self.body = Bytes::from(compression::decompress(&self.body, MAX_LENGTH)?);
+            self.length = util::compute_length(&self.body);
+            self.class[0] &= !COMPRESSED;
+        }
+        Ok(())
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.class[0] & COMPRESSED != 0
+    }
+
+    /// Splits the Wire into fragments should its body be larger than
+    /// FRAGMENT_SIZE, otherwise it is returned as it is. The entire
+    /// Wire including the signature is split, so the fragments
+    /// themselves are unsigned. Fails if the Wire would need more
+    /// than u16::MAX fragments.
+    pub fn fragment(self) -> Result<Vec<Wire>, Error> {
+        if self.body.len() <= FRAGMENT_SIZE {
+            return Ok(vec![self]);
+        }
+        let bytes = self.as_bytes();
+        let count = bytes.len().div_ceil(FRAGMENT_SIZE);
+        let count =
+            u16::try_from(count).map_err(|_| Error::Invalid(String::from("wire is too large")))?;
+        let fragments = bytes
+            .chunks(FRAGMENT_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut body = Vec::with_capacity(FRAGMENT_HEADER + chunk.len());
+                body.extend_from_slice(&(index as u16).to_be_bytes());
+                body.extend_from_slice(&count.to_be_bytes());
+                body.extend_from_slice(chunk);
+                let mut class = self.class;
+                class[0] |= FRAGMENTED;
+                Wire {
+                    length: util::compute_length(&body),
+                    class,
+                    body: Bytes::from(body),
+                    signature: None,
+                    ..self.clone()
+                }
+            })
+            .collect();
+        Ok(fragments)
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.class[0] & FRAGMENTED != 0
+    }
+
+    /// Returns the signature should the Wire be signed.
+    pub fn signature(&self) -> Option<Signature> {
+        self.signature.as_deref().copied()
+    }
 
-        return data;
+    /// Returns the Address of the source of the Wire.
+    pub fn source(&self) -> Address {
+        Address::from_bytes(self.source)
+    }
+
+    /// Returns the Address of the target of the Wire.
+    pub fn target(&self) -> Address {
+        Address::from_bytes(self.target)
+    }
+
+    /// Number of bytes following the body, which depends on the
+    /// header. Used when reading Wires from a stream.
+    pub fn trailer_length(header: &[u8]) -> usize {
+        match header.get(2) {
+            Some(class) if class & SIGNED != 0 => SIGNATURE_LENGTH,
+            _ => 0,
+        }
     }
 
     /// Turns a Wire Object into a Transaction. It constructs a new
     /// Message and Transaction from the data in Wire.
-    pub fn convert(self) -> Result<Transaction, Error> {
+    pub fn convert(mut self) -> Result<Transaction, Error> {
+        let priority = self.priority();
+        self.class[0] &= !PRIORITY;
+        let mut author = None;
+        if self.class[1] & AUTHORED != 0 {
+            self.class[1] &= !AUTHORED;
+            if self.body.len() < SIGNATURE_LENGTH {
+                return Err(Error::Invalid(String::from("signature is missing")));
+            }
+            let trailer = self.body.split_off(self.body.len() - SIGNATURE_LENGTH);
+            let mut key = [0; 32];
+            let mut bytes = [0; 64];
+            key.copy_from_slice(&trailer[..32]);
+            bytes.copy_from_slice(&trailer[32..]);
+            author = Some(Box::new(Signature { key, bytes }));
+        }
+        let mut hops = HOPS;
+        let mut deadline = None;
+        if self.class[0] & LIMITED != 0 {
+            self.class[0] &= !LIMITED;
+            if self.body.len() < LIMIT_LENGTH {
+                return Err(Error::Invalid(String::from("limit is missing")));
+            }
+            let body = self.body.split_off(LIMIT_LENGTH);
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&self.body[1..]);
+            hops = self.body[0];
+            if hops > HOPS {
+                return Err(Error::Invalid(String::from("hops are not valid")));
+            }
+            deadline = match u64::from_be_bytes(bytes) {
+                0 => None,
+                millis => Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis)),
+            };
+            self.body = body;
+        }
+        let mut sequence = None;
+        if self.class[0] & SEQUENCED != 0 {
+            self.class[0] &= !SEQUENCED;
+            if self.body.len() < SEQUENCE_LENGTH {
+                return Err(Error::Invalid(String::from("sequence is missing")));
+            }
+            let body = self.body.split_off(SEQUENCE_LENGTH);
+            let mut bytes = [0; SEQUENCE_LENGTH];
+            bytes.copy_from_slice(&self.body);
+            sequence = Some(u64::from_be_bytes(bytes));
+            self.body = body;
+        }
         let class = Class::from_bytes(self.class)?;
         let source = Address::from_bytes(self.source);
         let target = Address::from_bytes(self.target);
         let topic = Address::from_bytes(self.topic);
         let seed = Seed::from_bytes(&self.nonce)?;
         let uuid = Uuid::from_bytes(self.uuid);
-        let message = Message::create(class, source, target, topic, seed, self.body);
+        let mut message = Message::create(class, source, target, topic, seed, self.body);
+        message.sequence = sequence;
         Ok(Transaction {
             uuid,
             created: SystemTime::now(),
             message,
+            signature: self.signature,
+            author,
+            verified: false,
+            encrypted: false,
+            wildcard: false,
+            ingress: None,
+            loopback: false,
+            priority,
+            hops,
+            deadline,
         })
     }
 
+    /// Returns the Class of the Wire without any of the flags, None
+    /// if it isn't valid.
+    pub fn class(&self) -> Option<Class> {
+        let mut class = self.class;
+        class[0] &= !(SIGNED | COMPRESSED | FRAGMENTED | SEQUENCED | PRIORITY | LIMITED);
+        class[1] &= !AUTHORED;
+        Class::from_bytes(class).ok()
+    }
+
+    /// Returns the Priority the Wire was sent with.
+    pub fn priority(&self) -> Priority {
+        if self.class[0] & PRIORITY != 0 {
+            Priority::High
+        } else {
+            Priority::Low
+        }
+    }
+
     /// Checks if every field of the wire header is zero but ignores
     /// the two length bytes.
     pub fn is_empty(&self) -> bool {
@@ -407,15 +1285,128 @@ impl Wire {
             && self.topic == [0; 32]
     }
 
+    /// Checks if the Wire is a keep-alive frame, which are not part
+    /// of any Transaction.
+    pub fn is_keepalive(&self) -> bool {
+        self.class == Class::Ping.as_bytes()
+            && self.source == [0; 32]
+            && self.target == [0; 32]
+            && self.topic == [0; 32]
+            && self.body.is_empty()
+    }
+
+    /// Returns the time stamp and reply flag of a heartbeat Wire,
+    /// None for every other Wire.
+    pub fn heartbeat_stamp(&self) -> Option<(u64, bool)> {
+        if self.class != Class::Heartbeat.as_bytes()
+            || self.source != [0; 32]
+            || self.target != [0; 32]
+            || self.topic != [0; 32]
+        {
+            return None;
+        }
+        match self.body.as_ref() {
+            [flag @ (0 | 1), stamp @ ..] if stamp.len() == 8 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(stamp);
+                Some((u64::from_be_bytes(bytes), *flag == 1))
+            }
+            _ => None,
+        }
+    }
+
     /// Simple wrapper to return the body of a Wire.
-    pub fn body(&self) -> &Vec<u8> {
+    pub fn body(&self) -> &[u8] {
         &self.body
     }
 }
 
+impl Fragments {
+    pub fn new() -> Self {
+        Self::with_limit(MESSAGE_SIZE)
+    }
+
+    /// Reassembles Wires of at most limit bytes.
+    pub fn with_limit(limit: usize) -> Self {
+        Self {
+            pending: HashMap::new(),
+            limit,
+        }
+    }
+
+    /// Adds an incoming Wire and returns the original one once all of
+    /// its fragments arrived. Wires that aren't fragments are
+    /// returned right away. Fragments that don't match the previous
+    /// ones of the same Wire are rejected, as are new Wires while
+    /// FRAGMENT_PENDING others are incomplete. Wires larger than the
+    /// limit fail with a protocol error.
+    pub fn insert(&mut self, wire: Wire) -> Result<Option<Wire>, Error> {
+        if !wire.is_fragment() {
+            return Ok(Some(wire));
+        }
+        let now = Instant::now();
+        self.pending
+            .retain(|_, pending| now.duration_since(pending.updated) < FRAGMENT_TIMEOUT);
+        let invalid = || Error::Invalid(String::from("fragment is not valid"));
+        if wire.body.len() < FRAGMENT_HEADER || wire.body.len() > FRAGMENT_HEADER + FRAGMENT_SIZE {
+            return Err(invalid());
+        }
+        let index = u16::from_be_bytes([wire.body[0], wire.body[1]]) as usize;
+        let count = u16::from_be_bytes([wire.body[2], wire.body[3]]) as usize;
+        if index >= count {
+            return Err(invalid());
+        }
+        Wire::bounded((count - 1) * FRAGMENT_SIZE, self.limit)?;
+        if self.pending.len() >= FRAGMENT_PENDING && !self.pending.contains_key(&wire.uuid) {
+            return Err(Error::Busy(String::from("too many incomplete wires")));
+        }
+        let pending = self.pending.entry(wire.uuid).or_insert_with(|| Pending {
+            parts: vec![None; count],
+            missing: count,
+            updated: now,
+        });
+        if pending.parts.len() != count {
+            self.pending.remove(&wire.uuid);
+            return Err(invalid());
+        }
+        pending.updated = now;
+        if pending.parts[index].is_none() {
+            pending.missing -= 1;
+        }
+        pending.parts[index] = Some(wire.body.slice(FRAGMENT_HEADER..));
+        if pending.missing > 0 {
+            return Ok(None);
+        }
+        let pending = self.pending.remove(&wire.uuid).ok_or_else(invalid)?;
+        let length = pending.parts.iter().flatten().map(|part| part.len()).sum();
+        Wire::bounded(length, self.limit)?;
+        let mut bytes = Vec::with_capacity(length);
+        for part in pending.parts.into_iter().flatten() {
+            bytes.extend_from_slice(&part);
+        }
+        Ok(Some(Wire::split(Bytes::from(bytes))?))
+    }
+
+    /// Number of Wires that are still missing fragments.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+impl Default for Fragments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use sodiumoxide::crypto::box_;
 
     #[test]
     fn test_class_parse() {
@@ -430,6 +1421,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_class_custom() {
+        let class = Class::Custom(0x1234);
+        assert_eq!(class.as_bytes(), [2, 0, 0x12, 0x34]);
+        assert_eq!(Class::from_bytes(class.as_bytes()).unwrap(), class);
+        assert!(class.is_user());
+        assert!(!Class::Ping.is_user());
+        assert!(Class::from_bytes([2, 1, 0, 0]).is_err());
+        let message = Message::new(
+            class.clone(),
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let t = Transaction::from_bytes(&Transaction::new(message).as_bytes()).unwrap();
+        assert_eq!(t.class(), class);
+    }
+
     #[test]
     fn test_wire_from_bytes() {
         let data = generate_test_data();
@@ -439,7 +1449,7 @@ mod tests {
                 assert_eq!(wire.target, Address::generate("def").as_bytes());
                 assert_eq!(
                     wire.uuid,
-                    Uuid::parse_str(&mut "27d626f0-1515-47d4-a366-0b75ce6950bf")
+                    Uuid::parse_str("27d626f0-1515-47d4-a366-0b75ce6950bf")
                         .unwrap()
                         .as_bytes()
                         .to_owned()
@@ -453,6 +1463,15 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_wire_parse() {
+        let raw = Bytes::from(generate_test_data());
+        let wire = Wire::parse(raw.clone()).unwrap();
+        assert_eq!(wire, Wire::from_bytes(&raw).unwrap());
+        assert_eq!(wire.body().as_ptr(), raw[HEADER_LENGTH..].as_ptr());
+        assert!(Wire::parse(raw.slice(..HEADER_LENGTH - 1)).is_err());
+    }
+
     #[test]
     fn test_wire_to_transaction() {
         let data = generate_test_data();
@@ -460,11 +1479,33 @@ mod tests {
         assert_eq!(wire.convert().unwrap().message.class, Class::Ping);
     }
 
+    #[test]
+    fn test_wire_heartbeat() {
+        let wire = Wire::from_bytes(&Wire::heartbeat(42, false).as_bytes()).unwrap();
+        assert_eq!(wire.heartbeat_stamp(), Some((42, false)));
+        assert!(!wire.is_keepalive());
+        let reply = Wire::from_bytes(&Wire::heartbeat(42, true).as_bytes()).unwrap();
+        assert_eq!(reply.heartbeat_stamp(), Some((42, true)));
+        assert_eq!(Wire::keepalive().heartbeat_stamp(), None);
+    }
+
+    #[test]
+    fn test_wire_keepalive() {
+        let wire = Wire::from_bytes(&Wire::keepalive().as_bytes()).unwrap();
+        assert!(wire.is_keepalive());
+        assert!(!wire.is_empty());
+        assert_eq!(wire.class(), Some(Class::Ping));
+        assert!(!Wire::from_bytes(&generate_test_data())
+            .unwrap()
+            .is_keepalive());
+    }
+
     #[test]
     fn test_wire_as_bytes() {
         let data = generate_test_data();
         let wire = Wire::from_bytes(&data).unwrap();
         assert_eq!(wire.as_bytes(), data);
+        assert_eq!(wire.size(), data.len());
     }
 
     #[test]
@@ -477,7 +1518,8 @@ mod tests {
             Seed::from_bytes(&[0; 24]).unwrap(),
             Vec::new(),
         );
-        assert_eq!(Transaction::new(m).to_wire().class, [0, 0, 0, 1]);
+        // Pings are of high priority.
+        assert_eq!(Transaction::new(m).to_wire().class, [PRIORITY, 0, 0, 1]);
     }
 
     #[test]
@@ -492,7 +1534,7 @@ mod tests {
         );
         let t = Transaction::new(m);
         let d = t.age().unwrap();
-        assert_eq!(d > Duration::from_secs(0), true);
+        assert!(d > Duration::from_secs(0));
     }
 
     #[test]
@@ -526,7 +1568,7 @@ mod tests {
 
     #[test]
     fn test_transaction_build() {
-        let uuid = Uuid::parse_str(&mut "27d626f0-1515-47d4-a366-0b75ce6950bf").unwrap();
+        let uuid = Uuid::parse_str("27d626f0-1515-47d4-a366-0b75ce6950bf").unwrap();
         let time = SystemTime::now();
         let seed = Seed::from_bytes(&[0; 24]).unwrap();
         let message = Message::create(
@@ -542,18 +1584,356 @@ mod tests {
         assert_eq!(t.message, d.message);
     }
 
+    #[test]
+    fn test_wire_sign() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let mut wire = Wire::from_bytes(&generate_test_data()).unwrap();
+        wire.sign(&center);
+        assert!(wire.verify().is_ok());
+        let parsed = Wire::from_bytes(&wire.as_bytes()).unwrap();
+        assert_eq!(parsed, wire);
+        assert_eq!(wire.size(), wire.as_bytes().len());
+        assert!(parsed.verify().is_ok());
+        assert_eq!(
+            Wire::trailer_length(&wire.as_bytes()[..142]),
+            SIGNATURE_LENGTH
+        );
+    }
+
+    #[test]
+    fn test_wire_sign_invalid() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let mut wire = Wire::from_bytes(&generate_test_data()).unwrap();
+        assert!(wire.verify().is_err());
+        wire.sign(&center);
+        wire.body = Bytes::from("forged");
+        assert!(wire.verify().is_err());
+    }
+
+    #[test]
+    fn test_wire_compress() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::random(),
+            "actaeon ".repeat(200).into_bytes(),
+        );
+        let mut wire = Transaction::new(message).to_wire();
+        wire.sign(&center);
+        let original = wire.clone();
+        wire.compress(2000);
+        assert!(!wire.is_compressed());
+        wire.compress(100);
+        assert!(wire.is_compressed());
+        assert_eq!(wire.class(), Some(Class::Action));
+        assert!(wire.body.len() < 100);
+        let mut parsed = Wire::from_bytes(&wire.as_bytes()).unwrap();
+        assert!(parsed.verify().is_err());
+        parsed.decompress().unwrap();
+        assert_eq!(parsed, original);
+        assert!(parsed.verify().is_ok());
+    }
+
+    #[test]
+    fn test_wire_fragment() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let body: Vec<u8> = (0..FRAGMENT_SIZE * 3).map(|_| rand::random()).collect();
+        let message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::random(),
+            body,
+        );
+        let mut wire = Transaction::new(message).to_wire();
+        wire.sign(&center);
+        let mut fragments = wire.clone().fragment().unwrap();
+        assert_eq!(fragments.len(), 4);
+        assert!(fragments
+            .iter()
+            .all(|f| f.is_fragment() && f.uuid == wire.uuid));
+        let mut reassembly = Fragments::new();
+        // Order and duplicates don't matter.
+        fragments.swap(0, 3);
+        let last = fragments.pop().unwrap();
+        for fragment in fragments.iter().chain(fragments.iter()) {
+            let parsed = Wire::from_bytes(&fragment.as_bytes()).unwrap();
+            assert!(reassembly.insert(parsed).unwrap().is_none());
+        }
+        assert_eq!(reassembly.len(), 1);
+        let restored = reassembly.insert(last).unwrap().unwrap();
+        assert!(reassembly.is_empty());
+        assert_eq!(restored, wire);
+        assert!(restored.verify().is_ok());
+        assert_eq!(restored.convert().unwrap(), wire.convert().unwrap());
+    }
+
+    #[test]
+    fn test_wire_fragment_small() {
+        let wire = Wire::from_bytes(&generate_test_data()).unwrap();
+        let fragments = wire.clone().fragment().unwrap();
+        assert_eq!(fragments, vec![wire.clone()]);
+        assert_eq!(Fragments::new().insert(wire.clone()).unwrap(), Some(wire));
+    }
+
+    #[test]
+    fn test_wire_fragment_invalid() {
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42; FRAGMENT_SIZE * 2],
+        );
+        let wire = Transaction::new(message).to_wire();
+        let mut fragments = wire.fragment().unwrap();
+        let mut reassembly = Fragments::new();
+        assert!(reassembly.insert(fragments[0].clone()).unwrap().is_none());
+        // A different count for the same Wire.
+        let mut body = fragments[1].body.to_vec();
+        body[3] = 7;
+        fragments[1].body = Bytes::from(body.clone());
+        assert!(reassembly.insert(fragments[1].clone()).is_err());
+        assert!(reassembly.is_empty());
+        body[0] = 9;
+        fragments[1].body = Bytes::from(body);
+        assert!(reassembly.insert(fragments[1].clone()).is_err());
+    }
+
+    #[test]
+    fn test_wire_fragment_limit() {
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42; FRAGMENT_SIZE * 4],
+        );
+        let wire = Transaction::new(message).to_wire();
+        let fragments = wire.fragment().unwrap();
+        let mut reassembly = Fragments::with_limit(FRAGMENT_SIZE * 2);
+        match reassembly.insert(fragments[0].clone()) {
+            Err(Error::Protocol(_)) => {}
+            other => panic!("fragment was accepted: {:?}", other),
+        }
+        assert!(Wire::bounded(FRAGMENT_SIZE, FRAGMENT_SIZE).is_ok());
+
+        // Only a limited number of Wires get reassembled at once.
+        let mut reassembly = Fragments::new();
+        for _ in 0..FRAGMENT_PENDING {
+            let mut fragment = fragments[0].clone();
+            fragment.uuid = Uuid::new_v4().as_bytes().to_owned();
+            assert!(reassembly.insert(fragment).unwrap().is_none());
+        }
+        assert!(matches!(
+            reassembly.insert(fragments[0].clone()),
+            Err(Error::Busy(_))
+        ));
+        assert!(Wire::bounded(FRAGMENT_SIZE + 1, FRAGMENT_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_transaction_keeps_signature() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let mut wire = Transaction::new(message).to_wire();
+        wire.sign(&center);
+        let t = Transaction::from_wire(&wire).unwrap();
+        assert!(t.to_wire().verify().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_author() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let mut message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        message.sequence = Some(7);
+        let mut t = Transaction::new(message);
+        assert!(t.verify().is_err());
+        t.sign(&center);
+        t.hops = 3;
+        assert!(t.verify().is_ok());
+
+        let mut wire = t.to_wire();
+        wire.sign(&center);
+        let parsed = Transaction::from_bytes(&wire.as_bytes()).unwrap();
+        assert!(parsed.to_wire().verify().is_ok());
+        assert_eq!(parsed.class(), Class::Action);
+        assert_eq!(parsed.message.body.as_bytes(), vec![42]);
+        assert_eq!(parsed.message.sequence, Some(7));
+        assert_eq!(parsed.author(), t.author());
+        assert!(parsed.verify().is_ok());
+        assert!(parsed.verified_source().is_none());
+
+        let mut verified = parsed.clone();
+        verified.set_verified(true);
+        assert_eq!(verified.verified_source(), Some(center.public.clone()));
+
+        // Copies sent to another node or under a new UUID are replays.
+        assert!(parsed.redirect(Address::random()).verify().is_err());
+        let mut replayed = parsed.clone();
+        replayed.uuid = Uuid::new_v4();
+        assert!(replayed.verify().is_err());
+
+        let mut forged = parsed.clone();
+        forged.message.body = crate::message::Body::new(vec![43]);
+        assert!(forged.verify().is_err());
+        forged.message.body = crate::message::Body::new(vec![42]);
+        forged.message.source = Address::random();
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_transaction_metadata() {
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let mut t = Transaction::new(message);
+        let metadata = t.metadata();
+        assert_eq!(metadata.hops, 0);
+        assert_eq!(metadata.topic, t.topic());
+        assert!(metadata.ingress.is_none());
+        assert!(!metadata.encrypted && !metadata.verified && !metadata.wildcard);
+
+        assert!(t.hop() && t.hop());
+        let mut received = Transaction::from_wire(&t.to_wire()).unwrap();
+        let peer = Address::random();
+        received.set_ingress(peer.clone());
+        received.set_encrypted(true);
+        received.set_wildcard(true);
+        let metadata = received.metadata();
+        assert_eq!(metadata.hops, 2);
+        assert_eq!(metadata.ingress, Some(peer));
+        assert!(metadata.encrypted && metadata.wildcard);
+        assert!(metadata.received >= t.metadata().received);
+    }
+
+    #[test]
+    fn test_transaction_sequence() {
+        let mut message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42, 7],
+        );
+        message.sequence = Some(258);
+        let wire = Transaction::new(message).to_wire();
+        assert_eq!(wire.body().len(), 10);
+        let t = Transaction::from_bytes(&wire.as_bytes()).unwrap();
+        assert_eq!(t.message.sequence, Some(258));
+        assert_eq!(t.class(), Class::Action);
+        assert_eq!(t.message.body.as_bytes(), vec![42, 7]);
+
+        let mut bytes = Transaction::new(t.message.clone()).to_wire().as_bytes();
+        bytes.truncate(142 + 4);
+        bytes[1] = 4;
+        assert!(Transaction::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn test_transaction_priority() {
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let mut t = Transaction::new(message);
+        assert_eq!(t.priority, Priority::Low);
+        assert_eq!(t.to_wire().priority(), Priority::Low);
+        t.priority = Priority::High;
+        let wire = t.to_wire();
+        assert_eq!(wire.priority(), Priority::High);
+        let parsed = Transaction::from_bytes(&wire.as_bytes()).unwrap();
+        assert_eq!(parsed.priority, Priority::High);
+        assert_eq!(parsed.class(), Class::Action);
+
+        let mut message = t.message.clone();
+        message.class = Class::Ping;
+        assert_eq!(Transaction::new(message).priority, Priority::High);
+    }
+
+    #[test]
+    fn test_transaction_hops() {
+        let center = Center::new(box_::gen_keypair().1, String::from(""), 0);
+        let message = Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let t = Transaction::new(message);
+        assert_eq!(t.to_wire().body().len(), 1);
+        let deadline = SystemTime::UNIX_EPOCH + Duration::from_millis(4_102_444_800_000);
+        for deadline in [None, Some(deadline)].iter() {
+            let mut t = t.clone();
+            t.deadline = *deadline;
+            let mut wire = t.to_wire();
+            wire.sign(&center);
+
+            // Using up hops doesn't invalidate the signature.
+            let mut t = Transaction::from_wire(&wire).unwrap();
+            assert!(!t.is_forwarded());
+            assert!(t.hop());
+            assert!(t.is_forwarded());
+            let wire = t.to_wire();
+            assert_eq!(wire.body().len(), 1 + LIMIT_LENGTH);
+            assert!(wire.verify().is_ok());
+            let t = Transaction::from_wire(&wire).unwrap();
+            assert_eq!((t.hops, t.deadline), (HOPS - 1, *deadline));
+            assert_eq!(t.message.body.as_bytes(), vec![42]);
+        }
+
+        // Forwarders can't add hops beyond the initial ones.
+        let mut wire = t.to_wire();
+        wire.sign(&center);
+        let mut forged = Transaction::from_wire(&wire).unwrap();
+        forged.hops = HOPS + 1;
+        let forged = forged.to_wire();
+        assert!(forged.verify().is_err());
+        assert!(forged.convert().is_err());
+
+        let mut t = t.clone();
+        t.hops = 0;
+        assert!(!t.hop());
+        t.hops = HOPS;
+        t.deadline = Some(SystemTime::now() - Duration::from_secs(1));
+        assert!(t.is_expired());
+        assert!(!t.hop());
+    }
+
     #[test]
     fn test_empty_wire() {
         let bytes = [0; 142];
         let wire = Wire::from_bytes(&bytes);
-        assert_eq!(wire.is_err(), false);
-        assert_eq!(wire.unwrap().is_empty(), true);
+        assert!(wire.is_ok());
+        assert!(wire.unwrap().is_empty());
     }
 
     fn generate_test_data() -> Vec<u8> {
         let mut data: Vec<u8> = Vec::new();
 
-        data.append(&mut [0, 8].to_vec());
+        data.append(&mut [0, 4].to_vec());
         data.append(&mut [0, 0, 0, 1].to_vec());
 
         let source = Address::generate("abc").as_bytes().to_owned().to_vec();
@@ -562,12 +1942,12 @@ mod tests {
         data.append(&mut target.clone());
         let target = Address::generate("tpc").as_bytes().to_owned().to_vec();
         data.append(&mut target.clone());
-        let uuid = Uuid::parse_str(&mut "27d626f0-1515-47d4-a366-0b75ce6950bf").unwrap();
+        let uuid = Uuid::parse_str("27d626f0-1515-47d4-a366-0b75ce6950bf").unwrap();
         data.append(&mut uuid.clone().as_bytes().to_vec());
 
         data.append(&mut [0; 24].to_vec());
 
         data.append(&mut "test".to_string().into_bytes());
-        return data;
+        data
     }
 }
@@ -0,0 +1,1019 @@
+//! # Transport
+//!
+//! The Listener isn't bound to TCP, instead it uses a Transport for
+//! accepting and establishing connections, each of which is a Stream
+//! of Wires. TCP, UDP and WebSockets are built in, other transports
+//! can be used by implementing both traits and passing them through
+//! the Config.
+
+use crate::error::Error;
+use crate::handshake::{self, Features, Hello};
+use crate::node::{Address, Link};
+use crate::transaction::Wire;
+use crate::util;
+use std::collections::{HashMap, VecDeque};
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tungstenite::handshake::HandshakeError;
+use tungstenite::Message;
+
+/// Default timeout of connecting, blocking reads and writes. Without
+/// one a peer that never answers, or lost datagrams, would block the
+/// Listener or a dialing thread forever.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum time the peer of a non-blocking TCP Stream may accept none
+/// of the waiting data before the connection counts as lost.
+const TCP_STALL: Duration = Duration::from_secs(5);
+
+/// Most bytes a non-blocking TCP Stream keeps waiting for the peer.
+const TCP_BACKLOG: usize = 1 << 20;
+
+/// Length of the fixed header in front of every Wire.
+const HEADER: usize = 142;
+
+/// Largest possible UDP payload, Wires have to fit into a single
+/// datagram.
+const UDP_LENGTH: usize = 65507;
+
+/// How long a blocking accepted UDP Stream waits for its queue before
+/// reading the shared socket again.
+const UDP_WAIT: Duration = Duration::from_millis(5);
+
+/// Accepts and establishes connections. The Listener thread accepts
+/// through the Transport, new connections are also established from
+/// the dialing threads. The Streams it creates get moved to the I/O
+/// threads.
+pub trait Transport: Send + Sync {
+    /// Starts listening on the given Link, called once for every Link
+    /// the node listens on before any other function. Connections on
+    /// all of them get returned by accept.
+    fn bind(&mut self, link: &Link) -> Result<(), Error>;
+
+    /// Returns a new incoming connection should one be available.
+    /// This must not block, since it gets called in the Listener
+    /// loop.
+    fn accept(&self) -> Option<Box<dyn Stream>>;
+
+    /// Establishes a new connection to the given Link.
+    fn connect(&self, link: &Link) -> Result<Box<dyn Stream>, Error>;
+
+    /// Sets the timeouts of connect and of blocking reads and writes
+    /// of all Streams created afterwards, called before bind. Blocking
+    /// operations exceeding them should fail with Error::Timeout.
+    fn set_timeouts(&mut self, _timeouts: Timeouts) {}
+
+    /// Sockets of all bound Links, the Listener waits until one of
+    /// them becomes readable instead of calling accept all the time.
+    /// Transports without them get polled periodically.
+    #[cfg(unix)]
+    fn raw_fds(&self) -> Vec<RawFd> {
+        Vec::new()
+    }
+}
+
+/// Limits how long blocking operations wait for the peer, all of them
+/// are 5 seconds by default.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    /// Establishing a new connection.
+    pub connect: Duration,
+    /// Waiting for data from the peer.
+    pub read: Duration,
+    /// Waiting for the peer to accept written data.
+    pub write: Duration,
+}
+
+/// A single connection to another node. After the Node handshake
+/// only Wires get exchanged.
+pub trait Stream: Send {
+    /// Reads the next Wire. In non-blocking mode an error is returned
+    /// if no complete Wire is available. Wires larger than the limit
+    /// in bytes fail with a protocol error, ideally before they are
+    /// read into memory.
+    fn read_wire(&mut self, limit: usize) -> Result<Wire, Error>;
+
+    /// Writes an entire Wire.
+    fn write_wire(&mut self, wire: &Wire) -> Result<(), Error>;
+
+    /// Writes several Wires at once. Streams that can should pack
+    /// them into a single write, by default they are written one
+    /// after another. Since every Wire contains its length the other
+    /// side reads them like any others.
+    fn write_wires(&mut self, wires: &[Wire]) -> Result<(), Error> {
+        for wire in wires {
+            self.write_wire(wire)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the handshake of the other side, it is always the first
+    /// thing sent through a new connection. Plain Node bytes of
+    /// legacy nodes have to be accepted as well.
+    fn read_node(&mut self) -> Result<Hello, Error>;
+
+    /// Sends the handshake to the other side.
+    fn write_node(&mut self, hello: &Hello) -> Result<(), Error>;
+
+    /// I/O threads only use non-blocking Streams, the handshake
+    /// is blocking.
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Error>;
+
+    /// Writes data a non-blocking Stream couldn't write right away,
+    /// returns true while some of it is still waiting.
+    fn drain(&mut self) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    /// Socket the I/O threads wait on until it becomes readable or
+    /// writable. Streams without a socket of their own get polled
+    /// periodically instead.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// The default Transport, every connection is a BufferedTcp.
+#[derive(Default)]
+pub struct Tcp {
+    /// One for every bound Link.
+    listeners: Vec<TcpListener>,
+    timeouts: Timeouts,
+}
+
+/// Every Wire is sent as a single datagram. Incoming datagrams all
+/// arrive on the same socket and get passed on to the Stream of the
+/// sender, an unknown sender is a new connection.
+#[derive(Default)]
+pub struct Udp {
+    /// One for every bound Link.
+    sockets: Vec<Arc<UdpSocket>>,
+    /// Shared with the accepted Streams, which read the sockets
+    /// themselves while they block during the handshake.
+    demux: Arc<Demux>,
+    timeouts: Timeouts,
+}
+
+/// Socket, sender and queue of a Stream waiting to be accepted.
+type Fresh = (Arc<UdpSocket>, SocketAddr, Receiver<Vec<u8>>);
+
+/// Passes incoming datagrams on to the Stream of their sender.
+#[derive(Default)]
+struct Demux {
+    /// Queues of all accepted Streams by the address of the peer.
+    peers: Mutex<HashMap<SocketAddr, Sender<Vec<u8>>>>,
+    /// Streams of new senders that were not accepted yet.
+    fresh: Mutex<VecDeque<Fresh>>,
+}
+
+/// Every Wire is sent as a single binary WebSocket message, which
+/// allows connections from gateways or clients that can only speak
+/// WebSockets. The Node handshake is the first message in both
+/// directions, all other message types get ignored.
+#[derive(Default)]
+pub struct WebSocket {
+    /// One for every bound Link.
+    listeners: Vec<TcpListener>,
+    timeouts: Timeouts,
+}
+
+/// A TcpStream with buffers for partially read and written Wires, so
+/// non-blocking Streams never have to wait for the peer.
+pub struct BufferedTcp {
+    stream: TcpStream,
+    /// Bytes of the Wire that is currently being read.
+    incoming: Vec<u8>,
+    /// Bytes the socket didn't accept yet.
+    outgoing: Vec<u8>,
+    /// Since when none of the outgoing bytes got accepted.
+    stalled: Option<Instant>,
+    nonblocking: bool,
+}
+
+/// A single WebSocket connection, the opening handshake is already
+/// completed.
+struct WebSocketStream(tungstenite::WebSocket<TcpStream>);
+
+/// UDP connections are either established by this node, in which
+/// case they have a dedicated socket, or accepted, in which case
+/// they share the socket of the Transport.
+enum UdpStream {
+    /// Outgoing connection with its own connected socket.
+    Connected(UdpSocket),
+    /// Incoming connection fed by the Transport.
+    Accepted {
+        /// The bound socket of the Transport, used for sending.
+        socket: Arc<UdpSocket>,
+        /// Address of the other side.
+        peer: SocketAddr,
+        /// Datagrams received from the peer.
+        queue: Receiver<Vec<u8>>,
+        /// Mirrors set_nonblocking, since the queue has no such
+        /// setting.
+        nonblocking: bool,
+        /// Read timeout in blocking mode.
+        timeout: Duration,
+        /// Reads the socket while blocking, since the Transport only
+        /// does so when accepting.
+        demux: Arc<Demux>,
+    },
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: TIMEOUT,
+            read: TIMEOUT,
+            write: TIMEOUT,
+        }
+    }
+}
+
+impl Timeouts {
+    /// Longest time dialing a peer may take: Connecting, exchanging
+    /// the handshake and writing the first Wire.
+    pub fn dial(&self) -> Duration {
+        self.connect + self.read + self.write
+    }
+
+    /// Applies the read and write timeouts to the TcpStream.
+    fn apply(&self, stream: &TcpStream) -> Result<(), Error> {
+        stream.set_read_timeout(Some(self.read))?;
+        stream.set_write_timeout(Some(self.write))?;
+        Ok(())
+    }
+}
+
+impl Tcp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Transport for Tcp {
+    fn bind(&mut self, link: &Link) -> Result<(), Error> {
+        let listener = TcpListener::bind(link)?;
+        listener.set_nonblocking(true)?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    fn accept(&self) -> Option<Box<dyn Stream>> {
+        let (stream, _) = self
+            .listeners
+            .iter()
+            .find_map(|listener| listener.accept().ok())?;
+        // Accepted sockets don't inherit the non-blocking mode on
+        // all platforms.
+        stream.set_nonblocking(false).ok()?;
+        self.timeouts.apply(&stream).ok()?;
+        Some(Box::new(BufferedTcp::new(stream)))
+    }
+
+    #[cfg(unix)]
+    fn raw_fds(&self) -> Vec<RawFd> {
+        self.listeners.iter().map(|l| l.as_raw_fd()).collect()
+    }
+
+    fn connect(&self, link: &Link) -> Result<Box<dyn Stream>, Error> {
+        Ok(Box::new(BufferedTcp::new(dial(link, &self.timeouts)?)))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl Stream for BufferedTcp {
+    fn read_wire(&mut self, limit: usize) -> Result<Wire, Error> {
+        self.receive(HEADER)?;
+        let header = &self.incoming[..HEADER];
+        let length = HEADER + util::get_length(header) + Wire::trailer_length(header);
+        Wire::bounded(length, limit)?;
+        self.receive(length)?;
+        let message: Vec<u8> = self.incoming.drain(..length).collect();
+        Wire::parse(message.into())
+    }
+
+    fn write_wire(&mut self, wire: &Wire) -> Result<(), Error> {
+        self.send(&wire.as_bytes())
+    }
+
+    /// All Wires are copied into one buffer, so they only need a
+    /// single syscall and TCP segment.
+    fn write_wires(&mut self, wires: &[Wire]) -> Result<(), Error> {
+        let mut data = Vec::new();
+        for wire in wires {
+            data.append(&mut wire.as_bytes());
+        }
+        self.send(&data)
+    }
+
+    /// Legacy nodes only send the Node, in that case the prefix is
+    /// already the start of it.
+    fn read_node(&mut self) -> Result<Hello, Error> {
+        let mut prefix = [0; handshake::PREFIX];
+        self.exact(&mut prefix).map_err(timed_out)?;
+        let mut data = Vec::new();
+        let mut header = [0; 34];
+        if prefix[..3] == handshake::MAGIC {
+            data.extend_from_slice(&prefix);
+            self.exact(&mut header).map_err(timed_out)?;
+        } else {
+            header[..handshake::PREFIX].copy_from_slice(&prefix);
+            self.exact(&mut header[handshake::PREFIX..])
+                .map_err(timed_out)?;
+        }
+        let length = util::get_length(&header);
+        let mut link = vec![0; length];
+        self.exact(&mut link).map_err(timed_out)?;
+        // Invalid keys would otherwise only fail inside of the Node.
+        Address::from_slice(&header[2..])?;
+        data.extend_from_slice(&header);
+        data.append(&mut link);
+        if prefix[..3] == handshake::MAGIC
+            && Features::from_bits(prefix[4]).contains(Features::TOKEN)
+        {
+            let mut length = [0; 2];
+            self.exact(&mut length).map_err(timed_out)?;
+            let mut token = vec![0; util::get_length(&length)];
+            self.exact(&mut token).map_err(timed_out)?;
+            data.extend_from_slice(&length);
+            data.append(&mut token);
+        }
+        if prefix[..3] == handshake::MAGIC
+            && Features::from_bits(prefix[4]).contains(Features::LINKS)
+        {
+            let mut count = [0; 1];
+            self.exact(&mut count).map_err(timed_out)?;
+            data.push(count[0]);
+            for _ in 0..count[0] {
+                let mut length = [0; 2];
+                self.exact(&mut length).map_err(timed_out)?;
+                let mut link = vec![0; util::get_length(&length)];
+                self.exact(&mut link).map_err(timed_out)?;
+                data.extend_from_slice(&length);
+                data.append(&mut link);
+            }
+        }
+        if prefix[..3] == handshake::MAGIC
+            && Features::from_bits(prefix[4]).contains(Features::PROOF)
+        {
+            let mut nonce = [0; handshake::NONCE_LENGTH];
+            self.exact(&mut nonce).map_err(timed_out)?;
+            data.extend_from_slice(&nonce);
+        }
+        Hello::from_bytes(data)
+    }
+
+    fn write_node(&mut self, hello: &Hello) -> Result<(), Error> {
+        self.send(&hello.as_bytes())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Error> {
+        self.stream.set_nonblocking(nonblocking)?;
+        self.nonblocking = nonblocking;
+        Ok(())
+    }
+
+    fn drain(&mut self) -> Result<bool, Error> {
+        self.write_waiting()?;
+        Ok(!self.outgoing.is_empty())
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.stream.as_raw_fd())
+    }
+}
+
+/// Connects to the first reachable address of the Link within the
+/// connect timeout, the read and write timeouts apply to the new
+/// TcpStream.
+fn dial(link: &Link, timeouts: &Timeouts) -> Result<TcpStream, Error> {
+    let mut last = None;
+    for addr in link.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeouts.connect) {
+            Ok(stream) => {
+                timeouts.apply(&stream)?;
+                return Ok(stream);
+            }
+            Err(e) => last = Some(e),
+        }
+    }
+    match last {
+        Some(e) => Err(timed_out(e)),
+        None => Err(Error::Connection(String::from("link can't be resolved"))),
+    }
+}
+
+/// Blocking operations exceeding their timeout fail with WouldBlock
+/// or TimedOut depending on the platform, both become a Timeout.
+fn timed_out(e: std::io::Error) -> Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+            Error::Timeout(String::from("peer did not answer in time"))
+        }
+        _ => e.into(),
+    }
+}
+
+impl BufferedTcp {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            stalled: None,
+            nonblocking: false,
+        }
+    }
+
+    /// Reads until at least length bytes are buffered. Non-blocking
+    /// Streams return Busy once the socket has no more data, the
+    /// bytes read so far are kept for the next attempt.
+    fn receive(&mut self, length: usize) -> Result<(), Error> {
+        while self.incoming.len() < length {
+            let start = self.incoming.len();
+            self.incoming.resize(length, 0);
+            let result = self.stream.read(&mut self.incoming[start..]);
+            let read = *result.as_ref().unwrap_or(&0);
+            self.incoming.truncate(start + read);
+            match result {
+                Ok(0) => return Err(Error::Connection(String::from("connection is closed"))),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock && self.nonblocking => {
+                    return Err(Error::Busy(String::from("no wire available")));
+                }
+                Err(e) => return Err(timed_out(e)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like read_exact, but takes buffered bytes first.
+    fn exact(&mut self, buffer: &mut [u8]) -> std::io::Result<()> {
+        let buffered = self.incoming.len().min(buffer.len());
+        buffer[..buffered].copy_from_slice(&self.incoming[..buffered]);
+        self.incoming.drain(..buffered);
+        self.stream.read_exact(&mut buffer[buffered..])
+    }
+
+    /// Blocking Streams write all of the data. Non-blocking ones keep
+    /// whatever the socket doesn't accept right away, it gets written
+    /// by later calls or drain.
+    fn send(&mut self, data: &[u8]) -> Result<(), Error> {
+        if !self.nonblocking {
+            self.write_waiting()?;
+            return self.stream.write_all(data).map_err(timed_out);
+        }
+        if self.outgoing.len() + data.len() > TCP_BACKLOG {
+            return Err(Error::Connection(String::from("peer does not keep up")));
+        }
+        self.outgoing.extend_from_slice(data);
+        self.write_waiting()
+    }
+
+    /// Writes as much of the waiting data as the socket accepts. Fails
+    /// once the peer accepted nothing for TCP_STALL.
+    fn write_waiting(&mut self) -> Result<(), Error> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.outgoing.len() {
+                break Ok(());
+            }
+            match self.stream.write(&self.outgoing[written..]) {
+                Ok(0) => break Err(Error::Connection(String::from("connection is closed"))),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock && self.nonblocking => break Ok(()),
+                Err(e) => break Err(timed_out(e)),
+            }
+        };
+        self.outgoing.drain(..written);
+        if self.outgoing.is_empty() || written > 0 {
+            self.stalled = None;
+        } else if self.stalled.get_or_insert_with(Instant::now).elapsed() >= TCP_STALL {
+            return Err(Error::Connection(String::from("peer stopped reading")));
+        }
+        result
+    }
+}
+
+impl Udp {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Transport for Udp {
+    fn bind(&mut self, link: &Link) -> Result<(), Error> {
+        let socket = UdpSocket::bind(link)?;
+        socket.set_nonblocking(true)?;
+        self.sockets.push(Arc::new(socket));
+        Ok(())
+    }
+
+    /// Reads all available datagrams of every socket and passes them
+    /// on to their Streams. Returns the Stream of the first unknown
+    /// sender, if there is one.
+    fn accept(&self) -> Option<Box<dyn Stream>> {
+        for socket in &self.sockets {
+            self.demux.pump(socket);
+        }
+        let (socket, peer, queue) = self.demux.fresh.lock().unwrap().pop_front()?;
+        Some(Box::new(UdpStream::Accepted {
+            socket,
+            peer,
+            queue,
+            nonblocking: false,
+            timeout: self.timeouts.read,
+            demux: self.demux.clone(),
+        }))
+    }
+
+    fn connect(&self, link: &Link) -> Result<Box<dyn Stream>, Error> {
+        let addr = link
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::Connection(String::from("link can't be resolved")))?;
+        let local = match addr {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+        socket.set_read_timeout(Some(self.timeouts.read))?;
+        socket.set_write_timeout(Some(self.timeouts.write))?;
+        Ok(Box::new(UdpStream::Connected(socket)))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl Demux {
+    /// Reads all available datagrams of the socket. Unknown senders
+    /// get a new queue, which is returned by the next accept.
+    fn pump(&self, socket: &Arc<UdpSocket>) {
+        let mut peers = self.peers.lock().unwrap();
+        let mut buffer = [0; UDP_LENGTH];
+        while let Ok((length, peer)) = socket.recv_from(&mut buffer) {
+            let datagram = buffer[..length].to_vec();
+            // A closed queue means the Stream is gone, so the sender
+            // is treated like a new connection.
+            let datagram = match peers.get(&peer) {
+                Some(sender) => match sender.send(datagram) {
+                    Ok(()) => continue,
+                    Err(mpsc::SendError(datagram)) => datagram,
+                },
+                None => datagram,
+            };
+            let (sender, queue) = mpsc::channel();
+            let _ = sender.send(datagram);
+            peers.insert(peer, sender);
+            self.fresh
+                .lock()
+                .unwrap()
+                .push_back((socket.clone(), peer, queue));
+        }
+    }
+}
+
+impl UdpStream {
+    /// Receives a single datagram, respecting the blocking mode.
+    fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Connected(socket) => {
+                let mut buffer = vec![0; UDP_LENGTH];
+                let length = socket.recv(&mut buffer).map_err(timed_out)?;
+                buffer.truncate(length);
+                Ok(buffer)
+            }
+            Self::Accepted {
+                socket,
+                queue,
+                nonblocking,
+                timeout,
+                demux,
+                ..
+            } => {
+                if *nonblocking {
+                    return queue.try_recv().map_err(|e| match e {
+                        TryRecvError::Empty => Error::Busy(String::from("no datagram available")),
+                        TryRecvError::Disconnected => {
+                            Error::Connection(String::from("transport is closed"))
+                        }
+                    });
+                }
+                // Nobody else reads the socket while the handshake
+                // blocks the Listener.
+                let deadline = Instant::now() + *timeout;
+                loop {
+                    demux.pump(socket);
+                    match queue.recv_timeout(UDP_WAIT) {
+                        Ok(datagram) => return Ok(datagram),
+                        Err(_) if Instant::now() < deadline => {}
+                        Err(_) => return Err(Error::Timeout(String::from("no datagram received"))),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends a single datagram to the peer.
+    fn send(&mut self, datagram: &[u8]) -> Result<(), Error> {
+        if datagram.len() > UDP_LENGTH {
+            return Err(Error::Invalid(String::from("wire is too large for udp")));
+        }
+        match self {
+            Self::Connected(socket) => socket.send(datagram)?,
+            Self::Accepted { socket, peer, .. } => socket.send_to(datagram, *peer)?,
+        };
+        Ok(())
+    }
+}
+
+impl Stream for UdpStream {
+    fn read_wire(&mut self, limit: usize) -> Result<Wire, Error> {
+        let datagram = self.recv()?;
+        Wire::bounded(datagram.len(), limit)?;
+        Wire::parse(datagram.into())
+    }
+
+    fn write_wire(&mut self, wire: &Wire) -> Result<(), Error> {
+        self.send(&wire.as_bytes())
+    }
+
+    fn read_node(&mut self) -> Result<Hello, Error> {
+        let datagram = self.recv()?;
+        Hello::from_bytes(datagram)
+    }
+
+    fn write_node(&mut self, hello: &Hello) -> Result<(), Error> {
+        self.send(&hello.as_bytes())
+    }
+
+    fn set_nonblocking(&mut self, value: bool) -> Result<(), Error> {
+        match self {
+            Self::Connected(socket) => socket.set_nonblocking(value)?,
+            Self::Accepted { nonblocking, .. } => *nonblocking = value,
+        }
+        Ok(())
+    }
+
+    /// Accepted Streams share the socket of the Transport, their
+    /// datagrams only arrive through the queue.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        match self {
+            Self::Connected(socket) => Some(socket.as_raw_fd()),
+            Self::Accepted { .. } => None,
+        }
+    }
+}
+
+impl WebSocket {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Transport for WebSocket {
+    fn bind(&mut self, link: &Link) -> Result<(), Error> {
+        let listener = TcpListener::bind(link)?;
+        listener.set_nonblocking(true)?;
+        self.listeners.push(listener);
+        Ok(())
+    }
+
+    /// Accepts the TCP connection and completes the WebSocket
+    /// handshake. Connections with a failed handshake get dropped.
+    fn accept(&self) -> Option<Box<dyn Stream>> {
+        let (stream, _) = self
+            .listeners
+            .iter()
+            .find_map(|listener| listener.accept().ok())?;
+        stream.set_nonblocking(false).ok()?;
+        // The handshake blocks the Listener thread.
+        self.timeouts.apply(&stream).ok()?;
+        let socket = tungstenite::accept(stream).ok()?;
+        Some(Box::new(WebSocketStream(socket)))
+    }
+
+    #[cfg(unix)]
+    fn raw_fds(&self) -> Vec<RawFd> {
+        self.listeners.iter().map(|l| l.as_raw_fd()).collect()
+    }
+
+    fn connect(&self, link: &Link) -> Result<Box<dyn Stream>, Error> {
+        let stream = dial(link, &self.timeouts)?;
+        let (socket, _) =
+            tungstenite::client(format!("ws://{}/", link), stream).map_err(|e| match e {
+                HandshakeError::Failure(tungstenite::Error::Io(e)) => timed_out(e),
+                HandshakeError::Failure(e) => e.into(),
+                HandshakeError::Interrupted(_) => {
+                    Error::Handshake(String::from("websocket handshake was interrupted"))
+                }
+            })?;
+        Ok(Box::new(WebSocketStream(socket)))
+    }
+
+    fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl WebSocketStream {
+    /// Reads the next binary message.
+    fn recv(&mut self) -> Result<Vec<u8>, Error> {
+        match self.0.read() {
+            Ok(Message::Binary(data)) => Ok(data),
+            Ok(_) => Err(Error::Invalid(String::from("message is not binary"))),
+            Err(tungstenite::Error::Capacity(e)) => Err(Error::Protocol(e.to_string())),
+            Err(tungstenite::Error::Io(e)) => Err(timed_out(e)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Sends a single binary message. If the socket isn't ready the
+    /// message stays buffered and gets flushed on the next read or
+    /// write.
+    fn send(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        match self.0.send(Message::Binary(data)) {
+            Err(tungstenite::Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+            result => Ok(result?),
+        }
+    }
+}
+
+impl Stream for WebSocketStream {
+    fn read_wire(&mut self, limit: usize) -> Result<Wire, Error> {
+        // Larger messages get rejected while their frames arrive.
+        self.0.set_config(|config| {
+            config.max_message_size = Some(limit);
+            config.max_frame_size = Some(limit);
+        });
+        let data = self.recv()?;
+        Wire::bounded(data.len(), limit)?;
+        Wire::parse(data.into())
+    }
+
+    fn write_wire(&mut self, wire: &Wire) -> Result<(), Error> {
+        self.send(wire.as_bytes())
+    }
+
+    fn read_node(&mut self) -> Result<Hello, Error> {
+        let data = self.recv()?;
+        Hello::from_bytes(data)
+    }
+
+    fn write_node(&mut self, hello: &Hello) -> Result<(), Error> {
+        self.send(hello.as_bytes())
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> Result<(), Error> {
+        self.0.get_ref().set_nonblocking(nonblocking)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.0.get_ref().as_raw_fd())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handshake::Features;
+    use crate::message::Message;
+    use crate::node::{Center, Node};
+    use crate::transaction::{Class, Transaction, MESSAGE_SIZE};
+    use sodiumoxide::crypto::box_;
+
+    #[test]
+    fn test_udp_handshake() {
+        let (_, secret) = box_::gen_keypair();
+        let lcenter = Center::new(secret, String::from("127.0.0.1"), 45610);
+        let (_, secret) = box_::gen_keypair();
+        let rcenter = Center::new(secret, String::from("127.0.0.1"), 45611);
+        let mut local = Udp::new();
+        local.bind(&lcenter.link).unwrap();
+
+        let mut outgoing = Udp::new().connect(&lcenter.link).unwrap();
+        outgoing
+            .write_node(&Hello::new(&rcenter, Features::empty()))
+            .unwrap();
+
+        let mut incoming = loop {
+            if let Some(stream) = local.accept() {
+                break stream;
+            }
+        };
+        assert_eq!(incoming.read_node().unwrap().node.address, rcenter.public);
+        incoming
+            .write_node(&Hello::new(&lcenter, Features::empty()))
+            .unwrap();
+        assert_eq!(outgoing.read_node().unwrap().node.address, lcenter.public);
+
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let wire = Transaction::new(message).to_wire();
+        outgoing.write_wire(&wire).unwrap();
+        incoming.set_nonblocking(true).unwrap();
+        let received = loop {
+            assert!(local.accept().is_none());
+            if let Ok(wire) = incoming.read_wire(MESSAGE_SIZE) {
+                break wire;
+            }
+        };
+        assert_eq!(received, wire);
+        incoming.write_wire(&wire).unwrap();
+        assert_eq!(outgoing.read_wire(MESSAGE_SIZE).unwrap(), wire);
+    }
+
+    #[test]
+    fn test_tcp_bind_multiple() {
+        let mut local = Tcp::new();
+        local
+            .bind(&Link::new(String::from("127.0.0.1"), 45668))
+            .unwrap();
+        local
+            .bind(&Link::new(String::from("127.0.0.1"), 45669))
+            .unwrap();
+        assert!(local.accept().is_none());
+
+        // Connections on every bound Link get accepted.
+        for port in [45669, 45668] {
+            let link = Link::new(String::from("127.0.0.1"), port);
+            let _outgoing = Tcp::new().connect(&link).unwrap();
+            let start = Instant::now();
+            let mut incoming = None;
+            while incoming.is_none() && start.elapsed() < Duration::from_secs(5) {
+                incoming = local.accept();
+            }
+            assert!(incoming.is_some());
+        }
+    }
+
+    #[test]
+    fn test_websocket_handshake() {
+        let (_, secret) = box_::gen_keypair();
+        let lcenter = Center::new(secret, String::from("127.0.0.1"), 45612);
+        let (_, secret) = box_::gen_keypair();
+        let rcenter = Center::new(secret, String::from("127.0.0.1"), 45613);
+        let mut local = WebSocket::new();
+        local.bind(&lcenter.link).unwrap();
+
+        let link = lcenter.link.clone();
+        let center = rcenter.clone();
+        let remote = std::thread::spawn(move || {
+            let mut outgoing = WebSocket::new().connect(&link).unwrap();
+            outgoing
+                .write_node(&Hello::new(&center, Features::empty()))
+                .unwrap();
+            outgoing
+        });
+
+        let mut incoming = loop {
+            if let Some(stream) = local.accept() {
+                break stream;
+            }
+        };
+        let mut outgoing = remote.join().unwrap();
+        assert_eq!(incoming.read_node().unwrap().node.address, rcenter.public);
+        incoming
+            .write_node(&Hello::new(&lcenter, Features::empty()))
+            .unwrap();
+        assert_eq!(outgoing.read_node().unwrap().node.address, lcenter.public);
+
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42],
+        );
+        let wire = Transaction::new(message).to_wire();
+        outgoing.write_wire(&wire).unwrap();
+        assert_eq!(incoming.read_wire(MESSAGE_SIZE).unwrap(), wire);
+        incoming.write_wire(&wire).unwrap();
+        assert_eq!(outgoing.read_wire(MESSAGE_SIZE).unwrap(), wire);
+    }
+
+    #[test]
+    fn test_tcp_handshake_legacy() {
+        let (_, secret) = box_::gen_keypair();
+        let lcenter = Center::new(secret, String::from("127.0.0.1"), 45615);
+        let mut local = Tcp::new();
+        local.bind(&lcenter.link).unwrap();
+
+        // Legacy nodes only send their Node bytes.
+        let node = Node::new(
+            Address::random(),
+            Some(Link::new(String::from("127.0.0.1"), 1)),
+        );
+        let mut outgoing = TcpStream::connect("127.0.0.1:45615").unwrap();
+        outgoing.write_all(&node.as_bytes()).unwrap();
+        let mut incoming = loop {
+            if let Some(stream) = local.accept() {
+                break stream;
+            }
+        };
+        let hello = incoming.read_node().unwrap();
+        assert_eq!(hello.version, handshake::LEGACY);
+        assert_eq!(hello.node, node);
+
+        let hello = Hello::new(&lcenter, Features::ENCRYPTION);
+        let mut outgoing: Box<dyn Stream> = Box::new(BufferedTcp::new(outgoing));
+        outgoing.write_node(&hello).unwrap();
+        assert_eq!(incoming.read_node().unwrap(), hello);
+    }
+
+    #[test]
+    fn test_tcp_partial() {
+        let local = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut outgoing = TcpStream::connect(local.local_addr().unwrap()).unwrap();
+        let mut incoming = BufferedTcp::new(local.accept().unwrap().0);
+        incoming.set_nonblocking(true).unwrap();
+
+        // Partial Wires are kept instead of waiting for the rest.
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![42; 1000],
+        );
+        let wire = Transaction::new(message).to_wire();
+        let data = wire.as_bytes();
+        assert!(matches!(
+            incoming.read_wire(MESSAGE_SIZE),
+            Err(Error::Busy(_))
+        ));
+        outgoing.write_all(&data[..500]).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        let started = Instant::now();
+        assert!(matches!(
+            incoming.read_wire(MESSAGE_SIZE),
+            Err(Error::Busy(_))
+        ));
+        assert!(started.elapsed() < Duration::from_millis(100));
+        outgoing.write_all(&data[500..]).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(incoming.read_wire(MESSAGE_SIZE).unwrap(), wire);
+
+        // Data the peer doesn't accept right away waits in the Stream.
+        let mut written = 0;
+        while !incoming.drain().unwrap() {
+            incoming.write_wire(&wire).unwrap();
+            written += 1;
+        }
+        let mut outgoing = BufferedTcp::new(outgoing);
+        for _ in 0..written {
+            assert_eq!(outgoing.read_wire(MESSAGE_SIZE).unwrap(), wire);
+            let _ = incoming.drain();
+        }
+    }
+
+    #[test]
+    fn test_tcp_timeouts() {
+        let (_, secret) = box_::gen_keypair();
+        let lcenter = Center::new(secret, String::from("127.0.0.1"), 45645);
+        let timeouts = Timeouts {
+            connect: Duration::from_millis(100),
+            read: Duration::from_millis(100),
+            write: Duration::from_millis(100),
+        };
+        let mut local = Tcp::new();
+        local.set_timeouts(timeouts);
+        local.bind(&lcenter.link).unwrap();
+
+        // Neither side sends its handshake.
+        let mut outgoing = local.connect(&lcenter.link).unwrap();
+        let mut incoming = loop {
+            if let Some(stream) = local.accept() {
+                break stream;
+            }
+        };
+        let started = Instant::now();
+        assert!(matches!(incoming.read_node(), Err(Error::Timeout(_))));
+        assert!(matches!(outgoing.read_node(), Err(Error::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
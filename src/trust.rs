@@ -0,0 +1,136 @@
+//! # Trust
+//!
+//! By default every node completing the handshake can join the
+//! network. Permissioned clusters can restrict that with a
+//! TrustPolicy, which gets consulted with the Address of the peer
+//! and the optional token from its Hello. Untrusted peers are
+//! rejected during the handshake, before they receive the own Hello
+//! or enter the routing table. Nodes learned from trusted peers or
+//! through discovery don't complete a handshake before they are
+//! added, they get rejected once dialed and count as unreachable.
+//!
+//! Tokens are bound to the Address of the sender but could be copied
+//! from an unencrypted handshake. Only the owner of the secret key
+//! can read encrypted messages or sign Wires, so the policies should
+//! be combined with encryption and signatures.
+
+use crate::node::Address;
+use sodiumoxide::crypto::auth::hmacsha256;
+use sodiumoxide::crypto::hash::sha256;
+use std::collections::HashSet;
+use std::fmt;
+
+/// Decides which peers are allowed to connect. It is shared by all
+/// threads handling handshakes.
+pub trait TrustPolicy: Send + Sync {
+    /// Token sent in the handshakes of the node with the Address,
+    /// none by default.
+    fn token(&self, _address: &Address) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// True if the peer with the Address and the token from its
+    /// handshake is accepted.
+    fn admit(&self, address: &Address, token: Option<&[u8]>) -> bool;
+}
+
+/// Accepts every peer, the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+/// Only accepts peers with one of the Addresses, tokens are ignored.
+#[derive(Debug, Clone, Default)]
+pub struct StaticAllowlist {
+    addresses: HashSet<Address>,
+}
+
+/// Accepts peers proving they know the shared secret. The token is
+/// an HMAC-SHA-256 of the Address of the sender, keyed with a hash
+/// of the secret.
+#[derive(Clone)]
+pub struct SharedSecretHmac {
+    key: hmacsha256::Key,
+}
+
+impl TrustPolicy for AllowAll {
+    fn admit(&self, _address: &Address, _token: Option<&[u8]>) -> bool {
+        true
+    }
+}
+
+impl StaticAllowlist {
+    pub fn new(addresses: Vec<Address>) -> Self {
+        Self {
+            addresses: addresses.into_iter().collect(),
+        }
+    }
+
+    pub fn insert(&mut self, address: Address) {
+        self.addresses.insert(address);
+    }
+}
+
+impl TrustPolicy for StaticAllowlist {
+    fn admit(&self, address: &Address, _token: Option<&[u8]>) -> bool {
+        self.addresses.contains(address)
+    }
+}
+
+impl SharedSecretHmac {
+    /// Derives the key from the secret, all nodes of the cluster need
+    /// the same one.
+    pub fn new(secret: &[u8]) -> Self {
+        let sha256::Digest(digest) = sha256::hash(secret);
+        Self {
+            key: hmacsha256::Key(digest),
+        }
+    }
+}
+
+impl TrustPolicy for SharedSecretHmac {
+    fn token(&self, address: &Address) -> Option<Vec<u8>> {
+        let tag = hmacsha256::authenticate(address.as_slice(), &self.key);
+        Some(tag.as_ref().to_vec())
+    }
+
+    fn admit(&self, address: &Address, token: Option<&[u8]>) -> bool {
+        token
+            .and_then(hmacsha256::Tag::from_slice)
+            .map(|tag| hmacsha256::verify(&tag, address.as_slice(), &self.key))
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for SharedSecretHmac {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SharedSecretHmac").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trust_allowlist() {
+        let address = Address::random();
+        assert!(AllowAll.admit(&address, None));
+        let mut policy = StaticAllowlist::new(vec![Address::random()]);
+        assert!(!policy.admit(&address, None));
+        policy.insert(address.clone());
+        assert!(policy.admit(&address, None));
+        assert!(policy.token(&address).is_none());
+    }
+
+    #[test]
+    fn test_trust_hmac() {
+        let policy = SharedSecretHmac::new(b"cluster");
+        let address = Address::random();
+        let token = policy.token(&address).unwrap();
+        assert!(policy.admit(&address, Some(&token)));
+        assert!(!policy.admit(&address, None));
+        assert!(!policy.admit(&address, Some(&token[1..])));
+        assert!(!policy.admit(&Address::random(), Some(&token)));
+        assert!(!SharedSecretHmac::new(b"other").admit(&address, Some(&token)));
+    }
+}
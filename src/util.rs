@@ -3,40 +3,181 @@
 //! Collection of non specific helpers & utility functions / objects.
 
 use crate::error::Error;
-use std::sync::mpsc::{self, Receiver, Sender};
+use crate::node::Address;
+use std::future::{self, Future};
+use std::pin::pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
 
-/// Bidirectional communcation wrapper around mspc channels.
+/// Bidirectional communcation wrapper around tokio mpsc channels. The
+/// same Channel can be used from the dedicated threads (through the
+/// blocking functions) and from the async tasks (through recv_async),
+/// so both sides don't have to agree on a runtime.
+///
+/// Channels are either unbounded or bounded, on bounded ones senders
+/// have to wait or get an error once the other side falls behind.
 #[derive(Debug)]
 pub struct Channel<T> {
-    sender: Sender<T>,
-    receiver: Receiver<T>,
+    sender: ChannelSender<T>,
+    /// The tokio receiver requires a mutable reference, the Mutex is
+    /// only locked for a single poll and never held across an await.
+    receiver: Mutex<ChannelReceiver<T>>,
+    /// Several threads or tasks can wait on the same Channel, but the
+    /// tokio receiver only keeps the waker of the last one.
+    waiters: Arc<Waiters>,
+}
+
+/// Wakers of everyone waiting on a Channel. The receiver gets this
+/// one instead of theirs and passes every wake up on to all of them.
+#[derive(Debug, Default)]
+struct Waiters(Mutex<Vec<Waker>>);
+
+/// Sending half of either an unbounded or a bounded mpsc channel.
+#[derive(Debug)]
+enum ChannelSender<T> {
+    Unbounded(UnboundedSender<T>),
+    Bounded(Sender<T>),
+    /// Shares the queue of a Mux, the Tag gets sent along with every
+    /// message.
+    Tagged {
+        tag: Tag,
+        sender: Sender<(Tag, T)>,
+        closed: UnboundedSender<Tag>,
+    },
+}
+
+/// Identifies the Channels of a Mux by the Address of the peer owning
+/// them and the Address of the Topic, so neither of them alone has to
+/// be unique.
+pub type Tag = (Address, Address);
+
+/// Receiving end of the Channels created through an Inlet. Instead of
+/// polling each of them the owner waits on a single queue, so the
+/// cost of receiving doesn't depend on the number of Channels.
+#[derive(Debug)]
+pub struct Mux<T> {
+    /// Kept so that recv never completes with a closed queue.
+    inlet: Inlet<T>,
+    receiver: Mutex<Receiver<(Tag, T)>>,
+    /// Dropped Channels are reported through their own queue, which
+    /// is unbounded so no notice ever gets lost.
+    notices: Mutex<UnboundedReceiver<Tag>>,
+}
+
+/// Creates the Channels whose messages end up in a Mux.
+#[derive(Debug)]
+pub struct Inlet<T> {
+    sender: Sender<(Tag, T)>,
+    closed: UnboundedSender<Tag>,
+}
+
+/// Receiving half of either an unbounded or a bounded mpsc channel.
+#[derive(Debug)]
+enum ChannelReceiver<T> {
+    Unbounded(UnboundedReceiver<T>),
+    Bounded(Receiver<T>),
 }
 
 impl<T> Channel<T> {
     /// Creates a new pair of Channels. Since two of them are always
     /// connected they have to be created together.
     pub fn new() -> (Self, Self) {
-        let (s1, r1): (mpsc::Sender<T>, mpsc::Receiver<T>) = mpsc::channel();
-        let (s2, r2): (mpsc::Sender<T>, mpsc::Receiver<T>) = mpsc::channel();
+        let (s1, r1) = mpsc::unbounded_channel();
+        let (s2, r2) = mpsc::unbounded_channel();
         (
             Self {
-                sender: s1,
-                receiver: r2,
+                sender: ChannelSender::Unbounded(s1),
+                receiver: Mutex::new(ChannelReceiver::Unbounded(r2)),
+                waiters: Arc::default(),
             },
             Self {
-                sender: s2,
-                receiver: r1,
+                sender: ChannelSender::Unbounded(s2),
+                receiver: Mutex::new(ChannelReceiver::Unbounded(r1)),
+                waiters: Arc::default(),
+            },
+        )
+    }
+
+    /// Creates a new pair of Channels that can each hold at most
+    /// capacity messages in both directions. The capacity has to be
+    /// larger than zero.
+    pub fn bounded(capacity: usize) -> (Self, Self) {
+        let (s1, r1) = mpsc::channel(capacity);
+        let (s2, r2) = mpsc::channel(capacity);
+        (
+            Self {
+                sender: ChannelSender::Bounded(s1),
+                receiver: Mutex::new(ChannelReceiver::Bounded(r2)),
+                waiters: Arc::default(),
+            },
+            Self {
+                sender: ChannelSender::Bounded(s2),
+                receiver: Mutex::new(ChannelReceiver::Bounded(r1)),
+                waiters: Arc::default(),
             },
         )
     }
 
     /// Sends a message through the Channel. This can fail if the
     /// remote socket is unavailable. Currently this error case is not
-    /// handled.
+    /// handled. On bounded Channels it blocks the current thread
+    /// until there is space, inside of async code send_async or
+    /// try_send have to be used.
     pub fn send(&self, message: T) -> Result<(), Error> {
-        match self.sender.send(message) {
-            Ok(()) => Ok(()),
-            Err(_) => Err(Error::Connection(String::from("channel is not available"))),
+        match &self.sender {
+            ChannelSender::Unbounded(sender) => sender.send(message).map_err(|_| closed()),
+            ChannelSender::Bounded(_) | ChannelSender::Tagged { .. } => {
+                block_on(self.send_async(message))
+            }
+        }
+    }
+
+    /// Async version of send, on bounded Channels it only resolves
+    /// once there is space for the message.
+    pub async fn send_async(&self, message: T) -> Result<(), Error> {
+        match &self.sender {
+            ChannelSender::Unbounded(sender) => sender.send(message).map_err(|_| closed()),
+            ChannelSender::Bounded(sender) => sender.send(message).await.map_err(|_| closed()),
+            ChannelSender::Tagged { tag, sender, .. } => sender
+                .send((tag.clone(), message))
+                .await
+                .map_err(|_| closed()),
+        }
+    }
+
+    /// Sends a message without ever waiting. Should a bounded Channel
+    /// be full the message gets returned as Busy error, unbounded
+    /// Channels behave the same as send.
+    pub fn try_send(&self, message: T) -> Result<(), Error> {
+        match &self.sender {
+            ChannelSender::Unbounded(sender) => sender.send(message).map_err(|_| closed()),
+            ChannelSender::Bounded(sender) => match sender.try_send(message) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(busy()),
+                Err(TrySendError::Closed(_)) => Err(closed()),
+            },
+            ChannelSender::Tagged { tag, sender, .. } => {
+                match sender.try_send((tag.clone(), message)) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(_)) => Err(busy()),
+                    Err(TrySendError::Closed(_)) => Err(closed()),
+                }
+            }
+        }
+    }
+
+    /// Returns true once the other side of the Channel has been
+    /// dropped, after which no more messages can be sent. Threads use
+    /// this to detect that the system is shutting down.
+    pub fn is_closed(&self) -> bool {
+        match &self.sender {
+            ChannelSender::Unbounded(sender) => sender.is_closed(),
+            ChannelSender::Bounded(sender) => sender.is_closed(),
+            ChannelSender::Tagged { sender, .. } => sender.is_closed(),
         }
     }
 
@@ -44,23 +185,251 @@ impl<T> Channel<T> {
     /// method. Currently error are not getting handled and if the
     /// socket is unavailable None will be returned.
     pub fn try_recv(&self) -> Option<T> {
-        match self.receiver.try_recv() {
-            Ok(m) => Some(m),
-            Err(_) => None,
+        match &mut *self.receiver.lock().unwrap() {
+            ChannelReceiver::Unbounded(receiver) => receiver.try_recv().ok(),
+            ChannelReceiver::Bounded(receiver) => receiver.try_recv().ok(),
+        }
+    }
+
+    /// Like try_recv, but should no message be available the waker of
+    /// the context gets woken once one arrives. Threads use this to
+    /// wait on several Channels and sockets at once.
+    pub fn recv_with(&self, cx: &mut Context<'_>) -> Option<T> {
+        match self.poll_recv(cx) {
+            Poll::Ready(message) => message,
+            Poll::Pending => None,
         }
     }
 
     /// Like send this is also a wrapper around the mpsc recv method.
     /// Currently error are not getting handled and if the socket is
-    /// unavailable None will be returned.
+    /// unavailable None will be returned. It blocks the current
+    /// thread, inside of async code recv_async has to be used.
     pub fn recv(&self) -> Option<T> {
-        match self.receiver.recv() {
-            Ok(m) => Some(m),
-            Err(_) => None,
+        block_on(self.recv_async())
+    }
+
+    /// Like recv but it only blocks the current thread for the
+    /// timeout. None is returned if no message arrived in time or
+    /// the Channel is no longer available.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<T> {
+        block_on_timeout(self.recv_async(), timeout).flatten()
+    }
+
+    /// Async version of recv, it only resolves once a message is
+    /// available or the Channel is no longer available (None).
+    pub async fn recv_async(&self) -> Option<T> {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Polls the Channel once and registers the waker of the context
+    /// should no message be available. This can be used to wait on
+    /// an unknown number of Channels at once.
+    pub fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let mut receiver = self.receiver.lock().unwrap();
+        self.waiters.register(cx.waker());
+        let waker = Waker::from(self.waiters.clone());
+        let mut shared = Context::from_waker(&waker);
+        let poll = match &mut *receiver {
+            ChannelReceiver::Unbounded(receiver) => receiver.poll_recv(&mut shared),
+            ChannelReceiver::Bounded(receiver) => receiver.poll_recv(&mut shared),
+        };
+        if poll.is_ready() {
+            self.waiters.remove(cx.waker());
+        }
+        poll
+    }
+
+    /// Wakes up everyone waiting on the Channel without a message,
+    /// for example once messages were taken out of it on their
+    /// behalf.
+    pub fn wake(&self) {
+        self.waiters.wake_all();
+    }
+}
+
+impl<T> Drop for ChannelSender<T> {
+    /// Informs the Mux that no more messages will arrive from the
+    /// Channel.
+    fn drop(&mut self) {
+        if let ChannelSender::Tagged { tag, closed, .. } = self {
+            let _ = closed.send(tag.clone());
         }
     }
 }
 
+impl<T> Mux<T> {
+    /// Creates a Mux that can hold at most capacity messages of all
+    /// Channels combined, the capacity has to be larger than zero.
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (closed, notices) = mpsc::unbounded_channel();
+        Self {
+            inlet: Inlet { sender, closed },
+            receiver: Mutex::new(receiver),
+            notices: Mutex::new(notices),
+        }
+    }
+
+    /// Returns the Inlet for creating new Channels of the Mux.
+    pub fn inlet(&self) -> Inlet<T> {
+        self.inlet.clone()
+    }
+
+    /// Waits until any of the Channels sends a message and returns it
+    /// together with the Tag of the Channel. None means the
+    /// Channel has been dropped, its earlier messages always come
+    /// first. Without any Channels the future never completes, so it
+    /// can safely be used in a select.
+    pub async fn recv(&self) -> (Tag, Option<T>) {
+        future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Non-blocking version of recv.
+    pub fn try_recv(&self) -> Option<(Tag, Option<T>)> {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        match self.poll_recv(&mut Context::from_waker(&waker)) {
+            Poll::Ready(message) => Some(message),
+            Poll::Pending => None,
+        }
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<(Tag, Option<T>)> {
+        if let Poll::Ready(Some((tag, message))) = self.receiver.lock().unwrap().poll_recv(cx) {
+            return Poll::Ready((tag, Some(message)));
+        }
+        if let Poll::Ready(Some(tag)) = self.notices.lock().unwrap().poll_recv(cx) {
+            return Poll::Ready((tag, None));
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Clone for Inlet<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            closed: self.closed.clone(),
+        }
+    }
+}
+
+impl<T> Inlet<T> {
+    /// Creates a new pair of Channels like Channel::bounded, except
+    /// that the messages sent through the first one go into the Mux
+    /// tagged with the peer and the topic. The second one can't
+    /// receive anything and only sends to the first one.
+    pub fn pair(&self, peer: Address, topic: Address, capacity: usize) -> (Channel<T>, Channel<T>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        let (_, unused) = mpsc::channel(1);
+        (
+            Channel {
+                sender: ChannelSender::Tagged {
+                    tag: (peer, topic),
+                    sender: self.sender.clone(),
+                    closed: self.closed.clone(),
+                },
+                receiver: Mutex::new(ChannelReceiver::Bounded(receiver)),
+                waiters: Arc::default(),
+            },
+            Channel {
+                sender: ChannelSender::Bounded(sender),
+                receiver: Mutex::new(ChannelReceiver::Bounded(unused)),
+                waiters: Arc::default(),
+            },
+        )
+    }
+}
+
+impl Waiters {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn remove(&self, waker: &Waker) {
+        self.0.lock().unwrap().retain(|w| !w.will_wake(waker));
+    }
+
+    /// The wakers are taken out first, everyone still waiting
+    /// registers again while polling.
+    fn wake_all(&self) {
+        let wakers = std::mem::take(&mut *self.0.lock().unwrap());
+        wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_all();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_all();
+    }
+}
+
+/// Error of sending through a Channel whose other side is gone.
+fn closed() -> Error {
+    Error::Connection(String::from("channel is not available"))
+}
+
+/// Error of sending through a full bounded Channel.
+fn busy() -> Error {
+    Error::Busy(String::from("channel is full"))
+}
+
+/// Wakes up a parked thread, used by block_on and block_on_timeout.
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+thread_local! {
+    /// Waker of the current thread, reusing it keeps Channels from
+    /// collecting a new waker every time the thread waits on them.
+    static UNPARK: Waker = Waker::from(Arc::new(Unpark(thread::current())));
+}
+
+/// Minimal executor for blocking the current thread until a future
+/// is ready. It doesn't depend on any runtime, so it can be called
+/// from the dedicated threads as well as from inside a tokio runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = UNPARK.with(Waker::clone);
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
+
+/// Like block_on, but it gives up once the timeout passed and
+/// returns None instead.
+pub fn block_on_timeout<F: Future>(future: F, timeout: Duration) -> Option<F::Output> {
+    let deadline = Instant::now() + timeout;
+    let waker = UNPARK.with(Waker::clone);
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Some(output);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        thread::park_timeout(deadline - now);
+    }
+}
+
 /// Computes the length of a slice and returns it in the system wide
 /// two byte array.
 pub fn compute_length(data: &[u8]) -> [u8; 2] {
@@ -93,6 +462,111 @@ mod tests {
         assert_eq!(c2.recv(), Some(42));
     }
 
+    #[tokio::test]
+    async fn test_channel_recv_async() {
+        let (c1, c2) = Channel::new();
+        let _ = c1.send(42);
+        assert_eq!(c2.recv_async().await, Some(42));
+        drop(c1);
+        assert_eq!(c2.recv_async().await, None);
+    }
+
+    #[test]
+    fn test_channel_shared_recv() {
+        let (c1, c2) = Channel::new();
+        let c2 = Arc::new(c2);
+        let receivers: Vec<_> = (0..3)
+            .map(|_| {
+                let c2 = c2.clone();
+                thread::spawn(move || c2.recv())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(20));
+        for i in 0..3 {
+            let _ = c1.send(i);
+        }
+        let mut received: Vec<_> = receivers.into_iter().map(|r| r.join().unwrap()).collect();
+        received.sort();
+        assert_eq!(received, vec![Some(0), Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn test_channel_recv_timeout() {
+        let (c1, c2) = Channel::new();
+        let start = Instant::now();
+        assert_eq!(c2.recv_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            let _ = c1.send(42);
+            c1
+        });
+        assert_eq!(c2.recv_timeout(Duration::from_secs(5)), Some(42));
+        drop(sender.join().unwrap());
+        assert_eq!(c2.recv_timeout(Duration::from_secs(5)), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_channel_closed() {
+        let (c1, c2) = Channel::<u8>::new();
+        assert!(!c1.is_closed());
+        drop(c2);
+        assert!(c1.is_closed());
+        assert!(c1.send(42).is_err());
+    }
+
+    #[test]
+    fn test_channel_bounded() {
+        let (c1, c2) = Channel::bounded(2);
+        assert!(c1.try_send(1).is_ok());
+        assert!(c1.send(2).is_ok());
+        assert!(matches!(c1.try_send(3), Err(Error::Busy(_))));
+        assert_eq!(c2.try_recv(), Some(1));
+        assert!(c1.try_send(3).is_ok());
+        drop(c2);
+        assert!(matches!(c1.try_send(4), Err(Error::Connection(_))));
+    }
+
+    #[tokio::test]
+    async fn test_channel_send_async() {
+        let (c1, c2) = Channel::bounded(1);
+        let _ = c1.send_async(1).await;
+        let receiver = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            (c2.recv(), c2.recv())
+        });
+        // Only resolves once the other thread made space.
+        let _ = c1.send_async(2).await;
+        assert_eq!(receiver.join().unwrap(), (Some(1), Some(2)));
+    }
+
+    #[tokio::test]
+    async fn test_mux_recv() {
+        let mux = Mux::new(2);
+        // The same Topic of two peers must not be confused.
+        let topic = Address::random();
+        let first = (Address::random(), topic.clone());
+        let second = (Address::random(), topic);
+        let (c1, c2) = mux.inlet().pair(first.0.clone(), first.1.clone(), 1);
+        let (c3, _c4) = mux.inlet().pair(second.0.clone(), second.1.clone(), 1);
+        assert!(mux.try_recv().is_none());
+        c1.try_send(1).unwrap();
+        c3.try_send(2).unwrap();
+        assert!(matches!(c1.try_send(3), Err(Error::Busy(_))));
+        c2.try_send(4).unwrap();
+        assert_eq!(c1.try_recv(), Some(4));
+        assert!(c2.try_recv().is_none());
+
+        drop(c1);
+        assert_eq!(mux.recv().await, (first.clone(), Some(1)));
+        assert_eq!(mux.recv().await, (second, Some(2)));
+        assert_eq!(mux.recv().await, (first, None));
+        assert!(mux.try_recv().is_none());
+        drop(mux);
+        assert!(c3.is_closed());
+    }
+
     #[test]
     fn test_length_simple() {
         let data = vec![0, 1, 244, 213];
@@ -0,0 +1,149 @@
+use actaeon::{
+    message::Message,
+    node::{Address, ToAddress},
+    testing::{Cluster, Fault},
+    transaction::{Class, Transaction},
+    Topic,
+};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cluster_routing() {
+    let cluster = Cluster::new(4).await.unwrap();
+    assert_eq!(cluster.len(), 4);
+    assert_eq!(cluster.network().len(), 4);
+    for index in 0..4 {
+        let snapshot = cluster.node(index).routing_snapshot();
+        let known: usize = snapshot.buckets.iter().map(|b| b.nodes.len()).sum();
+        assert_eq!(known, 3);
+    }
+
+    let pairs = [(0, 3), (3, 1), (2, 0)];
+    for (source, target) in pairs.iter() {
+        let test = action(&cluster, *source, *target, vec![*source as u8]);
+        cluster.node(*source).send(test.clone()).unwrap();
+        let ret = cluster.node(*target).recv_timeout(TIMEOUT).await.unwrap();
+        assert_eq!(ret, test);
+    }
+    cluster.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cluster_topic() {
+    let cluster = Cluster::new(3).await.unwrap();
+    let address = "cluster".to_string().to_address();
+    let mut topics: Vec<Topic> = (0..3)
+        .map(|index| cluster.node(index).subscribe(&address))
+        .collect();
+    for index in 1..3 {
+        wait_for_subscriber(&mut topics[0], &cluster.address(index)).await;
+    }
+
+    topics[0].broadcast(vec![42]).unwrap();
+    for topic in topics.iter_mut().skip(1) {
+        let ret = topic.recv_timeout(TIMEOUT).unwrap();
+        assert_eq!(ret.message.body.as_bytes(), vec![42]);
+        assert_eq!(ret.source(), cluster.address(0));
+    }
+    cluster.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cluster_churn() {
+    let mut cluster = Cluster::new(4).await.unwrap();
+    assert!(cluster.leave(2).await);
+    assert!(!cluster.leave(2).await);
+    assert_eq!(cluster.running(), vec![0, 1, 3]);
+    assert_eq!(cluster.network().len(), 3);
+
+    let test = action(&cluster, 0, 3, vec![1]);
+    cluster.node(0).send(test.clone()).unwrap();
+    assert_eq!(cluster.node(3).recv_timeout(TIMEOUT).await.unwrap(), test);
+
+    let index = cluster.join().await.unwrap();
+    assert_eq!(index, 4);
+    let test = action(&cluster, index, 1, vec![2]);
+    cluster.node(index).send(test.clone()).unwrap();
+    assert_eq!(cluster.node(1).recv_timeout(TIMEOUT).await.unwrap(), test);
+    let test = action(&cluster, 1, index, vec![3]);
+    cluster.node(1).send(test.clone()).unwrap();
+    assert_eq!(
+        cluster.node(index).recv_timeout(TIMEOUT).await.unwrap(),
+        test
+    );
+
+    cluster.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cluster_partition() {
+    let cluster = Cluster::new(3).await.unwrap();
+    cluster.partition(&[0, 1], &[2]);
+
+    let lost = action(&cluster, 0, 2, vec![1]);
+    cluster.node(0).send(lost).unwrap();
+    let short = Duration::from_millis(200);
+    assert!(cluster.node(2).recv_timeout(short).await.is_none());
+    let test = action(&cluster, 1, 0, vec![2]);
+    cluster.node(1).send(test.clone()).unwrap();
+    assert_eq!(cluster.node(0).recv_timeout(TIMEOUT).await.unwrap(), test);
+
+    cluster.heal();
+    let test = action(&cluster, 0, 2, vec![3]);
+    cluster.node(0).send(test.clone()).unwrap();
+    assert_eq!(cluster.node(2).recv_timeout(TIMEOUT).await.unwrap(), test);
+    cluster.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_cluster_latency() {
+    let cluster = Cluster::new(2).await.unwrap();
+    let latency = Duration::from_millis(100);
+    cluster.set_fault(
+        0,
+        1,
+        Fault {
+            latency,
+            jitter: Duration::from_millis(20),
+            ..Fault::default()
+        },
+    );
+
+    let started = std::time::Instant::now();
+    for i in 0..3 {
+        let test = action(&cluster, 0, 1, vec![i]);
+        cluster.node(0).send(test.clone()).unwrap();
+    }
+    let mut bodies = Vec::new();
+    for _ in 0..3 {
+        let ret = cluster.node(1).recv_timeout(TIMEOUT).await.unwrap();
+        bodies.push(ret.message.body.as_bytes()[0]);
+    }
+    assert!(started.elapsed() >= latency);
+    bodies.sort_unstable();
+    assert_eq!(bodies, vec![0, 1, 2]);
+    cluster.shutdown().await;
+}
+
+fn action(cluster: &Cluster, source: usize, target: usize, body: Vec<u8>) -> Transaction {
+    Transaction::new(Message::new(
+        Class::Action,
+        cluster.address(source),
+        cluster.address(target),
+        Address::default(),
+        body,
+    ))
+}
+
+async fn wait_for_subscriber(topic: &mut Topic, subscriber: &Address) {
+    for _ in 0..200 {
+        let _ = topic.try_recv();
+        if topic.subscribers().get(subscriber).is_some() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("subscriber never arrived");
+}
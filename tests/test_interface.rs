@@ -1,31 +1,42 @@
 use actaeon::{
     self,
-    config::Config,
+    backend::{self, MemoryStorage, Storage},
+    config::{Config, ConfigDelta, Transport},
+    connection::Notification,
+    database::{DataTopic, Database},
+    event::{Event, EventReceiver},
+    handshake::Features,
     message::Message,
-    node::{Address, Center},
+    node::{Address, Center, Node},
+    record::{Record, RecordBucket},
+    router::Safe,
+    status::{Bootstrap, Report},
+    trace::{Decision, Reason, TraceSink, Verdict},
     transaction::{Class, Transaction},
+    trust::SharedSecretHmac,
     Interface,
 };
 
 use sodiumoxide::crypto::box_;
+use std::sync::{Arc, Mutex};
 
-#[test]
-fn test_interface() {
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface() {
     let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42443);
     let (_, secret) = box_::gen_keypair();
     let lcenter = Center::new(secret, String::from("127.0.0.1"), 42444);
 
-    let linterface = Interface::new(lconfig, lcenter.clone()).unwrap();
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42444);
     let (_, secret) = box_::gen_keypair();
     let rcenter = Center::new(secret, String::from("127.0.0.1"), 42443);
 
-    let rinterface = Interface::new(rconfig, rcenter.clone()).unwrap();
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let test = Transaction::new(Message::new(
         Class::Action,
@@ -37,7 +48,7 @@ fn test_interface() {
 
     let _ = linterface.send(test.clone());
 
-    let ret = rinterface.recv().unwrap();
+    let ret = rinterface.recv().await.unwrap();
     assert_eq!(ret, test);
 
     let test = Transaction::new(Message::new(
@@ -50,6 +61,2010 @@ fn test_interface() {
 
     let _ = rinterface.send(test.clone());
 
-    let ret = linterface.recv().unwrap();
+    let ret = linterface.recv().await.unwrap();
     assert_eq!(ret, test);
 }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_shutdown() {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42445);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42446);
+
+    let interface = Interface::new(config, center).await.unwrap();
+    interface.shutdown().await;
+
+    // The TcpListener must be closed once shutdown returns.
+    assert!(std::net::TcpListener::bind("127.0.0.1:42446").is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_snapshot() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("actaeon-{}.table", rand::random::<u64>()));
+    let path = path.to_string_lossy().to_string();
+
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42449);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42447);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42447);
+    rconfig.database = Some(path.clone());
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42448);
+    let rinterface = Interface::new(rconfig, rcenter).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+
+    // The bootstrap node is stored in the final snapshot.
+    let nodes = Database::new(&path).load().unwrap();
+    assert!(nodes.iter().any(|n| n.address == lcenter.public));
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_snapshot_bootstrap() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("actaeon-{}.table", rand::random::<u64>()));
+    let path = path.to_string_lossy().to_string();
+
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42560);
+    let table = Safe::new(20, center.clone());
+    let link = actaeon::node::Link::new(String::from("127.0.0.1"), 42559);
+    table.add(Node::new(Address::random(), Some(link)));
+    Database::new(&path).save(&table).unwrap();
+
+    // The signaling server would notice a bootstrap attempt.
+    let server = std::net::TcpListener::bind("127.0.0.1:42558").unwrap();
+    server.set_nonblocking(true).unwrap();
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42558);
+    config.database = Some(path.clone());
+    let interface = Interface::new(config, center).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(matches!(
+        server.accept(),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock
+    ));
+
+    interface.shutdown().await;
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(std::path::PathBuf::from(path).with_extension("banned"));
+}
+
+#[tokio::test]
+async fn test_interface_capacity() {
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42463);
+    config.capacity = 0;
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42462);
+    assert!(Interface::new(config, center).await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_udp() {
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42451);
+    lconfig.transport = Transport::Udp;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42450);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42450);
+    rconfig.transport = Transport::Udp;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42451);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+
+    let _ = rinterface.send(test.clone());
+
+    let ret = linterface.recv().await.unwrap();
+    assert_eq!(ret, test);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_websocket() {
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42453);
+    lconfig.transport = Transport::WebSocket;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42452);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42452);
+    rconfig.transport = Transport::WebSocket;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42453);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+
+    let _ = rinterface.send(test.clone());
+
+    let ret = linterface.recv().await.unwrap();
+    assert_eq!(ret, test);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_lookup() {
+    // All nodes bootstrap through the first one, the second one only
+    // learns about the third through the lookup.
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42457);
+    let (_, secret) = box_::gen_keypair();
+    let scenter = Center::new(secret, String::from("127.0.0.1"), 42454);
+    let _sinterface = Interface::new(config, scenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42454);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42455);
+    let linterface = Interface::new(config, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42454);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42456);
+    let _rinterface = Interface::new(config, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let found = linterface.lookup(&rcenter.public).await;
+    assert_eq!(found.first().unwrap().address, rcenter.public);
+    assert!(found.iter().any(|node| node.address == scenter.public));
+    assert!(found.iter().all(|node| node.address != lcenter.public));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_republish() {
+    let mut paths = Vec::new();
+    for _ in 0..2 {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+        paths.push(path.to_string_lossy().to_string());
+    }
+
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42459);
+    lconfig.records = Some(paths[0].clone());
+    lconfig.republish = 1;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42458);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42458);
+    rconfig.records = Some(paths[1].clone());
+    rconfig.republish = 1;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42459);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Only the closer node hosts the Record at first, the other one
+    // gets it once it is republished.
+    let address = Address::random();
+    let _topic = rinterface.subscribe(&address);
+
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+
+    for path in paths {
+        let records = RecordBucket::open(&path).unwrap();
+        let record = records.get(&address).unwrap();
+        assert_eq!(record.subscribers, vec![rcenter.public.clone()]);
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_transfer() {
+    let mut paths = Vec::new();
+    for _ in 0..2 {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+        paths.push(path.to_string_lossy().to_string());
+    }
+
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42508);
+    lconfig.records = Some(paths[0].clone());
+    lconfig.transfer = 1;
+    lconfig.replicas = 1;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42507);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42508);
+
+    // The Record is created while this node is alone, the other node
+    // is closer to it and joins afterwards.
+    let mut bytes = rcenter.public.as_bytes().to_vec();
+    bytes[31] ^= 1;
+    let address = Address::from_slice(&bytes).unwrap();
+    let _topic = linterface.subscribe(&address);
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42507);
+    rconfig.records = Some(paths[1].clone());
+    rconfig.transfer = 1;
+    rconfig.replicas = 1;
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+
+    let records = RecordBucket::open(&paths[0]).unwrap();
+    assert!(records.get(&address).is_none());
+    let records = RecordBucket::open(&paths[1]).unwrap();
+    let record = records.get(&address).unwrap();
+    assert_eq!(record.subscribers, vec![lcenter.public.clone()]);
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_transfer_foreign() {
+    let ports = [42569, 42570, 42571];
+    let address = Address::random();
+    let mut secrets: Vec<_> = (0..3).map(|_| box_::gen_keypair().1).collect();
+    secrets.sort_by_key(|secret| &Center::new(secret.clone(), String::new(), 0).public ^ &address);
+    let mut path = std::env::temp_dir();
+    path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+    let path = path.to_string_lossy().to_string();
+
+    let mut interfaces = Vec::new();
+    let mut centers = Vec::new();
+    for (secret, port) in secrets.into_iter().zip(ports.iter()) {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42572);
+        config.replicas = 1;
+        if centers.is_empty() {
+            config.records = Some(path.clone());
+        }
+        config.bootstrap = centers
+            .iter()
+            .map(|center: &Center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    // The farthest node never hosted the Record, since another one is
+    // closer than it even without the closest node.
+    let mut record = Record::new(address.clone());
+    record.subscribe(centers[2].public.clone());
+    let message = Message::new(
+        Class::RecordTransfer,
+        centers[2].public.clone(),
+        centers[0].public.clone(),
+        address.clone(),
+        DataTopic::from(&record).as_bytes(),
+    );
+    interfaces[2].send(Transaction::new(message)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    for interface in interfaces {
+        interface.shutdown().await;
+    }
+    let records = RecordBucket::open(&path).unwrap();
+    assert!(records.get(&address).is_none());
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_replicas() {
+    // Both nodes host the Record, but each of them missed one of the
+    // subscribers.
+    let address = Address::random();
+    let subscribers = vec![Address::random(), Address::random()];
+    let mut paths = Vec::new();
+    for subscriber in subscribers.iter() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+        let path = path.to_string_lossy().to_string();
+        let mut record = Record::new(address.clone());
+        record.subscribe(subscriber.clone());
+        RecordBucket::open(&path).unwrap().add(record);
+        paths.push(path);
+    }
+
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42510);
+    lconfig.records = Some(paths[0].clone());
+    lconfig.transfer = 1;
+    lconfig.replicas = 2;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42509);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42509);
+    rconfig.records = Some(paths[1].clone());
+    rconfig.transfer = 1;
+    rconfig.replicas = 2;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42510);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(2500)).await;
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+
+    for path in paths {
+        let records = RecordBucket::open(&path).unwrap();
+        let mut found = records.get(&address).unwrap().subscribers;
+        found.sort();
+        let mut expected = subscribers.clone();
+        expected.sort();
+        assert_eq!(found, expected);
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_reconcile_foreign() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+    let path = path.to_string_lossy().to_string();
+
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42573);
+    // Only the closer node hosts the Record.
+    let mut bytes = lcenter.public.as_bytes().to_vec();
+    bytes[31] ^= 1;
+    let address = Address::from_slice(&bytes).unwrap();
+    let subscriber = Address::random();
+    let mut record = Record::new(address.clone());
+    record.subscribe(subscriber.clone());
+    RecordBucket::open(&path).unwrap().add(record);
+
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42574);
+    lconfig.records = Some(path.clone());
+    lconfig.replicas = 1;
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42573);
+    rconfig.replicas = 1;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42574);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut forged = Record::new(address.clone());
+    forged.subscribe(Address::random());
+    let message = Message::new(
+        Class::Reconcile,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        address.clone(),
+        DataTopic::from(&forged).as_bytes(),
+    );
+    rinterface.send(Transaction::new(message)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+
+    let records = RecordBucket::open(&path).unwrap();
+    assert_eq!(records.get(&address).unwrap().subscribers, vec![subscriber]);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_expiry() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("actaeon-{}.records", rand::random::<u64>()));
+    let path = path.to_string_lossy().to_string();
+
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42512);
+    config.records = Some(path.clone());
+    config.transfer = 1;
+    config.expiry = Some(1);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42511);
+    let interface = Interface::new(config, center).await.unwrap();
+    let mut events = interface.events();
+    let address = Address::random();
+    async fn expired(events: &mut EventReceiver, address: &Address) {
+        while events.recv().await.unwrap() != Event::RecordExpired(address.clone()) {}
+    }
+
+    // The Record is removed as soon as its last subscriber leaves,
+    // long before it would expire.
+    let topic = interface.subscribe(&address);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    drop(topic);
+    tokio::time::timeout(
+        std::time::Duration::from_millis(500),
+        expired(&mut events, &address),
+    )
+    .await
+    .unwrap();
+
+    // Subscribing again creates a new Record, without any activity
+    // it expires after the configured period.
+    let _topic = interface.subscribe(&address);
+    tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        expired(&mut events, &address),
+    )
+    .await
+    .unwrap();
+
+    interface.shutdown().await;
+    let records = RecordBucket::open(&path).unwrap();
+    assert!(records.get(&address).is_none());
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_metrics() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42465);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42464);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42464);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42465);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let _ = rinterface.message(lcenter.public.clone(), vec![42]);
+    let _ = linterface.recv().await.unwrap();
+    // The Switch records the delivery after passing the message on.
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let local = linterface.metrics();
+    assert_eq!(local.table, 1);
+    assert_eq!(local.connections, 1);
+    assert_eq!(local.delivered, 1);
+    assert!(local.received >= 1);
+    assert!(local.cache_misses >= 1);
+
+    let remote = rinterface.metrics();
+    assert_eq!(remote.table, 1);
+    assert!(remote.sent >= 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_events() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42467);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42466);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42466);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42467);
+    let _rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < 2 {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event != Event::BootstrapFailed {
+            received.push(event);
+        }
+    }
+    assert!(received.contains(&Event::NodeAdded(rcenter.public.clone())));
+    assert!(received.contains(&Event::Connected(rcenter.public.clone())));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_connect() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42477);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42476);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // A short timeout detects the lost peer faster.
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42476);
+    rconfig.timeout = 1;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42477);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    assert!(rinterface.connect(&Address::random()).await.is_err());
+
+    let mut features = Features::ENCRYPTION;
+    features.insert(Features::HEARTBEAT);
+    features.insert(Features::PROOF);
+    let rconnection = rinterface.connect(&lcenter.public).await.unwrap();
+    let lconnection = linterface.connect(&rcenter.public).await.unwrap();
+    for connection in [&rconnection, &lconnection] {
+        let notification =
+            tokio::time::timeout(std::time::Duration::from_secs(5), connection.recv())
+                .await
+                .unwrap();
+        assert!(matches!(notification, Some(Notification::Connected)));
+        assert!(connection.is_alive());
+        assert_eq!(connection.features(), features);
+    }
+
+    rconnection.send(vec![42]).unwrap();
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(5), lconnection.recv())
+        .await
+        .unwrap();
+    match notification {
+        Some(Notification::Message(t)) => {
+            assert_eq!(t.source(), rcenter.public);
+            assert_eq!(t.message.body.as_bytes(), vec![42]);
+        }
+        _ => panic!("expected a message"),
+    }
+
+    // Messages of nodes without a Connection still reach the Interface.
+    drop(lconnection);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    rconnection.send(vec![43]).unwrap();
+    let t = linterface.recv().await.unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![43]);
+
+    linterface.shutdown().await;
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(10), rconnection.recv())
+        .await
+        .unwrap();
+    assert!(matches!(notification, Some(Notification::Disconnected)));
+    assert!(rconnection.send(vec![44]).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_relay() {
+    // Both nodes register with the first one as their relay, so they
+    // only ever talk to each other through it.
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42481);
+    let (_, secret) = box_::gen_keypair();
+    let scenter = Center::new(secret, String::from("127.0.0.1"), 42478);
+    let _sinterface = Interface::new(config, scenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42478);
+    config.relay = true;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42479);
+    let linterface = Interface::new(config, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42478);
+    config.relay = true;
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42480);
+    let rinterface = Interface::new(config, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let found = rinterface.lookup(&lcenter.public).await;
+    let node = found.first().unwrap();
+    assert_eq!(node.address, lcenter.public);
+    assert_eq!(
+        node.link.as_ref().unwrap().relay,
+        Some(scenter.public.clone())
+    );
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    let _ = linterface.send(test.clone());
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), rinterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![43],
+    ));
+    let _ = rinterface.send(test.clone());
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), linterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_relay_lost() {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42562);
+    let (_, secret) = box_::gen_keypair();
+    let scenter = Center::new(secret, String::from("127.0.0.1"), 42561);
+    let sinterface = Interface::new(config, scenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42561);
+    config.relay = true;
+    config.timeout = 1;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42562);
+    let linterface = Interface::new(config, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Without its relay the node advertises its own Link again.
+    sinterface.shutdown().await;
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42562);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42563);
+    let rinterface = Interface::new(config, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let found = rinterface.lookup(&lcenter.public).await;
+    let node = found.first().unwrap();
+    assert_eq!(node.address, lcenter.public);
+    assert_eq!(node.link, Some(lcenter.link.clone()));
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    let _ = rinterface.send(test.clone());
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), linterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_compression() {
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42483);
+    lconfig.compression = Some(64);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42482);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42482);
+    rconfig.compression = Some(64);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42483);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut features = Features::COMPRESSION;
+    features.insert(Features::ENCRYPTION);
+    features.insert(Features::HEARTBEAT);
+    features.insert(Features::PROOF);
+    let rconnection = rinterface.connect(&lcenter.public).await.unwrap();
+    let lconnection = linterface.connect(&rcenter.public).await.unwrap();
+    for connection in [&rconnection, &lconnection] {
+        let notification =
+            tokio::time::timeout(std::time::Duration::from_secs(5), connection.recv())
+                .await
+                .unwrap();
+        assert!(matches!(notification, Some(Notification::Connected)));
+        assert_eq!(connection.features(), features);
+    }
+
+    let body = r#"{"name": "actaeon", "type": "topic"}"#.repeat(200).into_bytes();
+    rconnection.send(body.clone()).unwrap();
+    let notification = tokio::time::timeout(std::time::Duration::from_secs(5), lconnection.recv())
+        .await
+        .unwrap();
+    match notification {
+        Some(Notification::Message(t)) => {
+            assert_eq!(t.source(), rcenter.public);
+            assert_eq!(t.message.body.as_bytes(), body);
+        }
+        _ => panic!("expected a message"),
+    }
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_fragments() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42485);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42484);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42484);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42485);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Far beyond what the two length bytes can describe.
+    let body: Vec<u8> = (0..500_000).map(|_| rand::random()).collect();
+    rinterface
+        .message(lcenter.public.clone(), body.clone())
+        .unwrap();
+    let t = tokio::time::timeout(std::time::Duration::from_secs(10), linterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(t.source(), rcenter.public);
+    assert_eq!(t.message.body.as_bytes(), body);
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_custom() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42487);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42486);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42486);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42487);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    rinterface
+        .register(7, move |t| {
+            let _ = sender.send(t);
+        })
+        .unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let custom = |number, body| {
+        Transaction::new(Message::new(
+            Class::Custom(number),
+            lcenter.public.clone(),
+            rcenter.public.clone(),
+            Address::default(),
+            body,
+        ))
+    };
+    linterface.send(custom(7, vec![42])).unwrap();
+    linterface.send(custom(8, vec![43])).unwrap();
+
+    // Classes without a handler reach the Interface like Actions.
+    let t = tokio::time::timeout(std::time::Duration::from_secs(5), rinterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(t.class(), Class::Custom(8));
+    assert_eq!(t.message.body.as_bytes(), vec![43]);
+
+    let t = receiver
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.class(), Class::Custom(7));
+    assert_eq!(t.source(), lcenter.public);
+    assert_eq!(t.message.body.as_bytes(), vec![42]);
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_builder() {
+    // Nothing listens on the signaling port, the nodes only know each
+    // other through the bootstrap nodes.
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45618);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45616);
+    let linterface = Interface::builder(lconfig, lcenter.clone())
+        .transport(Transport::Udp)
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let mut events = linterface.events();
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45618);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 45617);
+    let rinterface = Interface::builder(rconfig, rcenter.clone())
+        .transport(Transport::Udp)
+        .disable_signaling()
+        .bootstrap_nodes(vec![Node::new(
+            lcenter.public.clone(),
+            Some(lcenter.link.clone()),
+        )])
+        .build()
+        .await
+        .unwrap();
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    rinterface.send(test.clone()).unwrap();
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), linterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![43],
+    ));
+    linterface.send(test.clone()).unwrap();
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), rinterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+
+    while let Some(event) = events.try_recv() {
+        assert_ne!(event, Event::BootstrapFailed);
+    }
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_status() {
+    // Nobody listens on the signaling port, so the bootstrap fails.
+    let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42533);
+    config.status = Some(42532);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42534);
+    let interface = Interface::new(config, center).await.unwrap();
+
+    let mut report = status("GET /health HTTP/1.1\r\n\r\n").await;
+    for _ in 0..50 {
+        if report.starts_with("HTTP/1.0 200") && !report.contains("pending") {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        report = status("GET / HTTP/1.1\r\n\r\n").await;
+    }
+    let body = report.split("\r\n\r\n").nth(1).unwrap();
+    let report: Report = serde_json::from_str(body).unwrap();
+    assert_eq!(report.bootstrap, Bootstrap::Failed);
+    assert_eq!((report.table, report.records), (0, 0));
+
+    let report = status("DELETE / HTTP/1.1\r\n\r\n").await;
+    assert!(report.starts_with("HTTP/1.0 405"));
+    #[cfg(feature = "prometheus")]
+    {
+        let metrics = status("GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(metrics.contains("# TYPE actaeon_connections gauge\n"));
+        assert!(interface.prometheus().contains("actaeon_bucket_nodes"));
+    }
+    interface.shutdown().await;
+    assert!(std::net::TcpStream::connect("127.0.0.1:42532").is_err());
+}
+
+/// Sends the raw request to the status endpoint and returns the full
+/// response.
+async fn status(request: &'static str) -> String {
+    tokio::task::spawn_blocking(move || {
+        use std::io::{Read, Write};
+        let mut stream = std::net::TcpStream::connect("127.0.0.1:42532").unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+    .await
+    .unwrap()
+}
+
+#[derive(Default)]
+struct Verdicts(Mutex<Vec<Verdict>>);
+
+impl TraceSink for Verdicts {
+    fn decide(&self, verdict: &Verdict) {
+        self.0.lock().unwrap().push(verdict.clone());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_trace() {
+    let verdicts = Arc::new(Verdicts::default());
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42535);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42536);
+    let linterface = Interface::builder(config, lcenter.clone())
+        .disable_signaling()
+        .trace(verdicts.clone())
+        .build()
+        .await
+        .unwrap();
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42535);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42537);
+    let rinterface = Interface::builder(config, rcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let lnode = Node::new(lcenter.public.clone(), Some(lcenter.link.clone()));
+    rinterface.import_nodes(vec![lnode]);
+
+    let action = |target: Address| {
+        Transaction::new(Message::new(
+            Class::Action,
+            rcenter.public.clone(),
+            target,
+            Address::default(),
+            vec![1],
+        ))
+    };
+    let delivered = action(lcenter.public.clone());
+    rinterface.send(delivered.clone()).unwrap();
+    assert!(linterface
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .await
+        .is_some());
+    // The only other known node is the sender, which is farther away.
+    let target = loop {
+        let target = Address::random();
+        if (&lcenter.public ^ &target) < (&rcenter.public ^ &target) {
+            break target;
+        }
+    };
+    let unroutable = action(target);
+    rinterface.send(unroutable.clone()).unwrap();
+    for _ in 0..50 {
+        if verdicts.0.lock().unwrap().len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let verdicts = verdicts.0.lock().unwrap().clone();
+    let decision = |uuid| {
+        verdicts
+            .iter()
+            .find(|verdict| verdict.uuid == uuid)
+            .map(|verdict| verdict.decision.clone())
+    };
+    assert_eq!(decision(delivered.uuid), Some(Decision::Delivered));
+    assert_eq!(
+        decision(unroutable.uuid),
+        Some(Decision::Dropped(Reason::NoRoute))
+    );
+    assert!(verdicts
+        .iter()
+        .all(|verdict| verdict.source == rcenter.public && verdict.class == Some(Class::Action)));
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_retry() {
+    let gen_config = || {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42538);
+        config.retry = 1;
+        config
+    };
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42539);
+    let linterface = Interface::builder(gen_config(), lcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42540);
+    let rinterface = Interface::builder(gen_config(), rcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let mut events = linterface.events();
+
+    let action = |target: Address| {
+        Transaction::new(Message::new(
+            Class::Action,
+            lcenter.public.clone(),
+            target,
+            Address::default(),
+            vec![1],
+        ))
+    };
+    // Without any known node the Transaction is given up after the TTL.
+    let lost = action(Address::random());
+    linterface.send(lost.clone()).unwrap();
+    let failed = loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if let Event::DeliveryFailed(uuid) = event {
+            break uuid;
+        }
+    };
+    assert_eq!(failed, lost.uuid);
+
+    // A node that becomes known in time still receives it.
+    let late = action(rcenter.public.clone());
+    linterface.send(late.clone()).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    let rnode = Node::new(rcenter.public.clone(), Some(rcenter.link.clone()));
+    linterface.import_nodes(vec![rnode]);
+    let ret = rinterface
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(ret, late);
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_discovery() {
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45621);
+    lconfig.discovery = Some(45620);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45622);
+    let linterface = Interface::builder(lconfig, lcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let mut events = linterface.events();
+
+    // The discovery port is already bound, so this node only sends
+    // announcements and learns about the other one from its reply.
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45621);
+    rconfig.discovery = Some(45620);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 45623);
+    let rinterface = Interface::builder(rconfig, rcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::NodeAdded(rcenter.public.clone()) {
+            break;
+        }
+    }
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    linterface.send(test.clone()).unwrap();
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), rinterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_bootstrap_nodes() {
+    // Nothing listens on the signaling port.
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45624);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45625);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45624);
+    rconfig.bootstrap = vec![Node::new(
+        lcenter.public.clone(),
+        Some(lcenter.link.clone()),
+    )];
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 45626);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    // The other node connects without sending any message.
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::Connected(rcenter.public.clone()) {
+            break;
+        }
+    }
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    linterface.send(test.clone()).unwrap();
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), rinterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_eviction() {
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45630);
+    lconfig.ping = 1;
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45627);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    let gen_remote = |port| {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45630);
+        config.bootstrap = vec![Node::new(
+            lcenter.public.clone(),
+            Some(lcenter.link.clone()),
+        )];
+        let (_, secret) = box_::gen_keypair();
+        (config, Center::new(secret, String::from("127.0.0.1"), port))
+    };
+    let (config, dcenter) = gen_remote(45628);
+    let dinterface = Interface::new(config, dcenter.clone()).await.unwrap();
+    let (config, acenter) = gen_remote(45629);
+    let ainterface = Interface::new(config, acenter.clone()).await.unwrap();
+
+    let mut connected = 0;
+    while connected < 2 {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if let Event::Connected(_) = event {
+            connected += 1;
+        }
+    }
+
+    // Only the node that is gone stops answering the Pings.
+    dinterface.shutdown().await;
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(15), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(event, Event::NodeRemoved(acenter.public.clone()));
+        if event == Event::NodeRemoved(dcenter.public.clone()) {
+            break;
+        }
+    }
+
+    linterface.shutdown().await;
+    ainterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_routing_snapshot() {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45632);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 45631);
+    let interface = Interface::builder(config, center)
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+
+    let nodes: Vec<Node> = (0..2)
+        .map(|i| {
+            let link = actaeon::node::Link::new(String::from("127.0.0.1"), 45633 + i);
+            Node::new(Address::random(), Some(link))
+        })
+        .collect();
+    interface.import_nodes(nodes.clone());
+
+    let snapshot = interface.routing_snapshot();
+    let mut addresses: Vec<String> = snapshot
+        .buckets
+        .iter()
+        .flat_map(|bucket| bucket.nodes.iter().map(|node| node.address.clone()))
+        .collect();
+    addresses.sort();
+    let mut expected: Vec<String> = nodes.iter().map(|node| node.address.to_hex()).collect();
+    expected.sort();
+    assert_eq!(addresses, expected);
+
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert!(json.contains("127.0.0.1:45633"));
+
+    interface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_routing_rtt() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42552);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42550);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42552);
+    rconfig.bootstrap = vec![Node::new(
+        lcenter.public.clone(),
+        Some(lcenter.link.clone()),
+    )];
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42551);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::Connected(rcenter.public.clone()) {
+            break;
+        }
+    }
+
+    let rtt = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let rtt = linterface
+                .routing_snapshot()
+                .buckets
+                .into_iter()
+                .flat_map(|bucket| bucket.nodes)
+                .find(|node| node.address == rcenter.public.to_hex())
+                .and_then(|node| node.rtt);
+            if let Some(rtt) = rtt {
+                break rtt;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(rtt < 5_000_000);
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_listen() {
+    // Both nodes share a network besides the ones of their Centers,
+    // all of them are loopback addresses.
+    let mut lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42555);
+    let lan = actaeon::node::Link::new(String::from("127.0.1.1"), 42554);
+    lconfig.listen = vec![lan.clone()];
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.2.1"), 42553);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    // The node is reachable through the further Link as well.
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42555);
+    rconfig.listen = vec![actaeon::node::Link::new(String::from("127.0.1.2"), 42557)];
+    rconfig.bootstrap = vec![Node::new(lcenter.public.clone(), Some(lan))];
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.3.1"), 42556);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::Connected(rcenter.public.clone()) {
+            break;
+        }
+    }
+
+    // The peer is stored with the Link of the shared network.
+    let link = linterface
+        .routing_snapshot()
+        .buckets
+        .into_iter()
+        .flat_map(|bucket| bucket.nodes)
+        .find(|node| node.address == rcenter.public.to_hex())
+        .and_then(|node| node.link);
+    assert_eq!(link, Some(String::from("127.0.1.2:42557")));
+
+    rinterface.shutdown().await;
+    linterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_placement() {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45648);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 45647);
+    let interface = Interface::builder(config, center.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+
+    let target = Address::random();
+    assert!(interface.is_responsible_for(&target));
+    assert_eq!(interface.closest(&target, 3), vec![center.public.clone()]);
+
+    let nodes: Vec<Node> = (0..4)
+        .map(|i| {
+            let link = actaeon::node::Link::new(String::from("127.0.0.1"), 45649 + i);
+            Node::new(Address::random(), Some(link))
+        })
+        .collect();
+    interface.import_nodes(vec![nodes[0].clone()]);
+    assert!(!interface.is_responsible_for(&nodes[0].address));
+    interface.import_nodes(nodes[1..].to_vec());
+
+    let mut expected: Vec<Address> = nodes.iter().map(|node| node.address.clone()).collect();
+    expected.push(center.public.clone());
+    expected.sort_by_key(|address| address ^ &target);
+    assert_eq!(interface.closest(&target, 3), expected[..3].to_vec());
+    assert!(interface.is_responsible_for(&center.public));
+
+    let closest: Vec<Address> = interface
+        .iter_closest(&target)
+        .map(|node| node.address)
+        .collect();
+    expected.retain(|address| address != &center.public);
+    assert_eq!(closest, expected);
+
+    interface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_difficulty() {
+    let gen_config = || {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45636);
+        config.difficulty = 8;
+        config
+    };
+    let weak = loop {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, String::from("127.0.0.1"), 45635);
+        if center.public.work() < 8 {
+            break center;
+        }
+    };
+    let result = Interface::builder(gen_config(), weak.clone())
+        .disable_signaling()
+        .build()
+        .await;
+    assert!(result.is_err());
+
+    let center = Center::generate(String::from("127.0.0.1"), 45635, 8);
+    let interface = Interface::builder(gen_config(), center)
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let strong = Center::generate(String::from("127.0.0.1"), 45637, 8);
+    interface.import_nodes(vec![
+        Node::new(weak.public.clone(), Some(weak.link.clone())),
+        Node::new(strong.public.clone(), Some(strong.link.clone())),
+    ]);
+    let snapshot = interface.routing_snapshot();
+    let addresses: Vec<String> = snapshot
+        .buckets
+        .iter()
+        .flat_map(|bucket| bucket.nodes.iter().map(|node| node.address.clone()))
+        .collect();
+    assert_eq!(addresses, vec![strong.public.to_hex()]);
+
+    interface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_ban() {
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45640);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45638);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+    let mut events = linterface.events();
+
+    let mut rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45640);
+    rconfig.bootstrap = vec![Node::new(
+        lcenter.public.clone(),
+        Some(lcenter.link.clone()),
+    )];
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 45639);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::Connected(rcenter.public.clone()) {
+            break;
+        }
+    }
+
+    linterface.ban(rcenter.public.clone());
+    assert!(linterface
+        .routing_snapshot()
+        .buckets
+        .iter()
+        .all(|bucket| bucket.nodes.is_empty()));
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    rinterface.send(test).unwrap();
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(2), linterface.recv()).await;
+    assert!(ret.is_err());
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_reconfigure() {
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42496);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42497);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+    let mut events = rinterface.events();
+
+    let invalid = ConfigDelta {
+        connections: Some(0),
+        ..Default::default()
+    };
+    assert!(rinterface.reconfigure(invalid).is_err());
+    // Only connections opened afterwards use the new limit.
+    let delta = ConfigDelta {
+        message_size: Some(4096),
+        ..Default::default()
+    };
+    rinterface.reconfigure(delta).unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42497);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42496);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![42; 8192],
+    ));
+    let _ = linterface.send(test);
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == Event::ProtocolError(lcenter.public.clone()) {
+            break;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_recv_timeout() {
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42499);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42498);
+    let interface = Interface::builder(config, center)
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+
+    let timeout = std::time::Duration::from_millis(50);
+    let started = std::time::Instant::now();
+    assert_eq!(interface.recv_timeout(timeout).await, None);
+    assert!(started.elapsed() >= timeout);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_stream_sink() {
+    use futures::{SinkExt, StreamExt};
+
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42500);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 42501);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42501);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42500);
+    let mut rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let messages: Vec<Transaction> = (0..3)
+        .map(|i| {
+            Transaction::new(Message::new(
+                Class::Action,
+                lcenter.public.clone(),
+                rcenter.public.clone(),
+                Address::default(),
+                vec![i],
+            ))
+        })
+        .collect();
+    let mut sink = linterface.sink();
+    for message in messages.clone() {
+        sink.send(message).await.unwrap();
+    }
+
+    let received = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        (&mut rinterface).take(3).collect::<Vec<_>>(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(received, messages);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_forwarding() {
+    // Each node only knows the next one, which is closer to the last
+    // node. Messages to it have to travel along the entire chain.
+    let mut centers: Vec<Center> = (0..4)
+        .map(|i| {
+            let (_, secret) = box_::gen_keypair();
+            Center::new(secret, String::from("127.0.0.1"), 42502 + i)
+        })
+        .collect();
+    let target = centers.pop().unwrap();
+    centers.sort_by_key(|center| std::cmp::Reverse(&center.public ^ &target.public));
+    centers.push(target.clone());
+
+    let mut interfaces = Vec::new();
+    for center in centers.iter() {
+        let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42506);
+        let interface = Interface::builder(config, center.clone())
+            .disable_signaling()
+            .build()
+            .await
+            .unwrap();
+        interfaces.push(interface);
+    }
+    for (interface, next) in interfaces.iter().zip(centers.iter().skip(1)) {
+        interface.import_nodes(vec![Node::new(
+            next.public.clone(),
+            Some(next.link.clone()),
+        )]);
+    }
+
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        centers[0].public.clone(),
+        target.public.clone(),
+        Address::default(),
+        vec![42],
+    ));
+    interfaces[0].send(test.clone()).unwrap();
+
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), interfaces[3].recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, test);
+    assert_eq!(ret.hops, actaeon::transaction::HOPS - 2);
+
+    // The second node is closer to this target than any node it
+    // knows, so it drops the message instead of sending it back.
+    let mut bytes = centers[1].public.as_bytes().to_vec();
+    bytes[31] ^= 1;
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        centers[0].public.clone(),
+        Address::from_slice(&bytes).unwrap(),
+        Address::default(),
+        vec![43],
+    ));
+    interfaces[0].send(test).unwrap();
+
+    let started = std::time::Instant::now();
+    while interfaces[1].metrics().dropped == 0 {
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert_eq!(interfaces[0].metrics().dropped, 0);
+
+    for interface in interfaces {
+        interface.shutdown().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_storage() {
+    let ports = [42523, 42524, 42525];
+    let mut interfaces = Vec::new();
+    let mut centers: Vec<Center> = Vec::new();
+    for port in ports.iter() {
+        // Values are only stored on the closest node, so the others
+        // have to request them.
+        let mut config = Config::new(20, 10, 1000, "127.0.0.1".to_string(), 42526);
+        config.replicas = 1;
+        config.bootstrap = centers
+            .iter()
+            .map(|center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let key = Address::random();
+    let ttl = std::time::Duration::from_secs(60);
+    interfaces[0].put(key.clone(), vec![42], ttl).unwrap();
+    let short = Address::random();
+    let ttl = std::time::Duration::from_secs(1);
+    interfaces[0].put(short.clone(), vec![43], ttl).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    for interface in &interfaces {
+        assert_eq!(interface.get(&key).await, Some(vec![42]));
+        assert_eq!(interface.get(&short).await, Some(vec![43]));
+        assert_eq!(interface.get(&Address::random()).await, None);
+    }
+
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    for interface in &interfaces {
+        assert_eq!(interface.get(&short).await, None);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_storage_foreign() {
+    let ports = [42590, 42591, 42592];
+    let key = Address::random();
+    let mut secrets: Vec<_> = (0..3).map(|_| box_::gen_keypair().1).collect();
+    secrets.sort_by_key(|secret| &Center::new(secret.clone(), String::new(), 0).public ^ &key);
+    let mut interfaces = Vec::new();
+    let mut centers: Vec<Center> = Vec::new();
+    for (secret, port) in secrets.into_iter().zip(ports.iter()) {
+        let mut config = Config::new(20, 10, 1000, "127.0.0.1".to_string(), 42593);
+        config.replicas = 1;
+        config.bootstrap = centers
+            .iter()
+            .map(|center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    // Only the node that stored the value can replace it.
+    let ttl = std::time::Duration::from_secs(60);
+    interfaces[1].put(key.clone(), vec![42], ttl).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    interfaces[2].put(key.clone(), vec![43], ttl).unwrap();
+
+    // The farthest node isn't a replica of the key, so it doesn't
+    // store the value and still asks the closest node for it.
+    let message = Message::new(
+        Class::Store,
+        centers[1].public.clone(),
+        centers[2].public.clone(),
+        key.clone(),
+        actaeon::storage::encode(&[44], ttl),
+    );
+    interfaces[1].send(Transaction::new(message)).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    for interface in &interfaces {
+        assert_eq!(interface.get(&key).await, Some(vec![42]));
+    }
+    for interface in interfaces {
+        interface.shutdown().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_storage_backend() {
+    let storage = MemoryStorage::new();
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45654);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 45653);
+    let interface = Interface::builder(config, center)
+        .disable_signaling()
+        .storage(Arc::new(storage.clone()))
+        .build()
+        .await
+        .unwrap();
+
+    // Without any other nodes the Record is hosted locally.
+    let topic = Address::random();
+    let _topic = interface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let link = actaeon::node::Link::new(String::from("127.0.0.1"), 45655);
+    let node = Node::new(Address::random(), Some(link));
+    interface.import_nodes(vec![node.clone()]);
+    interface.shutdown().await;
+
+    let records = storage.iterate(backend::RECORDS).unwrap();
+    let keys: Vec<Vec<u8>> = records.into_iter().map(|(key, _)| key).collect();
+    assert_eq!(keys, vec![topic.as_bytes().to_vec()]);
+    let nodes = Database::with_storage(Arc::new(storage)).load().unwrap();
+    assert!(nodes.contains(&node));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_outbox() {
+    let storage = MemoryStorage::new();
+    let gen_config = || {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42541);
+        config.outbox = Some(8);
+        config
+    };
+    let (_, lsecret) = box_::gen_keypair();
+    let lcenter = Center::new(lsecret.clone(), String::from("127.0.0.1"), 42542);
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 42543);
+    // The restarted node keeps its identity but listens on another
+    // port, the old socket might not be released right away.
+    let start = |port| {
+        let center = Center::new(lsecret.clone(), String::from("127.0.0.1"), port);
+        Interface::builder(gen_config(), center)
+            .disable_signaling()
+            .storage(Arc::new(storage.clone()))
+            .build()
+    };
+
+    // Messages sent while offline survive a restart.
+    let linterface = start(42542).await.unwrap();
+    let test = Transaction::new(Message::new(
+        Class::Action,
+        lcenter.public.clone(),
+        rcenter.public.clone(),
+        Address::default(),
+        vec![7],
+    ));
+    linterface.send(test.clone()).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(linterface.pending(), vec![test.clone()]);
+    linterface.shutdown().await;
+    assert_eq!(storage.iterate(backend::OUTBOX).unwrap().len(), 1);
+
+    let linterface = start(45666).await.unwrap();
+    assert_eq!(linterface.pending(), vec![test.clone()]);
+    let rinterface = Interface::builder(gen_config(), rcenter.clone())
+        .disable_signaling()
+        .build()
+        .await
+        .unwrap();
+    let rnode = Node::new(rcenter.public.clone(), Some(rcenter.link.clone()));
+    linterface.import_nodes(vec![rnode]);
+    let ret = rinterface
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert_eq!(ret, test);
+    assert_eq!(ret.message.body.as_bytes(), vec![7]);
+    assert!(linterface.pending().is_empty());
+    assert!(storage.iterate(backend::OUTBOX).unwrap().is_empty());
+
+    linterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_trust() {
+    let policy = || Arc::new(SharedSecretHmac::new(b"cluster"));
+    let lconfig = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45658);
+    let (_, secret) = box_::gen_keypair();
+    let lcenter = Center::new(secret, String::from("127.0.0.1"), 45656);
+    let linterface = Interface::builder(lconfig, lcenter.clone())
+        .trust(policy())
+        .build()
+        .await
+        .unwrap();
+    let mut events = linterface.events();
+
+    let gen_config = || {
+        let mut config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 45658);
+        config.bootstrap = vec![Node::new(
+            lcenter.public.clone(),
+            Some(lcenter.link.clone()),
+        )];
+        config
+    };
+    let (_, secret) = box_::gen_keypair();
+    let ucenter = Center::new(secret, String::from("127.0.0.1"), 45659);
+    let uinterface = Interface::builder(gen_config(), ucenter.clone())
+        .trust(Arc::new(SharedSecretHmac::new(b"other")))
+        .build()
+        .await
+        .unwrap();
+    let (_, secret) = box_::gen_keypair();
+    let rcenter = Center::new(secret, String::from("127.0.0.1"), 45657);
+    let rinterface = Interface::builder(gen_config(), rcenter.clone())
+        .trust(policy())
+        .build()
+        .await
+        .unwrap();
+
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(event, Event::Connected(ucenter.public.clone()));
+        if event == Event::Connected(rcenter.public.clone()) {
+            break;
+        }
+    }
+    let addresses: Vec<String> = linterface
+        .routing_snapshot()
+        .buckets
+        .iter()
+        .flat_map(|bucket| bucket.nodes.iter().map(|node| node.address.clone()))
+        .collect();
+    assert_eq!(addresses, vec![rcenter.public.to_hex()]);
+
+    for (interface, center) in [(&uinterface, &ucenter), (&rinterface, &rcenter)] {
+        let test = Transaction::new(Message::new(
+            Class::Action,
+            center.public.clone(),
+            lcenter.public.clone(),
+            Address::default(),
+            vec![42],
+        ));
+        interface.send(test).unwrap();
+    }
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(5), linterface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret.source(), rcenter.public);
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(2), linterface.recv()).await;
+    assert!(ret.is_err());
+
+    linterface.shutdown().await;
+    uinterface.shutdown().await;
+    rinterface.shutdown().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_interface_loopback() {
+    // Nothing listens on the signaling port, the node is on its own.
+    let config = Config::new(20, 5, 100, "127.0.0.1".to_string(), 42530);
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42529);
+    let interface = Interface::new(config, center.clone()).await.unwrap();
+
+    let direct = Transaction::new(Message::new(
+        Class::Action,
+        center.public.clone(),
+        center.public.clone(),
+        Address::random(),
+        vec![1],
+    ));
+    interface.send(direct.clone()).unwrap();
+    let ret = tokio::time::timeout(std::time::Duration::from_secs(1), interface.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(ret, direct);
+    assert_eq!(ret.message.body.as_bytes(), vec![1]);
+    assert!(ret.metadata().ingress.is_none());
+    assert!(ret.metadata().loopback);
+
+    // The local Topic receives its copy even though the target can't
+    // be reached.
+    let address = Address::random();
+    let mut topic = interface.subscribe(&address);
+    for body in 2..5 {
+        let t = Transaction::new(Message::new(
+            Class::Action,
+            center.public.clone(),
+            Address::random(),
+            address.clone(),
+            vec![body],
+        ));
+        interface.send(t).unwrap();
+    }
+    for body in 2..5 {
+        let t = topic
+            .recv_timeout(std::time::Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(t.message.body.as_bytes(), vec![body]);
+    }
+}
@@ -0,0 +1,100 @@
+use actaeon::{
+    message::Message,
+    node::{Address, Link, Node},
+    transaction::{Class, Fragments, Transaction, Wire, HOPS},
+};
+use proptest::prelude::*;
+use std::time::{Duration, SystemTime};
+
+fn address() -> impl Strategy<Value = Address> {
+    any::<[u8; 32]>().prop_map(Address::from_bytes)
+}
+
+fn link() -> impl Strategy<Value = Link> {
+    let host = prop_oneof![
+        any::<[u8; 4]>().prop_map(|ip| std::net::Ipv4Addr::from(ip).to_string()),
+        any::<[u8; 16]>().prop_map(|ip| std::net::Ipv6Addr::from(ip).to_string()),
+        "[a-z][a-z0-9-]{0,20}(\\.[a-z]{2,6})?",
+    ];
+    (host, 0..=u16::MAX as usize, proptest::option::of(address())).prop_map(
+        |(host, port, relay)| match relay {
+            Some(relay) => Link::relayed(host, port, relay),
+            None => Link::new(host, port),
+        },
+    )
+}
+
+fn transaction() -> impl Strategy<Value = Transaction> {
+    (
+        address(),
+        address(),
+        address(),
+        proptest::collection::vec(any::<u8>(), 0..4096),
+        proptest::option::of(any::<u64>()),
+        0..=HOPS,
+        proptest::option::of(1..u32::MAX as u64),
+    )
+        .prop_map(|(source, target, topic, body, sequence, hops, deadline)| {
+            let mut message = Message::new(Class::Action, source, target, topic, body);
+            message.sequence = sequence;
+            let mut t = Transaction::new(message);
+            t.hops = hops;
+            t.deadline =
+                deadline.map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis));
+            t
+        })
+}
+
+proptest! {
+    #[test]
+    fn test_parse_transaction(t in transaction()) {
+        let wire = Wire::from_bytes(&t.to_wire().as_bytes()).unwrap();
+        let parsed = wire.convert().unwrap();
+        prop_assert_eq!(&parsed, &t);
+        prop_assert_eq!(parsed.message, t.message);
+        prop_assert_eq!(parsed.hops, t.hops);
+        prop_assert_eq!(parsed.deadline, t.deadline);
+    }
+
+    #[test]
+    fn test_parse_wire_arbitrary(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        if let Ok(wire) = Wire::from_bytes(&data) {
+            prop_assert_eq!(wire.as_bytes(), data);
+            let _ = wire.convert();
+        }
+    }
+
+    #[test]
+    fn test_parse_wire_truncated(t in transaction(), cut in 1..4096usize) {
+        let data = t.to_wire().as_bytes();
+        let cut = cut.min(data.len());
+        prop_assert!(Wire::from_bytes(&data[..data.len() - cut]).is_err());
+    }
+
+    #[test]
+    fn test_parse_fragments_arbitrary(data in proptest::collection::vec(any::<u8>(), 0..1024)) {
+        if let Ok(wire) = Wire::from_bytes(&data) {
+            let _ = Fragments::new().insert(wire);
+        }
+    }
+
+    #[test]
+    fn test_parse_link(link in link()) {
+        prop_assert_eq!(Link::from_bytes(link.as_bytes()).unwrap(), link);
+    }
+
+    #[test]
+    fn test_parse_node(address in address(), link in proptest::option::of(link())) {
+        let node = Node::new(address, link);
+        let parsed = Node::from_bytes(node.as_bytes()).unwrap();
+        prop_assert_eq!(parsed.address, node.address);
+        prop_assert_eq!(parsed.link, node.link);
+    }
+
+    #[test]
+    fn test_parse_node_arbitrary(data in proptest::collection::vec(any::<u8>(), 0..512)) {
+        let _ = Node::from_bytes(data.clone());
+        let _ = Node::from_bulk(data.clone());
+        let _ = Link::from_bytes(data);
+    }
+}
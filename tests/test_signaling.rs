@@ -1,4 +1,4 @@
-use actaeon::config::Signaling;
+use actaeon::config::Config;
 use actaeon::handler::Listener;
 use actaeon::message::Message;
 use actaeon::node::{Address, Center, Link, Node};
@@ -14,32 +14,34 @@ fn test_auto_bootstrap() {
         Some(Link::new(String::from("example.com"), 45678)),
     );
 
-    let (w1, _) = Channel::new();
+    let (w1, _w2) = Channel::new();
     let (_, secret) = box_::gen_keypair();
     let lcenter = Center::new(secret, String::from("127.0.0.1"), 42437);
     let rnode = Node::new(lcenter.public.clone(), Some(lcenter.link.clone()));
     let ltable = Safe::new(42, lcenter.clone());
     ltable.add(test_node.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 42438);
-    let llistener = Listener::new(lcenter.clone(), w1, 10, ltable.clone(), signaling).unwrap();
-    let _ = llistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 42438);
+    let llistener = Listener::new(lcenter.clone(), w1, ltable.clone(), config).unwrap();
+    llistener.start();
 
     std::thread::sleep(std::time::Duration::from_millis(25));
 
     // remote
-    let (r1, _) = Channel::new();
+    let (r1, _r2) = Channel::new();
     let (_, secret) = box_::gen_keypair();
     let rcenter = Center::new(secret, String::from("127.0.0.1"), 42438);
     let rtable = Safe::new(42, rcenter.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 42437);
-    let rlistener = Listener::new(rcenter.clone(), r1, 10, rtable.clone(), signaling).unwrap();
-    let _ = rlistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 42437);
+    let rlistener = Listener::new(rcenter.clone(), r1, rtable.clone(), config).unwrap();
+    rlistener.start();
 
-    std::thread::sleep(std::time::Duration::from_millis(25));
+    std::thread::sleep(std::time::Duration::from_millis(100));
 
+    // The node from the reply can't be reached, so only the one that
+    // completed the handshake gets added.
     let found = rtable.get_copy(&Address::random(), 5);
-    assert_eq!(found.len(), 2);
-    assert_eq!(found, vec![test_node.clone(), rnode.clone()]);
+    assert_eq!(found, vec![rnode.clone()]);
+    assert!(rtable.find(&test_node.address).is_none());
 
     // test if the nodes are actually equal (custom Eq)
     let mut bytes = Vec::new();
@@ -47,7 +49,7 @@ fn test_auto_bootstrap() {
         bytes.push(n.as_bytes());
     }
 
-    assert_eq!(bytes, vec![test_node.as_bytes(), rnode.as_bytes()]);
+    assert_eq!(bytes, vec![rnode.as_bytes()]);
 }
 
 #[test]
@@ -57,9 +59,9 @@ fn test_auto_messaging() {
     let lcenter = Center::new(secret, String::from("127.0.0.1"), 42441);
     let target = lcenter.public.clone();
     let ltable = Safe::new(42, lcenter.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 42442);
-    let llistener = Listener::new(lcenter.clone(), w1, 10, ltable.clone(), signaling).unwrap();
-    let _ = llistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 42442);
+    let llistener = Listener::new(lcenter.clone(), w1, ltable.clone(), config).unwrap();
+    llistener.start();
 
     std::thread::sleep(std::time::Duration::from_millis(25));
 
@@ -69,9 +71,9 @@ fn test_auto_messaging() {
     let rcenter = Center::new(secret, String::from("127.0.0.1"), 42442);
     let source = rcenter.public.clone();
     let rtable = Safe::new(42, rcenter.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 42441);
-    let rlistener = Listener::new(rcenter.clone(), r1, 10, rtable.clone(), signaling).unwrap();
-    let _ = rlistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 42441);
+    let rlistener = Listener::new(rcenter.clone(), r1, rtable.clone(), config).unwrap();
+    rlistener.start();
 
     std::thread::sleep(std::time::Duration::from_millis(25));
 
@@ -1,9 +1,11 @@
-use actaeon::config::Signaling;
+use actaeon::config::Config;
 use actaeon::handler::Listener;
+use actaeon::handshake::{Features, Hello};
 use actaeon::message::Message;
 use actaeon::node::{Address, Center, Node};
 use actaeon::router::Safe;
 use actaeon::transaction::{Class, Transaction};
+use actaeon::transport::{BufferedTcp, Stream};
 use actaeon::util::Channel;
 use sodiumoxide::crypto::box_;
 use std::io::Write;
@@ -16,9 +18,10 @@ fn test_tcp_init() {
     let (_, secret) = box_::gen_keypair();
     let center = Center::new(secret, String::from("127.0.0.1"), 42424);
     let table = Safe::new(42, center.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let listener = Listener::new(center, w1, 10, table, signaling).unwrap();
-    let _ = listener.start();
+    let mut config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    config.signatures = false;
+    let listener = Listener::new(center, w1, table, config).unwrap();
+    listener.start();
 
     // message
     let message = Message::new(
@@ -54,9 +57,10 @@ fn test_tcp_message() {
     let (_, secret) = box_::gen_keypair();
     let center = Center::new(secret, String::from("127.0.0.1"), 42425);
     let table = Safe::new(42, center.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let listener = Listener::new(center, w1, 10, table, signaling).unwrap();
-    let _ = listener.start();
+    let mut config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    config.signatures = false;
+    let listener = Listener::new(center, w1, table, config).unwrap();
+    listener.start();
 
     // message
     let message = Message::new(
@@ -106,9 +110,10 @@ fn test_tcp_cache() {
     let (_, secret) = box_::gen_keypair();
     let center = Center::new(secret, String::from("127.0.0.1"), 42431);
     let table = Safe::new(42, center.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let listener = Listener::new(center, w1, 100, table, signaling).unwrap();
-    let _ = listener.start();
+    let mut config = Config::new(20, 100, 100, String::from("127.0.0.1"), 12345);
+    config.signatures = false;
+    let listener = Listener::new(center, w1, table, config).unwrap();
+    listener.start();
 
     // message
     let message = Message::new(
@@ -158,9 +163,10 @@ fn test_tcp_random() {
     let (_, secret) = box_::gen_keypair();
     let center = Center::new(secret, String::from("127.0.0.1"), 42426);
     let table = Safe::new(42, center.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let listener = Listener::new(center, w1, 10, table, signaling).unwrap();
-    let _ = listener.start();
+    let mut config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    config.signatures = false;
+    let listener = Listener::new(center, w1, table, config).unwrap();
+    listener.start();
 
     // message
     let message = Message::new(
@@ -210,9 +216,9 @@ fn test_tcp_outgoing() {
     let lcenter = Center::new(secret, String::from("127.0.0.1"), 42427);
     let lnode = Node::new(lcenter.public.clone(), Some(lcenter.link.clone()));
     let ltable = Safe::new(42, lcenter.clone());
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let llistener = Listener::new(lcenter.clone(), w1, 10, ltable, signaling).unwrap();
-    let _ = llistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    let llistener = Listener::new(lcenter.clone(), w1, ltable, config).unwrap();
+    llistener.start();
 
     // remote
     let (r1, r2) = Channel::new();
@@ -220,9 +226,9 @@ fn test_tcp_outgoing() {
     let rcenter = Center::new(secret, String::from("127.0.0.1"), 42428);
     let rtable = Safe::new(42, rcenter.clone());
     rtable.add(lnode);
-    let signaling = Signaling::new(String::from("127.0.0.1"), 12345);
-    let rlistener = Listener::new(rcenter.clone(), r1, 10, rtable, signaling).unwrap();
-    let _ = rlistener.start();
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    let rlistener = Listener::new(rcenter.clone(), r1, rtable, config).unwrap();
+    rlistener.start();
 
     // message
     let message = Message::new(
@@ -239,3 +245,56 @@ fn test_tcp_outgoing() {
 
     assert_eq!(rett, t);
 }
+
+#[test]
+fn test_tcp_unsigned() {
+    let (w1, w2) = Channel::new();
+    let (_, secret) = box_::gen_keypair();
+    let center = Center::new(secret, String::from("127.0.0.1"), 42429);
+    let table = Safe::new(42, center.clone());
+    let config = Config::new(20, 10, 100, String::from("127.0.0.1"), 12345);
+    let listener = Listener::new(center, w1, table, config).unwrap();
+    listener.start();
+
+    // remote
+    let (_, secret) = box_::gen_keypair();
+    let remote = Center::new(secret, String::from("8.8.8.8"), 12345);
+    let mut conn = BufferedTcp::new(TcpStream::connect("127.0.0.1:42429").unwrap());
+
+    // With signatures enabled the remote has to prove its Address.
+    let mut features = Features::ENCRYPTION;
+    features.insert(Features::PROOF);
+    let hello = Hello::new(&remote, features);
+    conn.write_node(&hello).unwrap();
+    let peer = conn.read_node().unwrap();
+    conn.write_wire(&hello.prove(&remote, &peer).unwrap())
+        .unwrap();
+    let proof = conn.read_wire(1024).unwrap();
+    assert!(hello.verify(&peer, &proof).is_ok());
+
+    // unsigned messages get dropped
+    let message = Message::new(
+        Class::Action,
+        remote.public.clone(),
+        Address::random(),
+        Address::random(),
+        String::from("unsigned").as_bytes().to_vec(),
+    );
+    let unsigned = Transaction::new(message);
+    let _ = conn.write_wire(&unsigned.to_wire());
+
+    let message = Message::new(
+        Class::Action,
+        remote.public.clone(),
+        Address::random(),
+        Address::random(),
+        String::from("signed").as_bytes().to_vec(),
+    );
+    let signed = Transaction::new(message);
+    let mut wire = signed.to_wire();
+    wire.sign(&remote);
+    let _ = conn.write_wire(&wire);
+
+    let ret = w2.recv().unwrap();
+    assert_eq!(ret, signed);
+}
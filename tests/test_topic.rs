@@ -1,123 +1,1544 @@
 use actaeon::{
     self,
     config::Config,
-    node::{Address, Center},
-    Interface,
+    error::Error,
+    event::Event,
+    group::GroupKey,
+    inbox::Retention,
+    message::Message,
+    node::{Address, Center, Node},
+    presence::Presence,
+    record::{Policy, Role},
+    topic::{
+        Command, Delivery, Filter, Gossip, Simple, TopicBucket, TopicOptions, TopicPath, Wildcard,
+        CHUNK_SIZE,
+    },
+    transaction::{Class, Transaction},
+    util::Channel,
+    Interface, Subscription, Topic,
 };
 use sodiumoxide::crypto::box_;
+use std::io::{Read, Write};
 
-#[test]
-fn test_topic_multi() {
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_multi() {
     let port1 = 42460;
     let port2 = 42461;
 
     let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
     let lcenter = gen_center_near("127.0.0.1", port2);
-    let linterface = Interface::new(lconfig, lcenter.clone()).unwrap();
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
     let rcenter = gen_center_far("127.0.0.1", port1);
-    let rinterface = Interface::new(rconfig, rcenter.clone()).unwrap();
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let topic = Address::default();
     // the topic is guaranteed not to be on this node.
     let mut rtopic = rinterface.subscribe(&topic);
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
     let mut ltopic = linterface.subscribe(&topic);
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
 
     for i in 0..23 {
-        std::thread::sleep(std::time::Duration::from_millis(16));
+        tokio::time::sleep(std::time::Duration::from_millis(16)).await;
         let _ = ltopic.broadcast(vec![i]);
         let rret = rtopic.recv().unwrap();
         assert_eq!(rret.message.body.as_bytes(), vec![i]);
     }
 
     for i in 0..55 {
-        std::thread::sleep(std::time::Duration::from_millis(16));
+        tokio::time::sleep(std::time::Duration::from_millis(16)).await;
         let _ = rtopic.broadcast(vec![i]);
         let rret = ltopic.recv().unwrap();
         assert_eq!(rret.message.body.as_bytes(), vec![i]);
     }
 }
 
-use sodiumoxide::crypto::box_::curve25519xsalsa20poly1305::SecretKey;
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_batch() {
+    let (port1, port2) = (42513, 42514);
+    let mut lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    lconfig.coalesce = Some(1400);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
 
-fn gen_center_near(ip: &str, port: usize) -> Center {
-    let secret = [1; 32];
-    let secret = SecretKey::from_slice(&secret).unwrap();
-    let public = [1; 32];
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
-    let b = [1; 32];
-    let s = SecretKey::from_slice(&b).unwrap();
-    let base = Center::new(s, ip.to_string(), port);
+    let mut rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    rconfig.coalesce = Some(1400);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
-    Center {
-        secret,
-        public: Address::from_bytes(public),
-        ..base
+    let topic = Address::default();
+    let mut rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+
+    let bodies: Vec<Vec<u8>> = (0..64).map(|i| vec![i]).collect();
+    ltopic.broadcast_batch(bodies.clone()).unwrap();
+    let mut received = Vec::new();
+    while received.len() < bodies.len() {
+        let t = rtopic
+            .recv_timeout(std::time::Duration::from_secs(5))
+            .unwrap();
+        received.push(t.message.body.as_bytes());
     }
+    received.sort();
+    assert_eq!(received, bodies);
 }
 
-fn gen_center_far(ip: &str, port: usize) -> Center {
-    let secret = [127; 32];
-    let secret = SecretKey::from_slice(&secret).unwrap();
-    let public = [127; 32];
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_verified_source() {
+    let (port1, port2) = (42527, 42528);
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let topic = Address::default();
+    let mut rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
 
-    let b = [127; 32];
-    let s = SecretKey::from_slice(&b).unwrap();
-    let base = Center::new(s, ip.to_string(), port);
+    ltopic.broadcast(vec![1]).unwrap();
+    let t = rtopic
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![1]);
+    assert_eq!(t.verified_source(), Some(lcenter.public.clone()));
+    let metadata = t.metadata();
+    assert_eq!(metadata.ingress, Some(lcenter.public.clone()));
+    assert_eq!(metadata.topic, topic);
+    assert!(metadata.verified && metadata.encrypted && !metadata.wildcard);
 
-    Center {
-        secret,
-        public: Address::from_bytes(public),
-        ..base
+    rtopic.broadcast(vec![2]).unwrap();
+    let t = ltopic
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![2]);
+    assert_eq!(t.verified_source(), Some(rcenter.public.clone()));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_foreign_author() {
+    let (port1, port2) = (42567, 42568);
+    // Signed Wires have to come from their source, without them only
+    // the signature of the author is left to check. The body would be
+    // encrypted for the claimed source, so encryption is disabled.
+    let mut lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    lconfig.encryption = false;
+    lconfig.signatures = false;
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    rconfig.encryption = false;
+    rconfig.signatures = false;
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let topic = Address::default();
+    let mut rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
+
+    // Signed by the sender, whose key the claimed source isn't
+    // derived from.
+    let owner = gen_center_far("127.0.0.1", port1);
+    let message = Message::new(
+        Class::Action,
+        owner.public.clone(),
+        lcenter.public.clone(),
+        topic.clone(),
+        vec![1],
+    );
+    let mut t = Transaction::new(message.clone());
+    t.sign(&rcenter);
+    rinterface.send(t).unwrap();
+    let t = ltopic
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![1]);
+    assert_eq!(t.source(), owner.public);
+    assert_eq!(t.verified_source(), None);
+
+    // The forged message doesn't lock out the actual owner.
+    let mut t = Transaction::new(message);
+    t.sign(&owner);
+    rinterface.send(t).unwrap();
+    let t = ltopic
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![1]);
+    assert_eq!(t.verified_source(), Some(owner.public.clone()));
+}
+
+/// Subscribing requires a round trip through the network, before
+/// that a broadcast would not reach anyone.
+async fn wait_for_subscriber(topic: &mut Topic, subscriber: &Address) {
+    for _ in 0..200 {
+        let _ = topic.try_recv();
+        if topic.subscribers().get(subscriber).is_some() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
     }
+    panic!("subscriber never arrived");
 }
 
-#[test]
-fn test_topic_random() {
+fn gen_center_near(ip: &str, port: usize) -> Center {
+    gen_center_prefix(1, ip, port)
+}
+
+fn gen_center_far(ip: &str, port: usize) -> Center {
+    gen_center_prefix(127, ip, port)
+}
+
+/// Addresses are derived from the secret, so keys get generated until
+/// the first byte matches.
+fn gen_center_prefix(prefix: u8, ip: &str, port: usize) -> Center {
+    loop {
+        let (_, secret) = box_::gen_keypair();
+        let center = Center::new(secret, ip.to_string(), port);
+        if center.public.as_bytes()[0] == prefix {
+            return center;
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_random() {
     let port1 = 42270;
     let port2 = 42271;
 
     let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
     let (_, s1) = box_::gen_keypair();
     let lcenter = Center::new(s1, "127.0.0.1".to_string(), port2);
-    let linterface = Interface::new(lconfig, lcenter.clone()).unwrap();
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     let (_, s2) = box_::gen_keypair();
     let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
 
     let rcenter = Center::new(s2, "127.0.0.1".to_string(), port1);
-    let rinterface = Interface::new(rconfig, rcenter.clone()).unwrap();
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
 
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
     let topic = Address::random();
     // the topic is guaranteed not to be on this node.
     let mut rtopic = rinterface.subscribe(&topic);
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
     let mut ltopic = linterface.subscribe(&topic);
-    std::thread::sleep(std::time::Duration::from_millis(10));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
 
     for i in 0..23 {
-        std::thread::sleep(std::time::Duration::from_millis(8));
+        tokio::time::sleep(std::time::Duration::from_millis(8)).await;
         let _ = ltopic.broadcast(vec![i]);
-        std::thread::sleep(std::time::Duration::from_millis(8));
+        tokio::time::sleep(std::time::Duration::from_millis(8)).await;
         let rret = rtopic.recv().unwrap();
         assert_eq!(rret.message.body.as_bytes(), vec![i]);
     }
 
     for i in 0..55 {
-        std::thread::sleep(std::time::Duration::from_millis(8));
+        tokio::time::sleep(std::time::Duration::from_millis(8)).await;
         let _ = rtopic.broadcast(vec![i]);
-        std::thread::sleep(std::time::Duration::from_millis(8));
+        tokio::time::sleep(std::time::Duration::from_millis(8)).await;
         let rret = ltopic.recv().unwrap();
         assert_eq!(rret.message.body.as_bytes(), vec![i]);
     }
 }
+
+#[test]
+fn test_topic_busy() {
+    let (c1, c2) = Channel::bounded(1);
+    let subscribers = vec![Address::random(), Address::random()];
+    let mut topic = Topic::new(Address::random(), c1, subscribers, Address::random());
+    assert!(matches!(topic.broadcast(vec![42]), Err(Error::Busy(_))));
+    assert!(matches!(c2.try_recv(), Some(Command::Broadcast(_, _))));
+    assert!(c2.try_recv().is_none());
+}
+
+#[test]
+fn test_topic_unsubscribe_busy() {
+    let (c1, c2) = Channel::bounded(1);
+    let subscribers = vec![Address::random()];
+    let mut topic = Topic::new(Address::random(), c1, subscribers, Address::random());
+    let _ = topic.broadcast(vec![42]);
+    assert!(matches!(topic.unsubscribe(), Err(Error::Busy(_))));
+    assert!(matches!(c2.try_recv(), Some(Command::Broadcast(_, _))));
+    assert!(topic.unsubscribe().is_ok());
+    assert!(matches!(c2.try_recv(), Some(Command::Drop)));
+
+    topic.fail(Error::Busy(String::from("full")));
+    assert!(!topic.is_ready());
+    let timeout = std::time::Duration::from_millis(10);
+    assert!(matches!(topic.wait_ready(timeout), Err(Error::Busy(_))));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_set() {
+    let port1 = 42468;
+    let port2 = 42469;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let (_, s1) = box_::gen_keypair();
+    let lcenter = Center::new(s1, "127.0.0.1".to_string(), port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let (_, s2) = box_::gen_keypair();
+    let rcenter = Center::new(s2, "127.0.0.1".to_string(), port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let topics = vec![Address::random(), Address::random()];
+    let mut rset = rinterface.subscribe_many(&topics);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut lset = linterface.subscribe_many(&topics);
+    assert_eq!(lset.addresses(), topics);
+    for topic in &topics {
+        wait_for_subscriber(lset.get(topic).unwrap(), &rcenter.public).await;
+        wait_for_subscriber(rset.get(topic).unwrap(), &lcenter.public).await;
+    }
+
+    for (i, topic) in topics.iter().enumerate() {
+        let _ = lset.get(topic).unwrap().broadcast(vec![i as u8]);
+        let (address, t) = tokio::time::timeout(std::time::Duration::from_secs(5), rset.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&address, topic);
+        assert_eq!(t.message.body.as_bytes(), vec![i as u8]);
+    }
+    assert!(rset.try_recv().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_wildcard() {
+    let port1 = 42470;
+    let port2 = 42471;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let (_, s1) = box_::gen_keypair();
+    let lcenter = Center::new(s1, "127.0.0.1".to_string(), port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let (_, s2) = box_::gen_keypair();
+    let rcenter = Center::new(s2, "127.0.0.1".to_string(), port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    let wildcard = rinterface.subscribe_wildcard(Filter::prefix(&[7])).unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    for (i, topic) in [[8; 32], [7; 32]].iter().enumerate() {
+        let message = Message::new(
+            Class::Action,
+            lcenter.public.clone(),
+            rcenter.public.clone(),
+            Address::from_bytes(*topic),
+            vec![i as u8],
+        );
+        linterface.send(Transaction::new(message)).unwrap();
+    }
+
+    let (topic, t) = tokio::time::timeout(std::time::Duration::from_secs(5), wildcard.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(topic, Address::from_bytes([7; 32]));
+    assert_eq!(t.message.body.as_bytes(), vec![1]);
+    let direct = rinterface.recv().await.unwrap();
+    assert_eq!(direct.topic(), Address::from_bytes([8; 32]));
+}
+
+#[test]
+fn test_topic_bucket_wildcard() {
+    let mut bucket = TopicBucket::new(1);
+    let (_, c2) = Channel::new();
+    bucket.add(Simple::new(Address::from_bytes([1; 32]), c2));
+    let (w1, w2) = Channel::new();
+    bucket.add_wildcard(Wildcard::new(
+        Filter::predicate(|a| a.as_bytes()[0] == 1),
+        w2,
+    ));
+    assert!(bucket.wildcard(&Address::from_bytes([1; 32])).is_some());
+    assert!(bucket.wildcard(&Address::from_bytes([2; 32])).is_none());
+    drop(w1);
+    assert!(bucket.wildcard(&Address::from_bytes([1; 32])).is_none());
+    let (_w3, w4) = Channel::new();
+    bucket.add_wildcard(Wildcard::new(Filter::prefix(&[2, 2]), w4));
+    assert_eq!(bucket.wildcards.len(), 1);
+    assert!(bucket.wildcard(&Address::from_bytes([2; 32])).is_some());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_several() {
+    let port1 = 42472;
+    let port2 = 42473;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let (_, s1) = box_::gen_keypair();
+    let lcenter = Center::new(s1, "127.0.0.1".to_string(), port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let (_, s2) = box_::gen_keypair();
+    let rcenter = Center::new(s2, "127.0.0.1".to_string(), port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let addresses = vec![Address::random(), Address::random(), Address::random()];
+    let mut ltopics = Vec::new();
+    let mut rtopics = Vec::new();
+    for address in &addresses {
+        rtopics.push(rinterface.subscribe(address));
+        ltopics.push(linterface.subscribe(address));
+    }
+    for (ltopic, rtopic) in ltopics.iter_mut().zip(rtopics.iter_mut()) {
+        wait_for_subscriber(ltopic, &rcenter.public).await;
+        wait_for_subscriber(rtopic, &lcenter.public).await;
+    }
+
+    for (i, ltopic) in ltopics.iter_mut().enumerate() {
+        let _ = ltopic.broadcast(vec![i as u8]);
+    }
+    for (i, rtopic) in rtopics.iter_mut().enumerate() {
+        let rret = rtopic.recv().unwrap();
+        assert_eq!(rret.topic(), addresses[i]);
+        assert_eq!(rret.message.body.as_bytes(), vec![i as u8]);
+        let _ = rtopic.broadcast(vec![i as u8 + 10]);
+    }
+    for (i, ltopic) in ltopics.iter_mut().enumerate() {
+        let lret = ltopic.recv().unwrap();
+        assert_eq!(lret.message.body.as_bytes(), vec![i as u8 + 10]);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_policy() {
+    let port1 = 42474;
+    let port2 = 42475;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+    let mut events = rinterface.events();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Both topics are closer to the near center, so linterface hosts
+    // their Records.
+    let private = Address::default();
+    let mut ptopic = linterface.subscribe_with(&private, Policy::Allowlist(Vec::new()));
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let _rtopic = rinterface.subscribe(&private);
+    wait_for_event(&mut events, Event::Denied(private.clone())).await;
+
+    // Interface::message uses the default Address as the topic, but
+    // direct messages aren't checked against its Record.
+    let _ = rinterface.message(lcenter.public.clone(), vec![42]);
+    let lret = ptopic.recv().unwrap();
+    assert_eq!(lret.message.body.as_bytes(), vec![42]);
+
+    let announcements = Address::from_bytes([2; 32]);
+    let mut rtopic = rinterface.subscribe_with(&announcements, Policy::OwnerPublish);
+    wait_for_event(&mut events, Event::Subscribed(announcements.clone())).await;
+    let mut ltopic = linterface.subscribe(&announcements);
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
+    let _ = rtopic.broadcast(vec![7]);
+    let lret = ltopic.recv().unwrap();
+    assert_eq!(lret.message.body.as_bytes(), vec![7]);
+    assert!(ltopic.subscribers().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_unsubscribe() {
+    let (port1, port2) = (42544, 42545);
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // linterface hosts the Record.
+    let topic = Address::default();
+    let rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    let hosted = |subscriber: &Address| {
+        linterface
+            .hosted_subscribers(&topic)
+            .is_some_and(|subscribers| subscribers.contains(subscriber))
+    };
+    assert!(hosted(&rcenter.public) && hosted(&lcenter.public));
+
+    // Only the last clone unsubscribes.
+    let clone = rtopic.clone();
+    drop(rtopic);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert!(hosted(&rcenter.public));
+
+    drop(clone);
+    for _ in 0..200 {
+        let _ = ltopic.try_recv();
+        if !hosted(&rcenter.public) && ltopic.subscribers().is_empty() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(!hosted(&rcenter.public));
+    assert!(ltopic.subscribers().is_empty());
+
+    // The last subscriber leaving removes the Record.
+    ltopic.unsubscribe().unwrap();
+    for _ in 0..200 {
+        if linterface.hosted_subscribers(&topic).is_none() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(linterface.hosted_subscribers(&topic), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_ready() {
+    let (port1, port2) = (42546, 42547);
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+    let mut events = rinterface.events();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // linterface hosts both Records, its own subscriptions are
+    // confirmed right away.
+    let timeout = std::time::Duration::from_secs(5);
+    let topic = Address::default();
+    let mut ltopic = linterface.subscribe(&topic);
+    ltopic.wait_ready(timeout).unwrap();
+    assert!(ltopic.is_ready());
+
+    // The confirmation already contains the known subscribers.
+    let mut rtopic = rinterface.subscribe(&topic);
+    assert!(!rtopic.is_ready());
+    rtopic.wait_ready(timeout).unwrap();
+    assert!(rtopic.is_ready());
+    assert!(rtopic.subscribers().get(&lcenter.public).is_some());
+    wait_for_event(&mut events, Event::Subscribed(topic.clone())).await;
+
+    let private = Address::from_bytes([2; 32]);
+    let _owned = linterface.subscribe_with(&private, Policy::Allowlist(Vec::new()));
+    let mut denied = rinterface.subscribe(&private);
+    assert!(matches!(denied.wait_ready(timeout), Err(Error::Invalid(_))));
+    assert!(!denied.is_ready());
+    wait_for_event(&mut events, Event::Denied(private.clone())).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_roles() {
+    let (port1, port2) = (42548, 42549);
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // linterface hosts the Record and only reads.
+    let timeout = std::time::Duration::from_secs(5);
+    let topic = Address::default();
+    let mut ltopic = linterface.subscribe_with(&topic, Role::Subscriber);
+    ltopic.wait_ready(timeout).unwrap();
+    assert!(matches!(ltopic.broadcast(vec![1]), Err(Error::Invalid(_))));
+
+    // The publisher gets the subscribers without becoming one.
+    let mut publisher = rinterface.publisher(&topic);
+    publisher.wait_ready(timeout).unwrap();
+    assert_eq!(publisher.address(), topic);
+    assert!(publisher.subscribers().get(&lcenter.public).is_some());
+    assert_eq!(
+        linterface.hosted_subscribers(&topic),
+        Some(vec![lcenter.public.clone()])
+    );
+
+    publisher.broadcast(vec![7]).unwrap();
+    let t = ltopic.recv_timeout(timeout).unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![7]);
+    assert_eq!(t.source(), rcenter.public);
+}
+
+#[test]
+fn test_topic_wait_ready() {
+    let (c1, c2) = Channel::new();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let timeout = std::time::Duration::from_millis(50);
+    assert!(matches!(topic.wait_ready(timeout), Err(Error::Timeout(_))));
+
+    // Messages arriving before the confirmation are kept.
+    let subscriber = Address::random();
+    let transaction = Transaction::new(Message::new(
+        Class::Action,
+        Address::random(),
+        Address::random(),
+        Address::random(),
+        vec![42],
+    ));
+    c2.send(Command::Message(transaction.clone())).unwrap();
+    c2.send(Command::Subscriber(subscriber.clone())).unwrap();
+    c2.send(Command::Confirmed).unwrap();
+    topic.wait_ready(timeout).unwrap();
+    assert!(topic.is_ready());
+    assert!(topic.subscribers().get(&subscriber).is_some());
+    assert_eq!(topic.try_recv(), Some(transaction));
+
+    let (c1, c2) = Channel::new();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let e = Error::Timeout(String::from("subscription was never confirmed"));
+    c2.send(Command::Failed(e)).unwrap();
+    assert!(matches!(topic.wait_ready(timeout), Err(Error::Timeout(_))));
+    assert!(!topic.is_ready());
+}
+
+async fn wait_for_event(events: &mut actaeon::event::EventReceiver, expected: Event) {
+    loop {
+        let event = tokio::time::timeout(std::time::Duration::from_secs(5), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if event == expected {
+            return;
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct Position {
+    x: i64,
+    y: i64,
+}
+
+#[test]
+fn test_topic_typed() {
+    let (c1, c2) = Channel::new();
+    let subscriber = Address::random();
+    let mut topic = Topic::new(
+        Address::random(),
+        c1,
+        vec![subscriber.clone()],
+        Address::random(),
+    );
+    let position = Position { x: 4, y: -2 };
+    topic.broadcast_typed(&position).unwrap();
+    let body = match c2.try_recv() {
+        Some(Command::Broadcast(target, body)) => {
+            assert_eq!(target, subscriber);
+            body
+        }
+        _ => panic!("expected a broadcast"),
+    };
+
+    let message = |body| {
+        let message = Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            body,
+        );
+        Command::Message(Transaction::new(message))
+    };
+    c2.send(message(body)).unwrap();
+    c2.send(message(vec![42])).unwrap();
+    assert_eq!(topic.recv_typed::<Position>().unwrap().unwrap(), position);
+    assert!(matches!(
+        topic.recv_typed::<Position>(),
+        Some(Err(Error::Serialization(_)))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_presence() {
+    let port1 = 42488;
+    let port2 = 42489;
+
+    let mut lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    lconfig.presence = 1;
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    rconfig.presence = 1;
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // The Record is hosted on the near center.
+    let topic = Address::default();
+    let mut rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
+
+    ltopic.announce(b"near".to_vec()).unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    rtopic.announce(b"far".to_vec()).unwrap();
+    loop {
+        let t = ltopic.recv().unwrap();
+        if t.class() == Class::Joined && t.message.body.as_bytes() == b"far" {
+            assert_eq!(t.source(), rcenter.public);
+            break;
+        }
+    }
+    assert!(ltopic
+        .presence()
+        .iter()
+        .any(|p| p.address == rcenter.public && p.metadata == b"far"));
+
+    let mut present = false;
+    for _ in 0..200 {
+        let list = rtopic.presence();
+        if list
+            .iter()
+            .any(|p| p.address == lcenter.public && p.metadata == b"near")
+        {
+            present = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    assert!(present);
+
+    // Without the Topic the far center stops announcing itself and
+    // expires after a few intervals.
+    drop(rtopic);
+    loop {
+        let t = ltopic.recv().unwrap();
+        if t.class() == Class::Left {
+            assert_eq!(t.source(), rcenter.public);
+            break;
+        }
+    }
+    assert!(ltopic
+        .presence()
+        .iter()
+        .all(|p| p.address != rcenter.public));
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_presence_spoofed() {
+    let (port1, port2, port3) = (42564, 42565, 42566);
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    rconfig.replicas = 1;
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let aconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let acenter = gen_center_prefix(200, "127.0.0.1", port3);
+    let ainterface = Interface::new(aconfig, acenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // The Record is hosted on the near center, the third node is
+    // further away and no replica.
+    let topic = Address::default();
+    let mut rtopic = rinterface.subscribe(&topic);
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let ltopic = linterface.subscribe(&topic);
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
+
+    let fake = gen_center_far("127.0.0.1", port3).public;
+    let body = Presence::new(fake.clone(), b"fake".to_vec(), std::time::Duration::ZERO);
+    let message = Message::new(
+        Class::Joined,
+        acenter.public.clone(),
+        rcenter.public.clone(),
+        topic.clone(),
+        body.as_bytes(),
+    );
+    ainterface.send(Transaction::new(message)).unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    while let Some(t) = rtopic.try_recv() {
+        assert_ne!(t.class(), Class::Joined);
+    }
+    assert!(rtopic.presence().iter().all(|p| p.address != fake));
+    drop(ltopic);
+}
+
+#[test]
+fn test_topic_ordered() {
+    let (c1, c2) = Channel::new();
+    let subscriber = Address::random();
+    let mut topic = Topic::new(
+        Address::random(),
+        c1,
+        vec![subscriber.clone()],
+        Address::random(),
+    );
+    topic.set_ordered(true);
+    topic.broadcast(vec![0]).unwrap();
+    topic.broadcast(vec![1]).unwrap();
+    let mut epoch = None;
+    for expected in 0..2 {
+        match c2.try_recv() {
+            Some(Command::Sequenced(target, sequence, body)) => {
+                // The upper half is the random epoch of the publisher.
+                assert_eq!(*epoch.get_or_insert(sequence >> 32), sequence >> 32);
+                assert_eq!(target, subscriber);
+                assert_eq!(sequence & u64::from(u32::MAX), expected);
+                assert_eq!(body, vec![expected as u8]);
+            }
+            _ => panic!("expected an ordered broadcast"),
+        }
+    }
+
+    let publisher = Address::random();
+    let message = |sequence| {
+        let mut message = Message::new(
+            Class::Action,
+            publisher.clone(),
+            Address::random(),
+            Address::random(),
+            vec![sequence as u8],
+        );
+        message.sequence = Some(sequence);
+        Command::Message(Transaction::new(message))
+    };
+    for sequence in [1, 0, 2, 5] {
+        c2.send(message(sequence)).unwrap();
+    }
+    for expected in 0..3 {
+        let t = topic.recv_ordered().unwrap();
+        assert_eq!(t.message.sequence, Some(expected));
+    }
+
+    // 3 and 4 never arrive, so 5 is released after the timeout and
+    // the late 4 gets dropped.
+    let started = std::time::Instant::now();
+    let t = topic.recv_ordered().unwrap();
+    assert_eq!(t.message.sequence, Some(5));
+    assert!(started.elapsed() >= actaeon::topic::ORDER_TIMEOUT);
+    c2.send(message(4)).unwrap();
+    c2.send(message(6)).unwrap();
+    let t = topic.recv_ordered().unwrap();
+    assert_eq!(t.message.sequence, Some(6));
+
+    // A restarted publisher uses a new epoch and starts over, the
+    // message still pending from the old one comes first.
+    c2.send(message(8)).unwrap();
+    let restarted = 7 << 32;
+    for sequence in [restarted + 1, restarted] {
+        c2.send(message(sequence)).unwrap();
+    }
+    for expected in [8, restarted, restarted + 1] {
+        let t = topic.recv_ordered().unwrap();
+        assert_eq!(t.message.sequence, Some(expected));
+    }
+    // Epochs are random, so the new one might be lower.
+    c2.send(message(3 << 32)).unwrap();
+    let t = topic.recv_ordered().unwrap();
+    assert_eq!(t.message.sequence, Some(3 << 32));
+}
+
+#[test]
+fn test_topic_stream() {
+    let (c1, c2) = Channel::bounded(4);
+    let subscriber = Address::random();
+    let mut topic = Topic::new(
+        Address::random(),
+        c1,
+        vec![subscriber.clone()],
+        Address::random(),
+    );
+    let data: Vec<u8> = (0..3 * CHUNK_SIZE + 100).map(|i| i as u8).collect();
+
+    // The Channel only holds four chunks, so the writer has to wait
+    // for the other side.
+    let reader = std::thread::spawn(move || {
+        let mut chunks = Vec::new();
+        while let Some(command) = c2.recv() {
+            match command {
+                Command::Sequenced(target, sequence, body) => {
+                    assert_eq!(target, subscriber);
+                    chunks.push((sequence, body));
+                }
+                // Sent once the Topic gets dropped.
+                Command::Drop => {}
+                _ => panic!("expected an ordered broadcast"),
+            }
+        }
+        chunks
+    });
+    {
+        let mut stream = topic.stream();
+        stream.write_all(&data[..100]).unwrap();
+        stream.write_all(&data[100..]).unwrap();
+    }
+    drop(topic);
+    let chunks = reader.join().unwrap();
+    assert_eq!(chunks.len(), 4);
+    assert_eq!(chunks[0].1.len(), CHUNK_SIZE);
+    let sent: Vec<u8> = chunks.iter().flat_map(|(_, body)| body.clone()).collect();
+    assert_eq!(sent, data);
+
+    // The subscriber gets the chunks out of order.
+    let (c1, c2) = Channel::new();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let publisher = Address::random();
+    for (sequence, body) in chunks.into_iter().rev() {
+        let mut message = Message::new(
+            Class::Action,
+            publisher.clone(),
+            Address::random(),
+            Address::random(),
+            body,
+        );
+        message.sequence = Some(sequence);
+        c2.send(Command::Message(Transaction::new(message)))
+            .unwrap();
+    }
+    drop(c2);
+    let mut received = Vec::new();
+    topic.stream().read_to_end(&mut received).unwrap();
+    assert_eq!(received, data);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_at_least_once() {
+    let port1 = 42490;
+    let port2 = 42491;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let lcenter = gen_center_near("127.0.0.1", port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let rcenter = gen_center_far("127.0.0.1", port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // The near center hosts the Record, so messages of the far one
+    // pass through it. It keeps the ones it can't deliver.
+    let topic = Address::default();
+    let options = TopicOptions {
+        delivery: Delivery::AtLeastOnce,
+        retention: Some(Retention::new(16, 4096, std::time::Duration::from_secs(60))),
+        ..TopicOptions::default()
+    };
+    let mut rtopic = rinterface.subscribe_with(&topic, options.clone());
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    let mut ltopic = linterface.subscribe_with(&topic, options);
+    wait_for_subscriber(&mut ltopic, &rcenter.public).await;
+    wait_for_subscriber(&mut rtopic, &lcenter.public).await;
+
+    for i in 0..5 {
+        rtopic.broadcast(vec![i]).unwrap();
+        ltopic.broadcast(vec![i + 10]).unwrap();
+    }
+    for i in 0..5 {
+        let lret = ltopic.recv_ordered().unwrap();
+        let counter = lret.message.sequence.map(|s| s & u64::from(u32::MAX));
+        assert_eq!(counter, Some(i as u64));
+        assert_eq!(lret.message.body.as_bytes(), vec![i]);
+        let rret = rtopic.recv_ordered().unwrap();
+        assert_eq!(rret.message.body.as_bytes(), vec![i + 10]);
+    }
+
+    // Everything got acknowledged, so nothing arrives again.
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+    assert!(ltopic.try_recv().is_none());
+    assert!(rtopic.try_recv().is_none());
+}
+
+#[test]
+fn test_topic_path() {
+    let path = TopicPath::new("game/lobby/42").unwrap();
+    let parent = path.parent().unwrap();
+    assert_eq!(parent.to_string(), "game/lobby");
+    assert!(path.starts_with(&parent));
+    assert!(!parent.starts_with(&path));
+
+    let chain = path.chain();
+    assert_eq!(chain.len(), 3);
+    assert_eq!(chain[..2], parent.chain()[..]);
+    assert_eq!(path.address(), chain[2]);
+    assert_ne!(
+        path.address(),
+        TopicPath::new("lobby/42").unwrap().address()
+    );
+    assert_eq!(path.subtrees()[1], parent.subtree());
+    assert!(!chain.contains(&path.subtree()));
+
+    assert!(TopicPath::new("game//42").is_err());
+    assert!(TopicPath::new("").is_err());
+    assert!(TopicPath::new("game").unwrap().parent().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_subtree() {
+    let port1 = 42492;
+    let port2 = 42493;
+
+    let lconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port1);
+    let (_, s1) = box_::gen_keypair();
+    let lcenter = Center::new(s1, "127.0.0.1".to_string(), port2);
+    let linterface = Interface::new(lconfig, lcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let rconfig = Config::new(20, 10, 1000, "127.0.0.1".to_string(), port2);
+    let (_, s2) = box_::gen_keypair();
+    let rcenter = Center::new(s2, "127.0.0.1".to_string(), port1);
+    let rinterface = Interface::new(rconfig, rcenter.clone()).await.unwrap();
+
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let lobby = TopicPath::new("game/lobby").unwrap();
+    let room = TopicPath::new("game/lobby/42").unwrap();
+    let chat = TopicPath::new("chat/42").unwrap();
+    let mut ltopic = linterface.subscribe_subtree(&lobby);
+    let mut rroom = rinterface.subscribe_path(&room);
+    let mut rchat = rinterface.subscribe_path(&chat);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let mut received = false;
+    for _ in 0..50 {
+        rchat.broadcast(vec![1]).unwrap();
+        rroom.broadcast(vec![42]).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        if let Some(t) = ltopic.try_recv() {
+            assert_eq!(t.topic(), room.address());
+            assert_eq!(t.verified_source(), Some(rcenter.public.clone()));
+            assert_eq!(t.message.body.as_bytes(), vec![42]);
+            received = true;
+            break;
+        }
+    }
+    assert!(received, "propagated message never arrived");
+
+    // A forged publisher without its signature doesn't replace the
+    // source.
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    while ltopic.try_recv().is_some() {}
+    let forged = gen_center_far("127.0.0.1", port1).public;
+    let mut body = forged.as_bytes().to_vec();
+    body.extend_from_slice(&[0; 96]);
+    body.extend_from_slice(&room.address().as_bytes());
+    body.push(7);
+    let message = Message::new(
+        Class::Subtree,
+        rcenter.public.clone(),
+        lcenter.public.clone(),
+        lobby.subtree(),
+        body,
+    );
+    let mut t = Transaction::new(message);
+    t.message.encrypt(&rcenter);
+    rinterface.send(t).unwrap();
+    let t = ltopic
+        .recv_timeout(std::time::Duration::from_secs(5))
+        .unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![7]);
+    assert_eq!(t.source(), rcenter.public);
+    assert_eq!(t.verified_source(), None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_delegate() {
+    let ports = [42515, 42516, 42517];
+    let lobby = TopicPath::new("game/lobby").unwrap();
+    let room = TopicPath::new("game/lobby/42").unwrap();
+    // The publisher hosts the Record and every node knows it, so they
+    // all agree on that.
+    let subtree = lobby.subtree();
+    let mut secrets: Vec<_> = (0..3).map(|_| box_::gen_keypair().1).collect();
+    secrets.sort_by_key(|secret| &Center::new(secret.clone(), String::new(), 0).public ^ &subtree);
+    let mut interfaces = Vec::new();
+    let mut centers = Vec::new();
+    for (secret, port) in secrets.into_iter().zip(ports.iter()) {
+        // With a branching factor of one the Record only sends each
+        // message to one subscriber, which has to pass it on.
+        let mut config = Config::new(20, 10, 1000, "127.0.0.1".to_string(), 42518);
+        config.branching = 1;
+        config.bootstrap = centers
+            .iter()
+            .map(|center: &Center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let mut first = interfaces[1].subscribe_subtree(&lobby);
+    let mut second = interfaces[2].subscribe_subtree(&lobby);
+    let mut publisher = interfaces[0].subscribe_path(&room);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let (mut left, mut right) = (false, false);
+    for _ in 0..50 {
+        publisher.broadcast(vec![42]).unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        for (topic, received) in [(&mut first, &mut left), (&mut second, &mut right)] {
+            while let Some(t) = topic.try_recv() {
+                assert_eq!(t.topic(), room.address());
+                assert_eq!(t.verified_source(), Some(centers[0].public.clone()));
+                assert_eq!(t.message.body.as_bytes(), vec![42]);
+                *received = true;
+            }
+        }
+        if left && right {
+            return;
+        }
+    }
+    panic!("delegated message never arrived");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_delegate_foreign() {
+    let ports = [42575, 42576];
+    let lobby = TopicPath::new("game/lobby").unwrap();
+    let room = TopicPath::new("game/lobby/42").unwrap();
+    let subtree = lobby.subtree();
+    let mut secrets: Vec<_> = (0..2).map(|_| box_::gen_keypair().1).collect();
+    secrets.sort_by_key(|secret| &Center::new(secret.clone(), String::new(), 0).public ^ &subtree);
+    let mut interfaces = Vec::new();
+    let mut centers = Vec::new();
+    for (secret, port) in secrets.into_iter().zip(ports.iter()) {
+        let mut config = Config::new(20, 10, 1000, "127.0.0.1".to_string(), 42577);
+        config.replicas = 1;
+        config.bootstrap = centers
+            .iter()
+            .map(|center: &Center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    let mut topic = interfaces[0].subscribe_subtree(&lobby);
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    while topic.try_recv().is_some() {}
+
+    // The farther node doesn't host the Record, so it can't make the
+    // subscriber pass anything on.
+    let mut body = vec![0, 0, 0, 1];
+    body.extend_from_slice(&Address::random().as_bytes());
+    body.extend_from_slice(&centers[1].public.as_bytes());
+    body.extend_from_slice(&[0; 96]);
+    body.extend_from_slice(&room.address().as_bytes());
+    body.push(7);
+    let message = Message::new(
+        Class::Delegate,
+        centers[1].public.clone(),
+        centers[0].public.clone(),
+        subtree,
+        body,
+    );
+    let mut t = Transaction::new(message);
+    t.message.encrypt(&centers[1]);
+    interfaces[1].send(t).unwrap();
+    assert!(topic
+        .recv_timeout(std::time::Duration::from_millis(500))
+        .is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_gossip() {
+    let ports = [42519, 42520, 42521];
+    // The publisher hosts the Record and every node knows it, so they
+    // all agree on that.
+    let topic = Address::random();
+    let mut secrets: Vec<_> = (0..3).map(|_| box_::gen_keypair().1).collect();
+    secrets.sort_by_key(|secret| &Center::new(secret.clone(), String::new(), 0).public ^ &topic);
+    let mut interfaces = Vec::new();
+    let mut centers = Vec::new();
+    for (secret, port) in secrets.into_iter().zip(ports.iter()) {
+        let mut config = Config::new(20, 10, 1000, "127.0.0.1".to_string(), 42522);
+        config.bootstrap = centers
+            .iter()
+            .map(|center: &Center| Node::new(center.public.clone(), Some(center.link.clone())))
+            .collect();
+        let center = Center::new(secret, "127.0.0.1".to_string(), *port);
+        interfaces.push(Interface::new(config, center.clone()).await.unwrap());
+        centers.push(center);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    // With a fanout of one the publisher only pushes each message to
+    // one subscriber, the other one has to pull it.
+    let gossip = Gossip {
+        fanout: 1,
+        rounds: 20,
+    };
+    let mut subscribers: Vec<Topic> = interfaces[1..]
+        .iter()
+        .map(|interface| interface.subscribe_with(&topic, TopicOptions::dissemination(gossip)))
+        .collect();
+    let mut publisher = interfaces[0].subscribe_with(&topic, TopicOptions::dissemination(gossip));
+    for center in &centers[1..] {
+        wait_for_subscriber(&mut publisher, &center.public).await;
+    }
+
+    let bodies: Vec<Vec<u8>> = (0..5).map(|i| vec![i]).collect();
+    for body in &bodies {
+        publisher.broadcast(body.clone()).unwrap();
+    }
+    for subscriber in subscribers.iter_mut() {
+        let mut received = Vec::new();
+        while received.len() < bodies.len() {
+            let t = subscriber
+                .recv_timeout(std::time::Duration::from_secs(5))
+                .expect("gossip message never arrived");
+            assert_eq!(t.source(), centers[0].public);
+            received.push(t.message.body.as_bytes());
+        }
+        received.sort();
+        assert_eq!(received, bodies);
+    }
+
+    // Every message is only delivered once, even though it keeps
+    // getting advertised.
+    tokio::time::sleep(std::time::Duration::from_millis(600)).await;
+    for subscriber in subscribers.iter_mut() {
+        assert!(subscriber.try_recv().is_none());
+    }
+}
+
+#[test]
+fn test_topic_recv_timeout() {
+    let (c1, c2) = Channel::new();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let timeout = std::time::Duration::from_millis(50);
+    let started = std::time::Instant::now();
+    assert_eq!(topic.recv_timeout(timeout), None);
+    assert!(started.elapsed() >= timeout);
+
+    let transaction = Transaction::new(Message::new(
+        Class::Action,
+        Address::random(),
+        Address::random(),
+        Address::random(),
+        vec![42],
+    ));
+    let sender = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        c2.send(Command::Message(transaction.clone())).unwrap();
+        (c2, transaction)
+    });
+    let received = topic.recv_timeout(std::time::Duration::from_secs(5));
+    let (_c2, transaction) = sender.join().unwrap();
+    assert_eq!(received, Some(transaction));
+}
+
+#[test]
+fn test_topic_clone() {
+    let (c1, c2) = Channel::new();
+    let subscriber = Address::random();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let message = |i| {
+        Command::Message(Transaction::new(Message::new(
+            Class::Action,
+            Address::random(),
+            Address::random(),
+            Address::random(),
+            vec![i],
+        )))
+    };
+
+    // Every message reaches exactly one of the clones.
+    let readers: Vec<_> = (0..2)
+        .map(|_| {
+            let mut topic = topic.clone();
+            std::thread::spawn(move || topic.recv())
+        })
+        .collect();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    c2.send(Command::Subscriber(subscriber.clone())).unwrap();
+    c2.send(message(0)).unwrap();
+    c2.send(message(1)).unwrap();
+    let mut received: Vec<u8> = readers
+        .into_iter()
+        .map(|r| r.join().unwrap().unwrap().message.body.as_bytes()[0])
+        .collect();
+    received.sort();
+    assert_eq!(received, vec![0, 1]);
+    assert!(topic.subscribers().get(&subscriber).is_some());
+
+    // A message read during a broadcast still wakes up the reader.
+    let mut clone = topic.clone();
+    let reader = std::thread::spawn(move || clone.recv());
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    c2.send(message(2)).unwrap();
+    topic.broadcast(vec![42]).unwrap();
+    let t = reader.join().unwrap().unwrap();
+    assert_eq!(t.message.body.as_bytes(), vec![2]);
+    assert!(matches!(c2.try_recv(), Some(Command::Broadcast(target, _)) if target == subscriber));
+
+    // Only dropping the last clone unsubscribes.
+    drop(topic.clone());
+    assert!(c2.try_recv().is_none());
+    drop(topic);
+    assert!(matches!(c2.try_recv(), Some(Command::Drop)));
+    assert!(c2.try_recv().is_none());
+}
+
+#[tokio::test]
+async fn test_topic_futures_stream() {
+    use futures::StreamExt;
+
+    let (c1, c2) = Channel::new();
+    let mut topic = Topic::new(Address::random(), c1, Vec::new(), Address::random());
+    let transactions: Vec<Transaction> = (0..3)
+        .map(|i| {
+            Transaction::new(Message::new(
+                Class::Action,
+                Address::random(),
+                Address::random(),
+                Address::random(),
+                vec![i],
+            ))
+        })
+        .collect();
+    c2.send(Command::Subscriber(Address::random())).unwrap();
+    for t in transactions.clone() {
+        c2.send(Command::Message(t)).unwrap();
+    }
+    let received: Vec<Transaction> = (&mut topic).take(3).collect().await;
+    assert_eq!(received, transactions);
+    assert_eq!(topic.subscribers().len(), 1);
+
+    drop(c2);
+    assert_eq!(topic.next().await, None);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_topic_subscription() {
+    let (c1, c2) = Channel::new();
+    let subscriber = Address::random();
+    let topic = Topic::new(
+        Address::random(),
+        c1,
+        vec![subscriber.clone()],
+        Address::random(),
+    );
+    let address = topic.address();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let subscription = Subscription::new(topic, move |t: Transaction| {
+        if t.message.body.as_bytes()[0] == 0 {
+            panic!("handler failed");
+        }
+        sender.send(t).unwrap();
+    });
+    assert_eq!(subscription.address(), address);
+
+    let transactions: Vec<Transaction> = (0..3)
+        .map(|i| {
+            Transaction::new(Message::new(
+                Class::Action,
+                Address::random(),
+                Address::random(),
+                Address::random(),
+                vec![i],
+            ))
+        })
+        .collect();
+    for t in transactions.clone() {
+        c2.send(Command::Message(t)).unwrap();
+    }
+    let timeout = std::time::Duration::from_secs(5);
+    assert_eq!(receiver.recv_timeout(timeout).unwrap(), transactions[1]);
+    assert_eq!(receiver.recv_timeout(timeout).unwrap(), transactions[2]);
+    assert!(subscription.is_active());
+
+    subscription.unsubscribe();
+    let command = tokio::time::timeout(timeout, c2.recv_async())
+        .await
+        .unwrap()
+        .unwrap();
+    // A single Drop, the Record informs the subscribers.
+    assert!(matches!(command, Command::Drop));
+    assert!(c2.try_recv().is_none());
+}
+
+#[test]
+fn test_topic_group_encryption() {
+    let address = Address::random();
+    let (lpublic, lsecret) = box_::gen_keypair();
+    let (rpublic, rsecret) = box_::gen_keypair();
+    let (lcenter, rcenter) = (Address::new(lpublic), Address::new(rpublic));
+    let (l1, l2) = Channel::new();
+    let mut ltopic = Topic::new(address.clone(), l1, vec![rcenter.clone()], lcenter.clone())
+        .with_secret(lsecret);
+    let (r1, r2) = Channel::new();
+    let mut rtopic = Topic::new(address.clone(), r1, vec![lcenter.clone()], rcenter.clone())
+        .with_secret(rsecret);
+
+    // Passes everything the manager sends to the member on, like the
+    // Switch would.
+    let forward = |expected: usize| {
+        let mut count = 0;
+        while let Some(command) = l2.try_recv() {
+            if let Command::Broadcast(target, body) = command {
+                if target == rcenter {
+                    let message = Message::new(
+                        Class::Action,
+                        lcenter.clone(),
+                        target,
+                        address.clone(),
+                        body,
+                    );
+                    let mut t = Transaction::new(message);
+                    t.set_verified(true);
+                    r2.send(Command::Message(t)).unwrap();
+                }
+                count += 1;
+            }
+        }
+        assert_eq!(count, expected);
+    };
+
+    // Topics that didn't join the group pass keys and plain messages
+    // on unchanged.
+    assert!(ltopic.rekey().is_err());
+    ltopic
+        .enable_group_encryption(GroupKey::generate())
+        .unwrap();
+    forward(1);
+    assert!(rtopic.try_recv().is_some());
+    assert!(rtopic.group_key().is_none());
+
+    rtopic.join_group(lcenter.clone());
+    ltopic.rekey().unwrap();
+    forward(1);
+    assert!(rtopic.try_recv().is_none());
+    assert_eq!(rtopic.group_key(), ltopic.group_key());
+
+    ltopic.broadcast(vec![1, 2, 3]).unwrap();
+    forward(1);
+    assert_eq!(
+        rtopic.try_recv().unwrap().message.body.as_bytes(),
+        vec![1, 2, 3]
+    );
+
+    // A new subscriber gets the new key together with the others.
+    let key = ltopic.group_key();
+    l2.send(Command::Subscriber(Address::random())).unwrap();
+    ltopic.broadcast(vec![4]).unwrap();
+    assert_ne!(ltopic.group_key(), key);
+    forward(4);
+    assert_eq!(rtopic.try_recv().unwrap().message.body.as_bytes(), vec![4]);
+    assert_eq!(rtopic.group_key(), ltopic.group_key());
+
+    // Keys of other nodes, unverified keys and plain messages are
+    // dropped.
+    let key = rtopic.group_key();
+    let other = Address::random();
+    let forged = GroupKey::generate().seal(&rcenter);
+    for (source, body, verified) in [
+        (other.clone(), forged.clone(), true),
+        (lcenter.clone(), forged, false),
+        (other, vec![5], false),
+    ] {
+        let message = Message::new(
+            Class::Action,
+            source,
+            rcenter.clone(),
+            address.clone(),
+            body,
+        );
+        let mut t = Transaction::new(message);
+        t.set_verified(verified);
+        r2.send(Command::Message(t)).unwrap();
+    }
+    assert!(rtopic.try_recv().is_none());
+    assert_eq!(rtopic.group_key(), key);
+
+    rtopic.broadcast(vec![6]).unwrap();
+    match r2.try_recv() {
+        Some(Command::Broadcast(target, body)) => {
+            assert_eq!(target, lcenter);
+            assert_ne!(body, vec![6]);
+        }
+        _ => panic!("expected an encrypted broadcast"),
+    }
+}